@@ -0,0 +1,139 @@
+//! Attachments of items (iCal `ATTACH` properties)
+
+use serde::{Deserialize, Serialize};
+use ical::property::Property;
+
+/// The content of an attachment
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AttachmentData {
+    /// A reference to a document stored elsewhere (e.g. `ATTACH:https://example.com/report.pdf`)
+    Uri(String),
+    /// A document stored inside the item itself (`ATTACH;ENCODING=BASE64;VALUE=BINARY:...`).
+    /// It is kept base64-encoded, as it is in the iCal file
+    Inline(String),
+}
+
+/// A document attached to an item
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Attachment {
+    data: AttachmentData,
+    /// The media type of the document (iCal `FMTTYPE`), e.g. `application/pdf`
+    fmt_type: Option<String>,
+}
+
+impl Attachment {
+    /// Create an attachment that refers to an external URI
+    pub fn from_uri(uri: String, fmt_type: Option<String>) -> Self {
+        Self { data: AttachmentData::Uri(uri), fmt_type }
+    }
+
+    /// Create an inline attachment out of the raw content of a document
+    pub fn from_content(content: &[u8], fmt_type: Option<String>) -> Self {
+        Self { data: AttachmentData::Inline(base64::encode(content)), fmt_type }
+    }
+
+    pub fn data(&self) -> &AttachmentData    { &self.data }
+    pub fn fmt_type(&self) -> Option<&str>    { self.fmt_type.as_deref() }
+
+    /// Returns whether the content of this attachment is stored inside the item
+    pub fn is_inline(&self) -> bool {
+        matches!(self.data, AttachmentData::Inline(_))
+    }
+
+    /// Decode the content of an inline attachment. Returns `None` for attachments that refer to an external URI
+    pub fn content(&self) -> Option<Result<Vec<u8>, base64::DecodeError>> {
+        match &self.data {
+            AttachmentData::Uri(_) => None,
+            AttachmentData::Inline(b64) => Some(base64::decode(b64)),
+        }
+    }
+
+    /// Parse an iCal `ATTACH` property.
+    ///
+    /// This returns `None` for properties this crate is not able to represent (e.g. with parameters other than `FMTTYPE`, `ENCODING` and `VALUE`).
+    /// They should be kept as raw properties instead, so that they can be written back unchanged.
+    pub fn from_property(prop: &Property) -> Option<Self> {
+        if prop.name != "ATTACH" {
+            return None;
+        }
+        let value = prop.value.clone()?;
+
+        let mut fmt_type = None;
+        let mut encoding = None;
+        let mut value_type = None;
+        for (key, values) in prop.params.iter().flatten() {
+            let single_value = match values.as_slice() {
+                [v] => v.clone(),
+                _ => return None,
+            };
+            match key.to_ascii_uppercase().as_str() {
+                "FMTTYPE" => fmt_type = Some(single_value),
+                "ENCODING" => encoding = Some(single_value.to_ascii_uppercase()),
+                "VALUE" => value_type = Some(single_value.to_ascii_uppercase()),
+                _ => return None,
+            }
+        }
+
+        let data = match (encoding.as_deref(), value_type.as_deref()) {
+            (None, None) | (None, Some("URI")) => AttachmentData::Uri(value),
+            (Some("BASE64"), Some("BINARY")) => AttachmentData::Inline(value),
+            _ => return None,
+        };
+        Some(Self { data, fmt_type })
+    }
+
+    /// The parameters and the value of the iCal `ATTACH` property of this attachment
+    pub fn to_ical_value(&self) -> (Vec<(&'static str, String)>, &str) {
+        let mut params = Vec::new();
+        if let Some(fmt_type) = &self.fmt_type {
+            params.push(("FMTTYPE", fmt_type.clone()));
+        }
+        match &self.data {
+            AttachmentData::Uri(uri) => (params, uri),
+            AttachmentData::Inline(b64) => {
+                params.push(("ENCODING", "BASE64".to_string()));
+                params.push(("VALUE", "BINARY".to_string()));
+                (params, b64)
+            },
+        }
+    }
+
+    /// Take the (base64-encoded) content of an inline attachment, leaving it empty
+    pub(crate) fn take_inline_data(&mut self) -> Option<String> {
+        match &mut self.data {
+            AttachmentData::Uri(_) => None,
+            AttachmentData::Inline(b64) => Some(std::mem::take(b64)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attach(params: Vec<(&str, &str)>, value: &str) -> Property {
+        Property {
+            name: "ATTACH".to_string(),
+            params: Some(params.into_iter().map(|(k, v)| (k.to_string(), vec![v.to_string()])).collect()),
+            value: Some(value.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_attachments() {
+        let uri = Attachment::from_property(&attach(vec![("FMTTYPE", "application/pdf")], "https://example.com/report.pdf")).unwrap();
+        assert_eq!(uri.data(), &AttachmentData::Uri("https://example.com/report.pdf".to_string()));
+        assert_eq!(uri.fmt_type(), Some("application/pdf"));
+        assert!(uri.content().is_none());
+
+        let inline = Attachment::from_property(&attach(vec![("ENCODING", "BASE64"), ("VALUE", "BINARY")], "SGVsbG8gd29ybGQ=")).unwrap();
+        assert!(inline.is_inline());
+        assert_eq!(inline.content().unwrap().unwrap(), b"Hello world");
+        assert_eq!(inline, Attachment::from_content(b"Hello world", None));
+
+        // These are not supported, and must be kept as raw properties
+        assert!(Attachment::from_property(&attach(vec![("ENCODING", "8BIT"), ("VALUE", "BINARY")], "Hello")).is_none());
+        assert!(Attachment::from_property(&attach(vec![("X-FILENAME", "report.pdf")], "https://example.com/report.pdf")).is_none());
+    }
+}
@@ -3,24 +3,34 @@
 use std::path::PathBuf;
 use std::path::Path;
 use std::error::Error;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use csscolorparser::Color;
 use url::Url;
 
-use crate::traits::CalDavSource;
+use crate::traits::{CalDavSource, SourceAdapter, SyncMetadata};
+use check::{Inconsistency, RepairPolicy, RepairReport};
+use outbound::QueuedOperation;
 use crate::traits::BaseCalendar;
 use crate::traits::CompleteCalendar;
 use crate::calendar::cached_calendar::CachedCalendar;
-use crate::calendar::SupportedComponents;
+use crate::calendar::{ComponentSet, SupportedComponents};
+use crate::item::{Item, SyncStatus};
+use crate::provider::quarantine::UploadFailure;
+#[cfg(feature = "local_calendar_mocks_remote_calendars")]
+use crate::transfer_stats::TransferCounter;
 
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
 use crate::mock_behaviour::MockBehaviour;
 
+pub mod check;
+pub mod outbound;
+
 const MAIN_FILE: &str = "data.json";
 
 /// A CalDAV source that stores its items in a local folder.
@@ -43,12 +53,18 @@ pub struct Cache {
 struct CachedData {
     #[serde(skip)]
     calendars: HashMap<Url, Arc<Mutex<CachedCalendar>>>,
+    /// See [`Cache::set_item_history_limit`]
+    #[serde(default)]
+    item_history_limit: Option<usize>,
 }
 
 impl Cache {
     /// Activate the "mocking remote source" features (i.e. tell its children calendars that they are mocked remote calendars)
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     pub fn set_mock_behaviour(&mut self, mock_behaviour: Option<Arc<Mutex<MockBehaviour>>>) {
+        for cal in self.data.calendars.values() {
+            cal.lock().unwrap().set_mock_behaviour(mock_behaviour.clone());
+        }
         self.mock_behaviour = mock_behaviour;
     }
 
@@ -94,13 +110,46 @@ impl Cache {
             }
         }
 
-        Ok(Self{
+        let cache = Self{
             backing_folder: PathBuf::from(folder),
             data,
 
             #[cfg(feature = "local_calendar_mocks_remote_calendars")]
             mock_behaviour: None,
-        })
+        };
+
+        if *crate::config::CHECK_CACHE_ON_LOAD.lock().unwrap() {
+            let found = cache.check();
+            if found.is_empty() {
+                log::debug!("No inconsistency found in cache {:?}", folder);
+            } else {
+                log::warn!("{} inconsistencies found in cache {:?}. Consider running Cache::repair", found.len(), folder);
+                for inconsistency in &found {
+                    log::info!("  {}", inconsistency);
+                }
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Change the URLs of the calendars and items that start with `old_base_url`, so that they start with `new_base_url` instead.
+    ///
+    /// This is useful in case the server has moved (e.g. to a new domain): the next sync will be incremental, instead of considering every calendar and item as brand new.
+    /// This is not a modification of the items, their sync statuses are not altered.
+    pub fn rebase_ids(&mut self, old_base_url: &Url, new_base_url: &Url) {
+        let calendars = std::mem::take(&mut self.data.calendars);
+        for (url, cal) in calendars {
+            let new_url = match crate::utils::rebase_url(&url, old_base_url, new_base_url) {
+                None => url,
+                Some(new_url) => {
+                    log::debug!("Rebasing calendar {} to {}", url, new_url);
+                    cal.lock().unwrap().rebase_urls(old_base_url, new_base_url);
+                    new_url
+                },
+            };
+            self.data.calendars.insert(new_url, cal);
+        }
     }
 
     fn load_calendar(path: &Path) -> Result<CachedCalendar, Box<dyn Error>> {
@@ -123,7 +172,16 @@ impl Cache {
     ///
     /// Note that this is automatically called when `self` is `drop`ped
     pub fn save_to_folder(&self) -> Result<(), std::io::Error> {
-        let folder = &self.backing_folder;
+        self.export_to_folder(&self.backing_folder)
+    }
+
+    /// Store the current Cache to another folder (e.g. to move it to another machine).
+    ///
+    /// Everything a sync relies on (version tags of the items, items that are marked for deletion, etc.) is stored, and no data depends on the folder path.
+    /// This means a Cache loaded from this folder (see [`Cache::from_folder`]) will be able to sync incrementally, just like the current one would.
+    ///
+    /// Calendar files that already exist in this folder are replaced.
+    pub fn export_to_folder(&self, folder: &Path) -> Result<(), std::io::Error> {
         std::fs::create_dir_all(folder)?;
 
         // Save the general data
@@ -132,22 +190,172 @@ impl Cache {
         serde_json::to_writer(file, &self.data)?;
 
         // Save each calendar
+        let mut written = HashSet::new();
         for (cal_url, cal_mutex) in &self.data.calendars {
-            let file_name = sanitize_filename::sanitize(cal_url.as_str()) + ".cal";
-            let cal_file = folder.join(file_name);
+            let file_name = Self::calendar_file_name(cal_url);
+            let cal_file = folder.join(&file_name);
             let file = std::fs::File::create(&cal_file)?;
             let cal = cal_mutex.lock().unwrap();
             serde_json::to_writer(file, &*cal)?;
+            written.insert(OsString::from(file_name));
+        }
+
+        // Only once everything has been written, remove the files of the calendars that are not part of this cache (anymore?), so that they are not loaded next time
+        for entry in std::fs::read_dir(folder)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension() == Some(OsStr::new("cal")) && !written.contains(&entry.file_name()) {
+                std::fs::remove_file(&path)?;
+            }
         }
 
         Ok(())
     }
 
 
+    /// Keep a history of the changes made to each item, up to `limit` entries per item (see [`CachedCalendar::item_history`]). `None` (the default) disables the history, and forgets the one that has been kept so far.
+    ///
+    /// This is disabled by default, because the history is stored along with the calendars. See also [`Cache::prune`]
+    pub fn set_item_history_limit(&mut self, limit: Option<usize>) {
+        for cal in self.data.calendars.values() {
+            cal.lock().unwrap().set_history_limit(limit);
+        }
+        self.data.item_history_limit = limit;
+    }
+
+    /// Keep the calendar at `calendar_id` on this device only (or pair it with the server again).
+    ///
+    /// Syncs skip local-only calendars in both directions, and their remote counterparts are left untouched. This is persisted along with the calendar.
+    /// Once it is not local-only anymore, the next sync compares every item with what is on the server now: changes made on either side in the meantime are merged as usual (and conflicting ones are handled as conflicts), rather than one side overwriting the other
+    pub fn set_local_only(&mut self, calendar_id: &Url, local_only: bool) -> Result<(), Box<dyn Error>> {
+        let cal = self.data.calendars.get(calendar_id)
+            .ok_or_else(|| format!("No calendar {} in the cache", calendar_id))?;
+        cal.lock().unwrap().set_local_only(local_only);
+        Ok(())
+    }
+
+    /// The items whose uploads keep failing, sorted by URL, with their last error (see [`crate::provider::quarantine`]).
+    ///
+    /// Syncs retry them less and less often, until [`Cache::retry_now`] is called or until one of their uploads succeeds
+    pub fn quarantined_items(&self) -> Vec<(Url, UploadFailure)> {
+        let mut items: Vec<(Url, UploadFailure)> = self.data.calendars.values()
+            .flat_map(|cal| cal.lock().unwrap().upload_failures().clone())
+            .collect();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        items
+    }
+
+    /// Make the next sync retry to upload an item whose uploads keep failing (see [`Cache::quarantined_items`]).
+    ///
+    /// Its count of failures is kept, so that it is spaced out again if this upload fails too
+    pub fn retry_now(&mut self, item_id: &Url) -> Result<(), Box<dyn Error>> {
+        for cal in self.data.calendars.values() {
+            let mut cal = cal.lock().unwrap();
+            if let Some(mut failure) = cal.upload_failure(item_id) {
+                failure.next_retry_in = 0;
+                cal.set_upload_failure(item_id, Some(failure));
+                return Ok(());
+            }
+        }
+        Err(format!("Item {} is not quarantined", item_id).into())
+    }
+
+    /// The local changes the next sync will push to the server, in the order they have been made.
+    ///
+    /// Successive changes of an item are coalesced into a single operation: e.g. a task that has been created then modified is uploaded once, and a task that has been created then deleted is not uploaded at all.
+    /// Local-only calendars, calendars that do not push their changes, and items that do not match the filter of their calendar are left out (see [`CalendarSyncPolicy`](crate::provider::CalendarSyncPolicy)).
+    /// See [`Provider::flush`](crate::provider::Provider::flush) to push only these changes
+    pub fn outbound_queue(&self) -> Vec<QueuedOperation> {
+        let mut operations = Vec::new();
+        for (cal_url, cal) in &self.data.calendars {
+            let cal = cal.lock().unwrap();
+            if cal.is_local_only() {
+                continue;
+            }
+            let policy = cal.sync_policy().unwrap_or_default();
+            let items = cal.get_items_sync().unwrap_or_default();
+            operations.extend(outbound::queued_operations(cal_url, &policy, items.into_values(), |url| cal.marked_for_deletion_at(url)));
+        }
+        outbound::sort(&mut operations);
+        operations
+    }
+
+    /// How many requests pushing the [`Cache::outbound_queue`] would take, e.g. to tell whether it is worth it on a metered connection
+    pub fn estimated_requests(&self) -> usize {
+        outbound::estimated_requests(&self.outbound_queue())
+    }
+
+    /// Drop the data that is only kept for information, and that is older than `before`.
+    ///
+    /// For now, these are the entries of the item histories (see [`Cache::set_item_history_limit`]). Returns how many entries have been dropped
+    pub fn prune(&mut self, before: DateTime<Utc>) -> usize {
+        self.data.calendars.values()
+            .map(|cal| cal.lock().unwrap().prune_history(before))
+            .sum()
+    }
+
+    /// Look for inconsistencies in this cache (and in its backing folder), that may have accumulated over time.
+    ///
+    /// This does not modify anything. See [`Cache::repair`] to fix what can be fixed.
+    pub fn check(&self) -> Vec<Inconsistency> {
+        let mut cal_urls: Vec<&Url> = self.data.calendars.keys().collect();
+        cal_urls.sort();
+        let mut found: Vec<Inconsistency> = cal_urls.into_iter()
+            .flat_map(|url| self.data.calendars[url].lock().unwrap().check())
+            .collect();
+        found.extend(self.stray_files().into_iter().map(|path| Inconsistency::StrayFile { path }));
+        found
+    }
+
+    /// Fix the inconsistencies that can be safely fixed (see [`Cache::check`]).
+    ///
+    /// Stray files are removed from the backing folder. Other fixes are done in memory, and are saved the next time the cache is saved
+    pub fn repair(&mut self, policy: &RepairPolicy) -> RepairReport {
+        let mut cal_urls: Vec<Url> = self.data.calendars.keys().cloned().collect();
+        cal_urls.sort();
+        let mut report = RepairReport::default();
+        for url in cal_urls {
+            let cal_report = self.data.calendars[&url].lock().unwrap().repair(policy);
+            report.repaired.extend(cal_report.repaired);
+            report.remaining.extend(cal_report.remaining);
+        }
+
+        for path in self.stray_files() {
+            match std::fs::remove_file(&path) {
+                Ok(()) => report.repaired.push(Inconsistency::StrayFile { path }),
+                Err(err) => {
+                    log::warn!("Unable to remove {:?}: {}", path, err);
+                    report.remaining.push(Inconsistency::StrayFile { path });
+                },
+            }
+        }
+        report
+    }
+
+    /// The calendar files of the backing folder that are not the file of any calendar of this cache
+    fn stray_files(&self) -> Vec<PathBuf> {
+        let expected: Vec<PathBuf> = self.data.calendars.keys()
+            .map(|url| self.backing_folder.join(Self::calendar_file_name(url)))
+            .collect();
+        let mut stray: Vec<PathBuf> = match std::fs::read_dir(&self.backing_folder) {
+            Err(_) => return Vec::new(),
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension() == Some(OsStr::new("cal")) && !expected.contains(path))
+                .collect(),
+        };
+        stray.sort();
+        stray
+    }
+
+    fn calendar_file_name(cal_url: &Url) -> String {
+        sanitize_filename::sanitize(cal_url.as_str()) + ".cal"
+    }
+
     /// Compares two Caches to check they have the same current content
     ///
     /// This is not a complete equality test: some attributes (sync status...) may differ. This should mostly be used in tests
-    #[cfg(any(test, feature = "integration_tests"))]
+    #[cfg(any(test, feature = "test-utils"))]
     pub async fn has_same_observable_content_as(&self, other: &Self) -> Result<bool, Box<dyn Error>> {
         let calendars_l = self.get_calendars().await?;
         let calendars_r = other.get_calendars().await?;
@@ -208,20 +416,36 @@ impl CalDavSource<CachedCalendar> for Cache {
         self.get_calendars_sync()
     }
 
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    fn transfer_counter(&self) -> Option<TransferCounter> {
+        self.mock_behaviour.as_ref().map(|b| b.lock().unwrap().simulated_transfers.clone())
+    }
+
+    fn checkpoint(&self) -> Result<(), Box<dyn Error>> {
+        Ok(self.save_to_folder()?)
+    }
+
     async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<CachedCalendar>>> {
         self.get_calendar_sync(url)
     }
 
     async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<Mutex<CachedCalendar>>, Box<dyn Error>> {
+        self.create_calendar_with_components(url, name, ComponentSet::from(supported_components), color).await
+    }
+
+    async fn create_calendar_with_components(&mut self, url: Url, name: String, components: ComponentSet, color: Option<Color>) -> Result<Arc<Mutex<CachedCalendar>>, Box<dyn Error>> {
         log::debug!("Inserting local calendar {}", url);
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_create_calendar())?;
 
-        let new_calendar = CachedCalendar::new(name, url.clone(), supported_components, color);
+        let mut new_calendar = CachedCalendar::new(name, url.clone(), components.known, color);
+        new_calendar.set_other_components(components.other);
+        new_calendar.set_history_limit(self.data.item_history_limit);
         let arc = Arc::new(Mutex::new(new_calendar));
 
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         if let Some(behaviour) = &self.mock_behaviour {
+            behaviour.lock().unwrap().record_operation("MKCALENDAR", &url);
             arc.lock().unwrap().set_mock_behaviour(Some(Arc::clone(behaviour)));
         };
 
@@ -232,12 +456,70 @@ impl CalDavSource<CachedCalendar> for Cache {
     }
 }
 
+impl Cache {
+    /// Returns the calendar matching the URL, or an error in case there is none
+    fn existing_calendar(&self, url: &Url) -> Result<Arc<Mutex<CachedCalendar>>, Box<dyn Error>> {
+        self.get_calendar_sync(url).ok_or_else(|| format!("No calendar {} in the cache", url).into())
+    }
+}
+
+#[async_trait(?Send)]
+impl SourceAdapter<CachedCalendar> for Cache {
+    async fn list_item_stubs(&self, calendar: &Url) -> Result<HashMap<Url, SyncStatus>, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar)?;
+        let cal = cal.lock().unwrap();
+        Ok(cal.get_items_sync()?
+            .into_iter()
+            .map(|(url, item)| (url, item.sync_status().clone()))
+            .collect())
+    }
+
+    async fn fetch_items(&self, calendar: &Url, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar)?;
+        let cal = cal.lock().unwrap();
+        Ok(urls.iter().map(|url| cal.get_item_by_url_sync(url).cloned()).collect())
+    }
+
+    async fn upsert_item(&mut self, calendar: &Url, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar)?;
+        let mut cal = cal.lock().unwrap();
+        match cal.get_item_by_url_sync(item.url()) {
+            None => cal.add_item_sync(item),
+            Some(_) => cal.update_item_sync(item),
+        }
+    }
+
+    async fn delete_item(&mut self, calendar: &Url, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar)?;
+        let mut cal = cal.lock().unwrap();
+        cal.immediately_delete_item_sync(item_url)
+    }
+
+    async fn sync_metadata(&self, calendar: &Url) -> Result<SyncMetadata, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar)?;
+        let cal = cal.lock().unwrap();
+        Ok(SyncMetadata {
+            ctag: cal.last_synced_ctag().cloned(),
+            sync_token: cal.last_sync_token().map(String::from),
+        })
+    }
+
+    async fn set_sync_metadata(&mut self, calendar: &Url, metadata: SyncMetadata) -> Result<(), Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar)?;
+        let mut cal = cal.lock().unwrap();
+        cal.set_last_synced_ctag(metadata.ctag);
+        cal.set_last_sync_token(metadata.sync_token);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use url::Url;
     use crate::calendar::SupportedComponents;
+    use crate::calendar::CalendarFilter;
     use crate::item::Item;
     use crate::task::Task;
 
@@ -288,6 +570,219 @@ mod tests {
         assert_eq!(test.unwrap(), true);
     }
 
+    #[tokio::test]
+    async fn cache_supported_components_compatibility() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/components_test"));
+        let cache = populate_cache(&cache_path).await;
+        cache.save_to_folder().unwrap();
+
+        let shopping_file = cache_path.join(sanitize_filename::sanitize("https://caldav.com/shopping") + ".cal");
+        let bucket_file = cache_path.join(sanitize_filename::sanitize("https://caldav.com/bucket-list") + ".cal");
+        let shopping_content = std::fs::read_to_string(&shopping_file).unwrap();
+        assert!(shopping_content.contains(r#""supported_components":["VTODO"]"#));
+
+        // A file written by a former version of this crate (that stored the raw flags)...
+        std::fs::write(&shopping_file, shopping_content.replace(r#"["VTODO"]"#, r#"{"bits":3}"#)).unwrap();
+        // ...and a file written by a newer version, that knows about more components
+        let bucket_content = std::fs::read_to_string(&bucket_file).unwrap();
+        std::fs::write(&bucket_file, bucket_content.replace(r#"["VTODO"]"#, r#"["VJOURNAL","VTODO"]"#)).unwrap();
+
+        let retrieved_cache = Cache::from_folder(&cache_path).unwrap();
+        let shopping = retrieved_cache.get_calendar_sync(&Url::parse("https://caldav.com/shopping").unwrap()).unwrap();
+        assert_eq!(shopping.lock().unwrap().supported_components(), SupportedComponents::EVENT | SupportedComponents::TODO);
+        let bucket_list = retrieved_cache.get_calendar_sync(&Url::parse("https://caldav.com/bucket-list").unwrap()).unwrap();
+        assert_eq!(bucket_list.lock().unwrap().supported_components(), SupportedComponents::TODO);
+        assert_eq!(bucket_list.lock().unwrap().get_items_sync().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn cache_check_and_repair() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/check_test"));
+        let bucket_list_url = Url::parse("https://caldav.com/bucket-list").unwrap();
+        {
+            let cache = populate_cache(&cache_path).await;
+            let bucket_list = cache.get_calendar_sync(&bucket_list_url).unwrap();
+            for name in &["Visit Petra", "See the northern lights"] {
+                bucket_list.lock().unwrap().add_item_sync(Item::Task(Task::new(name.to_string(), false, &bucket_list_url))).unwrap();
+            }
+            assert!(cache.check().is_empty());
+            cache.save_to_folder().unwrap();
+        }
+
+        // Corrupt the files
+        let bucket_file = cache_path.join(sanitize_filename::sanitize(bucket_list_url.as_str()) + ".cal");
+        let mut json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&bucket_file).unwrap()).unwrap();
+        let items = json["items"].as_object_mut().unwrap();
+        let mut keys: Vec<String> = items.keys().cloned().collect();
+        keys.sort();
+        let (misindexed, orphan, invalid, duplicate) = (&keys[0], &keys[1], &keys[2], &keys[3]);
+        let first_uid = items[misindexed]["Task"]["uid"].clone();
+        let moved = items.remove(misindexed).unwrap();
+        items.insert("https://caldav.com/wrong-key".to_string(), moved);
+        let mut moved = items.remove(orphan).unwrap();
+        moved["Task"]["url"] = serde_json::json!("https://elsewhere.com/orphan");
+        items.insert("https://elsewhere.com/orphan".to_string(), moved);
+        items[invalid]["Task"]["sync_status"] = serde_json::json!({"LocallyModified": {"tag": ""}});
+        items[duplicate]["Task"]["uid"] = first_uid.clone();
+        std::fs::write(&bucket_file, json.to_string()).unwrap();
+        let shopping_file = cache_path.join(sanitize_filename::sanitize("https://caldav.com/shopping") + ".cal");
+        std::fs::copy(&shopping_file, cache_path.join("stray.cal")).unwrap();
+
+        let _check_on_load = crate::config::OverrideGuard::set(&crate::config::CHECK_CACHE_ON_LOAD, true);
+        let mut cache = Cache::from_folder(&cache_path).unwrap();
+        let url = |s: &str| Url::parse(s).unwrap();
+        let duplicated_uid = Inconsistency::DuplicateUid {
+            calendar: bucket_list_url.clone(),
+            uid: first_uid.as_str().unwrap().to_string(),
+            items: { let mut v = vec![url(misindexed), url(duplicate)]; v.sort(); v },
+        };
+        let found = cache.check();
+        let expected = vec![
+            Inconsistency::OrphanItem { calendar: bucket_list_url.clone(), item: url("https://elsewhere.com/orphan") },
+            Inconsistency::InvalidSyncStatus { calendar: bucket_list_url.clone(), item: url(invalid) },
+            Inconsistency::MisindexedItem { calendar: bucket_list_url.clone(), key: url("https://caldav.com/wrong-key"), item: url(misindexed) },
+            duplicated_uid.clone(),
+            Inconsistency::StrayFile { path: cache_path.join("stray.cal") },
+        ];
+        assert_eq!(found.len(), expected.len());
+        for inconsistency in &expected {
+            assert!(found.contains(inconsistency), "{} has not been found", inconsistency);
+        }
+
+        // Orphans are kept, unless the policy allows dropping them
+        let orphan_item = Inconsistency::OrphanItem { calendar: bucket_list_url.clone(), item: url("https://elsewhere.com/orphan") };
+        let report = cache.repair(&RepairPolicy::default());
+        assert_eq!(report.repaired.len(), 3);
+        assert_eq!(report.remaining.len(), 2);
+        assert!(report.remaining.contains(&orphan_item));
+        let report = cache.repair(&RepairPolicy { drop_orphans: true });
+        assert_eq!(report.repaired, vec![orphan_item]);
+        assert_eq!(report.remaining, vec![duplicated_uid.clone()]);
+        cache.save_to_folder().unwrap();
+
+        let repaired_cache = Cache::from_folder(&cache_path).unwrap();
+        assert_eq!(repaired_cache.check(), vec![duplicated_uid]);
+        let bucket_list = repaired_cache.get_calendar_sync(&bucket_list_url).unwrap();
+        let bucket_list = bucket_list.lock().unwrap();
+        assert_eq!(bucket_list.get_items_sync().unwrap().len(), 3);
+        assert_eq!(bucket_list.get_item_by_url_sync(&url(invalid)).unwrap().sync_status(), &crate::item::SyncStatus::NotSynced);
+    }
+
+    #[tokio::test]
+    async fn cache_check_stale_deletions() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/stale_deletions_test"));
+        let bucket_list_url = Url::parse("https://caldav.com/bucket-list").unwrap();
+        let (never_synced, synced) = {
+            let cache = populate_cache(&cache_path).await;
+            let bucket_list = cache.get_calendar_sync(&bucket_list_url).unwrap();
+            let mut bucket_list = bucket_list.lock().unwrap();
+            let mut urls: Vec<Url> = bucket_list.get_item_urls_sync().unwrap().into_iter().collect();
+            urls.sort();
+            let (never_synced, synced) = (urls[0].clone(), urls[1].clone());
+            bucket_list.get_item_by_url_mut_sync(&synced).unwrap().set_sync_status(crate::item::SyncStatus::Synced(crate::item::VersionTag::from("1".to_string())));
+
+            // Items that have never been synced need no tombstone
+            bucket_list.mark_for_deletion_sync(&never_synced).unwrap();
+            assert!(bucket_list.get_item_by_url_sync(&never_synced).is_none());
+            bucket_list.mark_for_deletion_sync(&synced).unwrap();
+            assert!(bucket_list.get_item_by_url_sync(&synced).is_some());
+            drop(bucket_list);
+            assert!(cache.check().is_empty());
+            cache.save_to_folder().unwrap();
+            (never_synced, synced)
+        };
+
+        // Pretend the deletion has been pending for a long time
+        let bucket_file = cache_path.join(sanitize_filename::sanitize(bucket_list_url.as_str()) + ".cal");
+        let mut json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&bucket_file).unwrap()).unwrap();
+        let marked_at = chrono::Utc::now() - *crate::config::TOMBSTONE_WINDOW.lock().unwrap() - chrono::Duration::days(1);
+        json["marked_for_deletion_at"][synced.as_str()] = serde_json::json!(marked_at);
+        std::fs::write(&bucket_file, json.to_string()).unwrap();
+
+        let mut cache = Cache::from_folder(&cache_path).unwrap();
+        let stale = Inconsistency::StaleDeletion { calendar: bucket_list_url.clone(), item: synced.clone(), marked_at };
+        assert_eq!(cache.check(), vec![stale.clone()]);
+        // Only a sync can fix it
+        let report = cache.repair(&RepairPolicy::default());
+        assert_eq!(report.remaining, vec![stale]);
+        let bucket_list = cache.get_calendar_sync(&bucket_list_url).unwrap();
+        assert!(bucket_list.lock().unwrap().get_item_by_url_sync(&never_synced).is_none());
+        bucket_list.lock().unwrap().immediately_delete_item_sync(&synced).unwrap();
+        assert!(cache.check().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cache_rebase_ids() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/rebase_test"));
+        let mut cache = populate_cache(&cache_path).await;
+
+        let old_base = Url::parse("https://caldav.com/").unwrap();
+        let new_base = Url::parse("https://new.caldav.org/dav/").unwrap();
+        cache.rebase_ids(&old_base, &new_base);
+
+        let new_bucket_list_url = Url::parse("https://new.caldav.org/dav/bucket-list").unwrap();
+        let calendars = cache.get_calendars_sync().unwrap();
+        assert_eq!(calendars.len(), 2);
+        let bucket_list = calendars.get(&new_bucket_list_url).unwrap().lock().unwrap();
+        assert_eq!(bucket_list.url(), &new_bucket_list_url);
+        for (url, item) in bucket_list.get_items_sync().unwrap() {
+            assert!(url.as_str().starts_with("https://new.caldav.org/dav/"));
+            assert_eq!(item.url(), &url);
+        }
+        drop(bucket_list);
+
+        // Files of the former calendars must not be loaded again
+        cache.save_to_folder().unwrap();
+        let retrieved_cache = Cache::from_folder(&cache_path).unwrap();
+        let test = cache.has_same_observable_content_as(&retrieved_cache).await;
+        assert_eq!(test.unwrap(), true);
+    }
+
+    #[tokio::test]
+    async fn cache_calendars_matching() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/matching_test"));
+        let mut cache = populate_cache(&cache_path).await;
+
+        let _agenda = cache.create_calendar(
+            Url::parse("https://caldav.com/agenda").unwrap(),
+            "Agenda".to_string(),
+            SupportedComponents::EVENT | SupportedComponents::TODO,
+            None,
+        ).await.unwrap();
+        let shopping_list = cache.get_calendar_sync(&Url::parse("https://caldav.com/shopping").unwrap()).unwrap();
+        shopping_list.lock().unwrap().set_writable(false);
+
+        let names = |cals: Vec<Arc<Mutex<CachedCalendar>>>| -> Vec<String> {
+            cals.iter().map(|cal| cal.lock().unwrap().name().to_string()).collect()
+        };
+
+        let all = cache.get_calendars_matching(&CalendarFilter::default()).await.unwrap();
+        assert_eq!(names(all), vec!["Agenda", "My bucket list", "My shopping list"]);
+
+        let writable_task_lists = cache.get_calendars_matching(&CalendarFilter {
+            components: Some(SupportedComponents::TODO),
+            writable: Some(true),
+        }).await.unwrap();
+        assert_eq!(names(writable_task_lists), vec!["Agenda", "My bucket list"]);
+
+        let read_only = cache.get_calendars_matching(&CalendarFilter {
+            writable: Some(false),
+            ..CalendarFilter::default()
+        }).await.unwrap();
+        assert_eq!(names(read_only), vec!["My shopping list"]);
+
+        let events_and_tasks = cache.get_calendars_matching(&CalendarFilter {
+            components: Some(SupportedComponents::EVENT | SupportedComponents::TODO),
+            ..CalendarFilter::default()
+        }).await.unwrap();
+        assert_eq!(names(events_and_tasks), vec!["Agenda"]);
+    }
+
     #[tokio::test]
     async fn cache_sanity_checks() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -303,4 +798,32 @@ mod tests {
         ).await;
         assert!(second_addition_same_calendar.is_err());
     }
+
+    #[tokio::test]
+    async fn cache_source_adapter() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/source_adapter"));
+        let mut cache = populate_cache(&cache_path).await;
+        let cal_url = Url::parse("https://caldav.com/shopping").unwrap();
+
+        let mut task = Task::new(String::from("Buy milk"), false, &cal_url);
+        let task_url = task.url().clone();
+        assert_eq!(cache.upsert_item(&cal_url, Item::Task(task.clone())).await.unwrap(), SyncStatus::NotSynced);
+        task.set_name(String::from("Buy oat milk"));
+        cache.upsert_item(&cal_url, Item::Task(task)).await.unwrap();
+
+        let stubs = cache.list_item_stubs(&cal_url).await.unwrap();
+        assert_eq!(stubs.into_iter().collect::<Vec<_>>(), vec![(task_url.clone(), SyncStatus::NotSynced)]);
+        let fetched = cache.fetch_items(&cal_url, &[task_url.clone(), cal_url.join("missing.ics").unwrap()]).await.unwrap();
+        assert_eq!(fetched[0].as_ref().map(|item| item.name()), Some("Buy oat milk"));
+        assert!(fetched[1].is_none());
+
+        let metadata = SyncMetadata { ctag: Some(crate::item::VersionTag::from(String::from("ctag-1"))), sync_token: Some(String::from("token-1")) };
+        cache.set_sync_metadata(&cal_url, metadata.clone()).await.unwrap();
+        assert_eq!(cache.sync_metadata(&cal_url).await.unwrap(), metadata);
+
+        cache.delete_item(&cal_url, &task_url).await.unwrap();
+        assert!(cache.list_item_stubs(&cal_url).await.unwrap().is_empty());
+        assert!(cache.list_item_stubs(&Url::parse("https://caldav.com/missing").unwrap()).await.is_err());
+    }
 }
@@ -0,0 +1,60 @@
+//! Consistency checks of a [`Cache`](crate::cache::Cache) (see [`Cache::check`](crate::cache::Cache::check) and [`Cache::repair`](crate::cache::Cache::repair))
+
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+/// Something that is wrong in a cache
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inconsistency {
+    /// An item is stored under another URL than its own
+    MisindexedItem { calendar: Url, key: Url, item: Url },
+    /// An item whose URL is not in the folder of its calendar
+    OrphanItem { calendar: Url, item: Url },
+    /// An item has a sync status that refers to an empty version tag, which can never be the case of an item that has actually been synced
+    InvalidSyncStatus { calendar: Url, item: Url },
+    /// An item has been marked for deletion for longer than [`TOMBSTONE_WINDOW`](crate::config::TOMBSTONE_WINDOW), i.e. its deletion has never been pushed to the server
+    StaleDeletion { calendar: Url, item: Url, marked_at: DateTime<Utc> },
+    /// Several items of a calendar share the same UID
+    DuplicateUid { calendar: Url, uid: String, items: Vec<Url> },
+    /// These tasks are ancestors of themselves (see [`crate::calendar::hierarchy::Anomaly::Cycle`])
+    ParentCycle { calendar: Url, items: Vec<Url> },
+    /// This task has more ancestors than the limit (see [`crate::calendar::hierarchy::Anomaly::TooDeep`])
+    HierarchyTooDeep { calendar: Url, item: Url, limit: usize },
+    /// A calendar file of the cache folder that does not belong to any calendar of the cache (e.g. because two files describe the same calendar)
+    StrayFile { path: PathBuf },
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::MisindexedItem { calendar, key, item } => write!(f, "item {} is stored as {} in calendar {}", item, key, calendar),
+            Self::OrphanItem { calendar, item } => write!(f, "item {} does not belong to calendar {}", item, calendar),
+            Self::InvalidSyncStatus { calendar, item } => write!(f, "item {} of calendar {} has an invalid sync status", item, calendar),
+            Self::StaleDeletion { calendar, item, marked_at } => write!(f, "item {} of calendar {} has been marked for deletion since {}, but has never been deleted", item, calendar, marked_at),
+            Self::DuplicateUid { calendar, uid, items } => write!(f, "items {:?} of calendar {} share the UID {}", items, calendar, uid),
+            Self::ParentCycle { calendar, items } => write!(f, "items {:?} of calendar {} are ancestors of themselves", items, calendar),
+            Self::HierarchyTooDeep { calendar, item, limit } => write!(f, "item {} of calendar {} has more than {} ancestors", item, calendar, limit),
+            Self::StrayFile { path } => write!(f, "file {:?} does not belong to any calendar", path),
+        }
+    }
+}
+
+/// What [`Cache::repair`](crate::cache::Cache::repair) is allowed to fix
+#[derive(Clone, Debug, Default)]
+pub struct RepairPolicy {
+    /// Whether items that do not belong to their calendar are deleted. Otherwise (the default), they are kept and reported as remaining inconsistencies.
+    /// Such items may never have been synced, so that they would be lost for good
+    pub drop_orphans: bool,
+}
+
+/// What [`Cache::repair`](crate::cache::Cache::repair) has done
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    /// The inconsistencies that have been fixed
+    pub repaired: Vec<Inconsistency>,
+    /// The inconsistencies that could not be fixed safely (e.g. duplicated UIDs, that need a decision of the user)
+    pub remaining: Vec<Inconsistency>,
+}
@@ -0,0 +1,116 @@
+//! The local changes that have not been pushed to the server yet (see [`Cache::outbound_queue`](crate::cache::Cache::outbound_queue))
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+use crate::Item;
+use crate::item::SyncStatus;
+use crate::provider::CalendarSyncPolicy;
+
+/// What pushing a local change to the server takes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutboundKind {
+    /// The item has been created locally (and maybe modified since). It is uploaded with a single request
+    Create,
+    /// The item has been modified locally
+    Update,
+    /// The item has been deleted locally
+    Delete,
+}
+
+/// A local change that has not been pushed to the server yet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueuedOperation {
+    pub calendar: Url,
+    pub item: Url,
+    pub kind: OutboundKind,
+    /// When the change has been made, i.e. when the item has last been modified (or marked for deletion)
+    pub queued_at: DateTime<Utc>,
+}
+
+/// The operations that push the local changes of a calendar, in no particular order.
+///
+/// Items have at most one operation, because their sync status only tells the net effect of their changes:
+/// an item that has been created then modified is uploaded once, and an item that has been created then deleted is not in the calendar anymore
+pub(crate) fn queued_operations<'a, F>(cal_url: &Url, policy: &CalendarSyncPolicy, items: impl IntoIterator<Item = &'a Item>, marked_for_deletion_at: F) -> Vec<QueuedOperation>
+where
+    F: Fn(&Url) -> Option<DateTime<Utc>>,
+{
+    if !policy.direction.pushes() {
+        return Vec::new();
+    }
+    items.into_iter()
+        .filter_map(|item| {
+            let kind = match item.sync_status() {
+                SyncStatus::Synced(_) => return None,
+                SyncStatus::NotSynced => OutboundKind::Create,
+                SyncStatus::LocallyModified(_) => OutboundKind::Update,
+                SyncStatus::LocallyDeleted(_) => OutboundKind::Delete,
+            };
+            // Just like syncs do, the filter does not apply to deletions
+            if kind != OutboundKind::Delete && !policy.filter.matches(item) {
+                return None;
+            }
+            let queued_at = match kind {
+                OutboundKind::Delete => marked_for_deletion_at(item.url()).unwrap_or(*item.last_modified()),
+                _ => *item.last_modified(),
+            };
+            Some(QueuedOperation { calendar: cal_url.clone(), item: item.url().clone(), kind, queued_at })
+        })
+        .collect()
+}
+
+/// Sort operations in the order their changes have been made (and by URL, for changes made at the same time)
+pub(crate) fn sort(operations: &mut [QueuedOperation]) {
+    operations.sort_by(|a, b| a.queued_at.cmp(&b.queued_at).then_with(|| a.item.cmp(&b.item)));
+}
+
+/// How many requests pushing these operations takes: one per operation, and one per calendar to check that their items have not changed on the server in the meantime
+pub fn estimated_requests(operations: &[QueuedOperation]) -> usize {
+    let mut calendars: Vec<&Url> = operations.iter().map(|op| &op.calendar).collect();
+    calendars.sort();
+    calendars.dedup();
+    operations.len() + calendars.len()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Task;
+    use crate::item::VersionTag;
+    use crate::provider::SyncDirection;
+
+    #[test]
+    fn test_queued_operations() {
+        let cal_url: Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+        let tag = VersionTag::from("\"1\"".to_string());
+        let new_item = |name: &str, completed: bool, status: SyncStatus| {
+            let mut item = Item::Task(Task::new(name.to_string(), completed, &cal_url));
+            item.set_sync_status(status);
+            item
+        };
+        let items = [
+            new_item("Created", false, SyncStatus::NotSynced),
+            new_item("Created and completed", true, SyncStatus::NotSynced),
+            new_item("Synced", false, SyncStatus::Synced(tag.clone())),
+            new_item("Modified", false, SyncStatus::LocallyModified(tag.clone())),
+            new_item("Deleted", true, SyncStatus::LocallyDeleted(tag)),
+        ];
+
+        let mut policy = CalendarSyncPolicy::default();
+        policy.filter.skip_completed = true;
+        let operations = queued_operations(&cal_url, &policy, &items, |_| None);
+        let mut kinds: Vec<(&Url, OutboundKind)> = operations.iter().map(|op| (&op.item, op.kind)).collect();
+        kinds.sort_by_key(|(url, _)| items.iter().position(|item| item.url() == *url));
+        assert_eq!(kinds, vec![
+            (items[0].url(), OutboundKind::Create),
+            (items[3].url(), OutboundKind::Update),
+            (items[4].url(), OutboundKind::Delete),
+        ]);
+        assert_eq!(estimated_requests(&operations), 4);
+
+        policy.direction = SyncDirection::PullOnly;
+        assert!(queued_operations(&cal_url, &policy, &items, |_| None).is_empty());
+    }
+}
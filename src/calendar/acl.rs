@@ -0,0 +1,320 @@
+//! Who can access a (shared) calendar. See [`RemoteCalendar::acl`](crate::calendar::remote_calendar::RemoteCalendar::acl)
+//!
+//! This gathers both the WebDAV access control list (RFC 3744) and the sharing invitations of CalendarServer-based servers (Apple, Nextcloud...).
+//! This is read-only: modifying who can access a calendar is not supported.
+
+use minidom::Element;
+use reqwest::StatusCode;
+
+use crate::utils::{find_elem, find_elems};
+
+/// The `PROPFIND` body that asks for the access control properties of a calendar
+pub(crate) static ACL_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/">
+        <d:prop>
+            <d:owner />
+            <d:acl />
+            <cs:invite />
+        </d:prop>
+    </d:propfind>
+"#;
+
+/// Who an access control entry applies to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Principal {
+    /// A principal (a user or a group), identified by its URL (or any other URI the server uses, e.g. `mailto:alice@example.com`)
+    Href(String),
+    /// Everyone, authenticated or not
+    All,
+    /// Every authenticated user
+    Authenticated,
+    /// Users that are not authenticated
+    Unauthenticated,
+    /// The current user
+    CurrentUser,
+    /// The principal a property of the calendar points to (usually `owner`)
+    Property(String),
+}
+
+/// A summary of the privileges granted to a principal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    /// The principal can read the calendar, and nothing else
+    Read,
+    /// The principal can read and modify the items of the calendar
+    ReadWrite,
+    /// The principal can do everything, including changing who can access the calendar
+    All,
+}
+
+/// Where an [`AclEntry`] has been found
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclSource {
+    /// The WebDAV access control list (`DAV:acl`)
+    Acl,
+    /// A sharing invitation (CalendarServer's `invite` property)
+    Invite,
+}
+
+/// The access a principal has been granted on a calendar
+#[derive(Clone, Debug, PartialEq)]
+pub struct AclEntry {
+    pub principal: Principal,
+    /// The name of the principal, if the server told it
+    pub display_name: Option<String>,
+    /// A summary of the privileges below, or `None` in case they do not include reading or writing (e.g. `read-free-busy` only)
+    pub access: Option<Access>,
+    /// The names of the privileges, as the server reported them (e.g. `read`, `write-content` or `read-write` for invitations)
+    pub privileges: Vec<String>,
+    pub source: AclSource,
+}
+
+/// Who can access a calendar
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Acl {
+    /// The principals that have been granted access to the calendar. Denials are not listed
+    pub entries: Vec<AclEntry>,
+    /// The URL of the owner of the calendar, if the server told it
+    pub owner: Option<String>,
+    /// Whether the server refused to disclose (some of) the access control properties.
+    /// In this case, `entries` may be incomplete (or empty), but this is not an error: most servers only disclose ACLs to the owner of a calendar
+    pub forbidden: bool,
+}
+
+impl Acl {
+    /// The entries that apply to someone else than the owner of the calendar, e.g. to display who a calendar is shared with
+    pub fn shared_with(&self) -> impl Iterator<Item = &AclEntry> {
+        self.entries.iter().filter(move |entry| match &entry.principal {
+            Principal::Href(href) => Some(href) != self.owner.as_ref(),
+            Principal::Property(property) => property != "owner",
+            Principal::CurrentUser => false,
+            _ => true,
+        })
+    }
+
+    /// Add an entry, or merge it into the entry of the same principal
+    fn add(&mut self, entry: AclEntry) {
+        match self.entries.iter_mut().find(|existing| existing.principal == entry.principal) {
+            None => self.entries.push(entry),
+            Some(existing) => {
+                if existing.display_name.is_none() {
+                    existing.display_name = entry.display_name;
+                }
+                existing.access = existing.access.max(entry.access);
+                for privilege in entry.privileges {
+                    if !existing.privileges.contains(&privilege) {
+                        existing.privileges.push(privilege);
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Parse the reply to an [`ACL_BODY`] `PROPFIND`
+pub(crate) fn parse_acl_reply(reply: &Element) -> Acl {
+    let mut acl = Acl::default();
+    for propstat in find_elems(reply, "propstat") {
+        let status = find_elem(propstat, "status").and_then(|status| parse_status(&status.text()));
+        match status {
+            Some(status) if status.is_success() => (),
+            Some(StatusCode::FORBIDDEN) | Some(StatusCode::UNAUTHORIZED) => {
+                acl.forbidden = true;
+                continue;
+            },
+            // Typically a 404 for properties this server does not support
+            _ => continue,
+        }
+
+        if let Some(owner) = find_elem(propstat, "owner").and_then(|owner| find_elem(owner, "href")) {
+            acl.owner = Some(owner.text().trim().to_string());
+        }
+        for ace in find_elems(propstat, "ace") {
+            if let Some(entry) = parse_ace(ace) {
+                acl.add(entry);
+            }
+        }
+        if let Some(invite) = find_elem(propstat, "invite") {
+            for user in invite.children().filter(|el| el.name() == "user") {
+                if let Some(entry) = parse_invite(user) {
+                    acl.add(entry);
+                }
+            }
+        }
+    }
+    acl
+}
+
+fn parse_status(status_line: &str) -> Option<StatusCode> {
+    status_line.split_whitespace().nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+}
+
+fn parse_ace(ace: &Element) -> Option<AclEntry> {
+    let grant = match ace.children().find(|el| el.name() == "grant") {
+        None => {
+            log::debug!("Ignoring an access control entry that grants nothing");
+            return None;
+        },
+        Some(grant) => grant,
+    };
+    let principal = ace.children().find(|el| el.name() == "principal")?;
+    let principal = match principal.children().next()? {
+        el if el.name() == "href" => Principal::Href(el.text().trim().to_string()),
+        el if el.name() == "all" => Principal::All,
+        el if el.name() == "authenticated" => Principal::Authenticated,
+        el if el.name() == "unauthenticated" => Principal::Unauthenticated,
+        el if el.name() == "self" => Principal::CurrentUser,
+        el if el.name() == "property" => Principal::Property(el.children().next()?.name().to_string()),
+        el => {
+            log::warn!("Unsupported principal type {} in an access control entry", el.name());
+            return None;
+        },
+    };
+
+    let privileges: Vec<String> = grant.children()
+        .filter(|privilege| privilege.name() == "privilege")
+        .flat_map(|privilege| privilege.children())
+        .map(|privilege| privilege.name().to_string())
+        .collect();
+    let access = privileges.iter()
+        .filter_map(|privilege| match privilege.as_str() {
+            "all" => Some(Access::All),
+            "write" | "write-content" | "bind" => Some(Access::ReadWrite),
+            "read" => Some(Access::Read),
+            _ => None,
+        })
+        .max();
+
+    Some(AclEntry { principal, display_name: None, access, privileges, source: AclSource::Acl })
+}
+
+fn parse_invite(user: &Element) -> Option<AclEntry> {
+    let href = find_elem(user, "href")?.text().trim().to_string();
+    let display_name = find_elem(user, "common-name").map(|name| name.text().trim().to_string());
+    if user.children().any(|el| el.name() == "invite-declined" || el.name() == "invite-deleted") {
+        return None;
+    }
+
+    let privileges: Vec<String> = find_elem(user, "access")
+        .map(|access| access.children().map(|el| el.name().to_string()).collect())
+        .unwrap_or_default();
+    let access = privileges.iter()
+        .filter_map(|privilege| match privilege.as_str() {
+            "read-write" | "read-write-schedule" => Some(Access::ReadWrite),
+            "read" => Some(Access::Read),
+            _ => None,
+        })
+        .max();
+
+    Some(AclEntry { principal: Principal::Href(href), display_name, access, privileges, source: AclSource::Invite })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHARED_CALENDAR: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/">
+  <d:response>
+    <d:href>/calendars/john/tasks/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:owner><d:href>/principals/users/john/</d:href></d:owner>
+        <d:acl>
+          <d:ace>
+            <d:principal><d:href>/principals/users/john/</d:href></d:principal>
+            <d:grant><d:privilege><d:all/></d:privilege></d:grant>
+            <d:protected/>
+          </d:ace>
+          <d:ace>
+            <d:principal><d:href>/principals/users/alice/</d:href></d:principal>
+            <d:grant><d:privilege><d:read/></d:privilege></d:grant>
+          </d:ace>
+          <d:ace>
+            <d:principal><d:href>/principals/users/bob/</d:href></d:principal>
+            <d:grant><d:privilege><d:read/></d:privilege><d:privilege><d:write-content/></d:privilege></d:grant>
+          </d:ace>
+          <d:ace>
+            <d:principal><d:authenticated/></d:principal>
+            <d:grant><d:privilege><c:read-free-busy xmlns:c="urn:ietf:params:xml:ns:caldav"/></d:privilege></d:grant>
+          </d:ace>
+          <d:ace>
+            <d:principal><d:all/></d:principal>
+            <d:deny><d:privilege><d:write/></d:privilege></d:deny>
+          </d:ace>
+        </d:acl>
+        <cs:invite>
+          <cs:organizer><d:href>/principals/users/john/</d:href></cs:organizer>
+          <cs:user>
+            <d:href>/principals/users/bob/</d:href>
+            <cs:common-name>Bob</cs:common-name>
+            <cs:invite-accepted/>
+            <cs:access><cs:read-write/></cs:access>
+          </cs:user>
+          <cs:user>
+            <d:href>mailto:carol@example.com</d:href>
+            <cs:common-name>Carol</cs:common-name>
+            <cs:invite-noresponse/>
+            <cs:access><cs:read/></cs:access>
+          </cs:user>
+          <cs:user>
+            <d:href>mailto:dave@example.com</d:href>
+            <cs:invite-declined/>
+            <cs:access><cs:read/></cs:access>
+          </cs:user>
+        </cs:invite>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>
+"#;
+
+    #[test]
+    fn test_parse_acl() {
+        let acl = parse_acl_reply(&SHARED_CALENDAR.parse().unwrap());
+        assert!(!acl.forbidden);
+        assert_eq!(acl.owner.as_deref(), Some("/principals/users/john/"));
+
+        let summary: Vec<(Principal, Option<String>, Option<Access>)> = acl.entries.iter()
+            .map(|entry| (entry.principal.clone(), entry.display_name.clone(), entry.access))
+            .collect();
+        assert_eq!(summary, vec![
+            (Principal::Href("/principals/users/john/".to_string()), None, Some(Access::All)),
+            (Principal::Href("/principals/users/alice/".to_string()), None, Some(Access::Read)),
+            (Principal::Href("/principals/users/bob/".to_string()), Some("Bob".to_string()), Some(Access::ReadWrite)),
+            (Principal::Authenticated, None, None),
+            (Principal::Href("mailto:carol@example.com".to_string()), Some("Carol".to_string()), Some(Access::Read)),
+        ]);
+        assert_eq!(acl.entries[2].privileges, vec!["read", "write-content", "read-write"]);
+        assert_eq!(acl.shared_with().count(), 4);
+    }
+
+    #[test]
+    fn test_forbidden_acl() {
+        let reply = r#"<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/calendars/alice/shared/</d:href>
+    <d:propstat>
+      <d:prop><d:owner><d:href>/principals/users/alice/</d:href></d:owner></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop><d:acl/></d:prop>
+      <d:status>HTTP/1.1 403 Forbidden</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop><cs:invite xmlns:cs="http://calendarserver.org/ns/"/></d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let acl = parse_acl_reply(&reply.parse().unwrap());
+        assert!(acl.forbidden);
+        assert!(acl.entries.is_empty());
+        assert_eq!(acl.owner.as_deref(), Some("/principals/users/alice/"));
+    }
+}
@@ -1,21 +1,33 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
 use csscolorparser::Color;
 use url::Url;
 
-use crate::item::SyncStatus;
+use crate::item::{SyncStatus, VersionTag};
 use crate::traits::{BaseCalendar, CompleteCalendar};
 use crate::calendar::SupportedComponents;
+use crate::calendar::duplicates::{self, DuplicateCriteria, MergeRecord};
+use crate::calendar::history::{self, ChangeKind, ChangeSource, HistoryEntry};
+use crate::calendar::hierarchy::{Anomaly, Hierarchy};
+use crate::cache::check::{Inconsistency, RepairPolicy, RepairReport};
+use crate::provider::CalendarSyncPolicy;
+use crate::provider::quarantine::UploadFailure;
+use crate::provider::fidelity::AlteredUpload;
 use crate::Item;
+use crate::Task;
 
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
 use std::sync::{Arc, Mutex};
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
 use crate::mock_behaviour::MockBehaviour;
 
+/// The property a mocked server adds to the items it rewrites (see [`MockBehaviour::rewrite_uploads`])
+#[cfg(feature = "local_calendar_mocks_remote_calendars")]
+const MOCK_REWRITE_PROPERTY: &str = "X-MOCK-SERVER-REWRITTEN";
 
 /// A calendar used by the [`cache`](crate::cache) module
 ///
@@ -26,7 +38,52 @@ pub struct CachedCalendar {
     name: String,
     url: Url,
     supported_components: SupportedComponents,
+    /// See [`BaseCalendar::other_components`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    other_components: Vec<String>,
     color: Option<Color>,
+    /// Whether the remote counterpart of this calendar could not be accessed during the last sync
+    #[serde(default)]
+    unavailable: bool,
+    /// Whether the current user is allowed to write into the remote counterpart of this calendar
+    #[serde(default = "default_writable")]
+    writable: bool,
+    /// The ctag of the remote counterpart of this calendar, as it was at the end of the last successful sync
+    #[serde(default)]
+    last_synced_ctag: Option<VersionTag>,
+    /// See [`CompleteCalendar::last_sync_token`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_sync_token: Option<String>,
+    /// When the items of this calendar have last been re-fetched from the source of its remote counterpart, in case it is a subscription
+    #[serde(default)]
+    last_refreshed: Option<DateTime<Utc>>,
+    /// See [`CompleteCalendar::sync_policy`]
+    #[serde(default)]
+    sync_policy: Option<CalendarSyncPolicy>,
+    /// When the items that are marked for deletion have been marked, so that deletions that never reach the server can be spotted (see [`crate::cache::Cache::check`])
+    #[serde(default)]
+    marked_for_deletion_at: HashMap<Url, DateTime<Utc>>,
+    /// See [`CompleteCalendar::is_local_only`]
+    #[serde(default)]
+    local_only: bool,
+    /// See [`CompleteCalendar::upload_failure`]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    upload_failures: HashMap<Url, UploadFailure>,
+    /// See [`CompleteCalendar::altered_upload`]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    altered_uploads: HashMap<Url, AlteredUpload>,
+    /// How many history entries are kept for each item, in case the history is enabled (see [`crate::cache::Cache::set_item_history_limit`])
+    #[serde(default)]
+    history_limit: Option<usize>,
+    /// See [`Self::item_history`]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    history: HashMap<Url, VecDeque<HistoryEntry>>,
+    /// See [`CompleteCalendar::hold_item`]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    held_items: HashMap<Url, Item>,
+    /// Who the next changes come from (see [`CompleteCalendar::set_change_source`])
+    #[serde(skip)]
+    change_source: ChangeSource,
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     #[serde(skip)]
     mock_behaviour: Option<Arc<Mutex<MockBehaviour>>>,
@@ -34,6 +91,10 @@ pub struct CachedCalendar {
     items: HashMap<Url, Item>,
 }
 
+fn default_writable() -> bool {
+    true
+}
+
 impl CachedCalendar {
     /// Activate the "mocking remote calendar" feature (i.e. ignore sync statuses, since this is what an actual CalDAV sever would do)
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
@@ -42,10 +103,67 @@ impl CachedCalendar {
     }
 
 
+    /// Move an uploaded item to the URL the mock behaviour says the server stores it at (if any)
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    fn with_mocked_content_location(&mut self, mut item: Item) -> Item {
+        let canonical = self.mock_behaviour.as_ref()
+            .and_then(|b| b.lock().unwrap().content_locations.get(item.url()).cloned());
+        if let Some(canonical) = canonical {
+            self.items.remove(item.url());
+            item.set_url(canonical);
+        }
+        item
+    }
+
+    /// Rewrite an uploaded item in case the mock behaviour says the server does so
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    fn with_mocked_rewrite(&self, mut item: Item) -> Item {
+        let rewrite = self.mock_behaviour.as_ref().map(|b| b.lock().unwrap().rewrite_uploads).unwrap_or(false);
+        if let (true, Item::Task(task)) = (rewrite, &mut item) {
+            let mut extra_parameters = task.extra_parameters().to_vec();
+            extra_parameters.retain(|prop| prop.name != MOCK_REWRITE_PROPERTY);
+            extra_parameters.push(ical::property::Property { name: MOCK_REWRITE_PROPERTY.to_string(), params: None, value: Some("1".to_string()) });
+            *task = task.clone().with_extra_parameters(extra_parameters);
+        }
+        let truncate_names = self.mock_behaviour.as_ref().and_then(|b| b.lock().unwrap().truncate_names);
+        if let (Some(max_chars), Item::Task(task)) = (truncate_names, &mut item) {
+            let truncated: String = task.name().chars().take(max_chars).collect();
+            task.mock_remote_calendar_set_name(truncated);
+        }
+        item
+    }
+
+    /// Account for an item as if it had been sent to or received from a server
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    fn simulate_transfer(&self, operation: &str, item: &Item, is_upload: bool) {
+        if let Some(behaviour) = &self.mock_behaviour {
+            let size = crate::ical::build_from(item).map(|ical| ical.len() as u64).unwrap_or(0);
+            let (sent, received) = if is_upload { (size, 0) } else { (0, size) };
+            let mut behaviour = behaviour.lock().unwrap();
+            behaviour.record_operation(operation, item.url());
+            behaviour.simulated_transfers.lock().unwrap().record(operation, Some(&self.url), sent, received);
+        }
+    }
+
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     fn add_item_maybe_mocked(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
         if self.mock_behaviour.is_some() {
             self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_add_item())?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_upload_to(&self.url))?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_upload_item(item.url()))?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_modify_item(item.url()))?;
+            self.simulate_transfer("PUT", &item, true);
+            // Just like CalDAV servers, refuse to store two items with the same UID
+            if let Some(existing) = self.items.values().find(|other| other.uid() == item.uid() && other.url() != item.url()) {
+                return Err(Box::new(crate::dav_error::HttpError {
+                    status: reqwest::StatusCode::CONFLICT,
+                    detail: Some(crate::dav_error::DavErrorDetail::NoUidConflict { existing: Some(existing.url().path().to_string()) }),
+                    message: None,
+                    request_id: None,
+                }));
+            }
+            let item = self.with_mocked_content_location(item);
+            let item = self.with_mocked_rewrite(item);
             self.add_or_update_item_force_synced(item)
         } else {
             self.regular_add_or_update_item(item)
@@ -56,6 +174,13 @@ impl CachedCalendar {
     fn update_item_maybe_mocked(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
         if self.mock_behaviour.is_some() {
             self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_update_item())?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_upload_to(&self.url))?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_upload_item(item.url()))?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_modify_item(item.url()))?;
+            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_overwrite_item(item.url()))?;
+            self.simulate_transfer("PUT", &item, true);
+            let item = self.with_mocked_content_location(item);
+            let item = self.with_mocked_rewrite(item);
             self.add_or_update_item_force_synced(item)
         } else {
             self.regular_add_or_update_item(item)
@@ -66,6 +191,10 @@ impl CachedCalendar {
     fn regular_add_or_update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
         let ss_clone = item.sync_status().clone();
         log::debug!("Adding or updating an item with {:?}", ss_clone);
+        if !matches!(ss_clone, SyncStatus::LocallyDeleted(_)) {
+            self.marked_for_deletion_at.remove(item.url());
+        }
+        self.record_insertion(&item);
         self.items.insert(item.url().clone(), item);
         Ok(ss_clone)
     }
@@ -79,12 +208,141 @@ impl CachedCalendar {
             _ => item.set_sync_status(SyncStatus::random_synced()),
         };
         let ss_clone = item.sync_status().clone();
+        self.record_insertion(&item);
         self.items.insert(item.url().clone(), item);
+        self.apply_mocked_edits_during_uploads();
         Ok(ss_clone)
     }
 
+    /// Rename the tasks of this calendar that are part of [`MockBehaviour::edits_during_uploads`]
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    fn apply_mocked_edits_during_uploads(&mut self) {
+        let behaviour = match &self.mock_behaviour {
+            Some(behaviour) => behaviour.clone(),
+            None => return,
+        };
+        let mut behaviour = behaviour.lock().unwrap();
+        let items = &mut self.items;
+        behaviour.edits_during_uploads.retain(|url, name| {
+            match items.get_mut(url) {
+                Some(Item::Task(task)) => {
+                    task.mock_remote_calendar_set_name(name.clone());
+                    false
+                },
+                _ => true,
+            }
+        });
+    }
+
+    /// Add an entry to the history of an item, in case the history is enabled
+    fn record(&mut self, url: &Url, kind: ChangeKind) {
+        if let Some(limit) = self.history_limit {
+            let entry = HistoryEntry { timestamp: Utc::now(), source: self.change_source, kind };
+            history::push_bounded(self.history.entry(url.clone()).or_default(), entry, limit);
+        }
+    }
+
+    /// The items that have been put aside (see [`CompleteCalendar::hold_item`]), e.g. the local changes that were kept when this calendar has been reset, sorted by URL
+    pub fn held_items(&self) -> Vec<&Item> {
+        let mut held: Vec<&Item> = self.held_items.values().collect();
+        held.sort_by(|l, r| l.url().cmp(r.url()));
+        held
+    }
+
+    /// Apply a held item (see [`Self::held_items`]) again, and remove it from the holding area.
+    ///
+    /// In case the calendar has an item at the same URL, the held item replaces it, as a local change that will be pushed at the next sync (and that may conflict with changes of the server, as usual).
+    /// Otherwise, it is added as a new item.
+    pub fn reapply_held_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        let mut item = self.held_items.remove(item_url)
+            .ok_or_else(|| format!("Item {} is not held by this calendar", item_url))?;
+        let sync_status = match self.items.get(item_url).map(|current| current.sync_status()) {
+            None => SyncStatus::NotSynced,
+            Some(SyncStatus::NotSynced) => SyncStatus::NotSynced,
+            Some(SyncStatus::Synced(tag)) | Some(SyncStatus::LocallyModified(tag)) | Some(SyncStatus::LocallyDeleted(tag)) => SyncStatus::LocallyModified(tag.clone()),
+        };
+        item.set_sync_status(sync_status);
+        self.regular_add_or_update_item(item)?;
+        Ok(())
+    }
+
+    /// Forget a held item (see [`Self::held_items`]) for good
+    pub fn discard_held_item(&mut self, item_url: &Url) -> Result<Item, Box<dyn Error>> {
+        self.held_items.remove(item_url)
+            .ok_or_else(|| format!("Item {} is not held by this calendar", item_url).into())
+    }
+
+    /// Record that an item is about to be added or to replace its current version
+    fn record_insertion(&mut self, item: &Item) {
+        if self.history_limit.is_none() {
+            return;
+        }
+        let kind = match self.items.get(item.url()) {
+            None => ChangeKind::Created,
+            Some(previous) => ChangeKind::Modified { properties: history::changed_properties(previous, item) },
+        };
+        self.record(item.url(), kind);
+    }
+
+    /// The changes that have been made to an item (even in case it has been deleted since), oldest first.
+    ///
+    /// This is empty unless the history is enabled for the cache of this calendar (see [`crate::cache::Cache::set_item_history_limit`]).
+    /// Changes that are made through mutable references (e.g. [`Self::get_item_by_url_mut_sync`]) are not recorded, only the ones made through the methods of this calendar are.
+    pub fn item_history(&self, url: &Url) -> Vec<&HistoryEntry> {
+        self.history.get(url).map(|entries| entries.iter().collect()).unwrap_or_default()
+    }
+
+    /// The items whose uploads keep failing (see [`crate::cache::Cache::quarantined_items`])
+    pub fn upload_failures(&self) -> &HashMap<Url, UploadFailure> {
+        &self.upload_failures
+    }
+
+    /// See [`crate::cache::Cache::set_local_only`]
+    pub(crate) fn set_local_only(&mut self, local_only: bool) {
+        if self.local_only && !local_only {
+            // The server may have changed in the meantime: the next sync must compare every item, rather than trusting the ctag or the sync token of the last sync
+            self.last_synced_ctag = None;
+            self.last_sync_token = None;
+        }
+        self.local_only = local_only;
+    }
+
+    /// See [`BaseCalendar::other_components`]
+    pub(crate) fn set_other_components(&mut self, other_components: Vec<String>) {
+        self.other_components = other_components;
+    }
+
+    /// See [`crate::cache::Cache::set_item_history_limit`]
+    pub(crate) fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.history_limit = limit;
+        match limit {
+            None => self.history.clear(),
+            Some(limit) => {
+                for entries in self.history.values_mut() {
+                    while entries.len() > limit {
+                        entries.pop_front();
+                    }
+                }
+            },
+        }
+    }
+
+    /// Drop the history entries older than `before`, and the history of the items that do not exist anymore once their entries are all dropped.
+    /// Returns how many entries have been dropped. See [`crate::cache::Cache::prune`]
+    pub(crate) fn prune_history(&mut self, before: DateTime<Utc>) -> usize {
+        let mut dropped = 0;
+        for entries in self.history.values_mut() {
+            let len = entries.len();
+            entries.retain(|entry| entry.timestamp >= before);
+            dropped += len - entries.len();
+        }
+        let items = &self.items;
+        self.history.retain(|url, entries| !entries.is_empty() || items.contains_key(url));
+        dropped
+    }
+
     /// Some kind of equality check
-    #[cfg(any(test, feature = "integration_tests"))]
+    #[cfg(any(test, feature = "test-utils"))]
     pub async fn has_same_observable_content_as(&self, other: &CachedCalendar) -> Result<bool, Box<dyn Error>> {
         if self.name != other.name
         || self.url != other.url
@@ -119,6 +377,136 @@ impl CachedCalendar {
         Ok(true)
     }
 
+    /// Change the URL of this calendar and of its items, in case they start with `old_base`.
+    /// See [`crate::cache::Cache::rebase_ids`]
+    pub(crate) fn rebase_urls(&mut self, old_base: &Url, new_base: &Url) {
+        if let Some(new_url) = crate::utils::rebase_url(&self.url, old_base, new_base) {
+            self.url = new_url;
+        }
+
+        let items = std::mem::take(&mut self.items);
+        for (url, mut item) in items {
+            let url = match crate::utils::rebase_url(&url, old_base, new_base) {
+                None => url,
+                Some(new_url) => {
+                    item.set_url(new_url.clone());
+                    new_url
+                },
+            };
+            self.items.insert(url, item);
+        }
+    }
+
+    /// Look for inconsistencies in this calendar. See [`crate::cache::Cache::check`]
+    pub(crate) fn check(&self) -> Vec<Inconsistency> {
+        let mut found = Vec::new();
+        let tombstone_window = *crate::config::TOMBSTONE_WINDOW.lock().unwrap();
+        // Items are supposed to be in the same folder as their calendar (or in the calendar itself, in case its URL ends with a `/`)
+        let folder = self.url.join(".").unwrap_or_else(|_| self.url.clone());
+
+        let mut keys: Vec<&Url> = self.items.keys().collect();
+        keys.sort();
+        let mut items_by_uid: HashMap<&str, Vec<Url>> = HashMap::new();
+        for key in keys {
+            let item = &self.items[key];
+            if item.url() != key {
+                found.push(Inconsistency::MisindexedItem { calendar: self.url.clone(), key: key.clone(), item: item.url().clone() });
+            }
+            if !item.url().as_str().starts_with(folder.as_str()) {
+                found.push(Inconsistency::OrphanItem { calendar: self.url.clone(), item: item.url().clone() });
+            }
+            let has_empty_tag = match item.sync_status() {
+                SyncStatus::NotSynced => false,
+                SyncStatus::Synced(tag) | SyncStatus::LocallyModified(tag) | SyncStatus::LocallyDeleted(tag) => tag.as_str().is_empty(),
+            };
+            if has_empty_tag {
+                found.push(Inconsistency::InvalidSyncStatus { calendar: self.url.clone(), item: item.url().clone() });
+            }
+            if let SyncStatus::LocallyDeleted(_) = item.sync_status() {
+                // Caches written by older versions do not know when their items have been marked
+                let marked_at = self.marked_for_deletion_at.get(key).unwrap_or_else(|| item.last_modified());
+                if Utc::now() - *marked_at > tombstone_window {
+                    found.push(Inconsistency::StaleDeletion { calendar: self.url.clone(), item: item.url().clone(), marked_at: *marked_at });
+                }
+            }
+            items_by_uid.entry(item.uid()).or_default().push(item.url().clone());
+        }
+
+        let url_of = |uid: &str| items_by_uid.get(uid).and_then(|urls| urls.iter().min().cloned());
+        for anomaly in self.task_hierarchy().anomalies() {
+            match anomaly {
+                Anomaly::Cycle { uids, .. } => found.push(Inconsistency::ParentCycle {
+                    calendar: self.url.clone(),
+                    items: uids.iter().filter_map(|uid| url_of(uid)).collect(),
+                }),
+                Anomaly::TooDeep { uid, limit } => if let Some(item) = url_of(uid) {
+                    found.push(Inconsistency::HierarchyTooDeep { calendar: self.url.clone(), item, limit: *limit });
+                },
+            }
+        }
+
+        let mut duplicated_uids: Vec<(&str, Vec<Url>)> = items_by_uid.into_iter().filter(|(_, urls)| urls.len() > 1).collect();
+        duplicated_uids.sort();
+        for (uid, mut items) in duplicated_uids {
+            items.sort();
+            found.push(Inconsistency::DuplicateUid { calendar: self.url.clone(), uid: uid.to_string(), items });
+        }
+        found
+    }
+
+    /// Fix the inconsistencies of this calendar that can safely be fixed. See [`crate::cache::Cache::repair`]
+    pub(crate) fn repair(&mut self, policy: &RepairPolicy) -> RepairReport {
+        let mut report = RepairReport::default();
+
+        // Rebuild the index first, so that the other inconsistencies can be looked up by the URL of their items
+        for inconsistency in self.check() {
+            if let Inconsistency::MisindexedItem { key, item, .. } = &inconsistency {
+                if !self.items.contains_key(item) {
+                    if let Some(misindexed) = self.items.remove(key) {
+                        self.items.insert(item.clone(), misindexed);
+                    }
+                    report.repaired.push(inconsistency);
+                }
+            }
+        }
+
+        for inconsistency in self.check() {
+            let repaired = match &inconsistency {
+                Inconsistency::OrphanItem { item, .. } => {
+                    policy.drop_orphans && self.items.remove(item).is_some()
+                },
+                Inconsistency::InvalidSyncStatus { item, .. } => {
+                    match self.items.get_mut(item) {
+                        // Too bad, this deletion will never reach the server. At least, this item will not be resurrected
+                        Some(invalid) if matches!(invalid.sync_status(), SyncStatus::LocallyDeleted(_)) => {
+                            self.items.remove(item);
+                            true
+                        },
+                        // Uploading it again is the safest thing to do
+                        Some(invalid) => {
+                            invalid.set_sync_status(SyncStatus::NotSynced);
+                            true
+                        },
+                        // It has been dropped already
+                        None => true,
+                    }
+                },
+                // Deleting it locally would make it re-appear at the next sync. Only a sync can tell what happened to it on the server
+                Inconsistency::StaleDeletion { .. } => false,
+                // Only the user can tell which links are wrong
+                Inconsistency::ParentCycle { .. } | Inconsistency::HierarchyTooDeep { .. } => false,
+                Inconsistency::MisindexedItem { .. } | Inconsistency::DuplicateUid { .. } | Inconsistency::StrayFile { .. } => false,
+            };
+            if repaired {
+                report.repaired.push(inconsistency);
+            } else {
+                report.remaining.push(inconsistency);
+            }
+        }
+
+        report
+    }
+
     /// The non-async version of [`Self::get_item_urls`]
     pub fn get_item_urls_sync(&self) -> Result<HashSet<Url>, Box<dyn Error>> {
         Ok(self.items.iter()
@@ -158,6 +546,7 @@ impl CachedCalendar {
         if self.items.contains_key(item.url()) {
             return Err(format!("Item {:?} cannot be added, it exists already", item.url()).into());
         }
+        self.supported_components.check_item(&item)?;
         #[cfg(not(feature = "local_calendar_mocks_remote_calendars"))]
         return self.regular_add_or_update_item(item);
 
@@ -186,10 +575,14 @@ impl CachedCalendar {
                     SyncStatus::Synced(prev_ss) => {
                         let prev_ss = prev_ss.clone();
                         item.set_sync_status( SyncStatus::LocallyDeleted(prev_ss));
+                        self.marked_for_deletion_at.insert(item_url.clone(), Utc::now());
+                        self.record(item_url, ChangeKind::MarkedForDeletion);
                     },
                     SyncStatus::LocallyModified(prev_ss) => {
                         let prev_ss = prev_ss.clone();
                         item.set_sync_status( SyncStatus::LocallyDeleted(prev_ss));
+                        self.marked_for_deletion_at.insert(item_url.clone(), Utc::now());
+                        self.record(item_url, ChangeKind::MarkedForDeletion);
                     },
                     SyncStatus::LocallyDeleted(prev_ss) => {
                         let prev_ss = prev_ss.clone();
@@ -198,6 +591,7 @@ impl CachedCalendar {
                     SyncStatus::NotSynced => {
                         // This was never synced to the server, we can safely delete it as soon as now
                         self.items.remove(item_url);
+                        self.record(item_url, ChangeKind::Deleted);
                     },
                 };
                 Ok(())
@@ -205,11 +599,188 @@ impl CachedCalendar {
         }
     }
 
+    /// The tasks of this calendar, sorted by name (and by URL in case two tasks have the same name).
+    ///
+    /// Names are compared according to the rules of `locale` (e.g. `de-DE`), or of the locale of the system when it is `None` (see [`crate::collation`]).
+    /// Items that are marked for deletion are ignored.
+    pub fn tasks_sorted(&self, locale: Option<&str>) -> Vec<&Task> {
+        let collator = crate::collation::Collator::new(locale);
+        let mut tasks: Vec<&Task> = self.items.values()
+            .filter(|item| !matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .filter_map(|item| match item {
+                Item::Task(task) => Some(task),
+                Item::Event(_) => None,
+            })
+            .collect();
+        tasks.sort_by(|l, r| collator.compare(l.name(), r.name()).then_with(|| l.url().cmp(r.url())));
+        tasks
+    }
+
+    /// The tasks of this calendar that are not completed, and that depend on tasks of this calendar that are not completed either (see [`Task::dependencies`]), sorted by URL.
+    ///
+    /// This is e.g. useful for a UI to gray them out. Dependencies are resolved by UID; those that are not in this calendar are ignored.
+    /// Dependencies are not followed any further: a task that is not completed blocks its dependents, whatever its own dependencies.
+    /// This way, tasks that depend on each other (directly or not) are all blocked, rather than being an endless chain to follow.
+    /// Items that are marked for deletion are ignored.
+    pub fn blocked_tasks(&self) -> Vec<&Task> {
+        let tasks: Vec<&Task> = self.items.values()
+            .filter(|item| !matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .filter_map(|item| match item {
+                Item::Task(task) => Some(task),
+                Item::Event(_) => None,
+            })
+            .collect();
+        let uncompleted_uids: HashSet<&str> = tasks.iter()
+            .filter(|task| !task.completed())
+            .map(|task| task.uid())
+            .collect();
+
+        let mut blocked: Vec<&Task> = tasks.into_iter()
+            .filter(|task| !task.completed())
+            .filter(|task| task.dependencies().iter().any(|uid| uid != task.uid() && uncompleted_uids.contains(uid.as_str())))
+            .collect();
+        blocked.sort_by(|l, r| l.url().cmp(r.url()));
+        blocked
+    }
+
+    /// The tree of the tasks of this calendar and of their subtasks (see [`Task::parent`]).
+    ///
+    /// Inconsistent parent links (cycles, or chains that are too deep) are cut, see [`Hierarchy`]. Items that are marked for deletion are ignored.
+    pub fn task_hierarchy(&self) -> Hierarchy {
+        let mut tasks: Vec<&Task> = self.items.values()
+            .filter(|item| !matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .filter_map(|item| match item {
+                Item::Task(task) => Some(task),
+                Item::Event(_) => None,
+            })
+            .collect();
+        tasks.sort_by(|l, r| l.url().cmp(r.url()));
+        Hierarchy::build(tasks.into_iter().map(|task| (task.uid().to_string(), task.parent())))
+    }
+
+    /// How much time the tasks of this calendar that are not completed still need, according to their estimates (see [`Task::estimated_remaining`]).
+    ///
+    /// Tasks that have no estimate count as zero. Items that are marked for deletion are ignored
+    pub fn total_estimated_remaining(&self) -> Duration {
+        self.items.values()
+            .filter(|item| !matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .filter_map(|item| match item {
+                Item::Task(task) => task.estimated_remaining(),
+                Item::Event(_) => None,
+            })
+            .fold(Duration::zero(), |total, remaining| total + remaining)
+    }
+
+    /// Find groups of tasks that look like duplicates of each other (e.g. after a faulty import).
+    ///
+    /// This does not alter this calendar. Items that are marked for deletion are ignored. See [`Self::merge_duplicates`]
+    pub fn find_duplicates(&self, criteria: DuplicateCriteria) -> Vec<Vec<Url>> {
+        let tasks: Vec<&Task> = self.items.values()
+            .filter(|item| !matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .filter_map(|item| match item {
+                Item::Task(t) => Some(t),
+                _ => None,
+            })
+            .collect();
+        duplicates::group_duplicates(&tasks, criteria)
+    }
+
+    /// Merge a group of duplicate tasks (e.g. found by [`Self::find_duplicates`]) into one of them.
+    ///
+    /// The categories and alarms of the other tasks are added to the kept task, and the other tasks are marked for deletion.
+    /// The returned record can be given to [`Self::undo_merge`], as long as this calendar has not been synced in the meantime.
+    pub fn merge_duplicates(&mut self, group: &[Url], keep: &Url) -> Result<MergeRecord, Box<dyn Error>> {
+        if !group.contains(keep) {
+            return Err(format!("Item {} is not part of the group to merge", keep).into());
+        }
+        let task_to_merge = |url: &Url| -> Result<Item, Box<dyn Error>> {
+            match self.items.get(url) {
+                Some(item @ Item::Task(_)) => Ok(item.clone()),
+                Some(_) => Err(format!("Item {} is not a task", url).into()),
+                None => Err(format!("Item {} is absent from this calendar", url).into()),
+            }
+        };
+
+        let keeper_before = task_to_merge(keep)?;
+        let mut losers_before: Vec<Item> = Vec::new();
+        for url in group {
+            if url != keep && !losers_before.iter().any(|loser| loser.url() == url) {
+                losers_before.push(task_to_merge(url)?);
+            }
+        }
+
+        let keeper = keeper_before.unwrap_task();
+        let mut categories = keeper.categories();
+        let mut alarms = keeper.alarms().to_vec();
+        for loser in &losers_before {
+            let loser = loser.unwrap_task();
+            for category in loser.categories() {
+                if !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+            for alarm in loser.alarms() {
+                if !alarms.contains(alarm) {
+                    alarms.push(alarm.clone());
+                }
+            }
+        }
+
+        if let Some(Item::Task(keeper)) = self.items.get_mut(keep) {
+            if categories != keeper.categories() {
+                keeper.set_categories(categories);
+            }
+            if alarms.as_slice() != keeper.alarms() {
+                keeper.set_alarms(alarms);
+            }
+        }
+        for loser in &losers_before {
+            self.mark_for_deletion_sync(loser.url())?;
+        }
+
+        Ok(MergeRecord { keeper_before, losers_before })
+    }
+
+    /// Revert a [`Self::merge_duplicates`].
+    ///
+    /// This fails in case the merged items have been synced since the merge, because the removed items may have been deleted from the server already.
+    pub fn undo_merge(&mut self, record: MergeRecord) -> Result<(), Box<dyn Error>> {
+        for loser in &record.losers_before {
+            let still_pending = match self.items.get(loser.url()) {
+                None => loser.sync_status() == &SyncStatus::NotSynced,
+                Some(item) => matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)),
+            };
+            if !still_pending {
+                return Err(format!("Item {} has been synced since it was merged, the merge cannot be undone", loser.url()).into());
+            }
+        }
+        let keeper_url = record.keeper_before.url();
+        match self.items.get(keeper_url) {
+            None => return Err(format!("Item {} is absent from this calendar", keeper_url).into()),
+            Some(keeper) => {
+                if matches!(keeper.sync_status(), SyncStatus::Synced(_)) && keeper.sync_status() != record.keeper_before.sync_status() {
+                    return Err(format!("Item {} has been synced since it was merged, the merge cannot be undone", keeper_url).into());
+                }
+            },
+        }
+
+        for item in std::iter::once(record.keeper_before).chain(record.losers_before) {
+            self.items.insert(item.url().clone(), item);
+        }
+        Ok(())
+    }
+
     /// The non-async version of [`Self::immediately_delete_item`]
     pub fn immediately_delete_item_sync(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        self.marked_for_deletion_at.remove(item_url);
+        self.upload_failures.remove(item_url);
+        self.altered_uploads.remove(item_url);
         match self.items.remove(item_url) {
             None => Err(format!("Item {} is absent from this calendar", item_url).into()),
-            Some(_) => Ok(())
+            Some(_) => {
+                self.record(item_url, ChangeKind::Deleted);
+                Ok(())
+            }
         }
     }
 
@@ -234,6 +805,14 @@ impl BaseCalendar for CachedCalendar {
         self.color.as_ref()
     }
 
+    fn other_components(&self) -> &[String] {
+        &self.other_components
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
     async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
         self.add_item_sync(item)
     }
@@ -248,6 +827,21 @@ impl CompleteCalendar for CachedCalendar {
     fn new(name: String, url: Url, supported_components: SupportedComponents, color: Option<Color>) -> Self {
         Self {
             name, url, supported_components, color,
+            other_components: Vec::new(),
+            unavailable: false,
+            writable: true,
+            last_synced_ctag: None,
+            last_sync_token: None,
+            last_refreshed: None,
+            sync_policy: None,
+            local_only: false,
+            upload_failures: HashMap::new(),
+            altered_uploads: HashMap::new(),
+            marked_for_deletion_at: HashMap::new(),
+            history_limit: None,
+            history: HashMap::new(),
+            held_items: HashMap::new(),
+            change_source: ChangeSource::Local,
             #[cfg(feature = "local_calendar_mocks_remote_calendars")]
             mock_behaviour: None,
             items: HashMap::new(),
@@ -281,6 +875,89 @@ impl CompleteCalendar for CachedCalendar {
     async fn immediately_delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
         self.immediately_delete_item_sync(item_url)
     }
+
+    fn hold_item(&mut self, item: Item) -> Result<(), Box<dyn Error>> {
+        self.held_items.insert(item.url().clone(), item);
+        Ok(())
+    }
+
+    fn is_unavailable(&self) -> bool {
+        self.unavailable
+    }
+
+    fn set_unavailable(&mut self, unavailable: bool) {
+        self.unavailable = unavailable;
+    }
+
+    fn set_writable(&mut self, writable: bool) {
+        self.writable = writable;
+    }
+
+    fn last_synced_ctag(&self) -> Option<&VersionTag> {
+        self.last_synced_ctag.as_ref()
+    }
+
+    fn set_last_synced_ctag(&mut self, ctag: Option<VersionTag>) {
+        self.last_synced_ctag = ctag;
+    }
+
+    fn last_sync_token(&self) -> Option<&str> {
+        self.last_sync_token.as_deref()
+    }
+
+    fn set_last_sync_token(&mut self, sync_token: Option<String>) {
+        self.last_sync_token = sync_token;
+    }
+
+    fn last_refreshed(&self) -> Option<DateTime<Utc>> {
+        self.last_refreshed
+    }
+
+    fn set_last_refreshed(&mut self, last_refreshed: Option<DateTime<Utc>>) {
+        self.last_refreshed = last_refreshed;
+    }
+
+    fn sync_policy(&self) -> Option<CalendarSyncPolicy> {
+        self.sync_policy.clone()
+    }
+
+    fn set_sync_policy(&mut self, policy: Option<CalendarSyncPolicy>) {
+        self.sync_policy = policy;
+    }
+
+    fn upload_failure(&self, item_url: &Url) -> Option<UploadFailure> {
+        self.upload_failures.get(item_url).cloned()
+    }
+
+    fn set_upload_failure(&mut self, item_url: &Url, failure: Option<UploadFailure>) {
+        match failure {
+            None => { self.upload_failures.remove(item_url); },
+            Some(failure) => { self.upload_failures.insert(item_url.clone(), failure); },
+        }
+    }
+
+    fn altered_upload(&self, item_url: &Url) -> Option<AlteredUpload> {
+        self.altered_uploads.get(item_url).cloned()
+    }
+
+    fn set_altered_upload(&mut self, item_url: &Url, altered: Option<AlteredUpload>) {
+        match altered {
+            None => { self.altered_uploads.remove(item_url); },
+            Some(altered) => { self.altered_uploads.insert(item_url.clone(), altered); },
+        }
+    }
+
+    fn is_local_only(&self) -> bool {
+        self.local_only
+    }
+
+    fn marked_for_deletion_at(&self, item_url: &Url) -> Option<DateTime<Utc>> {
+        self.marked_for_deletion_at.get(item_url).copied()
+    }
+
+    fn set_change_source(&mut self, source: ChangeSource) {
+        self.change_source = source;
+    }
 }
 
 
@@ -288,7 +965,7 @@ impl CompleteCalendar for CachedCalendar {
 // This class can be used to mock a remote calendar for integration tests
 
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-use crate::{item::VersionTag,
+use crate::{calendar::{DateLimits, ItemChanges, Subscription},
             traits::DavCalendar,
             resource::Resource};
 
@@ -324,13 +1001,135 @@ impl DavCalendar for CachedCalendar {
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_get_item_by_url())?;
 
-        Ok(self.items.get(url).cloned())
+        let vanished = self.mock_behaviour.as_ref().is_some_and(|b| b.lock().unwrap().vanishing_items.contains(url));
+        let item = self.items.get(url).filter(|_| !vanished).cloned();
+        if let Some(item) = &item {
+            self.simulate_transfer("GET", item, false);
+        }
+        Ok(item)
+    }
+
+    fn take_rewritten_item(&self, url: &Url) -> Option<Item> {
+        let behaviour = self.mock_behaviour.as_ref()?.lock().unwrap();
+        if !behaviour.rewrite_uploads {
+            return None;
+        }
+        let stored_url = behaviour.content_locations.get(url).unwrap_or(url);
+        self.items.get(stored_url).cloned()
+    }
+
+    fn take_canonical_url(&self, url: &Url) -> Option<Url> {
+        self.mock_behaviour.as_ref()
+            .and_then(|b| b.lock().unwrap().content_locations.get(url).cloned())
+            .filter(|canonical| self.items.contains_key(canonical))
+    }
+
+    fn take_unsupported_component(&self, url: &Url) -> Option<Option<String>> {
+        self.mock_behaviour.as_ref()
+            .and_then(|b| b.lock().unwrap().unsupported_components.get(url).cloned())
+            .filter(|_| self.items.contains_key(url))
+            .map(Some)
+    }
+
+    /// A hash of the version tags of the items, since a mocked calendar has no ctag of its own
+    async fn get_ctag(&self) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        use std::hash::{Hash, Hasher};
+
+        let mut tags: Vec<(&Url, Option<&str>)> = self.items.iter()
+            .map(|(url, item)| match item.sync_status() {
+                SyncStatus::Synced(vt) => (url, Some(vt.as_str())),
+                _ => (url, None),
+            })
+            .collect();
+        tags.sort();
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        tags.hash(&mut hasher);
+        Ok(Some(VersionTag::from(format!("{:x}", hasher.finish()))))
+    }
+
+    /// Mocked calendars support sync tokens only when [`MockBehaviour::sync_tokens`] is set. A token stands for the version tags the items had when it has been handed out
+    async fn get_changes_since(&self, sync_token: Option<&str>) -> Result<Option<ItemChanges>, Box<dyn Error>> {
+        let mut behaviour = match &self.mock_behaviour {
+            None => return Ok(None),
+            Some(behaviour) => behaviour.lock().unwrap(),
+        };
+        let current: HashMap<Url, VersionTag> = self.items.iter()
+            .filter_map(|(url, item)| match item.sync_status() {
+                SyncStatus::Synced(vt) => Some((url.clone(), vt.clone())),
+                _ => None,
+            })
+            .collect();
+        let snapshots = match behaviour.sync_tokens.as_mut() {
+            None => return Ok(None),
+            Some(snapshots) => snapshots,
+        };
+        let previous = match sync_token {
+            None => HashMap::new(),
+            // Just like a `valid-sync-token` error
+            Some(token) => match snapshots.get(token) {
+                None => return Ok(None),
+                Some(previous) => previous.clone(),
+            },
+        };
+        let changed = current.iter()
+            .filter(|(url, vt)| previous.get(*url) != Some(*vt))
+            .map(|(url, vt)| (url.clone(), vt.clone()))
+            .collect();
+        let deleted = previous.keys().filter(|url| !current.contains_key(*url)).cloned().collect();
+        let sync_token = format!("mock-sync-token-{}", snapshots.len());
+        snapshots.insert(sync_token.clone(), current);
+        behaviour.record_operation("REPORT", &self.url);
+        Ok(Some(ItemChanges { changed, deleted, sync_token }))
+    }
+
+    fn item_size(&self, url: &Url) -> Option<u64> {
+        // As a server that tells the sizes of the items it lists
+        self.items.get(url)
+            .and_then(|item| crate::ical::build_from(item).ok())
+            .map(|ical| ical.len() as u64)
+    }
+
+    fn date_limits(&self) -> DateLimits {
+        self.mock_behaviour.as_ref()
+            .and_then(|b| b.lock().unwrap().date_limits.get(&self.url).copied())
+            .unwrap_or_default()
+    }
+
+    fn subscription(&self) -> Option<Subscription> {
+        self.mock_behaviour.as_ref()
+            .and_then(|b| b.lock().unwrap().subscriptions.get(&self.url).cloned())
+    }
+
+    fn enabled(&self) -> Option<bool> {
+        self.mock_behaviour.as_ref()
+            .and_then(|b| b.lock().unwrap().enabled.get(&self.url).copied())
+    }
+
+    /// The whole content of this calendar, just like a feed would contain
+    async fn fetch_subscribed_items(&self) -> Result<Vec<Item>, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_get_item_version_tags())?;
+
+        let items: Vec<Item> = self.items.values().cloned().collect();
+        for item in &items {
+            self.simulate_transfer("GET", item, false);
+        }
+        Ok(items)
     }
 
     async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
         let mut v = Vec::new();
         for url in urls {
-            v.push(DavCalendar::get_item_by_url(self, url).await?);
+            if self.take_unsupported_component(url).is_some() {
+                // Just like a remote calendar, that leaves out the items it is unable to parse
+                continue;
+            }
+            match DavCalendar::get_item_by_url(self, url).await {
+                Ok(item) => v.push(item),
+                Err(err) if v.is_empty() => return Err(err),
+                // Just like a connection that would drop in the middle of a reply
+                Err(err) => return Err(Box::new(crate::calendar::PartialFetchError{ fetched: v.into_iter().flatten().collect(), cause: err })),
+            }
         }
         Ok(v)
     }
@@ -338,7 +1137,147 @@ impl DavCalendar for CachedCalendar {
     async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_delete_item())?;
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_modify_item(item_url))?;
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_overwrite_item(item_url))?;
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        if let Some(behaviour) = &self.mock_behaviour {
+            behaviour.lock().unwrap().record_operation("DELETE", item_url);
+        }
 
         self.immediately_delete_item(item_url).await
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Add tasks that have the given UIDs, completion and `RELATED-TO` properties (`uid;PARAMS`)
+    fn add_related_tasks(calendar: &mut CachedCalendar, tasks: &[(&str, bool, Vec<&str>)]) {
+        let cal_url = calendar.url().clone();
+        for (uid, completed, relations) in tasks {
+            let relations: String = relations.iter()
+                .map(|relation| {
+                    let (related_uid, params) = relation.split_once(';').map(|(uid, params)| (uid, format!(";{}", params))).unwrap_or((relation, String::new()));
+                    format!("RELATED-TO{}:{}\r\n", params, related_uid)
+                })
+                .collect();
+            let status = if *completed { "STATUS:COMPLETED\r\n" } else { "" };
+            let ical = format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Some app\r\nBEGIN:VTODO\r\nUID:{}\r\nDTSTAMP:20210321T001600\r\nSUMMARY:{}\r\n{}{}END:VTODO\r\nEND:VCALENDAR\r\n", uid, uid, status, relations);
+            let item = crate::ical::parse(&ical, cal_url.join(&format!("{}.ics", uid)).unwrap(), SyncStatus::NotSynced).unwrap();
+            calendar.add_item_sync(item).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blocked_tasks() {
+        let cal_url: Url = "https://some.server/calendars/chores/".parse().unwrap();
+        let mut calendar = <CachedCalendar as CompleteCalendar>::new("Chores".to_string(), cal_url.clone(), SupportedComponents::TODO, None);
+        let tasks = [
+            ("paint", false, vec!["buy-paint;RELTYPE=DEPENDS-ON"]),
+            ("buy-paint", false, vec![]),
+            ("clean", false, vec!["sweep;RELTYPE=FINISHTOSTART", "missing;RELTYPE=DEPENDS-ON"]),
+            ("sweep", true, vec![]),
+            ("hang-frames", false, vec!["paint;RELTYPE=DEPENDS-ON"]),
+            ("subtask", false, vec!["buy-paint"]),
+            // A cycle of dependencies
+            ("chicken", false, vec!["egg;RELTYPE=DEPENDS-ON"]),
+            ("egg", false, vec!["chicken;RELTYPE=DEPENDS-ON"]),
+            ("itself", false, vec!["itself;RELTYPE=DEPENDS-ON"]),
+        ];
+        add_related_tasks(&mut calendar, &tasks);
+        assert_eq!(calendar.get_item_by_url_sync(&cal_url.join("clean.ics").unwrap()).unwrap().unwrap_task().dependencies(), vec!["sweep", "missing"]);
+
+        let blocked: Vec<&str> = calendar.blocked_tasks().iter().map(|task| task.uid()).collect();
+        // "hang-frames" depends on "paint", that is blocked but not completed
+        assert_eq!(blocked, vec!["chicken", "egg", "hang-frames", "paint"]);
+
+        calendar.get_item_by_url_mut_sync(&cal_url.join("buy-paint.ics").unwrap()).unwrap().unwrap_task_mut()
+            .set_completion_status(crate::task::CompletionStatus::Completed(None));
+        let blocked: Vec<&str> = calendar.blocked_tasks().iter().map(|task| task.uid()).collect();
+        assert_eq!(blocked, vec!["chicken", "egg", "hang-frames"]);
+    }
+
+    #[test]
+    fn test_task_hierarchy() {
+        let cal_url: Url = "https://some.server/calendars/chores/".parse().unwrap();
+        let mut calendar = <CachedCalendar as CompleteCalendar>::new("Chores".to_string(), cal_url.clone(), SupportedComponents::TODO, None);
+        add_related_tasks(&mut calendar, &[
+            ("house", false, vec![]),
+            ("paint", false, vec!["house;RELTYPE=PARENT", "buy-paint;RELTYPE=DEPENDS-ON"]),
+            ("buy-paint", false, vec!["paint"]),
+            ("chicken", false, vec!["egg"]),
+            ("egg", false, vec!["chicken"]),
+        ]);
+        assert_eq!(calendar.get_item_by_url_sync(&cal_url.join("paint.ics").unwrap()).unwrap().unwrap_task().parent().as_deref(), Some("house"));
+
+        let hierarchy = calendar.task_hierarchy();
+        assert_eq!(hierarchy.roots(), vec!["chicken", "house"]);
+        assert_eq!(hierarchy.ancestors("buy-paint"), vec!["paint", "house"]);
+        assert_eq!(calendar.check(), vec![Inconsistency::ParentCycle {
+            calendar: cal_url.clone(),
+            items: vec![cal_url.join("chicken.ics").unwrap(), cal_url.join("egg.ics").unwrap()],
+        }]);
+        assert_eq!(calendar.repair(&RepairPolicy::default()).remaining.len(), 1);
+    }
+
+    #[test]
+    fn test_total_estimated_remaining() {
+        let cal_url: Url = "https://some.server.com/calendars/estimates/".parse().unwrap();
+        let mut calendar = <CachedCalendar as CompleteCalendar>::new("Estimates".to_string(), cal_url.clone(), SupportedComponents::TODO, None);
+        let estimates = [(Some(2), false, false), (Some(3), true, false), (None, false, false), (Some(5), false, true)];
+        for (hours, completed, deleted) in estimates {
+            let mut task = Task::new("Some task".to_string(), completed, &cal_url);
+            task.set_estimated_duration(hours.map(Duration::hours)).unwrap();
+            let url = task.url().clone();
+            calendar.add_item_sync(Item::Task(task)).unwrap();
+            if deleted {
+                calendar.get_item_by_url_mut_sync(&url).unwrap().set_sync_status(SyncStatus::Synced(VersionTag::from("etag".to_string())));
+                calendar.mark_for_deletion_sync(&url).unwrap();
+            }
+        }
+        assert_eq!(calendar.total_estimated_remaining(), Duration::hours(2));
+    }
+
+    #[test]
+    fn test_item_history() {
+        let cal_url: Url = "https://some.server/calendars/chores/".parse().unwrap();
+        let mut calendar = <CachedCalendar as CompleteCalendar>::new("Chores".to_string(), cal_url.clone(), SupportedComponents::TODO, None);
+        let task = Task::new("Sweep".to_string(), false, &cal_url);
+        let url = task.url().clone();
+        calendar.add_item_sync(Item::Task(task.clone())).unwrap();
+        // Disabled by default
+        assert!(calendar.item_history(&url).is_empty());
+
+        calendar.set_history_limit(Some(3));
+        let mut renamed = task.clone();
+        renamed.set_name("Sweep the floor".to_string());
+        calendar.update_item_sync(Item::Task(renamed)).unwrap();
+        calendar.set_change_source(ChangeSource::Remote);
+        let mut completed = calendar.get_item_by_url_sync(&url).unwrap().unwrap_task().clone();
+        completed.set_completion_status(crate::task::CompletionStatus::Completed(None));
+        calendar.update_item_sync(Item::Task(completed)).unwrap();
+        let kinds: Vec<(ChangeSource, ChangeKind)> = calendar.item_history(&url).into_iter().map(|entry| (entry.source, entry.kind.clone())).collect();
+        assert_eq!(kinds, vec![
+            (ChangeSource::Local, ChangeKind::Modified { properties: vec!["SUMMARY".to_string()] }),
+            (ChangeSource::Remote, ChangeKind::Modified { properties: vec!["PERCENT-COMPLETE".to_string(), "STATUS".to_string()] }),
+        ]);
+
+        // The history is bounded
+        calendar.immediately_delete_item_sync(&url).unwrap();
+        calendar.add_item_sync(Item::Task(task)).unwrap();
+        let kinds: Vec<&ChangeKind> = calendar.item_history(&url).into_iter().map(|entry| &entry.kind).collect();
+        assert_eq!(kinds.len(), 3);
+        assert_eq!(kinds[1..], [&ChangeKind::Deleted, &ChangeKind::Created]);
+
+        // Histories are kept after the deletion of their items, until they are pruned
+        calendar.immediately_delete_item_sync(&url).unwrap();
+        assert_eq!(calendar.prune_history(Utc::now() - chrono::Duration::hours(1)), 0);
+        assert_eq!(calendar.item_history(&url).len(), 3);
+        assert_eq!(calendar.prune_history(Utc::now() + chrono::Duration::hours(1)), 3);
+        assert!(calendar.history.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! Detection of duplicate tasks in a calendar (see [`crate::calendar::cached_calendar::CachedCalendar::find_duplicates`])
+
+use url::Url;
+
+use crate::item::Item;
+use crate::Task;
+
+/// How similar two tasks must be to be considered duplicates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateCriteria {
+    /// Tasks that have the same name, due date and description.
+    /// Names and descriptions are compared case-insensitively, and regardless of their whitespaces
+    Exact,
+    /// Tasks whose names are similar enough, regardless of their other properties.
+    /// The similarity ranges from 0.0 (nothing in common) to 1.0 (same normalized names)
+    Fuzzy { threshold: f64 },
+}
+
+/// A record of what [`crate::calendar::cached_calendar::CachedCalendar::merge_duplicates`] did, that can be used to undo it
+#[derive(Clone, Debug)]
+pub struct MergeRecord {
+    /// The item that has been kept, as it was before the merge
+    pub(crate) keeper_before: Item,
+    /// The items that have been marked for deletion, as they were before the merge
+    pub(crate) losers_before: Vec<Item>,
+}
+
+impl MergeRecord {
+    /// The URL of the item that has been kept
+    pub fn kept(&self) -> &Url {
+        self.keeper_before.url()
+    }
+    /// The URLs of the items that have been marked for deletion
+    pub fn removed(&self) -> Vec<&Url> {
+        self.losers_before.iter().map(|item| item.url()).collect()
+    }
+}
+
+/// Group tasks that are duplicates of each other.
+///
+/// Every returned group contains at least two URLs, sorted. Groups are sorted by their first URL.
+pub(crate) fn group_duplicates(tasks: &[&Task], criteria: DuplicateCriteria) -> Vec<Vec<Url>> {
+    // A union-find structure, so that duplicates of duplicates end up in the same group
+    let mut parents: Vec<usize> = (0..tasks.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let names: Vec<String> = tasks.iter().map(|t| normalize(t.name())).collect();
+    let descriptions: Vec<String> = tasks.iter().map(|t| normalize(t.description().unwrap_or(""))).collect();
+
+    for i in 0..tasks.len() {
+        for j in (i + 1)..tasks.len() {
+            let are_duplicates = match criteria {
+                DuplicateCriteria::Exact => {
+                       names[i] == names[j]
+                    && tasks[i].due() == tasks[j].due()
+                    && descriptions[i] == descriptions[j]
+                },
+                DuplicateCriteria::Fuzzy { threshold } => similarity(&names[i], &names[j]) >= threshold,
+            };
+            if are_duplicates {
+                let (root_i, root_j) = (root(&mut parents, i), root(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<Url>> = Vec::new();
+    let mut group_of_root = std::collections::HashMap::new();
+    for (i, task) in tasks.iter().enumerate() {
+        let r = root(&mut parents, i);
+        let index = *group_of_root.entry(r).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(task.url().clone());
+    }
+
+    let mut groups: Vec<Vec<Url>> = groups.into_iter()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| { group.sort(); group })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Lowercase (and remove diacritics, see [`crate::collation::fold`]), and collapse whitespaces
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(crate::collation::fold)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A similarity between 0.0 and 1.0, based on the Levenshtein distance
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let max_len = std::cmp::max(a.len(), b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    1.0 - previous[b.len()] as f64 / max_len as f64
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ical::property::Property;
+    use crate::calendar::cached_calendar::CachedCalendar;
+    use crate::calendar::SupportedComponents;
+    use crate::item::{RawComponent, SyncStatus, VersionTag};
+    use crate::traits::{BaseCalendar, CompleteCalendar};
+
+    fn add_task(cal: &mut CachedCalendar, name: &str, categories: &[&str], alarm_trigger: Option<&str>) -> Url {
+        let mut task = Task::new(name.to_string(), false, cal.url());
+        task.set_categories(categories.iter().map(|c| c.to_string()).collect());
+        if let Some(trigger) = alarm_trigger {
+            let trigger = Property { name: "TRIGGER".to_string(), params: None, value: Some(trigger.to_string()) };
+            task.set_alarms(vec![RawComponent::new("VALARM".to_string(), vec![trigger], Vec::new())]);
+        }
+        task.set_sync_status(SyncStatus::Synced(VersionTag::from(format!("etag-{}", task.uid()))));
+        let url = task.url().clone();
+        cal.add_item_sync(Item::Task(task)).unwrap();
+        url
+    }
+
+    #[test]
+    fn test_find_and_merge_duplicates() {
+        let mut cal = CachedCalendar::new("cal".to_string(), "https://some.calend.ar/cal/".parse().unwrap(), SupportedComponents::TODO, None);
+        let milk_1 = add_task(&mut cal, "Buy milk", &["food"], Some("-PT15M"));
+        let milk_2 = add_task(&mut cal, "  buy  MILK", &["errands"], Some("-PT1H"));
+        let milk_3 = add_task(&mut cal, "Buy milk", &["food", "dairy"], Some("-PT15M"));
+        let silk = add_task(&mut cal, "Buy silk", &[], None);
+        let _mom = add_task(&mut cal, "Call mom", &[], None);
+
+        let mut milks = vec![milk_1.clone(), milk_2.clone(), milk_3.clone()];
+        milks.sort();
+        assert_eq!(cal.find_duplicates(DuplicateCriteria::Exact), vec![milks.clone()]);
+        let mut milks_and_silk = vec![milk_1.clone(), milk_2.clone(), milk_3.clone(), silk];
+        milks_and_silk.sort();
+        assert_eq!(cal.find_duplicates(DuplicateCriteria::Fuzzy { threshold: 0.8 }), vec![milks_and_silk]);
+        // Detection alone has no side effect
+        assert_eq!(cal.get_items_sync().unwrap().values().filter(|i| matches!(i.sync_status(), SyncStatus::Synced(_))).count(), 5);
+
+        let record = cal.merge_duplicates(&milks, &milk_1).unwrap();
+        assert_eq!(record.kept(), &milk_1);
+        let keeper = cal.get_item_by_url_sync(&milk_1).unwrap().unwrap_task();
+        let mut categories = keeper.categories();
+        assert_eq!(categories[0], "food");
+        categories.sort();
+        assert_eq!(categories, vec!["dairy", "errands", "food"]);
+        assert_eq!(keeper.alarms().len(), 2);
+        assert!(matches!(keeper.sync_status(), SyncStatus::LocallyModified(_)));
+        for loser in &[&milk_2, &milk_3] {
+            assert!(matches!(cal.get_item_by_url_sync(loser).unwrap().sync_status(), SyncStatus::LocallyDeleted(_)));
+        }
+        assert!(cal.find_duplicates(DuplicateCriteria::Exact).is_empty());
+
+        cal.undo_merge(record).unwrap();
+        assert_eq!(cal.find_duplicates(DuplicateCriteria::Exact), vec![milks]);
+        let keeper = cal.get_item_by_url_sync(&milk_1).unwrap().unwrap_task();
+        assert_eq!(keeper.categories(), vec!["food"]);
+        assert!(matches!(keeper.sync_status(), SyncStatus::Synced(_)));
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("buy milk", "buy milk"), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("abcd", "wxyz"), 0.0);
+        assert_eq!(similarity("buy milk", "buy silk"), 1.0 - 1.0 / 8.0);
+        assert_eq!(normalize("  Buy   MILK "), "buy milk");
+    }
+}
@@ -0,0 +1,249 @@
+//! The tree of tasks and subtasks of a calendar, as described by their `RELATED-TO` parents (see [`crate::task::Task::parent`])
+//!
+//! Parent links come from the server, and may be inconsistent: they may form cycles (A → B → A), or chains that are too deep to be rendered recursively.
+//! A [`Hierarchy`] is always a proper forest of bounded depth: such links are cut, and the items they involve are reported as [`Anomaly`]s.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Something that is wrong with the parent links of a calendar
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// These items (sorted by UID) are ancestors of themselves. The cycle has been broken by making `broken_at` (the smallest of these UIDs) a top-level item
+    Cycle { uids: Vec<String>, broken_at: String },
+    /// This item is deeper than the limit (see [`crate::config::MAX_HIERARCHY_DEPTH`]). It has been made a top-level item
+    TooDeep { uid: String, limit: usize },
+}
+
+/// A forest of items, built from the UIDs of their parents (see the [module documentation](self))
+#[derive(Clone, Debug, Default)]
+pub struct Hierarchy {
+    /// The parent of every item that has one, after inconsistent links have been cut
+    parents: BTreeMap<String, String>,
+    /// The children of every item that has some, sorted
+    children: BTreeMap<String, Vec<String>>,
+    /// Every item, sorted
+    uids: BTreeSet<String>,
+    anomalies: Vec<Anomaly>,
+}
+
+impl Hierarchy {
+    /// Build a hierarchy out of `(uid, parent uid)` pairs, with the depth limit of [`crate::config::MAX_HIERARCHY_DEPTH`].
+    ///
+    /// Parents that are not part of `items` are ignored, i.e. their children are top-level items.
+    pub fn build<I: IntoIterator<Item = (String, Option<String>)>>(items: I) -> Self {
+        let max_depth = *crate::config::MAX_HIERARCHY_DEPTH.lock().unwrap();
+        Self::build_with_max_depth(items, max_depth)
+    }
+
+    /// Same as [`Self::build`], with a given depth limit (top-level items have a depth of 0)
+    pub fn build_with_max_depth<I: IntoIterator<Item = (String, Option<String>)>>(items: I, max_depth: usize) -> Self {
+        let mut links: BTreeMap<String, Option<String>> = BTreeMap::new();
+        for (uid, parent) in items {
+            // In case of duplicated UIDs, the first one wins
+            links.entry(uid).or_insert(parent);
+        }
+        let uids: BTreeSet<String> = links.keys().cloned().collect();
+        let mut parents: BTreeMap<String, String> = links.into_iter()
+            .filter_map(|(uid, parent)| parent.map(|parent| (uid, parent)))
+            .filter(|(_, parent)| uids.contains(parent))
+            .collect();
+        let mut anomalies = Vec::new();
+
+        // Every item has at most one parent, so that walking up from any item either reaches a top-level item, or loops into a cycle
+        let mut done: BTreeSet<String> = BTreeSet::new();
+        for start in &uids {
+            let mut path: Vec<&String> = Vec::new();
+            let mut current = Some(start);
+            while let Some(uid) = current {
+                if done.contains(uid) {
+                    break;
+                }
+                if let Some(position) = path.iter().position(|visited| *visited == uid) {
+                    let mut cycle: Vec<String> = path[position..].iter().map(|uid| uid.to_string()).collect();
+                    cycle.sort();
+                    let broken_at = cycle[0].clone();
+                    log::warn!("Items {:?} are ancestors of themselves. Making {} a top-level item", cycle, broken_at);
+                    parents.remove(&broken_at);
+                    anomalies.push(Anomaly::Cycle { uids: cycle, broken_at });
+                    break;
+                }
+                path.push(uid);
+                current = parents.get(uid).and_then(|parent| uids.get(parent));
+            }
+            done.extend(path.into_iter().cloned());
+        }
+
+        // Now that there are no cycles left, cut the chains that are too deep
+        let mut depths: HashMap<String, usize> = HashMap::new();
+        for start in &uids {
+            let mut path: Vec<String> = Vec::new();
+            let mut current = Some(start.clone());
+            while let Some(uid) = current {
+                if depths.contains_key(&uid) {
+                    break;
+                }
+                current = parents.get(&uid).cloned();
+                path.push(uid);
+            }
+            // Assign the depths top-down, starting from the first ancestor whose depth is known (if any)
+            for uid in path.into_iter().rev() {
+                let depth = match parents.get(&uid).map(|parent| depths[parent] + 1) {
+                    None => 0,
+                    Some(depth) if depth > max_depth => {
+                        log::warn!("Item {} is more than {} levels deep. Making it a top-level item", uid, max_depth);
+                        parents.remove(&uid);
+                        anomalies.push(Anomaly::TooDeep { uid: uid.clone(), limit: max_depth });
+                        0
+                    },
+                    Some(depth) => depth,
+                };
+                depths.insert(uid, depth);
+            }
+        }
+        anomalies.sort_by(|a, b| anomaly_key(a).cmp(anomaly_key(b)));
+
+        let mut children: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (uid, parent) in &parents {
+            children.entry(parent.clone()).or_default().push(uid.clone());
+        }
+        Self { parents, children, uids, anomalies }
+    }
+
+    /// The parent of an item, in case it is not a top-level item
+    pub fn parent(&self, uid: &str) -> Option<&str> {
+        self.parents.get(uid).map(|parent| parent.as_str())
+    }
+
+    /// The children of an item, sorted by UID
+    pub fn children(&self, uid: &str) -> &[String] {
+        self.children.get(uid).map(|children| children.as_slice()).unwrap_or_default()
+    }
+
+    /// The top-level items, sorted by UID
+    pub fn roots(&self) -> Vec<&str> {
+        self.uids.iter()
+            .filter(|uid| !self.parents.contains_key(*uid))
+            .map(|uid| uid.as_str())
+            .collect()
+    }
+
+    /// The ancestors of an item, from its parent to its top-level ancestor
+    pub fn ancestors(&self, uid: &str) -> Vec<&str> {
+        let mut ancestors = Vec::new();
+        let mut current = self.parent(uid);
+        while let Some(parent) = current {
+            ancestors.push(parent);
+            current = self.parent(parent);
+        }
+        ancestors
+    }
+
+    /// How deep an item is (top-level items have a depth of 0)
+    pub fn depth(&self, uid: &str) -> usize {
+        self.ancestors(uid).len()
+    }
+
+    /// The inconsistent links that have been cut, sorted by the UIDs they involve
+    pub fn anomalies(&self) -> &[Anomaly] {
+        &self.anomalies
+    }
+
+    /// Whether an item is involved in an anomaly (e.g. so that a UI can flag it)
+    pub fn is_flagged(&self, uid: &str) -> bool {
+        self.anomalies.iter().any(|anomaly| match anomaly {
+            Anomaly::Cycle { uids, .. } => uids.iter().any(|flagged| flagged == uid),
+            Anomaly::TooDeep { uid: flagged, .. } => flagged == uid,
+        })
+    }
+}
+
+fn anomaly_key(anomaly: &Anomaly) -> &str {
+    match anomaly {
+        Anomaly::Cycle { broken_at, .. } => broken_at,
+        Anomaly::TooDeep { uid, .. } => uid,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(links: &[(&str, Option<&str>)], max_depth: usize) -> Hierarchy {
+        Hierarchy::build_with_max_depth(links.iter().map(|(uid, parent)| (uid.to_string(), parent.map(String::from))), max_depth)
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let hierarchy = build(&[
+            ("house", None),
+            ("paint", Some("house")),
+            ("buy-paint", Some("paint")),
+            ("orphan", Some("deleted-task")),
+            ("chicken", Some("egg")),
+            ("egg", Some("chicken")),
+            ("narcissus", Some("narcissus")),
+        ], 10);
+        assert_eq!(hierarchy.roots(), vec!["chicken", "house", "narcissus", "orphan"]);
+        assert_eq!(hierarchy.children("house"), ["paint"]);
+        assert_eq!(hierarchy.ancestors("buy-paint"), vec!["paint", "house"]);
+        assert_eq!(hierarchy.depth("buy-paint"), 2);
+        assert_eq!(hierarchy.parent("egg"), Some("chicken"));
+        assert_eq!(hierarchy.anomalies(), [
+            Anomaly::Cycle { uids: vec!["chicken".to_string(), "egg".to_string()], broken_at: "chicken".to_string() },
+            Anomaly::Cycle { uids: vec!["narcissus".to_string()], broken_at: "narcissus".to_string() },
+        ]);
+        assert!(hierarchy.is_flagged("egg"));
+        assert!(!hierarchy.is_flagged("paint"));
+
+        let hierarchy = build(&[("a", None), ("b", Some("a")), ("c", Some("b")), ("d", Some("c")), ("e", Some("d"))], 2);
+        assert_eq!(hierarchy.anomalies(), [Anomaly::TooDeep { uid: "d".to_string(), limit: 2 }]);
+        assert_eq!(hierarchy.roots(), vec!["a", "d"]);
+        assert_eq!(hierarchy.depth("e"), 1);
+    }
+
+    /// Random parent graphs always give a forest that respects the depth limit
+    #[test]
+    fn test_random_hierarchies() {
+        // A xorshift generator, so that failures can be reproduced
+        let mut state: u64 = 0x9e3779b97f4a7c15;
+        let mut next = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        for _ in 0..500 {
+            let n_items = 1 + next(60);
+            let max_depth = next(8);
+            let links: Vec<(String, Option<String>)> = (0..n_items)
+                .map(|i| {
+                    let parent = match next(5) {
+                        0 => None,
+                        // Some parents are not in the calendar
+                        1 => Some(format!("missing-{}", next(3))),
+                        _ => Some(format!("task-{}", next(n_items))),
+                    };
+                    (format!("task-{}", i), parent)
+                })
+                .collect();
+
+            let hierarchy = Hierarchy::build_with_max_depth(links, max_depth);
+            for i in 0..n_items {
+                let uid = format!("task-{}", i);
+                // Walking up always terminates within the limit
+                assert!(hierarchy.depth(&uid) <= max_depth);
+                if let Some(parent) = hierarchy.parent(&uid) {
+                    assert!(hierarchy.children(parent).contains(&uid));
+                }
+            }
+            let n_reachable: usize = hierarchy.roots().iter().map(|root| 1 + count_descendants(&hierarchy, root)).sum();
+            assert_eq!(n_reachable, n_items);
+        }
+    }
+
+    fn count_descendants(hierarchy: &Hierarchy, uid: &str) -> usize {
+        hierarchy.children(uid).iter().map(|child| 1 + count_descendants(hierarchy, child)).sum()
+    }
+}
@@ -0,0 +1,70 @@
+//! A bounded log of the changes made to the items of a cached calendar (see [`crate::calendar::cached_calendar::CachedCalendar::item_history`])
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use crate::item::Item;
+
+/// Who made a change
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeSource {
+    /// The user of the cache (e.g. an app)
+    #[default]
+    Local,
+    /// The server, whose changes have been applied during a sync
+    Remote,
+    /// The sync engine itself, e.g. when it forgets an item whose deletion has been pushed to the server
+    Sync,
+}
+
+/// What has changed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Created,
+    /// The item has been replaced. `properties` are the names of the iCal properties that differ (it is empty when only the sync status has changed)
+    Modified { properties: Vec<String> },
+    MarkedForDeletion,
+    Deleted,
+}
+
+/// An entry of the history of an item
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub source: ChangeSource,
+    pub kind: ChangeKind,
+}
+
+/// Append an entry to a history, and drop its oldest entries so that it has at most `limit` entries
+pub(crate) fn push_bounded(history: &mut VecDeque<HistoryEntry>, entry: HistoryEntry, limit: usize) {
+    history.push_back(entry);
+    while history.len() > limit {
+        history.pop_front();
+    }
+}
+
+/// The names of the properties that differ between two versions of an item, sorted. Properties that change at every save (e.g. `DTSTAMP`) are ignored
+pub(crate) fn changed_properties(before: &Item, after: &Item) -> Vec<String> {
+    let diff = crate::ical::build_from(before)
+        .and_then(|before| crate::ical::build_from(after).map(|after| (before, after)))
+        .and_then(|(before, after)| crate::ical::diff(&before, &after, true));
+    let changes = match diff {
+        Ok(changes) => changes,
+        Err(err) => {
+            log::warn!("Unable to tell how item {} has changed: {}", after.url(), err);
+            return Vec::new();
+        },
+    };
+    let mut properties: Vec<String> = changes.into_iter()
+        .map(|change| match change {
+            crate::ical::PropertyChange::Added { name, .. }
+            | crate::ical::PropertyChange::Removed { name, .. }
+            | crate::ical::PropertyChange::Changed { name, .. } => name,
+        })
+        .collect();
+    properties.sort();
+    properties.dedup();
+    properties
+}
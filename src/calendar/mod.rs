@@ -2,16 +2,29 @@
 
 pub mod cached_calendar;
 pub mod remote_calendar;
+pub mod duplicates;
+pub mod acl;
+pub mod history;
+pub mod hierarchy;
 
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::error::Error;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use bitflags::bitflags;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use url::Url;
+
+use crate::traits::BaseCalendar;
+use crate::item::{Item, VersionTag};
+use crate::error_code::{self, ErrorCode};
 
 bitflags! {
-    #[derive(Serialize, Deserialize)]
+    /// The kinds of items a calendar can contain.
+    ///
+    /// It is serialized as a list of iCal component names (e.g. `["VEVENT", "VTODO"]`), so that stored values do not depend on the values of the flags.
     pub struct SupportedComponents: u8 {
         /// An event, such as a calendar meeting
         const EVENT = 1;
@@ -20,42 +33,441 @@ bitflags! {
     }
 }
 
+/// The iCal names of the components
+const COMPONENT_NAMES: [(SupportedComponents, &str); 2] = [
+    (SupportedComponents::EVENT, "VEVENT"),
+    (SupportedComponents::TODO, "VTODO"),
+];
+
+/// The namespace of the `<supported-calendar-component-set>` element
+const CALDAV_NS: &str = "urn:ietf:params:xml:ns:caldav";
+
 impl SupportedComponents {
+    /// Returns the flag of an iCal component name (e.g. `VTODO`), if this crate supports it
+    pub fn from_component_name(name: &str) -> Option<Self> {
+        COMPONENT_NAMES.iter()
+            .find(|(_, n)| *n == name)
+            .map(|(flag, _)| *flag)
+    }
+
+    /// The iCal names of the components this contains
+    pub fn component_names(&self) -> Vec<&'static str> {
+        COMPONENT_NAMES.iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// Make sure a calendar that supports these components can store an item (e.g. that a task is not added to a calendar that only supports events)
+    pub(crate) fn check_item(&self, item: &crate::Item) -> Result<(), crate::ical::UnsupportedComponentError> {
+        match Self::from_component_name(item.component_name()) {
+            Some(component) if !self.contains(component) => Err(crate::ical::UnsupportedComponentError {
+                item: item.url().clone(),
+                component: Some(item.component_name().to_string()),
+                supported: *self,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Build a `<supported-calendar-component-set>` element, that is the inverse of `SupportedComponents::try_from(Element)`
+    pub fn to_xml_element(&self) -> minidom::Element {
+        ComponentSet::from(*self).to_xml_element()
+    }
+
+    /// The serialized form of [`Self::to_xml_element`], e.g. to be part of a `MKCALENDAR` body
     pub fn to_xml_string(&self) -> String {
-        format!(r#"
-            <B:supported-calendar-component-set>
-                {} {}
-            </B:supported-calendar-component-set>
-            "#,
-            if self.contains(Self::EVENT) { "<B:comp name=\"VEVENT\"/>" } else { "" },
-            if self.contains(Self::TODO)  { "<B:comp name=\"VTODO\"/>"  } else { "" },
-        )
+        ComponentSet::from(*self).to_xml_string()
+    }
+}
+
+/// The iCal names of the components, separated by commas (e.g. `VEVENT,VTODO`)
+impl std::fmt::Display for SupportedComponents {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.component_names().join(","))
     }
 }
 
 impl TryFrom<minidom::Element> for SupportedComponents {
     type Error = Box<dyn Error>;
 
-    /// Create an instance from an XML <supported-calendar-component-set> element
+    /// Create an instance from an XML `<supported-calendar-component-set>` element.
+    /// The components this crate does not know are ignored (see [`ComponentSet`] to keep them)
+    fn try_from(element: minidom::Element) -> Result<Self, Self::Error> {
+        let components = ComponentSet::try_from(element)?;
+        for name in &components.other {
+            log::warn!("Unimplemented supported component type: {:?}. Ignoring it", name);
+        }
+        Ok(components.known)
+    }
+}
+
+
+/// The components a calendar supports, including the ones this crate does not know (e.g. `VJOURNAL` or `VAVAILABILITY`).
+///
+/// The unknown ones are kept by name, so that they are advertised again when a calendar is created after another one (e.g. by [`crate::provider::copy::copy_calendar`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentSet {
+    pub known: SupportedComponents,
+    /// The names of the other components, in the order the server has listed them
+    pub other: Vec<String>,
+}
+
+impl ComponentSet {
+    /// The components a calendar supports (see [`BaseCalendar::other_components`])
+    pub fn of<C: BaseCalendar + ?Sized>(calendar: &C) -> Self {
+        Self { known: calendar.supported_components(), other: calendar.other_components().to_vec() }
+    }
+
+    /// Build a `<supported-calendar-component-set>` element, that is the inverse of `ComponentSet::try_from(Element)`
+    pub fn to_xml_element(&self) -> minidom::Element {
+        let names = self.known.component_names().into_iter()
+            .chain(self.other.iter().map(String::as_str));
+        minidom::Element::builder("supported-calendar-component-set", CALDAV_NS)
+            .append_all(names.map(|name| minidom::Element::builder("comp", CALDAV_NS).attr("name", name).build()))
+            .build()
+    }
+
+    /// The serialized form of [`Self::to_xml_element`], e.g. to be part of a `MKCALENDAR` body
+    pub fn to_xml_string(&self) -> String {
+        let mut xml = Vec::new();
+        match self.to_xml_element().write_to(&mut xml) {
+            Err(err) => {
+                // Writing into memory does not fail, and component names are escaped
+                log::error!("Unable to serialize supported components {}: {}", self, err);
+                String::new()
+            },
+            Ok(()) => String::from_utf8_lossy(&xml).into_owned(),
+        }
+    }
+}
+
+impl From<SupportedComponents> for ComponentSet {
+    fn from(known: SupportedComponents) -> Self {
+        Self { known, other: Vec::new() }
+    }
+}
+
+/// The iCal names of the components, separated by commas (e.g. `VTODO,VJOURNAL`)
+impl std::fmt::Display for ComponentSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut names: Vec<&str> = self.known.component_names();
+        names.extend(self.other.iter().map(String::as_str));
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl TryFrom<minidom::Element> for ComponentSet {
+    type Error = Box<dyn Error>;
+
+    /// Create an instance from an XML `<supported-calendar-component-set>` element
     fn try_from(element: minidom::Element) -> Result<Self, Self::Error> {
         if element.name() != "supported-calendar-component-set" {
             return Err("Element must be a <supported-calendar-component-set>".into());
         }
 
-        let mut flags = Self::empty();
-        for child in element.children() {
-            match child.attr("name") {
-                None => continue,
-                Some("VEVENT") => flags.insert(Self::EVENT),
-                Some("VTODO") => flags.insert(Self::TODO),
-                Some(other) => {
-                    log::warn!("Unimplemented supported component type: {:?}. Ignoring it", other);
-                    continue
-                },
-            };
+        let mut components = Self::from(SupportedComponents::empty());
+        for name in element.children().filter_map(|child| child.attr("name")) {
+            match SupportedComponents::from_component_name(name) {
+                Some(flag) => components.known.insert(flag),
+                None if components.other.iter().any(|other| other == name) => (),
+                None => components.other.push(name.to_string()),
+            }
+        }
+
+        Ok(components)
+    }
+}
+
+impl Serialize for SupportedComponents {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.component_names().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SupportedComponents {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Names(Vec<String>),
+            /// How former versions of this crate stored these flags
+            Bits { bits: u8 },
+        }
+
+        match Stored::deserialize(deserializer)? {
+            Stored::Names(names) => {
+                let mut flags = Self::empty();
+                for name in names {
+                    match Self::from_component_name(&name) {
+                        Some(flag) => flags.insert(flag),
+                        None => log::warn!("Unknown component type {:?} in stored supported components. Ignoring it", name),
+                    }
+                }
+                Ok(flags)
+            },
+            Stored::Bits { bits } => {
+                if Self::from_bits(bits).is_none() {
+                    log::warn!("Unknown flags in stored supported components ({:#b}). Ignoring them", bits);
+                }
+                Ok(Self::from_bits_truncate(bits))
+            },
+        }
+    }
+}
+
+
+/// Returns whether the privileges of a `<current-user-privilege-set>` grant the right to write items
+pub(crate) fn privileges_allow_writing(privileges: &[String]) -> bool {
+    privileges.iter()
+        .any(|p| matches!(p.as_str(), "all" | "write" | "write-content"))
+}
+
+
+/// Criteria to select calendars. See [`crate::traits::CalDavSource::get_calendars_matching`]
+///
+/// The default filter matches every calendar.
+#[derive(Clone, Debug, Default)]
+pub struct CalendarFilter {
+    /// If set, only calendars that support (at least) these components match
+    pub components: Option<SupportedComponents>,
+    /// If set, only calendars that are (or are not) writable match
+    pub writable: Option<bool>,
+}
+
+impl CalendarFilter {
+    /// Returns whether a given calendar matches this filter
+    pub fn matches<C: BaseCalendar + ?Sized>(&self, calendar: &C) -> bool {
+        if let Some(components) = self.components {
+            if !calendar.supported_components().contains(components) {
+                return false;
+            }
+        }
+        if let Some(writable) = self.writable {
+            if calendar.is_writable() != writable {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+
+/// The error returned by [`crate::traits::DavCalendar::get_items_by_url`] when only part of the requested items could be fetched
+/// (e.g. because the connection dropped in the middle of the server reply).
+///
+/// The items that are not in `fetched` can be requested again later.
+#[derive(Debug)]
+pub struct PartialFetchError {
+    /// The items that have been received before the error happened
+    pub fetched: Vec<Item>,
+    /// What prevented the other items from being fetched
+    pub cause: Box<dyn Error>,
+}
+
+impl PartialFetchError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::PARTIAL_FETCH
+    }
+}
+
+impl std::fmt::Display for PartialFetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "only {} items could be fetched: {} [{}]", self.fetched.len(), self.cause, self.code())
+    }
+}
+
+impl Error for PartialFetchError {}
+
+
+/// The error returned when an item cannot be uploaded because the account is over its storage quota
+/// (i.e. the server replied with `507 Insufficient Storage`, or with a `quota-not-exceeded` precondition failure)
+#[derive(Debug)]
+pub struct QuotaExceededError {
+    /// The calendar the upload was meant for
+    pub calendar: Url,
+}
+
+impl QuotaExceededError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::QUOTA_EXCEEDED
+    }
+}
+
+impl std::fmt::Display for QuotaExceededError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "storage quota exceeded for calendar {} [{}]", self.calendar, self.code())
+    }
+}
+
+impl Error for QuotaExceededError {}
+
+/// The error returned when an item cannot be updated or deleted on the server, because it has changed there since its version tag has been read
+/// (i.e. the server replied `412 Precondition Failed` to a conditional request).
+///
+/// Nothing has been overwritten: this is a conflict, that the next sync resolves like any other
+#[derive(Debug)]
+pub struct PreconditionFailedError {
+    /// The item that has changed on the server
+    pub item: Url,
+}
+
+impl PreconditionFailedError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::PRECONDITION_FAILED
+    }
+}
+
+impl std::fmt::Display for PreconditionFailedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "item {} has changed on the server since it has been read [{}]", self.item, self.code())
+    }
+}
+
+impl Error for PreconditionFailedError {}
+
+/// What has changed in a calendar since it had a given sync token (see [`crate::traits::DavCalendar::get_changes_since`])
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ItemChanges {
+    /// The items that have been created or modified, with their current version tags
+    pub changed: HashMap<Url, VersionTag>,
+    /// The items that have been deleted
+    pub deleted: HashSet<Url>,
+    /// The sync token of the calendar as it was when these changes have been listed, that the next changes are to be asked from
+    pub sync_token: String,
+}
+
+/// The range of date-times a calendar accepts, as advertised by the server (CalDAV `min-date-time` and `max-date-time` properties).
+///
+/// Some servers refuse items whose dates are out of this range (e.g. before 1970), usually with an unhelpful error
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DateLimits {
+    pub min: Option<DateTime<Utc>>,
+    pub max: Option<DateTime<Utc>>,
+}
+
+impl DateLimits {
+    /// Whether a date-time is within these limits
+    pub fn contains(&self, date: &DateTime<Utc>) -> bool {
+        !matches!(self.min, Some(min) if *date < min) && !matches!(self.max, Some(max) if *date > max)
+    }
+
+    /// Returns the closest date-time that is within these limits
+    pub fn clamp(&self, date: DateTime<Utc>) -> DateTime<Utc> {
+        match (self.min, self.max) {
+            (Some(min), _) if date < min => min,
+            (_, Some(max)) if date > max => max,
+            _ => date,
+        }
+    }
+
+    /// Whether no limit is set
+    pub fn is_unbounded(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+
+    /// Parse the value of a `min-date-time` or `max-date-time` property (a UTC date-time, e.g. `19700101T000000Z`)
+    pub(crate) fn parse_limit(value: &str) -> Option<DateTime<Utc>> {
+        let naive = NaiveDateTime::parse_from_str(value.trim().strip_suffix('Z')?, "%Y%m%dT%H%M%S").ok()?;
+        Some(DateTime::from_utc(naive, Utc))
+    }
+}
+
+
+/// A format the items of a calendar can be exchanged in, as advertised by the server (CalDAV `supported-calendar-data` property)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CalendarDataFormat {
+    /// `text/calendar`, i.e. iCalendar ([RFC5545](https://tools.ietf.org/html/rfc5545))
+    ICalendar,
+    /// `application/calendar+json`, i.e. jCal ([RFC7265](https://tools.ietf.org/html/rfc7265))
+    JCal,
+    /// Any other content type
+    Other(String),
+}
+
+impl CalendarDataFormat {
+    /// Parse a content type (e.g. `text/calendar; charset=utf-8`)
+    pub fn from_content_type(content_type: &str) -> Self {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "text/calendar" => Self::ICalendar,
+            "application/calendar+json" => Self::JCal,
+            _ => Self::Other(mime),
+        }
+    }
+
+    /// The content type of this format
+    pub fn content_type(&self) -> &str {
+        match self {
+            Self::ICalendar => "text/calendar",
+            Self::JCal => "application/calendar+json",
+            Self::Other(content_type) => content_type,
+        }
+    }
+
+    /// Whether this crate is able to read and write items in this format
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Self::ICalendar | Self::JCal)
+    }
+
+    /// Parse a `supported-calendar-data` element. Formats of another version than 2.0 are ignored
+    pub(crate) fn parse_supported(element: &minidom::Element) -> Vec<Self> {
+        element.children()
+            .filter(|child| child.name() == "calendar-data")
+            .filter(|child| !matches!(child.attr("version"), Some(version) if version.trim() != "2.0"))
+            .map(|child| Self::from_content_type(child.attr("content-type").unwrap_or("text/calendar")))
+            .collect()
+    }
+
+    /// The best format to exchange items in, among the ones a calendar supports.
+    ///
+    /// jCal is preferred when both this crate and the server support it. Otherwise, this is iCalendar, that every CalDAV server supports
+    pub fn negotiate(supported: &[Self]) -> Self {
+        match Self::JCal.is_supported() && supported.contains(&Self::JCal) {
+            true => Self::JCal,
+            false => Self::ICalendar,
         }
+    }
+}
 
-        Ok(flags)
+/// The source of a subscribed calendar, i.e. a calendar that mirrors an iCal feed (e.g. a `webcal://` URL) instead of storing its own items.
+///
+/// The items of such a calendar are regularly re-fetched from the source, and nothing is ever pushed into it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    /// The URL of the iCal feed
+    pub source: Url,
+    /// How often the feed should be refreshed, as advertised by the server (if any)
+    pub refresh_interval: Option<chrono::Duration>,
+}
+
+impl Subscription {
+    /// Parse the URL of a feed. `webcal://` URLs are fetched over HTTPS
+    pub fn parse_source(href: &str) -> Option<Url> {
+        let href = href.trim();
+        let lowercase = href.to_ascii_lowercase();
+        let url = match ["webcal://", "webcals://"].iter().find(|scheme| lowercase.starts_with(*scheme)) {
+            Some(scheme) => format!("https://{}", &href[scheme.len()..]),
+            None => href.to_string(),
+        };
+        Url::parse(&url).ok()
+            .filter(|url| url.scheme() == "https" || url.scheme() == "http")
+    }
+
+    /// How often the feed is refreshed: either the interval advertised by the server, or [`crate::config::SUBSCRIPTION_REFRESH_INTERVAL`]
+    pub fn effective_refresh_interval(&self) -> chrono::Duration {
+        self.refresh_interval.unwrap_or_else(|| *crate::config::SUBSCRIPTION_REFRESH_INTERVAL.lock().unwrap())
+    }
+
+    /// Whether the feed should be fetched again, given when it was last refreshed
+    pub fn is_due(&self, last_refreshed: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        match last_refreshed {
+            None => true,
+            Some(last) => now - last >= self.effective_refresh_interval(),
+        }
     }
 }
 
@@ -77,3 +489,30 @@ impl Default for SearchFilter {
         SearchFilter::All
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_components_xml_round_trip() {
+        let from_server: minidom::Element = r#"<C:supported-calendar-component-set xmlns:C="urn:ietf:params:xml:ns:caldav"><C:comp name="VTODO"/><C:comp name="VJOURNAL"/><C:comp name="VAVAILABILITY"/></C:supported-calendar-component-set>"#.parse().unwrap();
+        let parsed = ComponentSet::try_from(from_server.clone()).unwrap();
+        assert_eq!(parsed.known, SupportedComponents::TODO);
+        assert_eq!(parsed.other, vec!["VJOURNAL", "VAVAILABILITY"]);
+        assert_eq!(parsed.to_string(), "VTODO,VJOURNAL,VAVAILABILITY");
+        assert_eq!(SupportedComponents::try_from(from_server).unwrap(), SupportedComponents::TODO);
+
+        // Unknown components are written back, after the known ones
+        let reparsed: minidom::Element = parsed.to_xml_string().parse().unwrap();
+        assert_eq!(reparsed, parsed.to_xml_element());
+        assert_eq!(ComponentSet::try_from(reparsed).unwrap(), parsed);
+
+        for components in [SupportedComponents::all(), SupportedComponents::EVENT, SupportedComponents::empty()] {
+            let reparsed: minidom::Element = components.to_xml_string().parse().unwrap();
+            assert_eq!(reparsed, components.to_xml_element());
+            assert_eq!(SupportedComponents::try_from(reparsed).unwrap(), components);
+        }
+    }
+}
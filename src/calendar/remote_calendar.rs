@@ -1,10 +1,15 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::sync::Mutex;
 
 use async_trait::async_trait;
-use reqwest::{header::CONTENT_TYPE, header::CONTENT_LENGTH};
+use reqwest::{header::ACCEPT, header::CONTENT_TYPE, header::CONTENT_LENGTH, header::CONTENT_LOCATION, header::HeaderMap};
+use reqwest::header::{HeaderName, ETAG, IF_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED};
+use reqwest::{Method, StatusCode};
+use chrono::{DateTime, Duration, Utc};
 use csscolorparser::Color;
+use minidom::Element;
 use url::Url;
 
 use crate::traits::BaseCalendar;
@@ -13,28 +18,144 @@ use crate::calendar::SupportedComponents;
 use crate::item::Item;
 use crate::item::VersionTag;
 use crate::item::SyncStatus;
-use crate::resource::Resource;
-use crate::utils::find_elem;
+use crate::resource::{Reply, Resource};
+use crate::ical::StreamedIcal;
+use crate::ical::UnsupportedComponentError;
+use crate::utils::{find_elem, find_elems, parse_xml};
+use crate::calendar::PartialFetchError;
+use crate::calendar::QuotaExceededError;
+use crate::calendar::PreconditionFailedError;
+use crate::calendar::DateLimits;
+use crate::calendar::Subscription;
+use crate::calendar::CalendarDataFormat;
+use crate::calendar::ItemChanges;
+use crate::calendar::acl::{parse_acl_reply, Acl, ACL_BODY};
+use crate::client::{max_response_size, read_body, xml_content_type, TruncatedReply};
+use crate::dav_error::{DavErrorDetail, HttpError};
+use crate::error::KitchenFridgeError;
+use crate::ical::recurrence::{has_recurrence_rules, instances_of, Instance};
 
-static TASKS_BODY: &str = r#"
+/// The precondition header that asks the server not to overwrite a scheduling object whose Schedule-Tag has changed ([RFC6638](https://tools.ietf.org/html/rfc6638#section-8.3))
+const IF_SCHEDULE_TAG_MATCH: &str = "if-schedule-tag-match";
+
+/// A `calendar-query` that lists the items of a given kind (e.g. `VTODO`)
+fn items_body(component: &str) -> String {
+    format!(r#"
     <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
         <d:prop>
             <d:getetag />
+            <d:getlastmodified />
+            <d:getcontentlength />
+            <c:schedule-tag />
         </d:prop>
         <c:filter>
             <c:comp-filter name="VCALENDAR">
-                <c:comp-filter name="VTODO" />
+                <c:comp-filter name="{}" />
             </c:comp-filter>
         </c:filter>
     </c:calendar-query>
+"#, component)
+}
+
+static EXISTENCE_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:">
+        <d:prop>
+            <d:getetag />
+        </d:prop>
+    </d:propfind>
 "#;
 
-static MULTIGET_BODY_PREFIX: &str = r#"
-    <c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+static VERSION_TAG_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+            <d:getlastmodified />
+            <c:schedule-tag />
+        </d:prop>
+    </d:propfind>
+"#;
+
+static CTAG_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/">
         <d:prop>
-            <c:calendar-data />
+            <cs:getctag />
+            <d:sync-token />
         </d:prop>
+    </d:propfind>
 "#;
+
+/// A `PROPPATCH` that sets the Nextcloud flag that tells whether a calendar is enabled for sync
+fn enabled_body(enabled: bool) -> String {
+    format!(r#"
+    <d:propertyupdate xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+        <d:set>
+            <d:prop>
+                <oc:calendar-enabled>{}</oc:calendar-enabled>
+            </d:prop>
+        </d:set>
+    </d:propertyupdate>
+"#, if enabled { 1 } else { 0 })
+}
+
+/// A `calendar-query` for the components of a given kind within a time range, possibly with server-side expansion of their recurrences
+fn expand_body(component: &str, start: &DateTime<Utc>, end: &DateTime<Utc>, server_side_expansion: bool) -> String {
+    let start = start.format("%Y%m%dT%H%M%SZ");
+    let end = end.format("%Y%m%dT%H%M%SZ");
+    let expand = match server_side_expansion {
+        true => format!(r#"<c:expand start="{}" end="{}" />"#, start, end),
+        false => String::new(),
+    };
+    format!(r#"
+    <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <c:calendar-data>{}</c:calendar-data>
+        </d:prop>
+        <c:filter>
+            <c:comp-filter name="VCALENDAR">
+                <c:comp-filter name="{}">
+                    <c:time-range start="{}" end="{}" />
+                </c:comp-filter>
+            </c:comp-filter>
+        </c:filter>
+    </c:calendar-query>
+"#, expand, component, start, end)
+}
+
+/// A `sync-collection` REPORT, that lists the items that have changed since a sync token, or every item when there is no token ([RFC6578](https://tools.ietf.org/html/rfc6578#section-3.2))
+fn sync_collection_body(sync_token: Option<&str>) -> String {
+    let sync_token = sync_token.unwrap_or_default()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(r#"
+    <d:sync-collection xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:sync-token>{}</d:sync-token>
+        <d:sync-level>1</d:sync-level>
+        <d:prop>
+            <d:getetag />
+            <d:getlastmodified />
+            <d:getcontentlength />
+            <c:schedule-tag />
+        </d:prop>
+    </d:sync-collection>
+"#, sync_token)
+}
+
+/// The beginning of a `calendar-multiget` REPORT, that asks for the items in a given format
+fn multiget_body_prefix(data_format: &CalendarDataFormat) -> String {
+    let calendar_data = match data_format {
+        CalendarDataFormat::ICalendar => "<c:calendar-data />".to_string(),
+        format => format!(r#"<c:calendar-data content-type="{}" version="2.0" />"#, format.content_type()),
+    };
+    format!(r#"
+    <c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+            <c:schedule-tag />
+            {}
+        </d:prop>
+"#, calendar_data)
+}
 static MULTIGET_BODY_SUFFIX: &str = r#"
     </c:calendar-multiget>
 "#;
@@ -47,9 +168,692 @@ pub struct RemoteCalendar {
     name: String,
     resource: Resource,
     supported_components: SupportedComponents,
+    /// See [`BaseCalendar::other_components`]
+    other_components: Vec<String>,
     color: Option<Color>,
+    writable: bool,
+    privileges: Option<Vec<String>>,
+    supported_reports: Vec<String>,
+    date_limits: DateLimits,
+    subscription: Option<Subscription>,
+    data_formats: Vec<CalendarDataFormat>,
+    max_resource_size: Option<usize>,
+    enabled: Option<bool>,
 
     cached_version_tags: Mutex<Option<HashMap<Url, VersionTag>>>,
+    /// The version tags of the items the last [`DavCalendar::get_changes_since`] has listed, that are fetched without listing the whole calendar
+    changed_version_tags: Mutex<HashMap<Url, VersionTag>>,
+    /// The latest version tag of every item that has been listed, fetched or uploaded, that deletions are made conditional on (see [`DavCalendar::delete_item`])
+    item_version_tags: Mutex<HashMap<Url, VersionTag>>,
+    /// The sizes the server has told for the items it has listed (see [`DavCalendar::item_size`])
+    item_sizes: Mutex<HashMap<Url, u64>>,
+    /// The URLs the server has reported as canonical for items that have been uploaded to another URL (see [`DavCalendar::take_canonical_url`])
+    canonical_urls: Mutex<HashMap<Url, Url>>,
+    /// The versions the server actually stores of items it has rewritten when they were uploaded (see [`DavCalendar::take_rewritten_item`])
+    rewritten_items: Mutex<HashMap<Url, Item>>,
+    /// The components that have been found instead of supported ones in the items that have been requested (see [`DavCalendar::take_unsupported_component`])
+    unsupported_components: Mutex<HashMap<Url, Option<String>>>,
+    /// The items that have kept changing while they were being fetched (see [`DavCalendar::take_unstable_item`])
+    unstable_items: Mutex<HashSet<Url>>,
+}
+
+/// How the version of a fetched item compares to the one it has been listed with (see [`RemoteCalendar::check_fetched_version`])
+enum FetchedVersion {
+    /// The version of the fetched item is known, and matches its content
+    Consistent(VersionTag),
+    /// The item has been deleted since it has been listed
+    Deleted,
+    /// The item has changed again while its version was being checked
+    Unstable,
+}
+
+impl RemoteCalendar {
+    /// Parse the `<response>`s of a `calendar-multiget` REPORT
+    async fn parse_multiget_replies(&self, xml_replies: Vec<Element>) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        let urls: Vec<Url> = xml_replies.iter()
+            .filter_map(|xml_reply| find_elem(xml_reply, "href"))
+            .filter_map(|href| self.resource.combine(&href.text()).ok())
+            .map(|resource| resource.url().clone())
+            .collect();
+        let version_tags = self.listed_version_tags(&urls).await?;
+
+        let mut results = Vec::new();
+        for xml_reply in xml_replies {
+            let href = find_elem(&xml_reply, "href").ok_or("Missing HREF")?.text();
+            let url = self.resource.combine(&href)?.url().clone();
+            let ical_data = match find_elem(&xml_reply, "calendar-data") {
+                Some(data) => data.text(),
+                None if crate::client::failed_response_status(&xml_reply) == Some(StatusCode::NOT_FOUND) => {
+                    log::info!("Item {} has been deleted from the server since it has been listed", url);
+                    results.push(None);
+                    continue;
+                },
+                None => return Err("Missing calendar-data".into()),
+            };
+
+            let listed_vt = match version_tags.get(&url) {
+                None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
+                Some(vt) => vt,
+            };
+            let vt = match self.check_fetched_version(&url, listed_vt, version_tag_from_reply(&xml_reply)).await? {
+                FetchedVersion::Consistent(vt) => vt,
+                FetchedVersion::Deleted => {
+                    results.push(None);
+                    continue;
+                },
+                FetchedVersion::Unstable => continue,
+            };
+
+            let item = match crate::ical::parse_for_calendar(&decode_calendar_data(&ical_data)?, url.clone(), SyncStatus::Synced(vt), self.supported_components) {
+                Ok(item) => item,
+                Err(err) => match err.downcast::<UnsupportedComponentError>() {
+                    Ok(unsupported) => {
+                        log::warn!("Leaving out {}", unsupported);
+                        self.unsupported_components.lock().unwrap().insert(url, unsupported.component);
+                        continue;
+                    },
+                    Err(err) => return Err(err),
+                },
+            };
+            results.push(Some(item));
+        }
+
+        Ok(results)
+    }
+
+    /// Check that the version of an item that has been fetched is the one it has been listed with (see [`DavCalendar::get_item_version_tags`]).
+    ///
+    /// In case it is not, the item has been changed on the server in the meantime. Its current version tag is then fetched again, once: in case it still differs, the item is changing too often to be synced now
+    async fn check_fetched_version(&self, url: &Url, listed: &VersionTag, fetched: Option<VersionTag>) -> Result<FetchedVersion, Box<dyn Error>> {
+        let fetched = match fetched {
+            // Tags built from modification dates cannot be compared to ETags
+            Some(fetched) if fetched.is_etag() == listed.is_etag() && &fetched != listed => fetched,
+            _ => return Ok(FetchedVersion::Consistent(listed.clone())),
+        };
+
+        log::info!("Item {} has changed on the server since it has been listed. Checking its version again", url);
+        let current = match self.fetch_version_tag(url).await? {
+            None => {
+                log::info!("Item {} has been deleted from the server since it has been listed", url);
+                return Ok(FetchedVersion::Deleted);
+            },
+            Some(current) => current,
+        };
+        if current != fetched {
+            log::warn!("Item {} keeps changing on the server, leaving it out", url);
+            self.unstable_items.lock().unwrap().insert(url.clone());
+            return Ok(FetchedVersion::Unstable);
+        }
+        if let Some(version_tags) = self.cached_version_tags.lock().unwrap().as_mut() {
+            version_tags.insert(url.clone(), current.clone());
+        }
+        if let Some(version_tag) = self.changed_version_tags.lock().unwrap().get_mut(url) {
+            *version_tag = current.clone();
+        }
+        self.item_version_tags.lock().unwrap().insert(url.clone(), current.clone());
+        Ok(FetchedVersion::Consistent(current))
+    }
+
+    /// The version tags the items at these URLs have been listed with, be it by [`DavCalendar::get_item_version_tags`] (that is supposed to be cached) or by [`DavCalendar::get_changes_since`].
+    ///
+    /// The whole calendar is listed only in case some of them are not among the changes
+    async fn listed_version_tags(&self, urls: &[Url]) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        let mut version_tags = self.changed_version_tags.lock().unwrap().clone();
+        if urls.iter().any(|url| !version_tags.contains_key(url)) {
+            version_tags.extend(self.get_item_version_tags().await?);
+        }
+        Ok(version_tags)
+    }
+
+    /// Fetch the current version tag of a single item. Returns `None` in case it does not exist
+    async fn fetch_version_tag(&self, url: &Url) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        let resource = self.resource.combine(url.as_str())?;
+        let text = match crate::client::sub_request(&resource, "PROPFIND", VERSION_TAG_BODY.to_string(), 0).await {
+            Ok(text) => text,
+            Err(KitchenFridgeError::Status(err)) if err.status == StatusCode::NOT_FOUND => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let element = parse_xml(&text)?;
+        let response = find_elem(&element, "response").ok_or("Missing response")?;
+        if crate::client::failed_response_status(response) == Some(StatusCode::NOT_FOUND) {
+            return Ok(None);
+        }
+        version_tag_from_reply(response)
+            .map(Some)
+            .ok_or_else(|| format!("Unable to extract ETAG or last modification date for item {}", url).into())
+    }
+
+    /// Returns whether an item exists on the server, without downloading it.
+    ///
+    /// This sends a `HEAD` request, or a minimal `PROPFIND` in case the server does not allow `HEAD` requests.
+    pub async fn item_exists(&self, url: &Url) -> Result<bool, Box<dyn Error>> {
+        let head_status = self.existence_request(Method::HEAD, url, String::new()).await?;
+        if let Some(exists) = existence_from_status(head_status) {
+            return Ok(exists);
+        }
+        if head_status != StatusCode::METHOD_NOT_ALLOWED && head_status != StatusCode::NOT_IMPLEMENTED {
+            return Err(format!("Unexpected HTTP status code {:?}", head_status).into());
+        }
+
+        log::debug!("HEAD is not allowed for {}, using PROPFIND instead", url);
+        let propfind_status = self.existence_request(Method::from_bytes(b"PROPFIND").unwrap(), url, EXISTENCE_BODY.to_string()).await?;
+        existence_from_status(propfind_status)
+            .ok_or_else(|| format!("Unexpected HTTP status code {:?}", propfind_status).into())
+    }
+
+    async fn existence_request(&self, method: Method, url: &Url, body: String) -> Result<StatusCode, Box<dyn Error>> {
+        let operation = method.to_string();
+        let bytes_sent = body.len() as u64;
+        let request = self.resource.http_client()
+            .request(method, url.clone())
+            .header("Depth", 0)
+            .header(CONTENT_TYPE, xml_content_type())
+            .body(body);
+        let response = self.resource.send(request).await?;
+        Ok(self.resource.read_reply(response, &operation, bytes_sent, max_response_size()).await?.status)
+    }
+
+    /// Returns the concrete instances of the items of this calendar that start between `start` and `end`, recurring items being expanded into their occurrences.
+    ///
+    /// The server is asked to expand recurrences (with a `calendar-query` that contains an `<expand>` element).
+    /// In case it does not support it, recurrences are expanded by this crate (see [`crate::ical::recurrence::expand`] for its limitations).
+    /// Values without a timezone are then interpreted in the timezone of the system.
+    ///
+    /// Long ranges can be queried in several requests (see [`crate::config::TIME_RANGE_CHUNK_SIZE`]). Instances that belong to several of them are only returned once.
+    pub async fn get_expanded_instances(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Instance>, Box<dyn Error>> {
+        let chunk_size = *crate::config::TIME_RANGE_CHUNK_SIZE.lock().unwrap();
+        let mut instances = Vec::new();
+        for (chunk_start, chunk_end) in time_range_chunks(start, end, chunk_size) {
+            for (flag, component) in [(SupportedComponents::TODO, "VTODO"), (SupportedComponents::EVENT, "VEVENT")] {
+                if self.supported_components.contains(flag) {
+                    instances.extend(self.get_expanded_instances_of(component, &chunk_start, &chunk_end).await?);
+                }
+            }
+        }
+        // Components that overlap several chunks have been returned several times
+        Ok(crate::ical::recurrence::dedup(instances))
+    }
+
+    async fn get_expanded_instances_of(&self, component: &str, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<Instance>, Box<dyn Error>> {
+        let body = expand_body(component, start, end, true);
+        let responses = match crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", body, "response").await {
+            Ok(responses) => Some(responses),
+            Err(err) => {
+                log::info!("Server-side expansion failed ({}), recurrences will be expanded locally", err);
+                None
+            },
+        };
+        let responses = match responses {
+            Some(responses) => responses,
+            None => {
+                let body = expand_body(component, start, end, false);
+                crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", body, "response").await?
+            },
+        };
+
+        let reference_tz = crate::datetime::system_timezone();
+        let mut instances = Vec::new();
+        for response in responses {
+            let href = find_elem(&response, "href").ok_or("Missing HREF")?.text();
+            let url = self.resource.combine(&href)?.url().clone();
+            let ical_data = match find_elem(&response, "calendar-data") {
+                None => continue,
+                Some(elem) => elem.text(),
+            };
+            // Servers that do not support expansion may just ignore the <expand> element
+            let mut found = match has_recurrence_rules(&ical_data) {
+                true => crate::ical::recurrence::expand(&ical_data, &url, *start, *end, &reference_tz)?,
+                false => instances_of(&ical_data, &url)?,
+            };
+            instances.append(&mut found);
+        }
+        Ok(instances)
+    }
+
+    /// Build the error that matches a failed `PUT`. Quota errors are reported as [`QuotaExceededError`]s
+    fn upload_error(&self, reply: &Reply) -> Box<dyn Error> {
+        let body = reply.text();
+        if is_quota_error(reply.status, &body) {
+            return Box::new(QuotaExceededError{ calendar: self.resource.url().clone() });
+        }
+        Box::new(HttpError::from_reply(reply.status, &body))
+    }
+
+    /// `PUT` an item that is already on the server, provided the given precondition holds
+    async fn put_item(&self, item: &Item, precondition: (HeaderName, String)) -> Result<Reply, Box<dyn Error>> {
+        let (body, content_length, content_type) = upload_body(item.clone(), &self.data_format())?;
+        let request = self.resource.http_client()
+            .put(item.url().clone())
+            .header(precondition.0, precondition.1)
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_LENGTH, content_length)
+            .body(body);
+        let response = self.resource.send(request).await?;
+        self.resource.read_reply(response, "PUT", content_length as u64, self.max_item_size()).await
+    }
+
+    /// Upload a scheduling object whose Schedule-Tag has changed on the server, i.e. whose organizer or another attendee has changed it since the last sync.
+    ///
+    /// This is not a full conflict: the version the server stores only differs by the state of its attendees, which is taken into our version before it is uploaded again.
+    /// The merged version is remembered for [`DavCalendar::take_rewritten_item`]. In case the item has changed again in the meantime, the upload fails as usual, and the next sync handles it as a conflict
+    async fn merge_scheduling_changes(&self, mut item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let item_url = item.url().clone();
+        let stored = self.get_stored_version(&item_url).await?;
+        log::info!("Item {} has been changed by another attendee, merging their changes", item_url);
+        item.merge_attendees_of(&stored);
+
+        let stored_tag = stored.sync_status().version_tag().ok_or("The stored version has no version tag")?;
+        let precondition = match stored_tag.schedule_tag() {
+            Some(schedule_tag) => (HeaderName::from_static(IF_SCHEDULE_TAG_MATCH), schedule_tag.to_string()),
+            None => (IF_MATCH, stored_tag.as_str().to_string()),
+        };
+        let uploaded_hash = crate::ical::content_hash(&item).ok();
+        let reply = self.put_item(&item, precondition).await?;
+        if !reply.status.is_success() {
+            return Err(precondition_failed(self.upload_error(&reply), &item_url));
+        }
+
+        let new_ss = self.finish_upload(&item_url, uploaded_hash, reply).await?;
+        item.set_sync_status(new_ss.clone());
+        self.rewritten_items.lock().unwrap().entry(item_url).or_insert(item);
+        Ok(new_ss)
+    }
+
+    /// Handle the reply to a successful `PUT`, and return the sync status of the version the server now stores.
+    ///
+    /// A server that alters the content it receives (e.g. to normalise it) must not return a strong ETag ([RFC4791](https://tools.ietf.org/html/rfc4791#section-5.3.4)).
+    /// In this case, the stored version is read (from the body of the reply if the server has sent it, otherwise with a follow-up `GET`),
+    /// and remembered for [`DavCalendar::take_rewritten_item`] in case its content differs from the uploaded one
+    async fn finish_upload(&self, item_url: &Url, uploaded_hash: Option<u64>, reply: Reply) -> Result<SyncStatus, Box<dyn Error>> {
+        let reply_hdrs = &reply.headers;
+        self.remember_canonical_url(item_url, reply_hdrs);
+        let stored_url = content_location(item_url, reply_hdrs).unwrap_or_else(|| item_url.clone());
+        let etag = match reply_hdrs.get("ETag") {
+            None => None,
+            Some(etag) => Some(VersionTag::from(etag.to_str()?.to_string()).with_schedule_tag(schedule_tag_header(reply_hdrs))),
+        };
+        let returns_calendar_data = reply_hdrs.get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| CalendarDataFormat::from_content_type(content_type).is_supported())
+            .unwrap_or(false);
+
+        let stored = match (etag, returns_calendar_data) {
+            (Some(etag), false) if !is_weak_etag(&etag) => {
+                self.item_version_tags.lock().unwrap().insert(stored_url, etag.clone());
+                return Ok(SyncStatus::Synced(etag));
+            },
+            (Some(etag), true) => {
+                crate::ical::parse_for_calendar(&decode_calendar_data(&reply.text())?, stored_url, SyncStatus::Synced(etag), self.supported_components)?
+            },
+            _ => self.get_stored_version(&stored_url).await?,
+        };
+        if crate::ical::content_hash(&stored).ok() != uploaded_hash {
+            log::info!("The server has rewritten the content of item {}", item_url);
+            self.rewritten_items.lock().unwrap().insert(item_url.clone(), stored.clone());
+        }
+        if let Some(version_tag) = stored.sync_status().version_tag() {
+            self.item_version_tags.lock().unwrap().insert(stored.url().clone(), version_tag.clone());
+        }
+        Ok(stored.sync_status().clone())
+    }
+
+    /// Fetch the version of an item the server stores, along with its current ETag
+    async fn get_stored_version(&self, url: &Url) -> Result<Item, Box<dyn Error>> {
+        let request = self.resource.http_client()
+            .get(url.clone())
+            .header(ACCEPT, self.data_format().content_type());
+        let response = self.resource.send(request).await?;
+        let status = response.status();
+        let last_modified = response.headers().get(LAST_MODIFIED)
+            .and_then(|date| date.to_str().ok())
+            .and_then(crate::datetime::parse_http_date);
+        let schedule_tag = schedule_tag_header(response.headers());
+        let etag = response.headers().get("ETag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| VersionTag::from(etag.to_string()).with_last_modified(last_modified))
+            .or_else(|| last_modified.map(VersionTag::from_last_modified))
+            .map(|etag| etag.with_schedule_tag(schedule_tag));
+        let body = read_body(response, self.max_item_size(), &format!("GET {}", url)).await?;
+        self.resource.record_transfer("GET", 0, body.len() as u64);
+        let text = String::from_utf8_lossy(&body);
+        if !status.is_success() {
+            return Err(Box::new(HttpError::from_reply(status, &text)));
+        }
+        let etag = etag.ok_or_else(|| format!("No ETag nor Last-Modified for item {}", url))?;
+        crate::ical::parse_for_calendar(&decode_calendar_data(&text)?, url.clone(), SyncStatus::Synced(etag), self.supported_components)
+    }
+
+    /// Remember the sizes of the items a `sync-collection` reply lists (see [`DavCalendar::item_size`])
+    fn remember_item_sizes(&self, reply: &Element) {
+        let mut item_sizes = self.item_sizes.lock().unwrap();
+        for response in reply.children().filter(|el| el.name() == "response") {
+            let resource = find_elem(response, "href").and_then(|href| self.resource.combine(&href.text()).ok());
+            if let (Some(resource), Some(size)) = (resource, content_length_from_reply(response)) {
+                item_sizes.insert(resource.url().clone(), size);
+            }
+        }
+    }
+
+    fn remember_canonical_url(&self, item_url: &Url, reply_headers: &HeaderMap) {
+        if let Some(canonical) = content_location(item_url, reply_headers) {
+            log::info!("Item {} has been stored by the server as {}", item_url, canonical);
+            self.canonical_urls.lock().unwrap().insert(item_url.clone(), canonical);
+        }
+    }
+
+    /// Returns who can access this calendar, e.g. to display who a shared calendar is shared with.
+    ///
+    /// This reads both the WebDAV ACL and the sharing invitations some servers (e.g. Nextcloud or Apple's) expose.
+    /// Servers usually only disclose these to the owner of the calendar: in case they refuse to, this returns an [`Acl`] that is flagged as [`forbidden`](Acl::forbidden) rather than an error.
+    pub async fn acl(&self) -> Result<Acl, Box<dyn Error>> {
+        let body = ACL_BODY.to_string();
+        let bytes_sent = body.len() as u64;
+        let request = self.resource.http_client()
+            .request(Method::from_bytes(b"PROPFIND").unwrap(), self.resource.url().clone())
+            .header("Depth", 0)
+            .header(CONTENT_TYPE, xml_content_type())
+            .body(body);
+        let response = self.resource.send(request).await?;
+
+        let status = response.status();
+        if status == StatusCode::FORBIDDEN || status == StatusCode::UNAUTHORIZED {
+            self.resource.read_reply(response, "PROPFIND", bytes_sent, max_response_size()).await?;
+            log::info!("The server does not disclose who can access {}", self.resource.url());
+            return Ok(Acl{ forbidden: true, ..Acl::default() });
+        }
+        let body = read_body(response, max_response_size(), &format!("PROPFIND {}", self.resource.url())).await?;
+        let text = String::from_utf8_lossy(&body);
+        self.resource.record_transfer("PROPFIND", bytes_sent, text.len() as u64);
+        if !status.is_success() {
+            return Err(format!("Unexpected HTTP status code {:?}", status).into());
+        }
+        Ok(parse_acl_reply(&parse_xml(&text)?))
+    }
+
+    /// Set the components this calendar supports, that this crate does not know (as reported by the server)
+    pub(crate) fn with_other_components(mut self, other_components: Vec<String>) -> Self {
+        self.other_components = other_components;
+        self
+    }
+
+    /// Set whether the current user is allowed to write into this calendar (as reported by the server)
+    pub(crate) fn with_writable(mut self, writable: bool) -> Self {
+        self.writable = writable;
+        self
+    }
+
+    /// The privileges the current user has on this calendar (e.g. `read` or `write-content`), as reported by the server when the calendar has been discovered.
+    /// This is `None` in case the server did not tell
+    pub fn privileges(&self) -> Option<&[String]> {
+        self.privileges.as_deref()
+    }
+
+    /// The reports this calendar supports (e.g. `calendar-multiget` or `sync-collection`), as reported by the server when the calendar has been discovered
+    pub fn supported_reports(&self) -> &[String] {
+        &self.supported_reports
+    }
+
+    pub(crate) fn with_privileges(mut self, privileges: Option<Vec<String>>) -> Self {
+        self.privileges = privileges;
+        self
+    }
+
+    pub(crate) fn with_supported_reports(mut self, supported_reports: Vec<String>) -> Self {
+        self.supported_reports = supported_reports;
+        self
+    }
+
+    /// Set the range of date-times this calendar accepts (as reported by the server)
+    pub(crate) fn with_date_limits(mut self, date_limits: DateLimits) -> Self {
+        self.date_limits = date_limits;
+        self
+    }
+
+    /// The formats the items of this calendar can be exchanged in, as reported by the server when the calendar has been discovered.
+    /// Servers that do not tell only support iCalendar
+    pub fn supported_data_formats(&self) -> &[CalendarDataFormat] {
+        &self.data_formats
+    }
+
+    /// The format items of this calendar are exchanged in (see [`CalendarDataFormat::negotiate`])
+    pub fn data_format(&self) -> CalendarDataFormat {
+        CalendarDataFormat::negotiate(&self.data_formats)
+    }
+
+    pub(crate) fn with_data_formats(mut self, data_formats: Vec<CalendarDataFormat>) -> Self {
+        self.data_formats = data_formats;
+        self
+    }
+
+    /// The size (in bytes) of the largest item this calendar accepts, as reported by the server when the calendar has been discovered.
+    /// This is `None` in case the server did not tell
+    pub fn max_resource_size(&self) -> Option<usize> {
+        self.max_resource_size
+    }
+
+    pub(crate) fn with_max_resource_size(mut self, max_resource_size: Option<usize>) -> Self {
+        self.max_resource_size = max_resource_size;
+        self
+    }
+
+    pub(crate) fn with_enabled(mut self, enabled: Option<bool>) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Flag this calendar as enabled (or disabled) for sync on the server, so that other clients (and providers that respect it, see [`crate::provider::Provider::set_respect_server_enabled_flag`]) sync it (or not).
+    ///
+    /// This sets the `calendar-enabled` property Nextcloud uses for this purpose, with a `PROPPATCH` request.
+    pub async fn set_enabled(&mut self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        let responses = crate::client::sub_request_and_extract_elems(&self.resource, "PROPPATCH", enabled_body(enabled), "response").await?;
+        if let Some(status) = responses.iter().find_map(crate::client::failed_response_status) {
+            return Err(Box::new(HttpError::from_reply(status, "")));
+        }
+        self.enabled = Some(enabled);
+        Ok(())
+    }
+
+    /// The size of the largest item that is read from this calendar (see [`crate::config::MAX_ITEM_SIZE`])
+    fn max_item_size(&self) -> usize {
+        let max_item_size = *crate::config::MAX_ITEM_SIZE.lock().unwrap();
+        self.max_resource_size.map_or(max_item_size, |max_resource_size| max_resource_size.min(max_item_size))
+    }
+
+    /// Make this calendar a subscription to an iCal feed (as reported by the server)
+    pub(crate) fn with_subscription(mut self, subscription: Option<Subscription>) -> Self {
+        self.subscription = subscription;
+        self
+    }
+}
+
+/// Whether a reply to a `HEAD` or `PROPFIND` request means the resource exists (`None` if this status does not tell)
+fn existence_from_status(status: StatusCode) -> Option<bool> {
+    match status {
+        StatusCode::OK | StatusCode::MULTI_STATUS => Some(true),
+        StatusCode::NOT_FOUND | StatusCode::GONE => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the size of an item (`getcontentlength`), from a `<response>` that lists it
+fn content_length_from_reply(reply: &Element) -> Option<u64> {
+    crate::utils::find_elem(reply, "getcontentlength")
+        .and_then(|length| length.text().trim().parse().ok())
+}
+
+/// The version tag of an item, from its `<response>` to a REPORT that asks for its `getetag` and `getlastmodified` (and `schedule-tag`).
+///
+/// Servers that do not provide ETags usually provide a last modification date, that is used instead
+fn version_tag_from_reply(reply: &Element) -> Option<VersionTag> {
+    let etag = crate::utils::find_elem(reply, "getetag")
+        .map(|etag| etag.text())
+        .filter(|etag| !etag.is_empty());
+    let last_modified = crate::utils::find_elem(reply, "getlastmodified")
+        .and_then(|date| crate::datetime::parse_http_date(&date.text()));
+    let schedule_tag = crate::utils::find_elem(reply, "schedule-tag")
+        .map(|tag| tag.text())
+        .filter(|tag| !tag.is_empty());
+    match (etag, last_modified) {
+        (Some(etag), last_modified) => Some(VersionTag::from(etag).with_last_modified(last_modified).with_schedule_tag(schedule_tag)),
+        (None, Some(last_modified)) => Some(VersionTag::from_last_modified(last_modified).with_schedule_tag(schedule_tag)),
+        (None, None) => None,
+    }
+}
+
+/// Split a time range into consecutive ranges of at most `chunk_size` (see [`crate::config::TIME_RANGE_CHUNK_SIZE`])
+fn time_range_chunks(start: DateTime<Utc>, end: DateTime<Utc>, chunk_size: Option<Duration>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let chunk_size = match chunk_size {
+        Some(size) if size > Duration::zero() => size,
+        _ => return vec![(start, end)],
+    };
+    let mut chunks = Vec::new();
+    let mut chunk_start = start;
+    while chunk_start < end {
+        let chunk_end = std::cmp::min(chunk_start + chunk_size, end);
+        chunks.push((chunk_start, chunk_end));
+        chunk_start = chunk_end;
+    }
+    if chunks.is_empty() {
+        chunks.push((start, end));
+    }
+    chunks
+}
+
+/// Parse the reply to a `sync-collection` REPORT. This also tells whether the server has truncated it (with a `507 Insufficient Storage` for the calendar itself), i.e. whether there are more changes to ask for
+fn changes_from_reply(calendar: &Resource, reply: &Element) -> Result<(ItemChanges, bool), Box<dyn Error>> {
+    let sync_token = reply.children()
+        .find(|el| el.name() == "sync-token")
+        .map(|el| el.text().trim().to_string())
+        .filter(|token| !token.is_empty())
+        .ok_or("Missing sync-token")?;
+
+    let mut changes = ItemChanges { sync_token, ..ItemChanges::default() };
+    let mut truncated = false;
+    for response in reply.children().filter(|el| el.name() == "response") {
+        let href = find_elem(response, "href").ok_or("Missing HREF")?.text();
+        let url = calendar.combine(&href)?.url().clone();
+        // Deleted items (and truncated replies) have a status of their own, rather than properties
+        let status = response.children().find(|el| el.name() == "status").and_then(crate::client::parse_status);
+        if url.path() == calendar.url().path() {
+            truncated |= status == Some(StatusCode::INSUFFICIENT_STORAGE);
+            continue;
+        }
+        if status == Some(StatusCode::NOT_FOUND) {
+            changes.deleted.insert(url);
+            continue;
+        }
+        match version_tag_from_reply(response) {
+            None => log::warn!("Unable to extract ETAG or last modification date for item {}, ignoring it", url),
+            Some(version_tag) => { changes.changed.insert(url, version_tag); },
+        }
+    }
+    Ok((changes, truncated))
+}
+
+/// The `getctag` of a calendar, or its `sync-token` for servers that do not support ctags
+fn ctag_from_reply(reply: &Element) -> Option<VersionTag> {
+    ["getctag", "sync-token"].iter()
+        .filter_map(|name| find_elem(reply, name))
+        .map(|el| el.text().trim().to_string())
+        .find(|tag| !tag.is_empty())
+        .map(VersionTag::from)
+}
+
+/// The URL an item of a subscribed calendar is known by. Feeds do not give URLs to their items, so this is derived from their UID
+fn subscribed_item_url(calendar_url: &Url, uid: &str) -> Result<Url, Box<dyn Error>> {
+    let mut url = calendar_url.clone();
+    url.path_segments_mut()
+        .map_err(|_| format!("{} cannot be a base URL", calendar_url))?
+        .pop_if_empty()
+        .push(&format!("{}.ics", uid));
+    Ok(url)
+}
+
+/// Feeds do not give version tags to their items, so they are derived from their content.
+/// These tags are stored along with the items, so they must not depend on the build of this crate (as `DefaultHasher` does)
+fn content_version_tag(ical: &str) -> VersionTag {
+    VersionTag::from(format!("{:x}", md5::compute(ical)))
+}
+
+/// Turn the error of a conditional update or deletion of an item into a [`PreconditionFailedError`], in case the server has refused it because the item has changed since its version tag has been read,
+/// rather than because of a DAV precondition (e.g. `quota-not-exceeded`)
+fn precondition_failed(err: Box<dyn Error>, item_url: &Url) -> Box<dyn Error> {
+    match err.downcast_ref::<HttpError>() {
+        Some(http_error) if http_error.status == StatusCode::PRECONDITION_FAILED && http_error.detail.is_none() => Box::new(PreconditionFailedError { item: item_url.clone() }),
+        _ => err,
+    }
+}
+
+/// Whether a failed upload has been refused because of the storage quota (see RFC 4331, section 6)
+fn is_quota_error(status: StatusCode, body: &str) -> bool {
+    status == StatusCode::INSUFFICIENT_STORAGE
+        || DavErrorDetail::parse(body) == Some(DavErrorDetail::QuotaNotExceeded)
+}
+
+/// Returns the `Schedule-Tag` of a reply, for servers that support CalDAV scheduling
+fn schedule_tag_header(reply_headers: &HeaderMap) -> Option<String> {
+    reply_headers.get("Schedule-Tag")
+        .and_then(|tag| tag.to_str().ok())
+        .map(String::from)
+}
+
+/// Returns the `Content-Location` of a reply, in case it is not the URL the request was sent to.
+///
+/// Locations on another origin are ignored, since the credentials of this server would be sent there
+fn content_location(request_url: &Url, reply_headers: &HeaderMap) -> Option<Url> {
+    let location = reply_headers.get(CONTENT_LOCATION)?.to_str().ok()?;
+    let canonical = request_url.join(location).ok()?;
+    if canonical.origin() != request_url.origin() {
+        log::warn!("Ignoring the Content-Location {} of {}, that is on another origin", canonical, request_url);
+        return None;
+    }
+    match &canonical == request_url {
+        true => None,
+        false => Some(canonical),
+    }
+}
+
+/// Whether an ETag is weak (e.g. `W/"123"`), i.e. whether it does not guarantee the stored content is byte-for-byte the uploaded one
+fn is_weak_etag(etag: &VersionTag) -> bool {
+    etag.as_str().starts_with("W/")
+}
+
+/// Send an iCal file chunk by chunk, so that large items are never entirely copied into a single buffer
+fn streamed_body(ical: StreamedIcal) -> reqwest::Body {
+    reqwest::Body::wrap_stream(futures_util::stream::iter(ical.map(Ok::<_, std::io::Error>)))
+}
+
+/// The body of a PUT request for an item, in the given format, along with its content length and content type
+fn upload_body(item: Item, data_format: &CalendarDataFormat) -> Result<(reqwest::Body, usize, String), Box<dyn Error>> {
+    match data_format {
+        CalendarDataFormat::JCal => {
+            let jcal = item.to_jcal()?.to_string();
+            let length = jcal.len();
+            Ok((reqwest::Body::from(jcal), length, "application/calendar+json".to_string()))
+        },
+        _ => {
+            let content_type = ical_content_type(&item);
+            let ical = crate::ical::build_streamed_from(item)?;
+            let length = ical.content_length();
+            Ok((streamed_body(ical), length, content_type))
+        },
+    }
+}
+
+/// The `Content-Type` of an item uploaded as iCal, e.g. `text/calendar; charset=utf-8; component=VTODO` (see [`crate::config::ITEM_CONTENT_TYPE_PARAMETERS`])
+fn ical_content_type(item: &Item) -> String {
+    if !*crate::config::ITEM_CONTENT_TYPE_PARAMETERS.lock().unwrap() {
+        return "text/calendar".to_string();
+    }
+    format!("text/calendar; charset=utf-8; component={}", item.component_name())
+}
+
+/// The iCal content of an item, that the server may have sent either as iCal or as jCal
+fn decode_calendar_data(data: &str) -> Result<Cow<'_, str>, Box<dyn Error>> {
+    match data.trim_start().starts_with('[') {
+        true => Ok(Cow::Owned(crate::ical::jcal_to_ical(&serde_json::from_str(data)?)?)),
+        false => Ok(Cow::Borrowed(data)),
+    }
 }
 
 #[async_trait]
@@ -62,33 +866,34 @@ impl BaseCalendar for RemoteCalendar {
     fn color(&self) -> Option<&Color> {
         self.color.as_ref()
     }
+    fn other_components(&self) -> &[String] {
+        &self.other_components
+    }
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
 
     async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
-        let ical_text = crate::ical::build_from(&item)?;
+        self.supported_components.check_item(&item)?;
+        let item_url = item.url().clone();
+        let uploaded_hash = crate::ical::content_hash(&item).ok();
+        let (body, content_length, content_type) = upload_body(item, &self.data_format())?;
+        let bytes_sent = content_length as u64;
 
-        let response = reqwest::Client::new()
-            .put(item.url().clone())
+        let request = self.resource.http_client()
+            .put(item_url.clone())
             .header("If-None-Match", "*")
-            .header(CONTENT_TYPE, "text/calendar")
-            .header(CONTENT_LENGTH, ical_text.len())
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .body(ical_text)
-            .send()
-            .await?;
-
-        if response.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", response.status()).into());
-        }
-
-        let reply_hdrs = response.headers();
-        match reply_hdrs.get("ETag") {
-            None => Err(format!("No ETag in these response headers: {:?} (request was {:?})", reply_hdrs, item.url()).into()),
-            Some(etag) => {
-                let vtag_str = etag.to_str()?;
-                let vtag = VersionTag::from(String::from(vtag_str));
-                Ok(SyncStatus::Synced(vtag))
-            }
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_LENGTH, content_length)
+            .body(body);
+        let response = self.resource.send(request).await?;
+        let reply = self.resource.read_reply(response, "PUT", bytes_sent, self.max_item_size()).await?;
+
+        if !reply.status.is_success() {
+            return Err(self.upload_error(&reply));
         }
+
+        self.finish_upload(&item_url, uploaded_hash, reply).await
     }
 
     async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
@@ -98,31 +903,28 @@ impl BaseCalendar for RemoteCalendar {
             SyncStatus::LocallyModified(etag) => etag,
             SyncStatus::LocallyDeleted(etag) => etag,
         };
-        let ical_text = crate::ical::build_from(&item)?;
+        let old_etag = old_etag.clone();
+        let item_url = item.url().clone();
 
-        let request = reqwest::Client::new()
-            .put(item.url().clone())
-            .header("If-Match", old_etag.as_str())
-            .header(CONTENT_TYPE, "text/calendar")
-            .header(CONTENT_LENGTH, ical_text.len())
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .body(ical_text)
-            .send()
-            .await?;
-
-        if request.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", request.status()).into());
-        }
-
-        let reply_hdrs = request.headers();
-        match reply_hdrs.get("ETag") {
-            None => Err(format!("No ETag in these response headers: {:?} (request was {:?})", reply_hdrs, item.url()).into()),
-            Some(etag) => {
-                let vtag_str = etag.to_str()?;
-                let vtag = VersionTag::from(String::from(vtag_str));
-                Ok(SyncStatus::Synced(vtag))
-            }
+        // Scheduling objects are asked not to overwrite changes of their organizer or their other attendees only.
+        // The participation status the server updates when an attendee replies does not change their Schedule-Tag, and is merged back below ([RFC6638](https://tools.ietf.org/html/rfc6638#section-3.2.10))
+        let schedule_tag = old_etag.schedule_tag().filter(|_| item.is_scheduling_object());
+        let precondition = match (schedule_tag, old_etag.last_modified()) {
+            (Some(schedule_tag), _) => (HeaderName::from_static(IF_SCHEDULE_TAG_MATCH), schedule_tag.to_string()),
+            // Servers that do not provide ETags are asked not to overwrite an item that has been modified since the last sync instead
+            (None, Some(last_modified)) if !old_etag.is_etag() => (IF_UNMODIFIED_SINCE, crate::datetime::format_http_date(last_modified)),
+            _ => (IF_MATCH, old_etag.as_str().to_string()),
+        };
+        let uploaded_hash = crate::ical::content_hash(&item).ok();
+        let reply = self.put_item(&item, precondition).await?;
+        if reply.status == StatusCode::PRECONDITION_FAILED && schedule_tag.is_some() {
+            return self.merge_scheduling_changes(item).await;
         }
+        if !reply.status.is_success() {
+            return Err(precondition_failed(self.upload_error(&reply), &item_url));
+        }
+
+        self.finish_upload(&item_url, uploaded_hash, reply).await
     }
 }
 
@@ -130,8 +932,25 @@ impl BaseCalendar for RemoteCalendar {
 impl DavCalendar for RemoteCalendar {
     fn new(name: String, resource: Resource, supported_components: SupportedComponents, color: Option<Color>) -> Self {
         Self {
-            name, resource, supported_components, color,
+            name, supported_components, color,
+            other_components: Vec::new(),
+            resource: resource.accounted_to_calendar(),
+            writable: true,
+            privileges: None,
+            supported_reports: Vec::new(),
+            date_limits: DateLimits::default(),
+            subscription: None,
+            data_formats: vec![CalendarDataFormat::ICalendar],
+            max_resource_size: None,
+            enabled: None,
             cached_version_tags: Mutex::new(None),
+            changed_version_tags: Mutex::new(HashMap::new()),
+            item_version_tags: Mutex::new(HashMap::new()),
+            item_sizes: Mutex::new(HashMap::new()),
+            canonical_urls: Mutex::new(HashMap::new()),
+            rewritten_items: Mutex::new(HashMap::new()),
+            unsupported_components: Mutex::new(HashMap::new()),
+            unstable_items: Mutex::new(HashSet::new()),
         }
     }
 
@@ -142,7 +961,13 @@ impl DavCalendar for RemoteCalendar {
             return Ok(map.clone());
         };
 
-        let responses = crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", TASKS_BODY.to_string(), "response").await?;
+        // Only the kinds of items this calendar supports are queried
+        let mut responses = Vec::new();
+        for (flag, component) in [(SupportedComponents::TODO, "VTODO"), (SupportedComponents::EVENT, "VEVENT")] {
+            if self.supported_components.contains(flag) {
+                responses.extend(crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", items_body(component), "response").await?);
+            }
+        }
 
         let mut items = HashMap::new();
         for response in responses {
@@ -153,51 +978,71 @@ impl DavCalendar for RemoteCalendar {
                     log::warn!("Unable to extract HREF");
                     continue;
                 },
-                Some(resource) => {
+                Some(Err(err)) => {
+                    log::warn!("Invalid HREF ({}), ignoring it", err);
+                    continue;
+                },
+                Some(Ok(resource)) => {
                     resource.url().clone()
                 },
             };
 
-            let version_tag = match crate::utils::find_elem(&response, "getetag") {
+            let version_tag = match version_tag_from_reply(&response) {
                 None => {
-                    log::warn!("Unable to extract ETAG for item {}, ignoring it", item_url);
+                    log::warn!("Unable to extract ETAG or last modification date for item {}, ignoring it", item_url);
                     continue;
                 },
-                Some(etag) => {
-                    VersionTag::from(etag.text())
-                }
+                Some(version_tag) => version_tag,
             };
 
+            if let Some(size) = content_length_from_reply(&response) {
+                self.item_sizes.lock().unwrap().insert(item_url.clone(), size);
+            }
             items.insert(item_url.clone(), version_tag);
         }
 
         // Note: the mutex cannot be locked during this whole async function, but it can safely be re-entrant (this will just waste an unnecessary request)
         *self.cached_version_tags.lock().unwrap() = Some(items.clone());
+        self.item_version_tags.lock().unwrap().extend(items.clone());
         Ok(items)
     }
 
     async fn get_item_by_url(&self, url: &Url) -> Result<Option<Item>, Box<dyn Error>> {
-        let res = reqwest::Client::new()
+        let request = self.resource.http_client()
             .get(url.clone())
-            .header(CONTENT_TYPE, "text/calendar")
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .send()
-            .await?;
+            .header(ACCEPT, self.data_format().content_type());
+        let res = self.resource.send(request).await?;
 
+        if res.status() == StatusCode::NOT_FOUND {
+            self.resource.read_reply(res, "GET", 0, self.max_item_size()).await?;
+            log::info!("Item {} has been deleted from the server since it has been listed", url);
+            return Ok(None);
+        }
         if res.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+            let status = self.resource.read_reply(res, "GET", 0, self.max_item_size()).await?.status;
+            return Err(format!("Unexpected HTTP status code {:?}", status).into());
         }
 
-        let text = res.text().await?;
+        let canonical_url = content_location(url, res.headers()).unwrap_or_else(|| url.clone());
+        let fetched_vt = res.headers().get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| VersionTag::from(etag.to_string()));
+        let body = read_body(res, self.max_item_size(), &format!("GET {}", url)).await?;
+        self.resource.record_transfer("GET", 0, body.len() as u64);
+        let text = String::from_utf8_lossy(&body);
 
-        // This is supposed to be cached
-        let version_tags = self.get_item_version_tags().await?;
-        let vt = match version_tags.get(url) {
+        let version_tags = self.listed_version_tags(std::slice::from_ref(url)).await?;
+        let listed_vt = match version_tags.get(url) {
             None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
             Some(vt) => vt,
         };
+        let vt = match self.check_fetched_version(url, listed_vt, fetched_vt).await? {
+            FetchedVersion::Consistent(vt) => vt,
+            FetchedVersion::Deleted => return Ok(None),
+            FetchedVersion::Unstable => return Err(format!("Item {} keeps changing on the server", url).into()),
+        };
 
-        let item = crate::ical::parse(&text, url.clone(), SyncStatus::Synced(vt.clone()))?;
+        let item = crate::ical::parse_for_calendar(&decode_calendar_data(&text)?, canonical_url, SyncStatus::Synced(vt), self.supported_components)?;
         Ok(Some(item))
     }
 
@@ -205,48 +1050,533 @@ impl DavCalendar for RemoteCalendar {
         // Build the request body
         let mut hrefs = String::new();
         for url in urls {
-            hrefs.push_str(&format!("        <d:href>{}</d:href>\n", url.path()));
+            hrefs.push_str(&format!("        <d:href>{}</d:href>\n", Resource::href_of(url)));
         }
-        let body = format!("{}{}{}", MULTIGET_BODY_PREFIX, hrefs, MULTIGET_BODY_SUFFIX);
+        let body = format!("{}{}{}", multiget_body_prefix(&self.data_format()), hrefs, MULTIGET_BODY_SUFFIX);
 
         // Send the request
-        let xml_replies = crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", body, "response").await?;
+        let reply: Result<String, TruncatedReply> = match crate::client::sub_request(&self.resource, "REPORT", body, 1).await {
+            Ok(text) => Ok(text),
+            Err(KitchenFridgeError::TruncatedReply(truncated)) => Err(truncated),
+            Err(err) => return Err(err.into()),
+        };
+        let truncated = match reply {
+            Ok(text) => {
+                let element: Element = parse_xml(&text)?;
+                let xml_replies = find_elems(&element, "response").into_iter().cloned().collect();
+                return self.parse_multiget_replies(xml_replies).await;
+            },
+            Err(truncated) => truncated,
+        };
 
-        // This is supposed to be cached
-        let version_tags = self.get_item_version_tags().await?;
+        // The connection has dropped. Let's keep what has been received
+        let received = crate::utils::find_complete_elems_in_truncated_xml(&truncated.partial_body, "response");
+        log::info!("Connection dropped after {} items of the batch have been received", received.len());
+        let fetched: Vec<Item> = self.parse_multiget_replies(received).await?
+            .into_iter().flatten().collect();
+        if fetched.is_empty() {
+            return Err(Box::new(truncated.cause));
+        }
+        Err(Box::new(PartialFetchError{ fetched, cause: Box::new(truncated.cause) }))
+    }
 
-        // Parse the results
-        let mut results = Vec::new();
-        for xml_reply in xml_replies {
-            let href = find_elem(&xml_reply, "href").ok_or("Missing HREF")?.text();
-            let mut url = self.resource.url().clone();
-            url.set_path(&href);
-            let ical_data = find_elem(&xml_reply, "calendar-data").ok_or("Missing calendar-data")?.text();
+    /// Calendars that do not advertise the `sync-collection` REPORT are not asked for it
+    async fn get_changes_since(&self, sync_token: Option<&str>) -> Result<Option<ItemChanges>, Box<dyn Error>> {
+        if !self.supported_reports.iter().any(|report| report == "sync-collection") {
+            return Ok(None);
+        }
 
-            let vt = match version_tags.get(&url) {
-                None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
-                Some(vt) => vt,
+        let mut changes = ItemChanges { sync_token: sync_token.unwrap_or_default().to_string(), ..ItemChanges::default() };
+        loop {
+            let body = sync_collection_body(Some(changes.sync_token.as_str()).filter(|token| !token.is_empty()));
+            let text = match crate::client::sub_request(&self.resource, "REPORT", body, 0).await {
+                Ok(text) => text,
+                Err(KitchenFridgeError::Status(err)) if err.detail == Some(DavErrorDetail::ValidSyncToken) => {
+                    log::info!("The server does not accept the sync token of calendar {} anymore", self.name);
+                    return Ok(None);
+                },
+                Err(err) => return Err(err.into()),
             };
+            let reply = parse_xml(&text)?;
+            let (page, truncated) = changes_from_reply(&self.resource, &reply)?;
+            self.remember_item_sizes(&reply);
+            for url in page.deleted {
+                changes.changed.remove(&url);
+                changes.deleted.insert(url);
+            }
+            for (url, version_tag) in page.changed {
+                changes.deleted.remove(&url);
+                changes.changed.insert(url, version_tag);
+            }
+            // Servers that truncate their replies tell the rest of the changes from the token they return
+            let is_stuck = page.sync_token == changes.sync_token;
+            changes.sync_token = page.sync_token;
+            if !truncated || is_stuck {
+                break;
+            }
+            log::debug!("The changes of calendar {} have been truncated, asking for the next ones", self.name);
+        }
 
-            let item = crate::ical::parse(&ical_data, url.clone(), SyncStatus::Synced(vt.clone()))?;
-            results.push(Some(item));
+        *self.changed_version_tags.lock().unwrap() = changes.changed.clone();
+        {
+            let mut item_version_tags = self.item_version_tags.lock().unwrap();
+            item_version_tags.retain(|url, _| !changes.deleted.contains(url));
+            item_version_tags.extend(changes.changed.clone());
         }
+        Ok(Some(changes))
+    }
 
-        Ok(results)
+    fn item_size(&self, url: &Url) -> Option<u64> {
+        self.item_sizes.lock().unwrap().get(url).copied()
+    }
+
+    fn take_rewritten_item(&self, url: &Url) -> Option<Item> {
+        self.rewritten_items.lock().unwrap().remove(url)
+    }
+
+    fn take_canonical_url(&self, url: &Url) -> Option<Url> {
+        self.canonical_urls.lock().unwrap().remove(url)
+    }
+
+    fn take_unsupported_component(&self, url: &Url) -> Option<Option<String>> {
+        self.unsupported_components.lock().unwrap().remove(url)
+    }
+
+    fn take_unstable_item(&self, url: &Url) -> bool {
+        self.unstable_items.lock().unwrap().remove(url)
+    }
+
+    fn date_limits(&self) -> DateLimits {
+        self.date_limits
+    }
+
+    async fn get_ctag(&self) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        let text = crate::client::sub_request(&self.resource, "PROPFIND", CTAG_BODY.to_string(), 0).await?;
+        Ok(ctag_from_reply(&parse_xml(&text)?))
+    }
+
+    fn subscription(&self) -> Option<Subscription> {
+        self.subscription.clone()
+    }
+
+    /// As reported by the server when the calendar has been discovered, or as set by [`RemoteCalendar::set_enabled`]
+    fn enabled(&self) -> Option<bool> {
+        self.enabled
+    }
+
+    async fn fetch_subscribed_items(&self) -> Result<Vec<Item>, Box<dyn Error>> {
+        let source = match &self.subscription {
+            None => return Err("This calendar is not a subscription".into()),
+            Some(subscription) => subscription.source.clone(),
+        };
+
+        let request = self.resource.http_client()
+            .get(source.clone())
+            .header(ACCEPT, "text/calendar");
+        // Feeds are usually hosted elsewhere. Credentials are only sent to the server of this calendar
+        let res = match source.origin() == self.resource.url().origin() {
+            true => self.resource.send(request).await?,
+            false => {
+                // ...but they wait for it just as long
+                let request = match self.resource.timeout() {
+                    Some(timeout) => request.timeout(timeout),
+                    None => request,
+                };
+                request.send().await.map_err(KitchenFridgeError::from)?
+            },
+        };
+        let status = res.status();
+        let body = read_body(res, max_response_size(), &format!("GET {}", source)).await?;
+        self.resource.record_transfer("GET", 0, body.len() as u64);
+        if !status.is_success() {
+            return Err(format!("Unexpected HTTP status code {:?} for feed {}", status, source).into());
+        }
+
+        let text = String::from_utf8_lossy(&body);
+        let mut items = Vec::new();
+        for resource in crate::ical::split_ics(&text)? {
+            let url = subscribed_item_url(self.url(), &resource.uid)?;
+            let version_tag = content_version_tag(&resource.ical);
+            match crate::ical::parse_for_calendar(&resource.ical, url, SyncStatus::Synced(version_tag), self.supported_components) {
+                Ok(item) => items.push(item),
+                Err(err) => log::warn!("Skipping item {} of feed {}: {}", resource.uid, source, err),
+            }
+        }
+        Ok(items)
     }
 
     async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
-        let del_response = reqwest::Client::new()
-            .delete(item_url.clone())
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .send()
-            .await?;
+        let mut request = self.resource.http_client()
+            .delete(item_url.clone());
+        // The server is asked not to delete an item that has changed since this calendar has last seen it
+        let version_tag = self.item_version_tags.lock().unwrap().get(item_url).cloned();
+        match version_tag {
+            Some(version_tag) if version_tag.is_etag() => request = request.header(IF_MATCH, version_tag.as_str()),
+            Some(version_tag) => if let Some(last_modified) = version_tag.last_modified() {
+                request = request.header(IF_UNMODIFIED_SINCE, crate::datetime::format_http_date(last_modified));
+            },
+            None => log::debug!("The version tag of item {} is unknown, deleting it unconditionally", item_url),
+        }
+        let del_response = self.resource.send(request).await?;
+        let reply = self.resource.read_reply(del_response, "DELETE", 0, max_response_size()).await?;
 
-        if del_response.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", del_response.status()).into());
+        if !reply.status.is_success() {
+            return Err(precondition_failed(Box::new(HttpError::from_reply(reply.status, &reply.text())), item_url));
         }
 
+        self.item_version_tags.lock().unwrap().remove(item_url);
         Ok(())
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_existence_from_status() {
+        assert_eq!(existence_from_status(StatusCode::OK), Some(true));
+        assert_eq!(existence_from_status(StatusCode::MULTI_STATUS), Some(true));
+        assert_eq!(existence_from_status(StatusCode::NOT_FOUND), Some(false));
+        assert_eq!(existence_from_status(StatusCode::METHOD_NOT_ALLOWED), None);
+        assert_eq!(existence_from_status(StatusCode::UNAUTHORIZED), None);
+    }
+
+    #[test]
+    fn test_content_location() {
+        let requested: Url = "https://my.server.com/calendars/john/tasks/My%20Task.ics".parse().unwrap();
+        let reply_with = |location: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_LOCATION, location.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(content_location(&requested, &HeaderMap::new()), None);
+        assert_eq!(content_location(&requested, &reply_with("https://my.server.com/calendars/john/tasks/My%20Task.ics")), None);
+        assert_eq!(content_location(&requested, &reply_with("/calendars/john/tasks/My%20Task.ics")), None);
+        assert_eq!(content_location(&requested, &reply_with("https://evil.example.com/calendars/john/tasks/my-task.ics")), None);
+        assert_eq!(
+            content_location(&requested, &reply_with("/calendars/john/tasks/my-task.ics")),
+            Some("https://my.server.com/calendars/john/tasks/my-task.ics".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_subscribed_item_url() {
+        let cal_url: Url = "https://some.server/cal/holidays/".parse().unwrap();
+        assert_eq!(subscribed_item_url(&cal_url, "abc@feed").unwrap().as_str(), "https://some.server/cal/holidays/abc@feed.ics");
+        assert_eq!(subscribed_item_url(&cal_url, "a/b c").unwrap().as_str(), "https://some.server/cal/holidays/a%2Fb%20c.ics");
+        assert_eq!(content_version_tag("BEGIN:VCALENDAR"), content_version_tag("BEGIN:VCALENDAR"));
+        assert_ne!(content_version_tag("BEGIN:VCALENDAR"), content_version_tag("BEGIN:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn test_calendar_data_formats() {
+        let ical = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nEND:VCALENDAR\r\n";
+        let jcal = r#"["vcalendar", [["version", {}, "text", "2.0"]], []]"#;
+        assert_eq!(decode_calendar_data(ical).unwrap(), ical);
+        assert_eq!(decode_calendar_data(jcal).unwrap(), ical);
+        assert!(decode_calendar_data("[not json").is_err());
+
+        assert!(multiget_body_prefix(&CalendarDataFormat::ICalendar).contains("<c:calendar-data />"));
+        assert!(multiget_body_prefix(&CalendarDataFormat::JCal).contains(r#"<c:calendar-data content-type="application/calendar+json" version="2.0" />"#));
+    }
+
+    #[test]
+    fn test_is_weak_etag() {
+        assert!(is_weak_etag(&VersionTag::from(r#"W/"123""#.to_string())));
+        assert!(!is_weak_etag(&VersionTag::from(r#""123""#.to_string())));
+    }
+
+    #[test]
+    fn test_version_tag_from_reply() {
+        let reply = |props: &str, missing: &str| -> Element {
+            format!(r#"<d:response xmlns:d="DAV:"><d:href>/cal/1.ics</d:href>
+                <d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+                <d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>
+                </d:response>"#, props, missing).parse().unwrap()
+        };
+        let date = chrono::TimeZone::ymd(&chrono::Utc, 2021, 3, 21).and_hms(10, 0, 0);
+
+        let with_etag = version_tag_from_reply(&reply(r#"<d:getetag>"42"</d:getetag><d:getlastmodified>Sun, 21 Mar 2021 10:00:00 GMT</d:getlastmodified>"#, "")).unwrap();
+        assert_eq!(with_etag, VersionTag::from(r#""42""#.to_string()));
+        assert_eq!(with_etag.last_modified(), Some(&date));
+        assert!(with_etag.is_strong_etag());
+        assert_eq!(with_etag.schedule_tag(), None);
+
+        // A server that supports CalDAV scheduling
+        let scheduled = version_tag_from_reply(&reply(r#"<d:getetag>"42"</d:getetag><c:schedule-tag xmlns:c="urn:ietf:params:xml:ns:caldav">"7"</c:schedule-tag>"#, "")).unwrap();
+        assert_eq!(scheduled, with_etag);
+        assert_eq!(scheduled.schedule_tag(), Some(r#""7""#));
+
+        // A server that omits ETags
+        let without_etag = version_tag_from_reply(&reply("<d:getlastmodified>Sun, 21 Mar 2021 10:00:00 GMT</d:getlastmodified>", "<d:getetag/>")).unwrap();
+        assert_eq!(without_etag, VersionTag::from_last_modified(date));
+        assert_eq!(without_etag.last_modified(), Some(&date));
+        assert!(!without_etag.is_etag());
+        assert_ne!(without_etag, VersionTag::from_last_modified(date + chrono::Duration::seconds(1)));
+
+        assert_eq!(version_tag_from_reply(&reply("", "<d:getetag/><d:getlastmodified/>")), None);
+    }
+
+    #[test]
+    fn test_time_range_chunks() {
+        let date = |month: u32, day: u32| chrono::TimeZone::ymd(&Utc, 2021, month, day).and_hms(0, 0, 0);
+        assert_eq!(time_range_chunks(date(1, 1), date(3, 1), None), vec![(date(1, 1), date(3, 1))]);
+        assert_eq!(time_range_chunks(date(1, 1), date(3, 1), Some(Duration::days(31))), vec![(date(1, 1), date(2, 1)), (date(2, 1), date(3, 1))]);
+        assert_eq!(time_range_chunks(date(1, 1), date(3, 5), Some(Duration::days(31))).last(), Some(&(date(3, 4), date(3, 5))));
+        assert_eq!(time_range_chunks(date(3, 1), date(1, 1), Some(Duration::days(31))), vec![(date(3, 1), date(1, 1))]);
+    }
+
+    #[test]
+    fn test_ctag_from_reply() {
+        let reply = |props: &str| -> Element {
+            format!(r#"<d:multistatus xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/"><d:response><d:href>/cal/</d:href>
+                <d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+                <d:propstat><d:prop><cs:getctag/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>
+                </d:response></d:multistatus>"#, props).parse().unwrap()
+        };
+
+        assert_eq!(ctag_from_reply(&reply("<cs:getctag>42</cs:getctag><d:sync-token>http://s/ns/sync/7</d:sync-token>")), Some(VersionTag::from("42".to_string())));
+        assert_eq!(ctag_from_reply(&reply("<d:sync-token>http://s/ns/sync/7</d:sync-token>")), Some(VersionTag::from("http://s/ns/sync/7".to_string())));
+        assert_eq!(ctag_from_reply(&reply("")), None);
+    }
+
+    #[test]
+    fn test_is_quota_error() {
+        let precondition = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <d:error xmlns:d="DAV:"><d:quota-not-exceeded/></d:error>"#;
+        let other_precondition = r#"<d:error xmlns:d="DAV:"><d:lock-token-submitted/></d:error>"#;
+
+        assert!(is_quota_error(StatusCode::INSUFFICIENT_STORAGE, ""));
+        assert!(is_quota_error(StatusCode::FORBIDDEN, precondition));
+        assert!(!is_quota_error(StatusCode::FORBIDDEN, other_precondition));
+        assert!(!is_quota_error(StatusCode::PRECONDITION_FAILED, "not XML"));
+    }
+
+    /// A server that refuses the uploads of items whose `Content-Type` does not tell their charset and component.
+    /// It returns the `Content-Type`s it has received
+    fn strict_content_type_server() -> (Url, std::sync::Arc<Mutex<Vec<String>>>) {
+        let content_types = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let received = content_types.clone();
+        let url = crate::test_server::serve("/calendars/strict/", move |request| {
+            let content_type = request.header("content-type").unwrap_or_default().to_string();
+            let reply = match content_type.as_str() {
+                "text/calendar; charset=utf-8; component=VTODO" => "201 Created\r\nETag: \"strict-1\"",
+                _ => "415 Unsupported Media Type",
+            };
+            received.lock().unwrap().push(content_type);
+            Some(reply.to_string())
+        });
+        (url, content_types)
+    }
+
+    #[tokio::test]
+    async fn test_feed_timeout() {
+        // The feed is hosted on another server than the calendar, that never replies
+        let source = crate::test_server::serve("/feed.ics", |_| None);
+        let resource = Resource::new("https://my.server.com/calendars/holidays/".parse().unwrap(), "user".to_string(), "pass".to_string());
+        resource.set_timeout(Some(std::time::Duration::from_millis(200)));
+        let calendar = <RemoteCalendar as DavCalendar>::new("Holidays".to_string(), resource, SupportedComponents::EVENT, None)
+            .with_subscription(Some(Subscription { source, refresh_interval: None }));
+
+        let err = calendar.fetch_subscribed_items().await.unwrap_err();
+        assert!(KitchenFridgeError::of(&*err).unwrap().is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_upload_content_type() {
+        let (cal_url, content_types) = strict_content_type_server();
+        let resource = Resource::new(cal_url.clone(), "user".to_string(), "pass".to_string());
+        let mut calendar = <RemoteCalendar as DavCalendar>::new("Strict".to_string(), resource, SupportedComponents::TODO, None);
+
+        // Without its parameters, the upload is refused
+        let without_parameters = crate::config::OverrideGuard::set(&crate::config::ITEM_CONTENT_TYPE_PARAMETERS, false);
+        let task = crate::Task::new("Refused".to_string(), false, &cal_url);
+        let refused = calendar.add_item(Item::Task(task)).await;
+        drop(without_parameters);
+        assert!(refused.is_err());
+
+        let task = crate::Task::new("Accepted".to_string(), false, &cal_url);
+        let status = calendar.add_item(Item::Task(task)).await.unwrap();
+        assert_eq!(status, SyncStatus::Synced(VersionTag::from("\"strict-1\"".to_string())));
+
+        assert_eq!(*content_types.lock().unwrap(), vec!["text/calendar", "text/calendar; charset=utf-8; component=VTODO"]);
+        assert_eq!(xml_content_type(), "application/xml; charset=utf-8");
+    }
+
+    /// A server that replies to the requests it receives with the given replies (status lines and bodies), in order, and with errors once they have all been sent.
+    /// Status lines may be followed by extra `\r\n`-separated headers
+    fn sequential_server(replies: Vec<(&'static str, String)>) -> Url {
+        recording_server(replies).0
+    }
+
+    /// Same as [`sequential_server`], but also returns the requests it receives
+    fn recording_server(replies: Vec<(&'static str, String)>) -> (Url, std::sync::Arc<Mutex<Vec<crate::test_server::Request>>>) {
+        let requests = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let mut replies = replies.into_iter();
+        let url = crate::test_server::serve("/calendars/tasks/", move |request| {
+            received.lock().unwrap().push(request.clone());
+            let (status, body) = replies.next().unwrap_or(("500 Internal Server Error", String::new()));
+            Some(format!("{}\r\nContent-Type: application/xml\r\n\r\n{}", status, body))
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_items_changed_while_fetched() {
+        let todo = |uid: &str| format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\nBEGIN:VTODO\r\nUID:{}\r\nDTSTAMP:20210321T001600Z\r\nSUMMARY:Task {}\r\nEND:VTODO\r\nEND:VCALENDAR\r\n", uid, uid);
+        let fetched = |href: &str, etag: &str, uid: &str| format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:getetag>{}</d:getetag><c:calendar-data>{}</c:calendar-data></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+            href, etag, todo(uid));
+        let multistatus = |responses: &[String]| format!(r#"<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">{}</d:multistatus>"#, responses.concat());
+        let etag_reply = |href: &str, etag: &str| multistatus(&[format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:getetag>{}</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>", href, etag)]);
+
+        let cal_url = sequential_server(vec![
+            // The first multiget: `a` is as listed, `b` has changed since, and `c` has been deleted
+            ("207 Multi-Status", multistatus(&[
+                fetched("/calendars/tasks/a.ics", "\"a1\"", "a"),
+                fetched("/calendars/tasks/b.ics", "\"b2\"", "b"),
+                "<d:response><d:href>/calendars/tasks/c.ics</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>".to_string(),
+            ])),
+            // `b` has changed again when its version is checked
+            ("207 Multi-Status", etag_reply("/calendars/tasks/b.ics", "\"b3\"")),
+            // The second multiget: `b` is now stable
+            ("207 Multi-Status", multistatus(&[fetched("/calendars/tasks/b.ics", "\"b3\"", "b")])),
+            ("207 Multi-Status", etag_reply("/calendars/tasks/b.ics", "\"b3\"")),
+        ]);
+        let item_url = |name: &str| cal_url.join(name).unwrap();
+        let resource = Resource::new(cal_url.clone(), "user".to_string(), "pass".to_string());
+        let calendar = <RemoteCalendar as DavCalendar>::new("Tasks".to_string(), resource, SupportedComponents::TODO, None);
+        let listed = ["a", "b", "c"].iter()
+            .map(|name| (item_url(&format!("{}.ics", name)), VersionTag::from(format!("\"{}1\"", name))))
+            .collect();
+        *calendar.cached_version_tags.lock().unwrap() = Some(listed);
+
+        let items = calendar.get_items_by_url(&[item_url("a.ics"), item_url("b.ics"), item_url("c.ics")]).await.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap().url(), &item_url("a.ics"));
+        assert_eq!(items[0].as_ref().unwrap().sync_status(), &SyncStatus::Synced(VersionTag::from("\"a1\"".to_string())));
+        assert!(items[1].is_none());
+        assert!(calendar.take_unstable_item(&item_url("b.ics")));
+        assert!(!calendar.take_unstable_item(&item_url("b.ics")));
+
+        let items = calendar.get_items_by_url(&[item_url("b.ics")]).await.unwrap();
+        assert_eq!(items[0].as_ref().unwrap().sync_status(), &SyncStatus::Synced(VersionTag::from("\"b3\"".to_string())));
+        assert_eq!(calendar.get_item_version_tags().await.unwrap()[&item_url("b.ics")], VersionTag::from("\"b3\"".to_string()));
+        assert!(!calendar.take_unstable_item(&item_url("b.ics")));
+    }
+
+    #[tokio::test]
+    async fn test_get_changes_since() {
+        let todo = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\nBEGIN:VTODO\r\nUID:c\r\nDTSTAMP:20210321T001600Z\r\nSUMMARY:Task c\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let multistatus = |responses: &str, token: &str| format!(r#"<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">{}<d:sync-token>{}</d:sync-token></d:multistatus>"#, responses, token);
+        let changed = |href: &str, etag: &str| format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:getetag>{}</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>", href, etag);
+
+        let cal_url = sequential_server(vec![
+            // The server truncates its reply, and tells the rest of the changes from the token it returns
+            ("207 Multi-Status", multistatus(&format!("{}{}",
+                changed("/calendars/tasks/a.ics", "\"a2\""),
+                "<d:response><d:href>/calendars/tasks/</d:href><d:status>HTTP/1.1 507 Insufficient Storage</d:status></d:response>"), "t2")),
+            ("207 Multi-Status", multistatus(&format!("{}{}",
+                "<d:response><d:href>/calendars/tasks/b.ics</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+                changed("/calendars/tasks/c.ics", "\"c1\"")), "t3")),
+            // Changed items are fetched without listing the whole calendar
+            ("207 Multi-Status", format!(r#"<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:response><d:href>/calendars/tasks/c.ics</d:href><d:propstat><d:prop><d:getetag>"c1"</d:getetag><c:calendar-data>{}</c:calendar-data></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"#, todo)),
+            ("403 Forbidden", r#"<d:error xmlns:d="DAV:"><d:valid-sync-token/></d:error>"#.to_string()),
+        ]);
+        let item_url = |name: &str| cal_url.join(name).unwrap();
+        let resource = Resource::new(cal_url.clone(), "user".to_string(), "pass".to_string());
+        let calendar = <RemoteCalendar as DavCalendar>::new("Tasks".to_string(), resource.clone(), SupportedComponents::TODO, None)
+            .with_supported_reports(vec!["sync-collection".to_string()]);
+
+        let changes = calendar.get_changes_since(Some("t1")).await.unwrap().unwrap();
+        assert_eq!(changes.sync_token, "t3");
+        assert_eq!(changes.changed.len(), 2);
+        assert_eq!(changes.changed[&item_url("a.ics")], VersionTag::from("\"a2\"".to_string()));
+        assert_eq!(changes.deleted, vec![item_url("b.ics")].into_iter().collect());
+
+        let items = calendar.get_items_by_url(&[item_url("c.ics")]).await.unwrap();
+        assert_eq!(items[0].as_ref().unwrap().sync_status(), &SyncStatus::Synced(VersionTag::from("\"c1\"".to_string())));
+
+        // The calendar must then be listed in full
+        assert_eq!(calendar.get_changes_since(Some("t3")).await.unwrap(), None);
+        let unsupported = <RemoteCalendar as DavCalendar>::new("Tasks".to_string(), resource, SupportedComponents::TODO, None);
+        assert_eq!(unsupported.get_changes_since(None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes() {
+        let listing = r#"<d:multistatus xmlns:d="DAV:"><d:response><d:href>/calendars/tasks/a.ics</d:href><d:propstat><d:prop><d:getetag>"a1"</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response><d:response><d:href>/calendars/tasks/b.ics</d:href><d:propstat><d:prop><d:getetag>"b1"</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"#;
+        let (cal_url, requests) = recording_server(vec![
+            ("207 Multi-Status", listing.to_string()),
+            ("204 No Content", String::new()),
+            ("412 Precondition Failed", String::new()),
+            ("412 Precondition Failed", String::new()),
+            ("204 No Content", String::new()),
+        ]);
+        let item_url = |name: &str| cal_url.join(name).unwrap();
+        let resource = Resource::new(cal_url.clone(), "user".to_string(), "pass".to_string());
+        let mut calendar = <RemoteCalendar as DavCalendar>::new("Tasks".to_string(), resource, SupportedComponents::TODO, None);
+        calendar.get_item_version_tags().await.unwrap();
+
+        calendar.delete_item(&item_url("a.ics")).await.unwrap();
+        assert_eq!(requests.lock().unwrap()[1].method, "DELETE");
+        assert_eq!(requests.lock().unwrap()[1].header("if-match"), Some("\"a1\""));
+
+        // Items that have changed on the server since they have been listed are not overwritten
+        let err = calendar.delete_item(&item_url("b.ics")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<PreconditionFailedError>().unwrap().item, item_url("b.ics"));
+        let mut task = Item::Task(crate::Task::new("Task".to_string(), false, &cal_url));
+        task.set_url(item_url("c.ics"));
+        task.set_sync_status(SyncStatus::LocallyModified(VersionTag::from("\"c1\"".to_string())));
+        let err = calendar.update_item(task).await.unwrap_err();
+        assert_eq!(crate::error_code::code_of(&*err), Some(crate::error_code::PRECONDITION_FAILED));
+        assert_eq!(requests.lock().unwrap()[3].header("if-match"), Some("\"c1\""));
+
+        // Items whose version is unknown are deleted unconditionally
+        calendar.delete_item(&item_url("d.ics")).await.unwrap();
+        assert_eq!(requests.lock().unwrap()[4].header("if-match"), None);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_tag_mismatch() {
+        // The copy of an attendee, after the organizer has added another attendee, as sabre/dav stores it
+        let todo = |summary: &str, attendees: &str| format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Sabre//Sabre VObject 4.3.0//EN\r\nBEGIN:VTODO\r\nUID:meeting\r\nDTSTAMP:20210321T001600Z\r\nSUMMARY:{}\r\nORGANIZER:mailto:boss@example.com\r\n{}END:VTODO\r\nEND:VCALENDAR\r\n",
+            summary, attendees);
+        let cal_url = sequential_server(vec![
+            ("412 Precondition Failed", String::new()),
+            ("200 OK\r\nETag: \"e2\"\r\nSchedule-Tag: \"s2\"", todo("Prepare the meeting", "ATTENDEE;PARTSTAT=ACCEPTED:mailto:me@example.com\r\nATTENDEE;PARTSTAT=NEEDS-ACTION:mailto:other@example.com\r\n")),
+            ("204 No Content\r\nETag: \"e3\"\r\nSchedule-Tag: \"s3\"", String::new()),
+        ]);
+        let item_url = cal_url.join("meeting.ics").unwrap();
+        let resource = Resource::new(cal_url.clone(), "user".to_string(), "pass".to_string());
+        let mut calendar = <RemoteCalendar as DavCalendar>::new("Tasks".to_string(), resource, SupportedComponents::TODO, None);
+
+        let synced = VersionTag::from("\"e1\"".to_string()).with_schedule_tag(Some("\"s1\"".to_string()));
+        let local = crate::ical::parse(
+            &todo("Prepare the meeting, with slides", "ATTENDEE;PARTSTAT=ACCEPTED:mailto:me@example.com\r\n"),
+            item_url.clone(), SyncStatus::LocallyModified(synced)).unwrap();
+        assert!(local.is_scheduling_object());
+
+        let status = calendar.update_item(local).await.unwrap();
+        assert_eq!(status, SyncStatus::Synced(VersionTag::from("\"e3\"".to_string())));
+        assert_eq!(status.version_tag().unwrap().schedule_tag(), Some("\"s3\""));
+
+        // Our change is kept, along with the attendee the organizer has added
+        let merged = calendar.take_rewritten_item(&item_url).unwrap();
+        assert_eq!(merged.name(), "Prepare the meeting, with slides");
+        let attendees: Vec<_> = merged.unwrap_task().extra_parameters().iter()
+            .filter(|prop| prop.name == "ATTENDEE")
+            .filter_map(|prop| prop.value.clone())
+            .collect();
+        assert_eq!(attendees, vec!["mailto:me@example.com", "mailto:other@example.com"]);
+        assert_eq!(merged.sync_status(), &status);
+    }
+}
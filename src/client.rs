@@ -2,7 +2,7 @@
 
 use std::error::Error;
 use std::convert::TryFrom;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
@@ -11,84 +11,257 @@ use reqwest::header::CONTENT_TYPE;
 use minidom::Element;
 use url::Url;
 use csscolorparser::Color;
+use chrono::{DateTime, Duration, Utc};
 
-use crate::resource::Resource;
-use crate::utils::{find_elem, find_elems};
+use crate::resource::{Authentication, Credentials, CredentialsProvider, Resource};
+use crate::transfer_stats::{TransferCounter, TransferStats};
+use crate::dav_error::{HttpError, LoopDetectedError, ResponseTooLarge};
+use crate::error::KitchenFridgeError;
+use crate::error_code::{self, ErrorCode};
+use crate::utils::{find_elem, find_elems, find_multi_valued_property, parse_xml};
 use crate::calendar::remote_calendar::RemoteCalendar;
-use crate::calendar::SupportedComponents;
-use crate::traits::CalDavSource;
+use crate::calendar::{ComponentSet, SupportedComponents};
+use crate::traits::{CalDavSource, SourceAdapter, SyncMetadata};
 use crate::traits::BaseCalendar;
 use crate::traits::DavCalendar;
+use crate::item::{Item, SyncStatus};
 
+pub mod discovery;
+pub mod probe;
+pub mod retry;
+pub use retry::{RetryPolicy, Throttled};
+mod copy;
 
-static DAVCLIENT_BODY: &str = r#"
-    <d:propfind xmlns:d="DAV:">
-       <d:prop>
-           <d:current-user-principal />
-       </d:prop>
-    </d:propfind>
-"#;
+/// How long requests wait for the server by default (see [`Client::set_timeout`])
+pub const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
-static HOMESET_BODY: &str = r#"
-    <d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav" >
-      <d:self/>
-      <d:prop>
-        <c:calendar-home-set />
-      </d:prop>
-    </d:propfind>
-"#;
+/// How long connections to the server take at most by default (see [`ClientBuilder::connect_timeout`])
+pub const DEFAULT_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
-static CAL_BODY: &str = r#"
-    <d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav" >
-       <d:prop>
-         <d:displayname />
-         <E:calendar-color xmlns:E="http://apple.com/ns/ical/"/>
-         <d:resourcetype />
-         <c:supported-calendar-component-set />
-       </d:prop>
-    </d:propfind>
-"#;
+const DAV_NS: &str = "DAV:";
+const CALDAV_NS: &str = "urn:ietf:params:xml:ns:caldav";
+const APPLE_ICAL_NS: &str = "http://apple.com/ns/ical/";
+const CALENDARSERVER_NS: &str = "http://calendarserver.org/ns/";
+const CARDDAV_NS: &str = "urn:ietf:params:xml:ns:carddav";
+const OWNCLOUD_NS: &str = "http://owncloud.org/ns";
+
+/// The properties that tell the type of a principal (see [`PrincipalType`])
+const PRINCIPAL_TYPE_PROPERTIES: &[(&str, &str)] = &[
+    (CALDAV_NS, "calendar-home-set"),
+    (CARDDAV_NS, "addressbook-home-set"),
+    (CALDAV_NS, "schedule-inbox-URL"),
+];
+
+/// The properties the calendar discovery asks for
+const CALENDAR_PROPERTIES: &[(&str, &str)] = &[
+    (DAV_NS, "displayname"),
+    (APPLE_ICAL_NS, "calendar-color"),
+    (DAV_NS, "resourcetype"),
+    (DAV_NS, "current-user-privilege-set"),
+    (DAV_NS, "supported-report-set"),
+    (CALDAV_NS, "supported-calendar-component-set"),
+    (CALDAV_NS, "supported-calendar-data"),
+    (CALDAV_NS, "min-date-time"),
+    (CALDAV_NS, "max-date-time"),
+    (CALDAV_NS, "max-resource-size"),
+    (CALENDARSERVER_NS, "source"),
+    (APPLE_ICAL_NS, "refreshrate"),
+    (OWNCLOUD_NS, "calendar-enabled"),
+];
 
 
 
-pub(crate) async fn sub_request(resource: &Resource, method: &str, body: String, depth: u32) -> Result<String, Box<dyn Error>> {
+/// The `Content-Type` of XML request bodies (see [`crate::config::XML_CONTENT_TYPE`])
+pub(crate) fn xml_content_type() -> String {
+    crate::config::XML_CONTENT_TYPE.lock().unwrap().clone()
+}
+
+/// How many redirects a request may follow
+const MAX_REDIRECTS: usize = 10;
+
+/// A redirect policy that stops with a [`LoopDetectedError`] when a request is redirected to a URL it has already been redirected to, or too many times
+fn loop_detecting_redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        let is_loop = attempt.previous().contains(attempt.url());
+        if is_loop || attempt.previous().len() > MAX_REDIRECTS {
+            // The first visited URL is the one that has been requested
+            let mut redirects = attempt.previous().to_vec();
+            redirects.push(attempt.url().clone());
+            let url = redirects.remove(0);
+            return attempt.error(LoopDetectedError { url, redirects });
+        }
+        attempt.follow()
+    })
+}
+
+/// A builder of HTTP clients, that has the settings every request of this crate relies on
+pub(crate) fn http_client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .redirect(loop_detecting_redirect_policy())
+        .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+}
+
+/// Returns the [`LoopDetectedError`] a request has failed with, if any (see [`loop_detecting_redirect_policy`])
+fn loop_detected(err: &reqwest::Error) -> Option<LoopDetectedError> {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(loop_err) = err.downcast_ref::<LoopDetectedError>() {
+            return Some(loop_err.clone());
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Add a unique identifier to a request, in case a header name is configured for it (see [`crate::config::REQUEST_ID_HEADER`]).
+///
+/// This returns the identifier, so that it can be logged
+fn with_request_id(request: reqwest::RequestBuilder, header_name: Option<&str>) -> (reqwest::RequestBuilder, Option<String>) {
+    match header_name {
+        None => (request, None),
+        Some(header_name) => {
+            let request_id = uuid::Uuid::new_v4().to_hyphenated().to_string();
+            (request.header(header_name, request_id.as_str()), Some(request_id))
+        },
+    }
+}
+
+/// See [`crate::config::MAX_RESPONSE_SIZE`]
+pub(crate) fn max_response_size() -> usize {
+    *crate::config::MAX_RESPONSE_SIZE.lock().unwrap()
+}
+
+/// Read the body of a reply, but fail with a [`ResponseTooLarge`] error as soon as it turns out to be larger than `limit` bytes.
+///
+/// `request` describes the request this is the reply of (e.g. `GET https://my.server.com/calendars/john/tasks/item.ics`)
+pub(crate) async fn read_body(mut response: reqwest::Response, limit: usize, request: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let too_large = || Box::new(ResponseTooLarge { request: request.to_string(), limit });
+    if response.content_length().is_some_and(|length| length > limit as u64) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Read the body of an error reply, that is only used to tell what went wrong. Bodies that are too large are ignored
+pub(crate) async fn read_error_body(response: reqwest::Response, request: &str) -> String {
+    read_body(response, max_response_size(), request).await
+        .map(|body| String::from_utf8_lossy(&body).into_owned())
+        .unwrap_or_default()
+}
+
+pub(crate) async fn sub_request(resource: &Resource, method: &str, body: String, depth: u32) -> Result<String, KitchenFridgeError> {
+    let operation = method;
     let method = method.parse()
         .expect("invalid method name");
+    let bytes_sent = body.len() as u64;
 
-    let res = reqwest::Client::new()
+    let request = resource.http_client()
         .request(method, resource.url().clone())
         .header("Depth", depth)
-        .header(CONTENT_TYPE, "application/xml")
-        .basic_auth(resource.username(), Some(resource.password()))
-        .body(body)
-        .send()
-        .await?;
+        .header(CONTENT_TYPE, xml_content_type())
+        .body(body);
+    let header_name = crate::config::REQUEST_ID_HEADER.lock().unwrap().clone();
+    let (request, request_id) = with_request_id(request, header_name.as_deref());
+    let request_descr = match &request_id {
+        None => format!("{} {}", operation, resource.url()),
+        Some(request_id) => format!("{} {} (request id {})", operation, resource.url(), request_id),
+    };
+    log::debug!("Sending {}", request_descr);
+
+    let res = match resource.send(request).await {
+        Ok(res) => res,
+        Err(err) => {
+            log::debug!("{} failed: {}", request_descr, err);
+            if let KitchenFridgeError::Http(err) = &err {
+                if let Some(loop_err) = loop_detected(err) {
+                    return Err(loop_err.into());
+                }
+            }
+            return Err(err);
+        },
+    };
 
+    if res.status() == StatusCode::LOOP_DETECTED {
+        resource.record_transfer(operation, bytes_sent, 0);
+        let err = LoopDetectedError { url: res.url().clone(), redirects: Vec::new() };
+        log::debug!("{} failed: {}", request_descr, err);
+        return Err(err.into());
+    }
     if res.status().is_success() == false {
-        return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+        let status = res.status();
+        let body = read_error_body(res, &request_descr).await;
+        resource.record_transfer(operation, bytes_sent, body.len() as u64);
+        let err = HttpError::from_reply(status, &body).with_request_id(request_id);
+        log::debug!("{} failed: {}", request_descr, err);
+        return Err(err.into());
     }
 
-    let text = res.text().await?;
-    Ok(text)
+    let limit = max_response_size();
+    let too_large = |received: usize| {
+        resource.record_transfer(operation, bytes_sent, received as u64);
+        let err = ResponseTooLarge { request: request_descr.clone(), limit };
+        log::warn!("{}", err);
+        Err(err.into())
+    };
+    if res.content_length().is_some_and(|length| length > limit as u64) {
+        return too_large(0);
+    }
+
+    // Read the body chunk by chunk, so that what has been received can be used in case the connection drops
+    let mut res = res;
+    let mut body = Vec::new();
+    loop {
+        let chunk = res.chunk().await;
+        if !matches!(chunk, Ok(Some(_))) {
+            resource.record_transfer(operation, bytes_sent, body.len() as u64);
+        }
+        match chunk {
+            Ok(Some(chunk)) if body.len() + chunk.len() > limit => return too_large(body.len() + chunk.len()),
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(err) => return Err(TruncatedReply {
+                partial_body: String::from_utf8_lossy(&body).into_owned(),
+                cause: err,
+            }.into()),
+        }
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
 }
 
-pub(crate) async fn sub_request_and_extract_elem(resource: &Resource, body: String, items: &[&str]) -> Result<String, Box<dyn Error>> {
-    let text = sub_request(resource, "PROPFIND", body, 0).await?;
+/// The error returned when the connection drops while the body of a reply to a PROPFIND or a REPORT is being received
+#[derive(Debug)]
+pub struct TruncatedReply {
+    /// What has been received before the connection dropped
+    pub partial_body: String,
+    pub cause: reqwest::Error,
+}
 
-    let mut current_element: &Element = &text.parse()?;
-    for item in items {
-        current_element = match find_elem(&current_element, item) {
-            Some(elem) => elem,
-            None => return Err(format!("missing element {}", item).into()),
-        }
+impl TruncatedReply {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::TRUNCATED_REPLY
     }
-    Ok(current_element.text())
 }
 
-pub(crate) async fn sub_request_and_extract_elems(resource: &Resource, method: &str, body: String, item: &str) -> Result<Vec<Element>, Box<dyn Error>> {
+impl std::fmt::Display for TruncatedReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "reply truncated after {} bytes: {} [{}]", self.partial_body.len(), self.cause, self.code())
+    }
+}
+
+impl Error for TruncatedReply {}
+
+pub(crate) async fn sub_request_and_extract_elems(resource: &Resource, method: &str, body: String, item: &str) -> Result<Vec<Element>, KitchenFridgeError> {
     let text = sub_request(resource, method, body, 1).await?;
 
-    let element: &Element = &text.parse()?;
+    let element: &Element = &parse_xml(&text)?;
     Ok(find_elems(&element, item)
         .iter()
         .map(|elem| (*elem).clone())
@@ -96,6 +269,178 @@ pub(crate) async fn sub_request_and_extract_elems(resource: &Resource, method: &
     )
 }
 
+/// Parse the `<status>` of a multistatus response (e.g. `HTTP/1.1 404 Not Found`)
+pub(crate) fn parse_status(status: &Element) -> Option<StatusCode> {
+    status.text().split_whitespace().nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+}
+
+/// Returns the HTTP status of a multistatus `<response>`, in case the server did not succeed in describing it.
+///
+/// This is the case either when the `<response>` has a non-successful `<status>` itself, or when none of its `<propstat>`s is successful
+pub(crate) fn failed_response_status(response: &Element) -> Option<StatusCode> {
+    if let Some(status) = response.children().find(|el| el.name() == "status").and_then(parse_status) {
+        return if status.is_success() { None } else { Some(status) };
+    }
+
+    let propstat_statuses: Vec<StatusCode> = response.children()
+        .filter(|el| el.name() == "propstat")
+        .filter_map(|propstat| propstat.children().find(|el| el.name() == "status"))
+        .filter_map(parse_status)
+        .collect();
+    if propstat_statuses.is_empty() || propstat_statuses.iter().any(|s| s.is_success()) {
+        None
+    } else {
+        propstat_statuses.into_iter().next()
+    }
+}
+
+/// Parse the value of a `calendar-enabled` property (Nextcloud uses `1` and `0`)
+fn parse_enabled_flag(value: &str) -> Option<bool> {
+    match value.trim() {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// A `<response>` of a multistatus reply (see [`Client::raw_propfind`])
+#[derive(Clone, Debug)]
+pub struct RawResponse {
+    /// The URL of the resource this response describes
+    pub href: Url,
+    /// The status of the whole response, in case the server gave one (e.g. 404 when the resource does not exist)
+    pub status: Option<StatusCode>,
+    /// The properties the server returned, with their individual status
+    pub properties: Vec<RawProperty>,
+    /// The `<response>` element itself
+    pub element: Element,
+}
+
+/// A property of a [`RawResponse`]
+#[derive(Clone, Debug)]
+pub struct RawProperty {
+    pub namespace: String,
+    pub name: String,
+    /// The status of the `<propstat>` this property belongs to (e.g. 404 for a property the resource does not have)
+    pub status: StatusCode,
+    /// The property element, e.g. `<d:displayname>Tasks</d:displayname>`
+    pub element: Element,
+}
+
+impl RawResponse {
+    /// Returns a property that has been successfully retrieved
+    pub fn property(&self, namespace: &str, name: &str) -> Option<&Element> {
+        self.properties.iter()
+            .find(|prop| prop.status.is_success() && prop.namespace == namespace && prop.name == name)
+            .map(|prop| &prop.element)
+    }
+
+    /// Parse the `<response>`s of a multistatus reply. Their `<href>`s are resolved against `base`
+    fn parse_multistatus(root: &Element, base: &Resource) -> Vec<RawResponse> {
+        let mut responses = Vec::new();
+        for response in find_elems(root, "response") {
+            let href = match response.children().find(|el| el.name() == "href").map(|href| base.combine(&href.text())) {
+                Some(Ok(resource)) => resource.url().clone(),
+                Some(Err(err)) => {
+                    log::warn!("Ignoring a response with an invalid URL ({})", err);
+                    continue;
+                },
+                None => {
+                    log::warn!("Ignoring a response that has no URL");
+                    continue;
+                },
+            };
+
+            let mut properties = Vec::new();
+            for propstat in response.children().filter(|el| el.name() == "propstat") {
+                let status = propstat.children().find(|el| el.name() == "status")
+                    .and_then(parse_status)
+                    .unwrap_or(StatusCode::OK);
+                for prop in propstat.children().filter(|el| el.name() == "prop") {
+                    properties.extend(prop.children().map(|el| RawProperty {
+                        namespace: el.ns(),
+                        name: el.name().to_string(),
+                        status,
+                        element: el.clone(),
+                    }));
+                }
+            }
+
+            responses.push(RawResponse {
+                href,
+                status: response.children().find(|el| el.name() == "status").and_then(parse_status),
+                properties,
+                element: response.clone(),
+            });
+        }
+        responses
+    }
+}
+
+/// The body of a PROPFIND that asks for some properties, given as (namespace, name) pairs
+fn propfind_body(props: &[(&str, &str)]) -> String {
+    let props: String = props.iter().enumerate()
+        .map(|(i, (namespace, name))| format!("<x{0}:{1} xmlns:x{0}=\"{2}\"/>", i, name, namespace))
+        .collect();
+    format!(r#"<d:propfind xmlns:d="DAV:"><d:prop>{}</d:prop></d:propfind>"#, props)
+}
+
+/// Send a PROPFIND for some properties, given as (namespace, name) pairs
+async fn propfind(resource: &Resource, props: &[(&str, &str)], depth: u32) -> Result<Vec<RawResponse>, KitchenFridgeError> {
+    let text = sub_request(resource, "PROPFIND", propfind_body(props), depth).await?;
+    Ok(RawResponse::parse_multistatus(&parse_xml(&text)?, resource))
+}
+
+/// Returns the texts of the `<href>`s in a property (e.g. `<d:current-user-principal><d:href>/principals/john/</d:href></d:current-user-principal>`)
+fn property_hrefs(responses: &[RawResponse], namespace: &str, name: &str) -> Vec<String> {
+    responses.iter()
+        .filter_map(|response| response.property(namespace, name))
+        .flat_map(|prop| find_elems(prop, "href"))
+        .map(|href| href.text())
+        .collect()
+}
+
+/// Returns the text of the first `<href>` in a property (see [`property_hrefs`])
+fn property_href(responses: &[RawResponse], namespace: &str, name: &str) -> Result<String, KitchenFridgeError> {
+    property_hrefs(responses, namespace, name).into_iter().next()
+        .ok_or_else(|| KitchenFridgeError::MissingElement(name.to_string()))
+}
+
+/// What a principal is used for (see [`Client::get_principals`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PrincipalType {
+    /// The principal owns calendars (i.e. it has a calendar home set)
+    Calendar,
+    /// The principal owns address books (i.e. it has a CardDAV address book home set)
+    Addressbook,
+    /// The principal handles scheduling (i.e. it has a scheduling inbox, see RFC 6638)
+    Scheduling,
+    /// The server has told nothing about what this principal is used for
+    Unknown,
+}
+
+impl PrincipalType {
+    /// The types of a principal, given its reply to a PROPFIND for [`PRINCIPAL_TYPE_PROPERTIES`]
+    fn from_response(response: &RawResponse) -> Vec<PrincipalType> {
+        let mut types = Vec::new();
+        if response.property(CALDAV_NS, "calendar-home-set").is_some() {
+            types.push(PrincipalType::Calendar);
+        }
+        if response.property(CARDDAV_NS, "addressbook-home-set").is_some() {
+            types.push(PrincipalType::Addressbook);
+        }
+        if response.property(CALDAV_NS, "schedule-inbox-URL").is_some() {
+            types.push(PrincipalType::Scheduling);
+        }
+        if types.is_empty() {
+            types.push(PrincipalType::Unknown);
+        }
+        types
+    }
+}
+
 
 /// A CalDAV data source that fetches its data from a CalDAV server
 #[derive(Debug)]
@@ -105,14 +450,262 @@ pub struct Client {
     /// The interior mutable part of a Client.
     /// This data may be retrieved once and then cached
     cached_replies: Mutex<CachedReplies>,
+    /// Held while the calendars are being discovered, so that concurrent callers share a single discovery
+    discovery_lock: tokio::sync::Mutex<()>,
+    /// See [`ClientBuilder::keep_raw_discovery_responses`]
+    keep_raw_discovery_responses: bool,
 }
 
 
 #[derive(Debug, Default)]
 struct CachedReplies {
     principal: Option<Resource>,
+    /// Every principal the server has returned as the current user principal (most servers return only one)
+    principal_candidates: Vec<Resource>,
+    principals: Option<Vec<(PrincipalType, Url)>>,
+    principal_display_name: Option<Option<String>>,
     calendar_home_set: Option<Resource>,
     calendars: Option<HashMap<Url, Arc<Mutex<RemoteCalendar>>>>,
+    /// When the calendars have last been discovered
+    discovered_at: Option<DateTime<Utc>>,
+    /// How many discoveries have completed, so that callers that waited for an in-flight discovery can tell it has completed
+    discovery_generation: u64,
+    discovery_report: Option<DiscoveryReport>,
+    probe: Option<probe::ProbeReport>,
+}
+
+/// A summary of the last calendar discovery, e.g. to tell the user which calendars have been skipped and why
+#[derive(Clone, Debug, Default)]
+pub struct DiscoveryReport {
+    /// The URLs of the calendars that have been found
+    pub calendars: Vec<Url>,
+    /// The collections the server refused to describe. They have been skipped
+    pub unavailable_calendars: Vec<UnavailableCalendar>,
+    /// Every `<response>` the calendar home set has returned, in order, whether it has been accepted as a calendar or not
+    pub responses: Vec<DiscoveredCollection>,
+}
+
+/// A `<response>` of the calendar home set, as seen during a calendar discovery
+#[derive(Clone, Debug)]
+pub struct DiscoveredCollection {
+    /// The `<href>` of the response, as sent by the server
+    pub href: Option<String>,
+    /// The URL `href` resolves to
+    pub url: Option<Url>,
+    pub display_name: Option<String>,
+    /// The names of the `<resourcetype>`s (e.g. `collection`, `calendar`)
+    pub resource_types: Vec<String>,
+    /// The names of the components of the `<supported-calendar-component-set>`, including the ones this crate does not know, or `None` if the server sent none
+    pub components: Option<Vec<String>>,
+    /// Why this collection is not a calendar, or `None` if it has been accepted as one
+    pub skipped: Option<SkipReason>,
+    /// The `<response>` itself. This is only kept when the client is told to (see [`ClientBuilder::keep_raw_discovery_responses`])
+    pub raw: Option<Element>,
+}
+
+/// Why a collection of the calendar home set is not a calendar (see [`DiscoveredCollection::skipped`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The server refused to describe it (e.g. `403` for a share that has been revoked)
+    Unavailable(StatusCode),
+    /// It is neither a calendar nor a subscription (e.g. an address book)
+    NotACalendar,
+    /// It supports no components, e.g. because it is the root calendar collection
+    NoComponents,
+    NoUrl,
+    InvalidUrl(String),
+    InvalidComponents(String),
+    /// It is a subscription, but its source is missing or is not a valid URL
+    NoSubscriptionSource,
+}
+
+/// A collection that was listed in the calendar home set, but that could not be accessed
+#[derive(Clone, Debug)]
+pub struct UnavailableCalendar {
+    pub url: Url,
+    /// The HTTP status the server returned for this collection (e.g. 403 for a share that has been revoked)
+    pub status: StatusCode,
+}
+
+/// Builds a [`Client`] that has non-default settings (see [`Client::builder`])
+pub struct ClientBuilder {
+    url: String,
+    authentication: Authentication,
+    use_bundled_roots: bool,
+    root_certificates: Vec<reqwest::Certificate>,
+    keep_raw_discovery_responses: bool,
+    connect_timeout: std::time::Duration,
+    request_timeout: Option<std::time::Duration>,
+    retry_policy: RetryPolicy,
+    customize_http: Option<Box<dyn FnOnce(reqwest::ClientBuilder) -> reqwest::ClientBuilder + Send>>,
+}
+
+impl ClientBuilder {
+    /// Verify the certificates of servers with the root certificates that are bundled into this crate, rather than with the trust store of the system.
+    ///
+    /// This works out of the box on systems that have no trust store (e.g. minimal containers), but requires the `rustls-tls-webpki-roots` feature
+    pub fn use_bundled_roots(mut self, use_bundled_roots: bool) -> Self {
+        self.use_bundled_roots = use_bundled_roots;
+        self
+    }
+
+    /// Trust an additional root certificate (e.g. the one of a self-signed server)
+    pub fn add_root_certificate(mut self, certificate: reqwest::Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Authenticate with HTTP Digest authentication only, so that the password is never sent (see [`Authentication::Digest`]).
+    ///
+    /// By default, requests are sent with Basic authentication, and Digest challenges are answered in case the server sends some
+    pub fn digest_auth(mut self) -> Self {
+        if let Authentication::Basic(credentials) = &self.authentication {
+            self.authentication = Authentication::Digest(credentials.clone());
+        }
+        self
+    }
+
+    /// Keep the raw `<response>`s of the calendar home set in the reports of the calendar discoveries (see [`DiscoveredCollection::raw`]), e.g. to debug why a calendar is missing.
+    ///
+    /// This is `false` by default, since these may be large
+    pub fn keep_raw_discovery_responses(mut self, keep: bool) -> Self {
+        self.keep_raw_discovery_responses = keep;
+        self
+    }
+
+    /// Set how long connecting to the server takes at most. This is [`DEFAULT_CONNECT_TIMEOUT`] by default.
+    ///
+    /// A connection that takes longer fails with a [`KitchenFridgeError::Timeout`], so that the caller can tell the server is unreachable for now (and retry later) from e.g. a data error
+    pub fn connect_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set how long every request waits for the server, from the moment it is sent until its whole reply has been received (see [`Client::set_timeout`]). This is [`DEFAULT_TIMEOUT`] by default, and `None` waits forever
+    pub fn request_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set how requests that fail because of a transient failure (e.g. a connection error, or a server that is overloaded) are retried (see [`Client::set_retry_policy`]).
+    ///
+    /// By default, they are sent up to 3 times. [`RetryPolicy::disabled`] never retries them
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set options of the underlying HTTP client this crate does not expose (e.g. the local address to bind to, or a custom DNS resolver).
+    ///
+    /// `customize` is given the `reqwest` builder once the TLS settings of this builder have been applied to it, so that it can e.g. trust more certificates.
+    /// Settings every request of this crate relies on are then set again, so that they cannot be overridden by accident. For now, this is only the redirect policy (that detects redirect loops, see [`LoopDetectedError`])
+    pub fn customize_http<F>(mut self, customize: F) -> Self
+    where
+        F: FnOnce(reqwest::ClientBuilder) -> reqwest::ClientBuilder + Send + 'static,
+    {
+        self.customize_http = Some(Box::new(customize));
+        self
+    }
+
+    /// Create the client.
+    ///
+    /// This does not start a connection, but this sets up the TLS backend right away, so that a system that is unable to verify the certificates of servers is reported here (see [`TlsSetupError`]) rather than at the first request
+    pub fn build(mut self) -> Result<Client, Box<dyn Error>> {
+        let url = Url::parse(&self.url)?;
+        let http_client = self.build_http_client()?;
+        let resource = Resource::new_with_http_client(url, self.authentication, http_client);
+        resource.set_timeout(self.request_timeout);
+        resource.set_retry_policy(self.retry_policy);
+        Ok(Client {
+            resource,
+            cached_replies: Mutex::new(CachedReplies::default()),
+            discovery_lock: tokio::sync::Mutex::new(()),
+            keep_raw_discovery_responses: self.keep_raw_discovery_responses,
+        })
+    }
+
+    fn build_http_client(&mut self) -> Result<reqwest::Client, TlsSetupError> {
+        let mut builder = http_client_builder()
+            .connect_timeout(self.connect_timeout);
+        if self.use_bundled_roots {
+            #[cfg(feature = "rustls-tls-webpki-roots")]
+            {
+                builder = builder.use_rustls_tls().tls_built_in_root_certs(true);
+            }
+            #[cfg(not(feature = "rustls-tls-webpki-roots"))]
+            {
+                return Err(TlsSetupError::BundledRootsUnavailable);
+            }
+        } else if self.root_certificates.is_empty() && !system_has_trust_store() {
+            return Err(TlsSetupError::NoTrustStore);
+        }
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        if let Some(customize) = self.customize_http.take() {
+            builder = customize(builder)
+                .redirect(loop_detecting_redirect_policy());
+        }
+        builder.build().map_err(TlsSetupError::Backend)
+    }
+}
+
+/// Whether the system has a trust store, that the native TLS backend can verify the certificates of servers with
+fn system_has_trust_store() -> bool {
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+    {
+        // This also honours the `SSL_CERT_FILE` and `SSL_CERT_DIR` environment variables
+        let probe = openssl_probe::probe();
+        probe.cert_file.is_some() || probe.cert_dir.is_some()
+    }
+    // Other systems always have a trust store
+    #[cfg(not(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))))]
+    true
+}
+
+/// The error returned by [`ClientBuilder::build`] when the certificates of servers would not be verifiable
+#[derive(Debug)]
+pub enum TlsSetupError {
+    /// The system has no trust store (this is e.g. the case of minimal containers)
+    NoTrustStore,
+    /// Bundled root certificates have been asked for (see [`ClientBuilder::use_bundled_roots`]), but this crate has been built without the `rustls-tls-webpki-roots` feature
+    BundledRootsUnavailable,
+    /// The TLS backend could not be set up
+    Backend(reqwest::Error),
+}
+
+impl TlsSetupError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NoTrustStore => error_code::NO_TRUST_STORE,
+            Self::BundledRootsUnavailable => error_code::BUNDLED_ROOTS_UNAVAILABLE,
+            Self::Backend(_) => error_code::TLS_BACKEND,
+        }
+    }
+}
+
+impl std::fmt::Display for TlsSetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::NoTrustStore => write!(f, "No trust store has been found on this system, so the certificates of servers cannot be verified. \
+                Either install the root certificates of the system (e.g. the ca-certificates package, or point SSL_CERT_FILE to a bundle), \
+                trust some root certificates with ClientBuilder::add_root_certificate, \
+                or enable the rustls-tls-webpki-roots feature and use ClientBuilder::use_bundled_roots"),
+            Self::BundledRootsUnavailable => write!(f, "Bundled root certificates are only available when this crate is built with its rustls-tls-webpki-roots feature"),
+            Self::Backend(err) => write!(f, "Unable to set up the TLS backend: {}", err),
+        }?;
+        write!(f, " [{}]", self.code())
+    }
+}
+
+impl Error for TlsSetupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Backend(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 impl Client {
@@ -123,106 +716,479 @@ impl Client {
         Ok(Self{
             resource: Resource::new(url, username.to_string(), password.to_string()),
             cached_replies: Mutex::new(CachedReplies::default()),
+            discovery_lock: tokio::sync::Mutex::new(()),
+            keep_raw_discovery_responses: false,
         })
     }
 
-    /// Return the Principal URL, or fetch it from server if not known yet
-    async fn get_principal(&self) -> Result<Resource, Box<dyn Error>> {
+    /// Create a client that sends its requests with a given HTTP client (e.g. that has a proxy, or timeouts). This does not start a connection.
+    ///
+    /// Every client creates a single HTTP client, that the calendars it returns share, so that connections are reused. This one is used instead.
+    /// Contrary to the HTTP clients this crate builds, it does not detect redirect loops (see [`LoopDetectedError`]): [`ClientBuilder::customize_http`] sets the same options while keeping this detection.
+    /// Its own timeout applies, rather than [`DEFAULT_TIMEOUT`] (see [`Client::set_timeout`])
+    pub fn with_http_client<S: AsRef<str>, T: ToString, U: ToString>(url: S, username: T, password: U, http_client: reqwest::Client) -> Result<Self, Box<dyn Error>> {
+        let url = Url::parse(url.as_ref())?;
+        let authentication = Authentication::Basic(Credentials::new(username, password));
+        let resource = Resource::new_with_http_client(url, authentication, http_client);
+        resource.set_timeout(None);
+
+        Ok(Self{
+            resource,
+            cached_replies: Mutex::new(CachedReplies::default()),
+            discovery_lock: tokio::sync::Mutex::new(()),
+            keep_raw_discovery_responses: false,
+        })
+    }
+
+    /// Create a client that authenticates with an OAuth2 bearer token rather than a username and a password (see [`Client::set_token`] to refresh it). This does not start a connection
+    pub fn new_with_oauth<S: AsRef<str>, T: ToString>(url: S, token: T) -> Result<Self, Box<dyn Error>> {
+        let client = Self::new(url, "", "")?;
+        client.resource.set_credentials(Authentication::Bearer(token.to_string()));
+        Ok(client)
+    }
+
+    /// Create a client that authenticates with HTTP Digest authentication only, so that the password is never sent (see [`Authentication::Digest`]). This does not start a connection
+    pub fn new_with_digest<S: AsRef<str>, T: ToString, U: ToString>(url: S, username: T, password: U) -> Result<Self, Box<dyn Error>> {
+        let client = Self::new(url, "", "")?;
+        client.resource.set_credentials(Authentication::Digest(Credentials::new(username, password)));
+        Ok(client)
+    }
+
+    /// Create a client that asks a provider for the credentials of every request, e.g. to refresh OAuth2 tokens when they expire (see [`CredentialsProvider`]). This does not start a connection
+    pub fn new_with_credentials_provider<S: AsRef<str>>(url: S, provider: Arc<dyn CredentialsProvider>) -> Result<Self, Box<dyn Error>> {
+        let client = Self::new(url, "", "")?;
+        client.resource.set_credentials_provider(provider);
+        Ok(client)
+    }
+
+    /// Create a client from a host name (e.g. `fastmail.com`) or an email address (e.g. `john@fastmail.com`), so that users do not need to know the URL of their server.
+    ///
+    /// As RFC 6764 describes, the CalDAV server of the domain is first looked up in its DNS SRV records (`_caldavs._tcp`, and `_caldav._tcp` only when [`crate::config::SRV_PLAIN_HTTP`] is set), which requires the `dns-srv` feature (see [`discovery`]).
+    /// Records that point outside of the domain are ignored, unless their target is one of the [`crate::config::SRV_TRUSTED_TARGETS`].
+    /// This server (or the host itself, when it has no such records) is then asked for `/.well-known/caldav`, and the redirect it replies with leads to its actual DAV root.
+    /// In case it does not know this path (`404 Not Found`), `host_or_email` is used as a full URL instead (e.g. `https://dav.server.com/dav/`).
+    /// Which of these has succeeded is logged at info level.
+    /// Contrary to the other constructors, this connects to the server, and fetches the principal of the user, so that a wrong setup fails right away
+    pub async fn discover<S: AsRef<str>, T: ToString, U: ToString>(host_or_email: S, username: T, password: U) -> Result<Self, Box<dyn Error>> {
+        let (username, password) = (username.to_string(), password.to_string());
+        let base = match discovery::srv_url(host_or_email.as_ref()).await {
+            Some(url) => {
+                log::info!("Found the CalDAV server of {} through its SRV records: {}", host_or_email.as_ref(), url);
+                url
+            },
+            None => discovery::bootstrap_url(host_or_email.as_ref())?,
+        };
+        let well_known = base.join("/.well-known/caldav")?;
+
+        let bootstrap = Self::new(&well_known, &username, &password)?;
+        let response = bootstrap.resource.send(bootstrap.resource.http_client().get(well_known.clone())).await?;
+        let context_url = match response.status() {
+            StatusCode::NOT_FOUND => {
+                log::info!("{} does not exist, using {} as the DAV root", well_known, base);
+                base
+            },
+            status if status.is_success() => {
+                log::info!("Found the DAV root {} through {}", response.url(), well_known);
+                response.url().clone()
+            },
+            status => {
+                let body = read_error_body(response, &format!("GET {}", well_known)).await;
+                return Err(HttpError::from_reply(status, &body).into());
+            },
+        };
+
+        let client = Self::new(&context_url, username, password)?;
+        client.get_principal().await?;
+        Ok(client)
+    }
+
+    /// Start building a client that has non-default settings (e.g. for systems that have no trust store, see [`ClientBuilder::use_bundled_roots`])
+    pub fn builder<S: ToString, T: ToString, U: ToString>(url: S, username: T, password: U) -> ClientBuilder {
+        ClientBuilder {
+            url: url.to_string(),
+            authentication: Authentication::Basic(Credentials::new(username, password)),
+            use_bundled_roots: false,
+            root_certificates: Vec::new(),
+            keep_raw_discovery_responses: false,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            request_timeout: Some(DEFAULT_TIMEOUT),
+            retry_policy: RetryPolicy::default(),
+            customize_http: None,
+        }
+    }
+
+    /// Replace the credentials of this client, e.g. because they are about to expire.
+    ///
+    /// This applies at once to this client and to the calendars it has returned, and does not clear the replies that have been cached.
+    /// Requests that are in flight and are refused (`401 Unauthorized`) because of the old credentials are sent again with the new ones.
+    /// A client that uses Digest authentication only (see [`Client::new_with_digest`]) keeps doing so.
+    pub fn set_credentials(&self, credentials: Credentials) {
+        log::info!("Credentials for {} have been changed", self.resource.url());
+        let authentication = match self.resource.current_credentials() {
+            Some(Authentication::Digest(_)) => Authentication::Digest(credentials),
+            _ => Authentication::Basic(credentials),
+        };
+        self.resource.set_credentials(authentication);
+    }
+
+    /// Replace the OAuth2 bearer token of this client, e.g. because it has been refreshed. The client authenticates with this token from now on, even if it has been created with a username and a password.
+    ///
+    /// Just like [`Client::set_credentials`], this applies at once to the calendars this client has returned, and does not clear the replies that have been cached
+    pub fn set_token<S: ToString>(&self, token: S) {
+        log::info!("The token for {} has been changed", self.resource.url());
+        self.resource.set_credentials(Authentication::Bearer(token.to_string()));
+    }
+
+    /// Ask a provider for the credentials of every request from now on, rather than using fixed credentials (see [`CredentialsProvider`]).
+    ///
+    /// Just like [`Client::set_credentials`], this applies at once to the calendars this client has returned
+    pub fn set_credentials_provider(&self, provider: Arc<dyn CredentialsProvider>) {
+        log::info!("The credentials provider for {} has been changed", self.resource.url());
+        self.resource.set_credentials_provider(provider);
+    }
+
+    /// Set how long every request waits for the server (from the moment it is sent until its whole reply has been received) before it fails with a [`KitchenFridgeError::Timeout`] (see [`KitchenFridgeError::is_timeout`]).
+    ///
+    /// This is [`DEFAULT_TIMEOUT`] by default. `None` waits forever, unless the HTTP client has a timeout of its own (see [`Client::with_http_client`]).
+    /// This applies at once to this client and to the calendars it has returned
+    pub fn set_timeout(&self, timeout: Option<std::time::Duration>) {
+        self.resource.set_timeout(timeout);
+    }
+
+    /// Set how requests that fail because of a transient failure (a connection error, a timeout, or a `5xx` reply that tells the server is overloaded) are retried, with an exponential backoff.
+    ///
+    /// By default, requests are sent up to 3 times (see [`RetryPolicy`]). Only the requests that can safely be sent several times are retried (e.g. an upload is not, unless it has an `If-Match` precondition). \
+    /// This applies at once to this client and to the calendars it has returned
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        self.resource.set_retry_policy(policy);
+    }
+
+    /// Returns a snapshot of the data that has been exchanged with the server since this client has been created (see [`crate::transfer_stats`])
+    pub fn transfer_stats(&self) -> TransferStats {
+        self.resource.transfer_counter().lock().unwrap().clone()
+    }
+
+    /// Return the Principal URL, or fetch it from server if not known yet.
+    ///
+    /// In case the server returns several principals, this is the first one (see [`Self::get_principals`])
+    async fn get_principal(&self) -> Result<Resource, KitchenFridgeError> {
         if let Some(p) = &self.cached_replies.lock().unwrap().principal {
             return Ok(p.clone());
         }
 
-        let href = sub_request_and_extract_elem(&self.resource, DAVCLIENT_BODY.into(), &["current-user-principal", "href"]).await?;
-        let principal_url = self.resource.combine(&href);
-        self.cached_replies.lock().unwrap().principal = Some(principal_url.clone());
-        log::debug!("Principal URL is {}", href);
+        let responses = propfind(&self.resource, &[(DAV_NS, "current-user-principal")], 0).await?;
+        let candidates = property_hrefs(&responses, DAV_NS, "current-user-principal").iter()
+            .map(|href| self.resource.combine(href))
+            .collect::<Result<Vec<_>, _>>()?;
+        let principal_url = match candidates.first() {
+            None => return Err(KitchenFridgeError::MissingElement("current-user-principal".to_string())),
+            Some(principal_url) => principal_url.clone(),
+        };
+        log::debug!("Principal URL is {}", principal_url.url());
+        if candidates.len() > 1 {
+            log::debug!("The server has returned {} principals", candidates.len());
+        }
+
+        let mut replies = self.cached_replies.lock().unwrap();
+        replies.principal = Some(principal_url.clone());
+        replies.principal_candidates = candidates;
+        Ok(principal_url)
+    }
 
-        return Ok(principal_url);
+    /// Return every principal of the current user, with what it is used for, or fetch them from server if not known yet.
+    ///
+    /// Most servers have a single principal for everything, but some have distinct ones (e.g. for calendars and for scheduling).
+    /// A principal that is used for several things is listed once for each of them
+    pub async fn get_principals(&self) -> Result<Vec<(PrincipalType, Url)>, KitchenFridgeError> {
+        if let Some(p) = &self.cached_replies.lock().unwrap().principals {
+            return Ok(p.clone());
+        }
+        self.get_principal().await?;
+        let candidates = self.cached_replies.lock().unwrap().principal_candidates.clone();
+
+        let mut principals = Vec::new();
+        for candidate in candidates {
+            let responses = propfind(&candidate, PRINCIPAL_TYPE_PROPERTIES, 0).await?;
+            let types = responses.first()
+                .map(PrincipalType::from_response)
+                .unwrap_or_else(|| vec![PrincipalType::Unknown]);
+            log::debug!("Principal {} is used for {:?}", candidate.url(), types);
+            principals.extend(types.into_iter().map(|t| (t, candidate.url().clone())));
+        }
+        self.cached_replies.lock().unwrap().principals = Some(principals.clone());
+        Ok(principals)
+    }
+
+    /// Return the principal that owns the calendars.
+    ///
+    /// This is the principal returned by [`Self::get_principal`], unless the server has returned several ones
+    async fn get_calendar_principal(&self) -> Result<Resource, KitchenFridgeError> {
+        let principal = self.get_principal().await?;
+        if self.cached_replies.lock().unwrap().principal_candidates.len() <= 1 {
+            return Ok(principal);
+        }
+        let principals = self.get_principals().await?;
+        match principals.iter().find(|(principal_type, _)| *principal_type == PrincipalType::Calendar) {
+            None => Ok(principal),
+            Some((_, url)) => Ok(self.resource.combine(url.as_str())?),
+        }
+    }
+
+    /// Return the display name of the principal (i.e. usually the user's name), or fetch it from server if not known yet.
+    ///
+    /// This returns `None` in case the server does not provide any display name for the principal
+    pub async fn get_principal_display_name(&self) -> Result<Option<String>, KitchenFridgeError> {
+        if let Some(name) = &self.cached_replies.lock().unwrap().principal_display_name {
+            return Ok(name.clone());
+        }
+        let principal_url = self.get_principal().await?;
+
+        let responses = propfind(&principal_url, &[(DAV_NS, "displayname")], 0).await?;
+        let display_name = responses.iter()
+            .find_map(|response| response.property(DAV_NS, "displayname"))
+            .map(|elem| elem.text())
+            .filter(|name| !name.is_empty());
+        self.cached_replies.lock().unwrap().principal_display_name = Some(display_name.clone());
+        log::debug!("Principal display name is {:?}", display_name);
+
+        Ok(display_name)
     }
 
     /// Return the Homeset URL, or fetch it from server if not known yet
-    async fn get_cal_home_set(&self) -> Result<Resource, Box<dyn Error>> {
+    async fn get_cal_home_set(&self) -> Result<Resource, KitchenFridgeError> {
         if let Some(h) = &self.cached_replies.lock().unwrap().calendar_home_set {
             return Ok(h.clone());
         }
-        let principal_url = self.get_principal().await?;
+        let principal_url = self.get_calendar_principal().await?;
 
-        let href = sub_request_and_extract_elem(&principal_url, HOMESET_BODY.into(), &["calendar-home-set", "href"]).await?;
-        let chs_url = self.resource.combine(&href);
+        let responses = propfind(&principal_url, &[(CALDAV_NS, "calendar-home-set")], 0).await?;
+        let href = property_href(&responses, CALDAV_NS, "calendar-home-set")?;
+        let chs_url = self.resource.combine(&href)?;
         self.cached_replies.lock().unwrap().calendar_home_set = Some(chs_url.clone());
         log::debug!("Calendar home set URL is {:?}", href);
 
         Ok(chs_url)
     }
 
-    async fn populate_calendars(&self) -> Result<(), Box<dyn Error>> {
-        let cal_home_set = self.get_cal_home_set().await?;
+    /// Send a PROPFIND for arbitrary properties (given as (namespace, name) pairs, e.g. `("http://nextcloud.com/ns", "calendar-enabled")`) to a URL of this server.
+    ///
+    /// This is an escape hatch to retrieve properties this crate does not handle. It uses the credentials of this client, and its transfers are accounted in [`Self::transfer_stats`]
+    pub async fn raw_propfind(&self, url: &Url, props: &[(&str, &str)], depth: u32) -> Result<Vec<RawResponse>, KitchenFridgeError> {
+        let resource = self.resource.combine(url.as_str())?;
+        propfind(&resource, props, depth).await
+    }
 
-        let reps = sub_request_and_extract_elems(&cal_home_set, "PROPFIND", CAL_BODY.to_string(), "response").await?;
-        let mut calendars = HashMap::new();
-        for rep in reps {
-            let display_name = find_elem(&rep, "displayname").map(|e| e.text()).unwrap_or("<no name>".to_string());
-            log::debug!("Considering calendar {}", display_name);
+    /// Send a REPORT with an arbitrary body to a URL of this server, and return the root element of the reply (usually a `<multistatus>`).
+    ///
+    /// Like [`Self::raw_propfind`], this is an escape hatch for what this crate does not handle
+    pub async fn raw_report(&self, url: &Url, body: &str) -> Result<Element, KitchenFridgeError> {
+        let resource = self.resource.combine(url.as_str())?;
+        let text = sub_request(&resource, "REPORT", body.to_string(), 1).await?;
+        parse_xml(&text)
+    }
 
-            // We filter out non-calendar items
-            let resource_types = match find_elem(&rep, "resourcetype") {
-                None => continue,
-                Some(rt) => rt,
-            };
-            let mut found_calendar_type = false;
-            for resource_type in resource_types.children() {
-                if resource_type.name() == "calendar" {
-                    found_calendar_type = true;
-                    break;
-                }
+    /// Returns a report of what happened during the last calendar discovery, if any has been run already
+    pub fn discovery_report(&self) -> Option<DiscoveryReport> {
+        self.cached_replies.lock().unwrap().discovery_report.clone()
+    }
+
+    /// Discover the calendars, unless they have been discovered less than [`crate::config::DISCOVERY_TTL`] ago.
+    ///
+    /// Concurrent callers share a single discovery: the ones that have to wait for an in-flight discovery use its result.
+    async fn populate_calendars(&self) -> Result<(), KitchenFridgeError> {
+        let generation = self.cached_replies.lock().unwrap().discovery_generation;
+        let _discovery = self.discovery_lock.lock().await;
+        {
+            let replies = self.cached_replies.lock().unwrap();
+            if replies.discovery_generation != generation {
+                log::debug!("Calendars have been discovered by a concurrent call");
+                return Ok(());
             }
-            if found_calendar_type == false {
-                continue;
+            let ttl = *crate::config::DISCOVERY_TTL.lock().unwrap();
+            if replies.calendars.is_some() && replies.discovered_at.is_some_and(|at| Utc::now() - at < ttl) {
+                return Ok(());
             }
+        }
 
-            // We filter out the root calendar collection, that has an empty supported-calendar-component-set
-            let el_supported_comps = match find_elem(&rep, "supported-calendar-component-set") {
-                None => continue,
-                Some(comps) => comps,
+        let cal_home_set = self.get_cal_home_set().await?;
+
+        let reps = propfind(&cal_home_set, CALENDAR_PROPERTIES, 1).await?;
+        let (calendars, report) = self.parse_calendar_responses(reps.into_iter().map(|rep| rep.element).collect());
+
+        let mut replies = self.cached_replies.lock().unwrap();
+        replies.calendars = Some(calendars);
+        replies.discovery_report = Some(report);
+        replies.discovered_at = Some(Utc::now());
+        replies.discovery_generation += 1;
+        Ok(())
+    }
+
+    /// How long ago the calendars have been discovered, or `None` if they have not been discovered yet
+    pub fn discovery_age(&self) -> Option<Duration> {
+        self.cached_replies.lock().unwrap().discovered_at.map(|at| Utc::now() - at)
+    }
+
+    /// Build calendars out of the `<response>`s of a PROPFIND on the calendar home set.
+    ///
+    /// Collections the server refused to describe (e.g. a share that has been revoked) are not fatal: they are listed in the returned report, and the other calendars are still returned.
+    fn parse_calendar_responses(&self, reps: Vec<Element>) -> (HashMap<Url, Arc<Mutex<RemoteCalendar>>>, DiscoveryReport) {
+        let mut calendars = HashMap::new();
+        let mut report = DiscoveryReport::default();
+        for rep in reps {
+            let mut entry = DiscoveredCollection {
+                href: find_elem(&rep, "href").map(|href| href.text()),
+                url: None,
+                display_name: find_elem(&rep, "displayname").map(|e| e.text()),
+                resource_types: find_multi_valued_property(&rep, "resourcetype", &[]).unwrap_or_default(),
+                components: find_elem(&rep, "supported-calendar-component-set")
+                    .map(|comps| comps.children().filter_map(|comp| comp.attr("name")).map(String::from).collect()),
+                skipped: None,
+                raw: None,
             };
-            if el_supported_comps.children().count() == 0 {
-                continue;
-            }
+            entry.url = entry.href.as_ref().and_then(|href| self.resource.combine(href).ok()).map(|resource| resource.url().clone());
 
-            let calendar_href = match find_elem(&rep, "href") {
-                None => {
-                    log::warn!("Calendar {} has no URL! Ignoring it.", display_name);
-                    continue;
+            match self.parse_calendar_response(&rep) {
+                Err(reason) => {
+                    if let SkipReason::Unavailable(status) = reason {
+                        if let Some(url) = &entry.url {
+                            report.unavailable_calendars.push(UnavailableCalendar{ url: url.clone(), status });
+                        }
+                    }
+                    entry.skipped = Some(reason);
                 },
-                Some(h) => h.text(),
-            };
+                Ok(this_calendar) => {
+                    log::info!("Found calendar {}", this_calendar.name());
+                    report.calendars.push(this_calendar.url().clone());
+                    calendars.insert(this_calendar.url().clone(), Arc::new(Mutex::new(this_calendar)));
+                },
+            }
+            if self.keep_raw_discovery_responses {
+                entry.raw = Some(rep);
+            }
+            report.responses.push(entry);
+        }
 
-            let this_calendar_url = self.resource.combine(&calendar_href);
+        (calendars, report)
+    }
 
-            let supported_components = match crate::calendar::SupportedComponents::try_from(el_supported_comps.clone()) {
-                Err(err) => {
-                    log::warn!("Calendar {} has invalid supported components ({})! Ignoring it.", display_name, err);
-                    continue;
+    /// Build a calendar out of a `<response>` of a PROPFIND on the calendar home set, or tell why it is not one
+    fn parse_calendar_response(&self, rep: &Element) -> Result<RemoteCalendar, SkipReason> {
+        let display_name = find_elem(rep, "displayname").map(|e| e.text()).unwrap_or("<no name>".to_string());
+        log::debug!("Considering calendar {}", display_name);
+
+        if let Some(status) = failed_response_status(rep) {
+            match find_elem(rep, "href") {
+                None => log::warn!("A collection could not be described by the server (HTTP {}), and has no URL. Ignoring it.", status),
+                Some(href) => match self.resource.combine(&href.text()) {
+                    Err(err) => log::warn!("A collection could not be described by the server (HTTP {}), and has an invalid URL ({}). Ignoring it.", status, err),
+                    Ok(resource) => log::warn!("Collection {} could not be described by the server (HTTP {}). Skipping it.", resource.url(), status),
                 },
-                Ok(sc) => sc,
-            };
+            }
+            return Err(SkipReason::Unavailable(status));
+        }
 
-            let this_calendar_color = find_elem(&rep, "calendar-color")
-                .and_then(|col| {
-                    col.texts().next()
-                        .and_then(|t| csscolorparser::parse(t).ok())
-                });
+        // We filter out non-calendar items
+        let resource_types = match find_multi_valued_property(rep, "resourcetype", &[]) {
+            None => return Err(SkipReason::NotACalendar),
+            Some(rt) => rt,
+        };
+        // Subscribed calendars mirror an iCal feed (see `crate::calendar::Subscription`)
+        let is_subscription = resource_types.iter().any(|rt| rt == "subscribed");
+        if !is_subscription && !resource_types.iter().any(|rt| rt == "calendar") {
+            return Err(SkipReason::NotACalendar);
+        }
 
-            let this_calendar = RemoteCalendar::new(display_name, this_calendar_url, supported_components, this_calendar_color);
-            log::info!("Found calendar {}", this_calendar.name());
-            calendars.insert(this_calendar.url().clone(), Arc::new(Mutex::new(this_calendar)));
+        // We filter out the root calendar collection, that has an empty supported-calendar-component-set
+        // (subscriptions usually do not advertise their components at all)
+        let el_supported_comps = find_elem(rep, "supported-calendar-component-set");
+        match el_supported_comps {
+            None if !is_subscription => return Err(SkipReason::NoComponents),
+            Some(comps) if comps.children().count() == 0 => return Err(SkipReason::NoComponents),
+            _ => (),
         }
 
-        let mut replies = self.cached_replies.lock().unwrap();
-        replies.calendars = Some(calendars);
-        Ok(())
+        let calendar_href = match find_elem(rep, "href") {
+            None => {
+                log::warn!("Calendar {} has no URL! Ignoring it.", display_name);
+                return Err(SkipReason::NoUrl);
+            },
+            Some(h) => h.text(),
+        };
+
+        let this_calendar_url = match self.resource.combine(&calendar_href) {
+            Err(err) => {
+                log::warn!("Calendar {} has an invalid URL ({})! Ignoring it.", display_name, err);
+                return Err(SkipReason::InvalidUrl(err.to_string()));
+            },
+            Ok(resource) => resource,
+        };
+
+        let components = match el_supported_comps.map(|comps| ComponentSet::try_from(comps.clone())) {
+            None => ComponentSet::from(SupportedComponents::all()),
+            Some(Err(err)) => {
+                log::warn!("Calendar {} has invalid supported components ({})! Ignoring it.", display_name, err);
+                return Err(SkipReason::InvalidComponents(err.to_string()));
+            },
+            Some(Ok(components)) => components,
+        };
+
+        let subscription = match is_subscription {
+            false => None,
+            true => {
+                let source = find_elem(rep, "source")
+                    .and_then(|source| find_elem(source, "href"))
+                    .and_then(|href| crate::calendar::Subscription::parse_source(&href.text()));
+                match source {
+                    None => {
+                        log::warn!("Subscribed calendar {} has no valid source! Ignoring it.", display_name);
+                        return Err(SkipReason::NoSubscriptionSource);
+                    },
+                    Some(source) => Some(crate::calendar::Subscription {
+                        source,
+                        refresh_interval: find_elem(rep, "refreshrate").and_then(|rate| crate::datetime::parse_duration(&rate.text())),
+                    }),
+                }
+            },
+        };
+
+        let this_calendar_color = find_elem(rep, "calendar-color")
+            .and_then(|col| {
+                col.texts().next()
+                    .and_then(|t| csscolorparser::parse(t).ok())
+            });
+
+        let privileges = find_multi_valued_property(rep, "current-user-privilege-set", &["privilege"]);
+        let writable = subscription.is_none() && privileges.as_deref()
+            .map(crate::calendar::privileges_allow_writing)
+            .unwrap_or(true);
+        let supported_reports = find_multi_valued_property(rep, "supported-report-set", &["supported-report", "report"])
+            .unwrap_or_default();
+
+        let date_limits = crate::calendar::DateLimits {
+            min: find_elem(rep, "min-date-time").and_then(|el| crate::calendar::DateLimits::parse_limit(&el.text())),
+            max: find_elem(rep, "max-date-time").and_then(|el| crate::calendar::DateLimits::parse_limit(&el.text())),
+        };
+
+        let data_formats = find_elem(rep, "supported-calendar-data")
+            .map(crate::calendar::CalendarDataFormat::parse_supported)
+            .filter(|formats| !formats.is_empty())
+            .unwrap_or_else(|| vec![crate::calendar::CalendarDataFormat::ICalendar]);
+        let max_resource_size = find_elem(rep, "max-resource-size").and_then(|el| el.text().trim().parse().ok());
+        let enabled = find_elem(rep, "calendar-enabled").and_then(|el| parse_enabled_flag(&el.text()));
+
+        Ok(RemoteCalendar::new(display_name, this_calendar_url, components.known, this_calendar_color)
+            .with_other_components(components.other)
+            .with_writable(writable)
+            .with_privileges(privileges)
+            .with_supported_reports(supported_reports)
+            .with_date_limits(date_limits)
+            .with_subscription(subscription)
+            .with_data_formats(data_formats)
+            .with_max_resource_size(max_resource_size)
+            .with_enabled(enabled))
     }
 
+
 }
 
 #[async_trait]
@@ -238,6 +1204,18 @@ impl CalDavSource<RemoteCalendar> for Client {
         };
     }
 
+    fn unavailable_calendars(&self) -> HashSet<Url> {
+        self.cached_replies.lock().unwrap()
+            .discovery_report
+            .as_ref()
+            .map(|report| report.unavailable_calendars.iter().map(|cal| cal.url.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn transfer_counter(&self) -> Option<TransferCounter> {
+        Some(self.resource.transfer_counter().clone())
+    }
+
     async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<RemoteCalendar>>> {
         if let Err(err) = self.populate_calendars().await {
             log::warn!("Unable to fetch calendars: {}", err);
@@ -252,6 +1230,10 @@ impl CalDavSource<RemoteCalendar> for Client {
     }
 
     async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<Mutex<RemoteCalendar>>, Box<dyn Error>> {
+        self.create_calendar_with_components(url, name, ComponentSet::from(supported_components), color).await
+    }
+
+    async fn create_calendar_with_components(&mut self, url: Url, name: String, components: ComponentSet, color: Option<Color>) -> Result<Arc<Mutex<RemoteCalendar>>, Box<dyn Error>> {
         self.populate_calendars().await?;
 
         match self.cached_replies.lock().unwrap().calendars.as_ref() {
@@ -263,26 +1245,79 @@ impl CalDavSource<RemoteCalendar> for Client {
             },
         }
 
-        let creation_body = calendar_body(name, supported_components, color);
+        let creation_body = calendar_body(name, &components, color);
+        let bytes_sent = creation_body.len() as u64;
 
-        let response = reqwest::Client::new()
+        let request = self.resource.http_client()
             .request(Method::from_bytes(b"MKCALENDAR").unwrap(), url.clone())
-            .header(CONTENT_TYPE, "application/xml")
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .body(creation_body)
-            .send()
-            .await?;
+            .header(CONTENT_TYPE, xml_content_type())
+            .body(creation_body);
+        let response = self.resource.send(request).await?;
 
-        let status = response.status();
+        let status = match self.resource.combine(url.as_str()) {
+            Ok(cal_resource) => cal_resource.accounted_to_calendar().read_reply(response, "MKCALENDAR", bytes_sent, max_response_size()).await?.status,
+            Err(_) => response.status(),
+        };
         if status != StatusCode::CREATED {
             return Err(format!("Unexpected HTTP status code. Expected CREATED, got {}", status.as_u16()).into());
         }
 
+        // The new calendar is not part of the last discovery
+        self.cached_replies.lock().unwrap().discovered_at = None;
         self.get_calendar(&url).await.ok_or(format!("Unable to insert calendar {:?}", url).into())
     }
 }
 
-fn calendar_body(name: String, supported_components: SupportedComponents, color: Option<Color>) -> String {
+impl Client {
+    /// Returns the calendar matching the URL, or an error in case the server has none
+    async fn existing_calendar(&self, url: &Url) -> Result<Arc<Mutex<RemoteCalendar>>, Box<dyn Error>> {
+        self.get_calendar(url).await.ok_or_else(|| format!("No calendar {} on the server", url).into())
+    }
+}
+
+#[async_trait(?Send)]
+#[allow(clippy::await_holding_lock)]
+impl SourceAdapter<RemoteCalendar> for Client {
+    async fn list_item_stubs(&self, calendar: &Url) -> Result<HashMap<Url, SyncStatus>, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar).await?;
+        let version_tags = cal.lock().unwrap().get_item_version_tags().await?;
+        Ok(version_tags.into_iter().map(|(url, tag)| (url, SyncStatus::Synced(tag))).collect())
+    }
+
+    async fn fetch_items(&self, calendar: &Url, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar).await?;
+        let items = cal.lock().unwrap().get_items_by_url(urls).await;
+        items
+    }
+
+    /// Items that have never been synced are created on the server, other ones replace the version they are based on (see [`BaseCalendar::update_item`])
+    async fn upsert_item(&mut self, calendar: &Url, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar).await?;
+        let mut cal = cal.lock().unwrap();
+        match item.sync_status() {
+            SyncStatus::NotSynced => cal.add_item(item).await,
+            _ => cal.update_item(item).await,
+        }
+    }
+
+    async fn delete_item(&mut self, calendar: &Url, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar).await?;
+        let result = cal.lock().unwrap().delete_item(item_url).await;
+        result
+    }
+
+    async fn sync_metadata(&self, calendar: &Url) -> Result<SyncMetadata, Box<dyn Error>> {
+        let cal = self.existing_calendar(calendar).await?;
+        let ctag = cal.lock().unwrap().get_ctag().await?;
+        Ok(SyncMetadata { ctag, sync_token: None })
+    }
+
+    async fn set_sync_metadata(&mut self, _calendar: &Url, _metadata: SyncMetadata) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+fn calendar_body(name: String, components: &ComponentSet, color: Option<Color>) -> String {
     let color_property = match color {
         None => "".to_string(),
         Some(color) => format!("<D:calendar-color xmlns:D=\"http://apple.com/ns/ical/\">{}FF</D:calendar-color>", color.to_hex_string().to_ascii_uppercase()),
@@ -302,6 +1337,688 @@ fn calendar_body(name: String, supported_components: SupportedComponents, color:
         "#,
         name,
         color_property,
-        supported_components.to_xml_string(),
+        components.to_xml_string(),
     )
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME_SET_WITH_A_REVOKED_SHARE: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/dav/calendars/john/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:resourcetype><d:collection/></d:resourcetype>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/calendars/john/tasks/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:displayname>Tasks</d:displayname>
+        <d:resourcetype><d:collection/><cal:calendar/></d:resourcetype>
+        <cal:supported-calendar-component-set><cal:comp name="VTODO"/></cal:supported-calendar-component-set>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop>
+        <x1:calendar-color xmlns:x1="http://apple.com/ns/ical/"/>
+      </d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/calendars/john/revoked-share/</d:href>
+    <d:status>HTTP/1.1 403 Forbidden</d:status>
+  </d:response>
+  <d:response>
+    <d:href>/dav/calendars/john/other-revoked-share/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:displayname/>
+        <d:resourcetype/>
+      </d:prop>
+      <d:status>HTTP/1.1 403 Forbidden</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>
+"#;
+
+    #[test]
+    fn test_calendar_body() {
+        let components = ComponentSet { known: SupportedComponents::TODO, other: vec!["VJOURNAL".to_string()] };
+        let body = calendar_body("Tasks".to_string(), &components, None);
+        assert!(body.contains(r#"<comp name="VTODO"/><comp name="VJOURNAL"/>"#));
+    }
+
+    #[test]
+    fn test_request_id() {
+        let request = reqwest::Client::new().get("https://my.server.com/dav/");
+        let (request, request_id) = with_request_id(request, None);
+        assert_eq!(request_id, None);
+        assert!(request.build().unwrap().headers().is_empty());
+
+        let request = reqwest::Client::new().get("https://my.server.com/dav/");
+        let (request, request_id) = with_request_id(request, Some("X-Request-Id"));
+        let request = request.build().unwrap();
+        assert_eq!(request.headers()["x-request-id"].to_str().unwrap(), request_id.unwrap());
+
+        let (_, other_id) = with_request_id(reqwest::Client::new().get("https://my.server.com/dav/"), Some("X-Request-Id"));
+        assert_ne!(other_id, Some(request.headers()["x-request-id"].to_str().unwrap().to_string()));
+    }
+
+    /// A server that replies to the requests for the given paths with the given status lines and headers (and bodies, after an empty line).
+    /// In case a path is given several replies, they are sent in turn, and the last one is repeated
+    fn scripted_server(replies: &'static [(&'static str, &'static str)]) -> Url {
+        serve(replies, None, Arc::default())
+    }
+
+    /// Same as [`scripted_server`], but the requests are counted
+    fn counting_server(replies: &'static [(&'static str, &'static str)]) -> (Url, Arc<std::sync::atomic::AtomicUsize>) {
+        let counter = Arc::default();
+        (serve(replies, None, Arc::clone(&counter)), counter)
+    }
+
+    /// Same as [`scripted_server`], but requests that do not have the given `Authorization` header are refused
+    fn authenticated_server(replies: &'static [(&'static str, &'static str)], authorization: &'static str) -> Url {
+        serve(replies, Some(authorization), Arc::default())
+    }
+
+    fn serve(replies: &'static [(&'static str, &'static str)], authorization: Option<&'static str>, counter: Arc<std::sync::atomic::AtomicUsize>) -> Url {
+        let mut hits: HashMap<String, usize> = HashMap::new();
+        crate::test_server::serve("/", move |request| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if authorization.is_some() && request.header("authorization") != authorization {
+                return Some("401 Unauthorized".to_string());
+            }
+            let candidates: Vec<&str> = replies.iter().filter(|(p, _)| *p == request.path).map(|(_, reply)| *reply).collect();
+            let hit = hits.entry(request.path.clone()).or_default();
+            *hit += 1;
+            Some(candidates.get(*hit - 1).or(candidates.last()).copied().unwrap_or("404 Not Found").to_string())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_loops_are_detected() {
+        let base = scripted_server(&[
+            ("/dav/", "307 Temporary Redirect\r\nLocation: /proxy/dav/"),
+            ("/proxy/dav/", "307 Temporary Redirect\r\nLocation: /dav/"),
+            ("/broken/", "508 Loop Detected"),
+        ]);
+        let resource = Resource::new(base.join("/dav/").unwrap(), "user".to_string(), "pass".to_string());
+
+        let err = match sub_request(&resource, "PROPFIND", propfind_body(&[(DAV_NS, "displayname")]), 0).await {
+            Err(KitchenFridgeError::LoopDetected(err)) => err,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(err.url, base.join("/dav/").unwrap());
+        assert_eq!(err.redirects, vec![base.join("/proxy/dav/").unwrap(), base.join("/dav/").unwrap()]);
+        assert!(err.to_string().contains("redirected in a loop"));
+
+        let resource = resource.combine("/broken/").unwrap();
+        let err = match sub_request(&resource, "PROPFIND", propfind_body(&[(DAV_NS, "displayname")]), 0).await {
+            Err(KitchenFridgeError::LoopDetected(err)) => err,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(*err, LoopDetectedError { url: base.join("/broken/").unwrap(), redirects: Vec::new() });
+        assert!(err.to_string().contains("508 Loop Detected"));
+    }
+
+    #[tokio::test]
+    async fn test_response_size_limit() {
+        let base = scripted_server(&[
+            ("/big/", "200 OK\r\nContent-Type: text/calendar\r\n\r\nBEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n"),
+        ]);
+        let url = base.join("/big/").unwrap();
+        let get = || reqwest::Client::new().get(url.clone()).send();
+
+        let err = read_body(get().await.unwrap(), 30, "GET /big/").await.unwrap_err();
+        let err = err.downcast_ref::<ResponseTooLarge>().unwrap();
+        assert_eq!(err, &ResponseTooLarge { request: "GET /big/".to_string(), limit: 30 });
+        assert!(err.to_string().contains("GET /big/"));
+        assert_eq!(read_body(get().await.unwrap(), 32, "GET /big/").await.unwrap(), b"BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_credentials_rotation() {
+        const REPLIES: &[(&str, &str)] = &[
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/principals/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/principals/john/</d:href><d:propstat><d:prop><cal:calendar-home-set><d:href>/dav/calendars/john/</d:href></cal:calendar-home-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/calendars/john/tasks/</d:href><d:propstat><d:prop><d:displayname>Tasks</d:displayname><d:resourcetype><d:collection/><cal:calendar/></d:resourcetype><cal:supported-calendar-component-set><cal:comp name=\"VTODO\"/></cal:supported-calendar-component-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/tasks/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"/>"),
+        ];
+        // The first refused request is only replied to once the credentials have been rotated, so that they are rotated while it is in flight
+        let (refused_tx, refused_rx) = std::sync::mpsc::channel();
+        let (rotated_tx, rotated_rx) = std::sync::mpsc::channel();
+        let mut handshake = Some((refused_tx, rotated_rx));
+        let base = crate::test_server::serve("/", move |request| {
+            if request.header("authorization") != Some("Basic am9objpuZXctcGFzc3dvcmQ=") {
+                if let Some((refused, rotated)) = handshake.take() {
+                    refused.send(()).unwrap();
+                    rotated.recv().unwrap();
+                }
+                return Some("401 Unauthorized".to_string());
+            }
+            let reply = REPLIES.iter().find(|(path, _)| *path == request.path).map_or("404 Not Found", |(_, reply)| *reply);
+            Some(reply.to_string())
+        });
+        let url = base.join("/dav/").unwrap();
+
+        // A sync starts with stale credentials, that are rotated while its first request is in flight
+        let client = Client::new(url.as_str(), "john", "old-password").unwrap();
+        let handle = Client { resource: client.resource.clone(), cached_replies: Mutex::default(), discovery_lock: tokio::sync::Mutex::default(), keep_raw_discovery_responses: false };
+        let rotation = tokio::task::spawn_blocking(move || {
+            refused_rx.recv().unwrap();
+            handle.set_credentials(Credentials::new("john", "new-password"));
+            rotated_tx.send(()).unwrap();
+        });
+        let cache = crate::cache::Cache::new(&std::path::PathBuf::from("test_cache/credentials_rotation_test"));
+        let mut provider = crate::provider::Provider::new(client, cache);
+        assert!(provider.sync().await);
+        rotation.await.unwrap();
+        let calendar_url = base.join("/dav/calendars/john/tasks/").unwrap();
+        assert!(provider.local().get_calendar_sync(&calendar_url).is_some());
+        assert_eq!(provider.remote().resource.password(), "new-password");
+
+        // Credentials that are never rotated are refused for good
+        let stale_client = Client::new(url.as_str(), "john", "old-password").unwrap();
+        assert!(stale_client.get_calendars().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token() {
+        let base = authenticated_server(&[
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/principals/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/principals/john/</d:href><d:propstat><d:prop><cal:calendar-home-set><d:href>/dav/calendars/john/</d:href></cal:calendar-home-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/calendars/john/tasks/</d:href><d:propstat><d:prop><d:displayname>Tasks</d:displayname><d:resourcetype><d:collection/><cal:calendar/></d:resourcetype><cal:supported-calendar-component-set><cal:comp name=\"VTODO\"/></cal:supported-calendar-component-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+        ], "Bearer new-token");
+        let url = base.join("/dav/").unwrap();
+
+        let client = Client::new_with_oauth(url.as_str(), "expired-token").unwrap();
+        let err = client.get_calendars().await.unwrap_err();
+        assert!(matches!(KitchenFridgeError::of(&*err), Some(KitchenFridgeError::Auth(_))));
+
+        client.set_token("new-token");
+        assert_eq!(client.get_calendars().await.unwrap().len(), 1);
+        assert_eq!(client.resource.username(), "");
+    }
+
+    /// Returns an expired token the first time, and a fresh one afterwards
+    #[derive(Default)]
+    struct RefreshingProvider {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CredentialsProvider for RefreshingProvider {
+        async fn get_credentials(&self) -> Result<Authentication, Box<dyn Error + Send + Sync>> {
+            match self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Ok(Authentication::Bearer("expired-token".to_string())),
+                _ => Ok(Authentication::Bearer("new-token".to_string())),
+            }
+        }
+    }
+
+    struct FailingProvider;
+
+    #[async_trait]
+    impl CredentialsProvider for FailingProvider {
+        async fn get_credentials(&self) -> Result<Authentication, Box<dyn Error + Send + Sync>> {
+            Err("the refresh token has been revoked".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_credentials_provider() {
+        let base = authenticated_server(&[
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/principals/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/principals/john/</d:href><d:propstat><d:prop><cal:calendar-home-set><d:href>/dav/calendars/john/</d:href></cal:calendar-home-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/calendars/john/tasks/</d:href><d:propstat><d:prop><d:displayname>Tasks</d:displayname><d:resourcetype><d:collection/><cal:calendar/></d:resourcetype><cal:supported-calendar-component-set><cal:comp name=\"VTODO\"/></cal:supported-calendar-component-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+        ], "Bearer new-token");
+        let url = base.join("/dav/").unwrap();
+
+        // The first request is refused, and sent again once the provider has refreshed the token
+        let provider = Arc::new(RefreshingProvider::default());
+        let client = Client::new_with_credentials_provider(url.as_str(), provider.clone()).unwrap();
+        assert_eq!(client.get_calendars().await.unwrap().len(), 1);
+        // Once before each of the 3 requests, and once more after the first one has been refused
+        assert_eq!(provider.calls.load(std::sync::atomic::Ordering::SeqCst), 4);
+
+        let client = Client::new_with_credentials_provider(url.as_str(), Arc::new(FailingProvider)).unwrap();
+        let err = client.get_calendars().await.unwrap_err();
+        assert_eq!(error_code::code_of(&*err), Some(error_code::CREDENTIALS_UNAVAILABLE));
+        assert!(err.to_string().contains("the refresh token has been revoked"));
+    }
+
+    /// A server that accepts connections, but never replies
+    fn silent_server() -> Url {
+        crate::test_server::serve("/dav/", |_| None)
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let client = Client::new(silent_server().as_str(), "user", "pass").unwrap();
+        client.set_timeout(Some(std::time::Duration::from_millis(200)));
+        client.set_retry_policy(RetryPolicy::disabled());
+        let err = client.get_calendars().await.unwrap_err();
+        let err = KitchenFridgeError::of(&*err).unwrap();
+        assert!(matches!(err, KitchenFridgeError::Timeout(_)));
+        assert!(err.is_timeout());
+        assert_eq!(error_code::code_of(err), Some(error_code::TIMEOUT));
+        assert!(crate::provider::health::is_unreachable(err));
+
+        let client = Client::builder(silent_server(), "user", "pass")
+            .connect_timeout(std::time::Duration::from_millis(200))
+            .request_timeout(Some(std::time::Duration::from_millis(200)))
+            .retry_policy(RetryPolicy { initial_backoff: std::time::Duration::from_millis(10), ..RetryPolicy::default() })
+            .build().unwrap();
+        let started = std::time::Instant::now();
+        let err = client.get_calendars().await.unwrap_err();
+        assert!(KitchenFridgeError::of(&*err).unwrap().is_timeout());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        use std::sync::atomic::Ordering;
+
+        let (base, requests) = counting_server(&[("/busy/", "503 Service Unavailable")]);
+        let client = Client::builder(base.join("/busy/").unwrap(), "user", "pass")
+            .retry_policy(RetryPolicy { initial_backoff: std::time::Duration::from_millis(10), ..RetryPolicy::default() })
+            .build().unwrap();
+        let resource = &client.resource;
+
+        let err = sub_request(resource, "PROPFIND", propfind_body(&[(DAV_NS, "displayname")]), 0).await.unwrap_err();
+        assert!(matches!(err, KitchenFridgeError::Status(err) if err.status == StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+
+        // Uploads are not retried, unless a second attempt cannot overwrite anything
+        let response = resource.send(resource.http_client().put(resource.url().clone()).body("data")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 1);
+        resource.send(resource.http_client().put(resource.url().clone()).header(reqwest::header::IF_MATCH, "\"etag\"").body("data")).await.unwrap();
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+
+        client.set_retry_policy(RetryPolicy::disabled());
+        sub_request(resource, "PROPFIND", propfind_body(&[(DAV_NS, "displayname")]), 0).await.unwrap_err();
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        use std::sync::atomic::Ordering;
+
+        let (base, requests) = counting_server(&[
+            ("/throttled/", "429 Too Many Requests\r\nRetry-After: 0"),
+            ("/throttled/", "429 Too Many Requests"),
+            ("/throttled/", "200 OK\r\n\r\nhello"),
+            ("/busy/", "503 Service Unavailable\r\nRetry-After: 3600"),
+        ]);
+        let client = Client::builder(base.join("/throttled/").unwrap(), "user", "pass")
+            .retry_policy(RetryPolicy { initial_backoff: std::time::Duration::from_millis(10), ..RetryPolicy::default() })
+            .build().unwrap();
+        let resource = &client.resource;
+
+        // Even requests that are not idempotent are retried, since the server has not handled them
+        let response = resource.send(resource.http_client().post(base.join("/throttled/").unwrap()).body("data")).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "hello");
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+
+        // Waiting longer than allowed is left to the application
+        let err = resource.send(resource.http_client().get(base.join("/busy/").unwrap())).await.unwrap_err();
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 1);
+        assert!(matches!(&err, KitchenFridgeError::Throttled(throttled) if throttled.retry_after == std::time::Duration::from_secs(3600)));
+        assert_eq!(err.code(), Some(error_code::THROTTLED));
+    }
+
+    #[tokio::test]
+    async fn test_locked_items_are_retried() {
+        use std::sync::atomic::Ordering;
+
+        // The item is locked for the first two attempts
+        let (base, requests) = counting_server(&[
+            ("/cal/item.ics", "423 Locked"),
+            ("/cal/item.ics", "423 Locked\r\nContent-Type: application/xml\r\n\r\n<d:error xmlns:d=\"DAV:\"><d:lock-token-submitted><d:href>/cal/item.ics</d:href></d:lock-token-submitted></d:error>"),
+            ("/cal/item.ics", "201 Created\r\nETag: \"new\""),
+            ("/cal/other.ics", "423 Locked"),
+        ]);
+        let client = Client::builder(base.join("/cal/").unwrap(), "user", "pass")
+            .retry_policy(RetryPolicy { initial_backoff: std::time::Duration::from_millis(10), ..RetryPolicy::default() })
+            .build().unwrap();
+        let resource = &client.resource;
+
+        // Even uploads that have no precondition are retried, since a locked item has not been overwritten
+        let response = resource.send(resource.http_client().put(base.join("/cal/item.ics").unwrap()).body("data")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+
+        let response = resource.send(resource.http_client().delete(base.join("/cal/other.ics").unwrap())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::LOCKED);
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+        let err = HttpError::from_reply(response.status(), &response.text().await.unwrap());
+        assert!(err.is_locked());
+        assert_eq!(err.code(), error_code::LOCKED);
+    }
+
+    #[tokio::test]
+    async fn test_discover() {
+        let base = scripted_server(&[
+            ("/.well-known/caldav", "301 Moved Permanently\r\nLocation: /dav/"),
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+        ]);
+        let client = Client::discover(base.as_str(), "john", "password").await.unwrap();
+        assert_eq!(client.resource.url(), &base.join("/dav/").unwrap());
+        assert_eq!(client.get_principal().await.unwrap().url(), &base.join("/dav/principals/john/").unwrap());
+
+        // Servers that do not know the well-known path are given a full URL
+        let base = scripted_server(&[
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+        ]);
+        let client = Client::discover(base.join("/dav/").unwrap().as_str(), "john", "password").await.unwrap();
+        assert_eq!(client.resource.url(), &base.join("/dav/").unwrap());
+
+        // Neither is a DAV root here
+        let base = scripted_server(&[("/.well-known/caldav", "301 Moved Permanently\r\nLocation: /nowhere/")]);
+        assert!(Client::discover(base.as_str(), "john", "password").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_discoveries() {
+        let (base, requests) = counting_server(&[
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/principals/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/principals/john/</d:href><d:propstat><d:prop><cal:calendar-home-set><d:href>/dav/calendars/john/</d:href></cal:calendar-home-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/calendars/john/tasks/</d:href><d:propstat><d:prop><d:displayname>Tasks</d:displayname><d:resourcetype><d:collection/><cal:calendar/></d:resourcetype><cal:supported-calendar-component-set><cal:comp name=\"VTODO\"/></cal:supported-calendar-component-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+        ]);
+        let client = Client::new(base.join("/dav/").unwrap().as_str(), "john", "password").unwrap();
+        assert_eq!(client.discovery_age(), None);
+
+        let calls = (0..10).map(|_| client.get_calendars());
+        for calendars in futures_util::future::join_all(calls).await {
+            assert_eq!(calendars.unwrap().len(), 1);
+        }
+        // One request for the principal, one for the calendar home set, one for the calendars
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert!(client.discovery_age().unwrap() >= Duration::zero());
+    }
+
+    #[tokio::test]
+    async fn test_customized_http_client() {
+        let base = scripted_server(&[
+            ("/dav/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/</d:href><d:propstat><d:prop><d:current-user-principal><d:href>/dav/principals/john/</d:href></d:current-user-principal></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/principals/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/principals/john/</d:href><d:propstat><d:prop><cal:calendar-home-set><d:href>/dav/calendars/john/</d:href></cal:calendar-home-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\" xmlns:cal=\"urn:ietf:params:xml:ns:caldav\"><d:response><d:href>/dav/calendars/john/tasks/</d:href><d:propstat><d:prop><d:displayname>Tasks</d:displayname><d:resourcetype><d:collection/><cal:calendar/></d:resourcetype><cal:supported-calendar-component-set><cal:comp name=\"VTODO\"/></cal:supported-calendar-component-set></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/loop/", "307 Temporary Redirect\r\nLocation: /loop/"),
+        ]);
+        let client = Client::builder(base.join("/dav/").unwrap(), "john", "password")
+            .customize_http(|builder| builder
+                .local_address(Some(std::net::IpAddr::from([127, 0, 0, 1])))
+                // This would break the detection of redirect loops, so this is overridden
+                .redirect(reqwest::redirect::Policy::none()))
+            .build().unwrap();
+        let calendars = client.get_calendars().await.unwrap();
+        assert_eq!(calendars.keys().collect::<Vec<_>>(), vec![&base.join("/dav/calendars/john/tasks/").unwrap()]);
+
+        let looping = client.resource.combine("/loop/").unwrap();
+        let err = sub_request(&looping, "PROPFIND", propfind_body(&[(DAV_NS, "displayname")]), 0).await.unwrap_err();
+        assert!(matches!(err, KitchenFridgeError::LoopDetected(_)));
+    }
+
+    #[tokio::test]
+    async fn test_set_enabled() {
+        let base = scripted_server(&[
+            ("/dav/calendars/john/tasks/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/calendars/john/tasks/</d:href><d:propstat><d:prop><oc:calendar-enabled xmlns:oc=\"http://owncloud.org/ns\"/></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"),
+            ("/dav/calendars/john/shared/", "207 Multi-Status\r\nContent-Type: application/xml\r\n\r\n<d:multistatus xmlns:d=\"DAV:\"><d:response><d:href>/dav/calendars/john/shared/</d:href><d:propstat><d:prop><oc:calendar-enabled xmlns:oc=\"http://owncloud.org/ns\"/></d:prop><d:status>HTTP/1.1 403 Forbidden</d:status></d:propstat></d:response></d:multistatus>"),
+        ]);
+        let calendar = |path: &str| {
+            let resource = Resource::new(base.join(path).unwrap(), "user".to_string(), "pass".to_string());
+            <RemoteCalendar as DavCalendar>::new("Calendar".to_string(), resource, crate::calendar::SupportedComponents::TODO, None)
+        };
+
+        let mut tasks = calendar("/dav/calendars/john/tasks/");
+        assert_eq!(tasks.enabled(), None);
+        tasks.set_enabled(false).await.unwrap();
+        assert_eq!(tasks.enabled(), Some(false));
+
+        let mut shared = calendar("/dav/calendars/john/shared/").with_enabled(Some(true));
+        let err = shared.set_enabled(false).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<HttpError>().unwrap().status, StatusCode::FORBIDDEN);
+        assert_eq!(shared.enabled(), Some(true));
+    }
+
+    #[test]
+    fn test_client_builder() {
+        let client = Client::builder("https://my.server.com/dav/", "user", "pass").build().unwrap();
+        assert_eq!(client.resource.url().as_str(), "https://my.server.com/dav/");
+        assert!(Client::builder("not a URL", "user", "pass").build().is_err());
+
+        let bundled = Client::builder("https://my.server.com/dav/", "user", "pass").use_bundled_roots(true).build();
+        #[cfg(not(feature = "rustls-tls-webpki-roots"))]
+        assert!(matches!(bundled.unwrap_err().downcast_ref::<TlsSetupError>(), Some(TlsSetupError::BundledRootsUnavailable)));
+        #[cfg(feature = "rustls-tls-webpki-roots")]
+        assert!(bundled.is_ok());
+
+        assert!(TlsSetupError::NoTrustStore.to_string().contains("ClientBuilder::use_bundled_roots"));
+    }
+
+    #[test]
+    fn test_raw_responses() {
+        let body = propfind_body(&[(DAV_NS, "displayname"), ("http://nextcloud.com/ns", "calendar-enabled")]);
+        let body: Element = body.parse().unwrap();
+        let requested: Vec<(String, String)> = find_elem(&body, "prop").unwrap().children()
+            .map(|el| (el.ns(), el.name().to_string()))
+            .collect();
+        assert_eq!(requested, vec![
+            (DAV_NS.to_string(), "displayname".to_string()),
+            ("http://nextcloud.com/ns".to_string(), "calendar-enabled".to_string()),
+        ]);
+
+        let base = Resource::new("https://my.server.com/dav/".parse().unwrap(), "user".to_string(), "pass".to_string());
+        let root: Element = HOME_SET_WITH_A_REVOKED_SHARE.parse().unwrap();
+        let responses = RawResponse::parse_multistatus(&root, &base);
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[1].href.as_str(), "https://my.server.com/dav/calendars/john/tasks/");
+        assert_eq!(responses[1].status, None);
+        assert_eq!(responses[1].property(DAV_NS, "displayname").unwrap().text(), "Tasks");
+        assert!(responses[1].property(CALDAV_NS, "displayname").is_none());
+        assert!(responses[1].property(APPLE_ICAL_NS, "calendar-color").is_none());
+        assert!(responses[1].properties.iter().any(|prop| prop.name == "calendar-color" && prop.status == StatusCode::NOT_FOUND));
+        assert_eq!(responses[2].status, Some(StatusCode::FORBIDDEN));
+        let revoked = &responses[3];
+        assert_eq!(revoked.properties.len(), 2);
+        assert!(revoked.properties.iter().all(|prop| prop.status == StatusCode::FORBIDDEN));
+        assert!(revoked.property(DAV_NS, "displayname").is_none());
+    }
+
+    #[test]
+    fn test_principal_types() {
+        let base = Resource::new("https://my.server.com/dav/".parse().unwrap(), "user".to_string(), "pass".to_string());
+        let root: Element = r#"<d:multistatus xmlns:d="DAV:">
+              <d:response>
+                <d:href>/dav/</d:href>
+                <d:propstat>
+                  <d:prop><d:current-user-principal><d:href>/principals/john/</d:href><d:href>/scheduling/john/</d:href></d:current-user-principal></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#.parse().unwrap();
+        let responses = RawResponse::parse_multistatus(&root, &base);
+        assert_eq!(property_hrefs(&responses, DAV_NS, "current-user-principal"), vec!["/principals/john/", "/scheduling/john/"]);
+        assert_eq!(property_href(&responses, DAV_NS, "current-user-principal").unwrap(), "/principals/john/");
+        assert!(property_href(&responses, CALDAV_NS, "calendar-home-set").is_err());
+
+        let root: Element = r#"<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav" xmlns:card="urn:ietf:params:xml:ns:carddav">
+              <d:response>
+                <d:href>/principals/john/</d:href>
+                <d:propstat>
+                  <d:prop>
+                    <c:calendar-home-set><d:href>/calendars/john/</d:href></c:calendar-home-set>
+                    <card:addressbook-home-set><d:href>/addressbooks/john/</d:href></card:addressbook-home-set>
+                  </d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+                <d:propstat>
+                  <d:prop><c:schedule-inbox-URL/></d:prop>
+                  <d:status>HTTP/1.1 404 Not Found</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#.parse().unwrap();
+        let responses = RawResponse::parse_multistatus(&root, &base);
+        assert_eq!(PrincipalType::from_response(&responses[0]), vec![PrincipalType::Calendar, PrincipalType::Addressbook]);
+
+        let root: Element = r#"<d:multistatus xmlns:d="DAV:">
+              <d:response><d:href>/scheduling/john/</d:href></d:response>
+            </d:multistatus>"#.parse().unwrap();
+        let responses = RawResponse::parse_multistatus(&root, &base);
+        assert_eq!(PrincipalType::from_response(&responses[0]), vec![PrincipalType::Unknown]);
+    }
+
+    #[test]
+    fn test_discovery_skips_forbidden_calendars() {
+        let client = Client::new("https://my.server.com/dav/", "user", "pass").unwrap();
+        let root: Element = HOME_SET_WITH_A_REVOKED_SHARE.parse().unwrap();
+        let reps = find_elems(&root, "response").into_iter().cloned().collect();
+
+        let (calendars, report) = client.parse_calendar_responses(reps);
+
+        let tasks_url: Url = "https://my.server.com/dav/calendars/john/tasks/".parse().unwrap();
+        assert_eq!(calendars.len(), 1);
+        assert!(calendars.contains_key(&tasks_url));
+        assert_eq!(report.calendars, vec![tasks_url.clone()]);
+
+        let unavailable: Vec<(String, StatusCode)> = report.unavailable_calendars.iter()
+            .map(|cal| (cal.url.to_string(), cal.status))
+            .collect();
+        assert_eq!(unavailable, vec![
+            ("https://my.server.com/dav/calendars/john/revoked-share/".to_string(), StatusCode::FORBIDDEN),
+            ("https://my.server.com/dav/calendars/john/other-revoked-share/".to_string(), StatusCode::FORBIDDEN),
+        ]);
+
+        let skipped: Vec<(Option<&str>, Option<&SkipReason>)> = report.responses.iter()
+            .map(|response| (response.href.as_deref(), response.skipped.as_ref()))
+            .collect();
+        assert_eq!(skipped, vec![
+            (Some("/dav/calendars/john/"), Some(&SkipReason::NotACalendar)),
+            (Some("/dav/calendars/john/tasks/"), None),
+            (Some("/dav/calendars/john/revoked-share/"), Some(&SkipReason::Unavailable(StatusCode::FORBIDDEN))),
+            (Some("/dav/calendars/john/other-revoked-share/"), Some(&SkipReason::Unavailable(StatusCode::FORBIDDEN))),
+        ]);
+        let tasks = &report.responses[1];
+        assert_eq!(tasks.url, Some(tasks_url));
+        assert_eq!(tasks.display_name.as_deref(), Some("Tasks"));
+        assert_eq!(tasks.resource_types, vec!["collection", "calendar"]);
+        assert_eq!(tasks.components, Some(vec!["VTODO".to_string()]));
+        assert!(report.responses.iter().all(|response| response.raw.is_none()));
+
+        // Raw responses are only kept on demand
+        let client = Client::builder("https://my.server.com/dav/", "user", "pass").keep_raw_discovery_responses(true).build().unwrap();
+        let reps = find_elems(&root, "response").into_iter().cloned().collect();
+        let (_, report) = client.parse_calendar_responses(reps);
+        assert!(report.responses.iter().all(|response| response.raw.as_ref().is_some_and(|raw| raw.name() == "response")));
+    }
+
+    const HOME_SET_WITH_PRIVILEGES: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/dav/calendars/john/tasks/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:displayname>Tasks</d:displayname>
+        <d:resourcetype><d:collection/><cal:calendar/></d:resourcetype>
+        <cal:supported-calendar-component-set><cal:comp name="VTODO"/></cal:supported-calendar-component-set>
+        <d:current-user-privilege-set>
+          <d:privilege><d:read/></d:privilege>
+          <d:privilege><d:write-content/></d:privilege>
+        </d:current-user-privilege-set>
+        <d:supported-report-set>
+          <d:supported-report><d:report><cal:calendar-multiget/></d:report></d:supported-report>
+          <d:supported-report><d:report><d:sync-collection/></d:report></d:supported-report>
+        </d:supported-report-set>
+        <cal:supported-calendar-data>
+          <cal:calendar-data content-type="text/calendar" version="2.0"/>
+          <cal:calendar-data content-type="application/calendar+json" version="2.0"/>
+          <cal:calendar-data content-type="text/calendar" version="1.0"/>
+        </cal:supported-calendar-data>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/calendars/john/holidays/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:displayname>Holidays</d:displayname>
+        <d:resourcetype><d:collection/><cal:calendar/></d:resourcetype>
+        <cal:supported-calendar-component-set><cal:comp name="VTODO"/></cal:supported-calendar-component-set>
+        <d:current-user-privilege-set>
+          <d:privilege><d:read/></d:privilege>
+        </d:current-user-privilege-set>
+        <oc:calendar-enabled xmlns:oc="http://owncloud.org/ns">0</oc:calendar-enabled>
+        <cal:min-date-time>19700101T000000Z</cal:min-date-time>
+        <cal:max-date-time>20380119T031407Z</cal:max-date-time>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/calendars/john/moon-phases/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:displayname>Moon phases</d:displayname>
+        <d:resourcetype><d:collection/><cs:subscribed xmlns:cs="http://calendarserver.org/ns/"/></d:resourcetype>
+        <cs:source xmlns:cs="http://calendarserver.org/ns/"><d:href>webcal://feeds.example.com/moon.ics</d:href></cs:source>
+        <x1:refreshrate xmlns:x1="http://apple.com/ns/ical/">PT12H</x1:refreshrate>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>
+"#;
+
+    #[test]
+    fn test_discovery_reads_privileges() {
+        use chrono::TimeZone;
+        let client = Client::new("https://my.server.com/dav/", "user", "pass").unwrap();
+        let root: Element = HOME_SET_WITH_PRIVILEGES.parse().unwrap();
+        let reps = find_elems(&root, "response").into_iter().cloned().collect();
+
+        let (calendars, _report) = client.parse_calendar_responses(reps);
+
+        let tasks = &calendars[&"https://my.server.com/dav/calendars/john/tasks/".parse::<Url>().unwrap()];
+        let holidays = &calendars[&"https://my.server.com/dav/calendars/john/holidays/".parse::<Url>().unwrap()];
+        assert!(tasks.lock().unwrap().is_writable());
+        assert!(!holidays.lock().unwrap().is_writable());
+        assert_eq!(tasks.lock().unwrap().privileges().unwrap(), ["read", "write-content"]);
+        assert_eq!(tasks.lock().unwrap().supported_reports(), ["calendar-multiget", "sync-collection"]);
+        assert!(holidays.lock().unwrap().supported_reports().is_empty());
+        use crate::calendar::CalendarDataFormat;
+        assert_eq!(tasks.lock().unwrap().supported_data_formats(), [CalendarDataFormat::ICalendar, CalendarDataFormat::JCal]);
+        assert_eq!(holidays.lock().unwrap().supported_data_formats(), [CalendarDataFormat::ICalendar]);
+        assert_eq!(tasks.lock().unwrap().data_format(), CalendarDataFormat::negotiate(&[CalendarDataFormat::ICalendar, CalendarDataFormat::JCal]));
+        assert_eq!(holidays.lock().unwrap().data_format(), CalendarDataFormat::ICalendar);
+        assert_eq!(CalendarDataFormat::from_content_type("Text/Calendar; charset=utf-8"), CalendarDataFormat::ICalendar);
+
+        assert!(tasks.lock().unwrap().date_limits().is_unbounded());
+        let limits = holidays.lock().unwrap().date_limits();
+        assert_eq!(limits.min, Some(chrono::Utc.ymd(1970, 1, 1).and_hms(0, 0, 0)));
+        assert_eq!(limits.max, Some(chrono::Utc.ymd(2038, 1, 19).and_hms(3, 14, 7)));
+
+        assert_eq!(tasks.lock().unwrap().subscription(), None);
+        assert_eq!(tasks.lock().unwrap().enabled(), None);
+        assert_eq!(holidays.lock().unwrap().enabled(), Some(false));
+        let moon = &calendars[&"https://my.server.com/dav/calendars/john/moon-phases/".parse::<Url>().unwrap()];
+        let moon = moon.lock().unwrap();
+        assert!(!moon.is_writable());
+        assert_eq!(moon.supported_components(), crate::calendar::SupportedComponents::all());
+        assert_eq!(moon.subscription(), Some(crate::calendar::Subscription {
+            source: "https://feeds.example.com/moon.ics".parse().unwrap(),
+            refresh_interval: Some(chrono::Duration::hours(12)),
+        }));
+    }
+}
@@ -0,0 +1,26 @@
+//! Copies of calendars on the server (see [`Client::copy_calendar`])
+
+use std::error::Error;
+
+use url::Url;
+
+use crate::provider::copy;
+use crate::provider::sync_progress::FeedbackSender;
+use super::Client;
+
+impl Client {
+    /// Create a copy of the calendar at `source` on the server, named `new_name`, e.g. to try a sync on it before letting it manage the real calendar.
+    /// This returns the URL of the copy, which is a sibling of `source` (see [`copy::copy_url`]).
+    ///
+    /// Items keep their UIDs. They are downloaded and uploaded in batches, so that large calendars can be copied as well.
+    /// In case the copy gets interrupted, calling this again with the same `new_name` resumes it.
+    /// Use [`Provider::compare`](crate::provider::Provider::compare) to check the copy once it is done.
+    pub async fn copy_calendar(&mut self, source: &Url, new_name: &str) -> Result<Url, Box<dyn Error>> {
+        copy::copy_calendar(self, source, new_name, None).await
+    }
+
+    /// The same as [`Client::copy_calendar`], but progress is reported to `feedback`, just like syncs do
+    pub async fn copy_calendar_with_feedback(&mut self, source: &Url, new_name: &str, feedback: &FeedbackSender) -> Result<Url, Box<dyn Error>> {
+        copy::copy_calendar(self, source, new_name, Some(feedback)).await
+    }
+}
@@ -0,0 +1,206 @@
+//! Finding the CalDAV server of a domain, as [RFC6764](https://tools.ietf.org/html/rfc6764) describes (see [`Client::discover`](super::Client::discover))
+//!
+//! Domains advertise their CalDAV servers with DNS SRV records. Looking them up requires the `dns-srv` feature: without it, discovery starts from the well-known URL of the domain itself.
+//!
+//! Since DNS answers are easy to forge, only the `_caldavs._tcp` service is looked up (unless [`crate::config::SRV_PLAIN_HTTP`] is set),
+//! and only the records that point inside the looked up domain are followed (unless their target is one of the [`crate::config::SRV_TRUSTED_TARGETS`]).
+
+use std::error::Error;
+
+use url::Url;
+
+use crate::error_code::{self, ErrorCode};
+
+/// The services a domain may advertise, in order of preference (CalDAV over TLS first), with the scheme of their URLs.
+/// The second one is only looked up when [`crate::config::SRV_PLAIN_HTTP`] is set
+#[cfg_attr(not(feature = "dns-srv"), allow(dead_code))]
+const SERVICES: [(&str, &str); 2] = [("_caldavs._tcp", "https"), ("_caldav._tcp", "http")];
+
+/// A DNS SRV record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    /// The host that provides the service, without its trailing dot. An empty one (i.e. `.`) means the service is not provided at all
+    pub target: String,
+}
+
+/// The error returned when the CalDAV server of a domain could not be found from its SRV records
+#[derive(Debug)]
+pub enum DiscoveryError {
+    /// The DNS lookup has failed
+    Lookup { domain: String, message: String },
+    /// The domain advertises no CalDAV service, or tells it provides none
+    NoService { domain: String },
+    /// The domain only advertises servers outside of itself, that are not trusted (see [`crate::config::SRV_TRUSTED_TARGETS`])
+    UntrustedTarget { domain: String, target: String },
+}
+
+impl DiscoveryError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Lookup { .. } => error_code::SRV_LOOKUP_FAILED,
+            Self::NoService { .. } => error_code::NO_CALDAV_SERVICE,
+            Self::UntrustedTarget { .. } => error_code::UNTRUSTED_SRV_TARGET,
+        }
+    }
+}
+
+impl std::fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Lookup { domain, message } => write!(f, "Unable to look up the SRV records of {}: {}", domain, message),
+            Self::NoService { domain } => write!(f, "{} advertises no CalDAV service", domain),
+            Self::UntrustedTarget { domain, target } => write!(f, "{} advertises a CalDAV server outside of itself ({}), that is not trusted", domain, target),
+        }?;
+        write!(f, " [{}]", self.code())
+    }
+}
+
+impl Error for DiscoveryError {}
+
+/// The root URL of the best server among the records of a service (the one with the lowest priority, then with the highest weight), or `None` in case the service is not provided
+pub fn service_url(scheme: &str, records: &[SrvRecord]) -> Option<Url> {
+    let best = records.iter()
+        .filter(|record| !record.target.is_empty())
+        .min_by_key(|record| (record.priority, std::cmp::Reverse(record.weight)))?;
+    Url::parse(&format!("{}://{}:{}/", scheme, best.target, best.port)).ok()
+}
+
+/// Whether an SRV record of `domain` may be followed to `target`: it must be `domain` itself, one of its sub-domains, or one of the `trusted` hosts
+pub fn is_trusted_target(target: &str, domain: &str, trusted: &[String]) -> bool {
+    let (target, domain) = (target.trim_end_matches('.').to_ascii_lowercase(), domain.trim_end_matches('.').to_ascii_lowercase());
+    target == domain
+        || target.ends_with(&format!(".{}", domain))
+        || trusted.iter().any(|host| host.trim_end_matches('.').eq_ignore_ascii_case(&target))
+}
+
+/// The domain whose SRV records are looked up for an email address or a host name, or `None` for full URLs and for hosts that have an explicit port
+pub(crate) fn domain_of(host_or_email: &str) -> Option<&str> {
+    let input = host_or_email.trim();
+    if input.contains("://") {
+        return None;
+    }
+    let domain = input.rsplit('@').next().unwrap_or(input);
+    match domain.is_empty() || domain.contains(':') {
+        true => None,
+        false => Some(domain),
+    }
+}
+
+/// The URL discovery starts from when the domain has no SRV records: the root of the host of an email address, or of a host name, unless this is a full URL already
+pub(crate) fn bootstrap_url(host_or_email: &str) -> Result<Url, url::ParseError> {
+    let input = host_or_email.trim();
+    if input.contains("://") {
+        return Url::parse(input);
+    }
+    let host = input.rsplit('@').next().unwrap_or(input);
+    Url::parse(&format!("https://{}/", host))
+}
+
+/// Look up the CalDAV services a domain advertises (`_caldavs._tcp`, then `_caldav._tcp` in case [`crate::config::SRV_PLAIN_HTTP`] is set), and return the root URL of its best server.
+///
+/// Records whose target is outside of the domain are ignored (see [`is_trusted_target`])
+#[cfg(feature = "dns-srv")]
+pub async fn lookup_srv(domain: &str) -> Result<Url, DiscoveryError> {
+    use hickory_resolver::TokioAsyncResolver;
+    use hickory_resolver::error::ResolveErrorKind;
+
+    let lookup_error = |message: String| DiscoveryError::Lookup { domain: domain.to_string(), message };
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(|err| lookup_error(err.to_string()))?;
+    let plain_http = *crate::config::SRV_PLAIN_HTTP.lock().unwrap();
+    let trusted = crate::config::SRV_TRUSTED_TARGETS.lock().unwrap().clone();
+    let mut untrusted = None;
+    for (service, scheme) in SERVICES {
+        if scheme == "http" && !plain_http {
+            log::debug!("{} is not looked up, since it leads to plain HTTP", service);
+            continue;
+        }
+        let name = format!("{}.{}.", service, domain);
+        let records: Vec<SrvRecord> = match resolver.srv_lookup(name.as_str()).await {
+            Ok(lookup) => lookup.iter()
+                .map(|srv| SrvRecord {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    port: srv.port(),
+                    target: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                })
+                .collect(),
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => continue,
+            Err(err) => return Err(lookup_error(err.to_string())),
+        };
+        let (records, foreign): (Vec<SrvRecord>, Vec<SrvRecord>) = records.into_iter()
+            .partition(|record| record.target.is_empty() || is_trusted_target(&record.target, domain, &trusted));
+        for record in foreign {
+            log::warn!("Ignoring the {} record of {} that points to {}, outside of this domain", service, domain, record.target);
+            untrusted.get_or_insert(record.target);
+        }
+        match service_url(scheme, &records) {
+            Some(url) => return Ok(url),
+            None => log::debug!("{} tells it provides no {} service", domain, service),
+        }
+    }
+    match untrusted {
+        Some(target) => Err(DiscoveryError::UntrustedTarget { domain: domain.to_string(), target }),
+        None => Err(DiscoveryError::NoService { domain: domain.to_string() }),
+    }
+}
+
+/// The root URL of the CalDAV server an email address or a host name advertises with SRV records, in case it does
+pub(crate) async fn srv_url(host_or_email: &str) -> Option<Url> {
+    let domain = domain_of(host_or_email)?;
+    #[cfg(feature = "dns-srv")]
+    match lookup_srv(domain).await {
+        Ok(url) => return Some(url),
+        Err(err) => log::debug!("{}", err),
+    }
+    #[cfg(not(feature = "dns-srv"))]
+    log::debug!("This crate has been built without its dns-srv feature, the SRV records of {} are not looked up", domain);
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_url() {
+        let record = |priority, weight, port, target: &str| SrvRecord { priority, weight, port, target: target.to_string() };
+        let records = [
+            record(10, 20, 8443, "backup.server.com"),
+            record(0, 10, 8443, "light.server.com"),
+            record(0, 90, 443, "caldav.server.com"),
+        ];
+        assert_eq!(service_url("https", &records).unwrap().as_str(), "https://caldav.server.com/");
+        assert_eq!(service_url("http", &records[..2]).unwrap().as_str(), "http://light.server.com:8443/");
+        // A target of `.` tells the service is not provided
+        assert_eq!(service_url("https", &[record(0, 0, 0, "")]), None);
+        assert_eq!(service_url("https", &[]), None);
+    }
+
+    #[test]
+    fn test_is_trusted_target() {
+        assert!(is_trusted_target("fastmail.com", "fastmail.com", &[]));
+        assert!(is_trusted_target("caldav.FastMail.com.", "fastmail.com", &[]));
+        assert!(!is_trusted_target("evil.com", "fastmail.com", &[]));
+        assert!(!is_trusted_target("notfastmail.com", "fastmail.com", &[]));
+        assert!(!is_trusted_target("fastmail.com.evil.com", "fastmail.com", &[]));
+        assert!(is_trusted_target("caldav.provider.com", "example.org", &["caldav.provider.com".to_string()]));
+    }
+
+    #[test]
+    fn test_bootstrap_url() {
+        assert_eq!(bootstrap_url("john@fastmail.com").unwrap().as_str(), "https://fastmail.com/");
+        assert_eq!(bootstrap_url(" fastmail.com ").unwrap().as_str(), "https://fastmail.com/");
+        assert_eq!(bootstrap_url("my.server.com:8443").unwrap().as_str(), "https://my.server.com:8443/");
+        assert_eq!(bootstrap_url("http://my.server.com/dav/").unwrap().as_str(), "http://my.server.com/dav/");
+
+        assert_eq!(domain_of("john@fastmail.com"), Some("fastmail.com"));
+        assert_eq!(domain_of("fastmail.com"), Some("fastmail.com"));
+        assert_eq!(domain_of("my.server.com:8443"), None);
+        assert_eq!(domain_of("http://my.server.com/dav/"), None);
+    }
+}
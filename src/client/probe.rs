@@ -0,0 +1,189 @@
+//! Finding out what a server supports before a first sync, e.g. to warn the user during account setup (see [`Client::probe`])
+
+use std::error::Error;
+
+use reqwest::header::{HeaderMap, ALLOW};
+use reqwest::Method;
+
+use crate::resource::Resource;
+use crate::traits::CalDavSource;
+use super::{Client, PrincipalType};
+
+/// Whether a server supports a feature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Supported,
+    Unsupported,
+    /// The server did not tell
+    Unknown,
+}
+
+impl Capability {
+    fn from_bool(supported: bool) -> Self {
+        match supported {
+            true => Self::Supported,
+            false => Self::Unsupported,
+        }
+    }
+}
+
+/// Where the capabilities of a [`ProbeReport`] come from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeSource {
+    /// The reply to an `OPTIONS` request, along with the reports the calendars support
+    Options,
+    /// Only the properties of the principal and of the calendars, because the `OPTIONS` request has failed (e.g. because a reverse proxy refuses it)
+    Inferred,
+}
+
+/// What a server supports (see [`Client::probe`])
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeReport {
+    pub source: ProbeSource,
+    /// The compliance classes of the `DAV` header of the reply to `OPTIONS` (e.g. `calendar-access`). This is empty in case the capabilities have been inferred
+    pub dav_classes: Vec<String>,
+    /// Whether calendars can be synced incrementally with a `sync-collection` report (i.e. with sync tokens)
+    pub sync_token: Capability,
+    /// Whether several items can be downloaded at once with a `calendar-multiget` report
+    pub multiget: Capability,
+    /// Whether calendars can be created (see [`CalDavSource::create_calendar`])
+    pub mkcalendar: Capability,
+    /// Whether the server handles the scheduling of events (invitations, free-busy requests...)
+    pub scheduling: Capability,
+}
+
+impl ProbeReport {
+    /// Build a report from the reply to an `OPTIONS` request, and the reports the calendars support (`None` in case the server did not tell)
+    fn from_options(headers: &HeaderMap, reports: Option<&[String]>) -> Self {
+        let dav_classes: Vec<String> = headers.get_all("DAV").iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|class| class.trim().to_string())
+            .filter(|class| !class.is_empty())
+            .collect();
+        // Servers that send no `Allow` header do not tell which methods they accept
+        let allowed: Option<Vec<String>> = headers.get(ALLOW).map(|_| headers.get_all(ALLOW).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|method| method.trim().to_ascii_uppercase())
+            .collect());
+        let scheduling = dav_classes.iter().any(|class| class == "calendar-auto-schedule" || class == "calendar-schedule");
+
+        Self {
+            source: ProbeSource::Options,
+            sync_token: report_capability(reports, "sync-collection"),
+            multiget: report_capability(reports, "calendar-multiget"),
+            mkcalendar: allowed.map_or(Capability::Unknown, |allowed| Capability::from_bool(allowed.iter().any(|method| method == "MKCALENDAR"))),
+            scheduling: Capability::from_bool(scheduling),
+            dav_classes,
+        }
+    }
+
+    /// Build a report from the reports the calendars support, and the types of the principals of the current user
+    fn inferred(reports: Option<&[String]>, principal_types: &[PrincipalType]) -> Self {
+        Self {
+            source: ProbeSource::Inferred,
+            dav_classes: Vec::new(),
+            sync_token: report_capability(reports, "sync-collection"),
+            multiget: report_capability(reports, "calendar-multiget"),
+            mkcalendar: Capability::Unknown,
+            // Scheduling servers must advertise the schedule inbox of the principals (RFC 6638)
+            scheduling: Capability::from_bool(principal_types.contains(&PrincipalType::Scheduling)),
+        }
+    }
+}
+
+/// Whether a report is supported, given the reports the calendars support
+fn report_capability(reports: Option<&[String]>, report: &str) -> Capability {
+    match reports {
+        None => Capability::Unknown,
+        Some(reports) => Capability::from_bool(reports.iter().any(|supported| supported == report)),
+    }
+}
+
+/// Send an `OPTIONS` request, and return the headers of the reply, or `None` in case the server (or a proxy) refuses it
+async fn options(resource: &Resource) -> Result<Option<HeaderMap>, Box<dyn Error>> {
+    let request = resource.http_client()
+        .request(Method::OPTIONS, resource.url().clone());
+    let response = resource.send(request).await?;
+    let reply = resource.read_reply(response, "OPTIONS", 0, super::max_response_size()).await?;
+    let status = reply.status;
+    if !status.is_success() {
+        log::info!("OPTIONS {} has failed ({}), capabilities will be inferred from the properties of the calendars", resource.url(), status);
+        return Ok(None);
+    }
+    Ok(Some(reply.headers))
+}
+
+impl Client {
+    /// Find out what the server supports, e.g. to tell the user what will and will not work during account setup, before a first sync.
+    ///
+    /// This discovers the calendars, and asks the calendar home set for its capabilities with an `OPTIONS` request.
+    /// In case the server (or a reverse proxy) refuses `OPTIONS`, the capabilities are inferred from the properties of the principal and of the calendars instead.
+    /// The report is cached, so that later calls do not query the server again.
+    pub async fn probe(&self) -> Result<ProbeReport, Box<dyn Error>> {
+        if let Some(report) = &self.cached_replies.lock().unwrap().probe {
+            return Ok(report.clone());
+        }
+
+        let cal_home_set = self.get_cal_home_set().await?;
+        let calendars = self.get_calendars().await?;
+        let mut reports: Vec<String> = Vec::new();
+        for calendar in calendars.values() {
+            reports.extend(calendar.lock().unwrap().supported_reports().iter().cloned());
+        }
+        // Servers that do not list the supported reports of their calendars do not tell anything
+        let reports = match reports.is_empty() {
+            true => None,
+            false => Some(reports.as_slice()),
+        };
+
+        let report = match options(&cal_home_set).await? {
+            Some(headers) => ProbeReport::from_options(&headers, reports),
+            None => {
+                let principal_types: Vec<PrincipalType> = self.get_principals().await?
+                    .into_iter()
+                    .map(|(principal_type, _)| principal_type)
+                    .collect();
+                ProbeReport::inferred(reports, &principal_types)
+            },
+        };
+        log::debug!("Capabilities of the server: {:?}", report);
+        self.cached_replies.lock().unwrap().probe = Some(report.clone());
+        Ok(report)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_reports() {
+        let reports = vec!["calendar-multiget".to_string(), "calendar-query".to_string()];
+
+        let mut headers = HeaderMap::new();
+        headers.append("DAV", "1, 2, 3, calendar-access, calendar-auto-schedule".parse().unwrap());
+        headers.append("DAV", "extended-mkcol".parse().unwrap());
+        headers.append(ALLOW, "OPTIONS, GET, HEAD, PROPFIND, REPORT, mkcalendar".parse().unwrap());
+        let report = ProbeReport::from_options(&headers, Some(&reports));
+        assert_eq!(report.dav_classes, vec!["1", "2", "3", "calendar-access", "calendar-auto-schedule", "extended-mkcol"]);
+        assert_eq!(report.sync_token, Capability::Unsupported);
+        assert_eq!(report.multiget, Capability::Supported);
+        assert_eq!(report.mkcalendar, Capability::Supported);
+        assert_eq!(report.scheduling, Capability::Supported);
+
+        let mut headers = HeaderMap::new();
+        headers.append("DAV", "1, calendar-access".parse().unwrap());
+        let report = ProbeReport::from_options(&headers, None);
+        assert_eq!((report.sync_token, report.multiget, report.mkcalendar, report.scheduling),
+            (Capability::Unknown, Capability::Unknown, Capability::Unknown, Capability::Unsupported));
+
+        let report = ProbeReport::inferred(Some(&reports), &[PrincipalType::Calendar, PrincipalType::Scheduling]);
+        assert_eq!(report.source, ProbeSource::Inferred);
+        assert!(report.dav_classes.is_empty());
+        assert_eq!((report.sync_token, report.multiget, report.mkcalendar, report.scheduling),
+            (Capability::Unsupported, Capability::Supported, Capability::Unknown, Capability::Supported));
+    }
+}
@@ -0,0 +1,157 @@
+//! Retrying requests that have failed because of transient failures (see [`RetryPolicy`])
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{Method, StatusCode};
+use reqwest::header::{HeaderMap, IF_MATCH, RETRY_AFTER};
+
+use crate::error::KitchenFridgeError;
+use crate::error_code::{self, ErrorCode};
+
+/// How requests that fail because of a transient failure (a connection error, a timeout, or a `500`, `502`, `503` or `504` reply) are retried (see [`crate::client::ClientBuilder::retry_policy`])
+///
+/// Only the requests that can safely be sent several times are retried: `PROPFIND`, `REPORT`, `GET`, `HEAD` and `OPTIONS`, and `PUT` and `DELETE` that have an `If-Match` precondition.
+/// Requests the server has refused because their resource is locked (`423 Locked`) or because the client sends too many requests (`429 Too Many Requests`) are retried as well, whatever their method, since they have had no effect.
+/// In case the server tells how long to wait (with a `Retry-After` header), the request is retried after this delay rather than after the backoff, unless it exceeds [`Self::max_retry_after`].
+/// Every retry is logged at debug level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a request is sent at most, including the first one. `1` never retries
+    pub max_attempts: u32,
+    /// How long to wait before the first retry. This doubles at every retry, and a random part (up to a half) is taken off, so that clients do not retry all at once
+    pub initial_backoff: Duration,
+    /// How long to wait before a retry at most
+    pub max_backoff: Duration,
+    /// How long the server may ask to wait before a retry at most. Requests the server asks to wait longer for fail at once with a [`Throttled`] error
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 3, initial_backoff: Duration::from_millis(500), max_backoff: Duration::from_secs(10), max_retry_after: Duration::from_secs(30) }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn disabled() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// How long to wait before sending a request again, once it has failed `failed_attempts` times
+    pub(crate) fn backoff(&self, failed_attempts: u32) -> Duration {
+        let exponential = self.initial_backoff.saturating_mul(2u32.saturating_pow(failed_attempts.saturating_sub(1)));
+        let backoff = exponential.min(self.max_backoff);
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(1.0 - jitter / 2.0)
+    }
+}
+
+/// Whether a request can be sent several times without any other effect than sending it once
+pub(crate) fn is_idempotent(request: &reqwest::Request) -> bool {
+    let method = request.method();
+    if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS || method.as_str() == "PROPFIND" || method.as_str() == "REPORT" {
+        return true;
+    }
+    // A second attempt fails its precondition in case the first one has succeeded, rather than overwriting a newer version
+    (method == Method::PUT || method == Method::DELETE) && request.headers().contains_key(IF_MATCH)
+}
+
+/// The error returned when the server throttles requests (with a `429 Too Many Requests` or a `503 Service Unavailable` reply), and asks to wait longer than [`RetryPolicy::max_retry_after`] (or longer than the attempts left) before sending them again
+#[derive(Debug)]
+pub struct Throttled {
+    pub status: StatusCode,
+    /// How long the server has asked to wait (with a `Retry-After` header), e.g. to schedule the next sync
+    pub retry_after: Duration,
+}
+
+impl Throttled {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::THROTTLED
+    }
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the server has replied {}, and asked to wait {}s before retrying [{}]", self.status, self.retry_after.as_secs(), self.code())
+    }
+}
+
+impl Error for Throttled {}
+
+/// How long a reply with this status asks to wait before sending the request again, in case it has a `Retry-After` header (be it delay-seconds or an HTTP date)
+pub(crate) fn retry_after(status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+/// Whether a server that replies with this status is likely to succeed later
+pub(crate) fn is_transient_status(status: StatusCode) -> bool {
+    matches!(status, StatusCode::INTERNAL_SERVER_ERROR | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+}
+
+/// Whether a request that has failed this way is likely to succeed later
+pub(crate) fn is_transient_error(err: &KitchenFridgeError) -> bool {
+    match err {
+        KitchenFridgeError::Timeout(_) => true,
+        KitchenFridgeError::Http(err) => err.is_connect() || err.is_timeout(),
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default();
+        for failed_attempts in 1..=3 {
+            let backoff = policy.backoff(failed_attempts);
+            let nominal = Duration::from_millis(500 * 2u64.pow(failed_attempts - 1));
+            assert!(backoff <= nominal && backoff >= nominal / 2, "{:?} after {} attempts", backoff, failed_attempts);
+        }
+        assert!(policy.backoff(30) <= policy.max_backoff);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT").unwrap().with_timezone(&Utc);
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers("120"), now), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers("Wed, 21 Oct 2015 07:29:30 GMT"), now), Some(Duration::from_secs(90)));
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers("Wed, 21 Oct 2015 07:00:00 GMT"), now), Some(Duration::ZERO));
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers("soon"), now), None);
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), now), None);
+        assert_eq!(retry_after(StatusCode::FORBIDDEN, &headers("120"), now), None);
+    }
+
+    #[test]
+    fn test_idempotent_requests() {
+        let client = reqwest::Client::new();
+        let url = "https://some.server/cal/item.ics";
+        let propfind = client.request(Method::from_bytes(b"PROPFIND").unwrap(), url).build().unwrap();
+        assert!(is_idempotent(&propfind));
+        assert!(!is_idempotent(&client.put(url).build().unwrap()));
+        assert!(is_idempotent(&client.put(url).header(IF_MATCH, "\"etag\"").build().unwrap()));
+        assert!(!is_idempotent(&client.delete(url).build().unwrap()));
+        assert!(!is_idempotent(&client.post(url).build().unwrap()));
+    }
+}
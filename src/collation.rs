@@ -0,0 +1,118 @@
+//! Locale-aware comparison of names (e.g. to sort tasks, see [`crate::calendar::cached_calendar::CachedCalendar::tasks_sorted`])
+//!
+//! With the `collation` feature, names are compared with the ICU collation rules of a locale, so that e.g. `Ärzte` is sorted before `Zoo`. \
+//! Without it, they are compared case-insensitively (but accents are sorted after every unaccented letter).
+
+use std::cmp::Ordering;
+
+/// Compares names according to the rules of a locale
+pub struct Collator {
+    #[cfg(feature = "collation")]
+    inner: icu_collator::Collator,
+}
+
+impl Collator {
+    /// A collator for `locale` (a BCP 47 language tag, e.g. `de-DE`), or for the locale of the system when it is `None` (see [`system_locale`]).
+    ///
+    /// Unknown locales fall back to the root collation rules
+    pub fn new(locale: Option<&str>) -> Self {
+        #[cfg(feature = "collation")]
+        {
+            let locale = locale.map(|locale| locale.to_string()).or_else(system_locale);
+            let locale: icu_locid::Locale = locale
+                .and_then(|locale| locale.parse().ok())
+                .unwrap_or(icu_locid::Locale::UND);
+            let inner = icu_collator::Collator::try_new(&(&locale).into(), icu_collator::CollatorOptions::new())
+                .or_else(|_| icu_collator::Collator::try_new(&Default::default(), icu_collator::CollatorOptions::new()))
+                .expect("the root collation rules are built in");
+            Self { inner }
+        }
+        #[cfg(not(feature = "collation"))]
+        {
+            let _ = locale;
+            Self {}
+        }
+    }
+
+    /// Compare two names
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        #[cfg(feature = "collation")]
+        return self.inner.compare(a, b);
+
+        #[cfg(not(feature = "collation"))]
+        return a.to_lowercase().cmp(&b.to_lowercase()).then_with(|| a.cmp(b));
+    }
+}
+
+impl std::fmt::Debug for Collator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Collator")
+    }
+}
+
+/// The locale of the system (e.g. `de-DE`), as told by the `LC_ALL`, `LC_COLLATE` or `LANG` environment variables
+pub fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_COLLATE", "LANG"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .map(|value| {
+            // e.g. `de_DE.UTF-8@euro`
+            let language = value.split(['.', '@']).next().unwrap_or_default();
+            language.replace('_', "-")
+        })
+        .filter(|language| !language.is_empty() && language != "C" && language != "POSIX")
+}
+
+/// Fold a text for fuzzy comparisons: it is lowercased, and its diacritics are removed in case the `collation` feature is enabled (e.g. `Ärzte` becomes `arzte`)
+pub fn fold(text: &str) -> String {
+    #[cfg(feature = "collation")]
+    {
+        let decomposed = icu_normalizer::DecomposingNormalizer::new_nfd().normalize(text);
+        decomposed.chars()
+            .filter(|c| !is_combining_mark(*c))
+            .collect::<String>()
+            .to_lowercase()
+    }
+    #[cfg(not(feature = "collation"))]
+    text.to_lowercase()
+}
+
+/// Whether a character is a combining diacritical mark (e.g. the diaeresis of a decomposed `Ä`)
+#[cfg(feature = "collation")]
+fn is_combining_mark(c: char) -> bool {
+    matches!(c, '\u{0300}'..='\u{036F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}' | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let collator = Collator::new(Some("de-DE"));
+        let mut names = vec!["Zoo", "banana", "Apple", "apple"];
+        names.sort_by(|a, b| collator.compare(a, b));
+        // Only the order of names that differ by their case depends on the rules that are used
+        let expected = match cfg!(feature = "collation") {
+            true => vec!["apple", "Apple", "banana", "Zoo"],
+            false => vec!["Apple", "apple", "banana", "Zoo"],
+        };
+        assert_eq!(names, expected);
+
+        #[cfg(feature = "collation")]
+        {
+            assert_eq!(collator.compare("Ärzte", "Zoo"), Ordering::Less);
+            assert_eq!(collator.compare("Ärzte", "Arzt"), Ordering::Greater);
+            // Swedish sorts Ä after Z
+            assert_eq!(Collator::new(Some("sv-SE")).compare("Ärzte", "Zoo"), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold("Pay the RENT"), "pay the rent");
+        #[cfg(feature = "collation")]
+        assert_eq!(fold("Ärzte Café"), "arzte cafe");
+    }
+}
@@ -3,6 +3,8 @@
 use std::sync::{Arc, Mutex};
 use once_cell::sync::Lazy;
 
+use crate::ical::MultipleComponentsPolicy;
+
 /// Part of the ProdID string that describes the organization (example of a ProdID string: `-//ABC Corporation//My Product//EN`).
 /// Feel free to override it when initing this library.
 pub static ORG_NAME: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new("My organization".to_string())));
@@ -10,3 +12,117 @@ pub static ORG_NAME: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new
 /// Part of the ProdID string that describes the product name (example of a ProdID string: `-//ABC Corporation//My Product//EN`).
 /// Feel free to override it when initing this library.
 pub static PRODUCT_NAME: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new("KitchenFridge".to_string())));
+
+/// How iCal resources that contain several components (e.g. both a `VTODO` and a `VEVENT`) are handled when they are parsed.
+/// See [`MultipleComponentsPolicy`]
+pub static MULTIPLE_COMPONENTS_POLICY: Lazy<Arc<Mutex<MultipleComponentsPolicy>>> = Lazy::new(|| Arc::new(Mutex::new(MultipleComponentsPolicy::default())));
+
+/// How many times a batch of items that could not be downloaded during a sync is retried, before giving up until the next sync.
+/// In case a batch has been partially downloaded, only its missing items are requested again.
+pub static DOWNLOAD_BATCH_RETRIES: Lazy<Arc<Mutex<u32>>> = Lazy::new(|| Arc::new(Mutex::new(2)));
+
+/// How long to wait before retrying a batch of items that could not be downloaded for the first time. This doubles at every retry of the same batch (just like the spacing of the retries of uploads, see [`crate::provider::quarantine`]). This is 500ms by default.
+pub static DOWNLOAD_BATCH_RETRY_DELAY: Lazy<Arc<Mutex<std::time::Duration>>> = Lazy::new(|| Arc::new(Mutex::new(std::time::Duration::from_millis(500))));
+
+/// An identifier of the current device (e.g. `work-laptop`). It is `None` by default.
+///
+/// When it is set, tasks that are created or modified by this crate are stamped with it (see [`crate::task::DEVICE_PROPERTY`]), so that it is possible to tell which device created or last modified a task.
+/// This is useful to debug setups where several devices sync the same calendars. It should be a short identifier, without `:`, `;` or `,`.
+pub static DEVICE_ID: Lazy<Arc<Mutex<Option<String>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// Whether [`Cache::from_folder`](crate::cache::Cache::from_folder) looks for inconsistencies in the loaded cache (see [`Cache::check`](crate::cache::Cache::check)), and logs what it finds.
+/// It is `false` by default.
+pub static CHECK_CACHE_ON_LOAD: Lazy<Arc<Mutex<bool>>> = Lazy::new(|| Arc::new(Mutex::new(false)));
+
+/// The maximum nesting depth of the XML documents received from the server.
+/// Deeper documents are refused (with an error) rather than parsed, so that a buggy or malicious server cannot exhaust the stack.
+pub static XML_MAX_DEPTH: Lazy<Arc<Mutex<usize>>> = Lazy::new(|| Arc::new(Mutex::new(128)));
+
+/// The maximum number of elements of the XML documents received from the server.
+/// Larger documents are refused (with an error) rather than parsed, so that a buggy or malicious server cannot exhaust the memory.
+/// The default is high enough for calendars with hundreds of thousands of items.
+pub static XML_MAX_ELEMENTS: Lazy<Arc<Mutex<usize>>> = Lazy::new(|| Arc::new(Mutex::new(5_000_000)));
+
+/// The maximum size (in bytes) of the replies received from the server.
+/// The body of a larger reply is not read any further, and the request fails with a [`ResponseTooLarge`](crate::dav_error::ResponseTooLarge) error, so that a buggy or malicious server cannot exhaust the memory.
+/// The default (64 MiB) is high enough for calendars with tens of thousands of items.
+pub static MAX_RESPONSE_SIZE: Lazy<Arc<Mutex<usize>>> = Lazy::new(|| Arc::new(Mutex::new(64 * 1024 * 1024)));
+
+/// The maximum size (in bytes) of a single item received from the server, and of any text of the XML documents received from the server (e.g. the `calendar-data` of an item).
+/// Calendars whose server advertises a smaller `max-resource-size` use this one instead (see [`RemoteCalendar::max_resource_size`](crate::calendar::remote_calendar::RemoteCalendar::max_resource_size)).
+/// It is 10 MiB by default.
+pub static MAX_ITEM_SIZE: Lazy<Arc<Mutex<usize>>> = Lazy::new(|| Arc::new(Mutex::new(10 * 1024 * 1024)));
+
+/// How often subscribed calendars are refreshed during a sync, unless the server advertises another interval for them.
+/// See [`crate::calendar::Subscription`]
+pub static SUBSCRIPTION_REFRESH_INTERVAL: Lazy<Arc<Mutex<chrono::Duration>>> = Lazy::new(|| Arc::new(Mutex::new(chrono::Duration::days(1))));
+
+/// The name of an HTTP header (e.g. `X-Request-Id`) that carries a unique identifier on every CalDAV request, so that the logs of this crate can be matched with the access logs of the server.
+/// The identifier is included in the log lines and the errors about the request.
+/// It is `None` (i.e. no identifier is sent) by default.
+pub static REQUEST_ID_HEADER: Lazy<Arc<Mutex<Option<String>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// How long an item can stay marked for deletion before [`Cache::check`](crate::cache::Cache::check) reports it (see [`Inconsistency::StaleDeletion`](crate::cache::check::Inconsistency::StaleDeletion)).
+/// Deletions are normally pushed to the server at the next sync, so this only happens to deletions that repeatedly fail.
+pub static TOMBSTONE_WINDOW: Lazy<Arc<Mutex<chrono::Duration>>> = Lazy::new(|| Arc::new(Mutex::new(chrono::Duration::days(30))));
+
+/// The longest time range a single `calendar-query` REPORT asks for (see [`RemoteCalendar::get_expanded_instances`](crate::calendar::remote_calendar::RemoteCalendar::get_expanded_instances)).
+/// Longer ranges are split into several requests (e.g. monthly ones with `Some(chrono::Duration::days(31))`), so that each one is small enough for servers with strict per-request limits.
+/// It is `None` (i.e. a single request for the whole range) by default.
+pub static TIME_RANGE_CHUNK_SIZE: Lazy<Arc<Mutex<Option<chrono::Duration>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// Whether control characters (e.g. NUL or BEL), that iCal does not allow, are removed from the SUMMARY, DESCRIPTION, LOCATION and CATEGORIES of items when they are serialized.
+/// Line breaks are escaped instead (as `\n`). A warning is logged for every item that is changed this way.
+/// It is `false` (i.e. text properties are serialized as they are, and strict servers may refuse them) by default.
+pub static SANITIZE_CONTROL_CHARACTERS: Lazy<Arc<Mutex<bool>>> = Lazy::new(|| Arc::new(Mutex::new(false)));
+
+/// The `Content-Type` of the XML bodies of the requests (PROPFIND, REPORT, MKCALENDAR...).
+/// Some servers refuse requests that do not tell their charset, so this is `application/xml; charset=utf-8` by default.
+pub static XML_CONTENT_TYPE: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new(String::from("application/xml; charset=utf-8"))));
+
+/// Whether the `Content-Type` of the items that are uploaded as iCal tells their charset and the kind of their component (e.g. `text/calendar; charset=utf-8; component=VTODO`).
+/// Some servers require these parameters. This is `true` by default, set it to `false` for servers that refuse them.
+pub static ITEM_CONTENT_TYPE_PARAMETERS: Lazy<Arc<Mutex<bool>>> = Lazy::new(|| Arc::new(Mutex::new(true)));
+
+/// How deep the hierarchy of tasks (their `RELATED-TO` parents) may be (see [`crate::calendar::hierarchy::Hierarchy`]).
+/// Tasks that would be deeper are handled as top-level tasks, and are reported by [`Cache::check`](crate::cache::Cache::check). This is 64 by default.
+pub static MAX_HIERARCHY_DEPTH: Lazy<Arc<Mutex<usize>>> = Lazy::new(|| Arc::new(Mutex::new(64)));
+
+/// How long the calendars discovered by a [`Client`](crate::client::Client) are reused before they are discovered again.
+/// Concurrent calls always share a single discovery. This is zero by default, i.e. every (non-concurrent) call discovers the calendars again.
+pub static DISCOVERY_TTL: Lazy<Arc<Mutex<chrono::Duration>>> = Lazy::new(|| Arc::new(Mutex::new(chrono::Duration::zero())));
+
+/// After how many syncs, at most, an item whose uploads keep failing is retried (see [`crate::provider::quarantine`]). This is 32 by default.
+pub static UPLOAD_RETRY_SPACING_CAP: Lazy<Arc<Mutex<u32>>> = Lazy::new(|| Arc::new(Mutex::new(32)));
+
+/// Whether [`Client::discover`](crate::client::Client::discover) also looks up the `_caldav._tcp` SRV records of domains that advertise no `_caldavs._tcp` service.
+/// These lead to servers over plain HTTP, that would be sent the credentials of the user in the clear, so this is `false` by default.
+pub static SRV_PLAIN_HTTP: Lazy<Arc<Mutex<bool>>> = Lazy::new(|| Arc::new(Mutex::new(false)));
+
+/// The hosts (e.g. `caldav.provider.com`) the SRV records of any domain may point to (see [`Client::discover`](crate::client::Client::discover)).
+/// Since DNS answers are easy to forge, the SRV targets that are outside the looked up domain are ignored, unless they are listed here. This is empty by default.
+pub static SRV_TRUSTED_TARGETS: Lazy<Arc<Mutex<Vec<String>>>> = Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
+
+/// Overrides one of these settings until it is dropped, and then restores its former value (for tests)
+#[cfg(test)]
+pub(crate) struct OverrideGuard<T: 'static> {
+    setting: &'static Lazy<Arc<Mutex<T>>>,
+    former: Option<T>,
+}
+
+#[cfg(test)]
+impl<T: 'static> OverrideGuard<T> {
+    pub(crate) fn set(setting: &'static Lazy<Arc<Mutex<T>>>, value: T) -> Self {
+        let former = std::mem::replace(&mut *setting.lock().unwrap(), value);
+        Self { setting, former: Some(former) }
+    }
+}
+
+#[cfg(test)]
+impl<T: 'static> Drop for OverrideGuard<T> {
+    fn drop(&mut self) {
+        if let Some(former) = self.former.take() {
+            *self.setting.lock().unwrap() = former;
+        }
+    }
+}
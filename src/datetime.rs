@@ -0,0 +1,428 @@
+//! Date-times, as they can be expressed in iCal files
+//!
+//! RFC5545 makes a distinction between several kinds of date-times (see [`CalDateTime`]).
+//! Converting them into a `DateTime<Utc>` silently imposes a timezone, which gives wrong results for e.g. "what is due today" around midnight.
+//! This module keeps the original kind of value, and provides comparison helpers that take a reference timezone.
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use ical::property::Property;
+
+/// A date or a date-time, as found in `DUE` or `DTSTART` iCal properties
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CalDateTime {
+    /// A date without any time (e.g. `DUE;VALUE=DATE:20210321`)
+    Date(NaiveDate),
+    /// A "floating" date-time, that does not belong to any timezone (e.g. `DUE:20210321T100000`). It should be interpreted in the timezone of the user
+    Floating(NaiveDateTime),
+    /// A UTC date-time (e.g. `DUE:20210321T100000Z`)
+    Utc(DateTime<Utc>),
+    /// A local date-time in a given timezone (e.g. `DUE;TZID=Europe/Paris:20210321T100000`)
+    Zoned{ tzid: String, local: NaiveDateTime },
+}
+
+impl CalDateTime {
+    /// Parse an iCal property (e.g. `DUE` or `DTSTART`) into a `CalDateTime`
+    pub fn from_property(prop: &Property) -> Result<Self, Box<dyn Error>> {
+        let value = prop.value.as_deref().ok_or_else(|| format!("Property {} has no value", prop.name))?;
+
+        let param = |name: &str| -> Option<&str> {
+            prop.params.as_ref()?
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, values)| values.first())
+                .map(|v| v.as_str())
+        };
+
+        if param("VALUE").map(|v| v.eq_ignore_ascii_case("DATE")) == Some(true) {
+            return Ok(Self::Date(NaiveDate::parse_from_str(value, "%Y%m%d")?));
+        }
+
+        if let Some(utc_value) = value.strip_suffix('Z') {
+            let naive = NaiveDateTime::parse_from_str(utc_value, "%Y%m%dT%H%M%S")?;
+            return Ok(Self::Utc(DateTime::from_utc(naive, Utc)));
+        }
+
+        let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?;
+        match param("TZID") {
+            None => Ok(Self::Floating(local)),
+            Some(tzid) => Ok(Self::Zoned{ tzid: tzid.to_string(), local }),
+        }
+    }
+
+    /// Returns the parameters and the value that must be used to serialize this date in an iCal property
+    pub fn to_ical_value(&self) -> (Vec<(&'static str, String)>, String) {
+        match self {
+            Self::Date(d) => (vec![("VALUE", "DATE".to_string())], d.format("%Y%m%d").to_string()),
+            Self::Floating(dt) => (Vec::new(), dt.format("%Y%m%dT%H%M%S").to_string()),
+            Self::Utc(dt) => (Vec::new(), dt.format("%Y%m%dT%H%M%SZ").to_string()),
+            Self::Zoned{tzid, local} => (vec![("TZID", tzid.clone())], local.format("%Y%m%dT%H%M%S").to_string()),
+        }
+    }
+
+    /// Returns whether this is a date without any time part
+    pub fn is_date_only(&self) -> bool {
+        matches!(self, Self::Date(_))
+    }
+
+    /// The instant this value designates, provided that values without a timezone (dates and floating date-times) are interpreted in `reference_tz`.
+    ///
+    /// Dates are considered to designate their very beginning (midnight).
+    /// Zoned date-times whose TZID is not a known IANA timezone are interpreted in `reference_tz` as well.
+    pub fn to_utc(&self, reference_tz: &Tz) -> DateTime<Utc> {
+        match self {
+            Self::Date(d) => local_to_utc(&d.and_time(NaiveTime::from_hms(0, 0, 0)), reference_tz),
+            Self::Floating(dt) => local_to_utc(dt, reference_tz),
+            Self::Utc(dt) => *dt,
+            Self::Zoned{tzid, local} => local_to_utc(local, &tz_or(tzid, reference_tz)),
+        }
+    }
+
+    /// The calendar date of this value, as seen from `reference_tz`.
+    ///
+    /// Dates and floating date-times are returned as-is, since they are not bound to any timezone
+    pub fn date_in(&self, reference_tz: &Tz) -> NaiveDate {
+        match self {
+            Self::Date(d) => *d,
+            Self::Floating(dt) => dt.date(),
+            Self::Utc(_) | Self::Zoned{..} => self.to_utc(reference_tz).with_timezone(reference_tz).date().naive_local(),
+        }
+    }
+
+    /// Compare two values, provided that values without a timezone are interpreted in `reference_tz`
+    pub fn cmp_in(&self, other: &Self, reference_tz: &Tz) -> Ordering {
+        self.to_utc(reference_tz).cmp(&other.to_utc(reference_tz))
+    }
+
+    /// Returns whether this value designates a whole day, rather than an instant (this is the case of dates without any time part, see [`CalDateTime::Date`])
+    pub fn is_all_day(&self) -> bool {
+        self.is_date_only()
+    }
+
+    /// The wall-clock date-time this value must be displayed with to a user who lives in `tz`.
+    ///
+    /// UTC and zoned date-times are converted into `tz`. Floating date-times are shown as they are, since they are meant to be read in the timezone of the user.
+    /// Dates are shown as their midnight (see [`CalDateTime::is_all_day`] to tell them from date-times that happen to be at midnight), and never shift to another day
+    pub fn to_display(&self, tz: &Tz) -> NaiveDateTime {
+        match self {
+            Self::Date(d) => d.and_time(NaiveTime::from_hms(0, 0, 0)),
+            Self::Floating(dt) => *dt,
+            Self::Utc(_) | Self::Zoned{..} => self.to_utc(tz).with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// How many calendar days there are from `now` to this value, both seen from `tz` (see [`CalDateTime::date_in`]): this is `0` when this value is today, `1` when it is tomorrow, and negative when it is in the past.
+    ///
+    /// Since these are calendar days, this does not depend on the time of day, nor on DST transitions (e.g. a day that is 23 hours long still counts for one)
+    pub fn days_until(&self, now: &DateTime<Utc>, tz: &Tz) -> i64 {
+        let today = now.with_timezone(tz).date().naive_local();
+        (self.date_in(tz) - today).num_days()
+    }
+
+    /// Compare two values as deadlines (e.g. due dates), provided that values without a timezone are interpreted in `tz`.
+    ///
+    /// Contrary to [`CalDateTime::cmp_in`], dates compare as the end of their day (i.e. the midnight of the next day in `tz`): something that is due on a date is not late until this whole day is over, and sorts after everything that is due at a given time on that day
+    pub fn cmp_in_tz(&self, other: &Self, tz: &Tz) -> Ordering {
+        self.deadline_in(tz).cmp(&other.deadline_in(tz))
+    }
+
+    /// The instant this value designates as a deadline (see [`CalDateTime::cmp_in_tz`])
+    fn deadline_in(&self, tz: &Tz) -> DateTime<Utc> {
+        match self {
+            Self::Date(d) => local_to_utc(&d.succ().and_time(NaiveTime::from_hms(0, 0, 0)), tz),
+            _ => self.to_utc(tz),
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for CalDateTime {
+    fn from(dt: DateTime<Utc>) -> Self {
+        Self::Utc(dt)
+    }
+}
+
+impl From<NaiveDate> for CalDateTime {
+    fn from(d: NaiveDate) -> Self {
+        Self::Date(d)
+    }
+}
+
+/// The timezone of the system, or UTC in case it cannot be determined.
+///
+/// This is a sensible default for the `reference_tz` parameters of this module
+pub fn system_timezone() -> Tz {
+    iana_time_zone::get_timezone().ok()
+        .and_then(|name| Tz::from_str(&name).ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// Parse an iCal duration (e.g. `P1D`, `PT1H30M` or `-P2W`, see RFC5545 section 3.3.6)
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut rest = value.strip_prefix('P')?;
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut duration = Duration::zero();
+    let mut in_time = false;
+    while !rest.is_empty() {
+        if let Some(after_t) = rest.strip_prefix('T') {
+            in_time = true;
+            rest = after_t;
+            continue;
+        }
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let number: i64 = rest[..digits].parse().ok()?;
+        let unit = match (in_time, rest[digits..].chars().next()?) {
+            (false, 'W') => try_seconds(number, 7 * 86_400)?,
+            (false, 'D') => try_seconds(number, 86_400)?,
+            (true, 'H') => try_seconds(number, 3600)?,
+            (true, 'M') => try_seconds(number, 60)?,
+            (true, 'S') => try_seconds(number, 1)?,
+            _ => return None,
+        };
+        duration = duration.checked_add(&unit)?;
+        rest = &rest[digits + 1..];
+    }
+    Some(if negative { -duration } else { duration })
+}
+
+/// `number` times `unit` seconds, or `None` in case this does not fit in a [`Duration`] (values come from the server, they must not make `Duration::weeks` and the like panic)
+fn try_seconds(number: i64, unit: i64) -> Option<Duration> {
+    let seconds = number.checked_mul(unit)?;
+    let max = Duration::max_value().num_seconds();
+    match (-max..=max).contains(&seconds) {
+        true => Some(Duration::seconds(seconds)),
+        false => None,
+    }
+}
+
+/// Format a duration as an iCal duration (e.g. `P1DT2H30M`, see [`parse_duration`]). Fractions of seconds are dropped
+pub fn format_duration(duration: &Duration) -> String {
+    let sign = if *duration < Duration::zero() { "-" } else { "" };
+    let mut seconds = duration.num_seconds().abs();
+    if seconds == 0 {
+        return "PT0S".to_string();
+    }
+    let days = seconds / 86_400;
+    seconds %= 86_400;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+
+    let mut formatted = format!("{}P", sign);
+    if days > 0 {
+        formatted.push_str(&format!("{}D", days));
+    }
+    if hours > 0 || minutes > 0 || seconds > 0 {
+        formatted.push('T');
+        for (value, unit) in [(hours, 'H'), (minutes, 'M'), (seconds, 'S')] {
+            if value > 0 {
+                formatted.push_str(&format!("{}{}", value, unit));
+            }
+        }
+    }
+    formatted
+}
+
+/// Parse an HTTP-date (e.g. a `Last-Modified` header, or a `DAV:getlastmodified` property, see RFC7231 section 7.1.1.1).
+///
+/// The preferred format (`Sun, 06 Nov 1994 08:49:37 GMT`) is accepted, as well as the obsolete RFC850 and asctime ones
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    const FORMATS: [&str; 3] = [
+        "%a, %d %b %Y %H:%M:%S GMT",
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+    ];
+    FORMATS.iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|naive| Utc.from_utc_datetime(&naive))
+        // Some servers use a numeric offset rather than "GMT"
+        .or_else(|| DateTime::parse_from_rfc2822(value).ok().map(|date| date.with_timezone(&Utc)))
+}
+
+/// Format a date as an HTTP-date (e.g. for an `If-Unmodified-Since` header)
+pub fn format_http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn tz_or(tzid: &str, fallback: &Tz) -> Tz {
+    match Tz::from_str(tzid) {
+        Ok(tz) => tz,
+        Err(_) => {
+            log::warn!("Unknown timezone {:?}, using {} instead", tzid, fallback.name());
+            *fallback
+        },
+    }
+}
+
+/// Convert a local date-time to UTC.
+/// Ambiguous times (when clocks go back) resolve to the earliest instant, and non-existent ones (when clocks go forward) are shifted by the length of the gap.
+fn local_to_utc(local: &NaiveDateTime, tz: &Tz) -> DateTime<Utc> {
+    tz.from_local_datetime(local).earliest()
+        .or_else(|| tz.from_local_datetime(&(*local + Duration::hours(1))).earliest())
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| DateTime::from_utc(*local, Utc))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(name: &str, params: Vec<(&str, &str)>, value: &str) -> Property {
+        Property {
+            name: name.to_string(),
+            params: if params.is_empty() { None } else {
+                Some(params.into_iter().map(|(k, v)| (k.to_string(), vec![v.to_string()])).collect())
+            },
+            value: Some(value.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let expected = Utc.ymd(1994, 11, 6).and_hms(8, 49, 37);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 09:49:37 +0100"), Some(expected));
+        assert_eq!(parse_http_date("1994-11-06T08:49:37Z"), None);
+        assert_eq!(format_http_date(&expected), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn test_parse_and_serialize_round_trip() {
+        let cases = vec![
+            (vec![("VALUE", "DATE")], "20210321", CalDateTime::Date(NaiveDate::from_ymd(2021, 3, 21))),
+            (vec![], "20210321T100000", CalDateTime::Floating(NaiveDate::from_ymd(2021, 3, 21).and_hms(10, 0, 0))),
+            (vec![], "20210321T100000Z", CalDateTime::Utc(Utc.ymd(2021, 3, 21).and_hms(10, 0, 0))),
+            (vec![("TZID", "Europe/Paris")], "20210321T100000", CalDateTime::Zoned{ tzid: "Europe/Paris".to_string(), local: NaiveDate::from_ymd(2021, 3, 21).and_hms(10, 0, 0) }),
+        ];
+
+        for (params, value, expected) in cases {
+            let prop = property("DUE", params.clone(), value);
+            let parsed = CalDateTime::from_property(&prop).unwrap();
+            assert_eq!(parsed, expected);
+
+            let (out_params, out_value) = parsed.to_ical_value();
+            assert_eq!(out_value, value);
+            let out_params: Vec<(&str, &str)> = out_params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            assert_eq!(out_params, params);
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("P1D"), Some(Duration::days(1)));
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1DT12H"), Some(Duration::hours(36)));
+        assert_eq!(parse_duration("-P2W"), Some(Duration::weeks(-2)));
+        assert_eq!(parse_duration("+PT15S"), Some(Duration::seconds(15)));
+        assert_eq!(parse_duration("P"), None);
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("1D"), None);
+        assert_eq!(parse_duration("PT5"), None);
+        // Durations that do not fit are refused rather than panicking
+        assert_eq!(parse_duration("P99999999999999999W"), None);
+        assert_eq!(parse_duration("P15250284452471D"), None);
+        assert_eq!(parse_duration("P106751991167DT24H"), None);
+        assert!(parse_duration("P106751991167D").is_some());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(&Duration::minutes(90)), "PT1H30M");
+        assert_eq!(format_duration(&Duration::hours(36)), "P1DT12H");
+        assert_eq!(format_duration(&Duration::weeks(-2)), "-P14D");
+        assert_eq!(format_duration(&Duration::zero()), "PT0S");
+        for value in ["P1D", "PT15S", "P2DT1H0M5S"] {
+            let duration = parse_duration(value).unwrap();
+            assert_eq!(parse_duration(&format_duration(&duration)), Some(duration));
+        }
+    }
+
+    #[test]
+    fn test_comparisons_in_a_reference_timezone() {
+        let paris: Tz = "Europe/Paris".parse().unwrap();
+        let floating = CalDateTime::Floating(NaiveDate::from_ymd(2021, 3, 21).and_hms(23, 30, 0));
+        let utc = CalDateTime::Utc(Utc.ymd(2021, 3, 21).and_hms(23, 0, 0));
+
+        // 23:30 in Paris is 22:30 UTC
+        assert_eq!(floating.cmp_in(&utc, &paris), Ordering::Less);
+        assert_eq!(floating.cmp_in(&utc, &Tz::UTC), Ordering::Greater);
+
+        // 23:00 UTC is already the next day in Paris
+        assert_eq!(utc.date_in(&paris), NaiveDate::from_ymd(2021, 3, 22));
+        assert_eq!(utc.date_in(&Tz::UTC), NaiveDate::from_ymd(2021, 3, 21));
+        // ...but floating values are not bound to any timezone
+        assert_eq!(floating.date_in(&Tz::UTC), NaiveDate::from_ymd(2021, 3, 21));
+
+        let date = CalDateTime::Date(NaiveDate::from_ymd(2021, 3, 22));
+        assert_eq!(date.to_utc(&paris), Utc.ymd(2021, 3, 21).and_hms(23, 0, 0));
+    }
+
+    #[test]
+    fn test_display_across_dst_transitions() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+
+        // Clocks go forward at 07:00 UTC (02:00 EST becomes 03:00 EDT)
+        assert_eq!(CalDateTime::Utc(Utc.ymd(2021, 3, 14).and_hms(6, 30, 0)).to_display(&new_york), NaiveDate::from_ymd(2021, 3, 14).and_hms(1, 30, 0));
+        assert_eq!(CalDateTime::Utc(Utc.ymd(2021, 3, 14).and_hms(7, 30, 0)).to_display(&new_york), NaiveDate::from_ymd(2021, 3, 14).and_hms(3, 30, 0));
+        // A zoned time that does not exist is shifted by the length of the gap
+        let skipped = CalDateTime::Zoned{ tzid: "America/New_York".to_string(), local: NaiveDate::from_ymd(2021, 3, 14).and_hms(2, 30, 0) };
+        assert_eq!(skipped.to_display(&new_york), NaiveDate::from_ymd(2021, 3, 14).and_hms(3, 30, 0));
+        // A zoned time that happens twice (clocks go back) is its first occurrence
+        let repeated = CalDateTime::Zoned{ tzid: "America/New_York".to_string(), local: NaiveDate::from_ymd(2021, 11, 7).and_hms(1, 30, 0) };
+        assert_eq!(repeated.to_utc(&Tz::UTC), Utc.ymd(2021, 11, 7).and_hms(5, 30, 0));
+        assert_eq!(repeated.to_display(&Tz::UTC), NaiveDate::from_ymd(2021, 11, 7).and_hms(5, 30, 0));
+
+        // Floating times and dates are never shifted
+        let floating = CalDateTime::Floating(NaiveDate::from_ymd(2021, 3, 14).and_hms(2, 30, 0));
+        assert_eq!(floating.to_display(&new_york), NaiveDate::from_ymd(2021, 3, 14).and_hms(2, 30, 0));
+        let date = CalDateTime::Date(NaiveDate::from_ymd(2021, 3, 14));
+        assert_eq!(date.to_display(&new_york), NaiveDate::from_ymd(2021, 3, 14).and_hms(0, 0, 0));
+        assert!(date.is_all_day());
+        assert!(!floating.is_all_day());
+
+        // A day that is 23 hours long still counts for one
+        let now = Utc.ymd(2021, 3, 14).and_hms(4, 0, 0); // 23:00 EST on March 13th
+        assert_eq!(CalDateTime::Date(NaiveDate::from_ymd(2021, 3, 15)).days_until(&now, &new_york), 2);
+        assert_eq!(CalDateTime::Utc(Utc.ymd(2021, 3, 15).and_hms(3, 59, 0)).days_until(&now, &new_york), 1);
+    }
+
+    #[test]
+    fn test_dates_across_negative_offsets() {
+        let honolulu: Tz = "Pacific/Honolulu".parse().unwrap();
+        let los_angeles: Tz = "America/Los_Angeles".parse().unwrap();
+        let tokyo: Tz = "Asia/Tokyo".parse().unwrap();
+        let date = CalDateTime::Date(NaiveDate::from_ymd(2021, 3, 21));
+
+        // 05:00 UTC is still March 21st in Honolulu (UTC-10) and in Los Angeles (UTC-7), but already March 22nd in Tokyo
+        let now = Utc.ymd(2021, 3, 22).and_hms(5, 0, 0);
+        assert_eq!(date.days_until(&now, &honolulu), 0);
+        assert_eq!(date.days_until(&now, &los_angeles), 0);
+        assert_eq!(date.days_until(&now, &tokyo), -1);
+        let now = CalDateTime::Utc(now);
+        assert_eq!(date.cmp_in_tz(&now, &honolulu), Ordering::Greater);
+        assert_eq!(date.cmp_in_tz(&now, &tokyo), Ordering::Less);
+
+        // Dates compare as the end of their day, so that they sort after the times of that day
+        let evening = CalDateTime::Zoned{ tzid: "Pacific/Honolulu".to_string(), local: NaiveDate::from_ymd(2021, 3, 21).and_hms(23, 0, 0) };
+        assert_eq!(date.cmp_in_tz(&evening, &honolulu), Ordering::Greater);
+        assert_eq!(date.cmp_in(&evening, &honolulu), Ordering::Less);
+        // ...of the timezone they are seen from: Honolulu evening is already March 22nd in Los Angeles
+        assert_eq!(date.cmp_in_tz(&evening, &los_angeles), Ordering::Less);
+        let midnight = CalDateTime::Floating(NaiveDate::from_ymd(2021, 3, 22).and_hms(0, 0, 0));
+        assert_eq!(date.cmp_in_tz(&midnight, &honolulu), Ordering::Equal);
+    }
+}
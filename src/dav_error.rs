@@ -0,0 +1,321 @@
+//! Errors returned by CalDAV servers
+//!
+//! When a request fails, servers often reply with a `<d:error>` body that names the precondition the request has violated (see [RFC4918](https://tools.ietf.org/html/rfc4918#section-16) and [RFC4791](https://tools.ietf.org/html/rfc4791#section-1.3))
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use reqwest::StatusCode;
+use url::Url;
+
+use crate::utils::parse_xml;
+use crate::error_code::{self, ErrorCode};
+
+/// The namespace of the elements sabre/dav adds to its error bodies (its version, the name of the exception, a human-readable message)
+const SABRE_NAMESPACE: &str = "http://sabredav.org/ns";
+
+/// The precondition a failed request has violated, as told by the `<d:error>` body of the reply
+#[derive(Clone, Debug, PartialEq)]
+pub enum DavErrorDetail {
+    /// `valid-calendar-data`: the server could not understand the iCal data of the item
+    ValidCalendarData,
+    /// `valid-calendar-object-resource`: the item does not obey the restrictions of CalDAV (e.g. it contains components of different kinds)
+    ValidCalendarObjectResource,
+    /// `supported-calendar-data`: the server does not support this media type
+    SupportedCalendarData,
+    /// `supported-calendar-component`: the calendar does not accept this kind of component (e.g. tasks)
+    SupportedCalendarComponent,
+    /// `no-uid-conflict`: another item of the calendar already has this UID.
+    /// `existing` is the (possibly relative) URL of this other item, in case the server told it
+    NoUidConflict { existing: Option<String> },
+    /// `max-resource-size`: the item is too large for this server
+    MaxResourceSize,
+    /// `min-date-time` or `max-date-time`: the item has dates out of the range the calendar accepts (see [`crate::calendar::DateLimits`])
+    DateOutOfRange,
+    /// `quota-not-exceeded`: the account is over its storage quota
+    QuotaNotExceeded,
+    /// `need-privileges`: the current user is not allowed to do this
+    NeedPrivileges,
+    /// `lock-token-submitted`: the resource is locked (e.g. by another client that is editing it), and the request did not submit the lock token
+    LockTokenSubmitted,
+    /// `valid-sync-token`: the server does not accept this sync token (anymore), the calendar must be listed in full (see [`crate::traits::DavCalendar::get_changes_since`])
+    ValidSyncToken,
+    /// Any other precondition. `raw` is the whole XML body of the reply
+    Other { name: String, raw: String },
+}
+
+impl DavErrorDetail {
+    /// Parse the `<d:error>` body of a reply. This returns `None` in case the body is not such an XML document, or names no precondition
+    pub fn parse(body: &str) -> Option<Self> {
+        let root = parse_xml(body).ok()?;
+        if root.name() != "error" {
+            return None;
+        }
+        let precondition = root.children().find(|child| child.ns() != SABRE_NAMESPACE)?;
+        Some(match precondition.name() {
+            "valid-calendar-data" => Self::ValidCalendarData,
+            "valid-calendar-object-resource" => Self::ValidCalendarObjectResource,
+            "supported-calendar-data" => Self::SupportedCalendarData,
+            "supported-calendar-component" => Self::SupportedCalendarComponent,
+            "no-uid-conflict" => Self::NoUidConflict {
+                existing: precondition.children()
+                    .find(|child| child.name() == "href")
+                    .map(|href| href.text().trim().to_string())
+                    .filter(|href| !href.is_empty()),
+            },
+            "max-resource-size" => Self::MaxResourceSize,
+            "min-date-time" | "max-date-time" => Self::DateOutOfRange,
+            "quota-not-exceeded" => Self::QuotaNotExceeded,
+            "need-privileges" => Self::NeedPrivileges,
+            "lock-token-submitted" => Self::LockTokenSubmitted,
+            "valid-sync-token" => Self::ValidSyncToken,
+            name => Self::Other { name: name.to_string(), raw: body.trim().to_string() },
+        })
+    }
+
+    /// The stable identifier of this precondition (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ValidCalendarData => error_code::VALID_CALENDAR_DATA,
+            Self::ValidCalendarObjectResource => error_code::VALID_CALENDAR_OBJECT_RESOURCE,
+            Self::SupportedCalendarData => error_code::SUPPORTED_CALENDAR_DATA,
+            Self::SupportedCalendarComponent => error_code::SUPPORTED_CALENDAR_COMPONENT,
+            Self::NoUidConflict { .. } => error_code::NO_UID_CONFLICT,
+            Self::MaxResourceSize => error_code::MAX_RESOURCE_SIZE,
+            Self::DateOutOfRange => error_code::DATE_OUT_OF_RANGE,
+            Self::QuotaNotExceeded => error_code::QUOTA_EXCEEDED,
+            Self::NeedPrivileges => error_code::NEED_PRIVILEGES,
+            Self::LockTokenSubmitted => error_code::LOCK_TOKEN_SUBMITTED,
+            Self::ValidSyncToken => error_code::VALID_SYNC_TOKEN,
+            Self::Other { .. } => error_code::OTHER_PRECONDITION,
+        }
+    }
+
+    /// Whether the server has refused the content of the item itself (rather than e.g. its size or the state of the calendar)
+    pub fn is_invalid_item(&self) -> bool {
+        matches!(self,
+            Self::ValidCalendarData | Self::ValidCalendarObjectResource | Self::SupportedCalendarData | Self::SupportedCalendarComponent)
+    }
+}
+
+impl Display for DavErrorDetail {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::ValidCalendarData => write!(f, "valid-calendar-data"),
+            Self::ValidCalendarObjectResource => write!(f, "valid-calendar-object-resource"),
+            Self::SupportedCalendarData => write!(f, "supported-calendar-data"),
+            Self::SupportedCalendarComponent => write!(f, "supported-calendar-component"),
+            Self::NoUidConflict { existing: None } => write!(f, "no-uid-conflict"),
+            Self::NoUidConflict { existing: Some(existing) } => write!(f, "no-uid-conflict with {}", existing),
+            Self::MaxResourceSize => write!(f, "max-resource-size"),
+            Self::DateOutOfRange => write!(f, "min-date-time or max-date-time"),
+            Self::QuotaNotExceeded => write!(f, "quota-not-exceeded"),
+            Self::NeedPrivileges => write!(f, "need-privileges"),
+            Self::LockTokenSubmitted => write!(f, "lock-token-submitted"),
+            Self::ValidSyncToken => write!(f, "valid-sync-token"),
+            Self::Other { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The error returned when the server replies with an unexpected HTTP status code
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: StatusCode,
+    /// The precondition the request has violated, in case the server told it
+    pub detail: Option<DavErrorDetail>,
+    /// A human-readable description of the error, in case the server gave one (e.g. sabre/dav does)
+    pub message: Option<String>,
+    /// The identifier of the request, in case one has been sent (see [`crate::config::REQUEST_ID_HEADER`])
+    pub request_id: Option<String>,
+}
+
+impl HttpError {
+    /// Create an error from the status and the body of a reply
+    pub fn from_reply(status: StatusCode, body: &str) -> Self {
+        let message = parse_xml(body).ok()
+            .and_then(|root| root.get_child("message", SABRE_NAMESPACE).map(|message| message.text()));
+        Self { status, detail: DavErrorDetail::parse(body), message, request_id: None }
+    }
+
+    /// The stable identifier of this error (see [`crate::error_code`]). This is the one of the violated precondition, in case the server told it
+    pub fn code(&self) -> ErrorCode {
+        if let Some(detail) = &self.detail {
+            return detail.code();
+        }
+        match self.status {
+            StatusCode::UNAUTHORIZED => error_code::UNAUTHORIZED,
+            StatusCode::FORBIDDEN => error_code::FORBIDDEN,
+            StatusCode::NOT_FOUND => error_code::NOT_FOUND,
+            StatusCode::INSUFFICIENT_STORAGE => error_code::QUOTA_EXCEEDED,
+            StatusCode::LOCKED => error_code::LOCKED,
+            status if status.is_server_error() => error_code::SERVER_ERROR,
+            _ => error_code::UNEXPECTED_STATUS,
+        }
+    }
+
+    /// Whether the request has been refused because its resource is locked. It is likely to succeed once the lock is released
+    pub fn is_locked(&self) -> bool {
+        self.status == StatusCode::LOCKED || self.detail == Some(DavErrorDetail::LockTokenSubmitted)
+    }
+
+    pub(crate) fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Unexpected HTTP status code {:?}", self.status)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request id {})", request_id)?;
+        }
+        write!(f, " [{}]", self.code())
+    }
+}
+
+impl Error for HttpError {}
+
+/// The error returned when a request goes round in circles, either because it has been redirected in a loop, or because the server has detected a loop itself (`508 Loop Detected`).
+///
+/// This is usually caused by a misconfigured reverse proxy
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoopDetectedError {
+    /// The URL that has been requested
+    pub url: Url,
+    /// The URLs the request has been redirected to, in order. This is empty in case the server has replied with a `508 Loop Detected`
+    pub redirects: Vec<Url>,
+}
+
+impl LoopDetectedError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::LOOP_DETECTED
+    }
+}
+
+impl Display for LoopDetectedError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.redirects.is_empty() {
+            true => write!(f, "The server has detected a loop while handling {} (HTTP 508 Loop Detected)", self.url)?,
+            false => {
+                write!(f, "The request to {} has been redirected in a loop (", self.url)?;
+                for (i, url) in self.redirects.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { " -> " }, url)?;
+                }
+                write!(f, ")")?;
+            },
+        }
+        write!(f, ". This is usually caused by a misconfigured reverse proxy [{}]", self.code())
+    }
+}
+
+impl Error for LoopDetectedError {}
+
+/// The error returned when the reply of a server is larger than the limit that applies to it (see [`crate::config::MAX_RESPONSE_SIZE`] and [`crate::config::MAX_ITEM_SIZE`])
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseTooLarge {
+    /// The request whose reply is too large (e.g. `REPORT https://my.server.com/calendars/john/tasks/`)
+    pub request: String,
+    /// The limit, in bytes
+    pub limit: usize,
+}
+
+impl ResponseTooLarge {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::RESPONSE_TOO_LARGE
+    }
+}
+
+impl Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "The reply to {} is larger than {} bytes, it has not been read [{}]", self.request, self.limit, self.code())
+    }
+}
+
+impl Error for ResponseTooLarge {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// As returned by sabre/dav (e.g. Nextcloud) when an item has the UID of another one
+    const SABRE_UID_CONFLICT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:error xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <s:sabredav-version>4.1.5</s:sabredav-version>
+  <s:exception>Sabre\DAV\Exception\Conflict</s:exception>
+  <s:message>A calendar object with this UID already exists</s:message>
+  <cal:no-uid-conflict><d:href>/remote.php/dav/calendars/john/tasks/other.ics</d:href></cal:no-uid-conflict>
+</d:error>
+"#;
+
+    /// As returned by sabre/dav when an item contains a component the calendar does not accept
+    const SABRE_UNSUPPORTED_COMPONENT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:error xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <s:sabredav-version>4.1.5</s:sabredav-version>
+  <s:exception>Sabre\CalDAV\Exception\InvalidComponentType</s:exception>
+  <s:message>This calendar only supports VEVENT. We found a VTODO</s:message>
+  <cal:supported-calendar-component/>
+</d:error>
+"#;
+
+    /// As returned by sabre/dav for exceptions that have no precondition
+    const SABRE_BAD_REQUEST: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:error xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns">
+  <s:sabredav-version>4.1.5</s:sabredav-version>
+  <s:exception>Sabre\DAV\Exception\BadRequest</s:exception>
+  <s:message>Calendar objects must have a VERSION property</s:message>
+</d:error>
+"#;
+
+    /// As returned by Radicale
+    const RADICALE_UID_CONFLICT: &str = r#"<?xml version='1.0' encoding='utf-8'?>
+<D:error xmlns:C="urn:ietf:params:xml:ns:caldav" xmlns:D="DAV:"><C:no-uid-conflict /></D:error>"#;
+
+    const RADICALE_VALID_CALENDAR_DATA: &str = r#"<?xml version='1.0' encoding='utf-8'?>
+<D:error xmlns:C="urn:ietf:params:xml:ns:caldav" xmlns:D="DAV:"><C:valid-calendar-data /></D:error>"#;
+
+    const UNKNOWN_PRECONDITION: &str = r#"<d:error xmlns:d="DAV:"><d:cannot-modify-protected-property/></d:error>"#;
+
+    const LOCKED: &str = r#"<d:error xmlns:d="DAV:"><d:lock-token-submitted><d:href>/locked/</d:href></d:lock-token-submitted></d:error>"#;
+
+    #[test]
+    fn test_parse_dav_errors() {
+        assert_eq!(DavErrorDetail::parse(SABRE_UID_CONFLICT), Some(DavErrorDetail::NoUidConflict {
+            existing: Some("/remote.php/dav/calendars/john/tasks/other.ics".to_string())
+        }));
+        assert_eq!(DavErrorDetail::parse(SABRE_UNSUPPORTED_COMPONENT), Some(DavErrorDetail::SupportedCalendarComponent));
+        assert_eq!(DavErrorDetail::parse(SABRE_BAD_REQUEST), None);
+        assert_eq!(DavErrorDetail::parse(RADICALE_UID_CONFLICT), Some(DavErrorDetail::NoUidConflict { existing: None }));
+        assert_eq!(DavErrorDetail::parse(RADICALE_VALID_CALENDAR_DATA), Some(DavErrorDetail::ValidCalendarData));
+        assert_eq!(DavErrorDetail::parse(UNKNOWN_PRECONDITION), Some(DavErrorDetail::Other {
+            name: "cannot-modify-protected-property".to_string(),
+            raw: UNKNOWN_PRECONDITION.to_string(),
+        }));
+        assert_eq!(DavErrorDetail::parse(LOCKED), Some(DavErrorDetail::LockTokenSubmitted));
+        assert_eq!(DavErrorDetail::parse(r#"<d:error xmlns:d="DAV:"><d:valid-sync-token/></d:error>"#), Some(DavErrorDetail::ValidSyncToken));
+        assert_eq!(DavErrorDetail::parse("Forbidden"), None);
+        assert_eq!(DavErrorDetail::parse(""), None);
+
+        let err = HttpError::from_reply(StatusCode::FORBIDDEN, SABRE_UNSUPPORTED_COMPONENT);
+        assert!(err.detail.as_ref().unwrap().is_invalid_item());
+        assert_eq!(err.message.as_deref(), Some("This calendar only supports VEVENT. We found a VTODO"));
+        assert_eq!(err.to_string(), "Unexpected HTTP status code 403 (supported-calendar-component): This calendar only supports VEVENT. We found a VTODO [KF-DAV-004 supported-calendar-component]");
+
+        let err = HttpError::from_reply(StatusCode::NOT_FOUND, "").with_request_id(Some("some-id".to_string()));
+        assert_eq!(err.to_string(), "Unexpected HTTP status code 404 (request id some-id) [KF-HTTP-004 not-found]");
+
+        assert!(!err.is_locked());
+        assert!(HttpError::from_reply(StatusCode::LOCKED, "").is_locked());
+        assert!(HttpError::from_reply(StatusCode::FORBIDDEN, LOCKED).is_locked());
+    }
+}
@@ -0,0 +1,160 @@
+//! The errors of the requests this crate sends to CalDAV servers
+//!
+//! Discovering the principal, the calendar home set and the calendars of a [`crate::Client`] fails with a [`KitchenFridgeError`], that tells e.g. an authentication failure (the user should be prompted for new credentials) from a network failure (the request can just be retried later).
+//! Since most methods of this crate return a `Box<dyn Error>`, such errors are usually boxed: use [`KitchenFridgeError::of`] to get them back.
+
+use std::error::Error;
+
+use crate::client::{Throttled, TruncatedReply};
+use crate::dav_error::{HttpError, LoopDetectedError, ResponseTooLarge};
+use crate::error_code::{self, ErrorCode};
+use crate::resource::{CredentialsError, CrossOriginError};
+
+/// Why a request to a CalDAV server has failed
+///
+/// The largest errors are boxed, so that a `Result` that may hold this error is not much larger than its `Ok` value
+#[derive(Debug, thiserror::Error)]
+pub enum KitchenFridgeError {
+    /// The request could not be sent, or its reply could not be received (e.g. the server is unreachable)
+    #[error(transparent)]
+    Http(reqwest::Error),
+    /// The server has not replied in time (see [`crate::Client::set_timeout`]). The same request may well succeed later
+    #[error("{0} [{code}]", code = error_code::TIMEOUT)]
+    Timeout(reqwest::Error),
+    /// A URL (e.g. an `<href>` returned by the server) is invalid
+    #[error(transparent)]
+    UrlParse(#[from] url::ParseError),
+    /// The server has replied with an invalid XML document, or with one that exceeds the configured limits (see [`crate::config::XML_MAX_DEPTH`])
+    #[error("invalid XML document: {0}")]
+    XmlParse(String),
+    /// A URL returned by the server is on another origin, so that it is not sent the credentials (see [`crate::resource::Resource::combine`])
+    #[error(transparent)]
+    CrossOrigin(Box<CrossOriginError>),
+    /// The reply of the server lacks an element that is required (e.g. `calendar-home-set`)
+    #[error("missing element {0}")]
+    MissingElement(String),
+    /// The server has refused the credentials (this is an [`HttpError`] with a `401 Unauthorized` status), or they could not be provided (this is a [`CredentialsError`])
+    #[error(transparent)]
+    Auth(Box<dyn Error + Send + Sync>),
+    /// The server has replied with an unexpected HTTP status code (other than `401 Unauthorized`)
+    #[error(transparent)]
+    Status(Box<HttpError>),
+    #[error(transparent)]
+    LoopDetected(Box<LoopDetectedError>),
+    #[error(transparent)]
+    ResponseTooLarge(#[from] ResponseTooLarge),
+    #[error(transparent)]
+    TruncatedReply(#[from] TruncatedReply),
+    /// The server throttles requests, and has asked to wait too long before sending them again (see [`crate::client::RetryPolicy::max_retry_after`])
+    #[error(transparent)]
+    Throttled(#[from] Throttled),
+}
+
+impl KitchenFridgeError {
+    /// Returns the [`KitchenFridgeError`] a (usually boxed) error is, if it is one
+    pub fn of<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a Self> {
+        err.downcast_ref::<Self>()
+    }
+
+    /// The stable identifier of this error (see [`crate::error_code`]), or `None` in case it has none (e.g. network failures)
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::Http(_) | Self::UrlParse(_) | Self::XmlParse(_) | Self::MissingElement(_) => None,
+            Self::Timeout(_) => Some(error_code::TIMEOUT),
+            Self::CrossOrigin(err) => Some(err.code()),
+            Self::Auth(err) => error_code::code_of(&**err),
+            Self::Status(err) => Some(err.code()),
+            Self::LoopDetected(err) => Some(err.code()),
+            Self::ResponseTooLarge(err) => Some(err.code()),
+            Self::TruncatedReply(err) => Some(err.code()),
+            Self::Throttled(err) => Some(err.code()),
+        }
+    }
+
+    /// Whether the server has not replied in time, be it before it has started to reply, or while its reply was being received
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Self::Timeout(_) => true,
+            Self::TruncatedReply(err) => err.cause.is_timeout(),
+            _ => false,
+        }
+    }
+
+    /// The error this one wraps, or itself in case it wraps none
+    fn inner(&self) -> &(dyn Error + 'static) {
+        match self {
+            Self::Http(err) | Self::Timeout(err) => err,
+            Self::UrlParse(err) => err,
+            Self::CrossOrigin(err) => &**err,
+            Self::XmlParse(_) | Self::MissingElement(_) => self,
+            Self::Auth(err) => &**err,
+            Self::Status(err) => &**err,
+            Self::LoopDetected(err) => &**err,
+            Self::ResponseTooLarge(err) => err,
+            Self::TruncatedReply(err) => err,
+            Self::Throttled(err) => err,
+        }
+    }
+}
+
+impl From<reqwest::Error> for KitchenFridgeError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.is_timeout() {
+            true => Self::Timeout(err),
+            false => Self::Http(err),
+        }
+    }
+}
+
+impl From<HttpError> for KitchenFridgeError {
+    fn from(err: HttpError) -> Self {
+        match err.status {
+            reqwest::StatusCode::UNAUTHORIZED => Self::Auth(Box::new(err)),
+            _ => Self::Status(Box::new(err)),
+        }
+    }
+}
+
+impl From<CrossOriginError> for KitchenFridgeError {
+    fn from(err: CrossOriginError) -> Self {
+        Self::CrossOrigin(Box::new(err))
+    }
+}
+
+impl From<LoopDetectedError> for KitchenFridgeError {
+    fn from(err: LoopDetectedError) -> Self {
+        Self::LoopDetected(Box::new(err))
+    }
+}
+
+impl From<CredentialsError> for KitchenFridgeError {
+    fn from(err: CredentialsError) -> Self {
+        Self::Auth(Box::new(err))
+    }
+}
+
+/// Returns the error of type `E` an error is, or the one it wraps in case it is a [`KitchenFridgeError`]
+pub(crate) fn downcast_ref<'a, E: Error + 'static>(err: &'a (dyn Error + 'static)) -> Option<&'a E> {
+    match KitchenFridgeError::of(err) {
+        Some(err) => err.inner().downcast_ref::<E>(),
+        None => err.downcast_ref::<E>(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_failures() {
+        let err = KitchenFridgeError::from(HttpError::from_reply(reqwest::StatusCode::UNAUTHORIZED, ""));
+        assert!(matches!(err, KitchenFridgeError::Auth(_)));
+        assert_eq!(err.code(), Some(error_code::UNAUTHORIZED));
+
+        let err: Box<dyn Error> = Box::new(KitchenFridgeError::from(HttpError::from_reply(reqwest::StatusCode::FORBIDDEN, "")));
+        assert!(matches!(KitchenFridgeError::of(&*err), Some(KitchenFridgeError::Status(_))));
+        assert_eq!(downcast_ref::<HttpError>(&*err).unwrap().status, reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(error_code::code_of(&*err), Some(error_code::FORBIDDEN));
+    }
+}
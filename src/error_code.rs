@@ -0,0 +1,245 @@
+//! Stable identifiers of errors and sync conflicts
+//!
+//! Error messages may change from one version to another, but codes do not: they can be used to tell whether two bug reports are about the same issue.
+//! Every error of this crate and every kind of conflict (see [`crate::provider::conflict::ConflictKind`]) has a `code()`, that is also part of its `Display` output.
+//!
+//! A code is never reused with another meaning: codes that become unused are kept in [`ALL`], and new codes get a new number.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// A stable identifier, e.g. `KF-SYNC-003 modified-locally-deleted-remotely`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ErrorCode {
+    /// The identifier itself, e.g. `KF-SYNC-003`
+    pub code: &'static str,
+    /// A short name of what it means, e.g. `modified-locally-deleted-remotely`
+    pub name: &'static str,
+}
+
+impl ErrorCode {
+    const fn new(code: &'static str, name: &'static str) -> Self {
+        Self { code, name }
+    }
+
+    /// Find a code from its identifier (e.g. `KF-SYNC-003`)
+    pub fn from_code(code: &str) -> Option<Self> {
+        ALL.iter().find(|known| known.code == code).copied()
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.name)
+    }
+}
+
+pub const UNEXPECTED_STATUS: ErrorCode = ErrorCode::new("KF-HTTP-001", "unexpected-status");
+pub const UNAUTHORIZED: ErrorCode = ErrorCode::new("KF-HTTP-002", "unauthorized");
+pub const FORBIDDEN: ErrorCode = ErrorCode::new("KF-HTTP-003", "forbidden");
+pub const NOT_FOUND: ErrorCode = ErrorCode::new("KF-HTTP-004", "not-found");
+pub const SERVER_ERROR: ErrorCode = ErrorCode::new("KF-HTTP-005", "server-error");
+pub const LOOP_DETECTED: ErrorCode = ErrorCode::new("KF-HTTP-006", "loop-detected");
+pub const RESPONSE_TOO_LARGE: ErrorCode = ErrorCode::new("KF-HTTP-007", "response-too-large");
+pub const TRUNCATED_REPLY: ErrorCode = ErrorCode::new("KF-HTTP-008", "truncated-reply");
+pub const PARTIAL_FETCH: ErrorCode = ErrorCode::new("KF-HTTP-009", "partial-fetch");
+pub const TIMEOUT: ErrorCode = ErrorCode::new("KF-HTTP-010", "timeout");
+pub const LOCKED: ErrorCode = ErrorCode::new("KF-HTTP-011", "locked");
+pub const THROTTLED: ErrorCode = ErrorCode::new("KF-HTTP-012", "throttled");
+pub const PRECONDITION_FAILED: ErrorCode = ErrorCode::new("KF-HTTP-013", "precondition-failed");
+pub const CROSS_ORIGIN_URL: ErrorCode = ErrorCode::new("KF-HTTP-014", "cross-origin-url");
+
+pub const VALID_CALENDAR_DATA: ErrorCode = ErrorCode::new("KF-DAV-001", "valid-calendar-data");
+pub const VALID_CALENDAR_OBJECT_RESOURCE: ErrorCode = ErrorCode::new("KF-DAV-002", "valid-calendar-object-resource");
+pub const SUPPORTED_CALENDAR_DATA: ErrorCode = ErrorCode::new("KF-DAV-003", "supported-calendar-data");
+pub const SUPPORTED_CALENDAR_COMPONENT: ErrorCode = ErrorCode::new("KF-DAV-004", "supported-calendar-component");
+pub const NO_UID_CONFLICT: ErrorCode = ErrorCode::new("KF-DAV-005", "no-uid-conflict");
+pub const MAX_RESOURCE_SIZE: ErrorCode = ErrorCode::new("KF-DAV-006", "max-resource-size");
+pub const DATE_OUT_OF_RANGE: ErrorCode = ErrorCode::new("KF-DAV-007", "date-out-of-range");
+pub const QUOTA_EXCEEDED: ErrorCode = ErrorCode::new("KF-DAV-008", "quota-exceeded");
+pub const NEED_PRIVILEGES: ErrorCode = ErrorCode::new("KF-DAV-009", "need-privileges");
+pub const OTHER_PRECONDITION: ErrorCode = ErrorCode::new("KF-DAV-010", "other-precondition");
+pub const LOCK_TOKEN_SUBMITTED: ErrorCode = ErrorCode::new("KF-DAV-011", "lock-token-submitted");
+pub const VALID_SYNC_TOKEN: ErrorCode = ErrorCode::new("KF-DAV-012", "valid-sync-token");
+
+pub const NO_TRUST_STORE: ErrorCode = ErrorCode::new("KF-TLS-001", "no-trust-store");
+pub const BUNDLED_ROOTS_UNAVAILABLE: ErrorCode = ErrorCode::new("KF-TLS-002", "bundled-roots-unavailable");
+pub const TLS_BACKEND: ErrorCode = ErrorCode::new("KF-TLS-003", "tls-backend");
+
+pub const SRV_LOOKUP_FAILED: ErrorCode = ErrorCode::new("KF-DISC-001", "srv-lookup-failed");
+pub const NO_CALDAV_SERVICE: ErrorCode = ErrorCode::new("KF-DISC-002", "no-caldav-service");
+pub const UNTRUSTED_SRV_TARGET: ErrorCode = ErrorCode::new("KF-DISC-003", "untrusted-srv-target");
+
+pub const MODIFIED_IN_BOTH: ErrorCode = ErrorCode::new("KF-SYNC-001", "modified-in-both");
+pub const DELETED_LOCALLY_MODIFIED_REMOTELY: ErrorCode = ErrorCode::new("KF-SYNC-002", "deleted-locally-modified-remotely");
+pub const MODIFIED_LOCALLY_DELETED_REMOTELY: ErrorCode = ErrorCode::new("KF-SYNC-003", "modified-locally-deleted-remotely");
+pub const URL_REUSE: ErrorCode = ErrorCode::new("KF-SYNC-004", "url-reuse");
+pub const UID_ALREADY_ON_SERVER: ErrorCode = ErrorCode::new("KF-SYNC-005", "uid-already-on-server");
+pub const COMPONENT_TYPE_CHANGED: ErrorCode = ErrorCode::new("KF-SYNC-006", "component-type-changed");
+pub const CHANGED_DURING_SYNC: ErrorCode = ErrorCode::new("KF-SYNC-007", "changed-during-sync");
+pub const DIVERGENT_SYNC: ErrorCode = ErrorCode::new("KF-SYNC-008", "divergent-sync");
+pub const UPLOAD_ALTERED: ErrorCode = ErrorCode::new("KF-SYNC-009", "upload-altered");
+
+pub const UNSUPPORTED_COMPONENT: ErrorCode = ErrorCode::new("KF-ICAL-001", "unsupported-component");
+
+pub const CREDENTIALS_UNAVAILABLE: ErrorCode = ErrorCode::new("KF-AUTH-001", "credentials-unavailable");
+
+/// Every code, including the ones that are not used any more
+pub const ALL: &[ErrorCode] = &[
+    UNEXPECTED_STATUS,
+    UNAUTHORIZED,
+    FORBIDDEN,
+    NOT_FOUND,
+    SERVER_ERROR,
+    LOOP_DETECTED,
+    RESPONSE_TOO_LARGE,
+    TRUNCATED_REPLY,
+    PARTIAL_FETCH,
+    TIMEOUT,
+    LOCKED,
+    THROTTLED,
+    PRECONDITION_FAILED,
+    CROSS_ORIGIN_URL,
+
+    VALID_CALENDAR_DATA,
+    VALID_CALENDAR_OBJECT_RESOURCE,
+    SUPPORTED_CALENDAR_DATA,
+    SUPPORTED_CALENDAR_COMPONENT,
+    NO_UID_CONFLICT,
+    MAX_RESOURCE_SIZE,
+    DATE_OUT_OF_RANGE,
+    QUOTA_EXCEEDED,
+    NEED_PRIVILEGES,
+    OTHER_PRECONDITION,
+    LOCK_TOKEN_SUBMITTED,
+    VALID_SYNC_TOKEN,
+
+    NO_TRUST_STORE,
+    BUNDLED_ROOTS_UNAVAILABLE,
+    TLS_BACKEND,
+
+    SRV_LOOKUP_FAILED,
+    NO_CALDAV_SERVICE,
+    UNTRUSTED_SRV_TARGET,
+
+    MODIFIED_IN_BOTH,
+    DELETED_LOCALLY_MODIFIED_REMOTELY,
+    MODIFIED_LOCALLY_DELETED_REMOTELY,
+    URL_REUSE,
+    UID_ALREADY_ON_SERVER,
+    COMPONENT_TYPE_CHANGED,
+    CHANGED_DURING_SYNC,
+    DIVERGENT_SYNC,
+    UPLOAD_ALTERED,
+
+    UNSUPPORTED_COMPONENT,
+
+    CREDENTIALS_UNAVAILABLE,
+];
+
+/// The code of an error returned by this crate, or `None` in case it has no code (e.g. it comes from a dependency)
+pub fn code_of(err: &(dyn Error + 'static)) -> Option<ErrorCode> {
+    use crate::dav_error::{HttpError, LoopDetectedError, ResponseTooLarge};
+    use crate::calendar::{PartialFetchError, PreconditionFailedError, QuotaExceededError};
+    use crate::client::{Throttled, TlsSetupError, TruncatedReply};
+    use crate::client::discovery::DiscoveryError;
+    use crate::ical::UnsupportedComponentError;
+    use crate::resource::{CredentialsError, CrossOriginError};
+    use crate::error::KitchenFridgeError;
+
+    if let Some(err) = err.downcast_ref::<KitchenFridgeError>() { return err.code(); }
+    if let Some(err) = err.downcast_ref::<HttpError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<LoopDetectedError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<ResponseTooLarge>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<TruncatedReply>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<Throttled>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<PartialFetchError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<QuotaExceededError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<PreconditionFailedError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<TlsSetupError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<DiscoveryError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<UnsupportedComponentError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<CredentialsError>() { return Some(err.code()); }
+    if let Some(err) = err.downcast_ref::<CrossOriginError>() { return Some(err.code()); }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Every code that has ever been published, with its meaning. Entries must never be changed nor removed, only appended
+    const PUBLISHED: &[(&str, &str)] = &[
+        ("KF-HTTP-001", "unexpected-status"),
+        ("KF-HTTP-002", "unauthorized"),
+        ("KF-HTTP-003", "forbidden"),
+        ("KF-HTTP-004", "not-found"),
+        ("KF-HTTP-005", "server-error"),
+        ("KF-HTTP-006", "loop-detected"),
+        ("KF-HTTP-007", "response-too-large"),
+        ("KF-HTTP-008", "truncated-reply"),
+        ("KF-HTTP-009", "partial-fetch"),
+        ("KF-DAV-001", "valid-calendar-data"),
+        ("KF-DAV-002", "valid-calendar-object-resource"),
+        ("KF-DAV-003", "supported-calendar-data"),
+        ("KF-DAV-004", "supported-calendar-component"),
+        ("KF-DAV-005", "no-uid-conflict"),
+        ("KF-DAV-006", "max-resource-size"),
+        ("KF-DAV-007", "date-out-of-range"),
+        ("KF-DAV-008", "quota-exceeded"),
+        ("KF-DAV-009", "need-privileges"),
+        ("KF-DAV-010", "other-precondition"),
+        ("KF-TLS-001", "no-trust-store"),
+        ("KF-TLS-002", "bundled-roots-unavailable"),
+        ("KF-TLS-003", "tls-backend"),
+        ("KF-SYNC-001", "modified-in-both"),
+        ("KF-SYNC-002", "deleted-locally-modified-remotely"),
+        ("KF-SYNC-003", "modified-locally-deleted-remotely"),
+        ("KF-SYNC-004", "url-reuse"),
+        ("KF-SYNC-005", "uid-already-on-server"),
+        ("KF-SYNC-006", "component-type-changed"),
+        ("KF-SYNC-007", "changed-during-sync"),
+        ("KF-ICAL-001", "unsupported-component"),
+        ("KF-AUTH-001", "credentials-unavailable"),
+        ("KF-HTTP-010", "timeout"),
+        ("KF-SYNC-008", "divergent-sync"),
+        ("KF-HTTP-011", "locked"),
+        ("KF-DAV-011", "lock-token-submitted"),
+        ("KF-HTTP-012", "throttled"),
+        ("KF-SYNC-009", "upload-altered"),
+        ("KF-DAV-012", "valid-sync-token"),
+        ("KF-DISC-001", "srv-lookup-failed"),
+        ("KF-DISC-002", "no-caldav-service"),
+        ("KF-DISC-003", "untrusted-srv-target"),
+        ("KF-HTTP-013", "precondition-failed"),
+        ("KF-HTTP-014", "cross-origin-url"),
+    ];
+
+    #[test]
+    fn test_codes_are_never_reused() {
+        let codes: HashSet<&str> = ALL.iter().map(|code| code.code).collect();
+        let names: HashSet<&str> = ALL.iter().map(|code| code.name).collect();
+        assert_eq!(codes.len(), ALL.len(), "a code is used for several meanings");
+        assert_eq!(names.len(), ALL.len(), "a meaning has several codes");
+
+        for (code, name) in PUBLISHED {
+            assert_eq!(ErrorCode::from_code(code).map(|known| known.name), Some(*name), "published code {} has been changed or removed", code);
+        }
+        assert_eq!(ALL.len(), PUBLISHED.len(), "new codes must be added to the list of published codes");
+    }
+
+    #[test]
+    fn test_code_of() {
+        use crate::dav_error::{DavErrorDetail, HttpError};
+
+        let err: Box<dyn Error> = Box::new(HttpError::from_reply(reqwest::StatusCode::UNAUTHORIZED, ""));
+        assert_eq!(code_of(&*err), Some(UNAUTHORIZED));
+        let mut err = HttpError::from_reply(reqwest::StatusCode::CONFLICT, "");
+        err.detail = Some(DavErrorDetail::NoUidConflict { existing: None });
+        assert_eq!(code_of(&err), Some(NO_UID_CONFLICT));
+        assert_eq!(code_of(&*Box::<dyn Error>::from("some error")), None);
+        assert_eq!(MODIFIED_LOCALLY_DELETED_REMOTELY.to_string(), "KF-SYNC-003 modified-locally-deleted-remotely");
+    }
+}
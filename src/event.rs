@@ -1,58 +1,251 @@
 //! Calendar events (iCal `VEVENT` items)
 
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use chrono::{DateTime, Utc};
+use ical::property::Property;
 use url::Url;
 
-use crate::item::SyncStatus;
+use crate::item::{RawComponent, SyncStatus};
+use crate::utils::random_url;
+use crate::datetime::CalDateTime;
+use crate::provider::conflict::DerivedIdentity;
 
-/// TODO: implement `Event` one day.
-/// This crate currently only supports tasks, not calendar events.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A calendar event (iCal `VEVENT`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Event {
+    /// The event URL
+    url: Url,
+
+    /// Persistent, globally unique identifier for the calendar component
+    /// The [RFC](https://tools.ietf.org/html/rfc5545#page-117) recommends concatenating a timestamp with the server's domain name.
+    /// UUID are even better so we'll generate them, but we have to support events from the server, that may have any arbitrary strings here.
     uid: String,
-    name: String,
+
+    /// The sync status of this item
     sync_status: SyncStatus,
+    /// The time this item was created.
+    /// This is not required by RFC5545. This will be populated in events created by this crate, but can be None for events coming from a server
+    creation_date: Option<DateTime<Utc>>,
+    /// The last time this item was modified
+    last_modified: DateTime<Utc>,
+
+    /// The display name of the event (iCal `SUMMARY`)
+    name: String,
+    /// The date this event starts (iCal `DTSTART`)
+    start: Option<CalDateTime>,
+    /// The date this event ends (iCal `DTEND`)
+    end: Option<CalDateTime>,
+    /// Where this event takes place (iCal `LOCATION`)
+    location: Option<String>,
+
+    /// The alarms of this event (iCal `VALARM` components)
+    alarms: Vec<RawComponent>,
+
+    /// The PRODID, as defined in iCal files
+    ical_prod_id: String,
+
+    /// Extra parameters that have not been parsed from the iCal file (because they're not supported (yet) by this crate).
+    /// They are needed to serialize this item into an equivalent iCal file
+    extra_parameters: Vec<Property>,
+
+    /// Other components that share the UID of this event in the same iCal resource (e.g. the instances of a recurring event that have been changed).
+    /// They are needed to serialize this item into an equivalent iCal file
+    extra_components: Vec<RawComponent>,
 }
 
 impl Event {
-    pub fn new() -> Self {
-        unimplemented!();
+    /// Create a brand new Event that is not on a server yet.
+    /// This will pick a new (random) event ID.
+    pub fn new(name: String, start: Option<CalDateTime>, end: Option<CalDateTime>, parent_calendar_url: &Url) -> Self {
+        let new_url = random_url(parent_calendar_url);
+        let new_uid = Uuid::new_v4().to_hyphenated().to_string();
+        Self::new_with_parameters(name, new_uid, new_url, SyncStatus::NotSynced, Some(Utc::now()), Utc::now(), crate::ical::default_prod_id(), Vec::new())
+            .with_dates(start, end)
     }
 
-    pub fn url(&self) -> &Url {
-        unimplemented!();
+    /// Create a new Event instance, that may be synced on the server already
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_parameters(name: String, uid: String, new_url: Url,
+                               sync_status: SyncStatus, creation_date: Option<DateTime<Utc>>, last_modified: DateTime<Utc>,
+                               ical_prod_id: String, extra_parameters: Vec<Property>,
+                            ) -> Self
+    {
+        Self {
+            url: new_url,
+            uid,
+            sync_status,
+            creation_date,
+            last_modified,
+            name,
+            start: None,
+            end: None,
+            location: None,
+            alarms: Vec::new(),
+            ical_prod_id,
+            extra_parameters,
+            extra_components: Vec::new(),
+        }
     }
 
-    pub fn uid(&self) -> &str {
-        &self.uid
+    /// Set the start and end dates of an Event that is being created (e.g. out of an iCal file).
+    /// Contrary to [`Event::set_start`], this does not alter its sync status nor its last modification date
+    pub(crate) fn with_dates(mut self, start: Option<CalDateTime>, end: Option<CalDateTime>) -> Self {
+        self.start = start;
+        self.end = end;
+        self
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
+    /// Set the location of an Event that is being created (e.g. out of an iCal file).
+    pub(crate) fn with_location(mut self, location: Option<String>) -> Self {
+        self.location = location;
+        self
     }
 
-    pub fn ical_prod_id(&self) -> &str {
-        unimplemented!()
+    /// Set the alarms of an Event that is being created (e.g. out of an iCal file).
+    pub(crate) fn with_alarms(mut self, alarms: Vec<RawComponent>) -> Self {
+        self.alarms = alarms;
+        self
     }
 
-    pub fn creation_date(&self) -> Option<&DateTime<Utc>> {
-        unimplemented!()
+    /// Set the components that share the UID of an Event that is being created out of an iCal file.
+    pub(crate) fn with_extra_components(mut self, extra_components: Vec<RawComponent>) -> Self {
+        self.extra_components = extra_components;
+        self
     }
 
-    pub fn last_modified(&self) -> &DateTime<Utc> {
-        unimplemented!()
-    }
+    pub fn url(&self) -> &Url       { &self.url         }
+    pub fn uid(&self) -> &str       { &self.uid         }
+    pub fn name(&self) -> &str      { &self.name        }
+    pub fn ical_prod_id(&self) -> &str            { &self.ical_prod_id }
+    pub fn sync_status(&self) -> &SyncStatus      { &self.sync_status  }
+    pub fn last_modified(&self) -> &DateTime<Utc> { &self.last_modified }
+    pub fn creation_date(&self) -> Option<&DateTime<Utc>>   { self.creation_date.as_ref() }
+    pub fn start(&self) -> Option<&CalDateTime>             { self.start.as_ref() }
+    pub fn end(&self) -> Option<&CalDateTime>               { self.end.as_ref() }
+    pub fn location(&self) -> Option<&str>                  { self.location.as_deref() }
+    pub fn alarms(&self) -> &[RawComponent]                 { &self.alarms }
+    pub fn extra_parameters(&self) -> &[Property]           { &self.extra_parameters }
+    pub fn extra_components(&self) -> &[RawComponent]       { &self.extra_components }
 
-    pub fn sync_status(&self) -> &SyncStatus {
-        &self.sync_status
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn has_same_observable_content_as(&self, other: &Event) -> bool {
+           self.url == other.url
+        && self.uid == other.uid
+        && self.name == other.name
+        && self.start == other.start
+        && self.end == other.end
+        && self.location == other.location
+        && self.alarms == other.alarms
+        // sync status must be the same variant, but we ignore its embedded version tag
+        && std::mem::discriminant(&self.sync_status) == std::mem::discriminant(&other.sync_status)
+        // last modified dates are ignored (they are not totally mocked in integration tests)
     }
+
     pub fn set_sync_status(&mut self, new_status: SyncStatus) {
         self.sync_status = new_status;
     }
 
-    #[cfg(any(test, feature = "integration_tests"))]
-    pub fn has_same_observable_content_as(&self, _other: &Event) -> bool {
-        unimplemented!();
+    /// Change the URL of this event, e.g. because the server it is stored on has moved.
+    /// This is not a modification of the event, its sync status is not altered
+    pub(crate) fn set_url(&mut self, new_url: Url) {
+        self.url = new_url;
+    }
+
+    /// Whether this event takes part in a scheduling exchange, i.e. has an iCal `ORGANIZER` or `ATTENDEE` property ([RFC6638](https://tools.ietf.org/html/rfc6638#section-3.1))
+    pub fn is_scheduling_object(&self) -> bool {
+        self.extra_parameters.iter().any(|prop| prop.name == "ORGANIZER" || prop.name == "ATTENDEE")
+    }
+
+    /// Replace the `ATTENDEE` properties of this event with the ones of another version of it (see [`crate::Task::merge_attendees_of`]).
+    /// This is not a modification of the event, its sync status is not altered
+    pub(crate) fn merge_attendees_of(&mut self, other: &Event) {
+        let position = self.extra_parameters.iter().position(|prop| prop.name == "ATTENDEE")
+            .unwrap_or(self.extra_parameters.len());
+        self.extra_parameters.retain(|prop| prop.name != "ATTENDEE");
+        let attendees = other.extra_parameters.iter().filter(|prop| prop.name == "ATTENDEE").cloned();
+        self.extra_parameters.splice(position..position, attendees);
+    }
+
+    /// Take the fields a user sets (name, dates, location) from another version of this event (see [`crate::Task::keep_user_fields_of`]).
+    /// This is not a modification of the event, its sync status is not altered
+    pub(crate) fn keep_user_fields_of(&mut self, other: &Event) {
+        self.name = other.name.clone();
+        self.start = other.start.clone();
+        self.end = other.end.clone();
+        self.location = other.location.clone();
+    }
+
+    /// Returns a copy of this event, with the given name and UID and a new URL in the given calendar, that has not been synced yet
+    pub(crate) fn derive(&self, parent_calendar_url: &Url, identity: DerivedIdentity) -> Self {
+        let mut duplicate = self.clone();
+        duplicate.name = identity.name;
+        duplicate.uid = identity.uid;
+        duplicate.url = random_url(parent_calendar_url);
+        duplicate.sync_status = SyncStatus::NotSynced;
+        duplicate
+    }
+
+    /// Mark this event as modified, even though nothing has changed, so that it is uploaded again at the next sync (see [`crate::Task::touch`])
+    pub fn touch(&mut self) {
+        self.mark_modified();
+        for prop in self.extra_parameters.iter_mut().filter(|prop| prop.name == "SEQUENCE") {
+            if let Some(sequence) = prop.value.as_deref().and_then(|value| value.trim().parse::<u32>().ok()) {
+                prop.value = Some((sequence + 1).to_string());
+            }
+        }
+    }
+
+    fn mark_modified(&mut self) {
+        self.last_modified = Utc::now();
+        match &self.sync_status {
+            SyncStatus::NotSynced | SyncStatus::LocallyModified(_) => (),
+            SyncStatus::Synced(prev_vt) => {
+                self.sync_status = SyncStatus::LocallyModified(prev_vt.clone());
+            }
+            SyncStatus::LocallyDeleted(_) => {
+                log::warn!("Trying to update an item that has previously been deleted. These changes will probably be ignored at next sync.");
+            },
+        }
+    }
+
+    /// Rename an event.
+    /// This updates its "last modified" field
+    pub fn set_name(&mut self, new_name: String) {
+        if self.name == new_name {
+            return;
+        }
+        self.mark_modified();
+        self.name = new_name;
+    }
+
+    /// Set the start date of this event.
+    /// This updates its "last modified" field, unless the date is unchanged
+    pub fn set_start(&mut self, new_start: Option<CalDateTime>) {
+        if self.start == new_start {
+            return;
+        }
+        self.mark_modified();
+        self.start = new_start;
+    }
+
+    /// Set the end date of this event.
+    /// This updates its "last modified" field, unless the date is unchanged
+    pub fn set_end(&mut self, new_end: Option<CalDateTime>) {
+        if self.end == new_end {
+            return;
+        }
+        self.mark_modified();
+        self.end = new_end;
+    }
+
+    /// Set where this event takes place.
+    /// This updates its "last modified" field, unless the location is unchanged
+    pub fn set_location(&mut self, new_location: Option<String>) {
+        if self.location == new_location {
+            return;
+        }
+        self.mark_modified();
+        self.location = new_location;
     }
 }
@@ -1,28 +1,211 @@
 //! A module to build ICal files
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::error::Error;
 
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
-use ics::properties::{Completed, Created, LastModified, PercentComplete, Status, Summary};
+use ics::properties::{Completed, Created, LastModified, Location, PercentComplete, Status, Summary};
 use ics::{ICalendar, ToDo};
+use ics::components::Component as IcsComponent;
 use ics::components::Parameter as IcsParameter;
 use ics::components::Property as IcsProperty;
 use ical::property::Property as IcalProperty;
 
 use crate::Task;
-use crate::item::Item;
-use crate::task::CompletionStatus;
+use crate::Event;
+use crate::item::{Item, RawComponent};
+use crate::task::{CompletionStatus, DEVICE_ORIGIN_PARAMETER, DEVICE_PROPERTY};
+use crate::datetime::CalDateTime;
+use crate::attachment::Attachment;
 
 
 /// Create an iCal item from a `crate::item::Item`
 pub fn build_from(item: &Item) -> Result<String, Box<dyn Error>> {
     match item {
         Item::Task(t) => build_from_task(t),
-        _ => unimplemented!(),
+        Item::Event(e) => build_from_event(e),
     }
 }
 
 pub fn build_from_task(task: &Task) -> Result<String, Box<dyn Error>> {
+    Ok(build_calendar(task, true, sanitize_control_characters()).to_string())
+}
+
+pub fn build_from_event(event: &Event) -> Result<String, Box<dyn Error>> {
+    Ok(build_event_calendar(event, sanitize_control_characters()).to_string())
+}
+
+fn sanitize_control_characters() -> bool {
+    *crate::config::SANITIZE_CONTROL_CHARACTERS.lock().unwrap()
+}
+
+/// Create an iCal item from a `crate::item::Item`, as a sequence of chunks.
+///
+/// Contrary to [`build_from`], the content of inline attachments is not copied into a single buffer, but folded into iCal lines only when the chunks are consumed.
+/// This is the way to go to upload large items.
+pub fn build_streamed_from(item: Item) -> Result<StreamedIcal, Box<dyn Error>> {
+    let mut task = match item {
+        Item::Task(t) => t,
+        Item::Event(e) => {
+            // Events have no attachments, there is nothing to stream
+            let text = Bytes::from(build_from_event(&e)?);
+            return Ok(StreamedIcal { content_length: text.len(), parts: VecDeque::from(vec![StreamedPart::Raw(text)]) });
+        },
+    };
+
+    let mut inline_attachments = Vec::new();
+    for attachment in task.attachments_mut() {
+        if let Some(data) = attachment.take_inline_data() {
+            inline_attachments.push(FoldedProperty {
+                prefix: Bytes::from(attachment_prefix(attachment)),
+                data: Bytes::from(data),
+                pos: 0,
+            });
+        }
+    }
+
+    // Inline attachments are the last properties of the main component, before its alarms (if any)
+    let text = Bytes::from(build_calendar(&task, false, sanitize_control_characters()).to_string());
+    let end_of_properties = [&b"\r\nBEGIN:VALARM\r\n"[..], &b"\r\nEND:VTODO\r\n"[..]].iter()
+        .filter_map(|marker| find(&text, marker))
+        .min();
+    let split_at = match end_of_properties {
+        Some(pos) => pos + 2,
+        None => return Err("Unable to find the end of the VTODO component".into()),
+    };
+
+    let mut parts = VecDeque::new();
+    parts.push_back(StreamedPart::Raw(text.slice(..split_at)));
+    parts.extend(inline_attachments.into_iter().map(StreamedPart::Folded));
+    parts.push_back(StreamedPart::Raw(text.slice(split_at..)));
+    let content_length = parts.iter().map(|part| part.len()).sum();
+
+    Ok(StreamedIcal { content_length, parts })
+}
+
+/// An iCal file, produced chunk by chunk (see [`build_streamed_from`])
+pub struct StreamedIcal {
+    content_length: usize,
+    parts: VecDeque<StreamedPart>,
+}
+
+impl StreamedIcal {
+    /// The total length of the iCal file, in bytes
+    pub fn content_length(&self) -> usize {
+        self.content_length
+    }
+}
+
+impl Iterator for StreamedIcal {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        loop {
+            match self.parts.front_mut()? {
+                StreamedPart::Raw(bytes) => {
+                    // This is cheap, `Bytes` are reference-counted
+                    let chunk = bytes.clone();
+                    self.parts.pop_front();
+                    return Some(chunk);
+                },
+                StreamedPart::Folded(prop) => {
+                    if let Some(chunk) = prop.next() {
+                        return Some(chunk);
+                    }
+                    self.parts.pop_front();
+                },
+            }
+        }
+    }
+}
+
+enum StreamedPart {
+    Raw(Bytes),
+    Folded(FoldedProperty),
+}
+
+impl StreamedPart {
+    fn len(&self) -> usize {
+        match self {
+            StreamedPart::Raw(bytes) => bytes.len(),
+            StreamedPart::Folded(prop) => prop.folded_len(),
+        }
+    }
+}
+
+/// The content lines of a property, folded the same way the `ics` crate does
+struct FoldedProperty {
+    prefix: Bytes,
+    data: Bytes,
+    /// How many bytes of `prefix` + `data` have already been output
+    pos: usize,
+}
+
+impl FoldedProperty {
+    const LINE_LIMIT: usize = 75;
+    const LINES_PER_CHUNK: usize = 128;
+
+    fn content_len(&self) -> usize {
+        self.prefix.len() + self.data.len()
+    }
+
+    fn folded_len(&self) -> usize {
+        let len = self.content_len();
+        let lines = len.div_ceil(Self::LINE_LIMIT);
+        len + 3 * (lines - 1) + 2
+    }
+
+    fn next(&mut self) -> Option<Bytes> {
+        let len = self.content_len();
+        if self.pos >= len {
+            return None;
+        }
+
+        let mut chunk = Vec::with_capacity(Self::LINES_PER_CHUNK * (Self::LINE_LIMIT + 3));
+        for _ in 0..Self::LINES_PER_CHUNK {
+            if self.pos >= len {
+                break;
+            }
+            if self.pos > 0 {
+                chunk.extend_from_slice(b"\r\n ");
+            }
+            let end = std::cmp::min(self.pos + Self::LINE_LIMIT, len);
+            let prefix_len = self.prefix.len();
+            if self.pos < prefix_len {
+                chunk.extend_from_slice(&self.prefix[self.pos..std::cmp::min(end, prefix_len)]);
+            }
+            if end > prefix_len {
+                chunk.extend_from_slice(&self.data[std::cmp::max(self.pos, prefix_len) - prefix_len..end - prefix_len]);
+            }
+            self.pos = end;
+        }
+        if self.pos >= len {
+            chunk.extend_from_slice(b"\r\n");
+        }
+        Some(Bytes::from(chunk))
+    }
+}
+
+/// The beginning of an `ATTACH` content line, up to the `:` that separates the parameters from the value
+fn attachment_prefix(attachment: &Attachment) -> String {
+    let (mut params, _) = attachment.to_ical_value();
+    // The `ics` crate outputs parameters sorted by name
+    params.sort();
+    let mut prefix = String::from("ATTACH");
+    for (key, value) in params {
+        prefix.push_str(&format!(";{}={}", key, value));
+    }
+    prefix.push(':');
+    prefix
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn build_calendar<'a>(task: &'a Task, with_inline_attachments: bool, sanitize: bool) -> ICalendar<'a> {
     let s_last_modified = format_date_time(task.last_modified());
 
     let mut todo = ToDo::new(
@@ -34,7 +217,13 @@ pub fn build_from_task(task: &Task) -> Result<String, Box<dyn Error>> {
         todo.push(Created::new(format_date_time(dt)))
     );
     todo.push(LastModified::new(s_last_modified));
-    todo.push(Summary::new(task.name()));
+    todo.push(Summary::new(sanitized_text(task.name(), "SUMMARY", task.uid(), sanitize)));
+    if let Some(due) = task.due() {
+        todo.push(date_time_property("DUE", due));
+    }
+    if let Some(start) = task.start() {
+        todo.push(date_time_property("DTSTART", start));
+    }
 
     match task.completion_status() {
         CompletionStatus::Uncompleted => {
@@ -49,22 +238,139 @@ pub fn build_from_task(task: &Task) -> Result<String, Box<dyn Error>> {
         }
     }
 
+    if let Some(device) = task.last_modified_by() {
+        let mut prop = IcsProperty::new(DEVICE_PROPERTY, device);
+        if let Some(origin) = task.origin_device() {
+            prop.add(IcsParameter::new(DEVICE_ORIGIN_PARAMETER, origin));
+        }
+        todo.push(prop);
+    }
+
     // Also add fields that we have not handled
     for ical_property in task.extra_parameters() {
-        let ics_property = ical_to_ics_property(ical_property.clone());
+        let mut ical_property = ical_property.clone();
+        if SANITIZED_PROPERTIES.contains(&ical_property.name.as_str()) {
+            if let Some(value) = &ical_property.value {
+                if let Cow::Owned(value) = sanitized_text(value, &ical_property.name, task.uid(), sanitize) {
+                    ical_property.value = Some(value);
+                }
+            }
+        }
+        let ics_property = ical_to_ics_property(ical_property);
         todo.push(ics_property);
     }
 
+    for attachment in task.attachments() {
+        if attachment.is_inline() && !with_inline_attachments {
+            continue;
+        }
+        let (params, value) = attachment.to_ical_value();
+        let mut prop = IcsProperty::new("ATTACH", value.to_string());
+        for (key, value) in params {
+            prop.add(IcsParameter::new(key, value));
+        }
+        todo.push(prop);
+    }
+
+    let mut todo = IcsComponent::from(todo);
+    for alarm in task.alarms() {
+        todo.add_component(raw_to_ics_component(alarm));
+    }
+
     let mut calendar = ICalendar::new("2.0", task.ical_prod_id());
-    calendar.add_todo(todo);
+    calendar.add_component(todo);
+    for component in task.extra_components() {
+        calendar.add_component(raw_to_ics_component(component));
+    }
+
+    calendar
+}
+
+fn build_event_calendar(event: &Event, sanitize: bool) -> ICalendar<'_> {
+    let s_last_modified = format_date_time(event.last_modified());
+
+    let mut vevent = ics::Event::new(event.uid(), s_last_modified.clone());
+    if let Some(dt) = event.creation_date() {
+        vevent.push(Created::new(format_date_time(dt)));
+    }
+    vevent.push(LastModified::new(s_last_modified));
+    vevent.push(Summary::new(sanitized_text(event.name(), "SUMMARY", event.uid(), sanitize)));
+    if let Some(start) = event.start() {
+        vevent.push(date_time_property("DTSTART", start));
+    }
+    if let Some(end) = event.end() {
+        vevent.push(date_time_property("DTEND", end));
+    }
+    if let Some(location) = event.location() {
+        vevent.push(Location::new(sanitized_text(location, "LOCATION", event.uid(), sanitize)));
+    }
+    for ical_property in event.extra_parameters() {
+        vevent.push(ical_to_ics_property(ical_property.clone()));
+    }
+
+    let mut vevent = IcsComponent::from(vevent);
+    for alarm in event.alarms() {
+        vevent.add_component(raw_to_ics_component(alarm));
+    }
+
+    let mut calendar = ICalendar::new("2.0", event.ical_prod_id());
+    calendar.add_component(vevent);
+    for component in event.extra_components() {
+        calendar.add_component(raw_to_ics_component(component));
+    }
+    calendar
+}
+
+/// The text properties that are sanitized (see [`crate::config::SANITIZE_CONTROL_CHARACTERS`])
+const SANITIZED_PROPERTIES: [&str; 3] = ["DESCRIPTION", "LOCATION", "CATEGORIES"];
+
+/// Remove the control characters iCal does not allow in a text value (and escape its line breaks), in case `sanitize` is set
+fn sanitized_text<'a>(value: &'a str, property: &str, uid: &str, sanitize: bool) -> Cow<'a, str> {
+    let is_disallowed = |c: char| c.is_control() && c != '\t';
+    if !sanitize || !value.contains(is_disallowed) {
+        return Cow::Borrowed(value);
+    }
 
-    Ok(calendar.to_string())
+    let mut sanitized = String::with_capacity(value.len());
+    let mut n_removed = 0;
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\r' | '\n' => sanitized.push_str("\\n"),
+            c if is_disallowed(c) => n_removed += 1,
+            c => sanitized.push(c),
+        }
+    }
+    if n_removed > 0 {
+        log::warn!("Removed {} control character(s) from the {} of item {}", n_removed, property, uid);
+    }
+    Cow::Owned(sanitized)
 }
 
 fn format_date_time(dt: &DateTime<Utc>) -> String {
     dt.format("%Y%m%dT%H%M%S").to_string()
 }
 
+fn date_time_property(name: &'static str, dt: &CalDateTime) -> IcsProperty<'static> {
+    let (params, value) = dt.to_ical_value();
+    let mut prop = IcsProperty::new(name, value);
+    for (key, value) in params {
+        prop.add(IcsParameter::new(key, value));
+    }
+    prop
+}
+
+fn raw_to_ics_component(raw: &RawComponent) -> IcsComponent<'static> {
+    let mut component = IcsComponent::new(raw.name().to_string());
+    for prop in raw.properties() {
+        component.add_property(ical_to_ics_property(prop.clone()));
+    }
+    for sub in raw.subcomponents() {
+        component.add_component(raw_to_ics_component(sub));
+    }
+    component
+}
 
 fn ical_to_ics_property(prop: IcalProperty) -> IcsProperty<'static> {
     let mut ics_prop = match prop.value {
@@ -85,6 +391,7 @@ fn ical_to_ics_property(prop: IcalProperty) -> IcsProperty<'static> {
 mod tests {
     use super::*;
     use crate::Task;
+    use crate::attachment::Attachment;
     use crate::config::{ORG_NAME, PRODUCT_NAME};
 
     #[test]
@@ -143,8 +450,74 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn test_streamed_ical_with_attachments() {
+        let cal_url = "http://my.calend.ar/id".parse().unwrap();
+        let mut task = Task::new(String::from("Read the report"), false, &cal_url);
+        let large_content: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        task.set_attachments(vec![
+            Attachment::from_uri("https://example.com/report.pdf".to_string(), Some("application/pdf".to_string())),
+            Attachment::from_content(&large_content, Some("application/octet-stream".to_string())),
+        ]);
+        let item = Item::Task(task);
+
+        let expected = build_from(&item).unwrap();
+        let streamed = build_streamed_from(item).unwrap();
+        assert_eq!(streamed.content_length(), expected.len());
+        let chunks: Vec<Bytes> = streamed.collect();
+        assert!(chunks.len() > 3);
+        let concatenated: Vec<u8> = chunks.concat();
+        assert_eq!(String::from_utf8(concatenated).unwrap(), expected);
+
+        let parsed = crate::ical::parse(&expected, cal_url.clone(), crate::item::SyncStatus::NotSynced).unwrap();
+        let attachments = parsed.unwrap_task().attachments();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[1].content().unwrap().unwrap(), large_content);
+    }
+
+    #[test]
+    fn test_sanitized_control_characters() {
+        let cal_url = "http://my.calend.ar/id".parse().unwrap();
+        let task = Task::new(String::from("Ring the\u{7} bell"), false, &cal_url).with_extra_parameters(vec![IcalProperty {
+            name: "DESCRIPTION".to_string(),
+            params: None,
+            value: Some("First line\u{0}\r\nSecond\tline".to_string()),
+        }]);
+
+        assert_eq!(sanitized_text(task.name(), "SUMMARY", task.uid(), false), "Ring the\u{7} bell");
+        assert_eq!(sanitized_text(task.name(), "SUMMARY", task.uid(), true), "Ring the bell");
+        assert_eq!(sanitized_text("Nothing to do", "SUMMARY", task.uid(), true), Cow::Borrowed("Nothing to do"));
+
+        let ical = build_calendar(&task, true, true).to_string();
+        assert!(ical.contains("SUMMARY:Ring the bell\r\n"));
+        assert!(ical.contains("DESCRIPTION:First line\\nSecond\tline\r\n"));
+        assert!(!ical.contains(['\u{0}', '\u{7}']));
+    }
+
+    #[test]
     fn test_ical_from_event() {
-        unimplemented!();
+        let cal_url = "http://my.calend.ar/id".parse().unwrap();
+        let start = CalDateTime::Zoned{ tzid: "Europe/Paris".to_string(), local: chrono::NaiveDate::from_ymd(2021, 3, 22).and_hms(10, 0, 0) };
+        let mut event = Event::new("Weekly meeting".to_string(), Some(start), None, &cal_url);
+        event.set_location(Some("Room 101".to_string()));
+        let s_last_modified = format_date_time(event.last_modified());
+
+        let expected_ical = format!("BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            PRODID:-//{}//{}//EN\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:{}\r\n\
+            DTSTAMP:{}\r\n\
+            CREATED:{}\r\n\
+            LAST-MODIFIED:{}\r\n\
+            SUMMARY:Weekly meeting\r\n\
+            DTSTART;TZID=Europe/Paris:20210322T100000\r\n\
+            LOCATION:Room 101\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n", ORG_NAME.lock().unwrap(), PRODUCT_NAME.lock().unwrap(), event.uid(), s_last_modified,
+            format_date_time(event.creation_date().unwrap()), s_last_modified);
+        assert_eq!(build_from(&Item::Event(event.clone())).unwrap(), expected_ical);
+
+        let streamed: Vec<u8> = build_streamed_from(Item::Event(event)).unwrap().flat_map(|chunk| chunk.to_vec()).collect();
+        assert_eq!(String::from_utf8(streamed).unwrap(), expected_ical);
     }
 }
@@ -0,0 +1,240 @@
+//! A canonical form of items, that does not depend on the way they happen to be serialized
+//!
+//! Different clients order properties differently, fold lines at other places, quote parameters or not...
+//! None of these change the meaning of an item, and none of these should be seen as a modification.
+
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+
+use crate::Item;
+
+/// Properties that are rewritten by every client that serializes an item, and that do not tell anything about the item itself
+const IGNORED_PROPERTIES: [&str; 3] = ["PRODID", "DTSTAMP", "LAST-MODIFIED"];
+
+/// Returns a text representation of an item, that is the same for items that only differ in their serialization.
+///
+/// Properties (and sub-components) are sorted, lines are unfolded, names are upper-cased, parameters are sorted and only quoted when required,
+/// and default parameters (such as `VALUE=DATE-TIME`) are omitted. \
+/// Properties that every client rewrites (`PRODID`, `DTSTAMP` and `LAST-MODIFIED`) are ignored.
+///
+/// The URL and the sync status of the item are not part of its canonical form.
+pub fn canonical_form(item: &Item) -> Result<String, Box<dyn Error>> {
+    let ical = super::build_from(item)?;
+    canonicalize(&ical)
+}
+
+/// A hash of the [`canonical_form`] of an item.
+///
+/// This is only meant to be compared with hashes computed by the same build of this crate, it should not be stored.
+pub fn content_hash(item: &Item) -> Result<u64, Box<dyn Error>> {
+    let mut hasher = DefaultHasher::new();
+    canonical_form(item)?.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Whether two items have the same [`canonical_form`], i.e. whether they only differ in the way they are serialized
+pub fn have_same_content(left: &Item, right: &Item) -> bool {
+    match (canonical_form(left), canonical_form(right)) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => false,
+    }
+}
+
+/// The parameters of a property, with their (possibly multiple) values
+pub(super) type Parameters = Vec<(String, Vec<String>)>;
+
+/// A component being read: its name, its canonical properties and its canonical sub-components
+type PendingComponent = (String, Vec<String>, Vec<String>);
+
+/// Join the folded lines of an iCal file (the content lines are the `lines()` of the result)
+pub(super) fn unfold(ical: &str) -> String {
+    ical.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "")
+}
+
+fn canonicalize(ical: &str) -> Result<String, Box<dyn Error>> {
+    let unfolded = unfold(ical);
+
+    let mut stack: Vec<PendingComponent> = vec![(String::new(), Vec::new(), Vec::new())];
+    for line in unfolded.lines().filter(|line| !line.is_empty()) {
+        let (name, params, value) = split_content_line(line)?;
+        match name.as_str() {
+            "BEGIN" => stack.push((value.to_uppercase(), Vec::new(), Vec::new())),
+            "END" => {
+                let (component, mut properties, mut subcomponents) = match stack.pop() {
+                    Some(pending) if !stack.is_empty() => pending,
+                    _ => return Err(format!("Unexpected END:{}", value).into()),
+                };
+                if component != value.to_uppercase() {
+                    return Err(format!("Unexpected END:{} in component {}", value, component).into());
+                }
+                properties.sort();
+                subcomponents.sort();
+                let text = format!("BEGIN:{}\n{}{}END:{}\n", component, properties.concat(), subcomponents.concat(), component);
+                if let Some(parent) = stack.last_mut() {
+                    parent.2.push(text);
+                }
+            },
+            _ if IGNORED_PROPERTIES.contains(&name.as_str()) => (),
+            _ => {
+                let property = canonical_property(&name, params, value);
+                if let Some(current) = stack.last_mut() {
+                    current.1.push(property);
+                }
+            },
+        }
+    }
+
+    match stack.pop() {
+        Some((_, _, mut components)) if stack.is_empty() => {
+            components.sort();
+            Ok(components.concat())
+        },
+        _ => Err("Unterminated iCal component".into()),
+    }
+}
+
+/// Split a content line into its upper-cased name, its parameters (with upper-cased names and unquoted values) and its value
+pub(super) fn split_content_line(line: &str) -> Result<(String, Parameters, &str), Box<dyn Error>> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut value_start = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            },
+            ';' if !in_quotes => parts.push(std::mem::take(&mut current)),
+            ':' if !in_quotes => {
+                parts.push(std::mem::take(&mut current));
+                value_start = Some(i + 1);
+                break;
+            },
+            _ => current.push(c),
+        }
+    }
+    let value_start = value_start.ok_or_else(|| format!("Invalid iCal content line: {}", line))?;
+
+    let mut parts = parts.into_iter();
+    let name = parts.next().unwrap_or_default().to_uppercase();
+    let params = parts
+        .map(|param| {
+            let (key, values) = param.split_once('=').unwrap_or((&param, ""));
+            (key.to_uppercase(), split_param_values(values))
+        })
+        .collect();
+    Ok((name, params, &line[value_start..]))
+}
+
+fn split_param_values(values: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in values.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => result.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    result.push(current);
+    result
+}
+
+/// Sort parameters, and omit the ones that only state a default value
+pub(super) fn normalize_parameters(mut params: Parameters) -> Parameters {
+    // DATE-TIME is the default value type of every property that can hold a date
+    params.retain(|(key, values)| !(key == "VALUE" && values.len() == 1 && values[0].eq_ignore_ascii_case("DATE-TIME")));
+    params.sort();
+    params
+}
+
+/// Write parameters back as iCal text, quoting their values only when required
+pub(super) fn format_parameters(params: &[(String, Vec<String>)]) -> String {
+    let mut text = String::new();
+    for (key, values) in params {
+        let values: Vec<String> = values.iter()
+            .map(|value| match value.contains([':', ';', ',']) {
+                true => format!("\"{}\"", value),
+                false => value.clone(),
+            })
+            .collect();
+        text.push_str(&format!(";{}={}", key, values.join(",")));
+    }
+    text
+}
+
+/// Both are valid escapes of a line break
+pub(super) fn normalize_value(value: &str) -> String {
+    value.replace("\\N", "\\n")
+}
+
+fn canonical_property(name: &str, params: Parameters, value: &str) -> String {
+    format!("{}{}:{}\n", name, format_parameters(&normalize_parameters(params)), normalize_value(value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+    use crate::item::SyncStatus;
+
+    const CORPUS: &str = "tests/assets/equivalent";
+
+    fn parse_asset(path: &Path) -> Item {
+        let content = std::fs::read_to_string(path).unwrap();
+        // The URL and the sync status must not be taken into account
+        let url = format!("http://some.server/{}", path.file_name().unwrap().to_string_lossy()).parse().unwrap();
+        super::super::parse(&content, url, SyncStatus::NotSynced).unwrap()
+    }
+
+    /// Every `<case>_a.ics` of the corpus is a different serialization of the matching `<case>_b.ics`
+    #[test]
+    fn test_equivalent_serializations() {
+        let mut n_cases = 0;
+        for entry in std::fs::read_dir(CORPUS).unwrap() {
+            let path_a = entry.unwrap().path();
+            let file_name = path_a.file_name().unwrap().to_string_lossy().to_string();
+            let case = match file_name.strip_suffix("_a.ics") {
+                None => continue,
+                Some(case) => case.to_string(),
+            };
+            let path_b = path_a.with_file_name(format!("{}_b.ics", case));
+            assert_ne!(std::fs::read_to_string(&path_a).unwrap(), std::fs::read_to_string(&path_b).unwrap());
+
+            let item_a = parse_asset(&path_a);
+            let item_b = parse_asset(&path_b);
+            assert_eq!(canonical_form(&item_a).unwrap(), canonical_form(&item_b).unwrap(), "case {}", case);
+            assert_eq!(content_hash(&item_a).unwrap(), content_hash(&item_b).unwrap(), "case {}", case);
+            assert!(have_same_content(&item_a, &item_b), "case {}", case);
+            n_cases += 1;
+        }
+        assert!(n_cases >= 5);
+    }
+
+    #[test]
+    fn test_semantic_changes() {
+        let item = parse_asset(&Path::new(CORPUS).join("reordered_a.ics"));
+
+        let mut renamed = item.clone();
+        renamed.unwrap_task_mut().set_name("Another name".to_string());
+        assert!(!have_same_content(&item, &renamed));
+
+        let mut completed = item.clone();
+        completed.unwrap_task_mut().set_completion_status(crate::task::CompletionStatus::Completed(None));
+        assert!(!have_same_content(&item, &completed));
+    }
+
+    #[test]
+    fn test_canonical_property() {
+        let canonical = canonicalize("BEGIN:VTODO\r\nx-thing;x-b=\"two\";X-A=\"a:b\",c:value\\Nnext\r\nEND:VTODO\r\n").unwrap();
+        assert_eq!(canonical, "BEGIN:VTODO\nX-THING;X-A=\"a:b\",c;X-B=two:value\\nnext\nEND:VTODO\n");
+
+        assert!(canonicalize("BEGIN:VTODO\r\nEND:VEVENT\r\n").is_err());
+        assert!(canonicalize("BEGIN:VTODO\r\nSUMMARY:unterminated\r\n").is_err());
+    }
+}
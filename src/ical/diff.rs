@@ -0,0 +1,263 @@
+//! Property-level differences between two versions of an iCal item (see [`diff`])
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use super::canonical::{format_parameters, normalize_parameters, normalize_value, split_content_line, unfold, Parameters};
+use crate::task::DEVICE_PROPERTY;
+
+/// Properties that change whenever an item is saved, even without any actual modification
+const VOLATILE_PROPERTIES: [&str; 5] = ["PRODID", "DTSTAMP", "LAST-MODIFIED", "SEQUENCE", DEVICE_PROPERTY];
+
+/// Properties whose value is a comma-separated list, whose order does not matter
+const MULTI_VALUED_PROPERTIES: [&str; 2] = ["CATEGORIES", "RESOURCES"];
+
+/// The parameters and the value of a property
+///
+/// Parameters are sorted and unquoted, so that two serializations of the same property are equal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertyValue {
+    pub params: Vec<(String, Vec<String>)>,
+    pub value: String,
+}
+
+impl Display for PropertyValue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", format_parameters(&self.params), self.value)
+    }
+}
+
+/// A difference between two versions of an iCal item.
+///
+/// `component` is the path of the component that contains the property, e.g. `VCALENDAR/VTODO/VALARM`.
+/// In case a component contains several sub-components of the same kind, the next ones are numbered (e.g. `VALARM[2]`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyChange {
+    /// A property that only exists in the second version
+    Added { component: String, name: String, value: PropertyValue },
+    /// A property that only exists in the first version
+    Removed { component: String, name: String, value: PropertyValue },
+    /// A property whose value or parameters have changed
+    Changed { component: String, name: String, before: PropertyValue, after: PropertyValue },
+}
+
+impl Display for PropertyChange {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::Added { component, name, value } => write!(f, "+ {} {}{}", component, name, value),
+            Self::Removed { component, name, value } => write!(f, "- {} {}{}", component, name, value),
+            Self::Changed { component, name, before, after } => write!(f, "~ {} {}{} => {}{}", component, name, before, name, after),
+        }
+    }
+}
+
+/// Returns the properties that differ between two versions of an iCal item.
+///
+/// Properties that are present several times (e.g. `ATTENDEE`s) are matched by value, so that only the actual differences are reported.
+/// In case `ignore_volatile` is set, properties that change at every save (e.g. `DTSTAMP` or `LAST-MODIFIED`) are ignored.
+pub fn diff(before: &str, after: &str, ignore_volatile: bool) -> Result<Vec<PropertyChange>, Box<dyn Error>> {
+    let before = components_of(before, ignore_volatile)?;
+    let after = components_of(after, ignore_volatile)?;
+    let after_by_path: HashMap<&str, &Vec<(String, PropertyValue)>> = after.iter()
+        .map(|(path, props)| (path.as_str(), props))
+        .collect();
+    let before_paths: Vec<&str> = before.iter().map(|(path, _)| path.as_str()).collect();
+
+    let mut changes = Vec::new();
+    for (path, props) in &before {
+        let empty = Vec::new();
+        let after_props = after_by_path.get(path.as_str()).copied().unwrap_or(&empty);
+        diff_properties(path, props, after_props, &mut changes);
+    }
+    for (path, props) in &after {
+        if !before_paths.contains(&path.as_str()) {
+            diff_properties(path, &[], props, &mut changes);
+        }
+    }
+    Ok(changes)
+}
+
+fn diff_properties(component: &str, before: &[(String, PropertyValue)], after: &[(String, PropertyValue)], changes: &mut Vec<PropertyChange>) {
+    let mut names: Vec<&str> = Vec::new();
+    for (name, _) in before.iter().chain(after.iter()) {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+
+    for name in names {
+        let mut removed: Vec<&PropertyValue> = before.iter().filter(|(n, _)| n == name).map(|(_, v)| v).collect();
+        let mut added: Vec<&PropertyValue> = Vec::new();
+        for value in after.iter().filter(|(n, _)| n == name).map(|(_, v)| v) {
+            match removed.iter().position(|v| *v == value) {
+                Some(pos) => { removed.remove(pos); },
+                None => added.push(value),
+            }
+        }
+
+        if removed.len() == 1 && added.len() == 1 {
+            changes.push(PropertyChange::Changed {
+                component: component.to_string(), name: name.to_string(),
+                before: removed[0].clone(), after: added[0].clone(),
+            });
+            continue;
+        }
+        for value in removed {
+            changes.push(PropertyChange::Removed { component: component.to_string(), name: name.to_string(), value: value.clone() });
+        }
+        for value in added {
+            changes.push(PropertyChange::Added { component: component.to_string(), name: name.to_string(), value: value.clone() });
+        }
+    }
+}
+
+/// The properties of every component of an iCal file, by path
+type Components = Vec<(String, Vec<(String, PropertyValue)>)>;
+
+fn components_of(ical: &str, ignore_volatile: bool) -> Result<Components, Box<dyn Error>> {
+    let unfolded = unfold(ical);
+    let mut components: Components = Vec::new();
+    // The index (in `components`) of the components being read, and how many sub-components of each kind they have
+    let mut stack: Vec<(usize, HashMap<String, usize>)> = Vec::new();
+
+    for line in unfolded.lines().filter(|line| !line.is_empty()) {
+        let (name, params, value) = split_content_line(line)?;
+        match name.as_str() {
+            "BEGIN" => {
+                let kind = value.to_uppercase();
+                let path = match stack.last_mut() {
+                    None => kind.clone(),
+                    Some((parent, siblings)) => {
+                        let count = siblings.entry(kind.clone()).or_insert(0);
+                        *count += 1;
+                        match count {
+                            1 => format!("{}/{}", components[*parent].0, kind),
+                            _ => format!("{}/{}[{}]", components[*parent].0, kind, count),
+                        }
+                    },
+                };
+                components.push((path, Vec::new()));
+                stack.push((components.len() - 1, HashMap::new()));
+            },
+            "END" => {
+                if stack.pop().is_none() {
+                    return Err(format!("Unexpected END:{}", value).into());
+                }
+            },
+            _ if ignore_volatile && VOLATILE_PROPERTIES.contains(&name.as_str()) => (),
+            _ => {
+                let (current, _) = stack.last().ok_or_else(|| format!("Property {} is outside of any component", name))?;
+                let value = property_value(&name, params, value);
+                components[*current].1.push((name, value));
+            },
+        }
+    }
+    if !stack.is_empty() {
+        return Err("Unterminated iCal component".into());
+    }
+    Ok(components)
+}
+
+fn property_value(name: &str, params: Parameters, value: &str) -> PropertyValue {
+    let mut value = normalize_value(value);
+    if MULTI_VALUED_PROPERTIES.contains(&name) {
+        let mut values = split_unescaped_commas(&value);
+        values.sort();
+        value = values.join(",");
+    }
+    PropertyValue { params: normalize_parameters(params), value }
+}
+
+fn split_unescaped_commas(value: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => values.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    values.push(current);
+    values
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = "BEGIN:VCALENDAR\r
+PRODID:-//Some client//EN\r
+BEGIN:VTODO\r
+UID:abc\r
+DTSTAMP:20210321T001600Z\r
+SUMMARY:Prepare the meeting\r
+CATEGORIES:work,urgent\r
+ATTENDEE;CN=\"Jane\":mailto:jane@example.com\r
+ATTENDEE;CN=John:mailto:john@example.com\r
+X-LABEL;X-COLOR=red:label\r
+BEGIN:VALARM\r
+TRIGGER:-PT15M\r
+END:VALARM\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+    const AFTER: &str = "BEGIN:VCALENDAR\r
+PRODID:-//Another client//EN\r
+BEGIN:VTODO\r
+UID:abc\r
+DTSTAMP:20210322T001600Z\r
+SUMMARY:Prepare the meet\r
+ ing\r
+CATEGORIES:urgent,work\r
+ATTENDEE;CN=John:mailto:john@example.com\r
+ATTENDEE;CN=Jane;ROLE=CHAIR:mailto:jane@example.com\r
+ATTENDEE;CN=Bob:mailto:bob@example.com\r
+X-LABEL;X-COLOR=\"blue\":label\r
+PRIORITY:1\r
+BEGIN:VALARM\r
+TRIGGER:-PT15M\r
+END:VALARM\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+END:VALARM\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+    fn value(params: &[(&str, &str)], value: &str) -> PropertyValue {
+        PropertyValue {
+            params: params.iter().map(|(k, v)| (k.to_string(), vec![v.to_string()])).collect(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let changes = diff(BEFORE, AFTER, true).unwrap();
+        let todo = "VCALENDAR/VTODO".to_string();
+        assert_eq!(changes, vec![
+            PropertyChange::Removed { component: todo.clone(), name: "ATTENDEE".to_string(), value: value(&[("CN", "Jane")], "mailto:jane@example.com") },
+            PropertyChange::Added { component: todo.clone(), name: "ATTENDEE".to_string(), value: value(&[("CN", "Jane"), ("ROLE", "CHAIR")], "mailto:jane@example.com") },
+            PropertyChange::Added { component: todo.clone(), name: "ATTENDEE".to_string(), value: value(&[("CN", "Bob")], "mailto:bob@example.com") },
+            PropertyChange::Changed { component: todo.clone(), name: "X-LABEL".to_string(), before: value(&[("X-COLOR", "red")], "label"), after: value(&[("X-COLOR", "blue")], "label") },
+            PropertyChange::Added { component: todo, name: "PRIORITY".to_string(), value: value(&[], "1") },
+            PropertyChange::Added { component: "VCALENDAR/VTODO/VALARM[2]".to_string(), name: "TRIGGER".to_string(), value: value(&[], "-PT5M") },
+        ]);
+        assert_eq!(changes[3].to_string(), "~ VCALENDAR/VTODO X-LABEL;X-COLOR=red:label => X-LABEL;X-COLOR=blue:label");
+
+        let with_volatile = diff(BEFORE, AFTER, false).unwrap();
+        assert_eq!(with_volatile.len(), changes.len() + 2);
+        assert!(with_volatile.contains(&PropertyChange::Changed {
+            component: "VCALENDAR".to_string(), name: "PRODID".to_string(),
+            before: value(&[], "-//Some client//EN"), after: value(&[], "-//Another client//EN"),
+        }));
+
+        assert!(diff(BEFORE, BEFORE, false).unwrap().is_empty());
+        assert!(diff(BEFORE, "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\n", true).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+//! Conversion between iCalendar and jCal ([RFC7265](https://tools.ietf.org/html/rfc7265)), the JSON representation of iCalendar
+//!
+//! The conversion works on the generic structure of the data (components, properties, parameters), so that it does not lose anything this crate does not understand.
+
+use std::error::Error;
+
+use serde_json::{Map, Value};
+
+use super::canonical::{format_parameters, split_content_line, unfold};
+
+/// Properties whose values are date-times by default (RFC5545)
+const DATE_TIME_PROPERTIES: [&str; 10] = ["DTSTART", "DTEND", "DUE", "COMPLETED", "CREATED", "DTSTAMP", "LAST-MODIFIED", "RECURRENCE-ID", "EXDATE", "RDATE"];
+/// Properties whose values are integers by default
+const INTEGER_PROPERTIES: [&str; 4] = ["PERCENT-COMPLETE", "PRIORITY", "SEQUENCE", "REPEAT"];
+/// Properties whose values are URIs by default
+const URI_PROPERTIES: [&str; 7] = ["ATTACH", "URL", "TZURL", "IMAGE", "CONFERENCE", "ORGANIZER", "ATTENDEE"];
+/// Properties whose values are durations by default
+const DURATION_PROPERTIES: [&str; 2] = ["DURATION", "TRIGGER"];
+/// Properties whose values are UTC offsets
+const UTC_OFFSET_PROPERTIES: [&str; 2] = ["TZOFFSETFROM", "TZOFFSETTO"];
+/// Properties whose values are text, and that are known to this crate
+const TEXT_PROPERTIES: [&str; 23] = [
+    "SUMMARY", "DESCRIPTION", "LOCATION", "COMMENT", "STATUS", "CLASS", "UID", "CATEGORIES", "RESOURCES", "CONTACT",
+    "RELATED-TO", "TZID", "TZNAME", "PRODID", "VERSION", "METHOD", "CALSCALE", "ACTION", "TRANSP", "NAME", "COLOR",
+    "REFRESH-INTERVAL", "SOURCE",
+];
+/// Text properties that can hold several comma-separated values
+const MULTI_VALUED_TEXT_PROPERTIES: [&str; 2] = ["CATEGORIES", "RESOURCES"];
+/// Properties that always carry their `VALUE` parameter (RFC7986 requires it for `CONFERENCE`)
+const ALWAYS_TYPED_PROPERTIES: [&str; 2] = ["CONFERENCE", "IMAGE"];
+/// The parts of a recurrence rule whose values are integers
+const INTEGER_RECUR_PARTS: [&str; 10] = ["COUNT", "INTERVAL", "BYSECOND", "BYMINUTE", "BYHOUR", "BYMONTHDAY", "BYYEARDAY", "BYWEEKNO", "BYMONTH", "BYSETPOS"];
+
+/// The jCal value type of a property, when it has no `VALUE` parameter
+fn default_type(name: &str) -> &'static str {
+    let name = name.to_ascii_uppercase();
+    let name = name.as_str();
+    if DATE_TIME_PROPERTIES.contains(&name) { "date-time" }
+    else if INTEGER_PROPERTIES.contains(&name) { "integer" }
+    else if URI_PROPERTIES.contains(&name) { "uri" }
+    else if DURATION_PROPERTIES.contains(&name) { "duration" }
+    else if UTC_OFFSET_PROPERTIES.contains(&name) { "utc-offset" }
+    else if name == "RRULE" || name == "EXRULE" { "recur" }
+    else if name == "GEO" { "float" }
+    else if TEXT_PROPERTIES.contains(&name) { "text" }
+    else { "unknown" }
+}
+
+/// A component being read: its name, its properties and its sub-components
+type PendingComponent = (String, Vec<Value>, Vec<Value>);
+
+/// Convert an iCalendar text (e.g. a `VCALENDAR`) into jCal
+pub fn ical_to_jcal(ical: &str) -> Result<Value, Box<dyn Error>> {
+    let unfolded = unfold(ical);
+    let mut stack: Vec<PendingComponent> = Vec::new();
+    let mut root = None;
+    for line in unfolded.lines().filter(|line| !line.is_empty()) {
+        let (name, params, value) = split_content_line(line)?;
+        match name.as_str() {
+            "BEGIN" => stack.push((value.to_ascii_lowercase(), Vec::new(), Vec::new())),
+            "END" => {
+                let (component, properties, subcomponents) = stack.pop().ok_or_else(|| format!("Unexpected END:{}", value))?;
+                if !component.eq_ignore_ascii_case(value) {
+                    return Err(format!("Unexpected END:{} in component {}", value, component).into());
+                }
+                let component = Value::Array(vec![Value::String(component), Value::Array(properties), Value::Array(subcomponents)]);
+                match stack.last_mut() {
+                    Some(parent) => parent.2.push(component),
+                    None if root.is_none() => root = Some(component),
+                    None => return Err("Several top-level components".into()),
+                }
+            },
+            _ => {
+                let current = stack.last_mut().ok_or_else(|| format!("Property {} out of any component", name))?;
+                current.1.push(property_to_jcal(&name, params, value));
+            },
+        }
+    }
+    if !stack.is_empty() {
+        return Err("Unterminated iCal component".into());
+    }
+    root.ok_or_else(|| "No iCal component".into())
+}
+
+/// Convert a jCal value (e.g. a `vcalendar`) into iCalendar text
+pub fn jcal_to_ical(jcal: &Value) -> Result<String, Box<dyn Error>> {
+    let mut ical = String::new();
+    write_component(jcal, &mut ical)?;
+    Ok(ical)
+}
+
+fn property_to_jcal(name: &str, params: Vec<(String, Vec<String>)>, value: &str) -> Value {
+    let mut value_type = default_type(name).to_string();
+    let mut jcal_params = Map::new();
+    for (key, mut values) in params {
+        if key == "VALUE" && values.len() == 1 {
+            value_type = values.remove(0).to_ascii_lowercase();
+            continue;
+        }
+        let values = match values.len() {
+            1 => Value::String(values.remove(0)),
+            _ => Value::Array(values.into_iter().map(Value::String).collect()),
+        };
+        jcal_params.insert(key.to_ascii_lowercase(), values);
+    }
+
+    let mut property = vec![
+        Value::String(name.to_ascii_lowercase()),
+        Value::Object(jcal_params),
+        Value::String(value_type.clone()),
+    ];
+    property.extend(values_to_jcal(name, &value_type, value));
+    Value::Array(property)
+}
+
+fn values_to_jcal(name: &str, value_type: &str, value: &str) -> Vec<Value> {
+    let name = name.to_ascii_uppercase();
+    match value_type {
+        "date" | "date-time" => value.split(',').map(|v| Value::String(date_time_to_jcal(v))).collect(),
+        "integer" => vec![value.trim().parse::<i64>().map(Value::from).unwrap_or_else(|_| Value::String(value.to_string()))],
+        "float" if name == "GEO" => vec![Value::Array(value.split(';').map(float_to_jcal).collect())],
+        "float" => vec![float_to_jcal(value)],
+        "utc-offset" => vec![Value::String(utc_offset_to_jcal(value))],
+        "recur" => vec![recur_to_jcal(value)],
+        "text" if MULTI_VALUED_TEXT_PROPERTIES.contains(&name.as_str()) => split_unescaped_commas(value).iter().map(|v| Value::String(unescape_text(v))).collect(),
+        "text" => vec![Value::String(unescape_text(value))],
+        _ => vec![Value::String(value.to_string())],
+    }
+}
+
+fn write_component(component: &Value, ical: &mut String) -> Result<(), Box<dyn Error>> {
+    let parts = component.as_array().ok_or("A jCal component must be an array")?;
+    let (name, properties, subcomponents) = match parts.as_slice() {
+        [Value::String(name), Value::Array(properties), Value::Array(subcomponents)] => (name, properties, subcomponents),
+        _ => return Err("A jCal component must be [name, properties, components]".into()),
+    };
+    let name = name.to_ascii_uppercase();
+    write_line(&format!("BEGIN:{}", name), ical);
+    for property in properties {
+        write_line(&property_to_ical(property)?, ical);
+    }
+    for subcomponent in subcomponents {
+        write_component(subcomponent, ical)?;
+    }
+    write_line(&format!("END:{}", name), ical);
+    Ok(())
+}
+
+fn property_to_ical(property: &Value) -> Result<String, Box<dyn Error>> {
+    let parts = property.as_array().ok_or("A jCal property must be an array")?;
+    let (name, params, value_type, values) = match parts.as_slice() {
+        [Value::String(name), Value::Object(params), Value::String(value_type), values @ ..] => (name.to_ascii_uppercase(), params, value_type, values),
+        _ => return Err("A jCal property must be [name, parameters, type, values...]".into()),
+    };
+
+    let mut ical_params = Vec::new();
+    for (key, value) in params {
+        let values = match value {
+            Value::Array(values) => values.iter().map(scalar_to_string).collect(),
+            value => vec![scalar_to_string(value)],
+        };
+        ical_params.push((key.to_ascii_uppercase(), values));
+    }
+    if value_type != "unknown" && (value_type != default_type(&name) || ALWAYS_TYPED_PROPERTIES.contains(&name.as_str())) {
+        ical_params.push(("VALUE".to_string(), vec![value_type.to_ascii_uppercase()]));
+    }
+
+    let values: Vec<String> = values.iter().map(|value| value_to_ical(&name, value_type, value)).collect();
+    let separator = match value_type.as_str() {
+        "float" if name == "GEO" => ";",
+        _ => ",",
+    };
+    Ok(format!("{}{}:{}", name, format_parameters(&ical_params), values.join(separator)))
+}
+
+fn value_to_ical(name: &str, value_type: &str, value: &Value) -> String {
+    match (value_type, value) {
+        ("date" | "date-time", Value::String(v)) => date_time_to_ical(v),
+        ("utc-offset", Value::String(v)) => v.replace(':', ""),
+        ("recur", Value::Object(parts)) => recur_to_ical(parts),
+        ("text", Value::String(v)) => escape_text(v),
+        ("float", Value::Array(values)) if name == "GEO" => values.iter().map(scalar_to_string).collect::<Vec<_>>().join(";"),
+        (_, value) => scalar_to_string(value),
+    }
+}
+
+fn scalar_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// `20211103T214742Z` => `2021-11-03T21:47:42Z`, `20211103` => `2021-11-03`
+fn date_time_to_jcal(value: &str) -> String {
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    if date.len() != 8 || !is_digits(date) {
+        return value.to_string();
+    }
+    let date = format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]);
+    match time {
+        None => date,
+        Some(time) => {
+            let (digits, utc) = match time.strip_suffix('Z') {
+                Some(digits) => (digits, "Z"),
+                None => (time, ""),
+            };
+            if digits.len() != 6 || !is_digits(digits) {
+                return value.to_string();
+            }
+            format!("{}T{}:{}:{}{}", date, &digits[..2], &digits[2..4], &digits[4..], utc)
+        },
+    }
+}
+
+fn date_time_to_ical(value: &str) -> String {
+    value.replace(['-', ':'], "")
+}
+
+/// `+0200` => `+02:00`
+fn utc_offset_to_jcal(value: &str) -> String {
+    let (sign, digits) = value.split_at(value.len().min(1));
+    if !digits.bytes().all(|b| b.is_ascii_digit()) || digits.len() % 2 != 0 {
+        return value.to_string();
+    }
+    let pairs: Vec<&str> = (0..digits.len()).step_by(2).map(|i| &digits[i..i + 2]).collect();
+    format!("{}{}", sign, pairs.join(":"))
+}
+
+fn float_to_jcal(value: &str) -> Value {
+    value.trim().parse::<f64>().ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// `FREQ=WEEKLY;BYDAY=MO,TU;COUNT=3` => `{"freq": "WEEKLY", "byday": ["MO", "TU"], "count": 3}`
+fn recur_to_jcal(value: &str) -> Value {
+    let mut parts = Map::new();
+    for part in value.split(';').filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        let key = key.to_ascii_uppercase();
+        let convert = |v: &str| match key.as_str() {
+            "UNTIL" => Value::String(date_time_to_jcal(v)),
+            k if INTEGER_RECUR_PARTS.contains(&k) => v.parse::<i64>().map(Value::from).unwrap_or_else(|_| Value::String(v.to_string())),
+            _ => Value::String(v.to_string()),
+        };
+        let values: Vec<Value> = value.split(',').map(convert).collect();
+        let value = match values.len() {
+            1 => values.into_iter().next().unwrap_or(Value::Null),
+            _ => Value::Array(values),
+        };
+        parts.insert(key.to_ascii_lowercase(), value);
+    }
+    Value::Object(parts)
+}
+
+fn recur_to_ical(parts: &Map<String, Value>) -> String {
+    let format_part = |(key, value): (&String, &Value)| {
+        let key = key.to_ascii_uppercase();
+        let convert = |v: &Value| match key.as_str() {
+            "UNTIL" => date_time_to_ical(&scalar_to_string(v)),
+            _ => scalar_to_string(v),
+        };
+        let value = match value {
+            Value::Array(values) => values.iter().map(convert).collect::<Vec<_>>().join(","),
+            value => convert(value),
+        };
+        format!("{}={}", key, value)
+    };
+    // FREQ comes first, for compatibility with RFC2445 parsers
+    parts.iter().filter(|(key, _)| key.as_str() == "freq")
+        .chain(parts.iter().filter(|(key, _)| key.as_str() != "freq"))
+        .map(format_part)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The value of an iCal `TEXT`, whose `\\`, `;`, `,` and line breaks are escaped
+pub(crate) fn unescape_text(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(escaped @ ('\\' | ';' | ',')) => result.push(escaped),
+            Some(other) => { result.push('\\'); result.push(other); },
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Escape a value so that it can be written as an iCal `TEXT` (see [RFC5545](https://tools.ietf.org/html/rfc5545#section-3.3.11))
+pub(crate) fn escape_text(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+/// The (still escaped) values of a list of iCal `TEXT`s, e.g. the ones of a `CATEGORIES` property
+pub(crate) fn split_unescaped_commas(value: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                result.push(&value[start..i]);
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    result.push(&value[start..]);
+    result
+}
+
+/// Append a content line, folded every 75 octets as RFC5545 says
+fn write_line(line: &str, ical: &mut String) {
+    let mut line_length = 0;
+    for c in line.chars() {
+        if line_length + c.len_utf8() > 75 {
+            ical.push_str("\r\n ");
+            line_length = 1;
+        }
+        ical.push(c);
+        line_length += c.len_utf8();
+    }
+    ical.push_str("\r\n");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ical::canonical_form;
+    use crate::item::SyncStatus;
+
+    #[test]
+    fn test_ical_to_jcal() {
+        let ical = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nUID:abc\r\nSUMMARY:Buy milk\\, eggs\\nand bread\r\nDUE;TZID=Europe/Paris:20211103T220000\r\nDTSTAMP:20211103T214742Z\r\nPRIORITY:6\r\nCATEGORIES:Shopping,Food\\, and drinks\r\nRRULE:FREQ=WEEKLY;BYDAY=MO,TU;COUNT=3\r\nX-SOMETHING;X-PARAM=a,b:raw\\,value\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let jcal = ical_to_jcal(ical).unwrap();
+        let expected: Value = serde_json::from_str(r#"
+            ["vcalendar",
+                [["version", {}, "text", "2.0"]],
+                [["vtodo",
+                    [
+                        ["uid", {}, "text", "abc"],
+                        ["summary", {}, "text", "Buy milk, eggs\nand bread"],
+                        ["due", {"tzid": "Europe/Paris"}, "date-time", "2021-11-03T22:00:00"],
+                        ["dtstamp", {}, "date-time", "2021-11-03T21:47:42Z"],
+                        ["priority", {}, "integer", 6],
+                        ["categories", {}, "text", "Shopping", "Food, and drinks"],
+                        ["rrule", {}, "recur", {"freq": "WEEKLY", "byday": ["MO", "TU"], "count": 3}],
+                        ["x-something", {"x-param": ["a", "b"]}, "unknown", "raw\\,value"]
+                    ],
+                    []
+                ]]
+            ]"#).unwrap();
+        assert_eq!(jcal, expected);
+        assert_eq!(jcal_to_ical(&jcal).unwrap(), ical);
+
+        assert!(ical_to_jcal("BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\n").is_err());
+        assert!(jcal_to_ical(&serde_json::json!(["vcalendar", {}])).is_err());
+    }
+
+    #[test]
+    fn test_jcal_round_trip() {
+        for ical in [
+            std::fs::read_to_string("tests/assets/ical_with_unknown_fields.ics").unwrap(),
+            "BEGIN:VCALENDAR\r\nBEGIN:VTIMEZONE\r\nTZID:Europe/Paris\r\nBEGIN:STANDARD\r\nDTSTART:19701025T030000\r\nTZOFFSETFROM:+0200\r\nTZOFFSETTO:+0100\r\nEND:STANDARD\r\nEND:VTIMEZONE\r\nBEGIN:VTODO\r\nUID:x\r\nDTSTAMP:20211103T214742Z\r\nDTSTART;VALUE=DATE:20211103\r\nGEO:37.386013;-122.082932\r\nBEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER;RELATED=END:-PT15M\r\nEND:VALARM\r\nSUMMARY:Some task\r\nEND:VTODO\r\nEND:VCALENDAR\r\n".to_string(),
+        ] {
+            let round_trip = jcal_to_ical(&ical_to_jcal(&ical).unwrap()).unwrap();
+            let url: url::Url = "https://some.server/item.ics".parse().unwrap();
+            let original = crate::ical::parse(&ical, url.clone(), SyncStatus::NotSynced).unwrap();
+            let converted = crate::ical::parse(&round_trip, url, SyncStatus::NotSynced).unwrap();
+            assert_eq!(canonical_form(&original).unwrap(), canonical_form(&converted).unwrap());
+            assert_eq!(ical_to_jcal(&round_trip).unwrap(), ical_to_jcal(&ical).unwrap());
+        }
+    }
+}
@@ -4,8 +4,22 @@
 
 mod parser;
 pub use parser::parse;
+pub use parser::parse_with_policy;
+pub use parser::parse_for_calendar;
+pub use parser::UnsupportedComponentError;
 mod builder;
-pub use builder::build_from;
+pub use builder::{build_from, build_from_task};
+pub use builder::{build_streamed_from, StreamedIcal};
+mod canonical;
+pub use canonical::{canonical_form, content_hash, have_same_content};
+mod diff;
+pub use diff::{diff, PropertyChange, PropertyValue};
+pub mod recurrence;
+mod split;
+pub use split::{split_ics, IcalResource};
+mod jcal;
+pub use jcal::{ical_to_jcal, jcal_to_ical};
+pub(crate) use jcal::{escape_text, split_unescaped_commas, unescape_text};
 
 use crate::config::{ORG_NAME, PRODUCT_NAME};
 
@@ -13,6 +27,20 @@ pub fn default_prod_id() -> String {
     format!("-//{}//{}//EN", ORG_NAME.lock().unwrap(), PRODUCT_NAME.lock().unwrap())
 }
 
+/// What to do with an iCal resource that contains several components (e.g. both a `VTODO` and a `VEVENT`)
+///
+/// The default policy is set in [`crate::config::MULTIPLE_COMPONENTS_POLICY`]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MultipleComponentsPolicy {
+    /// Refuse to parse such resources
+    Reject,
+    /// In case every component shares the same UID, the item is built out of the main component (a `VTODO` if any, otherwise a `VEVENT`),
+    /// and the other ones are kept alongside it (see e.g. [`crate::Task::extra_components`]), so that they are not lost when the item is written back.
+    /// Resources whose components have different UIDs are refused.
+    #[default]
+    GroupByUid,
+}
+
 
 
 #[cfg(test)]
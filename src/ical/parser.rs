@@ -3,18 +3,75 @@
 use std::error::Error;
 
 use ical::parser::ical::component::{IcalCalendar, IcalEvent, IcalTodo};
+use ical::property::Property;
 use chrono::{DateTime, TimeZone, Utc};
 use url::Url;
 
 use crate::Item;
-use crate::item::SyncStatus;
+use crate::item::{RawComponent, SyncStatus};
 use crate::Task;
-use crate::task::CompletionStatus;
+use crate::task::{CompletionStatus, DeviceStamp, DEVICE_ORIGIN_PARAMETER, DEVICE_PROPERTY};
 use crate::Event;
+use crate::datetime::CalDateTime;
+use crate::attachment::Attachment;
+use crate::config::MULTIPLE_COMPONENTS_POLICY;
+use crate::calendar::SupportedComponents;
+use crate::error_code::{self, ErrorCode};
+use super::MultipleComponentsPolicy;
+
+
+/// The error returned when an item does not contain any component its calendar supports (e.g. a `VEVENT` in a calendar that only supports tasks)
+#[derive(Debug)]
+pub struct UnsupportedComponentError {
+    pub item: Url,
+    /// The main component the item contains instead (e.g. `VEVENT`), in case it contains one
+    pub component: Option<String>,
+    /// The components the calendar of the item supports
+    pub supported: SupportedComponents,
+}
+
+impl UnsupportedComponentError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::UNSUPPORTED_COMPONENT
+    }
+}
+
+impl std::fmt::Display for UnsupportedComponentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.component {
+            Some(component) => write!(f, "item {} contains a {}, but its calendar only supports {} [{}]", self.item, component, self.supported, self.code()),
+            None => write!(f, "item {} contains no {} [{}]", self.item, self.supported, self.code()),
+        }
+    }
+}
+
+impl Error for UnsupportedComponentError {}
 
 
 /// Parse an iCal file into the internal representation [`crate::Item`]
+///
+/// Resources that contain several components are handled according to [`crate::config::MULTIPLE_COMPONENTS_POLICY`]
 pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<Item, Box<dyn Error>> {
+    let policy = *MULTIPLE_COMPONENTS_POLICY.lock().unwrap();
+    parse_with_policy(content, item_url, sync_status, policy)
+}
+
+/// Parse an iCal file into the internal representation [`crate::Item`], with a given policy for resources that contain several components
+pub fn parse_with_policy(content: &str, item_url: Url, sync_status: SyncStatus, policy: MultipleComponentsPolicy) -> Result<Item, Box<dyn Error>> {
+    parse_with_policy_and_components(content, item_url, sync_status, policy, SupportedComponents::all())
+}
+
+/// Parse an iCal file that comes from a calendar that supports the given components into the internal representation [`crate::Item`]
+///
+/// Calendars that only support tasks (i.e. whose supported components are exactly [`SupportedComponents::TODO`]) take a fast path, that never considers `VEVENT`s as the main component of an item.
+/// The same goes for calendars that only support events, that never consider `VTODO`s.
+pub fn parse_for_calendar(content: &str, item_url: Url, sync_status: SyncStatus, supported_components: SupportedComponents) -> Result<Item, Box<dyn Error>> {
+    let policy = *MULTIPLE_COMPONENTS_POLICY.lock().unwrap();
+    parse_with_policy_and_components(content, item_url, sync_status, policy, supported_components)
+}
+
+fn parse_with_policy_and_components(content: &str, item_url: Url, sync_status: SyncStatus, policy: MultipleComponentsPolicy, supported_components: SupportedComponents) -> Result<Item, Box<dyn Error>> {
     let mut reader = ical::IcalParser::new(content.as_bytes());
     let parsed_item = match reader.next() {
         None => return Err(format!("Invalid iCal data to parse for item {}", item_url).into()),
@@ -28,9 +85,66 @@ pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<It
         .map(|s| s.to_string())
         .unwrap_or_else(|| super::default_prod_id());
 
-    let item = match assert_single_type(&parsed_item)? {
-        CurrentType::Event(_) => {
-            Item::Event(Event::new())
+    let tasks_only = supported_components == SupportedComponents::TODO;
+    let events_only = supported_components == SupportedComponents::EVENT;
+    if (tasks_only && parsed_item.todos.is_empty()) || (events_only && parsed_item.events.is_empty()) {
+        let component = [("VTODO", parsed_item.todos.len()), ("VEVENT", parsed_item.events.len()), ("VJOURNAL", parsed_item.journals.len())].iter()
+            .find(|(_, count)| *count > 0)
+            .map(|(name, _)| name.to_string());
+        return Err(Box::new(UnsupportedComponentError { item: item_url, component, supported: supported_components }));
+    }
+    let (main_component, extra_components) = select_main_component(&parsed_item, policy, supported_components)?;
+    let item = match main_component {
+        CurrentType::Event(event) => {
+            let mut name = None;
+            let mut uid = None;
+            let mut last_modified = None;
+            let mut creation_date = None;
+            let mut start = None;
+            let mut end = None;
+            let mut location = None;
+            let mut extra_parameters = Vec::new();
+
+            for prop in &event.properties {
+                match prop.name.as_str() {
+                    "SUMMARY" => { name = prop.value.clone() },
+                    "UID" => { uid = prop.value.clone() },
+                    // See the comments about these properties for tasks, below
+                    "DTSTAMP" | "LAST-MODIFIED" => { last_modified = parse_date_time_from_property(&prop.value) },
+                    "CREATED" => { creation_date = parse_date_time_from_property(&prop.value) },
+                    "LOCATION" => { location = prop.value.clone() },
+                    "DTSTART" | "DTEND" => {
+                        match CalDateTime::from_property(prop) {
+                            Err(err) => {
+                                log::warn!("Invalid {} for item {}: {}. Keeping it as an unparsed property", prop.name, item_url, err);
+                                extra_parameters.push(prop.clone());
+                            },
+                            Ok(dt) if prop.name == "DTSTART" => start = Some(dt),
+                            Ok(dt) => end = Some(dt),
+                        }
+                    },
+                    _ => {
+                        // This field is not supported. Let's store it anyway, so that we are able to re-create an identical iCal file
+                        extra_parameters.push(prop.clone());
+                    }
+                }
+            }
+            // Events are not required to have a SUMMARY (e.g. busy slots)
+            let name = name.unwrap_or_default();
+            let uid = match uid {
+                Some(uid) => uid,
+                None => return Err(format!("Missing UID for item {}", item_url).into()),
+            };
+            let last_modified = match last_modified {
+                Some(dt) => dt,
+                None => return Err(format!("Missing DTSTAMP for item {}, but this is required by RFC5545", item_url).into()),
+            };
+
+            Item::Event(Event::new_with_parameters(name, uid, item_url, sync_status, creation_date, last_modified, ical_prod_id, extra_parameters)
+                .with_dates(start, end)
+                .with_location(location)
+                .with_alarms(event.alarms.iter().map(|alarm| RawComponent::new("VALARM".to_string(), alarm.properties.clone(), Vec::new())).collect())
+                .with_extra_components(extra_components))
         },
 
         CurrentType::Todo(todo) => {
@@ -40,6 +154,10 @@ pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<It
             let mut last_modified = None;
             let mut completion_date = None;
             let mut creation_date = None;
+            let mut due = None;
+            let mut start = None;
+            let mut attachments = Vec::new();
+            let mut device_stamp = DeviceStamp::default();
             let mut extra_parameters = Vec::new();
 
             for prop in &todo.properties {
@@ -71,6 +189,30 @@ pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<It
                         // The property can be specified once, but is not mandatory
                         creation_date = parse_date_time_from_property(&prop.value)
                     },
+                    "DUE" | "DTSTART" => {
+                        // These can be dates, floating date-times, UTC date-times or date-times with a TZID
+                        match CalDateTime::from_property(prop) {
+                            Err(err) => {
+                                log::warn!("Invalid {} for item {}: {}. Keeping it as an unparsed property", prop.name, item_url, err);
+                                extra_parameters.push(prop.clone());
+                            },
+                            Ok(dt) if prop.name == "DUE" => due = Some(dt),
+                            Ok(dt) => start = Some(dt),
+                        }
+                    },
+                    "ATTACH" => {
+                        // Inline (base64) attachments or references to an external URI
+                        match Attachment::from_property(prop) {
+                            Some(attachment) => attachments.push(attachment),
+                            None => extra_parameters.push(prop.clone()),
+                        }
+                    },
+                    DEVICE_PROPERTY => {
+                        let origin = prop.params.iter().flatten()
+                            .find(|(key, _)| key.eq_ignore_ascii_case(DEVICE_ORIGIN_PARAMETER))
+                            .and_then(|(_, values)| values.first().cloned());
+                        device_stamp = DeviceStamp { origin, last_modified_by: prop.value.clone() };
+                    },
                     "STATUS" => {
                         // Possible values:
                         //   "NEEDS-ACTION" ;Indicates to-do needs action.
@@ -109,7 +251,12 @@ pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<It
                 true => CompletionStatus::Completed(completion_date),
             };
 
-            Item::Task(Task::new_with_parameters(name, uid, item_url, completion_status, sync_status, creation_date, last_modified, ical_prod_id, extra_parameters))
+            Item::Task(Task::new_with_parameters(name, uid, item_url, completion_status, sync_status, creation_date, last_modified, ical_prod_id, extra_parameters)
+                .with_dates(due, start)
+                .with_attachments(attachments)
+                .with_device_stamp(device_stamp)
+                .with_alarms(todo.alarms.iter().map(|alarm| RawComponent::new("VALARM".to_string(), alarm.properties.clone(), Vec::new())).collect())
+                .with_extra_components(extra_components))
         },
     };
 
@@ -155,28 +302,80 @@ enum CurrentType<'a> {
     Todo(&'a IcalTodo),
 }
 
-fn assert_single_type<'a>(item: &'a IcalCalendar) -> Result<CurrentType<'a>, Box<dyn Error>> {
-    let n_events = item.events.len();
-    let n_todos = item.todos.len();
-    let n_journals = item.journals.len();
+/// Returns the component an item should be built from, and the other ones (that will be kept aside).
+///
+/// In case the calendar only supports tasks, the main component can only be a `VTODO` (and `item` must contain one). The same goes for `VEVENT`s in calendars that only support events
+fn select_main_component(item: &IcalCalendar, policy: MultipleComponentsPolicy, supported_components: SupportedComponents) -> Result<(CurrentType<'_>, Vec<RawComponent>), Box<dyn Error>> {
+    let n_components = item.events.len() + item.todos.len() + item.journals.len();
 
-    if n_events == 1 {
-        if n_todos != 0 || n_journals != 0 {
-            return Err("Only a single TODO or a single EVENT is supported".into());
-        } else {
-            return Ok(CurrentType::Event(&item.events[0]));
+    if n_components == 1 {
+        if let Some(todo) = item.todos.first() {
+            return Ok((CurrentType::Todo(todo), Vec::new()));
         }
+        if let Some(event) = item.events.first() {
+            return Ok((CurrentType::Event(event), Vec::new()));
+        }
+    }
+    if n_components <= 1 || policy == MultipleComponentsPolicy::Reject {
+        return Err("Only a single TODO or a single EVENT is supported".into());
+    }
+
+    // Several components. They must be "grouped" by UID, i.e. they all must have the same UID
+    let uids: Vec<Option<&str>> = item.todos.iter().map(|c| &c.properties)
+        .chain(item.events.iter().map(|c| &c.properties))
+        .chain(item.journals.iter().map(|c| &c.properties))
+        .map(|props| property_value(props, "UID"))
+        .collect();
+    if uids.iter().any(|uid| uid.is_none() || uid != &uids[0]) {
+        return Err("Multiple components in a single iCal resource must share the same UID".into());
     }
 
-    if n_todos == 1 {
-        if n_events != 0 || n_journals != 0 {
-            return Err("Only a single TODO or a single EVENT is supported".into());
-        } else {
-            return Ok(CurrentType::Todo(&item.todos[0]));
+    // The main component is a non-overriding (i.e. without a RECURRENCE-ID) TODO, or EVENT, in this order
+    let is_main = |props: &Vec<Property>| property_value(props, "RECURRENCE-ID").is_none();
+    let main_todo = match supported_components == SupportedComponents::EVENT {
+        true => None,
+        false => item.todos.iter().position(|c| is_main(&c.properties)),
+    };
+    let main_event = match supported_components == SupportedComponents::TODO {
+        true => None,
+        false => item.events.iter().position(|c| is_main(&c.properties)),
+    };
+
+    let (main_component, main_todo, main_event) = match (main_todo, main_event) {
+        (Some(i), _) => (CurrentType::Todo(&item.todos[i]), Some(i), None),
+        (None, Some(i)) => (CurrentType::Event(&item.events[i]), None, Some(i)),
+        (None, None) => return Err("Unable to find the main TODO or EVENT among multiple components".into()),
+    };
+
+    let mut extra_components = Vec::new();
+    for (i, todo) in item.todos.iter().enumerate() {
+        if Some(i) != main_todo {
+            extra_components.push(raw_component("VTODO", &todo.properties, &todo.alarms.iter().map(|a| &a.properties).collect::<Vec<_>>()));
         }
     }
+    for (i, event) in item.events.iter().enumerate() {
+        if Some(i) != main_event {
+            extra_components.push(raw_component("VEVENT", &event.properties, &event.alarms.iter().map(|a| &a.properties).collect::<Vec<_>>()));
+        }
+    }
+    for journal in &item.journals {
+        extra_components.push(raw_component("VJOURNAL", &journal.properties, &[]));
+    }
 
-    return Err("Only a single TODO or a single EVENT is supported".into());
+    Ok((main_component, extra_components))
+}
+
+fn raw_component(name: &str, properties: &[Property], alarms: &[&Vec<Property>]) -> RawComponent {
+    let alarms = alarms.iter()
+        .map(|props| RawComponent::new("VALARM".to_string(), props.to_vec(), Vec::new()))
+        .collect();
+    RawComponent::new(name.to_string(), properties.to_vec(), alarms)
+}
+
+fn property_value<'a>(properties: &'a [Property], name: &str) -> Option<&'a str> {
+    properties.iter()
+        .find(|prop| prop.name == name)
+        .and_then(|prop| prop.value.as_deref())
 }
 
 
@@ -223,6 +422,36 @@ SUMMARY:Clean up your room or Mom will be angry
 STATUS:COMPLETED
 END:VTODO
 END:VCALENDAR
+"#;
+
+const EXAMPLE_ICAL_WITH_DATES: &str = r#"BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Nextcloud Tasks v0.13.6
+BEGIN:VTODO
+UID:0633de27-8c32-42be-bcb8-63bc879c6185@some-domain.com
+DTSTAMP:20210321T001600
+SUMMARY:Pay the rent
+DTSTART;TZID=Europe/Paris:20210328T090000
+DUE;VALUE=DATE:20210331
+END:VTODO
+END:VCALENDAR
+"#;
+
+const EXAMPLE_TODO_AND_EVENT: &str = r#"BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Nextcloud Tasks v0.13.6
+BEGIN:VEVENT
+UID:0633de27-8c32-42be-bcb8-63bc879c6185
+DTSTAMP:20210321T001600
+DTSTART:20210322T100000
+SUMMARY:Meeting to prepare the report
+END:VEVENT
+BEGIN:VTODO
+UID:0633de27-8c32-42be-bcb8-63bc879c6185
+DTSTAMP:20210321T001600
+SUMMARY:Write the report
+END:VTODO
+END:VCALENDAR
 "#;
 
     const EXAMPLE_MULTIPLE_ICAL: &str = r#"BEGIN:VCALENDAR
@@ -294,6 +523,97 @@ END:VCALENDAR
         assert_eq!(task.completion_status(), &CompletionStatus::Completed(None));
     }
 
+    #[test]
+    fn test_dates_ical_parsing() {
+        let sync_status = SyncStatus::NotSynced;
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        let item = parse(EXAMPLE_ICAL_WITH_DATES, item_url, sync_status).unwrap();
+        let task = item.unwrap_task();
+
+        assert_eq!(task.due(), Some(&CalDateTime::Date(chrono::NaiveDate::from_ymd(2021, 3, 31))));
+        assert_eq!(task.start(), Some(&CalDateTime::Zoned{ tzid: "Europe/Paris".to_string(), local: chrono::NaiveDate::from_ymd(2021, 3, 28).and_hms(9, 0, 0) }));
+        assert!(task.extra_parameters().is_empty());
+
+        // A date-only task is overdue once its whole day is over, wherever the user is
+        let tokyo: chrono_tz::Tz = "Asia/Tokyo".parse().unwrap();
+        let honolulu: chrono_tz::Tz = "Pacific/Honolulu".parse().unwrap();
+        let now = Utc.ymd(2021, 3, 31).and_hms(20, 0, 0);
+        assert_eq!(task.is_overdue(&now, &tokyo), true);
+        assert_eq!(task.is_overdue(&now, &honolulu), false);
+        assert_eq!(task.is_due_today(&now, &tokyo), false);
+        assert_eq!(task.is_due_today(&now, &honolulu), true);
+    }
+
+    #[test]
+    fn test_todo_and_event_in_ical() {
+        let sync_status = SyncStatus::NotSynced;
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        let rejected = parse_with_policy(EXAMPLE_TODO_AND_EVENT, item_url.clone(), sync_status.clone(), MultipleComponentsPolicy::Reject);
+        assert!(rejected.is_err());
+
+        let item = parse_with_policy(EXAMPLE_TODO_AND_EVENT, item_url.clone(), sync_status.clone(), MultipleComponentsPolicy::GroupByUid).unwrap();
+        let task = item.unwrap_task();
+        assert_eq!(task.name(), "Write the report");
+        assert_eq!(task.extra_components().len(), 1);
+        assert_eq!(task.extra_components()[0].name(), "VEVENT");
+
+        // The event must not be lost when the task is written back
+        let serialized = crate::ical::build_from(&item).unwrap();
+        assert!(serialized.contains("BEGIN:VEVENT\r\n"));
+        assert!(serialized.contains("SUMMARY:Meeting to prepare the report\r\n"));
+        let reparsed = parse_with_policy(&serialized, item_url.clone(), sync_status.clone(), MultipleComponentsPolicy::GroupByUid).unwrap();
+        assert_eq!(reparsed.unwrap_task().extra_components().len(), 1);
+
+        // Components with different UIDs cannot be grouped
+        let different_uids = EXAMPLE_TODO_AND_EVENT.replacen("UID:0633de27", "UID:1744ef38", 1);
+        assert!(parse_with_policy(&different_uids, item_url, sync_status, MultipleComponentsPolicy::GroupByUid).is_err());
+    }
+
+    #[test]
+    fn test_tasks_only_calendar() {
+        let sync_status = SyncStatus::NotSynced;
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+        let event_only = EXAMPLE_TODO_AND_EVENT.replace("BEGIN:VTODO\nUID:0633de27-8c32-42be-bcb8-63bc879c6185\nDTSTAMP:20210321T001600\nSUMMARY:Write the report\nEND:VTODO\n", "");
+        assert!(event_only.contains("BEGIN:VEVENT") && !event_only.contains("BEGIN:VTODO"));
+
+        let err = parse_for_calendar(&event_only, item_url.clone(), sync_status.clone(), SupportedComponents::TODO).unwrap_err();
+        let err = err.downcast_ref::<UnsupportedComponentError>().unwrap();
+        assert_eq!(err.component.as_deref(), Some("VEVENT"));
+
+        // Tasks are parsed as usual, and events that share their resource are still kept aside
+        let item = parse_for_calendar(EXAMPLE_TODO_AND_EVENT, item_url.clone(), sync_status.clone(), SupportedComponents::TODO).unwrap();
+        assert_eq!(item.unwrap_task().name(), "Write the report");
+        assert_eq!(item.unwrap_task().extra_components().len(), 1);
+        let item = parse_for_calendar(EXAMPLE_ICAL, item_url, sync_status, SupportedComponents::TODO).unwrap();
+        assert_eq!(item.unwrap_task().name(), "Do not forget to do this");
+    }
+
+    #[test]
+    fn test_event_ical_parsing() {
+        let sync_status = SyncStatus::NotSynced;
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+        let event_only = EXAMPLE_TODO_AND_EVENT.replace("BEGIN:VTODO\nUID:0633de27-8c32-42be-bcb8-63bc879c6185\nDTSTAMP:20210321T001600\nSUMMARY:Write the report\nEND:VTODO\n", "")
+            .replace("END:VEVENT", "DTEND:20210322T110000\nLOCATION:Room 101\nEND:VEVENT");
+
+        let item = parse(&event_only, item_url.clone(), sync_status.clone()).unwrap();
+        let event = item.unwrap_event();
+        assert_eq!(event.name(), "Meeting to prepare the report");
+        assert_eq!(event.uid(), "0633de27-8c32-42be-bcb8-63bc879c6185");
+        assert_eq!(event.start(), Some(&CalDateTime::Floating(chrono::NaiveDate::from_ymd(2021, 3, 22).and_hms(10, 0, 0))));
+        assert_eq!(event.end(), Some(&CalDateTime::Floating(chrono::NaiveDate::from_ymd(2021, 3, 22).and_hms(11, 0, 0))));
+        assert_eq!(event.location(), Some("Room 101"));
+        assert!(event.extra_parameters().is_empty());
+
+        // Calendars that only support events pick the event, and refuse tasks
+        let item = parse_for_calendar(EXAMPLE_TODO_AND_EVENT, item_url.clone(), sync_status.clone(), SupportedComponents::EVENT).unwrap();
+        assert_eq!(item.unwrap_event().name(), "Meeting to prepare the report");
+        assert_eq!(item.unwrap_event().extra_components()[0].name(), "VTODO");
+        let err = parse_for_calendar(EXAMPLE_ICAL, item_url, sync_status, SupportedComponents::EVENT).unwrap_err();
+        assert_eq!(err.downcast_ref::<UnsupportedComponentError>().unwrap().component.as_deref(), Some("VTODO"));
+    }
+
     #[test]
     fn test_multiple_items_in_ical() {
         let version_tag = VersionTag::from(String::from("test-tag"));
@@ -0,0 +1,433 @@
+//! Concrete instances of (possibly recurring) iCal components (see [`crate::calendar::remote_calendar::RemoteCalendar::get_expanded_instances`])
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use ical::property::Property;
+use url::Url;
+
+use crate::datetime::CalDateTime;
+
+/// How many occurrences of a recurrence rule are considered at most, so that rules without an end do not loop forever
+const MAX_OCCURRENCES: usize = 10_000;
+
+/// A single occurrence of an iCal component
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+    /// The URL of the resource that contains this instance
+    pub url: Url,
+    /// The kind of component (`VTODO` or `VEVENT`)
+    pub component: String,
+    pub uid: String,
+    pub name: String,
+    /// The original start of this occurrence, in case it belongs to a recurring component
+    pub recurrence_id: Option<CalDateTime>,
+    /// The `DTSTART` of this occurrence
+    pub start: Option<CalDateTime>,
+    /// The `DUE` (for tasks) or the `DTEND` (for events) of this occurrence
+    pub end: Option<CalDateTime>,
+}
+
+impl Instance {
+    /// The date this instance is sorted and filtered by: its start, or its end for components that have no start
+    fn reference_date(&self) -> Option<&CalDateTime> {
+        self.start.as_ref().or(self.end.as_ref())
+    }
+
+    fn is_within(&self, range_start: &DateTime<Utc>, range_end: &DateTime<Utc>, reference_tz: &Tz) -> bool {
+        match self.reference_date() {
+            // Components without any date belong to every time range (RFC 4791, section 9.9)
+            None => true,
+            Some(date) => {
+                let date = date.to_utc(reference_tz);
+                range_start <= &date && &date < range_end
+            },
+        }
+    }
+}
+
+/// Returns whether an iCal resource contains recurrence rules, that must be expanded to get its instances
+pub(crate) fn has_recurrence_rules(ical: &str) -> bool {
+    ical.lines().map(|line| line.trim_start()).any(|line| line.starts_with("RRULE:") || line.starts_with("RRULE;"))
+}
+
+/// Returns the instances of an iCal resource, as they are (e.g. because a server has already expanded its recurrences)
+pub(crate) fn instances_of(ical: &str, url: &Url) -> Result<Vec<Instance>, Box<dyn Error>> {
+    Ok(components_of(ical)?.into_iter()
+        .map(|(component, props)| instance_from(url, component, &props))
+        .collect())
+}
+
+/// Remove the instances that are listed several times (e.g. because they have been returned by several time-range queries), keeping the first one.
+///
+/// Instances are told apart by their UID and their `RECURRENCE-ID`
+pub(crate) fn dedup(instances: Vec<Instance>) -> Vec<Instance> {
+    let mut seen = HashSet::new();
+    instances.into_iter()
+        .filter(|instance| seen.insert((instance.uid.clone(), instance.recurrence_id.clone())))
+        .collect()
+}
+
+/// Returns the instances of an iCal resource that start within a time range, expanding its recurrence rules.
+///
+/// Only rules that consist of `FREQ`, `INTERVAL`, `COUNT`, `UNTIL` (and `WKST`) are supported, other rules are refused. \
+/// `EXDATE`s and overridden occurrences (i.e. components with a `RECURRENCE-ID`) are taken into account.
+/// Values without a timezone are interpreted in `reference_tz`.
+pub fn expand(ical: &str, url: &Url, range_start: DateTime<Utc>, range_end: DateTime<Utc>, reference_tz: &Tz) -> Result<Vec<Instance>, Box<dyn Error>> {
+    let components = components_of(ical)?;
+
+    // Overridden occurrences, by UID and original start
+    let mut overrides: HashMap<(String, DateTime<Utc>), Instance> = HashMap::new();
+    for (component, props) in &components {
+        let instance = instance_from(url, component, props);
+        if let Some(recurrence_id) = &instance.recurrence_id {
+            overrides.insert((instance.uid.clone(), recurrence_id.to_utc(reference_tz)), instance);
+        }
+    }
+
+    let mut instances = Vec::new();
+    for (component, props) in &components {
+        let master = instance_from(url, component, props);
+        if master.recurrence_id.is_some() {
+            continue;
+        }
+        let rule = match property(props, "RRULE").and_then(|p| p.value.as_deref()) {
+            None => {
+                if master.is_within(&range_start, &range_end, reference_tz) {
+                    instances.push(master);
+                }
+                continue;
+            },
+            Some(rule) => RecurrenceRule::parse(rule)?,
+        };
+        let excluded: Vec<DateTime<Utc>> = props.iter()
+            .filter(|p| p.name == "EXDATE")
+            .flat_map(|p| split_dates(p, reference_tz))
+            .collect();
+
+        for occurrence in rule.occurrences(&master, &range_end, reference_tz) {
+            let original_start = match occurrence.reference_date() {
+                None => break,
+                Some(date) => date.to_utc(reference_tz),
+            };
+            if excluded.contains(&original_start) {
+                continue;
+            }
+            if overrides.contains_key(&(master.uid.clone(), original_start)) {
+                continue;
+            }
+            if occurrence.is_within(&range_start, &range_end, reference_tz) {
+                instances.push(occurrence);
+            }
+        }
+    }
+
+    instances.extend(overrides.into_values().filter(|instance| instance.is_within(&range_start, &range_end, reference_tz)));
+    instances.sort_by_key(|instance| instance.reference_date().map(|date| date.to_utc(reference_tz)));
+    Ok(instances)
+}
+
+/// The kind (e.g. `VTODO`) and the properties of iCal components
+type Components = Vec<(&'static str, Vec<Property>)>;
+
+fn components_of(ical: &str) -> Result<Components, Box<dyn Error>> {
+    let mut components = Vec::new();
+    for calendar in ical::IcalParser::new(ical.as_bytes()) {
+        let calendar = calendar?;
+        components.extend(calendar.todos.into_iter().map(|todo| ("VTODO", todo.properties)));
+        components.extend(calendar.events.into_iter().map(|event| ("VEVENT", event.properties)));
+    }
+    Ok(components)
+}
+
+fn property<'a>(props: &'a [Property], name: &str) -> Option<&'a Property> {
+    props.iter().find(|p| p.name == name)
+}
+
+fn date_property(props: &[Property], name: &str) -> Option<CalDateTime> {
+    property(props, name).and_then(|p| {
+        CalDateTime::from_property(p)
+            .map_err(|err| log::warn!("Invalid {}: {}", name, err))
+            .ok()
+    })
+}
+
+fn instance_from(url: &Url, component: &str, props: &[Property]) -> Instance {
+    let text = |name: &str| property(props, name).and_then(|p| p.value.clone()).unwrap_or_default();
+    let end_property = if component == "VTODO" { "DUE" } else { "DTEND" };
+    Instance {
+        url: url.clone(),
+        component: component.to_string(),
+        uid: text("UID"),
+        name: text("SUMMARY"),
+        recurrence_id: date_property(props, "RECURRENCE-ID"),
+        start: date_property(props, "DTSTART"),
+        end: date_property(props, end_property),
+    }
+}
+
+/// The dates of a multi-valued date property (such as `EXDATE`)
+fn split_dates(prop: &Property, reference_tz: &Tz) -> Vec<DateTime<Utc>> {
+    prop.value.as_deref().unwrap_or_default()
+        .split(',')
+        .filter_map(|value| {
+            let single = Property { name: prop.name.clone(), params: prop.params.clone(), value: Some(value.to_string()) };
+            CalDateTime::from_property(&single).ok()
+        })
+        .map(|date| date.to_utc(reference_tz))
+        .collect()
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The subset of RFC 5545 recurrence rules this crate is able to expand
+#[derive(Clone, Debug)]
+struct RecurrenceRule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<CalDateTime>,
+}
+
+impl RecurrenceRule {
+    fn parse(rule: &str) -> Result<Self, Box<dyn Error>> {
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("Invalid RRULE part {}", part))?;
+            match key.to_uppercase().as_str() {
+                "FREQ" => frequency = Some(match value.to_uppercase().as_str() {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(format!("Unsupported RRULE frequency {}", value).into()),
+                }),
+                "INTERVAL" => interval = value.parse()?,
+                "COUNT" => count = Some(value.parse()?),
+                "UNTIL" => until = Some(parse_until(value)?),
+                "WKST" => (),
+                _ => return Err(format!("Unsupported RRULE part {}", part).into()),
+            }
+        }
+        if interval == 0 {
+            return Err("Invalid RRULE interval 0".into());
+        }
+        let frequency = frequency.ok_or("RRULE without a FREQ")?;
+        Ok(Self { frequency, interval, count, until })
+    }
+
+    /// The occurrences of `master` (including itself), until `range_end` (or until the rule ends, if it ends before)
+    fn occurrences(&self, master: &Instance, range_end: &DateTime<Utc>, reference_tz: &Tz) -> Vec<Instance> {
+        let mut result = Vec::new();
+        let until = self.until.as_ref().map(|until| until.to_utc(reference_tz));
+        for step in 0..MAX_OCCURRENCES {
+            if self.count.map(|count| result.len() >= count) == Some(true) {
+                break;
+            }
+            let n = step as i64 * self.interval as i64;
+            // `None` if the shifted date does not exist, `Some(None)` if there is no date to shift
+            let shifted = |date: &Option<CalDateTime>| -> Option<Option<CalDateTime>> {
+                match date {
+                    None => Some(None),
+                    Some(date) => shift(date, self.frequency, n).map(Some),
+                }
+            };
+            let (start, end) = match (shifted(&master.start), shifted(&master.end)) {
+                (Some(start), Some(end)) => (start, end),
+                // This occurrence does not exist (e.g. a 31st in a 30-day month)
+                _ => continue,
+            };
+            let occurrence = Instance {
+                recurrence_id: start.clone().or_else(|| end.clone()),
+                start, end,
+                ..master.clone()
+            };
+            let date = match occurrence.reference_date() {
+                None => break,
+                Some(date) => date.to_utc(reference_tz),
+            };
+            if &date >= range_end || until.map(|until| date > until) == Some(true) {
+                break;
+            }
+            result.push(occurrence);
+        }
+        result
+    }
+}
+
+fn parse_until(value: &str) -> Result<CalDateTime, Box<dyn Error>> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")?;
+        return Ok(CalDateTime::Utc(DateTime::from_utc(naive, Utc)));
+    }
+    if value.len() == 8 {
+        return Ok(CalDateTime::Date(NaiveDate::parse_from_str(value, "%Y%m%d")?));
+    }
+    Ok(CalDateTime::Floating(NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?))
+}
+
+/// Shift a date by `n` periods of `frequency`. Returns `None` in case the resulting date does not exist
+fn shift(date: &CalDateTime, frequency: Frequency, n: i64) -> Option<CalDateTime> {
+    let shift_naive = |dt: &NaiveDateTime| shift_date(&dt.date(), frequency, n).map(|d| d.and_time(dt.time()));
+    match date {
+        CalDateTime::Date(d) => shift_date(d, frequency, n).map(CalDateTime::Date),
+        CalDateTime::Floating(dt) => shift_naive(dt).map(CalDateTime::Floating),
+        CalDateTime::Utc(dt) => shift_naive(&dt.naive_utc()).map(|dt| CalDateTime::Utc(DateTime::from_utc(dt, Utc))),
+        CalDateTime::Zoned{ tzid, local } => shift_naive(local).map(|local| CalDateTime::Zoned{ tzid: tzid.clone(), local }),
+    }
+}
+
+fn shift_date(date: &NaiveDate, frequency: Frequency, n: i64) -> Option<NaiveDate> {
+    match frequency {
+        Frequency::Daily => date.checked_add_signed(Duration::days(n)),
+        Frequency::Weekly => date.checked_add_signed(Duration::weeks(n)),
+        Frequency::Monthly => add_months(date, n),
+        Frequency::Yearly => add_months(date, 12 * n),
+    }
+}
+
+fn add_months(date: &NaiveDate, n: i64) -> Option<NaiveDate> {
+    let months = date.year() as i64 * 12 + date.month0() as i64 + n;
+    NaiveDate::from_ymd_opt((months.div_euclid(12)) as i32, (months.rem_euclid(12) + 1) as u32, date.day())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    const RECURRING_TASK: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Some client//EN\r
+BEGIN:VTODO\r
+UID:recurring\r
+SUMMARY:Pay the rent\r
+DTSTART;VALUE=DATE:20210131\r
+DUE;VALUE=DATE:20210201\r
+RRULE:FREQ=MONTHLY;COUNT=6\r
+EXDATE;VALUE=DATE:20210731\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:recurring\r
+SUMMARY:Pay the rent (late)\r
+RECURRENCE-ID;VALUE=DATE:20210331\r
+DTSTART;VALUE=DATE:20210405\r
+DUE;VALUE=DATE:20210406\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+    fn url() -> Url {
+        "https://some.server/calendars/rent.ics".parse().unwrap()
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> Option<CalDateTime> {
+        Some(CalDateTime::Date(NaiveDate::from_ymd(y, m, d)))
+    }
+
+    #[test]
+    fn test_client_side_expansion() {
+        let tz = chrono_tz::UTC;
+        let instances = expand(RECURRING_TASK, &url(), Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Utc.ymd(2022, 1, 1).and_hms(0, 0, 0), &tz).unwrap();
+        let starts: Vec<_> = instances.iter().map(|i| i.start.clone()).collect();
+        // Months without a 31st are skipped (and not counted), July is excluded (but counted), and March is overridden
+        assert_eq!(starts, vec![date(2021, 1, 31), date(2021, 4, 5), date(2021, 5, 31), date(2021, 8, 31), date(2021, 10, 31)]);
+        assert_eq!(instances[0].end, date(2021, 2, 1));
+        assert_eq!(instances[1].name, "Pay the rent (late)");
+        assert_eq!(instances[1].recurrence_id, date(2021, 3, 31));
+        assert_eq!(instances[2].recurrence_id, date(2021, 5, 31));
+        assert_eq!(instances[2].end, date(2021, 6, 1));
+        assert!(instances.iter().all(|i| i.uid == "recurring" && i.component == "VTODO"));
+
+        // Only the instances within the range are returned
+        let instances = expand(RECURRING_TASK, &url(), Utc.ymd(2021, 5, 1).and_hms(0, 0, 0), Utc.ymd(2021, 10, 1).and_hms(0, 0, 0), &tz).unwrap();
+        let starts: Vec<_> = instances.iter().map(|i| i.start.clone()).collect();
+        assert_eq!(starts, vec![date(2021, 5, 31), date(2021, 8, 31)]);
+    }
+
+    #[test]
+    fn test_dedup_across_chunks() {
+        let tz = chrono_tz::UTC;
+        let single = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+UID:holidays\r
+SUMMARY:Holidays\r
+DTSTART;VALUE=DATE:20210328\r
+DTEND;VALUE=DATE:20210405\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let whole_range = expand(RECURRING_TASK, &url(), Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Utc.ymd(2022, 1, 1).and_hms(0, 0, 0), &tz).unwrap();
+
+        // Overlapping chunks return some instances twice, and the single event belongs to both of them
+        let mut chunked = Vec::new();
+        for (start, end) in [(Utc.ymd(2021, 1, 1), Utc.ymd(2021, 6, 1)), (Utc.ymd(2021, 4, 1), Utc.ymd(2022, 1, 1))] {
+            chunked.extend(expand(RECURRING_TASK, &url(), start.and_hms(0, 0, 0), end.and_hms(0, 0, 0), &tz).unwrap());
+            chunked.extend(instances_of(single, &url()).unwrap());
+        }
+        assert_eq!(chunked.len(), whole_range.len() + 2 + 2);
+
+        let deduped = dedup(chunked);
+        assert_eq!(deduped.len(), whole_range.len() + 1);
+        assert_eq!(deduped.iter().filter(|i| i.uid == "holidays").count(), 1);
+        for instance in &whole_range {
+            assert_eq!(deduped.iter().filter(|i| *i == instance).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let tz = chrono_tz::UTC;
+        let weekly = RECURRING_TASK
+            .replace("RRULE:FREQ=MONTHLY;COUNT=6", "RRULE:FREQ=WEEKLY;INTERVAL=2;UNTIL=20210301T000000Z");
+        let instances = expand(&weekly, &url(), Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Utc.ymd(2022, 1, 1).and_hms(0, 0, 0), &tz).unwrap();
+        let starts: Vec<_> = instances.iter().map(|i| i.start.clone()).collect();
+        assert_eq!(starts, vec![date(2021, 1, 31), date(2021, 2, 14), date(2021, 2, 28), date(2021, 4, 5)]);
+
+        let unsupported = RECURRING_TASK.replace("RRULE:FREQ=MONTHLY;COUNT=6", "RRULE:FREQ=MONTHLY;BYDAY=MO");
+        assert!(expand(&unsupported, &url(), Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Utc.ymd(2022, 1, 1).and_hms(0, 0, 0), &tz).is_err());
+    }
+
+    #[test]
+    fn test_expanded_by_the_server() {
+        let expanded = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+UID:meeting\r
+SUMMARY:Weekly meeting\r
+RECURRENCE-ID:20210104T090000Z\r
+DTSTART:20210104T090000Z\r
+DTEND:20210104T100000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:meeting\r
+SUMMARY:Weekly meeting\r
+RECURRENCE-ID:20210111T090000Z\r
+DTSTART:20210111T090000Z\r
+DTEND:20210111T100000Z\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        assert!(!has_recurrence_rules(expanded));
+        assert!(has_recurrence_rules(RECURRING_TASK));
+
+        let instances = instances_of(expanded, &url()).unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[1].component, "VEVENT");
+        assert_eq!(instances[1].start, Some(CalDateTime::Utc(Utc.ymd(2021, 1, 11).and_hms(9, 0, 0))));
+        assert_eq!(instances[1].end, Some(CalDateTime::Utc(Utc.ymd(2021, 1, 11).and_hms(10, 0, 0))));
+    }
+}
@@ -0,0 +1,183 @@
+//! Split an iCal file that contains many items (e.g. an exported calendar, or the feed of a subscription) into single-item iCal resources
+
+use std::error::Error;
+
+use super::canonical::unfold;
+
+/// Components that are shared by every item of an iCal file, rather than being items themselves
+const SHARED_COMPONENTS: [&str; 1] = ["VTIMEZONE"];
+
+/// Calendar properties that are not copied into the split resources, since they only make sense for the whole file
+const SKIPPED_CALENDAR_PROPERTIES: [&str; 1] = ["METHOD"];
+
+/// An iCal resource that contains a single item (i.e. the components that share a UID, such as a recurring task and its overrides)
+#[derive(Clone, Debug, PartialEq)]
+pub struct IcalResource {
+    pub uid: String,
+    pub ical: String,
+}
+
+/// Split an iCal file into one resource per UID.
+///
+/// Every resource keeps the calendar properties (e.g. `VERSION` or `PRODID`) and the timezones of the original file.
+/// Components that have no UID cannot be told apart, they are skipped.
+pub fn split_ics(ics: &str) -> Result<Vec<IcalResource>, Box<dyn Error>> {
+    let mut header: Vec<&str> = Vec::new();
+    let mut shared: Vec<&str> = Vec::new();
+    // The lines of the components of every UID, in the order they first appeared
+    let mut resources: Vec<(String, Vec<&str>)> = Vec::new();
+
+    let mut current: Vec<&str> = Vec::new();
+    let mut depth = 0;
+    let mut in_calendar = false;
+    let mut skipping_property = false;
+    for line in ics.lines().map(|line| line.trim_end_matches('\r')).filter(|line| !line.is_empty()) {
+        let upper = line.to_ascii_uppercase();
+        if !in_calendar {
+            if upper == "BEGIN:VCALENDAR" {
+                in_calendar = true;
+            }
+            continue;
+        }
+        if depth == 0 {
+            if upper == "END:VCALENDAR" {
+                in_calendar = false;
+            } else if upper.starts_with("BEGIN:") {
+                depth = 1;
+                current.push(line);
+            } else {
+                // Folded lines continue the previous property
+                if !line.starts_with([' ', '\t']) {
+                    let name = upper.split([':', ';']).next().unwrap_or_default();
+                    skipping_property = SKIPPED_CALENDAR_PROPERTIES.contains(&name);
+                }
+                if !skipping_property {
+                    header.push(line);
+                }
+            }
+            continue;
+        }
+
+        current.push(line);
+        if upper.starts_with("BEGIN:") {
+            depth += 1;
+        } else if upper.starts_with("END:") {
+            depth -= 1;
+            if depth == 0 {
+                let component = std::mem::take(&mut current);
+                let kind = component[0][6..].to_ascii_uppercase();
+                if SHARED_COMPONENTS.contains(&kind.as_str()) {
+                    shared.extend(component);
+                    continue;
+                }
+                match component_uid(&component) {
+                    None => log::warn!("Skipping a {} that has no UID", kind),
+                    Some(uid) => match resources.iter_mut().find(|(existing, _)| *existing == uid) {
+                        Some((_, lines)) => lines.extend(component),
+                        None => resources.push((uid, component)),
+                    },
+                }
+            }
+        }
+    }
+    if depth != 0 || in_calendar {
+        return Err("Unterminated iCal component".into());
+    }
+
+    Ok(resources.into_iter()
+        .map(|(uid, lines)| {
+            let mut ical = String::from("BEGIN:VCALENDAR\r\n");
+            for line in header.iter().chain(shared.iter()).chain(lines.iter()) {
+                ical.push_str(line);
+                ical.push_str("\r\n");
+            }
+            ical.push_str("END:VCALENDAR\r\n");
+            IcalResource { uid, ical }
+        })
+        .collect())
+}
+
+/// The UID of a component (and not the one of its sub-components, e.g. alarms)
+fn component_uid(lines: &[&str]) -> Option<String> {
+    let unfolded = unfold(&lines.join("\n"));
+    let mut depth = 0;
+    for line in unfolded.lines() {
+        let upper = line.to_ascii_uppercase();
+        if upper.starts_with("BEGIN:") {
+            depth += 1;
+        } else if upper.starts_with("END:") {
+            depth -= 1;
+        } else if depth == 1 && (upper.starts_with("UID:") || upper.starts_with("UID;")) {
+            let value = &line[line.find(':')? + 1..];
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Some feed//EN\r
+METHOD:PUB\r
+ LISH\r
+BEGIN:VTIMEZONE\r
+TZID:Europe/Paris\r
+BEGIN:STANDARD\r
+DTSTART:19701025T030000\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+END:STANDARD\r
+END:VTIMEZONE\r
+BEGIN:VTODO\r
+UID:first\r
+SUMMARY:Water the plants\r
+RRULE:FREQ=WEEKLY\r
+BEGIN:VALARM\r
+UID:alarm-uid\r
+TRIGGER:-PT15M\r
+END:VALARM\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:sec\r
+ ond\r
+DTSTAMP:20210321T001600Z\r
+SUMMARY:Second\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:first\r
+RECURRENCE-ID;TZID=Europe/Paris:20210322T100000\r
+SUMMARY:Water the plants (moved)\r
+END:VTODO\r
+BEGIN:VTODO\r
+SUMMARY:No UID\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn test_split_ics() {
+        let resources = split_ics(FEED).unwrap();
+        let uids: Vec<&str> = resources.iter().map(|r| r.uid.as_str()).collect();
+        assert_eq!(uids, vec!["first", "second"]);
+
+        let first = &resources[0].ical;
+        assert!(first.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Some feed//EN\r\nBEGIN:VTIMEZONE\r\n"));
+        assert!(!first.contains("METHOD"));
+        assert_eq!(first.matches("BEGIN:VTODO").count(), 2);
+        assert!(first.contains("RECURRENCE-ID"));
+        assert!(!first.contains("Second"));
+        assert!(first.ends_with("END:VTODO\r\nEND:VCALENDAR\r\n"));
+
+        let second = crate::ical::parse(&resources[1].ical, "https://some.server/second.ics".parse().unwrap(), crate::item::SyncStatus::NotSynced).unwrap();
+        assert_eq!(second.uid(), "second");
+        assert_eq!(second.name(), "Second");
+
+        assert!(split_ics("BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:x\r\n").is_err());
+        assert!(split_ics("").unwrap().is_empty());
+    }
+}
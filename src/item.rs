@@ -1,12 +1,16 @@
 //! CalDAV items (todo, events, journals...)
 // TODO: move Event and Task to nest them in crate::items::calendar::Calendar?
 
+use std::error::Error;
+
 use serde::{Deserialize, Serialize};
 use url::Url;
 use chrono::{DateTime, Utc};
+use ical::property::Property;
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum Item {
     Event(crate::event::Event),
     Task(crate::task::Task),
@@ -33,6 +37,11 @@ impl Item {
     synthetise_common_getter!(sync_status, &SyncStatus);
     synthetise_common_getter!(ical_prod_id, &str);
 
+    /// When the server has last modified this item (its `DAV:getlastmodified`), as of the last sync, in case the server has provided it
+    pub fn server_last_modified(&self) -> Option<&DateTime<Utc>> {
+        self.sync_status().version_tag().and_then(|tag| tag.last_modified())
+    }
+
     pub fn set_sync_status(&mut self, new_status: SyncStatus) {
         match self {
             Item::Event(e) => e.set_sync_status(new_status),
@@ -40,6 +49,66 @@ impl Item {
         }
     }
 
+    /// The jCal ([RFC7265](https://tools.ietf.org/html/rfc7265)) representation of this item, i.e. its iCal representation as JSON
+    pub fn to_jcal(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        crate::ical::ical_to_jcal(&crate::ical::build_from(self)?)
+    }
+
+    /// Parse the jCal representation of an item (see [`Item::to_jcal`])
+    pub fn from_jcal(jcal: &serde_json::Value, item_url: Url, sync_status: SyncStatus) -> Result<Self, Box<dyn Error>> {
+        crate::ical::parse(&crate::ical::jcal_to_ical(jcal)?, item_url, sync_status)
+    }
+
+    /// Mark this item as modified, so that it is uploaded again at the next sync (see [`crate::Task::touch`])
+    pub fn touch(&mut self) {
+        match self {
+            Item::Event(e) => e.touch(),
+            Item::Task(t) => t.touch(),
+        }
+    }
+
+    /// Change the URL of this item, e.g. because the server it is stored on has moved
+    pub(crate) fn set_url(&mut self, new_url: Url) {
+        match self {
+            Item::Event(e) => e.set_url(new_url),
+            Item::Task(t) => t.set_url(new_url),
+        }
+    }
+
+    /// Whether this item takes part in a scheduling exchange, i.e. has an `ORGANIZER` or `ATTENDEE` ([RFC6638](https://tools.ietf.org/html/rfc6638#section-3.1)).
+    pub fn is_scheduling_object(&self) -> bool {
+        match self {
+            Item::Event(e) => e.is_scheduling_object(),
+            Item::Task(t) => t.is_scheduling_object(),
+        }
+    }
+
+    /// Replace the attendees of this item with the ones of another version of it (see [`crate::Task::merge_attendees_of`])
+    pub(crate) fn merge_attendees_of(&mut self, other: &Item) {
+        match (self, other) {
+            (Item::Task(task), Item::Task(other)) => task.merge_attendees_of(other),
+            (Item::Event(event), Item::Event(other)) => event.merge_attendees_of(other),
+            _ => (),
+        }
+    }
+
+    /// Take the fields a user sets from another version of this item (see [`crate::Task::keep_user_fields_of`])
+    pub(crate) fn keep_user_fields_of(&mut self, other: &Item) {
+        match (self, other) {
+            (Item::Task(task), Item::Task(other)) => task.keep_user_fields_of(other),
+            (Item::Event(event), Item::Event(other)) => event.keep_user_fields_of(other),
+            _ => (),
+        }
+    }
+
+    /// Returns a copy of this item, with the given name and UID and a new URL in the given calendar, that has not been synced yet
+    pub(crate) fn derive(&self, parent_calendar_url: &Url, identity: crate::provider::conflict::DerivedIdentity) -> Item {
+        match self {
+            Item::Event(e) => Item::Event(e.derive(parent_calendar_url, identity)),
+            Item::Task(t) => Item::Task(t.derive(parent_calendar_url, identity)),
+        }
+    }
+
     pub fn is_event(&self) -> bool {
         match &self {
             Item::Event(_) => true,
@@ -54,6 +123,14 @@ impl Item {
         }
     }
 
+    /// The iCal component this item is made of (`VEVENT` or `VTODO`)
+    pub fn component_name(&self) -> &'static str {
+        match self {
+            Item::Event(_) => "VEVENT",
+            Item::Task(_) => "VTODO",
+        }
+    }
+
     /// Returns a mutable reference to the inner Task
     ///
     /// # Panics
@@ -76,7 +153,29 @@ impl Item {
         }
     }
 
-    #[cfg(any(test, feature = "integration_tests"))]
+    /// Returns a mutable reference to the inner Event
+    ///
+    /// # Panics
+    /// Panics if the inner item is not an Event
+    pub fn unwrap_event_mut(&mut self) -> &mut crate::event::Event {
+        match self {
+            Item::Event(e) => e,
+            _ => panic!("Not an event"),
+        }
+    }
+
+    /// Returns a reference to the inner Event
+    ///
+    /// # Panics
+    /// Panics if the inner item is not an Event
+    pub fn unwrap_event(&self) -> &crate::event::Event {
+        match self {
+            Item::Event(e) => e,
+            _ => panic!("Not an event"),
+        }
+    }
+
+    #[cfg(any(test, feature = "test-utils"))]
     pub fn has_same_observable_content_as(&self, other: &Item) -> bool {
         match (self, other) {
             (Item::Event(s), Item::Event(o)) => s.has_same_observable_content_as(o),
@@ -88,30 +187,126 @@ impl Item {
 
 
 
+/// An iCal component that lives in the same resource as an item, but that is not parsed by this crate.
+///
+/// This happens when a single iCal resource contains several components that share the same UID (e.g. a `VTODO` and a `VEVENT`, or recurrence overrides).
+/// See [`crate::ical::MultipleComponentsPolicy`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RawComponent {
+    name: String,
+    properties: Vec<Property>,
+    subcomponents: Vec<RawComponent>,
+}
+
+impl RawComponent {
+    pub fn new(name: String, properties: Vec<Property>, subcomponents: Vec<RawComponent>) -> Self {
+        Self { name, properties, subcomponents }
+    }
+
+    /// The kind of component (e.g. `VEVENT`)
+    pub fn name(&self) -> &str { &self.name }
+    pub fn properties(&self) -> &[Property] { &self.properties }
+    /// The components that are nested into this one (e.g. `VALARM`s)
+    pub fn subcomponents(&self) -> &[RawComponent] { &self.subcomponents }
+}
+
+impl PartialEq for RawComponent {
+    fn eq(&self, other: &Self) -> bool {
+        // ical::property::Property does not implement PartialEq
+        let same_props = |l: &Property, r: &Property| l.name == r.name && l.params == r.params && l.value == r.value;
+
+           self.name == other.name
+        && self.properties.len() == other.properties.len()
+        && self.properties.iter().zip(other.properties.iter()).all(|(l, r)| same_props(l, r))
+        && self.subcomponents == other.subcomponents
+    }
+}
+
+
+/// The prefix of the version tags that are built from a `DAV:getlastmodified`, for servers that do not provide ETags
+const LAST_MODIFIED_TAG_PREFIX: &str = "last-modified:";
 
 /// A VersionTag is basically a CalDAV `ctag` or `etag`. Whenever it changes, this means the data has changed.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+///
+/// For servers that do not provide ETags, this is built from the `DAV:getlastmodified` of the item instead (see [`Self::from_last_modified`])
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct VersionTag {
-    tag: String
+    tag: String,
+    /// The `DAV:getlastmodified` of the item, in case the server has provided it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<DateTime<Utc>>,
+    /// The `Schedule-Tag` of the item, in case the server supports CalDAV scheduling ([RFC6638](https://tools.ietf.org/html/rfc6638#section-3.2.10))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule_tag: Option<String>,
 }
 
 impl From<String> for VersionTag {
     fn from(tag: String) -> VersionTag {
-        Self { tag }
+        Self { tag, last_modified: None, schedule_tag: None }
+    }
+}
+
+/// Two version tags are the same when their tags are, whether their `last_modified` and `schedule_tag` are known or not
+impl PartialEq for VersionTag {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
     }
 }
 
 impl VersionTag {
+    /// Build a version tag from the `DAV:getlastmodified` of an item, for servers that do not provide ETags
+    pub fn from_last_modified(last_modified: DateTime<Utc>) -> Self {
+        Self {
+            tag: format!("{}{}", LAST_MODIFIED_TAG_PREFIX, last_modified.timestamp()),
+            last_modified: Some(last_modified),
+            schedule_tag: None,
+        }
+    }
+
+    /// Attach the `DAV:getlastmodified` of the item to this tag
+    pub fn with_last_modified(mut self, last_modified: Option<DateTime<Utc>>) -> Self {
+        self.last_modified = last_modified;
+        self
+    }
+
+    /// Attach the `Schedule-Tag` of the item to this tag
+    pub fn with_schedule_tag(mut self, schedule_tag: Option<String>) -> Self {
+        self.schedule_tag = schedule_tag;
+        self
+    }
+
     /// Get the inner version tag (usually a WebDAV `ctag` or `etag`)
     pub fn as_str(&self) -> &str {
         &self.tag
     }
 
+    /// The `DAV:getlastmodified` of the item, in case the server has provided it
+    pub fn last_modified(&self) -> Option<&DateTime<Utc>> {
+        self.last_modified.as_ref()
+    }
+
+    /// The `Schedule-Tag` of the item, in case the server has provided it.
+    ///
+    /// Unlike the ETag, it does not change when the server only updates the participation status of the attendees, e.g. because an attendee has replied to an invitation
+    pub fn schedule_tag(&self) -> Option<&str> {
+        self.schedule_tag.as_deref()
+    }
+
+    /// Whether this tag is an actual ETag, rather than being built from a `DAV:getlastmodified` (see [`Self::from_last_modified`])
+    pub fn is_etag(&self) -> bool {
+        !self.tag.starts_with(LAST_MODIFIED_TAG_PREFIX)
+    }
+
+    /// Whether this tag is a strong ETag, i.e. it changes whenever the content of the item changes
+    pub fn is_strong_etag(&self) -> bool {
+        self.is_etag() && !self.tag.starts_with("W/")
+    }
+
     /// Generate a random VersionTag
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     pub fn random() -> Self {
         let random = uuid::Uuid::new_v4().to_hyphenated().to_string();
-        Self { tag: random }
+        Self::from(random)
     }
 }
 
@@ -131,6 +326,14 @@ pub enum SyncStatus {
     LocallyDeleted(VersionTag),
 }
 impl SyncStatus {
+    /// The version tag the item had the last time it was synced (`None` if it has never been)
+    pub fn version_tag(&self) -> Option<&VersionTag> {
+        match self {
+            SyncStatus::NotSynced => None,
+            SyncStatus::Synced(tag) | SyncStatus::LocallyModified(tag) | SyncStatus::LocallyDeleted(tag) => Some(tag),
+        }
+    }
+
     /// Generate a random SyncStatus::Synced
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     pub fn random_synced() -> Self {
@@ -26,6 +26,9 @@
 //! ## Configuration options
 //!
 //! Have a look at the [`config`] module to see what default options can be overridden.
+//!
+//! The `collation` feature sorts names according to the rules of the locale of the user (see [`collation`]). \
+//! The `rustls-tls-webpki-roots` feature bundles root certificates, for systems that have no trust store (see [`client::ClientBuilder::use_bundled_roots`]).
 
 #![doc(html_logo_url = "https://raw.githubusercontent.com/daladim/kitchen-fridge/master/resources/kitchen-fridge.svg")]
 
@@ -36,13 +39,24 @@ pub mod item;
 pub use item::Item;
 pub mod task;
 pub use task::Task;
+pub mod quick_add;
 pub mod event;
 pub use event::Event;
+pub mod attachment;
+pub use attachment::Attachment;
+pub mod datetime;
+pub use datetime::CalDateTime;
+pub mod collation;
 pub mod provider;
 pub mod mock_behaviour;
+pub mod scenarios;
 
 pub mod client;
 pub use client::Client;
+pub mod dav_error;
+pub mod error;
+pub use error::KitchenFridgeError;
+pub mod error_code;
 pub mod cache;
 pub use cache::Cache;
 pub mod ical;
@@ -50,6 +64,9 @@ pub mod ical;
 pub mod config;
 pub mod utils;
 pub mod resource;
+pub mod transfer_stats;
+#[cfg(test)]
+mod test_server;
 
 /// Unless you want another kind of Provider to write integration tests, you'll probably want this kind of Provider. \
 /// See alse the [`Provider` documentation](crate::provider::Provider)
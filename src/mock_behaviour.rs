@@ -1,8 +1,16 @@
 //! This module provides ways to tweak mocked calendars, so that they can return errors on some tests
 #![cfg(feature = "local_calendar_mocks_remote_calendars")]
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 
+use url::Url;
+
+use crate::calendar::{DateLimits, PreconditionFailedError, QuotaExceededError, Subscription};
+use crate::dav_error::HttpError;
+use crate::item::VersionTag;
+use crate::transfer_stats::TransferCounter;
+
 /// This stores some behaviour tweaks, that describe how a mocked instance will behave during a given test
 ///
 /// So that a functions fails _n_ times after _m_ initial successes, set `(m, n)` for the suited parameter
@@ -24,6 +32,58 @@ pub struct MockBehaviour {
     pub get_item_version_tags_behaviour: (u32, u32),
     pub get_item_by_url_behaviour: (u32, u32),
     pub delete_item_behaviour: (u32, u32),
+
+    /// If this is true, every upload fails, as if the account was over its storage quota
+    pub quota_exceeded: bool,
+
+    /// If this is true, every action fails, as if the server refused connections
+    pub unreachable: bool,
+
+    /// Items that are uploaded to one of these URLs are stored at the matching URL instead, as if the server had replied with a `Content-Location`
+    pub content_locations: HashMap<Url, Url>,
+
+    /// If this is true, the mocked server rewrites the items it receives (it adds a property of its own), as some servers normalise the iCal data they are sent
+    pub rewrite_uploads: bool,
+
+    /// If this is set, the mocked server silently truncates the names of the tasks it receives to this many characters, as some misconfigured servers truncate long fields
+    pub truncate_names: Option<usize>,
+
+    /// The range of date-times the calendar at a given URL advertises (see [`crate::traits::DavCalendar::date_limits`])
+    pub date_limits: HashMap<Url, DateLimits>,
+
+    /// The calendars at these URLs are subscriptions (see [`crate::traits::DavCalendar::subscription`])
+    pub subscriptions: HashMap<Url, Subscription>,
+
+    /// The calendars at these URLs are flagged as enabled (or disabled) for sync (see [`crate::traits::DavCalendar::enabled`])
+    pub enabled: HashMap<Url, bool>,
+
+    /// Uploads of the items at these URLs are refused with this HTTP status, every time
+    pub refused_uploads: HashMap<Url, reqwest::StatusCode>,
+
+    /// The items at these URLs are locked for this many more attempts to upload or delete them, that are refused with `423 Locked`
+    pub locked_items: HashMap<Url, u32>,
+
+    /// The items at these URLs are served as if they contained this component (e.g. `VEVENT`) instead of a `VTODO`, as if another kind of item had replaced them on the server
+    pub unsupported_components: HashMap<Url, String>,
+
+    /// The items at these URLs are listed, but they cannot be fetched, as if another client deleted them in between
+    pub vanishing_items: HashSet<Url>,
+
+    /// Updates and deletions of the items at these URLs fail their precondition (`412 Precondition Failed`), as if another client had changed them since they have been listed
+    pub concurrent_changes: HashSet<Url>,
+
+    /// As soon as an item is uploaded to the calendar that holds them, the tasks at these URLs are renamed this way, as if another client had changed them during the sync. Every rename happens once
+    pub edits_during_uploads: HashMap<Url, String>,
+
+    /// If this is set, mocked calendars support sync tokens (see [`crate::traits::DavCalendar::get_changes_since`]). This holds the version tags of the items each token that has been handed out stands for.
+    /// Tokens that are not in there are refused, as servers do with tokens that have expired
+    pub sync_tokens: Option<HashMap<String, HashMap<Url, VersionTag>>>,
+
+    /// Mocked calendars account their items here, as if they had been transferred over the network
+    pub simulated_transfers: TransferCounter,
+
+    /// The requests mocked sources have served, in order (e.g. `PUT https://some.server/cal/item.ics`)
+    pub operations: Vec<String>,
 }
 
 impl MockBehaviour {
@@ -43,6 +103,23 @@ impl MockBehaviour {
             get_item_version_tags_behaviour: (0, n_fails),
             get_item_by_url_behaviour: (0, n_fails),
             delete_item_behaviour: (0, n_fails),
+            quota_exceeded: false,
+            unreachable: false,
+            content_locations: HashMap::new(),
+            rewrite_uploads: false,
+            truncate_names: None,
+            date_limits: HashMap::new(),
+            subscriptions: HashMap::new(),
+            enabled: HashMap::new(),
+            refused_uploads: HashMap::new(),
+            locked_items: HashMap::new(),
+            unsupported_components: HashMap::new(),
+            vanishing_items: HashSet::new(),
+            concurrent_changes: HashSet::new(),
+            edits_during_uploads: HashMap::new(),
+            sync_tokens: None,
+            simulated_transfers: TransferCounter::default(),
+            operations: Vec::new(),
         }
     }
 
@@ -60,8 +137,22 @@ impl MockBehaviour {
         self.create_calendar_behaviour = other.create_calendar_behaviour;
     }
 
+    /// Record a request to `url` in [`Self::operations`]
+    pub fn record_operation(&mut self, operation: &str, url: &Url) {
+        self.operations.push(format!("{} {}", operation, url));
+    }
+
+    /// Fail in case [`Self::unreachable`] is set
+    fn check_reachable(&self) -> Result<(), Box<dyn Error>> {
+        match self.unreachable {
+            true => Err(Box::new(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "Mocked behaviour: connection refused"))),
+            false => Ok(()),
+        }
+    }
+
     pub fn can_get_calendars(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.get_calendars_behaviour, "get_calendars")
     }
     // pub fn can_get_calendar(&mut self) -> Result<(), Box<dyn Error>> {
@@ -70,26 +161,62 @@ impl MockBehaviour {
     // }
     pub fn can_create_calendar(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.create_calendar_behaviour, "create_calendar")
     }
     pub fn can_add_item(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.add_item_behaviour, "add_item")
     }
     pub fn can_update_item(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.update_item_behaviour, "update_item")
     }
+    /// Uploads to a calendar fail in case [`Self::quota_exceeded`] is set
+    pub fn can_upload_to(&self, calendar: &Url) -> Result<(), Box<dyn Error>> {
+        if self.is_suspended || !self.quota_exceeded { return Ok(()) }
+        Err(Box::new(QuotaExceededError{ calendar: calendar.clone() }))
+    }
+    /// Uploads of an item fail in case it is part of [`Self::refused_uploads`]
+    pub fn can_upload_item(&self, item: &Url) -> Result<(), Box<dyn Error>> {
+        match self.refused_uploads.get(item) {
+            Some(status) if !self.is_suspended => Err(Box::new(HttpError::from_reply(*status, ""))),
+            _ => Ok(()),
+        }
+    }
+    /// Uploads and deletions of an item fail in case it is part of [`Self::locked_items`], as long as its lock has attempts left
+    pub fn can_modify_item(&mut self, item: &Url) -> Result<(), Box<dyn Error>> {
+        if self.is_suspended { return Ok(()) }
+        match self.locked_items.get_mut(item) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                Err(Box::new(HttpError::from_reply(reqwest::StatusCode::LOCKED, "")))
+            },
+            _ => Ok(()),
+        }
+    }
+    /// Updates and deletions of an item fail in case it is part of [`Self::concurrent_changes`]
+    pub fn can_overwrite_item(&self, item: &Url) -> Result<(), Box<dyn Error>> {
+        match self.concurrent_changes.contains(item) && !self.is_suspended {
+            true => Err(Box::new(PreconditionFailedError { item: item.clone() })),
+            false => Ok(()),
+        }
+    }
     pub fn can_get_item_version_tags(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.get_item_version_tags_behaviour, "get_item_version_tags")
     }
     pub fn can_get_item_by_url(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.get_item_by_url_behaviour, "get_item_by_url")
     }
     pub fn can_delete_item(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        self.check_reachable()?;
         decrement(&mut self.delete_item_behaviour, "delete_item")
     }
 }
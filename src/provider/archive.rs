@@ -0,0 +1,174 @@
+//! Archiving of old completed tasks into another calendar (see [`Provider::archive`])
+
+use std::error::Error;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+use url::Url;
+
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use crate::calendar::ComponentSet;
+use crate::item::{Item, SyncStatus, VersionTag};
+use crate::task::CompletionStatus;
+use super::Provider;
+
+/// How many items are moved before the local source is saved
+const ARCHIVE_BATCH_SIZE: usize = 30;
+
+/// Which items [`Provider::archive`] moves
+#[derive(Clone, Debug)]
+pub struct ArchivePolicy {
+    /// Tasks are archived once they have been completed for longer than this
+    pub completed_for_longer_than: Duration,
+    /// Whether the archive calendar should be created (on both sides) in case it does not exist yet
+    pub create_archive_calendar: bool,
+}
+
+/// What [`Provider::archive`] has done
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveReport {
+    /// The items that have been moved, as (former URL, new URL)
+    pub moved: Vec<(Url, Url)>,
+    /// Items that should be archived, but that have local changes that have not been synced yet. They will be archived once they are synced
+    pub skipped: Vec<Url>,
+    /// Items that could not be moved, and why. Calling [`Provider::archive`] again will resume them where they failed
+    pub failed: Vec<(Url, String)>,
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Move the tasks of `source_cal` that have been completed for a while (see [`ArchivePolicy`]) into `archive_cal`, both in the local and the remote sources.
+    ///
+    /// Moved items keep their UID and every property, only their URL changes. The local source is saved (see [`CalDavSource::checkpoint`](crate::traits::CalDavSource::checkpoint)) after every batch of moved items. \
+    /// Every item is first copied, and only then deleted from `source_cal`, so that an interrupted archiving never loses any item. Calling this function again resumes it.
+    // Just like during a sync, calendars are locked while their (async) methods are called
+    #[allow(clippy::await_holding_lock)]
+    pub async fn archive(&mut self, source_cal: &Url, archive_cal: &Url, policy: &ArchivePolicy) -> Result<ArchiveReport, Box<dyn Error>> {
+        let local_src = self.local.get_calendar(source_cal).await.ok_or_else(|| format!("No local calendar {}", source_cal))?;
+        let remote_src = self.remote.get_calendar(source_cal).await.ok_or_else(|| format!("No remote calendar {}", source_cal))?;
+
+        let (local_arch, remote_arch) = match (self.local.get_calendar(archive_cal).await, self.remote.get_calendar(archive_cal).await) {
+            (Some(local_arch), Some(remote_arch)) => (local_arch, remote_arch),
+            (Some(local_arch), None) => {
+                let remote_arch = self.get_or_insert_remote_counterpart_calendar(archive_cal, local_arch.clone()).await?;
+                (local_arch, remote_arch)
+            },
+            (None, Some(remote_arch)) => {
+                let local_arch = self.get_or_insert_local_counterpart_calendar(archive_cal, remote_arch.clone()).await?;
+                (local_arch, remote_arch)
+            },
+            (None, None) if policy.create_archive_calendar => {
+                let (name, components, color) = {
+                    let src = local_src.lock().unwrap();
+                    (format!("{} (archive)", src.name()), ComponentSet::of(&*src), src.color().cloned())
+                };
+                let remote_arch = self.remote.create_calendar_with_components(archive_cal.clone(), name, components, color).await?;
+                let local_arch = self.get_or_insert_local_counterpart_calendar(archive_cal, remote_arch.clone()).await?;
+                (local_arch, remote_arch)
+            },
+            (None, None) => return Err(format!("Archive calendar {} does not exist", archive_cal).into()),
+        };
+
+        let mut report = ArchiveReport::default();
+        let threshold = Utc::now() - policy.completed_for_longer_than;
+        let mut to_archive = Vec::new();
+        for (url, item) in local_src.lock().unwrap().get_items().await? {
+            if !is_archivable(item, &threshold) {
+                continue;
+            }
+            match item.sync_status() {
+                SyncStatus::Synced(_) => to_archive.push(url),
+                _ => report.skipped.push(url),
+            }
+        }
+        to_archive.sort();
+        report.skipped.sort();
+
+        // Previous (interrupted) calls may have moved some items halfway already
+        let remote_arch_tags = remote_arch.lock().unwrap().get_item_version_tags().await?;
+        let remote_src_tags = remote_src.lock().unwrap().get_item_version_tags().await?;
+
+        for batch in to_archive.into_iter().chunks(ARCHIVE_BATCH_SIZE).into_iter() {
+            let mut local_src = local_src.lock().unwrap();
+            let mut remote_src = remote_src.lock().unwrap();
+            let mut local_arch = local_arch.lock().unwrap();
+            let mut remote_arch = remote_arch.lock().unwrap();
+            for url in batch {
+                let result = move_item(&url, archive_cal, &remote_arch_tags, &remote_src_tags,
+                    &mut *local_src, &mut *remote_src, &mut *local_arch, &mut *remote_arch).await;
+                match result {
+                    Ok(new_url) => report.moved.push((url, new_url)),
+                    Err(err) => {
+                        log::warn!("Unable to archive item {}: {}", url, err);
+                        report.failed.push((url, err.to_string()));
+                    },
+                }
+            }
+            drop((local_src, remote_src, local_arch, remote_arch));
+            self.local.checkpoint()?;
+        }
+
+        Ok(report)
+    }
+}
+
+fn is_archivable(item: &Item, threshold: &DateTime<Utc>) -> bool {
+    let task = match item {
+        Item::Task(task) => task,
+        _ => return false,
+    };
+    match task.completion_status() {
+        CompletionStatus::Uncompleted => false,
+        CompletionStatus::Completed(Some(date)) => date < threshold,
+        CompletionStatus::Completed(None) => task.last_modified() < threshold,
+    }
+}
+
+/// Copy an item into the archive calendar (remote first, then local), then delete it from its source calendar (remote first, then local)
+#[allow(clippy::too_many_arguments)]
+async fn move_item<T, U>(
+    url: &Url,
+    archive_cal: &Url,
+    remote_arch_tags: &HashMap<Url, VersionTag>,
+    remote_src_tags: &HashMap<Url, VersionTag>,
+    local_src: &mut T,
+    remote_src: &mut U,
+    local_arch: &mut T,
+    remote_arch: &mut U,
+) -> Result<Url, Box<dyn Error>>
+where
+    T: CompleteCalendar,
+    U: DavCalendar,
+{
+    let item = local_src.get_item_by_url(url).await.ok_or("item has vanished")?.clone();
+    let file_name = url.path_segments().and_then(|mut segments| segments.next_back()).unwrap_or_default();
+    let new_url = archive_cal.join(file_name)?;
+
+    let remote_status = match remote_arch_tags.get(&new_url) {
+        Some(tag) => SyncStatus::Synced(tag.clone()),
+        None => {
+            let mut copy = item.clone();
+            copy.set_url(new_url.clone());
+            copy.set_sync_status(SyncStatus::NotSynced);
+            remote_arch.add_item(copy).await?
+        },
+    };
+    if local_arch.get_item_by_url(&new_url).await.is_none() {
+        let mut copy = item;
+        copy.set_url(new_url.clone());
+        copy.set_sync_status(remote_status);
+        local_arch.add_item(copy).await?;
+    }
+
+    if remote_src_tags.contains_key(url) {
+        remote_src.delete_item(url).await?;
+    }
+    local_src.immediately_delete_item(url).await?;
+    Ok(new_url)
+}
@@ -0,0 +1,67 @@
+//! The points of a sync where it can be stopped, and resumed by the next sync (see [`crate::provider::Provider::cancellation_handle`])
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use url::Url;
+
+/// A point of the sync of a calendar where the local calendar is consistent: a sync that stops there leaves a state the next sync resumes from, as if it had not been interrupted.
+///
+/// They are met in this order, for every calendar that is synced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncCheckpoint {
+    /// The differences between the local and the remote calendar have been found. Nothing has been modified yet
+    RemoteListed,
+    /// The deletions have been applied to both calendars
+    DeletionsApplied,
+    /// The remote additions and changes have been downloaded and applied locally, and the conflicts have been resolved
+    DownloadsApplied,
+    /// A batch of local additions and changes has been uploaded (see [`crate::provider::CalendarSyncPolicy::batch_size`]). The last batch may be smaller
+    UploadBatch,
+}
+
+/// Stops a running sync at its next checkpoint (see [`SyncCheckpoint`]), e.g. when the user closes the app.
+///
+/// The local source is saved (see [`crate::traits::CalDavSource::checkpoint`]) before the sync returns, and [`crate::provider::sync_progress::SyncResult::cancelled`] is set.
+/// A cancellation only applies to the sync that is running: it is ignored in case no sync is running, and the next syncs run as usual.
+/// A cancelled sync is not recorded in [`crate::provider::Provider::sync_health`]
+#[derive(Clone, Debug, Default)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    /// Ask the running sync to stop at its next checkpoint
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a cancellation has been asked for, and not been honoured yet
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+type ObserverFn = dyn Fn(&Url, SyncCheckpoint) + Send + Sync;
+
+/// A callback that is told about every checkpoint a sync reaches (see [`crate::provider::Provider::set_checkpoint_observer`])
+#[derive(Clone)]
+pub(crate) struct CheckpointObserver(Arc<ObserverFn>);
+
+impl CheckpointObserver {
+    pub(crate) fn new<F: Fn(&Url, SyncCheckpoint) + Send + Sync + 'static>(observer: F) -> Self {
+        Self(Arc::new(observer))
+    }
+
+    pub(crate) fn notify(&self, calendar: &Url, checkpoint: SyncCheckpoint) {
+        (self.0)(calendar, checkpoint)
+    }
+}
+
+impl std::fmt::Debug for CheckpointObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CheckpointObserver")
+    }
+}
@@ -0,0 +1,153 @@
+//! Interactive resolution of sync conflicts (see [`crate::provider::Provider::set_conflict_resolver`])
+
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+use crate::item::Item;
+use crate::ical::PropertyChange;
+use crate::error_code::{self, ErrorCode};
+
+/// The kinds of conflicts a sync may run into (see [`crate::provider::sync_progress::SyncResult::conflicts`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The item has been modified both locally and remotely since the last sync
+    ModifiedInBoth,
+    /// The item has been locally deleted and remotely modified. The remote version is kept
+    DeletedLocallyModifiedRemotely,
+    /// The item has been locally modified and remotely deleted. The local copy is deleted
+    ModifiedLocallyDeletedRemotely,
+    /// A local item that has never been synced has the URL of a remote item. It is ignored by the sync
+    UrlReuse,
+    /// A new local item has the UID of an item the server already has. The one of the server is used instead
+    UidAlreadyOnServer,
+    /// The item has kept changing on the server while it was being fetched. It is left as is locally, and will be fetched again at the next sync
+    ChangedDuringSync,
+}
+
+impl ConflictKind {
+    /// The stable identifier of this kind of conflicts (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ModifiedInBoth => error_code::MODIFIED_IN_BOTH,
+            Self::DeletedLocallyModifiedRemotely => error_code::DELETED_LOCALLY_MODIFIED_REMOTELY,
+            Self::ModifiedLocallyDeletedRemotely => error_code::MODIFIED_LOCALLY_DELETED_REMOTELY,
+            Self::UrlReuse => error_code::URL_REUSE,
+            Self::UidAlreadyOnServer => error_code::UID_ALREADY_ON_SERVER,
+            Self::ChangedDuringSync => error_code::CHANGED_DURING_SYNC,
+        }
+    }
+}
+
+impl std::fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// An item that has been modified both locally and remotely since the last sync
+#[derive(Clone, Debug)]
+pub struct ConflictContext {
+    /// The URL of the calendar that contains this item
+    pub calendar: Url,
+    /// The local version of the item
+    pub local: Item,
+    /// The remote version of the item
+    pub remote: Item,
+}
+
+impl ConflictContext {
+    /// What has been changed in the local version, compared to the remote one (see [`crate::ical::diff`]).
+    /// Properties that change at every save are ignored
+    pub fn differences(&self) -> Result<Vec<PropertyChange>, Box<dyn Error>> {
+        let local = crate::ical::build_from(&self.local)?;
+        let remote = crate::ical::build_from(&self.remote)?;
+        crate::ical::diff(&remote, &local, true)
+    }
+}
+
+/// How a conflict must be resolved
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Resolution {
+    /// Overwrite the remote version with the local one
+    KeepLocal,
+    /// Overwrite the local version with the remote one. This is what happens when no resolver is set
+    KeepRemote,
+    /// Keep the remote version, and add the local version as a new item (with a new UID)
+    KeepBoth,
+    /// Replace both versions with this item. Its URL and sync status are ignored
+    Merged(Item),
+}
+
+type ResolverFn = dyn Fn(ConflictContext) -> Pin<Box<dyn Future<Output = Resolution> + Send>> + Send + Sync;
+
+/// An async callback that resolves conflicts
+pub(crate) struct ConflictResolver(Box<ResolverFn>);
+
+impl ConflictResolver {
+    pub(crate) fn new<F, Fut>(resolver: F) -> Self
+    where
+        F: Fn(ConflictContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Resolution> + Send + 'static,
+    {
+        Self(Box::new(move |context| Box::pin(resolver(context))))
+    }
+
+    pub(crate) async fn resolve(&self, context: ConflictContext) -> Resolution {
+        (self.0)(context).await
+    }
+}
+
+impl std::fmt::Debug for ConflictResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ConflictResolver")
+    }
+}
+
+
+/// The conflict a copy of an item is created for (see [`ConflictNaming`])
+#[derive(Clone, Debug)]
+pub struct ConflictMetadata {
+    /// The URL of the calendar that contains the conflicting item
+    pub calendar: Url,
+    /// The remote version of the item, that is kept alongside the copy
+    pub remote: Item,
+    /// When the conflict has been resolved
+    pub resolved_at: DateTime<Utc>,
+}
+
+/// The summary and the UID of an item that is derived from another one
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedIdentity {
+    pub name: String,
+    pub uid: String,
+}
+
+/// How the copies that [`Resolution::KeepBoth`] creates are named (see [`crate::provider::Provider::set_conflict_naming`])
+pub trait ConflictNaming: Send + Sync {
+    /// Returns the summary and the UID of the copy of `original` (the local version of a conflicting item)
+    fn name_copy(&self, original: &Item, conflict: &ConflictMetadata) -> DerivedIdentity;
+}
+
+impl std::fmt::Debug for dyn ConflictNaming {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ConflictNaming")
+    }
+}
+
+/// The default [`ConflictNaming`]: copies keep the summary of the original item, and get a random UID
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultConflictNaming;
+
+impl ConflictNaming for DefaultConflictNaming {
+    fn name_copy(&self, original: &Item, _conflict: &ConflictMetadata) -> DerivedIdentity {
+        DerivedIdentity {
+            name: original.name().to_string(),
+            uid: uuid::Uuid::new_v4().to_hyphenated().to_string(),
+        }
+    }
+}
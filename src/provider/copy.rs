@@ -0,0 +1,172 @@
+//! Copies of calendars on the same source, e.g. to try a sync on a staging copy of a calendar before letting it manage the real one (see [`crate::Client::copy_calendar`])
+
+use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use url::Url;
+
+use crate::Item;
+use crate::item::SyncStatus;
+use crate::traits::{CalDavSource, SourceAdapter, CompleteCalendar, DavCalendar};
+use crate::calendar::ComponentSet;
+use super::Provider;
+use super::sync_progress::{FeedbackSender, SyncEvent};
+
+/// How many items are downloaded at once when a calendar is copied
+const COPY_BATCH_SIZE: usize = 30;
+
+/// The differences between the items of two calendars, matched by UID (see [`Provider::compare`])
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CalendarDiff {
+    /// The UIDs of the items only the first calendar has, sorted
+    pub only_in_a: Vec<String>,
+    /// The UIDs of the items only the second calendar has, sorted
+    pub only_in_b: Vec<String>,
+    /// The UIDs of the items both calendars have, but with different contents (see [`crate::ical::have_same_content`]), sorted
+    pub different: Vec<String>,
+}
+
+impl CalendarDiff {
+    /// Whether both calendars have the same items
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty() && self.different.is_empty()
+    }
+}
+
+/// The URL the copy of `source` that is named `new_name` is created at: a sibling of `source`, named after `new_name`
+pub fn copy_url(source: &Url, new_name: &str) -> Result<Url, Box<dyn Error>> {
+    let slug: String = new_name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        return Err(format!("Unable to build a URL out of the name {:?}", new_name).into());
+    }
+    let copy_url = source.join(&format!("../{}/", slug))?;
+    if &copy_url == source {
+        return Err(format!("A copy of {} cannot be named after it", source).into());
+    }
+    Ok(copy_url)
+}
+
+/// Copy the calendar at `source` into a new calendar of the same source, named `new_name` (see [`copy_url`]). Items keep their UIDs and file names.
+///
+/// In case the copy already exists, the items it is still missing are copied into it, so that an interrupted copy can be resumed by calling this again.
+/// Progress is reported to `feedback` (if any), just like syncs do.
+#[allow(clippy::await_holding_lock)]
+pub async fn copy_calendar<R, U>(remote: &mut R, source: &Url, new_name: &str, feedback: Option<&FeedbackSender>) -> Result<Url, Box<dyn Error>>
+where
+    R: CalDavSource<U>,
+    U: DavCalendar + Sync + Send,
+{
+    let cal_source = remote.get_calendar(source).await
+        .ok_or_else(|| format!("No calendar {}", source))?;
+    let copy_url = copy_url(source, new_name)?;
+    let cal_copy = match remote.get_calendar(&copy_url).await {
+        Some(cal_copy) => {
+            log::info!("Calendar {} already exists, resuming the copy into it", copy_url);
+            cal_copy
+        },
+        None => {
+            let (components, color) = {
+                let cal_source = cal_source.lock().unwrap();
+                (ComponentSet::of(&*cal_source), cal_source.color().cloned())
+            };
+            remote.create_calendar_with_components(copy_url.clone(), new_name.to_string(), components, color).await?
+        },
+    };
+
+    let source_urls: BTreeSet<Url> = cal_source.lock().unwrap().get_item_version_tags().await?.into_keys().collect();
+    let already_copied: HashSet<String> = cal_copy.lock().unwrap().get_item_version_tags().await?.keys()
+        .filter_map(file_name)
+        .collect();
+    let to_copy: Vec<Url> = source_urls.iter()
+        .filter(|url| file_name(url).is_some_and(|name| !already_copied.contains(&name)))
+        .cloned()
+        .collect();
+    log::info!("Copying {} items of {} into {} ({} are there already)", to_copy.len(), source, copy_url, source_urls.len() - to_copy.len());
+
+    let mut done = source_urls.len() - to_copy.len();
+    for batch in to_copy.chunks(COPY_BATCH_SIZE) {
+        let items = cal_source.lock().unwrap().get_items_by_url(batch).await?;
+        for mut item in items.into_iter().flatten() {
+            let name = file_name(item.url()).ok_or_else(|| format!("Item {} has no file name", item.url()))?;
+            item.set_url(copy_url.join(&name)?);
+            item.set_sync_status(SyncStatus::NotSynced);
+            cal_copy.lock().unwrap().add_item(item).await?;
+        }
+        done += batch.len();
+        if let Some(feedback) = feedback {
+            let _ = feedback.send(SyncEvent::InProgress {
+                calendar: new_name.to_string(),
+                items_done_already: done,
+                details: format!("{} items out of {} copied", done, source_urls.len()),
+            });
+        }
+    }
+    Ok(copy_url)
+}
+
+/// The last segment of the path of an item (e.g. `task.ics`)
+fn file_name(url: &Url) -> Option<String> {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Compare the items of two calendars of the remote source, e.g. to check a copy made by [`crate::Client::copy_calendar`]. This does not modify anything.
+    ///
+    /// Items are matched by UID, whatever their URLs.
+    pub async fn compare(&self, calendar_a: &Url, calendar_b: &Url) -> Result<CalendarDiff, Box<dyn Error>> {
+        let items_a = self.remote_items_by_uid(calendar_a).await?;
+        let mut items_b = self.remote_items_by_uid(calendar_b).await?;
+
+        let mut diff = CalendarDiff::default();
+        for (uid, item_a) in items_a {
+            match items_b.remove(&uid) {
+                None => diff.only_in_a.push(uid),
+                Some(item_b) if !crate::ical::have_same_content(&item_a, &item_b) => diff.different.push(uid),
+                Some(_) => (),
+            }
+        }
+        diff.only_in_b = items_b.into_keys().collect();
+        Ok(diff)
+    }
+
+    #[allow(clippy::await_holding_lock)]
+    async fn remote_items_by_uid(&self, cal_url: &Url) -> Result<BTreeMap<String, Item>, Box<dyn Error>> {
+        let cal = self.remote.get_calendar(cal_url).await
+            .ok_or_else(|| format!("No remote calendar {}", cal_url))?;
+        let cal = cal.lock().unwrap();
+        let urls: Vec<Url> = cal.get_item_version_tags().await?.into_keys().collect();
+        let mut items = BTreeMap::new();
+        for batch in urls.chunks(COPY_BATCH_SIZE) {
+            for item in cal.get_items_by_url(batch).await?.into_iter().flatten() {
+                items.insert(item.uid().to_string(), item);
+            }
+        }
+        Ok(items)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_url() {
+        let source: Url = "https://my.server.com/calendars/john/tasks/".parse().unwrap();
+        assert_eq!(copy_url(&source, "Tasks (staging)").unwrap().as_str(), "https://my.server.com/calendars/john/tasks--staging/");
+        assert!(copy_url(&source, "tasks").is_err());
+        assert!(copy_url(&source, "???").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Handling of items whose dates are out of the range a calendar accepts (see [`DateLimits`])
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use url::Url;
+
+use crate::calendar::DateLimits;
+use crate::datetime::CalDateTime;
+use crate::task::CompletionStatus;
+use crate::Item;
+
+/// What to do when an item that is about to be uploaded has dates out of the range the calendar accepts.
+/// See [`crate::provider::Provider::set_date_limits_policy`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateLimitsPolicy {
+    /// Do not upload the item. It is kept locally, and will be checked again at the next sync
+    #[default]
+    Skip,
+    /// Move the offending dates to the closest date-time the calendar accepts, then upload the item
+    Clamp,
+    /// Remove the offending properties, then upload the item
+    Strip,
+}
+
+/// An item whose dates were out of the range its calendar accepts, and what has been done about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DateLimitViolation {
+    pub calendar: Url,
+    pub item: Url,
+    /// The iCal names of the offending properties (e.g. `DUE`)
+    pub properties: Vec<String>,
+    pub action: DateLimitsPolicy,
+}
+
+/// Check the dates of an item against `limits`, and apply `policy` to the ones that are out of range.
+///
+/// This returns the names of the offending properties, or `None` in case the item is within limits.
+/// Values without a timezone are interpreted in `reference_tz`.
+pub(crate) fn enforce(item: &mut Item, limits: &DateLimits, policy: DateLimitsPolicy, reference_tz: &Tz) -> Option<Vec<String>> {
+    if limits.is_unbounded() {
+        return None;
+    }
+    // Date limits are only enforced on tasks for now
+    let task = match item {
+        Item::Task(task) => task,
+        Item::Event(_) => return None,
+    };
+
+    let out_of_range = |date: &DateTime<Utc>| !limits.contains(date);
+    let mut offending = Vec::new();
+
+    if let Some(start) = task.start().filter(|start| out_of_range(&start.to_utc(reference_tz))).cloned() {
+        offending.push("DTSTART".to_string());
+        match policy {
+            DateLimitsPolicy::Skip => (),
+            DateLimitsPolicy::Clamp => task.set_start(Some(clamp(&start, limits, reference_tz))),
+            DateLimitsPolicy::Strip => task.set_start(None),
+        }
+    }
+    if let Some(due) = task.due().filter(|due| out_of_range(&due.to_utc(reference_tz))).cloned() {
+        offending.push("DUE".to_string());
+        match policy {
+            DateLimitsPolicy::Skip => (),
+            DateLimitsPolicy::Clamp => task.set_due(Some(clamp(&due, limits, reference_tz))),
+            DateLimitsPolicy::Strip => task.set_due(None),
+        }
+    }
+    if let CompletionStatus::Completed(Some(completed)) = task.completion_status().clone() {
+        if out_of_range(&completed) {
+            offending.push("COMPLETED".to_string());
+            match policy {
+                DateLimitsPolicy::Skip => (),
+                DateLimitsPolicy::Clamp => task.set_completion_status(CompletionStatus::Completed(Some(limits.clamp(completed)))),
+                DateLimitsPolicy::Strip => task.set_completion_status(CompletionStatus::Completed(None)),
+            }
+        }
+    }
+    if let Some(created) = task.creation_date().filter(|created| out_of_range(created)).cloned() {
+        offending.push("CREATED".to_string());
+        match policy {
+            DateLimitsPolicy::Skip => (),
+            DateLimitsPolicy::Clamp => task.set_creation_date(Some(limits.clamp(created))),
+            DateLimitsPolicy::Strip => task.set_creation_date(None),
+        }
+    }
+
+    match offending.is_empty() {
+        true => None,
+        false => Some(offending),
+    }
+}
+
+/// Move a date to the closest value within `limits`, keeping it a date (without time) if it was one
+fn clamp(date: &CalDateTime, limits: &DateLimits, reference_tz: &Tz) -> CalDateTime {
+    let clamped = limits.clamp(date.to_utc(reference_tz));
+    match date {
+        CalDateTime::Date(_) => {
+            let day = clamped.with_timezone(reference_tz).date().naive_local();
+            // The beginning of this day may still be before the lower limit
+            match limits.contains(&CalDateTime::Date(day).to_utc(reference_tz)) {
+                true => CalDateTime::Date(day),
+                false => CalDateTime::Date(day.succ()),
+            }
+        },
+        _ => CalDateTime::Utc(clamped),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{NaiveDate, TimeZone};
+    use crate::Task;
+
+    fn limits() -> DateLimits {
+        DateLimits {
+            min: Some(Utc.ymd(1970, 1, 1).and_hms(12, 0, 0)),
+            max: Some(Utc.ymd(2038, 1, 19).and_hms(3, 14, 7)),
+        }
+    }
+
+    fn old_task() -> Item {
+        let cal_url: Url = "https://some.server/cal/".parse().unwrap();
+        let mut task = Task::new("Old task".to_string(), false, &cal_url);
+        task.set_start(Some(CalDateTime::Date(NaiveDate::from_ymd(1965, 3, 1))));
+        task.set_due(Some(CalDateTime::Utc(Utc.ymd(2020, 1, 1).and_hms(0, 0, 0))));
+        task.set_completion_status(CompletionStatus::Completed(Some(Utc.ymd(2050, 1, 1).and_hms(0, 0, 0))));
+        Item::Task(task)
+    }
+
+    #[test]
+    fn test_enforce_date_limits() {
+        let tz = Tz::UTC;
+        let offending = Some(vec!["DTSTART".to_string(), "COMPLETED".to_string()]);
+
+        let mut item = old_task();
+        assert_eq!(enforce(&mut item, &DateLimits::default(), DateLimitsPolicy::Skip, &tz), None);
+        assert_eq!(enforce(&mut item, &limits(), DateLimitsPolicy::Skip, &tz), offending);
+        assert_eq!(item.unwrap_task().start(), old_task().unwrap_task().start());
+
+        let mut item = old_task();
+        assert_eq!(enforce(&mut item, &limits(), DateLimitsPolicy::Clamp, &tz), offending);
+        let task = item.unwrap_task();
+        assert_eq!(task.start(), Some(&CalDateTime::Date(NaiveDate::from_ymd(1970, 1, 2))));
+        assert_eq!(task.due(), old_task().unwrap_task().due());
+        assert_eq!(task.completion_status(), &CompletionStatus::Completed(limits().max));
+        assert_eq!(enforce(&mut item, &limits(), DateLimitsPolicy::Clamp, &tz), None);
+
+        let mut item = old_task();
+        assert_eq!(enforce(&mut item, &limits(), DateLimitsPolicy::Strip, &tz), offending);
+        assert_eq!(item.unwrap_task().start(), None);
+        assert_eq!(item.unwrap_task().completion_status(), &CompletionStatus::Completed(None));
+        assert_eq!(enforce(&mut item, &limits(), DateLimitsPolicy::Strip, &tz), None);
+    }
+}
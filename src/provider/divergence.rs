@@ -0,0 +1,146 @@
+//! A safety valve, that pauses the sync of calendars whose local and remote copies have diverged too much (see [`crate::provider::Provider::set_divergence_threshold`])
+//!
+//! A calendar that has been paired with the wrong remote calendar (e.g. after a migration) looks like it has lost most of its items, and syncing it would delete them for good.
+
+use std::collections::BTreeSet;
+
+use url::Url;
+
+use crate::error_code::ErrorCode;
+
+/// How much a sync may delete (and optionally overwrite) in a calendar before it is paused
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DivergenceThreshold {
+    /// The fraction of the items of a calendar that may be deleted (e.g. `0.3` for 30%)
+    pub max_fraction: f64,
+    /// How many items of a calendar may be deleted, whatever their fraction. There is no such limit when this is `None`
+    pub max_count: Option<usize>,
+    /// Calendars with fewer items than this are never paused because of [`Self::max_fraction`], since a few deletions are enough to exceed it
+    pub min_items: usize,
+    /// Whether the items that would be overwritten (by their remote version, or on the server by their local version) count along with the deleted ones.
+    /// This is `false` by default, since every ordinary edit overwrites an item
+    pub count_overwrites: bool,
+}
+
+impl Default for DivergenceThreshold {
+    fn default() -> Self {
+        Self { max_fraction: 0.3, max_count: None, min_items: 10, count_overwrites: false }
+    }
+}
+
+impl DivergenceThreshold {
+    /// How many of these operations count against this threshold
+    pub fn counted(&self, planned: &PlannedOperations) -> usize {
+        match self.count_overwrites {
+            true => planned.deletions() + planned.overwrites(),
+            false => planned.deletions(),
+        }
+    }
+
+    /// Whether these operations on a calendar that holds `items` items exceed this threshold
+    pub fn is_exceeded_by(&self, planned: &PlannedOperations, items: usize) -> bool {
+        let destructive = self.counted(planned);
+        if destructive == 0 {
+            return false;
+        }
+        if self.max_count.is_some_and(|max_count| destructive > max_count) {
+            return true;
+        }
+        items >= self.min_items && destructive as f64 > self.max_fraction * items as f64
+    }
+}
+
+/// What a sync was about to do in a calendar
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlannedOperations {
+    /// The items that were about to be downloaded from the server
+    pub pulled_additions: usize,
+    /// The local items that were about to be overwritten by their remote version
+    pub pulled_changes: usize,
+    /// The local items that were about to be deleted, because they are not on the server
+    pub pulled_deletions: usize,
+    /// The local items that were about to be uploaded to the server
+    pub pushed_additions: usize,
+    /// The remote items that were about to be overwritten by their local version
+    pub pushed_changes: usize,
+    /// The remote items that were about to be deleted, because they have been deleted locally
+    pub pushed_deletions: usize,
+}
+
+impl PlannedOperations {
+    /// How many items were about to be deleted, be it locally or on the server
+    pub fn deletions(&self) -> usize {
+        self.pulled_deletions + self.pushed_deletions
+    }
+
+    /// How many items were about to be overwritten, be it locally or on the server
+    pub fn overwrites(&self) -> usize {
+        self.pulled_changes + self.pushed_changes
+    }
+}
+
+/// A calendar whose sync has been paused, because it was about to delete (or overwrite, see [`DivergenceThreshold::count_overwrites`]) too many of its items
+///
+/// It is synced again (just like any other calendar) by every sync, and paused again as long as its copies still diverge that much.
+/// Use [`crate::provider::Provider::confirm_divergent_sync`] with the [`Self::token`] of this warning to sync it anyway.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DivergenceWarning {
+    pub calendar: Url,
+    /// How many items the local calendar holds
+    pub items: usize,
+    pub planned: PlannedOperations,
+    /// Identifies these very operations: a confirmation does not apply in case they have changed since this warning
+    pub token: String,
+}
+
+impl DivergenceWarning {
+    /// The stable identifier of this anomaly (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        crate::error_code::DIVERGENT_SYNC
+    }
+}
+
+/// The token of the operations a sync is about to apply to a calendar (see [`DivergenceWarning::token`])
+pub(crate) fn token_of(calendar: &Url, operations: &[(&str, &BTreeSet<Url>)]) -> String {
+    let mut digest = calendar.to_string();
+    for (kind, urls) in operations {
+        digest.push('\n');
+        digest.push_str(kind);
+        for url in urls.iter() {
+            digest.push(' ');
+            digest.push_str(url.as_str());
+        }
+    }
+    format!("{:x}", md5::compute(digest))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let threshold = DivergenceThreshold::default();
+        let deletions = |n| PlannedOperations { pulled_deletions: n, pulled_additions: 100, ..PlannedOperations::default() };
+        assert!(!threshold.is_exceeded_by(&deletions(3), 10));
+        assert!(threshold.is_exceeded_by(&deletions(4), 10));
+        assert!(!threshold.is_exceeded_by(&deletions(8), 9));
+        assert!(!threshold.is_exceeded_by(&deletions(0), 0));
+
+        let threshold = DivergenceThreshold { max_count: Some(5), ..DivergenceThreshold::default() };
+        assert!(threshold.is_exceeded_by(&deletions(6), 9));
+        assert!(!threshold.is_exceeded_by(&deletions(5), 100));
+
+        // Overwrites only count when asked to
+        let overwrites = PlannedOperations { pulled_changes: 6, pushed_changes: 6, ..PlannedOperations::default() };
+        assert!(!DivergenceThreshold::default().is_exceeded_by(&overwrites, 12));
+        assert!(DivergenceThreshold { count_overwrites: true, ..DivergenceThreshold::default() }.is_exceeded_by(&overwrites, 12));
+
+        let cal: Url = "https://some.server/cal/".parse().unwrap();
+        let urls: BTreeSet<Url> = vec!["https://some.server/cal/a.ics".parse().unwrap()].into_iter().collect();
+        let none = BTreeSet::new();
+        assert_eq!(token_of(&cal, &[("del", &urls), ("chg", &none)]), token_of(&cal, &[("del", &urls), ("chg", &none)]));
+        assert_ne!(token_of(&cal, &[("del", &urls), ("chg", &none)]), token_of(&cal, &[("del", &none), ("chg", &urls)]));
+    }
+}
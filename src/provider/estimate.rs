@@ -0,0 +1,250 @@
+//! Estimating what a sync would do, before running it (see [`crate::provider::Provider::estimate`])
+//!
+//! This is meant for user interfaces, e.g. to tell that a first sync is about to download thousands of items.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use url::Url;
+
+use crate::item::VersionTag;
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use super::divergence::PlannedOperations;
+use super::sync_progress::SyncProgress;
+use super::{sorted_by_url, ConflictPreference, Provider, SyncPlan, DOWNLOAD_BATCH_SIZE};
+
+/// What the next sync is expected to do in a calendar
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CalendarEstimate {
+    pub calendar: Url,
+    pub planned: PlannedOperations,
+    /// How many items have been modified in both sources (or in a way that conflicts otherwise)
+    pub conflicts: usize,
+    /// How many bytes are expected to be downloaded, as far as the server tells the sizes of its items
+    pub download_bytes: u64,
+    /// How many downloads the server has not told the size of, and that [`Self::download_bytes`] does not account for
+    pub unknown_sizes: usize,
+    /// How many bytes are expected to be uploaded
+    pub upload_bytes: u64,
+    /// How many requests are expected to transfer these items (the listings are not included, since they have been made by the estimate already)
+    pub requests: usize,
+}
+
+impl CalendarEstimate {
+    /// How many items are expected to be downloaded from the server
+    pub fn downloads(&self) -> usize {
+        self.planned.pulled_additions + self.planned.pulled_changes
+    }
+
+    /// How many items are expected to be uploaded to the server
+    pub fn uploads(&self) -> usize {
+        self.planned.pushed_additions + self.planned.pushed_changes
+    }
+
+    /// How many items are expected to be deleted, be it locally or on the server
+    pub fn deletions(&self) -> usize {
+        self.planned.pulled_deletions + self.planned.pushed_deletions
+    }
+}
+
+/// What the next sync is expected to do (see [`Provider::estimate`])
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncEstimate {
+    /// The calendars the sync would handle, sorted by URL
+    pub calendars: Vec<CalendarEstimate>,
+}
+
+impl SyncEstimate {
+    /// How many items are expected to be downloaded from the server
+    pub fn downloads(&self) -> usize {
+        self.calendars.iter().map(CalendarEstimate::downloads).sum()
+    }
+
+    /// How many items are expected to be uploaded to the server
+    pub fn uploads(&self) -> usize {
+        self.calendars.iter().map(CalendarEstimate::uploads).sum()
+    }
+
+    /// How many items are expected to be deleted, be it locally or on the server
+    pub fn deletions(&self) -> usize {
+        self.calendars.iter().map(CalendarEstimate::deletions).sum()
+    }
+
+    /// How many bytes are expected to be transferred, both ways
+    pub fn bytes(&self) -> u64 {
+        self.calendars.iter().map(|calendar| calendar.download_bytes + calendar.upload_bytes).sum()
+    }
+
+    /// How many requests are expected to be sent
+    pub fn requests(&self) -> usize {
+        self.calendars.iter().map(|calendar| calendar.requests).sum()
+    }
+
+    /// How long the sync is expected to last, given how long a request takes to be answered and how fast data is transferred
+    pub fn duration(&self, round_trip: Duration, bytes_per_second: u64) -> Duration {
+        let transfer = Duration::from_secs_f64(self.bytes() as f64 / bytes_per_second.max(1) as f64);
+        round_trip.saturating_mul(self.requests() as u32) + transfer
+    }
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Tell what the next sync would download, upload and delete, without writing anything (be it locally or on the server).
+    ///
+    /// Only the cheap steps of a sync are made: the calendars are listed, and so are the items of the remote calendars whose ctag has changed since the last sync.
+    /// The next sync starts from these listings rather than listing the calendars again, unless another estimate has been made in between.
+    #[allow(clippy::await_holding_lock)]
+    pub async fn estimate(&mut self) -> Result<SyncEstimate, Box<dyn Error>> {
+        self.prefetched_listings.clear();
+        let mut estimate = SyncEstimate::default();
+        let mut handled_calendars = BTreeSet::new();
+
+        let cals_remote = self.remote.list_calendars().await?;
+        for (cal_url, cal_remote) in sorted_by_url(cals_remote) {
+            handled_calendars.insert(cal_url.clone());
+            if self.is_disabled(&cal_remote) || self.is_kept_local(&cal_url).await || cal_remote.lock().unwrap().subscription().is_some() {
+                continue;
+            }
+            let cal_local = match self.local.get_calendar(&cal_url).await {
+                Some(cal_local) => cal_local,
+                None => {
+                    // The sync would create this calendar locally, and download all of its items
+                    let cal_remote = cal_remote.lock().unwrap();
+                    Arc::new(Mutex::new(T::new(cal_remote.name().to_string(), cal_url.clone(), cal_remote.supported_components(), cal_remote.color().cloned())))
+                },
+            };
+            let cal_remote = cal_remote.lock().unwrap();
+            let cal_local = cal_local.lock().unwrap();
+            let policy = cal_local.sync_policy().unwrap_or_default();
+            let listing = Self::list_remote_items(&*cal_local, &*cal_remote, &policy, &mut SyncProgress::new()).await?;
+            let calendar_estimate = self.estimate_calendar(&*cal_local, Some(&*cal_remote), listing.items.clone()).await?;
+            estimate.calendars.push(calendar_estimate);
+            self.prefetched_listings.insert(cal_url, listing);
+        }
+
+        // The local calendars that are not on the server yet would be created there
+        let unavailable_remote_calendars = self.remote.unavailable_calendars();
+        let cals_local = self.local.list_calendars().await?;
+        for (cal_url, cal_local) in sorted_by_url(cals_local) {
+            if handled_calendars.contains(&cal_url) || unavailable_remote_calendars.contains(&cal_url) {
+                continue;
+            }
+            let cal_local = cal_local.lock().unwrap();
+            if cal_local.is_local_only() {
+                continue;
+            }
+            let mut calendar_estimate = self.estimate_calendar(&*cal_local, None, HashMap::new()).await?;
+            // Creating it on the server
+            calendar_estimate.requests += 1;
+            estimate.calendars.push(calendar_estimate);
+        }
+
+        Ok(estimate)
+    }
+
+    /// What the sync of a calendar would do, given its remote items (`cal_remote` is `None` for calendars that are not on the server yet)
+    async fn estimate_calendar(&self, cal_local: &T, cal_remote: Option<&U>, remote_items: HashMap<Url, VersionTag>) -> Result<CalendarEstimate, Box<dyn Error>> {
+        let policy = cal_local.sync_policy().unwrap_or_default();
+        let has_resolver = match policy.effective_conflict_resolution() {
+            Some(ConflictPreference::PreferLocal) => true,
+            Some(ConflictPreference::PreferRemote) => false,
+            None => self.conflict_resolver.is_some(),
+        };
+        let breaks_ties_by_date = !has_resolver && policy.effective_conflict_resolution().is_none();
+
+        let mut progress = SyncProgress::new();
+        let SyncPlan { mut local_del, mut remote_del, mut local_changes, mut remote_changes, mut local_additions, mut remote_additions, conflicts, .. } =
+            Self::plan_operations(cal_local, cal_remote, remote_items, has_resolver, breaks_ties_by_date, &mut progress).await?;
+
+        // Just like the sync applies the policy of this calendar
+        if !policy.direction.pulls() {
+            remote_del.clear();
+            remote_additions.clear();
+            remote_changes.clear();
+        }
+        if !policy.direction.pushes() {
+            local_del.clear();
+            local_additions.clear();
+            local_changes.clear();
+        }
+        let mut skipped = Vec::new();
+        for url in local_additions.iter().chain(local_changes.iter()) {
+            let filtered_out = matches!(cal_local.get_item_by_url(url).await, Some(item) if !policy.filter.matches(item));
+            let backing_off = cal_local.upload_failure(url).is_some_and(|failure| failure.is_backing_off());
+            if filtered_out || backing_off {
+                skipped.push(url.clone());
+            }
+        }
+        for url in skipped {
+            local_additions.remove(&url);
+            local_changes.remove(&url);
+        }
+
+        let mut download_bytes = 0;
+        let mut unknown_sizes = 0;
+        for url in remote_additions.iter().chain(remote_changes.iter()).chain(conflicts.iter().map(|(url, _)| url)) {
+            match cal_remote.and_then(|cal_remote| cal_remote.item_size(url)) {
+                Some(size) => download_bytes += size,
+                None => unknown_sizes += 1,
+            }
+        }
+        let mut upload_bytes = 0;
+        for url in local_additions.iter().chain(local_changes.iter()) {
+            if let Some(item) = cal_local.get_item_by_url(url).await {
+                upload_bytes += crate::ical::build_from(item)?.len() as u64;
+            }
+        }
+
+        let batch_size = policy.batch_size.unwrap_or(DOWNLOAD_BATCH_SIZE).max(1);
+        // Downloads are batched, every upload and deletion is a request of its own, and a conflict needs the remote version before the resolved one is pushed
+        let requests = remote_additions.len().div_ceil(batch_size) + remote_changes.len().div_ceil(batch_size)
+            + local_additions.len() + local_changes.len() + local_del.len() + 2 * conflicts.len();
+
+        Ok(CalendarEstimate {
+            calendar: cal_local.url().clone(),
+            planned: PlannedOperations {
+                pulled_additions: remote_additions.len(), pulled_changes: remote_changes.len(), pulled_deletions: remote_del.len(),
+                pushed_additions: local_additions.len(), pushed_changes: local_changes.len(), pushed_deletions: local_del.len(),
+            },
+            conflicts: progress.conflicts().len(),
+            download_bytes,
+            unknown_sizes,
+            upload_bytes,
+            requests,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totals() {
+        let calendar = |pulled_additions, download_bytes, requests| CalendarEstimate {
+            calendar: "https://some.server/cal/".parse().unwrap(),
+            planned: PlannedOperations { pulled_additions, pushed_deletions: 1, ..PlannedOperations::default() },
+            conflicts: 0,
+            download_bytes,
+            unknown_sizes: 0,
+            upload_bytes: 0,
+            requests,
+        };
+        let estimate = SyncEstimate { calendars: vec![calendar(10, 4000, 2), calendar(5, 1000, 3)] };
+        assert_eq!(estimate.downloads(), 15);
+        assert_eq!(estimate.uploads(), 0);
+        assert_eq!(estimate.deletions(), 2);
+        assert_eq!(estimate.bytes(), 5000);
+        assert_eq!(estimate.requests(), 5);
+        assert_eq!(estimate.duration(Duration::from_millis(100), 1000), Duration::from_millis(5500));
+        assert_eq!(SyncEstimate::default().duration(Duration::from_millis(100), 0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,150 @@
+//! Checking that the server stores the items a sync uploads just as they have been sent (see [`crate::provider::Provider::set_verify_uploads`])
+//!
+//! Some servers silently alter what they store (e.g. a misconfigured one that truncates long descriptions). Once the local copy has been refreshed from the server, what has been lost is lost for good.
+
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::error_code::ErrorCode;
+use crate::ical::PropertyChange;
+use crate::item::SyncStatus;
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use crate::Item;
+use super::Provider;
+use super::sync_progress::SyncProgress;
+
+/// An uploaded item the server has not stored as it has been sent
+#[derive(Clone, Debug, PartialEq)]
+pub struct FidelityFinding {
+    pub calendar: Url,
+    pub item: Url,
+    /// How the stored version differs from the uploaded one (the `before` values are the uploaded ones)
+    pub differences: Vec<PropertyChange>,
+}
+
+impl FidelityFinding {
+    /// The stable identifier of this anomaly (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        crate::error_code::UPLOAD_ALTERED
+    }
+}
+
+/// An upload the server has altered (see [`CompleteCalendar::altered_upload`]).
+///
+/// The item is not uploaded again as long as its local version is the one that has been sent, since the server would only alter it once more
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlteredUpload {
+    /// The [`upload_digest`] of the version that has been sent
+    pub digest: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// A digest of the content of an item, that does not depend on the way it happens to be serialized (see [`crate::ical::canonical_form`])
+pub fn upload_digest(item: &Item) -> Result<String, Box<dyn Error>> {
+    Ok(format!("{:x}", md5::compute(crate::ical::canonical_form(item)?)))
+}
+
+/// How the version of an item a server stores differs from the one that has been `sent` to it.
+///
+/// The properties the server has added (e.g. to normalise items, see [`DavCalendar::take_rewritten_item`]) are rewrites rather than alterations, and so are the properties that change at every save (e.g. `DTSTAMP`).
+pub fn alterations(sent: &Item, stored: &Item) -> Result<Vec<PropertyChange>, Box<dyn Error>> {
+    let changes = crate::ical::diff(&crate::ical::build_from(sent)?, &crate::ical::build_from(stored)?, true)?;
+    Ok(changes.into_iter().filter(|change| !matches!(change, PropertyChange::Added { .. })).collect())
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Fetch an item that has just been uploaded (and is now stored at `url`), and check the server stores it as it has been `sent`.
+    ///
+    /// In case it does not, this is recorded in `progress`, and the local copy is reverted to the uploaded version and left modified, so that it is not considered synced.
+    /// This version is then not uploaded again until it is edited locally (see [`CompleteCalendar::altered_upload`])
+    pub(crate) async fn verify_upload(cal_local: &mut T, cal_remote: &U, mut sent: Item, url: &Url, progress: &mut SyncProgress) {
+        let stored = match cal_remote.get_item_by_url(url).await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => {
+                progress.error(&format!("Item {} has been uploaded, but the server does not have it", url));
+                return;
+            },
+            Err(err) => {
+                progress.error(&format!("Unable to fetch item {} back from the server: {}", url, err));
+                return;
+            },
+        };
+        let differences = match alterations(&sent, &stored) {
+            Ok(differences) if differences.is_empty() => {
+                cal_local.set_altered_upload(url, None);
+                return;
+            },
+            Ok(differences) => differences,
+            Err(err) => {
+                progress.error(&format!("Unable to compare item {} with the version the server stores: {}", url, err));
+                return;
+            },
+        };
+        progress.add_fidelity_finding(FidelityFinding { calendar: cal_local.url().clone(), item: url.clone(), differences });
+
+        let tag = match cal_local.get_item_by_url(url).await.map(|item| item.sync_status()) {
+            Some(SyncStatus::Synced(tag)) | Some(SyncStatus::LocallyModified(tag)) => tag.clone(),
+            _ => {
+                progress.error(&format!("Inconsistency: item {} has been uploaded but is locally missing", url));
+                return;
+            },
+        };
+        sent.set_url(url.clone());
+        sent.set_sync_status(SyncStatus::LocallyModified(tag));
+        let digest = upload_digest(&sent);
+        if let Err(err) = cal_local.update_item(sent).await {
+            progress.error(&format!("Unable to revert item {} to its uploaded version: {}", url, err));
+            return;
+        }
+        match digest {
+            Ok(digest) => cal_local.set_altered_upload(url, Some(AlteredUpload { digest, detected_at: Utc::now() })),
+            Err(err) => progress.info(&format!("Unable to tell the content of item {}, it will be uploaded again: {}", url, err)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Task;
+
+    #[test]
+    fn test_alterations() {
+        let cal_url: Url = "https://some.server/cal/".parse().unwrap();
+        let sent = Task::new("A rather long name".to_string(), false, &cal_url);
+        let mut stored = sent.clone();
+        stored.set_name("A rather".to_string());
+        let (sent, stored) = (Item::Task(sent), Item::Task(stored));
+
+        let differences = alterations(&sent, &stored).unwrap();
+        assert_eq!(differences.len(), 1);
+        assert!(matches!(&differences[0], PropertyChange::Changed { name, .. } if name == "SUMMARY"));
+        assert!(alterations(&sent, &sent).unwrap().is_empty());
+
+        // Properties the server adds are not alterations
+        let added = ical::property::Property { name: "X-SERVER-ID".to_string(), params: None, value: Some("42".to_string()) };
+        let rewritten = match &sent {
+            Item::Task(task) => Item::Task(task.clone().with_extra_parameters(vec![added])),
+            Item::Event(_) => unreachable!(),
+        };
+        assert!(alterations(&sent, &rewritten).unwrap().is_empty());
+        assert_eq!(alterations(&rewritten, &sent).unwrap().len(), 1);
+
+        // The sync status is not part of the digest
+        let mut synced = sent.clone();
+        synced.set_sync_status(SyncStatus::Synced(crate::item::VersionTag::from(String::from("v1"))));
+        assert_eq!(upload_digest(&sent).unwrap(), upload_digest(&synced).unwrap());
+        assert_ne!(upload_digest(&sent).unwrap(), upload_digest(&stored).unwrap());
+    }
+}
@@ -0,0 +1,197 @@
+//! Pushing the local changes without pulling anything, e.g. on a poor connection (see [`Provider::flush`])
+
+use std::error::Error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use url::Url;
+
+use crate::cache::outbound::{self, OutboundKind, QueuedOperation};
+use crate::calendar::history::ChangeSource;
+use crate::item::{SyncStatus, VersionTag};
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use super::{DateLimitsPolicy, Provider};
+use super::sync_progress::SyncProgress;
+
+/// How [`Provider::flush`] pushes the outbound queue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlushPolicy {
+    /// The local source is saved (see [`crate::traits::CalDavSource::checkpoint`]) every time this many operations have been pushed, so that an interrupted flush does not lose track of what it has pushed already
+    pub batch_size: usize,
+    /// Push at most this many operations, the oldest ones first. The other ones are left for the next flush (or sync)
+    pub max_operations: Option<usize>,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self { batch_size: 30, max_operations: None }
+    }
+}
+
+/// What [`Provider::flush`] has done
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlushReport {
+    /// The operations that have been pushed, in this order
+    pub pushed: Vec<QueuedOperation>,
+    /// The operations that are left for a sync, e.g. because the item has changed on the server in the meantime (this is a conflict only a sync can handle), or because of [`FlushPolicy::max_operations`]
+    pub deferred: Vec<QueuedOperation>,
+    /// The operations that have failed, with their errors. They are still queued
+    pub failed: Vec<(QueuedOperation, String)>,
+}
+
+impl FlushReport {
+    /// Whether no operation has failed
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Push the outbound queue (see [`crate::Cache::outbound_queue`]) to the server, oldest changes first. Nothing is pulled from the server.
+    ///
+    /// This is a push-only sync, that only asks the server for the version tags of the calendars that have queued changes, to make sure it does not overwrite changes made on the server in the meantime.
+    /// The items whose remote versions have changed are left for a sync, that handles them as conflicts. So are the items of calendars that are not on the server yet.
+    ///
+    /// This returns an error in case the server cannot be reached. The operations that have been pushed so far are not pushed again by the next flush (or sync)
+    pub async fn flush(&mut self, policy: &FlushPolicy) -> Result<FlushReport, Box<dyn Error>> {
+        let mut progress = SyncProgress::new();
+        let cals_local = self.local.list_calendars().await?;
+        let mut queue = Vec::new();
+        for (cal_url, cal_local) in &cals_local {
+            queue.extend(Self::queued_operations(cal_url, cal_local).await?);
+        }
+        outbound::sort(&mut queue);
+
+        let mut report = FlushReport::default();
+        if let Some(max_operations) = policy.max_operations {
+            if queue.len() > max_operations {
+                report.deferred = queue.split_off(max_operations);
+            }
+        }
+        if queue.is_empty() {
+            return Ok(report);
+        }
+
+        let cals_remote = self.remote.list_calendars().await?;
+        let mut remote_tags: HashMap<Url, HashMap<Url, VersionTag>> = HashMap::new();
+        for batch in queue.chunks(policy.batch_size.max(1)) {
+            for op in batch {
+                let cal_remote = match cals_remote.get(&op.calendar) {
+                    Some(cal_remote) if cal_remote.lock().unwrap().subscription().is_none() && !self.is_disabled(cal_remote) => cal_remote,
+                    _ => {
+                        // Syncs create the calendars that are missing from the server
+                        report.deferred.push(op.clone());
+                        continue;
+                    },
+                };
+                if !remote_tags.contains_key(&op.calendar) {
+                    let tags = Self::remote_version_tags(cal_remote).await?;
+                    remote_tags.insert(op.calendar.clone(), tags);
+                }
+
+                let cal_local = &cals_local[&op.calendar];
+                cal_local.lock().unwrap().set_change_source(ChangeSource::Sync);
+                let result = Self::flush_operation(op, cal_local, cal_remote, &remote_tags[&op.calendar], self.date_limits_policy, &mut progress).await;
+                cal_local.lock().unwrap().set_change_source(ChangeSource::Local);
+                match result {
+                    Ok(true) => report.pushed.push(op.clone()),
+                    Ok(false) => report.deferred.push(op.clone()),
+                    Err(err) => {
+                        log::warn!("Unable to push the local change of item {}: {}", op.item, err);
+                        report.failed.push((op.clone(), err.to_string()));
+                    },
+                }
+            }
+            self.local.checkpoint()?;
+        }
+        Ok(report)
+    }
+
+    #[allow(clippy::await_holding_lock)]
+    async fn queued_operations(cal_url: &Url, cal_local: &Arc<Mutex<T>>) -> Result<Vec<QueuedOperation>, Box<dyn Error>> {
+        let cal_local = cal_local.lock().unwrap();
+        if cal_local.is_local_only() {
+            return Ok(Vec::new());
+        }
+        let policy = cal_local.sync_policy().unwrap_or_default();
+        let items = cal_local.get_items().await?;
+        Ok(outbound::queued_operations(cal_url, &policy, items.into_values(), |url| cal_local.marked_for_deletion_at(url)))
+    }
+
+    #[allow(clippy::await_holding_lock)]
+    async fn remote_version_tags(cal_remote: &Arc<Mutex<U>>) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        cal_remote.lock().unwrap().get_item_version_tags().await
+    }
+
+    /// Push a single operation. Returns whether it has been pushed, or whether it is left for a sync
+    #[allow(clippy::await_holding_lock)]
+    async fn flush_operation(op: &QueuedOperation, cal_local: &Arc<Mutex<T>>, cal_remote: &Arc<Mutex<U>>, remote_tags: &HashMap<Url, VersionTag>, date_limits_policy: DateLimitsPolicy, progress: &mut SyncProgress) -> Result<bool, Box<dyn Error>> {
+        let mut cal_local = cal_local.lock().unwrap();
+        let mut cal_remote = cal_remote.lock().unwrap();
+        if cal_local.upload_failure(&op.item).is_some_and(|failure| failure.is_backing_off()) {
+            return Ok(false);
+        }
+        let item = cal_local.get_item_by_url(&op.item).await
+            .ok_or("the local item has vanished")?;
+        let remote_tag = remote_tags.get(&op.item);
+
+        let is_creation = match (op.kind, item.sync_status(), remote_tag) {
+            (OutboundKind::Create, SyncStatus::NotSynced, None) => true,
+            (OutboundKind::Update, SyncStatus::LocallyModified(local_tag), Some(remote_tag)) if local_tag == remote_tag => false,
+            (OutboundKind::Delete, SyncStatus::LocallyDeleted(local_tag), Some(remote_tag)) if local_tag == remote_tag => {
+                cal_remote.delete_item(&op.item).await?;
+                cal_local.immediately_delete_item(&op.item).await?;
+                return Ok(true);
+            },
+            (OutboundKind::Delete, SyncStatus::LocallyDeleted(_), None) => {
+                // It has been deleted from the server too
+                cal_local.immediately_delete_item(&op.item).await?;
+                return Ok(true);
+            },
+            // The server has another version of this item: this is a conflict
+            _ => return Ok(false),
+        };
+        let item = match cal_local.get_item_by_url_mut(&op.item).await {
+            None => return Err("the local item has vanished".into()),
+            Some(item) => {
+                if !Self::check_date_limits(item, &op.calendar, cal_remote.date_limits(), date_limits_policy, progress) {
+                    return Ok(false);
+                }
+                item.clone()
+            },
+        };
+        let upload = match is_creation {
+            true => cal_remote.add_item(item).await,
+            false => cal_remote.update_item(item).await,
+        };
+        match upload {
+            Err(err) => {
+                if super::uid_conflict_url(&*err, &op.calendar).is_some() {
+                    // Syncs replace the local item with the one the server already has
+                    return Ok(false);
+                }
+                Self::track_upload(&mut *cal_local, &op.item, Some(&*err));
+                Err(err)
+            },
+            Ok(new_ss) => {
+                if let Some(local_item) = cal_local.get_item_by_url_mut(&op.item).await {
+                    local_item.set_sync_status(new_ss);
+                }
+                Self::track_upload(&mut *cal_local, &op.item, None);
+                if let Some(canonical_url) = cal_remote.take_canonical_url(&op.item) {
+                    Self::move_to_canonical_url(&mut *cal_local, &op.item, canonical_url).await?;
+                }
+                if let Some(stored) = cal_remote.take_rewritten_item(&op.item) {
+                    Self::adopt_rewritten_item(&mut *cal_local, stored, progress).await;
+                }
+                Ok(true)
+            },
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Whether the remote source of a [`Provider`](crate::provider::Provider) could be reached by the last syncs (see [`crate::provider::Provider::sync_health`])
+
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+
+/// How the last syncs of a provider went
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncHealth {
+    /// When the last totally successful sync has finished
+    pub last_success: Option<DateTime<Utc>>,
+    /// When the remote source has first been found unreachable, in case it still is
+    pub unreachable_since: Option<DateTime<Utc>>,
+    /// How many syncs in a row have not been able to reach the remote source
+    pub consecutive_unreachable_syncs: u32,
+}
+
+impl SyncHealth {
+    /// Whether the last sync has been able to reach the remote source (this is also the case when no sync has run yet)
+    pub fn is_remote_reachable(&self) -> bool {
+        self.unreachable_since.is_none()
+    }
+
+    /// Record the outcome of a sync
+    pub(crate) fn record(&mut self, success: bool, remote_unreachable: bool, at: DateTime<Utc>) {
+        if remote_unreachable {
+            self.unreachable_since.get_or_insert(at);
+            self.consecutive_unreachable_syncs += 1;
+            return;
+        }
+        self.unreachable_since = None;
+        self.consecutive_unreachable_syncs = 0;
+        if success {
+            self.last_success = Some(at);
+        }
+    }
+}
+
+/// Whether an error means the remote source could not be reached at all (e.g. the network is down, or the server refuses connections), so that the same request may well succeed later
+pub(crate) fn is_unreachable(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(err) = crate::error::downcast_ref::<reqwest::Error>(err) {
+            if err.is_connect() || err.is_timeout() {
+                return true;
+            }
+        }
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind;
+            if matches!(err.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::NotConnected | ErrorKind::TimedOut) {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_sync_health() {
+        let at = |hour| Utc.ymd(2021, 3, 21).and_hms(hour, 0, 0);
+        let mut health = SyncHealth::default();
+        assert!(health.is_remote_reachable());
+
+        health.record(false, true, at(1));
+        health.record(false, true, at(2));
+        assert_eq!(health.unreachable_since, Some(at(1)));
+        assert_eq!(health.consecutive_unreachable_syncs, 2);
+        assert!(!health.is_remote_reachable());
+
+        health.record(true, false, at(3));
+        assert_eq!(health, SyncHealth { last_success: Some(at(3)), unreachable_since: None, consecutive_unreachable_syncs: 0 });
+    }
+
+    #[test]
+    fn test_is_unreachable() {
+        let refused: Box<dyn Error> = Box::new(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused"));
+        assert!(is_unreachable(&*refused));
+        let other: Box<dyn Error> = "Unexpected HTTP status code 500".into();
+        assert!(!is_unreachable(&*other));
+    }
+}
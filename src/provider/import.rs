@@ -0,0 +1,121 @@
+//! Importing iCal files into a calendar, and pushing them to the server right away (see [`Provider::import_and_sync_ics`])
+
+use std::error::Error;
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use crate::item::SyncStatus;
+use crate::utils::random_url;
+use super::conflict::DerivedIdentity;
+use super::sync_progress::{SyncProgress, SyncResult};
+use super::Provider;
+
+/// What [`Provider::import_and_sync_ics`] does with an imported item that has the UID of an item the calendar already has
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UidCollisionPolicy {
+    /// The existing item is kept, the imported one is ignored
+    #[default]
+    Skip,
+    /// The existing item is replaced by the imported one (at the URL of the existing one)
+    Replace,
+    /// The imported item is added alongside the existing one, with a new UID
+    KeepBoth,
+}
+
+/// What [`Provider::import_and_sync_ics`] has done
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    /// The URLs of the items that have been created
+    pub created: Vec<Url>,
+    /// The URLs of the existing items that have been replaced by imported ones (see [`UidCollisionPolicy::Replace`])
+    pub replaced: Vec<Url>,
+    /// The UIDs of the imported items that have been ignored, since the calendar already has them (see [`UidCollisionPolicy::Skip`])
+    pub skipped: Vec<String>,
+    /// The UIDs of the items of the file that could not be parsed, and why
+    pub invalid: Vec<(String, String)>,
+    /// The created or replaced items that the sync has not been able to upload yet. They will be uploaded at the next sync
+    pub not_uploaded: Vec<Url>,
+    /// The outcome of the sync of the calendar
+    pub sync_result: SyncResult,
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Import the items of an iCal file (e.g. an exported calendar) into the local calendar at `cal_url`, then sync this calendar only, so that they are pushed to the server right away.
+    ///
+    /// Imported items that have the UID of an item the calendar already has are handled as `policy` says.
+    /// Items that could not be uploaded during this sync are kept locally, and uploaded by the next one.
+    // Just like during a sync, calendars are locked while their (async) methods are called
+    #[allow(clippy::await_holding_lock)]
+    pub async fn import_and_sync_ics(&mut self, cal_url: &Url, ics: &str, policy: UidCollisionPolicy) -> Result<ImportReport, Box<dyn Error>> {
+        let cal_local = self.local.get_calendar(cal_url).await.ok_or_else(|| format!("No local calendar {}", cal_url))?;
+        let resources = crate::ical::split_ics(ics)?;
+
+        let mut report = ImportReport::default();
+        {
+            let mut cal_local = cal_local.lock().unwrap();
+            let supported_components = cal_local.supported_components();
+            let existing: HashMap<String, (Url, SyncStatus)> = cal_local.get_items().await?
+                .into_iter()
+                .map(|(url, item)| (item.uid().to_string(), (url, item.sync_status().clone())))
+                .collect();
+
+            for resource in resources {
+                let parsed = crate::ical::parse_for_calendar(&resource.ical, random_url(cal_url), SyncStatus::NotSynced, supported_components);
+                let mut item = match parsed {
+                    Err(err) => {
+                        report.invalid.push((resource.uid, err.to_string()));
+                        continue;
+                    },
+                    Ok(item) => item,
+                };
+
+                match (existing.get(item.uid()), policy) {
+                    (None, _) => {
+                        report.created.push(item.url().clone());
+                        cal_local.add_item(item).await?;
+                    },
+                    (Some(_), UidCollisionPolicy::Skip) => report.skipped.push(resource.uid),
+                    (Some((existing_url, existing_status)), UidCollisionPolicy::Replace) => {
+                        item.set_url(existing_url.clone());
+                        item.set_sync_status(match existing_status {
+                            SyncStatus::NotSynced => SyncStatus::NotSynced,
+                            SyncStatus::Synced(tag) | SyncStatus::LocallyModified(tag) | SyncStatus::LocallyDeleted(tag) => SyncStatus::LocallyModified(tag.clone()),
+                        });
+                        report.replaced.push(existing_url.clone());
+                        cal_local.update_item(item).await?;
+                    },
+                    (Some(_), UidCollisionPolicy::KeepBoth) => {
+                        let identity = DerivedIdentity {
+                            name: item.name().to_string(),
+                            uid: uuid::Uuid::new_v4().to_hyphenated().to_string(),
+                        };
+                        let copy = item.derive(cal_url, identity);
+                        report.created.push(copy.url().clone());
+                        cal_local.add_item(copy).await?;
+                    },
+                }
+            }
+        }
+
+        let mut progress = SyncProgress::new();
+        self.run_sync(&mut progress, Some(cal_url)).await;
+        report.sync_result = self.last_sync_result.clone().unwrap_or_default();
+
+        let cal_local = cal_local.lock().unwrap();
+        for url in report.created.iter().chain(report.replaced.iter()) {
+            let is_uploaded = matches!(cal_local.get_item_by_url(url).await.map(|item| item.sync_status()), Some(SyncStatus::Synced(_)));
+            if !is_uploaded {
+                report.not_uploaded.push(url.clone());
+            }
+        }
+        Ok(report)
+    }
+}
@@ -3,21 +3,50 @@
 //! It is also responsible for syncing them together
 
 use std::error::Error;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex};
 use std::fmt::{Display, Formatter};
 
+use chrono::Utc;
 use url::Url;
 use itertools::Itertools;
 
-use crate::traits::{BaseCalendar, CalDavSource, DavCalendar};
+use crate::traits::{BaseCalendar, CalDavSource, DavCalendar, SourceAdapter};
 use crate::traits::CompleteCalendar;
-use crate::item::SyncStatus;
+use crate::item::{SyncStatus, VersionTag};
+use crate::calendar::{ComponentSet, DateLimits, PartialFetchError, PreconditionFailedError, QuotaExceededError};
+use crate::calendar::history::ChangeSource;
+use crate::dav_error::{DavErrorDetail, HttpError};
+use crate::client::Throttled;
+use crate::Item;
 
 pub mod sync_progress;
+pub mod archive;
+pub mod import;
+pub mod pairing;
+pub mod verify;
+pub mod conflict;
+pub mod date_limits;
+pub use date_limits::{DateLimitViolation, DateLimitsPolicy};
+pub mod policy;
+pub mod quarantine;
+pub mod health;
+pub mod copy;
+pub mod flush;
+pub mod checkpoint;
+pub mod reset;
+pub mod divergence;
+pub mod fidelity;
+pub mod estimate;
+use checkpoint::{CancellationHandle, CheckpointObserver, SyncCheckpoint};
+use health::SyncHealth;
+use divergence::{DivergenceThreshold, DivergenceWarning, PlannedOperations};
+pub use policy::{CalendarSyncPolicy, ConflictPreference, SyncDirection, SyncFilter};
+use conflict::{ConflictContext, ConflictKind, ConflictMetadata, ConflictNaming, ConflictResolver, DefaultConflictNaming, Resolution};
 use sync_progress::SyncProgress;
-use sync_progress::{FeedbackSender, SyncEvent};
+use quarantine::UploadFailure;
+use sync_progress::{ComponentTypeChange, ConflictRecord, FeedbackSender, LockedItem, RejectedItem, SyncEvent, SyncResult};
 
 /// How many items will be batched in a single HTTP request when downloading from the server
 #[cfg(not(test))]
@@ -42,17 +71,47 @@ impl Display for BatchDownloadType {
     }
 }
 
+/// The items of a remote calendar, as a sync starts from (see [`Provider::list_remote_items`])
+#[derive(Clone, Debug)]
+struct RemoteListing {
+    /// The ctag of the remote calendar when it has been listed
+    ctag: Option<VersionTag>,
+    /// The version tags of the remote items
+    items: HashMap<Url, VersionTag>,
+    /// The sync token these items are up to date with, in case they have been listed as changes since the last sync
+    sync_token: Option<String>,
+    /// Whether the server has refused the sync token of the local calendar, that must then be forgotten
+    sync_token_refused: bool,
+}
+
+/// What a sync has to do in a calendar (see [`Provider::plan_operations`])
+struct SyncPlan {
+    local_del: BTreeSet<Url>,
+    remote_del: BTreeSet<Url>,
+    local_changes: BTreeSet<Url>,
+    remote_changes: BTreeSet<Url>,
+    local_additions: BTreeSet<Url>,
+    remote_additions: BTreeSet<Url>,
+    deleted_from_both: BTreeSet<Url>,
+    /// The items that have been modified in both sources, with their remote version tags, that the conflict resolver is asked about
+    conflicts: Vec<(Url, VersionTag)>,
+    /// How many items the local calendar holds
+    local_items: usize,
+}
+
 
 /// A data source that combines two `CalDavSource`s, which is able to sync both sources.
 ///
+/// Any data source can be synced, as long as it implements [`SourceAdapter`] (see the [`traits`](crate::traits) module).
+///
 /// Usually, you will only need to use a provider between a server and a local cache, that is to say a [`CalDavProvider`](crate::CalDavProvider), i.e. a `Provider<Cache, CachedCalendar, Client, RemoteCalendar>`. \
 /// However, providers can be used for integration tests, where the remote source is mocked by a `Cache`.
 #[derive(Debug)]
 pub struct Provider<L, T, R, U>
 where
-    L: CalDavSource<T>,
+    L: SourceAdapter<T>,
     T: CompleteCalendar + Sync + Send,
-    R: CalDavSource<U>,
+    R: SourceAdapter<U>,
     U: DavCalendar + Sync + Send,
 {
     /// The remote source (usually a server)
@@ -60,15 +119,45 @@ where
     /// The local cache
     local: L,
 
+    /// See [`Provider::set_transfer_budget`]
+    transfer_budget: Option<u64>,
+    last_sync_result: Option<SyncResult>,
+    /// See [`Provider::set_conflict_resolver`]
+    conflict_resolver: Option<ConflictResolver>,
+    /// See [`Provider::set_conflict_naming`]
+    conflict_naming: Box<dyn ConflictNaming>,
+    /// See [`Provider::set_date_limits_policy`]
+    date_limits_policy: DateLimitsPolicy,
+    /// See [`Provider::set_validate_before_first_sync`]
+    validate_before_first_sync: bool,
+    /// See [`Provider::set_respect_server_enabled_flag`]
+    respect_server_enabled_flag: bool,
+    /// See [`Provider::last_pairing_report`]
+    pairing_report: Option<pairing::PairingReport>,
+    /// See [`Provider::sync_health`]
+    sync_health: SyncHealth,
+    /// See [`Provider::cancellation_handle`]
+    cancellation: CancellationHandle,
+    /// See [`Provider::set_checkpoint_observer`]
+    checkpoint_observer: Option<CheckpointObserver>,
+    /// See [`Provider::set_divergence_threshold`]
+    divergence_threshold: Option<DivergenceThreshold>,
+    /// See [`Provider::confirm_divergent_sync`]
+    confirmed_divergences: HashMap<Url, String>,
+    /// See [`Provider::set_verify_uploads`]
+    verify_uploads: bool,
+    /// The remote calendars the last [`Provider::estimate`] has listed, that the next sync starts from
+    prefetched_listings: HashMap<Url, RemoteListing>,
+
     phantom_t: PhantomData<T>,
     phantom_u: PhantomData<U>,
 }
 
 impl<L, T, R, U> Provider<L, T, R, U>
 where
-    L: CalDavSource<T>,
+    L: SourceAdapter<T>,
     T: CompleteCalendar + Sync + Send,
-    R: CalDavSource<U>,
+    R: SourceAdapter<U>,
     U: DavCalendar + Sync + Send,
 {
     /// Create a provider.
@@ -77,6 +166,21 @@ where
     /// However, both can be interchangeable. The only difference is that `remote` always wins in case of a sync conflict
     pub fn new(remote: R, local: L) -> Self {
         Self { remote, local,
+            transfer_budget: None,
+            last_sync_result: None,
+            conflict_resolver: None,
+            conflict_naming: Box::new(DefaultConflictNaming),
+            date_limits_policy: DateLimitsPolicy::default(),
+            validate_before_first_sync: false,
+            respect_server_enabled_flag: false,
+            pairing_report: None,
+            sync_health: SyncHealth::default(),
+            cancellation: CancellationHandle::default(),
+            checkpoint_observer: None,
+            divergence_threshold: Some(DivergenceThreshold::default()),
+            confirmed_divergences: HashMap::new(),
+            verify_uploads: false,
+            prefetched_listings: HashMap::new(),
             phantom_t: PhantomData, phantom_u: PhantomData,
         }
     }
@@ -85,6 +189,10 @@ where
     pub fn local(&self)  -> &L { &self.local }
     /// Returns the data source described as `local`
     pub fn local_mut(&mut self)  -> &mut L { &mut self.local }
+    /// Returns the data source described as `local`, for apps to display what it contains.
+    ///
+    /// This is the same as [`Self::local`]. It never contacts the remote source, so that what has already been synced can be displayed even when the remote source is unreachable
+    pub fn local_only_view(&self) -> &L { &self.local }
     /// Returns the data source described as `remote`.
     ///
     /// Apart from tests, there are very few (if any) reasons to access `remote` directly.
@@ -92,17 +200,246 @@ where
     /// To be sure `local` accurately mirrors the `remote` source, you can run [`Provider::sync`]
     pub fn remote(&self) -> &R { &self.remote }
 
+    /// Set a soft limit of the bytes a single sync may exchange with the remote source (or `None` to remove the limit).
+    ///
+    /// When a sync exceeds this budget, it finishes the current batch, saves the local source (see [`CalDavSource::checkpoint`]) and stops.
+    /// [`Provider::last_sync_result`] then reports it, and the next sync will resume the work. \
+    /// This is only effective in case the remote source accounts for its transfers (see [`CalDavSource::transfer_counter`]), which a [`Client`](crate::client::Client) does.
+    pub fn set_transfer_budget(&mut self, max_bytes: Option<u64>) {
+        self.transfer_budget = max_bytes;
+    }
+
+    /// A handle that cancels the running sync, e.g. from another task when the user closes the app.
+    ///
+    /// The sync does not stop right away, but at its next checkpoint (see [`SyncCheckpoint`]), so that the next sync resumes its work as if it had not been interrupted
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.cancellation.clone()
+    }
+
+    /// Set a callback that is told about every checkpoint syncs reach (see [`SyncCheckpoint`]), along with the URL of the calendar that is being synced
+    pub fn set_checkpoint_observer<F>(&mut self, observer: F)
+    where
+        F: Fn(&Url, SyncCheckpoint) + Send + Sync + 'static,
+    {
+        self.checkpoint_observer = Some(CheckpointObserver::new(observer));
+    }
+
+    /// Set an async callback that decides how to resolve conflicts, i.e. items that have been modified both locally and remotely since the last sync.
+    ///
+    /// A sync waits for the callback to return, e.g. so that an app can ask the user what to do. \
+    /// Without a callback (which is the default), the remote version always wins.
+    pub fn set_conflict_resolver<F, Fut>(&mut self, resolver: F)
+    where
+        F: Fn(ConflictContext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Resolution> + Send + 'static,
+    {
+        self.conflict_resolver = Some(ConflictResolver::new(resolver));
+    }
+
+    /// Set how the copies of conflicting items are named when a conflict is resolved with [`Resolution::KeepBoth`].
+    ///
+    /// By default, copies keep the name of the original item and get a random UID (see [`DefaultConflictNaming`])
+    pub fn set_conflict_naming<N: ConflictNaming + 'static>(&mut self, naming: N) {
+        self.conflict_naming = Box::new(naming);
+    }
+
+    /// Set what to do with local items that have dates out of the range their calendar accepts (see [`DavCalendar::date_limits`]), when they are about to be uploaded.
+    ///
+    /// By default, such items are not uploaded ([`DateLimitsPolicy::Skip`]). In any case, they are listed in the [`SyncResult`]
+    pub fn set_date_limits_policy(&mut self, policy: DateLimitsPolicy) {
+        self.date_limits_policy = policy;
+    }
+
+    /// Set how much a sync may delete in a calendar before it is paused (or `None` to never pause syncs).
+    ///
+    /// By default, the sync of a calendar is paused when it would delete (be it locally or on the server) more than 30% of the items of a calendar that holds at least 10 items (see [`DivergenceThreshold`]).
+    /// Items that would be overwritten only count when [`DivergenceThreshold::count_overwrites`] is set.
+    /// The calendar is then left untouched, and the [`SyncResult`] holds a [`DivergenceWarning`], that tells what the sync was about to do. See [`Self::confirm_divergent_sync`] to sync it anyway. \
+    /// Downloading the items of an empty local calendar is never paused, since there is nothing to lose.
+    pub fn set_divergence_threshold(&mut self, threshold: Option<DivergenceThreshold>) {
+        self.divergence_threshold = threshold;
+    }
+
+    /// Whether syncs fetch every item they upload back from the server, to check it stores what has been sent (modulo the properties it adds of its own). This is `false` by default, since it doubles the requests of uploads.
+    ///
+    /// Items the server has altered (e.g. truncated) are reported in [`SyncResult::fidelity_findings`], and are left modified locally (in the version that has been uploaded), so that they are not considered synced.
+    /// They are then not uploaded again until they are edited locally, since the server would only alter them once more.
+    /// Calendars can override this setting (see [`CalendarSyncPolicy::verify_uploads`]). Uploads of [`Self::flush`] are never verified.
+    pub fn set_verify_uploads(&mut self, verify: bool) {
+        self.verify_uploads = verify;
+    }
+
+    /// Sync the calendar at `cal_url` right now, even though the last sync has paused it because it would have deleted too many of its items.
+    ///
+    /// `token` is the one of the [`DivergenceWarning`] of this calendar in the last [`SyncResult`]. In case the sync would now do something else than what this warning tells, the calendar is paused again (with a new warning). \
+    /// This returns whether the sync of this calendar has succeeded.
+    pub async fn confirm_divergent_sync(&mut self, cal_url: &Url, token: &str) -> Result<bool, Box<dyn Error>> {
+        let is_pending = self.last_sync_result.as_ref()
+            .map(|result| result.divergence_warnings.iter().any(|warning| &warning.calendar == cal_url && warning.token == token))
+            .unwrap_or(false);
+        if !is_pending {
+            return Err(format!("The last sync has not paused calendar {} with this token", cal_url).into());
+        }
+
+        self.confirmed_divergences.insert(cal_url.clone(), token.to_string());
+        let mut progress = SyncProgress::new();
+        let success = self.run_sync(&mut progress, Some(cal_url)).await;
+        self.confirmed_divergences.remove(cal_url);
+        Ok(success)
+    }
+
+    /// Set how the calendar at `cal_url` is synced, overriding the defaults of this provider (or go back to them with `None`).
+    ///
+    /// The policy is stored along with the local calendar (e.g. in the cache), so that it survives restarts. Every [`SyncResult`] tells which policy each calendar has been synced with
+    pub async fn set_calendar_sync_policy(&mut self, cal_url: &Url, policy: Option<CalendarSyncPolicy>) -> Result<(), Box<dyn Error>> {
+        let cal_local = self.local.get_calendar(cal_url).await
+            .ok_or_else(|| format!("No local calendar {}", cal_url))?;
+        cal_local.lock().unwrap().set_sync_policy(policy);
+        Ok(())
+    }
+
+    /// Returns the policy the calendar at `cal_url` has, in case it has one of its own (see [`Self::set_calendar_sync_policy`])
+    pub async fn calendar_sync_policy(&self, cal_url: &Url) -> Option<CalendarSyncPolicy> {
+        let cal_local = self.local.get_calendar(cal_url).await?;
+        let policy = cal_local.lock().unwrap().sync_policy();
+        policy
+    }
+
+    /// Returns whether a sync would have anything to do, without actually syncing.
+    ///
+    /// This is much cheaper than a sync: local items are checked for changes that have not been uploaded yet,
+    /// and remote calendars are only asked for their ctag (see [`DavCalendar::get_ctag`]), that is compared to the one recorded at the start of the last successful sync.
+    /// Calendars that are not able to tell their ctag are considered changed, and so are the calendars the last sync has pushed changes to (until they are synced once more).
+    /// Subscribed calendars (see [`DavCalendar::subscription`]) are pending only when they are due for a refresh.
+    #[allow(clippy::await_holding_lock)]
+    pub async fn has_pending_work(&self) -> Result<bool, Box<dyn Error>> {
+        let cals_remote = self.remote.list_calendars().await?;
+        let subscriptions: HashSet<Url> = cals_remote.iter()
+            .filter(|(_, cal)| cal.lock().unwrap().subscription().is_some())
+            .map(|(url, _)| url.clone())
+            .collect();
+        let disabled: HashSet<Url> = cals_remote.iter()
+            .filter(|(_, cal)| self.is_disabled(cal))
+            .map(|(url, _)| url.clone())
+            .collect();
+
+        let cals_local = self.local.list_calendars().await?;
+        let kept_local: HashSet<Url> = cals_local.iter()
+            .filter(|(_, cal)| cal.lock().unwrap().is_local_only())
+            .map(|(url, _)| url.clone())
+            .collect();
+        for (cal_url, cal_local) in sorted_by_url(cals_local.clone()) {
+            if subscriptions.contains(&cal_url) || disabled.contains(&cal_url) || kept_local.contains(&cal_url) {
+                // Local changes are never pushed into subscriptions (nor into disabled calendars, nor out of local-only calendars)
+                continue;
+            }
+            let cal_local = cal_local.lock().unwrap();
+            let policy = cal_local.sync_policy().unwrap_or_default();
+            if !policy.direction.pushes() {
+                continue;
+            }
+            let items = cal_local.get_items().await?;
+            if items.values().any(|item| !matches!(item.sync_status(), SyncStatus::Synced(_)) && policy.filter.matches(item)) {
+                return Ok(true);
+            }
+        }
+
+        let unavailable_remote_calendars = self.remote.unavailable_calendars();
+        let local_only = cals_local.keys()
+            .any(|url| !cals_remote.contains_key(url) && !unavailable_remote_calendars.contains(url) && !kept_local.contains(url));
+        if local_only {
+            return Ok(true);
+        }
+
+        for (cal_url, cal_remote) in sorted_by_url(cals_remote) {
+            if disabled.contains(&cal_url) || kept_local.contains(&cal_url) {
+                continue;
+            }
+            let cal_local = match cals_local.get(&cal_url) {
+                None => return Ok(true),
+                Some(cal_local) => cal_local,
+            };
+            let subscription = cal_remote.lock().unwrap().subscription();
+            if let Some(subscription) = subscription {
+                if subscription.is_due(cal_local.lock().unwrap().last_refreshed(), Utc::now()) {
+                    return Ok(true);
+                }
+                continue;
+            }
+            let pulls = cal_local.lock().unwrap().sync_policy().map(|policy| policy.direction.pulls()).unwrap_or(true);
+            if !pulls {
+                continue;
+            }
+            let last_synced_ctag = self.local.sync_metadata(&cal_url).await?.ctag;
+            let ctag = cal_remote.lock().unwrap().get_ctag().await?;
+            match (ctag, last_synced_ctag) {
+                (Some(ctag), Some(last_synced_ctag)) if ctag == last_synced_ctag => (),
+                _ => return Ok(true),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether the first sync of this provider starts by checking how the calendars of both sources are paired (see [`Provider::validate`]). This is `false` by default.
+    ///
+    /// In case some calendars look like they should be paired but are not (see [`pairing::PairingReport::is_clean`]), this first sync stops before modifying anything.
+    /// An app can then present the report (see [`Provider::last_pairing_report`]) to the user, e.g. to remap the URLs of the local calendars (see [`Cache::rebase_ids`](crate::cache::Cache::rebase_ids)).
+    /// Next syncs do not check anything.
+    pub fn set_validate_before_first_sync(&mut self, validate: bool) {
+        self.validate_before_first_sync = validate;
+    }
+
+    /// Whether calendars that are flagged as disabled on the server (see [`DavCalendar::enabled`]) are left out of syncs. This is `false` by default.
+    ///
+    /// Disabled calendars are skipped just like calendars a sync is not asked for: their local copies are kept as-is (they are not considered deleted), and local changes are not pushed into them
+    pub fn set_respect_server_enabled_flag(&mut self, respect: bool) {
+        self.respect_server_enabled_flag = respect;
+    }
+
+    /// Whether the local copy of a calendar is kept on this device only (see [`CompleteCalendar::is_local_only`])
+    async fn is_kept_local(&self, cal_url: &Url) -> bool {
+        match self.local.get_calendar(cal_url).await {
+            None => false,
+            Some(cal_local) => cal_local.lock().unwrap().is_local_only(),
+        }
+    }
+
+    /// Whether a remote calendar must be left out of syncs (see [`Provider::set_respect_server_enabled_flag`])
+    fn is_disabled(&self, cal_remote: &Arc<Mutex<U>>) -> bool {
+        self.respect_server_enabled_flag && cal_remote.lock().unwrap().enabled() == Some(false)
+    }
+
+    /// Returns the last report made by the check of [`Provider::set_validate_before_first_sync`] (if any)
+    pub fn last_pairing_report(&self) -> Option<&pairing::PairingReport> {
+        self.pairing_report.as_ref()
+    }
+
+    /// Returns the outcome of the last sync (if any)
+    pub fn last_sync_result(&self) -> Option<&SyncResult> {
+        self.last_sync_result.as_ref()
+    }
+
+    /// Returns how the last syncs went, e.g. whether the remote source has been unreachable for a while
+    pub fn sync_health(&self) -> &SyncHealth {
+        &self.sync_health
+    }
+
     /// Performs a synchronisation between `local` and `remote`, and provide feeedback to the user about the progress.
     ///
     /// This bidirectional sync applies additions/deletions made on a source to the other source.
     /// In case of conflicts (the same item has been modified on both ends since the last sync, `remote` always wins).
+    /// For servers that do not provide strong ETags, the most recent version wins instead, according to the `DAV:getlastmodified` of the remote item.
+    ///
+    /// Calendars and items are always handled in the order of their URLs (and so are the lists of [`SyncResult`]), so that syncing the same data twice performs the same requests in the same order.
+    /// The items of remote calendars whose ctag (see [`DavCalendar::get_ctag`]) has not changed since the last successful sync are not listed: only the local changes of these calendars are pushed.
+    /// For the other calendars, servers that support sync tokens (see [`DavCalendar::get_changes_since`]) only list the items that have changed since the last successful sync. The other servers list every item.
     ///
-    /// It returns whether the sync was totally successful (details about errors are logged using the `log::*` macros).
+    /// It returns whether the sync was totally successful (details about errors are logged using the `log::*` macros, and more details are available in [`Provider::last_sync_result`]).
     /// In case errors happened, the sync might have been partially executed but your data will never be correupted (either locally nor in the server).
     /// Simply run this function again, it will re-start a sync, picking up where it failed.
     pub async fn sync_with_feedback(&mut self, feedback_sender: FeedbackSender) -> bool {
         let mut progress = SyncProgress::new_with_feedback_channel(feedback_sender);
-        self.run_sync(&mut progress).await
+        self.run_sync(&mut progress, None).await
     }
 
     /// Performs a synchronisation between `local` and `remote`, without giving any feedback.
@@ -110,26 +447,103 @@ where
     /// See [`Self::sync_with_feedback`]
     pub async fn sync(&mut self) -> bool {
         let mut progress = SyncProgress::new();
-        self.run_sync(&mut progress).await
+        self.run_sync(&mut progress, None).await
     }
 
-    async fn run_sync(&mut self, progress: &mut SyncProgress) -> bool {
-        if let Err(err) = self.run_sync_inner(progress).await {
-            progress.error(&format!("Sync terminated because of an error: {}", err));
+    /// Sync every calendar, or only the one at `only`
+    async fn run_sync(&mut self, progress: &mut SyncProgress, only: Option<&Url>) -> bool {
+        let transfer_counter = self.remote.transfer_counter();
+        let transfers_at_start = transfer_counter.as_ref().map(|counter| counter.lock().unwrap().clone());
+        if let (Some(max_bytes), Some(counter)) = (self.transfer_budget, &transfer_counter) {
+            progress.set_transfer_budget(max_bytes, counter.clone());
         }
+        // Cancellations that have been asked for while no sync was running are ignored
+        self.cancellation.reset();
+        progress.set_cancellation(self.cancellation.clone(), self.checkpoint_observer.clone());
+
+        if let Err(err) = self.run_sync_inner(progress, only).await {
+            if let Some(retry_after) = throttling_of(&*err) {
+                progress.set_throttled(retry_after);
+            } else if health::is_unreachable(&*err) {
+                // Nothing has been changed. This is likely to work at a next sync
+                progress.set_remote_unreachable();
+                progress.warn(&format!("The remote source is unreachable ({}). Nothing has been synced", err));
+            } else {
+                progress.error(&format!("Sync terminated because of an error: {}", err));
+            }
+        }
+        // A cancelled sync has neither failed nor fully succeeded
+        if !progress.is_cancelled() {
+            self.sync_health.record(progress.is_success(), progress.is_remote_unreachable(), Utc::now());
+        }
+        self.cancellation.reset();
+        if progress.is_budget_exhausted() || progress.is_cancelled() {
+            if let Err(err) = self.local.checkpoint() {
+                progress.error(&format!("Unable to save the local source: {}", err));
+            }
+        }
+
+        self.last_sync_result = Some(SyncResult {
+            success: progress.is_success(),
+            budget_exhausted: progress.is_budget_exhausted(),
+            cancelled: progress.is_cancelled(),
+            transfers: transfer_counter.zip(transfers_at_start)
+                .map(|(counter, at_start)| counter.lock().unwrap().since(&at_start)),
+            quota_exceeded: progress.quota_exceeded().cloned(),
+            remote_unreachable: progress.is_remote_unreachable(),
+            throttled: progress.throttled(),
+            date_limit_violations: progress.date_limit_violations().to_vec(),
+            rejected_items: progress.rejected_items().to_vec(),
+            conflicts: progress.conflicts().to_vec(),
+            component_type_changes: progress.component_type_changes().to_vec(),
+            calendars: progress.calendars().clone(),
+            divergence_warnings: progress.divergence_warnings().to_vec(),
+            locked_items: progress.locked_items().to_vec(),
+            fidelity_findings: progress.fidelity_findings().to_vec(),
+        });
         progress.feedback(SyncEvent::Finished{ success: progress.is_success() });
         progress.is_success()
     }
 
-    async fn run_sync_inner(&mut self, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+    async fn run_sync_inner(&mut self, progress: &mut SyncProgress, only: Option<&Url>) -> Result<(), Box<dyn Error>> {
         progress.info("Starting a sync.");
         progress.feedback(SyncEvent::Started);
 
+        if self.validate_before_first_sync && self.last_sync_result.is_none() {
+            let report = self.validate().await?;
+            let is_clean = report.is_clean();
+            self.pairing_report = Some(report);
+            if !is_clean {
+                return Err("Some local and remote calendars look alike but have different URLs (see Provider::last_pairing_report). Nothing has been synced".into());
+            }
+        }
+
+        // Only the very next sync may start from the listings of an estimate, later ones would miss what has changed since
+        let mut prefetched = std::mem::take(&mut self.prefetched_listings);
         let mut handled_calendars = HashSet::new();
+        let is_in_scope = |cal_url: &Url| only.map(|only| only == cal_url).unwrap_or(true);
 
         // Sync every remote calendar
-        let cals_remote = self.remote.get_calendars().await?;
-        for (cal_url, cal_remote) in cals_remote {
+        let cals_remote = self.remote.list_calendars().await?;
+        let remote_cal_urls: HashSet<Url> = cals_remote.keys().cloned().collect();
+        for (cal_url, cal_remote) in sorted_by_url(cals_remote) {
+            if !is_in_scope(&cal_url) {
+                continue;
+            }
+            if progress.check_budget() || progress.is_cancelled() || progress.throttled().is_some() {
+                return Ok(());
+            }
+            if self.is_disabled(&cal_remote) {
+                // Just like a calendar this sync is not asked for. In particular, its local copy (if any) must not be handled as a calendar that is missing from the server
+                progress.info(&format!("Calendar {} is disabled on the server. Skipping it", cal_url));
+                handled_calendars.insert(cal_url);
+                continue;
+            }
+            if self.is_kept_local(&cal_url).await {
+                progress.info(&format!("Calendar {} is kept on this device only. Skipping it", cal_url));
+                handled_calendars.insert(cal_url);
+                continue;
+            }
             let counterpart = match self.get_or_insert_local_counterpart_calendar(&cal_url, cal_remote.clone()).await {
                 Err(err) => {
                     progress.warn(&format!("Unable to get or insert local counterpart calendar for {} ({}). Skipping this time", cal_url, err));
@@ -137,21 +551,69 @@ where
                 },
                 Ok(arc) => arc,
             };
+            let subscription = cal_remote.lock().unwrap().subscription();
+            {
+                let writable = cal_remote.lock().unwrap().is_writable() && subscription.is_none();
+                let mut counterpart = counterpart.lock().unwrap();
+                counterpart.set_unavailable(false);
+                counterpart.set_writable(writable);
+            }
+
+            if let Some(subscription) = subscription {
+                // Nothing is ever pushed into subscriptions, they are only refreshed from time to time
+                handled_calendars.insert(cal_url.clone());
+                let last_refreshed = counterpart.lock().unwrap().last_refreshed();
+                if subscription.is_due(last_refreshed, Utc::now()) {
+                    if let Err(err) = Self::refresh_subscription(counterpart, cal_remote, progress).await {
+                        // The local copy is kept as-is, and the refresh is retried at the next sync
+                        progress.warn(&format!("Unable to refresh subscribed calendar {}: {}", cal_url, err));
+                    }
+                }
+                continue;
+            }
 
-            if let Err(err) = Self::sync_calendar_pair(counterpart, cal_remote, progress).await {
-                progress.warn(&format!("Unable to sync calendar {}: {}, skipping this time.", cal_url, err));
+            let policy = Self::calendar_policy(&counterpart, &cal_url, progress);
+            if let Err(err) = Self::sync_calendar_pair(counterpart, cal_remote, self.conflict_resolver.as_ref(), &*self.conflict_naming, self.date_limits_policy, &policy, policy.verify_uploads.unwrap_or(self.verify_uploads), self.divergence_check(&cal_url), prefetched.remove(&cal_url), progress).await {
+                match throttling_of(&*err) {
+                    Some(retry_after) => progress.set_throttled(retry_after),
+                    None => progress.warn(&format!("Unable to sync calendar {}: {}, skipping this time.", cal_url, err)),
+                }
                 continue;
             }
             handled_calendars.insert(cal_url);
         }
 
         // Sync every local calendar that would not be in the remote yet
-        let cals_local = self.local.get_calendars().await?;
-        for (cal_url, cal_local) in cals_local {
-            if handled_calendars.contains(&cal_url) {
+        let unavailable_remote_calendars = self.remote.unavailable_calendars();
+        let cals_local = self.local.list_calendars().await?;
+        for (cal_url, cal_local) in sorted_by_url(cals_local) {
+            if handled_calendars.contains(&cal_url) || !is_in_scope(&cal_url) {
+                continue;
+            }
+            if progress.check_budget() || progress.is_cancelled() || progress.throttled().is_some() {
+                return Ok(());
+            }
+
+            if cal_local.lock().unwrap().is_local_only() {
+                // It must not be created on the server either
+                progress.info(&format!("Calendar {} is kept on this device only. Skipping it", cal_url));
                 continue;
             }
 
+            if unavailable_remote_calendars.contains(&cal_url) {
+                // This calendar exists on the server, but we are not allowed to access it (anymore?). Let's keep our local copy untouched
+                progress.info(&format!("Calendar {} is currently unavailable on the server. Keeping the local copy as-is.", cal_url));
+                cal_local.lock().unwrap().set_unavailable(true);
+                continue;
+            }
+
+            if !remote_cal_urls.contains(&cal_url) {
+                // This calendar is not on the server (anymore?). Neither are the items that have been marked for deletion
+                if let Err(err) = Self::forget_deleted_items(&cal_local, progress).await {
+                    progress.warn(&format!("Unable to forget the deleted items of calendar {}: {}", cal_url, err));
+                }
+            }
+
             let counterpart = match self.get_or_insert_remote_counterpart_calendar(&cal_url, cal_local.clone()).await {
                 Err(err) => {
                     progress.warn(&format!("Unable to get or insert remote counterpart calendar for {} ({}). Skipping this time", cal_url, err));
@@ -160,8 +622,12 @@ where
                 Ok(arc) => arc,
             };
 
-            if let Err(err) = Self::sync_calendar_pair(cal_local, counterpart, progress).await {
-                progress.warn(&format!("Unable to sync calendar {}: {}, skipping this time.", cal_url, err));
+            let policy = Self::calendar_policy(&cal_local, &cal_url, progress);
+            if let Err(err) = Self::sync_calendar_pair(cal_local, counterpart, self.conflict_resolver.as_ref(), &*self.conflict_naming, self.date_limits_policy, &policy, policy.verify_uploads.unwrap_or(self.verify_uploads), self.divergence_check(&cal_url), prefetched.remove(&cal_url), progress).await {
+                match throttling_of(&*err) {
+                    Some(retry_after) => progress.set_throttled(retry_after),
+                    None => progress.warn(&format!("Unable to sync calendar {}: {}, skipping this time.", cal_url, err)),
+                }
                 continue;
             }
         }
@@ -172,6 +638,78 @@ where
     }
 
 
+    /// Permanently delete the items of a local calendar that are marked for deletion, in case their remote counterparts are known not to exist
+    #[allow(clippy::await_holding_lock)]
+    async fn forget_deleted_items(cal_local: &Arc<Mutex<T>>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+        let mut cal_local = cal_local.lock().unwrap();
+        let deleted: BTreeSet<Url> = cal_local.get_items().await?
+            .into_iter()
+            .filter(|(_, item)| matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .map(|(url, _)| url)
+            .collect();
+        cal_local.set_change_source(ChangeSource::Sync);
+        let result = async {
+            for url in deleted {
+                progress.debug(&format!("> Forgetting {}, whose calendar is not on the server", url));
+                cal_local.immediately_delete_item(&url).await?;
+            }
+            Ok::<(), Box<dyn Error>>(())
+        }.await;
+        cal_local.set_change_source(ChangeSource::Local);
+        result
+    }
+
+    /// Re-fetch the source of a subscribed calendar right now (see [`DavCalendar::subscription`]), whether its refresh is due or not.
+    ///
+    /// Like any refresh, this replaces the local copy of the calendar with the content of its source. Local changes are discarded.
+    pub async fn refresh_now(&mut self, cal_url: &Url) -> Result<(), Box<dyn Error>> {
+        let cal_remote = self.remote.get_calendar(cal_url).await
+            .ok_or_else(|| format!("No calendar {} on the remote source", cal_url))?;
+        if cal_remote.lock().unwrap().subscription().is_none() {
+            return Err(format!("Calendar {} is not a subscription", cal_url).into());
+        }
+        let cal_local = self.get_or_insert_local_counterpart_calendar(cal_url, cal_remote.clone()).await?;
+        cal_local.lock().unwrap().set_writable(false);
+
+        let mut progress = SyncProgress::new();
+        Self::refresh_subscription(cal_local, cal_remote, &mut progress).await
+    }
+
+    /// Replace the content of the local copy of a subscribed calendar with the current content of its source
+    #[allow(clippy::await_holding_lock)]
+    async fn refresh_subscription(cal_local: Arc<Mutex<T>>, cal_remote: Arc<Mutex<U>>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+        let cal_remote = cal_remote.lock().unwrap();
+        let mut cal_local = cal_local.lock().unwrap();
+        progress.info(&format!("Refreshing subscribed calendar {}", cal_local.name()));
+
+        let mut fetched = cal_remote.fetch_subscribed_items().await?;
+        fetched.sort_by(|a, b| a.url().cmp(b.url()));
+        let fetched_urls: HashSet<Url> = fetched.iter().map(|item| item.url().clone()).collect();
+        cal_local.set_change_source(ChangeSource::Remote);
+        let result = async {
+            for url in cal_local.get_item_urls().await?.into_iter().collect::<BTreeSet<_>>() {
+                if !fetched_urls.contains(&url) {
+                    progress.debug(&format!("> Removing {}, that is not in the feed anymore", url));
+                    cal_local.immediately_delete_item(&url).await?;
+                }
+            }
+            for item in fetched {
+                let local_status = cal_local.get_item_by_url(item.url()).await.map(|local| local.sync_status().clone());
+                match local_status {
+                    Some(status) if &status == item.sync_status() => (),
+                    Some(_) => { cal_local.update_item(item).await?; },
+                    None => { cal_local.add_item(item).await?; },
+                }
+            }
+            Ok::<(), Box<dyn Error>>(())
+        }.await;
+        cal_local.set_change_source(ChangeSource::Local);
+        result?;
+
+        cal_local.set_last_refreshed(Some(Utc::now()));
+        Ok(())
+    }
+
     async fn get_or_insert_local_counterpart_calendar(&mut self, cal_url: &Url, needle: Arc<Mutex<U>>) -> Result<Arc<Mutex<T>>, Box<dyn Error>> {
         get_or_insert_counterpart_calendar("local", &mut self.local, cal_url, needle).await
     }
@@ -180,10 +718,35 @@ where
     }
 
 
-    async fn sync_calendar_pair(cal_local: Arc<Mutex<T>>, cal_remote: Arc<Mutex<U>>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+    /// The policy a calendar is synced with (its own one, or the default one), that is recorded in the result of the sync
+    fn calendar_policy(cal_local: &Arc<Mutex<T>>, cal_url: &Url, progress: &mut SyncProgress) -> CalendarSyncPolicy {
+        let policy = cal_local.lock().unwrap().sync_policy();
+        let is_custom_policy = policy.is_some();
+        let policy = policy.unwrap_or_default();
+        progress.set_calendar_policy(cal_url, policy.clone(), is_custom_policy);
+        policy
+    }
+
+    /// The threshold the sync of a calendar is checked against (see [`Self::set_divergence_threshold`]), and the token of the operations that have been confirmed for it, if any
+    fn divergence_check(&self, cal_url: &Url) -> Option<(DivergenceThreshold, Option<&str>)> {
+        self.divergence_threshold.map(|threshold| (threshold, self.confirmed_divergences.get(cal_url).map(String::as_str)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn sync_calendar_pair(cal_local: Arc<Mutex<T>>, cal_remote: Arc<Mutex<U>>, conflict_resolver: Option<&ConflictResolver>, conflict_naming: &dyn ConflictNaming, date_limits_policy: DateLimitsPolicy, policy: &CalendarSyncPolicy, verify_uploads: bool, divergence: Option<(DivergenceThreshold, Option<&str>)>, prefetched: Option<RemoteListing>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
         let mut cal_remote = cal_remote.lock().unwrap();
         let mut cal_local = cal_local.lock().unwrap();
         let cal_name = cal_local.name().to_string();
+        let cal_url = cal_local.url().clone();
+        let batch_size = policy.batch_size.unwrap_or(DOWNLOAD_BATCH_SIZE).max(1);
+        let keep_local = ConflictResolver::new(|_| async { Resolution::KeepLocal });
+        let conflict_resolver = match policy.effective_conflict_resolution() {
+            Some(ConflictPreference::PreferLocal) => Some(&keep_local),
+            Some(ConflictPreference::PreferRemote) => None,
+            None => conflict_resolver,
+        };
+        // Unless the remote version must always win, the most recent version wins when the server cannot tell reliably whether an item has changed
+        let breaks_ties_by_date = conflict_resolver.is_none() && policy.effective_conflict_resolution().is_none();
 
         progress.info(&format!("Syncing calendar {}", cal_name));
         progress.reset_counter();
@@ -195,111 +758,133 @@ where
 
         // Step 1 - find the differences
         progress.debug("Finding the differences to sync...");
-        let mut local_del = HashSet::new();
-        let mut remote_del = HashSet::new();
-        let mut local_changes = HashSet::new();
-        let mut remote_changes = HashSet::new();
-        let mut local_additions = HashSet::new();
-        let mut remote_additions = HashSet::new();
-
-        let remote_items = cal_remote.get_item_version_tags().await?;
+        let listing = match prefetched {
+            Some(listing) => {
+                progress.debug("The remote calendar has been listed by the last estimate, starting from this listing");
+                listing
+            },
+            None => Self::list_remote_items(&*cal_local, &*cal_remote, policy, progress).await?,
+        };
+        if listing.sync_token_refused {
+            // In case the server does not accept this sync token anymore, the next sync starts over without one
+            cal_local.set_last_sync_token(None);
+        }
+        let ctag_at_start = listing.ctag;
+        let sync_token_at_start = listing.sync_token;
+        let remote_items = listing.items;
         progress.feedback(SyncEvent::InProgress{
             calendar: cal_name.clone(),
             items_done_already: 0,
             details: format!("{} remote items", remote_items.len()),
         });
 
-        let mut local_items_to_handle = cal_local.get_item_urls().await?;
-        for (url, remote_tag) in remote_items {
-            progress.trace(&format!("***** Considering remote item {}...", url));
-            match cal_local.get_item_by_url(&url).await {
-                None => {
-                    // This was created on the remote
-                    progress.debug(&format!("*   {} is a remote addition", url));
-                    remote_additions.insert(url);
-                },
-                Some(local_item) => {
-                    if local_items_to_handle.remove(&url) == false {
-                        progress.error(&format!("Inconsistent state: missing task {} from the local tasks", url));
-                    }
+        let SyncPlan { mut local_del, mut remote_del, mut local_changes, mut remote_changes, mut local_additions, mut remote_additions, deleted_from_both, conflicts, local_items: local_item_count } =
+            Self::plan_operations(&*cal_local, Some(&*cal_remote), remote_items, conflict_resolver.is_some(), breaks_ties_by_date, progress).await?;
 
-                    match local_item.sync_status() {
-                        SyncStatus::NotSynced => {
-                            progress.error(&format!("URL reuse between remote and local sources ({}). Ignoring this item in the sync", url));
-                            continue;
-                        },
-                        SyncStatus::Synced(local_tag) => {
-                            if &remote_tag != local_tag {
-                                // This has been modified on the remote
-                                progress.debug(&format!("*   {} is a remote change", url));
-                                remote_changes.insert(url);
-                            }
-                        },
-                        SyncStatus::LocallyModified(local_tag) => {
-                            if &remote_tag == local_tag {
-                                // This has been changed locally
-                                progress.debug(&format!("*   {} is a local change", url));
-                                local_changes.insert(url);
-                            } else {
-                                progress.info(&format!("Conflict: task {} has been modified in both sources. Using the remote version.", url));
-                                progress.debug(&format!("*   {} is considered a remote change", url));
-                                remote_changes.insert(url);
-                            }
-                        },
-                        SyncStatus::LocallyDeleted(local_tag) => {
-                            if &remote_tag == local_tag {
-                                // This has been locally deleted
-                                progress.debug(&format!("*   {} is a local deletion", url));
-                                local_del.insert(url);
-                            } else {
-                                progress.info(&format!("Conflict: task {} has been locally deleted and remotely modified. Reverting to the remote version.", url));
-                                progress.debug(&format!("*   {} is a considered a remote change", url));
-                                remote_changes.insert(url);
-                            }
-                        },
-                    }
+        if progress.reach_checkpoint(&cal_url, SyncCheckpoint::RemoteListed) {
+            return Ok(());
+        }
+
+        // Step 2 - apply the policy of this calendar
+        if !policy.direction.pulls() {
+            progress.debug("Remote changes are not pulled into this calendar");
+            remote_del.clear();
+            remote_additions.clear();
+            remote_changes.clear();
+        }
+        if !policy.direction.pushes() {
+            progress.debug("Local changes are not pushed from this calendar");
+            local_del.clear();
+            local_additions.clear();
+            local_changes.clear();
+        }
+        let mut filtered_out = Vec::new();
+        for url in local_additions.iter().chain(local_changes.iter()) {
+            if matches!(cal_local.get_item_by_url(url).await, Some(item) if !policy.filter.matches(item)) {
+                filtered_out.push(url.clone());
+            }
+        }
+        for url in filtered_out {
+            progress.debug(&format!("*   {} does not match the filter of this calendar, it is not pushed", url));
+            local_additions.remove(&url);
+            local_changes.remove(&url);
+        }
+        let mut backing_off = Vec::new();
+        for url in local_additions.iter().chain(local_changes.iter()) {
+            if let Some(mut failure) = cal_local.upload_failure(url).filter(|failure| failure.is_backing_off()) {
+                failure.next_retry_in -= 1;
+                // The sync is not complete as long as this item is not uploaded
+                progress.warn(&format!("Item {} has failed to upload {} times in a row, it is skipped for {} more syncs", url, failure.consecutive_failures, failure.next_retry_in));
+                cal_local.set_upload_failure(url, Some(failure));
+                backing_off.push(url.clone());
+            }
+        }
+        for url in backing_off {
+            local_additions.remove(&url);
+            local_changes.remove(&url);
+        }
+        if verify_uploads {
+            let mut altered = Vec::new();
+            for url in local_additions.iter().chain(local_changes.iter()) {
+                let recorded = match cal_local.altered_upload(url) {
+                    None => continue,
+                    Some(recorded) => recorded,
+                };
+                match cal_local.get_item_by_url(url).await.map(fidelity::upload_digest) {
+                    Some(Ok(digest)) if digest == recorded.digest => altered.push((url.clone(), recorded.detected_at)),
+                    // It has been edited since
+                    _ => cal_local.set_altered_upload(url, None),
                 }
             }
+            for (url, detected_at) in altered {
+                // This has already been reported, and nothing will change until the item is edited: this does not make the sync fail
+                progress.info(&format!("Item {} is not uploaded again, since the server has altered it on {}. It stays modified locally until it is edited", url, detected_at));
+                local_additions.remove(&url);
+                local_changes.remove(&url);
+            }
         }
 
-        // Also iterate on the local tasks that are not on the remote
-        for url in local_items_to_handle {
-            progress.trace(&format!("##### Considering local item {}...", url));
-            let local_item = match cal_local.get_item_by_url(&url).await {
-                None => {
-                    progress.error(&format!("Inconsistent state: missing task {} from the local tasks", url));
-                    continue;
-                },
-                Some(item) => item,
+        // Pause this calendar in case the sync would delete too many of its items, e.g. because it has been paired with the wrong remote calendar
+        if let Some((threshold, confirmed)) = divergence {
+            let planned = PlannedOperations {
+                pulled_additions: remote_additions.len(), pulled_changes: remote_changes.len(), pulled_deletions: remote_del.len(),
+                pushed_additions: local_additions.len(), pushed_changes: local_changes.len(), pushed_deletions: local_del.len(),
             };
-
-            match local_item.sync_status() {
-                SyncStatus::Synced(_) => {
-                    // This item has been removed from the remote
-                    progress.debug(&format!("#   {} is a deletion from the server", url));
-                    remote_del.insert(url);
-                },
-                SyncStatus::NotSynced => {
-                    // This item has just been locally created
-                    progress.debug(&format!("#   {} has been locally created", url));
-                    local_additions.insert(url);
-                },
-                SyncStatus::LocallyDeleted(_) => {
-                    // This item has been deleted from both sources
-                    progress.debug(&format!("#   {} has been deleted from both sources", url));
-                    remote_del.insert(url);
-                },
-                SyncStatus::LocallyModified(_) => {
-                    progress.info(&format!("Conflict: item {} has been deleted from the server and locally modified. Deleting the local copy", url));
-                    remote_del.insert(url);
-                },
+            if threshold.is_exceeded_by(&planned, local_item_count) {
+                // Only the operations that are counted are confirmed: other edits may happen in the meantime
+                let token = match threshold.count_overwrites {
+                    true => divergence::token_of(&cal_url, &[("pulled-changes", &remote_changes), ("pulled-deletions", &remote_del), ("pushed-changes", &local_changes), ("pushed-deletions", &local_del)]),
+                    false => divergence::token_of(&cal_url, &[("pulled-deletions", &remote_del), ("pushed-deletions", &local_del)]),
+                };
+                if confirmed == Some(token.as_str()) {
+                    progress.info(&format!("Calendar {} has diverged from the server, but its sync has been confirmed", cal_url));
+                } else {
+                    progress.add_divergence_warning(DivergenceWarning { calendar: cal_url.clone(), items: local_item_count, planned, token });
+                    return Ok(());
+                }
             }
         }
 
+        // Items are written to the server at most once per sync, from their current state: an item that has been changed several times since the last sync is pushed once
+        let planned_writes = local_additions.iter().chain(local_changes.iter()).chain(local_del.iter()).chain(conflicts.iter().map(|(url, _)| url));
+        let written_twice = written_twice(planned_writes);
+        debug_assert!(written_twice.is_empty(), "items planned for several writes: {:?}", written_twice);
+        for url in &written_twice {
+            progress.error(&format!("Inconsistency: item {} is planned for several writes to the server", url));
+        }
+        progress.trace(&format!("Planned writes to the server: {} additions, {} changes, {} deletions and {} conflicts, one per item",
+            local_additions.len(), local_changes.len(), local_del.len(), conflicts.len()));
+
+        // Whether something is written to the remote calendar
+        let pushes = !(local_del.is_empty() && local_additions.is_empty() && local_changes.is_empty() && conflicts.is_empty());
 
-        // Step 2 - commit changes
+        // Step 3 - commit changes
         progress.trace("Committing changes...");
+        cal_local.set_change_source(ChangeSource::Sync);
         for url_del in local_del {
+            if progress.check_budget() || progress.throttled().is_some() {
+                break;
+            }
             progress.debug(&format!("> Pushing local deletion {} to the server", url_del));
             progress.increment_counter(1);
             progress.feedback(SyncEvent::InProgress{
@@ -309,9 +894,20 @@ where
             });
 
             match cal_remote.delete_item(&url_del).await {
-                Err(err) => {
+                Err(err) if is_locked(&*err) => {
+                    // It stays marked for deletion, so that the next sync tries again
+                    progress.add_locked_item(LockedItem { calendar: cal_url.clone(), item: url_del.clone(), deletion: true });
+                },
+                Err(err) if is_precondition_failure(&*err) => {
+                    // It stays marked for deletion, and the next sync reverts it to the remote version, just like any item that has been deleted locally and modified remotely
+                    progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url_del.clone(), kind: ConflictKind::DeletedLocallyModifiedRemotely },
+                        "it has been modified on the server during the sync. Leaving it as it is on the server");
                     progress.warn(&format!("Unable to delete remote item {}: {}", url_del, err));
                 },
+                Err(err) => match throttling_of(&*err) {
+                    Some(retry_after) => progress.set_throttled(retry_after),
+                    None => progress.warn(&format!("Unable to delete remote item {}: {}", url_del, err)),
+                },
                 Ok(()) => {
                     // Change the local copy from "marked to deletion" to "actually deleted"
                     if let Err(err) = cal_local.immediately_delete_item(&url_del).await {
@@ -321,6 +917,14 @@ where
             }
         }
 
+        for url_del in deleted_from_both {
+            progress.debug(&format!("> Forgetting {}, that has been deleted from both sources", url_del));
+            if let Err(err) = cal_local.immediately_delete_item(&url_del).await {
+                progress.error(&format!("Unable to permanently delete local item {}: {}", url_del, err));
+            }
+        }
+
+        cal_local.set_change_source(ChangeSource::Remote);
         for url_del in remote_del {
             progress.debug(&format!("> Applying remote deletion {} locally", url_del));
             progress.increment_counter(1);
@@ -334,10 +938,16 @@ where
             }
         }
 
+        if progress.reach_checkpoint(&cal_url, SyncCheckpoint::DeletionsApplied) {
+            cal_local.set_change_source(ChangeSource::Local);
+            return Ok(());
+        }
+
         Self::apply_remote_additions(
             remote_additions,
             &mut *cal_local,
             &mut *cal_remote,
+            batch_size,
             progress,
             &cal_name
         ).await;
@@ -346,12 +956,43 @@ where
             remote_changes,
             &mut *cal_local,
             &mut *cal_remote,
+            batch_size,
             progress,
             &cal_name
         ).await;
 
+        cal_local.set_change_source(ChangeSource::Sync);
+        if let Some(resolver) = conflict_resolver {
+            for (url, remote_tag) in conflicts {
+                if progress.check_budget() {
+                    break;
+                }
+                progress.debug(&format!("> Resolving conflict {}", url));
+                match Self::resolve_conflict(&url, remote_tag, &mut *cal_local, &mut *cal_remote, resolver, conflict_naming).await {
+                    Err(err) => progress.warn(&format!("Unable to resolve the conflict of item {}: {}", url, err)),
+                    Ok(Some(duplicate_url)) => { local_additions.insert(duplicate_url); },
+                    Ok(None) => (),
+                }
+            }
+        }
+
+        if progress.reach_checkpoint(&cal_url, SyncCheckpoint::DownloadsApplied) {
+            cal_local.set_change_source(ChangeSource::Local);
+            return Ok(());
+        }
 
+        let mut uploads_since_checkpoint = 0;
         for url_add in local_additions {
+            if progress.check_budget() || progress.quota_exceeded().is_some() || progress.throttled().is_some() {
+                break;
+            }
+            if uploads_since_checkpoint == batch_size {
+                uploads_since_checkpoint = 0;
+                if progress.reach_checkpoint(&cal_url, SyncCheckpoint::UploadBatch) {
+                    break;
+                }
+            }
+            uploads_since_checkpoint += 1;
             progress.debug(&format!("> Pushing local addition {} to the server", url_add));
             progress.increment_counter(1);
             progress.feedback(SyncEvent::InProgress{
@@ -359,24 +1000,69 @@ where
                 items_done_already: progress.counter(),
                 details: Self::item_name(&cal_local, &url_add).await,
             });
-            match cal_local.get_item_by_url_mut(&url_add).await {
+            let mut sent = None;
+            let uid_conflict = match cal_local.get_item_by_url_mut(&url_add).await {
                 None => {
                     progress.error(&format!("Inconsistency: created item {} has been marked for upload but is locally missing", url_add));
                     continue;
                 },
                 Some(item) => {
+                    if !Self::check_date_limits(item, &cal_url, cal_remote.date_limits(), date_limits_policy, progress) {
+                        continue;
+                    }
                     match cal_remote.add_item(item.clone()).await {
-                        Err(err) => progress.error(&format!("Unable to add item {} to remote calendar: {}", url_add, err)),
+                        Err(err) => match uid_conflict_url(&*err, &cal_url) {
+                            Some(existing_url) => Some(existing_url),
+                            None => {
+                                Self::track_upload(&mut *cal_local, &url_add, Some(&*err));
+                                Self::report_upload_error(err, &format!("Unable to add item {} to remote calendar", url_add), &url_add, &cal_url, progress);
+                                None
+                            },
+                        },
                         Ok(new_ss) => {
                             // Update local sync status
                             item.set_sync_status(new_ss);
+                            sent = Some(item.clone());
+                            Self::track_upload(&mut *cal_local, &url_add, None);
+                            None
                         },
                     }
                 },
             };
+            if let Some(existing_url) = uid_conflict {
+                progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url_add.clone(), kind: ConflictKind::UidAlreadyOnServer },
+                    &format!("the server already has this item at {}. Using this one instead", existing_url));
+                if let Err(err) = Self::adopt_existing_item(&mut *cal_local, &*cal_remote, &url_add, existing_url).await {
+                    progress.error(&format!("Unable to replace item {} with the one the server already has: {}", url_add, err));
+                }
+                continue;
+            }
+            let mut stored_url = url_add.clone();
+            if let Some(canonical_url) = cal_remote.take_canonical_url(&url_add) {
+                stored_url = canonical_url.clone();
+                if let Err(err) = Self::move_to_canonical_url(&mut *cal_local, &url_add, canonical_url).await {
+                    progress.error(&format!("Unable to move item {} to the URL the server stores it at: {}", url_add, err));
+                }
+            }
+            if let Some(stored) = cal_remote.take_rewritten_item(&url_add) {
+                Self::adopt_rewritten_item(&mut *cal_local, stored, progress).await;
+            }
+            if let Some(sent) = sent.filter(|_| verify_uploads) {
+                Self::verify_upload(&mut *cal_local, &*cal_remote, sent, &stored_url, progress).await;
+            }
         }
 
         for url_change in local_changes {
+            if progress.check_budget() || progress.quota_exceeded().is_some() || progress.throttled().is_some() || progress.is_cancelled() {
+                break;
+            }
+            if uploads_since_checkpoint == batch_size {
+                uploads_since_checkpoint = 0;
+                if progress.reach_checkpoint(&cal_url, SyncCheckpoint::UploadBatch) {
+                    break;
+                }
+            }
+            uploads_since_checkpoint += 1;
             progress.debug(&format!("> Pushing local change {} to the server", url_change));
             progress.increment_counter(1);
             progress.feedback(SyncEvent::InProgress{
@@ -384,51 +1070,448 @@ where
                 items_done_already: progress.counter(),
                 details: Self::item_name(&cal_local, &url_change).await,
             });
+            let mut sent = None;
             match cal_local.get_item_by_url_mut(&url_change).await {
                 None => {
                     progress.error(&format!("Inconsistency: modified item {} has been marked for upload but is locally missing", url_change));
                     continue;
                 },
                 Some(item) => {
+                    if !Self::check_date_limits(item, &cal_url, cal_remote.date_limits(), date_limits_policy, progress) {
+                        continue;
+                    }
                     match cal_remote.update_item(item.clone()).await {
-                        Err(err) => progress.error(&format!("Unable to update item {} in remote calendar: {}", url_change, err)),
+                        Err(err) => {
+                            Self::track_upload(&mut *cal_local, &url_change, Some(&*err));
+                            Self::report_upload_error(err, &format!("Unable to update item {} in remote calendar", url_change), &url_change, &cal_url, progress);
+                        },
                         Ok(new_ss) => {
                             // Update local sync status
                             item.set_sync_status(new_ss);
+                            sent = Some(item.clone());
+                            Self::track_upload(&mut *cal_local, &url_change, None);
                         },
                     };
                 }
             };
+            let mut stored_url = url_change.clone();
+            if let Some(canonical_url) = cal_remote.take_canonical_url(&url_change) {
+                stored_url = canonical_url.clone();
+                if let Err(err) = Self::move_to_canonical_url(&mut *cal_local, &url_change, canonical_url).await {
+                    progress.error(&format!("Unable to move item {} to the URL the server stores it at: {}", url_change, err));
+                }
+            }
+            if let Some(stored) = cal_remote.take_rewritten_item(&url_change) {
+                Self::adopt_rewritten_item(&mut *cal_local, stored, progress).await;
+            }
+            if let Some(sent) = sent.filter(|_| verify_uploads) {
+                Self::verify_upload(&mut *cal_local, &*cal_remote, sent, &stored_url, progress).await;
+            }
+        }
+
+        if uploads_since_checkpoint > 0 && !progress.is_cancelled() {
+            progress.reach_checkpoint(&cal_url, SyncCheckpoint::UploadBatch);
+        }
+
+        // Remember the current state of the remote calendar, so that `has_pending_work` can tell whether it has changed since
+        if progress.is_success() && !progress.is_budget_exhausted() {
+            // Whatever has changed since this token (including what this sync has pushed) is listed by the next sync
+            if let Some(sync_token) = sync_token_at_start {
+                cal_local.set_last_sync_token(Some(sync_token));
+            }
+            // This is the ctag that has been read before listing the items: a ctag read now would also cover the changes other clients may have made during this sync, that have not been pulled.
+            // In case this sync has pushed anything, it does not match the server anymore, so that the next sync lists the items once more
+            if pushes {
+                progress.debug("Changes have been pushed, the next sync lists the remote items again");
+            }
+            cal_local.set_last_synced_ctag(ctag_at_start);
         }
 
+        cal_local.set_change_source(ChangeSource::Local);
         Ok(())
     }
 
 
+    /// List the items of a remote calendar (or only its changes since the last sync), as a sync of this calendar starts with. Nothing is written, be it locally or on the server.
+    ///
+    /// This is skipped in case nothing has changed on the server since the last successful sync
+    async fn list_remote_items(cal_local: &T, cal_remote: &U, policy: &CalendarSyncPolicy, progress: &mut SyncProgress) -> Result<RemoteListing, Box<dyn Error>> {
+        // Listing the remote items is not needed in case nothing has changed on the server since the last successful sync
+        let ctag = match cal_remote.get_ctag().await {
+            Ok(ctag) => ctag,
+            Err(err) => {
+                progress.info(&format!("Unable to get the ctag of calendar {}: {}", cal_local.name(), err));
+                None
+            },
+        };
+        let mut sync_token = None;
+        let mut sync_token_refused = false;
+        let items = match (&ctag, cal_local.last_synced_ctag()) {
+            (Some(ctag), Some(last_synced_ctag)) if ctag == last_synced_ctag => {
+                progress.debug("The remote calendar has not changed since the last sync, its items are not listed");
+                // The server still has the version of every item that has been synced
+                Self::synced_version_tags(cal_local).await?
+            },
+            // The changes a sync token has skipped would never be pulled
+            _ if !policy.direction.pulls() => cal_remote.get_item_version_tags().await?,
+            _ => match cal_remote.get_changes_since(cal_local.last_sync_token()).await? {
+                Some(changes) => {
+                    progress.debug(&format!("{} remote items have changed and {} have been deleted since the last sync", changes.changed.len(), changes.deleted.len()));
+                    // Without a sync token, every item is a change
+                    let mut remote_items = match cal_local.last_sync_token() {
+                        None => HashMap::new(),
+                        Some(_) => Self::synced_version_tags(cal_local).await?,
+                    };
+                    for url in &changes.deleted {
+                        remote_items.remove(url);
+                    }
+                    remote_items.extend(changes.changed);
+                    sync_token = Some(changes.sync_token);
+                    remote_items
+                },
+                None => {
+                    sync_token_refused = cal_local.last_sync_token().is_some();
+                    cal_remote.get_item_version_tags().await?
+                },
+            },
+        };
+        Ok(RemoteListing { ctag, items, sync_token, sync_token_refused })
+    }
+
+    /// Compare the local items of a calendar with its remote ones, and find what a sync has to do.
+    /// Conflicts are recorded in `progress`, but nothing is written.
+    ///
+    /// Items that have been modified in both sources are fetched from `cal_remote` (if any): in case both versions only differ in the way they are serialized (see [`crate::ical::have_same_content`]), this is not a conflict, and the remote version is just pulled
+    async fn plan_operations(cal_local: &T, cal_remote: Option<&U>, remote_items: HashMap<Url, VersionTag>, has_resolver: bool, breaks_ties_by_date: bool, progress: &mut SyncProgress) -> Result<SyncPlan, Box<dyn Error>> {
+        let cal_url = cal_local.url().clone();
+        let mut local_del = BTreeSet::new();
+        let mut remote_del = BTreeSet::new();
+        let mut local_changes = BTreeSet::new();
+        let mut remote_changes = BTreeSet::new();
+        let mut local_additions = BTreeSet::new();
+        let mut remote_additions = BTreeSet::new();
+        let mut deleted_from_both = BTreeSet::new();
+        let mut conflicts = Vec::new();
+
+
+        let mut local_items_to_handle: BTreeSet<Url> = cal_local.get_item_urls().await?.into_iter().collect();
+        let local_items = local_items_to_handle.len();
+        for (url, remote_tag) in sorted_by_url(remote_items) {
+            progress.trace(&format!("***** Considering remote item {}...", url));
+            match cal_local.get_item_by_url(&url).await {
+                None => {
+                    // This was created on the remote
+                    progress.debug(&format!("*   {} is a remote addition", url));
+                    remote_additions.insert(url);
+                },
+                Some(local_item) => {
+                    if !local_items_to_handle.remove(&url) {
+                        progress.error(&format!("Inconsistent state: missing task {} from the local tasks", url));
+                    }
+
+                    match local_item.sync_status() {
+                        SyncStatus::NotSynced => {
+                            progress.error(&format!("URL reuse between remote and local sources ({}). Ignoring this item in the sync [{}]", url, ConflictKind::UrlReuse));
+                            progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url.clone(), kind: ConflictKind::UrlReuse }, "ignoring this item in the sync");
+                            continue;
+                        },
+                        SyncStatus::Synced(local_tag) => {
+                            if &remote_tag != local_tag {
+                                // This has been modified on the remote
+                                progress.debug(&format!("*   {} is a remote change", url));
+                                remote_changes.insert(url);
+                            }
+                        },
+                        SyncStatus::LocallyModified(local_tag) => {
+                            if &remote_tag == local_tag {
+                                // This has been changed locally
+                                progress.debug(&format!("*   {} is a local change", url));
+                                local_changes.insert(url);
+                            } else if Self::has_same_remote_content(local_item, cal_remote, &url).await {
+                                progress.debug(&format!("*   {} has been modified in both sources, but only differs in its serialization. It is considered a remote change", url));
+                                remote_changes.insert(url);
+                            } else if has_resolver {
+                                progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url.clone(), kind: ConflictKind::ModifiedInBoth }, "it has been modified in both sources");
+                                conflicts.push((url, remote_tag));
+                            } else if breaks_ties_by_date && is_local_version_newer(local_item, &remote_tag) {
+                                progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url.clone(), kind: ConflictKind::ModifiedInBoth },
+                                    "it has been modified in both sources. The server provides no reliable ETag, and the local version is the most recent one: using it.");
+                                progress.debug(&format!("*   {} is considered a local change", url));
+                                local_changes.insert(url);
+                            } else {
+                                progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url.clone(), kind: ConflictKind::ModifiedInBoth }, "it has been modified in both sources. Using the remote version.");
+                                progress.debug(&format!("*   {} is considered a remote change", url));
+                                remote_changes.insert(url);
+                            }
+                        },
+                        SyncStatus::LocallyDeleted(local_tag) => {
+                            if &remote_tag == local_tag {
+                                // This has been locally deleted
+                                progress.debug(&format!("*   {} is a local deletion", url));
+                                local_del.insert(url);
+                            } else {
+                                progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url.clone(), kind: ConflictKind::DeletedLocallyModifiedRemotely },
+                                    "it has been locally deleted and remotely modified. Reverting to the remote version.");
+                                progress.debug(&format!("*   {} is a considered a remote change", url));
+                                remote_changes.insert(url);
+                            }
+                        },
+                    }
+                }
+            }
+        }
+
+        // Also iterate on the local tasks that are not on the remote
+        for url in local_items_to_handle {
+            progress.trace(&format!("##### Considering local item {}...", url));
+            let local_item = match cal_local.get_item_by_url(&url).await {
+                None => {
+                    progress.error(&format!("Inconsistent state: missing task {} from the local tasks", url));
+                    continue;
+                },
+                Some(item) => item,
+            };
+
+            match local_item.sync_status() {
+                SyncStatus::Synced(_) => {
+                    // This item has been removed from the remote
+                    progress.debug(&format!("#   {} is a deletion from the server", url));
+                    remote_del.insert(url);
+                },
+                SyncStatus::NotSynced => {
+                    // This item has just been locally created
+                    progress.debug(&format!("#   {} has been locally created", url));
+                    local_additions.insert(url);
+                },
+                SyncStatus::LocallyDeleted(_) => {
+                    // This item has been deleted from both sources. Whatever the policy of this calendar, there is nothing left to sync
+                    progress.debug(&format!("#   {} has been deleted from both sources", url));
+                    deleted_from_both.insert(url);
+                },
+                SyncStatus::LocallyModified(_) => {
+                    progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: url.clone(), kind: ConflictKind::ModifiedLocallyDeletedRemotely },
+                        "it has been deleted from the server and locally modified. Deleting the local copy");
+                    remote_del.insert(url);
+                },
+            }
+        }
+
+        Ok(SyncPlan { local_del, remote_del, local_changes, remote_changes, local_additions, remote_additions, deleted_from_both, conflicts, local_items })
+    }
+
+    /// Whether the remote version of an item has the same content as the local one, i.e. whether they only differ in the way they are serialized
+    async fn has_same_remote_content(local_item: &Item, cal_remote: Option<&U>, url: &Url) -> bool {
+        let cal_remote = match cal_remote {
+            None => return false,
+            Some(cal_remote) => cal_remote,
+        };
+        match cal_remote.get_item_by_url(url).await {
+            Ok(Some(remote_item)) => crate::ical::have_same_content(local_item, &remote_item),
+            Ok(None) => false,
+            Err(err) => {
+                log::debug!("Unable to fetch {} to compare it with its local version: {}", url, err);
+                false
+            },
+        }
+    }
+
+    /// The version tags the server had for the local items when they have last been synced
+    async fn synced_version_tags(cal_local: &T) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        Ok(cal_local.get_items().await?.into_iter()
+            .filter_map(|(url, item)| match item.sync_status() {
+                SyncStatus::NotSynced => None,
+                SyncStatus::Synced(tag) | SyncStatus::LocallyModified(tag) | SyncStatus::LocallyDeleted(tag) => Some((url, tag.clone())),
+            })
+            .collect())
+    }
+
+    /// Ask the resolver how to resolve a conflict, and apply its decision.
+    ///
+    /// The local version is the current state of the item, whatever it has gone through since the last sync (see [`crate::calendar::history`]): it is written to the server once.
+    ///
+    /// This returns the URL of the local item that must be uploaded, in case the local version has been duplicated
+    async fn resolve_conflict(url: &Url, remote_tag: VersionTag, cal_local: &mut T, cal_remote: &mut U, resolver: &ConflictResolver, naming: &dyn ConflictNaming) -> Result<Option<Url>, Box<dyn Error>> {
+        let local_item = cal_local.get_item_by_url(url).await.ok_or("the local item has vanished")?.clone();
+        let remote_item = cal_remote.get_item_by_url(url).await?.ok_or("the remote item has vanished")?;
+        if crate::ical::have_same_content(&local_item, &remote_item) {
+            // Both versions only differ in the way they are serialized, this is not an actual conflict
+            log::debug!("Both versions of {} have the same content", url);
+            cal_local.update_item(remote_item).await?;
+            return Ok(None);
+        }
+        let context = ConflictContext {
+            calendar: cal_local.url().clone(),
+            local: local_item.clone(),
+            remote: remote_item.clone(),
+        };
+
+        let mut to_upload = match resolver.resolve(context).await {
+            Resolution::KeepRemote => {
+                cal_local.update_item(remote_item).await?;
+                return Ok(None);
+            },
+            Resolution::KeepBoth => {
+                let metadata = ConflictMetadata {
+                    calendar: cal_local.url().clone(),
+                    remote: remote_item.clone(),
+                    resolved_at: chrono::Utc::now(),
+                };
+                let duplicate = local_item.derive(cal_local.url(), naming.name_copy(&local_item, &metadata));
+                let duplicate_url = duplicate.url().clone();
+                cal_local.add_item(duplicate).await?;
+                cal_local.update_item(remote_item).await?;
+                return Ok(Some(duplicate_url));
+            },
+            Resolution::KeepLocal => local_item,
+            Resolution::Merged(mut merged) => {
+                merged.set_url(url.clone());
+                merged
+            },
+        };
+
+        // Overwrite the current remote version
+        to_upload.set_sync_status(SyncStatus::LocallyModified(remote_tag));
+        let new_ss = cal_remote.update_item(to_upload.clone()).await?;
+        to_upload.set_sync_status(new_ss);
+        cal_local.update_item(to_upload).await?;
+        Ok(None)
+    }
+
+    /// Apply the date limits policy to an item that is about to be uploaded. Returns whether it should actually be uploaded
+    fn check_date_limits(item: &mut Item, cal_url: &Url, limits: DateLimits, policy: DateLimitsPolicy, progress: &mut SyncProgress) -> bool {
+        let reference_tz = crate::datetime::system_timezone();
+        match date_limits::enforce(item, &limits, policy, &reference_tz) {
+            None => true,
+            Some(properties) => {
+                progress.add_date_limit_violation(DateLimitViolation {
+                    calendar: cal_url.clone(),
+                    item: item.url().clone(),
+                    properties,
+                    action: policy,
+                });
+                policy != DateLimitsPolicy::Skip
+            },
+        }
+    }
+
+    /// Keep track of the uploads of an item (`error` being `None` when it has succeeded), so that an item whose uploads keep failing is retried less and less often (see [`quarantine`])
+    fn track_upload(cal_local: &mut T, item_url: &Url, error: Option<&(dyn Error + 'static)>) {
+        match error {
+            None => cal_local.set_upload_failure(item_url, None),
+            // Only the errors that are about this very item count (e.g. `403 Forbidden`), not the ones of the server or of the network
+            Some(err) if !is_refusal_of_item(err) => (),
+            Some(err) => {
+                let failure = UploadFailure::record(cal_local.upload_failure(item_url), err.to_string());
+                if failure.is_backing_off() {
+                    log::info!("Upload of item {} has failed {} times in a row. Skipping it for {} syncs", item_url, failure.consecutive_failures, failure.next_retry_in);
+                }
+                cal_local.set_upload_failure(item_url, Some(failure));
+            },
+        }
+    }
+
+    /// Report an upload the server has refused. `failure` describes what could not be done
+    fn report_upload_error(err: Box<dyn Error>, failure: &str, item_url: &Url, cal_url: &Url, progress: &mut SyncProgress) {
+        if err.is::<QuotaExceededError>() {
+            progress.set_quota_exceeded(cal_url);
+            return;
+        }
+        if is_precondition_failure(&*err) {
+            progress.add_conflict(ConflictRecord { calendar: cal_url.clone(), item: item_url.clone(), kind: ConflictKind::ModifiedInBoth },
+                "it has been modified on the server during the sync. Leaving both versions as they are, until the next sync resolves this conflict");
+            // The sync is not complete as long as this conflict is not resolved
+            progress.warn(&format!("{}: {}", failure, err));
+            return;
+        }
+        if is_locked(&*err) {
+            progress.add_locked_item(LockedItem { calendar: cal_url.clone(), item: item_url.clone(), deletion: false });
+            return;
+        }
+        if let Some(retry_after) = throttling_of(&*err) {
+            progress.set_throttled(retry_after);
+            return;
+        }
+        if let Some(http_error) = crate::error::downcast_ref::<HttpError>(&*err) {
+            if let Some(reason) = http_error.detail.as_ref().filter(|detail| detail.is_invalid_item()) {
+                progress.add_rejected_item(RejectedItem {
+                    calendar: cal_url.clone(),
+                    item: item_url.clone(),
+                    reason: reason.clone(),
+                    message: http_error.message.clone(),
+                });
+                return;
+            }
+        }
+        progress.error(&format!("{}: {}", failure, err));
+    }
+
+    /// Replace a local item the server has refused because it already has an item with the same UID (at `existing_url`) with this existing item
+    async fn adopt_existing_item(cal_local: &mut T, cal_remote: &U, url: &Url, existing_url: Url) -> Result<(), Box<dyn Error>> {
+        // The existing item has usually been downloaded already, since it was on the server when this sync started
+        if cal_local.get_item_by_url(&existing_url).await.is_none() {
+            let existing = cal_remote.get_item_by_url(&existing_url).await?
+                .ok_or("the existing item has vanished")?;
+            cal_local.add_item(existing).await?;
+        }
+        cal_local.immediately_delete_item(url).await
+    }
+
+    /// Replace the local copy of an item with the version the server has stored after rewriting it, so that this rewrite does not look like a remote modification at the next sync.
+    /// The fields the user has set are kept as they were uploaded
+    async fn adopt_rewritten_item(cal_local: &mut T, mut stored: Item, progress: &mut SyncProgress) {
+        let url = stored.url().clone();
+        match cal_local.get_item_by_url(&url).await {
+            None => progress.error(&format!("Inconsistency: item {} has been rewritten by the server but is locally missing", url)),
+            Some(uploaded) => {
+                progress.info(&format!("The server has rewritten item {}, refreshing the local copy", url));
+                stored.keep_user_fields_of(uploaded);
+                if let Err(err) = cal_local.update_item(stored).await {
+                    progress.error(&format!("Unable to refresh item {} from the server: {}", url, err));
+                }
+            },
+        }
+    }
+
+    /// Store a local item under the URL the server has reported for it (see [`DavCalendar::take_canonical_url`]), and forget the one it was stored under
+    async fn move_to_canonical_url(cal_local: &mut T, url: &Url, canonical_url: Url) -> Result<(), Box<dyn Error>> {
+        let mut item = cal_local.get_item_by_url(url).await.ok_or("the local item has vanished")?.clone();
+        item.set_url(canonical_url);
+        cal_local.add_item(item).await?;
+        cal_local.immediately_delete_item(url).await
+    }
+
     async fn item_name(cal: &T, url: &Url) -> String {
         cal.get_item_by_url(url).await.map(|item| item.name()).unwrap_or_default().to_string()
     }
 
     async fn apply_remote_additions(
-        mut remote_additions: HashSet<Url>,
+        remote_additions: BTreeSet<Url>,
         cal_local: &mut T,
         cal_remote: &mut U,
+        batch_size: usize,
         progress: &mut SyncProgress,
         cal_name: &str
     ) {
-        for batch in remote_additions.drain().chunks(DOWNLOAD_BATCH_SIZE).into_iter() {
+        for batch in remote_additions.into_iter().chunks(batch_size).into_iter() {
+            if progress.check_budget() {
+                break;
+            }
             Self::fetch_batch_and_apply(BatchDownloadType::RemoteAdditions, batch, cal_local, cal_remote, progress, cal_name).await;
         }
     }
 
     async fn apply_remote_changes(
-        mut remote_changes: HashSet<Url>,
+        remote_changes: BTreeSet<Url>,
         cal_local: &mut T,
         cal_remote: &mut U,
+        batch_size: usize,
         progress: &mut SyncProgress,
         cal_name: &str
     ) {
-        for batch in remote_changes.drain().chunks(DOWNLOAD_BATCH_SIZE).into_iter() {
+        for batch in remote_changes.into_iter().chunks(batch_size).into_iter() {
+            if progress.check_budget() {
+                break;
+            }
             Self::fetch_batch_and_apply(BatchDownloadType::RemoteChanges, batch, cal_local, cal_remote, progress, cal_name).await;
         }
     }
@@ -443,47 +1526,178 @@ where
     ) {
         progress.debug(&format!("> Applying a batch of {} locally", batch_type) /* too bad Chunks does not implement ExactSizeIterator, that could provide useful debug info. See https://github.com/rust-itertools/itertools/issues/171 */);
 
-        let list_of_additions: Vec<Url> = remote_additions.map(|url| url.clone()).collect();
-        match cal_remote.get_items_by_url(&list_of_additions).await {
-            Err(err) => {
-                progress.warn(&format!("Unable to get the batch of {} {:?}: {}. Skipping them.", batch_type, list_of_additions, err));
-            },
-            Ok(items) => {
-                for item in items {
-                    match item {
-                        None => {
-                            progress.error(&format!("Inconsistency: an item from the batch has vanished from the remote end"));
-                            continue;
-                        },
-                        Some(new_item) => {
-                            let local_update_result = match batch_type {
-                                BatchDownloadType::RemoteAdditions => cal_local.add_item(new_item.clone()).await,
-                                BatchDownloadType::RemoteChanges => cal_local.update_item(new_item.clone()).await,
-                            };
-                            if let Err(err) = local_update_result {
-                                progress.error(&format!("Not able to add item {} to local calendar: {}", new_item.url(), err));
-                            }
-                        },
+        let list_of_additions: Vec<Url> = remote_additions.collect();
+
+        // Download the batch. In case this fails, retry the items that have not been received yet
+        let max_retries = *crate::config::DOWNLOAD_BATCH_RETRIES.lock().unwrap();
+        let retry_delay = *crate::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap();
+        let mut retries = 0;
+        let mut remaining = list_of_additions.clone();
+        let mut items = Vec::new();
+        loop {
+            let err = match cal_remote.get_items_by_url(&remaining).await {
+                Ok(fetched) => {
+                    items.extend(fetched);
+                    remaining.clear();
+                    break;
+                },
+                Err(err) => err,
+            };
+            let err = match err.downcast::<PartialFetchError>() {
+                Ok(partial) => {
+                    let partial = *partial;
+                    remaining.retain(|url| partial.fetched.iter().all(|item| item.url() != url));
+                    items.extend(partial.fetched.into_iter().map(Some));
+                    partial.cause
+                },
+                Err(err) => err,
+            };
+            if retries >= max_retries {
+                progress.warn(&format!("Unable to get the batch of {} {:?}: {}. Skipping them.", batch_type, remaining, err));
+                break;
+            }
+            retries += 1;
+            let delay = retry_delay * 2u32.saturating_pow(retries - 1);
+            progress.debug(&format!("Unable to get {} items of the batch of {}: {}. Retrying them in {:?} ({}/{})", remaining.len(), batch_type, err, delay, retries, max_retries));
+            tokio::time::sleep(delay).await;
+        }
+
+        // Items the server now stores another kind of component for have been left out of the replies.
+        // Their former versions are removed, just like items that have been deleted on the server
+        for url in &list_of_additions {
+            let component = match cal_remote.take_unsupported_component(url) {
+                None => continue,
+                Some(component) => component,
+            };
+            match cal_local.get_item_by_url(url).await.cloned() {
+                None => progress.debug(&format!("Ignoring remote item {}, since it contains no component its calendar supports", url)),
+                Some(previous) => {
+                    let change = ComponentTypeChange { calendar: cal_local.url().clone(), item: url.clone(), previous, component };
+                    progress.add_component_type_change(change);
+                    if let Err(err) = cal_local.immediately_delete_item(url).await {
+                        progress.error(&format!("Unable to delete local item {}: {}", url, err));
                     }
-                }
+                },
+            }
+        }
 
-                // Notifying every item at the same time would not make sense. Let's notify only one of them
-                let one_item_name = match list_of_additions.get(0) {
-                    Some(url) => Self::item_name(&cal_local, &url).await,
-                    None => String::from("<unable to get the name of the first batched item>"),
-                };
-                progress.increment_counter(list_of_additions.len());
-                progress.feedback(SyncEvent::InProgress{
-                    calendar: cal_name.to_string(),
-                    items_done_already: progress.counter(),
-                    details: one_item_name,
-                });
-            },
+        // Items can also be changed or deleted on the server between the time they have been listed and the time they are fetched
+        let received: HashSet<&Url> = items.iter().flatten().map(|item| item.url()).collect();
+        let mut vanished = Vec::new();
+        for url in list_of_additions.iter().filter(|url| !received.contains(url) && !remaining.contains(url)) {
+            if cal_remote.take_unstable_item(url) {
+                progress.add_conflict(ConflictRecord { calendar: cal_local.url().clone(), item: url.clone(), kind: ConflictKind::ChangedDuringSync },
+                    "it has kept changing on the server while it was being fetched. It will be fetched again at the next sync");
+                // The sync is not complete as long as this item is not fetched
+                progress.warn(&format!("Item {} could not be fetched in a stable state", url));
+            } else if cal_local.get_item_by_url(url).await.is_some() {
+                vanished.push(url.clone());
+            }
+        }
+        for url in vanished {
+            // This is handled just like an item that was already deleted from the server when it was listed
+            if matches!(cal_local.get_item_by_url(&url).await.map(|item| item.sync_status()), Some(SyncStatus::LocallyModified(_))) {
+                progress.add_conflict(ConflictRecord { calendar: cal_local.url().clone(), item: url.clone(), kind: ConflictKind::ModifiedLocallyDeletedRemotely },
+                    "it has been deleted from the server while it was being fetched, and locally modified. Deleting the local copy");
+            }
+            progress.debug(&format!("*   {} has been deleted from the server while it was being fetched", url));
+            if let Err(err) = cal_local.immediately_delete_item(&url).await {
+                progress.warn(&format!("Unable to delete local item {}: {}", url, err));
+            }
         }
+
+        if items.is_empty() {
+            return;
+        }
+        for item in items {
+            match item {
+                // Items that have vanished from the remote end have been handled above
+                None => continue,
+                Some(new_item) => {
+                    let local_update_result = match batch_type {
+                        BatchDownloadType::RemoteAdditions => cal_local.add_item(new_item.clone()).await,
+                        BatchDownloadType::RemoteChanges => cal_local.update_item(new_item.clone()).await,
+                    };
+                    if let Err(err) = local_update_result {
+                        progress.error(&format!("Not able to add item {} to local calendar: {}", new_item.url(), err));
+                    }
+                },
+            }
+        }
+
+        // Notifying every item at the same time would not make sense. Let's notify only one of them
+        let one_item_name = match list_of_additions.first() {
+            Some(url) => Self::item_name(cal_local, url).await,
+            None => String::from("<unable to get the name of the first batched item>"),
+        };
+        progress.increment_counter(list_of_additions.len() - remaining.len());
+        progress.feedback(SyncEvent::InProgress{
+            calendar: cal_name.to_string(),
+            items_done_already: progress.counter(),
+            details: one_item_name,
+        });
     }
 }
 
 
+/// The entries of a map, sorted by URL, so that syncs always handle calendars and items in the same order
+fn sorted_by_url<V>(map: HashMap<Url, V>) -> Vec<(Url, V)> {
+    let mut entries: Vec<(Url, V)> = map.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+}
+
+/// The items that are planned for several writes to the server (which would be a bug)
+fn written_twice<'a>(planned: impl IntoIterator<Item = &'a Url>) -> BTreeSet<Url> {
+    let mut seen = BTreeSet::new();
+    planned.into_iter().filter(|url| !seen.insert(*url)).cloned().collect()
+}
+
+/// Whether the local version of an item that has been modified in both sources is more recent than the remote one.
+///
+/// This is only known for servers that do not provide a strong ETag, but provide a `DAV:getlastmodified`:
+/// the local modification date is compared to the remote one.
+fn is_local_version_newer(local_item: &Item, remote_tag: &VersionTag) -> bool {
+    if remote_tag.is_strong_etag() {
+        return false;
+    }
+    match remote_tag.last_modified() {
+        None => false,
+        Some(remote_modified) => local_item.last_modified() > remote_modified,
+    }
+}
+
+/// Whether the server has refused an upload because of the item itself, i.e. retrying it as-is would fail again
+fn is_refusal_of_item(err: &(dyn Error + 'static)) -> bool {
+    match crate::error::downcast_ref::<HttpError>(err) {
+        None => false,
+        Some(err) => err.status.is_client_error() && !matches!(err.status.as_u16(), 401 | 408 | 429) && !err.is_locked(),
+    }
+}
+
+/// How long the server has asked to wait before sending requests again, in case it has throttled a request
+fn throttling_of(err: &(dyn Error + 'static)) -> Option<std::time::Duration> {
+    crate::error::downcast_ref::<Throttled>(err).map(|throttled| throttled.retry_after)
+}
+
+/// Whether the server has refused to change an item because it has changed since its version tag has been read, rather than overwriting this change
+fn is_precondition_failure(err: &(dyn Error + 'static)) -> bool {
+    crate::error::downcast_ref::<PreconditionFailedError>(err).is_some()
+}
+
+/// Whether the server has refused to change an item because it is locked, i.e. retrying it later is likely to succeed
+fn is_locked(err: &(dyn Error + 'static)) -> bool {
+    crate::error::downcast_ref::<HttpError>(err).is_some_and(|err| err.is_locked())
+}
+
+/// The URL of the item the server already has, in case an upload has been refused because of a UID conflict
+fn uid_conflict_url(err: &(dyn Error + 'static), cal_url: &Url) -> Option<Url> {
+    match &crate::error::downcast_ref::<HttpError>(err)?.detail {
+        Some(DavErrorDetail::NoUidConflict { existing: Some(existing) }) => cal_url.join(existing).ok(),
+        _ => None,
+    }
+}
+
 async fn get_or_insert_counterpart_calendar<H, N, I>(haystack_descr: &str, haystack: &mut H, cal_url: &Url, needle: Arc<Mutex<N>>)
     -> Result<Arc<Mutex<I>>, Box<dyn Error>>
 where
@@ -500,16 +1714,14 @@ where
         log::debug!("Adding a {} calendar {}", haystack_descr, cal_url);
         let src = needle.lock().unwrap();
         let name = src.name().to_string();
-        let supported_comps = src.supported_components();
+        let components = ComponentSet::of(&*src);
         let color = src.color();
-        if let Err(err) = haystack.create_calendar(
+        haystack.create_calendar_with_components(
             cal_url.clone(),
             name,
-            supported_comps,
+            components,
             color.cloned(),
-        ).await{
-            return Err(err);
-        }
+        ).await?;
     }
 }
 
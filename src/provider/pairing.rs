@@ -0,0 +1,129 @@
+//! Checking how the calendars of both sources of a provider are paired, before they are synced (see [`Provider::validate`])
+
+use std::error::Error;
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use super::Provider;
+
+/// How the calendars of the local and the remote sources are paired (see [`Provider::validate`])
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PairingReport {
+    /// The calendars both sources have (at the same URL). These will be synced together
+    pub paired: Vec<Url>,
+    /// The calendars that are only in the local source. A sync creates them on the remote source
+    pub only_local: Vec<Url>,
+    /// The calendars that are only in the remote source. A sync creates them in the local source
+    pub only_remote: Vec<Url>,
+    /// Calendars that are only in one source, but that look like the same calendar (they have the same name), e.g. because the server has moved
+    pub suspicious: Vec<SuspiciousPairing>,
+}
+
+impl PairingReport {
+    /// Whether every calendar is either paired, or looks like it should not be
+    pub fn is_clean(&self) -> bool {
+        self.suspicious.is_empty()
+    }
+}
+
+/// A local calendar and a remote calendar that have different URLs, but the same name
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuspiciousPairing {
+    pub name: String,
+    pub local: Url,
+    pub remote: Url,
+    /// The bases of the URLs that differ, that can be given to [`Cache::rebase_ids`](crate::cache::Cache::rebase_ids) so that both calendars are paired
+    pub rebase: (Url, Url),
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Compare the calendars of both sources, and tell how a sync would pair them. This does not modify anything.
+    ///
+    /// This is e.g. useful for an app to ask the user for a confirmation before a first sync, that may create many calendars in case the server has moved.
+    /// See also [`Provider::set_validate_before_first_sync`]
+    pub async fn validate(&self) -> Result<PairingReport, Box<dyn Error>> {
+        let local: HashMap<Url, String> = self.local.list_calendars().await?
+            .into_iter()
+            .map(|(url, cal)| { let name = cal.lock().unwrap().name().to_string(); (url, name) })
+            .collect();
+        let remote: HashMap<Url, String> = self.remote.list_calendars().await?
+            .into_iter()
+            .map(|(url, cal)| { let name = cal.lock().unwrap().name().to_string(); (url, name) })
+            .collect();
+
+        let mut report = PairingReport::default();
+        for url in local.keys() {
+            match remote.contains_key(url) {
+                true => report.paired.push(url.clone()),
+                false => report.only_local.push(url.clone()),
+            }
+        }
+        report.only_remote = remote.keys().filter(|url| !local.contains_key(url)).cloned().collect();
+        report.paired.sort();
+        report.only_local.sort();
+        report.only_remote.sort();
+
+        for local_url in &report.only_local {
+            let name = local[local_url].trim();
+            for remote_url in report.only_remote.iter().filter(|url| remote[*url].trim() == name) {
+                report.suspicious.push(SuspiciousPairing {
+                    name: name.to_string(),
+                    local: local_url.clone(),
+                    remote: remote_url.clone(),
+                    rebase: rebase_bases(local_url, remote_url),
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// The parts of two URLs that differ, once their common trailing path segments are removed (e.g. `https://old.com/` and `https://new.com/remote.php/` for two calendars at `.../dav/calendars/work/`)
+fn rebase_bases(old: &Url, new: &Url) -> (Url, Url) {
+    let segments = |url: &Url| -> Vec<String> { url.path().split('/').map(String::from).collect() };
+    let (old_segments, new_segments) = (segments(old), segments(new));
+    let common = old_segments.iter().rev().zip(new_segments.iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let base_of = |url: &Url, segments: &[String]| -> Url {
+        // Keep at least the root of the path
+        let kept = std::cmp::max(segments.len().saturating_sub(common), 1);
+        let mut base = url.clone();
+        base.set_path(&format!("{}/", segments[..kept].join("/")));
+        base.set_query(None);
+        base.set_fragment(None);
+        base
+    };
+    (base_of(old, &old_segments), base_of(new, &new_segments))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase_bases() {
+        let url = |s: &str| -> Url { s.parse().unwrap() };
+        assert_eq!(
+            rebase_bases(&url("https://old.com/dav/calendars/john/work/"), &url("https://new.com/remote.php/dav/calendars/john/work/")),
+            (url("https://old.com/"), url("https://new.com/remote.php/")),
+        );
+        assert_eq!(
+            rebase_bases(&url("https://cloud.com/calendars/john/work/"), &url("https://cloud.com/calendars/john.doe/work/")),
+            (url("https://cloud.com/calendars/john/"), url("https://cloud.com/calendars/john.doe/")),
+        );
+        assert_eq!(
+            rebase_bases(&url("https://old.com/work/"), &url("https://new.com/personal/")),
+            (url("https://old.com/work/"), url("https://new.com/personal/")),
+        );
+    }
+}
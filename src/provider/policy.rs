@@ -0,0 +1,119 @@
+//! Per-calendar sync policies, that override the defaults of a [`Provider`](crate::provider::Provider) (see [`crate::provider::Provider::set_calendar_sync_policy`])
+
+use serde::{Deserialize, Serialize};
+
+use crate::Item;
+
+/// Which way changes flow between the local and the remote copies of a calendar
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncDirection {
+    /// Changes are pulled from the server, and local changes are pushed to it
+    #[default]
+    Both,
+    /// Changes are only pulled from the server. Local changes are kept locally, and conflicts are always won by the server
+    PullOnly,
+    /// Local changes are only pushed to the server. Remote changes are ignored, and conflicts are always won by the local version
+    PushOnly,
+}
+
+impl SyncDirection {
+    pub fn pulls(&self) -> bool {
+        matches!(self, Self::Both | Self::PullOnly)
+    }
+
+    pub fn pushes(&self) -> bool {
+        matches!(self, Self::Both | Self::PushOnly)
+    }
+}
+
+/// Which version wins when an item has been modified both locally and remotely since the last sync
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPreference {
+    /// The remote version overwrites the local one
+    PreferRemote,
+    /// The local version overwrites the remote one
+    PreferLocal,
+}
+
+/// Which local items are pushed to the server. Items that do not match are kept locally, and are not uploaded until they do
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncFilter {
+    /// Whether completed tasks are not pushed
+    pub skip_completed: bool,
+    /// Only the tasks that have one of these categories are pushed. Every task is pushed when this is `None`
+    pub categories: Option<Vec<String>>,
+}
+
+impl SyncFilter {
+    /// Whether this item should be pushed
+    pub fn matches(&self, item: &Item) -> bool {
+        let task = match item {
+            Item::Task(task) => task,
+            Item::Event(_) => return true,
+        };
+        if self.skip_completed && task.completed() {
+            return false;
+        }
+        match &self.categories {
+            None => true,
+            Some(categories) => task.categories().iter().any(|category| categories.contains(category)),
+        }
+    }
+}
+
+/// How a given calendar is synced.
+///
+/// Calendars that have no policy of their own use the default one, i.e. the defaults of the [`Provider`](crate::provider::Provider)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CalendarSyncPolicy {
+    pub direction: SyncDirection,
+    /// How conflicts are resolved. When this is `None`, they are resolved by the resolver of the provider (see [`crate::provider::Provider::set_conflict_resolver`])
+    pub conflict_resolution: Option<ConflictPreference>,
+    pub filter: SyncFilter,
+    /// How many items are downloaded in a single request. The default is used when this is `None`
+    pub batch_size: Option<usize>,
+    /// Whether uploaded items are fetched back and compared with what has been sent. The setting of the provider is used when this is `None` (see [`crate::provider::Provider::set_verify_uploads`])
+    pub verify_uploads: Option<bool>,
+}
+
+impl CalendarSyncPolicy {
+    /// How conflicts are actually resolved, given the direction of the sync (`None` meaning they are left to the resolver of the provider)
+    pub fn effective_conflict_resolution(&self) -> Option<ConflictPreference> {
+        match self.direction {
+            SyncDirection::PullOnly => Some(ConflictPreference::PreferRemote),
+            SyncDirection::PushOnly => Some(ConflictPreference::PreferLocal),
+            SyncDirection::Both => self.conflict_resolution,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Task;
+
+    #[test]
+    fn test_sync_filter() {
+        let cal_url: url::Url = "https://some.server/cal/".parse().unwrap();
+        let mut task = Task::new("Report".to_string(), false, &cal_url);
+        task.set_categories(vec!["work".to_string()]);
+        let mut completed = Task::new("Old report".to_string(), true, &cal_url);
+        completed.set_categories(vec!["work".to_string()]);
+        let (task, completed) = (Item::Task(task), Item::Task(completed));
+
+        assert!(SyncFilter::default().matches(&task));
+        assert!(SyncFilter::default().matches(&completed));
+
+        let filter = SyncFilter { skip_completed: true, categories: Some(vec!["work".to_string()]) };
+        assert!(filter.matches(&task));
+        assert!(!filter.matches(&completed));
+        let filter = SyncFilter { skip_completed: false, categories: Some(vec!["personal".to_string()]) };
+        assert!(!filter.matches(&task));
+
+        let policy = CalendarSyncPolicy { direction: SyncDirection::PullOnly, conflict_resolution: Some(ConflictPreference::PreferLocal), ..CalendarSyncPolicy::default() };
+        assert_eq!(policy.effective_conflict_resolution(), Some(ConflictPreference::PreferRemote));
+        assert_eq!(CalendarSyncPolicy::default().effective_conflict_resolution(), None);
+    }
+}
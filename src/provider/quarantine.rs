@@ -0,0 +1,65 @@
+//! Items whose uploads keep failing, and that are retried less and less often (see [`crate::cache::Cache::quarantined_items`])
+//!
+//! After `n` consecutive failures, an item is retried after 2<sup>n-1</sup> syncs (i.e. at the next sync after its first failure, then after 2 syncs, 4 syncs...), up to [`crate::config::UPLOAD_RETRY_SPACING_CAP`] syncs.
+//! Only the syncs that would have uploaded the item are counted, and only the failures that are about the item itself (i.e. the server has refused it, e.g. with `403 Forbidden`): errors of the network or of the server do not make items back off.
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Why the uploads of an item keep failing
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadFailure {
+    /// How many uploads of this item have failed in a row
+    pub consecutive_failures: u32,
+    /// The error of the last failed upload
+    pub last_error: String,
+    pub last_failed_at: DateTime<Utc>,
+    /// How many syncs will skip this item before it is retried (0 meaning it is retried at the next sync)
+    pub next_retry_in: u32,
+}
+
+impl UploadFailure {
+    /// Record a failed upload, given the previous failures of this item (if any)
+    pub(crate) fn record(previous: Option<UploadFailure>, error: String) -> Self {
+        let cap = *crate::config::UPLOAD_RETRY_SPACING_CAP.lock().unwrap();
+        Self::record_with_cap(previous, error, cap)
+    }
+
+    fn record_with_cap(previous: Option<UploadFailure>, error: String, cap: u32) -> Self {
+        let consecutive_failures = previous.map(|previous| previous.consecutive_failures).unwrap_or(0).saturating_add(1);
+        let cap = cap.max(1);
+        let spacing = 2u32.checked_pow(consecutive_failures - 1).unwrap_or(u32::MAX).min(cap);
+        Self {
+            consecutive_failures,
+            last_error: error,
+            last_failed_at: Utc::now(),
+            next_retry_in: spacing - 1,
+        }
+    }
+
+    /// Whether the next sync skips this item
+    pub fn is_backing_off(&self) -> bool {
+        self.next_retry_in > 0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_spacing() {
+        let mut failure = None;
+        let mut spacings = Vec::new();
+        for _ in 0..6 {
+            let recorded = UploadFailure::record_with_cap(failure, "403 Forbidden".to_string(), 8);
+            spacings.push(recorded.next_retry_in + 1);
+            failure = Some(recorded);
+        }
+        assert_eq!(spacings, vec![1, 2, 4, 8, 8, 8]);
+        let failure = failure.unwrap();
+        assert_eq!(failure.consecutive_failures, 6);
+        assert!(failure.is_backing_off());
+    }
+}
@@ -0,0 +1,90 @@
+//! Throwing away the local copy of a calendar, and downloading it again (see [`Provider::reset_calendar`])
+
+use std::error::Error;
+
+use url::Url;
+
+use crate::calendar::history::ChangeSource;
+use crate::item::SyncStatus;
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use super::sync_progress::{SyncProgress, SyncResult};
+use super::Provider;
+
+/// What [`Provider::reset_calendar`] has done
+#[derive(Clone, Debug, Default)]
+pub struct ResetReport {
+    /// The local changes (additions and modifications) that have not been pushed yet, and that have been put aside (see [`CompleteCalendar::hold_item`]) to be applied again by hand
+    pub held: Vec<Url>,
+    /// The local changes that have been thrown away: additions and modifications in case they have not been kept, and deletions that have not been pushed yet (these items are downloaded again)
+    pub discarded: Vec<Url>,
+    /// How many synced items have been removed, to be downloaded again
+    pub removed: usize,
+    /// The outcome of the sync that has downloaded the calendar again
+    pub sync_result: SyncResult,
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Throw away the local copy of the calendar at `cal_url`, and download it again from the server, e.g. because its sync state looks confused. The other calendars are left untouched.
+    ///
+    /// Every item of the local calendar is removed, including the ones that are marked for deletion, and so is what the calendar knows about past syncs (e.g. its last synced ctag, and its upload failures).
+    /// In case `keep_unsynced` is set, the local additions and modifications that have not been pushed yet are put aside first (see [`crate::calendar::cached_calendar::CachedCalendar::held_items`]), so that they can be applied again by hand.
+    /// The calendar is then synced, which downloads every item of its remote counterpart.
+    // Just like during a sync, calendars are locked while their (async) methods are called
+    #[allow(clippy::await_holding_lock)]
+    pub async fn reset_calendar(&mut self, cal_url: &Url, keep_unsynced: bool) -> Result<ResetReport, Box<dyn Error>> {
+        let cal_local = self.local.get_calendar(cal_url).await.ok_or_else(|| format!("No local calendar {}", cal_url))?;
+        if self.remote.get_calendar(cal_url).await.is_none() {
+            return Err(format!("No remote calendar {}, there would be nothing to download", cal_url).into());
+        }
+
+        let mut report = ResetReport::default();
+        {
+            let mut cal_local = cal_local.lock().unwrap();
+            if cal_local.is_local_only() {
+                return Err(format!("Calendar {} is kept on this device only, there is nothing to download", cal_url).into());
+            }
+            let mut items: Vec<_> = cal_local.get_items().await?.into_values().cloned().collect();
+            items.sort_by(|l, r| l.url().cmp(r.url()));
+
+            // Local changes are put aside before anything is removed, so that a calendar that cannot hold them is left untouched
+            let mut urls = Vec::with_capacity(items.len());
+            for item in items {
+                let url = item.url().clone();
+                match item.sync_status() {
+                    SyncStatus::Synced(_) => report.removed += 1,
+                    SyncStatus::NotSynced | SyncStatus::LocallyModified(_) if keep_unsynced => {
+                        cal_local.hold_item(item)?;
+                        report.held.push(url.clone());
+                    },
+                    _ => report.discarded.push(url.clone()),
+                }
+                urls.push(url);
+            }
+
+            cal_local.set_change_source(ChangeSource::Sync);
+            let result = async {
+                for url in &urls {
+                    cal_local.immediately_delete_item(url).await?;
+                    cal_local.set_upload_failure(url, None);
+                }
+                Ok::<(), Box<dyn Error>>(())
+            }.await;
+            cal_local.set_change_source(ChangeSource::Local);
+            result?;
+            cal_local.set_last_synced_ctag(None);
+            cal_local.set_last_sync_token(None);
+        }
+        self.local.checkpoint()?;
+
+        let mut progress = SyncProgress::new();
+        self.run_sync(&mut progress, Some(cal_url)).await;
+        report.sync_result = self.last_sync_result.clone().unwrap_or_default();
+        Ok(report)
+    }
+}
@@ -1,6 +1,21 @@
 //! Utilities to track the progression of a sync
 
+use std::collections::BTreeMap;
 use std::fmt::{Display, Error, Formatter};
+use std::time::Duration;
+
+use url::Url;
+
+use crate::transfer_stats::{TransferCounter, TransferStats};
+use crate::provider::date_limits::DateLimitViolation;
+use crate::dav_error::DavErrorDetail;
+use crate::error_code::ErrorCode;
+use crate::provider::conflict::ConflictKind;
+use crate::provider::policy::CalendarSyncPolicy;
+use crate::provider::divergence::DivergenceWarning;
+use crate::provider::fidelity::FidelityFinding;
+use crate::provider::checkpoint::{CancellationHandle, CheckpointObserver, SyncCheckpoint};
+use crate::Item;
 
 /// An event that happens during a sync
 #[derive(Clone, Debug)]
@@ -50,18 +65,349 @@ pub fn feedback_channel() -> (FeedbackSender, FeedbackReceiver) {
 
 
 
+/// The outcome of a sync (see [`crate::provider::Provider::last_sync_result`])
+#[derive(Clone, Debug, Default)]
+pub struct SyncResult {
+    /// Whether the sync happened without any error
+    pub success: bool,
+    /// Whether the sync stopped early, because it exceeded its transfer budget (see [`crate::provider::Provider::set_transfer_budget`]).
+    /// What has not been synced yet will be synced by the next sync
+    pub budget_exhausted: bool,
+    /// Whether the sync has been cancelled (see [`crate::provider::Provider::cancellation_handle`]).
+    /// It has stopped at a checkpoint, and what has not been synced yet will be synced by the next sync
+    pub cancelled: bool,
+    /// What has been exchanged with the remote source during this sync, in case it is able to account for it
+    pub transfers: Option<TransferStats>,
+    /// The calendar an upload has been refused for, because the account is over its storage quota.
+    /// In this case, no further upload has been attempted during this sync (but remote changes have still been pulled)
+    pub quota_exceeded: Option<Url>,
+    /// Whether the remote source could not be reached at all (see [`crate::provider::Provider::sync_health`]). Nothing has been synced then, and the local source is left as is
+    pub remote_unreachable: bool,
+    /// How long the server has asked to wait before sending requests again, in case it has throttled this sync (see [`crate::client::Throttled`]).
+    /// In this case, the sync has stopped early, and the next one should not be scheduled before this delay
+    pub throttled: Option<Duration>,
+    /// The items whose dates were out of the range their calendar accepts, and what has been done about them (see [`crate::provider::Provider::set_date_limits_policy`])
+    pub date_limit_violations: Vec<DateLimitViolation>,
+    /// The items the server has refused because of their content (see [`DavErrorDetail::is_invalid_item`]). They are kept locally, and will be uploaded again at the next sync
+    pub rejected_items: Vec<RejectedItem>,
+    /// The conflicts this sync has run into, in the order they have been met
+    pub conflicts: Vec<ConflictRecord>,
+    /// The items that have been replaced on the server by another kind of item (e.g. a task that has become an event). They have been removed locally
+    pub component_type_changes: Vec<ComponentTypeChange>,
+    /// What has happened to every calendar this sync has handled, sorted by URL
+    pub calendars: BTreeMap<Url, CalendarSyncReport>,
+    /// The calendars whose sync has been paused, because it would have deleted or overwritten too many of their items (see [`crate::provider::Provider::set_divergence_threshold`])
+    pub divergence_warnings: Vec<DivergenceWarning>,
+    /// The items whose upload or deletion has been refused because they are locked on the server. They are kept as they are locally, and will be retried at the next sync
+    pub locked_items: Vec<LockedItem>,
+    /// The uploaded items the server has not stored as they have been sent, in case uploads are verified (see [`crate::provider::Provider::set_verify_uploads`]).
+    /// They are left modified locally, and will be uploaded again at the next sync
+    pub fidelity_findings: Vec<FidelityFinding>,
+}
+
+/// What has happened to a calendar during a sync
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalendarSyncReport {
+    /// The policy this calendar has been synced with
+    pub policy: CalendarSyncPolicy,
+    /// Whether this policy has been set for this calendar (see [`crate::provider::Provider::set_calendar_sync_policy`]), rather than being the default one
+    pub is_custom_policy: bool,
+}
+
+/// An item the server has refused to store, and why
+#[derive(Clone, Debug, PartialEq)]
+pub struct RejectedItem {
+    pub calendar: Url,
+    pub item: Url,
+    pub reason: DavErrorDetail,
+    /// A human-readable description of the reason, in case the server gave one
+    pub message: Option<String>,
+}
+
+impl RejectedItem {
+    /// The stable identifier of the reason (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        self.reason.code()
+    }
+}
+
+/// An item whose upload or deletion the server has refused, because it is locked (e.g. by another client that is editing it)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockedItem {
+    pub calendar: Url,
+    pub item: Url,
+    /// Whether it was about to be deleted (rather than uploaded)
+    pub deletion: bool,
+}
+
+impl LockedItem {
+    /// The stable identifier of this condition (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        crate::error_code::LOCKED
+    }
+}
+
+/// A conflict a sync has run into
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConflictRecord {
+    pub calendar: Url,
+    pub item: Url,
+    pub kind: ConflictKind,
+}
+
+impl ConflictRecord {
+    /// The stable identifier of the kind of this conflict (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        self.kind.code()
+    }
+}
+
+/// An item the server now stores another kind of component for (e.g. a `VEVENT` at the URL of a task), as it has been found during a sync
+///
+/// This is handled as if the former item had been deleted on the server: it is removed from the local calendar, and kept here.
+/// The new item is not stored, in case its calendar does not support its component.
+#[derive(Clone, Debug)]
+pub struct ComponentTypeChange {
+    pub calendar: Url,
+    pub item: Url,
+    /// The local copy of the former item, as it was before it has been removed
+    pub previous: Item,
+    /// The component the server now stores (e.g. `VEVENT`), in case it is known
+    pub component: Option<String>,
+}
+
+impl ComponentTypeChange {
+    /// The stable identifier of this anomaly (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        crate::error_code::COMPONENT_TYPE_CHANGED
+    }
+}
+
+
+/// A soft limit of the bytes a sync may exchange
+struct TransferBudget {
+    max_bytes: u64,
+    counter: TransferCounter,
+    at_start: TransferStats,
+}
+
 /// A structure that tracks the progression and the errors that happen during a sync
 pub struct SyncProgress {
     n_errors: u32,
     feedback_channel: Option<FeedbackSender>,
     counter: usize,
+    budget: Option<TransferBudget>,
+    budget_exhausted: bool,
+    quota_exceeded: Option<Url>,
+    remote_unreachable: bool,
+    throttled: Option<Duration>,
+    cancellation: Option<CancellationHandle>,
+    checkpoint_observer: Option<CheckpointObserver>,
+    cancelled: bool,
+    date_limit_violations: Vec<DateLimitViolation>,
+    rejected_items: Vec<RejectedItem>,
+    conflicts: Vec<ConflictRecord>,
+    component_type_changes: Vec<ComponentTypeChange>,
+    calendars: BTreeMap<Url, CalendarSyncReport>,
+    divergence_warnings: Vec<DivergenceWarning>,
+    locked_items: Vec<LockedItem>,
+    fidelity_findings: Vec<FidelityFinding>,
 }
 impl SyncProgress {
     pub fn new() -> Self {
-        Self { n_errors: 0, feedback_channel: None, counter: 0 }
+        Self { n_errors: 0, feedback_channel: None, counter: 0, budget: None, budget_exhausted: false, quota_exceeded: None, remote_unreachable: false, throttled: None, cancellation: None, checkpoint_observer: None, cancelled: false, date_limit_violations: Vec::new(), rejected_items: Vec::new(), conflicts: Vec::new(), component_type_changes: Vec::new(), calendars: BTreeMap::new(), divergence_warnings: Vec::new(), locked_items: Vec::new(), fidelity_findings: Vec::new() }
     }
     pub fn new_with_feedback_channel(channel: FeedbackSender) -> Self {
-        Self { n_errors: 0, feedback_channel: Some(channel), counter: 0 }
+        Self { n_errors: 0, feedback_channel: Some(channel), counter: 0, budget: None, budget_exhausted: false, quota_exceeded: None, remote_unreachable: false, throttled: None, cancellation: None, checkpoint_observer: None, cancelled: false, date_limit_violations: Vec::new(), rejected_items: Vec::new(), conflicts: Vec::new(), component_type_changes: Vec::new(), calendars: BTreeMap::new(), divergence_warnings: Vec::new(), locked_items: Vec::new(), fidelity_findings: Vec::new() }
+    }
+
+    /// Limit the bytes that can be exchanged from now on, as reported by a transfer `counter`.
+    ///
+    /// This limit is not enforced by itself, see [`Self::check_budget`]
+    pub fn set_transfer_budget(&mut self, max_bytes: u64, counter: TransferCounter) {
+        let at_start = counter.lock().unwrap().clone();
+        self.budget = Some(TransferBudget { max_bytes, counter, at_start });
+    }
+
+    /// Returns whether the transfer budget has been exceeded.
+    /// Once it has, this always returns `true`, so that the sync stops at the next opportunity
+    pub fn check_budget(&mut self) -> bool {
+        if self.budget_exhausted {
+            return true;
+        }
+        if let Some(budget) = &self.budget {
+            let transferred = budget.counter.lock().unwrap().since(&budget.at_start).total().total_bytes();
+            if transferred > budget.max_bytes {
+                log::info!("Transfer budget exhausted ({} bytes exchanged, {} allowed). Stopping the sync here", transferred, budget.max_bytes);
+                self.budget_exhausted = true;
+            }
+        }
+        self.budget_exhausted
+    }
+
+    /// Returns whether [`Self::check_budget`] has found the budget to be exceeded
+    pub fn is_budget_exhausted(&self) -> bool {
+        self.budget_exhausted
+    }
+
+    /// Record that the server has refused an upload to `calendar` because the account is over its storage quota.
+    /// No further upload should be attempted during this sync
+    pub fn set_quota_exceeded(&mut self, calendar: &Url) {
+        if self.quota_exceeded.is_none() {
+            self.error(&format!("The storage quota is exceeded (when uploading to {}). Local changes will not be uploaded until some space is freed", calendar));
+            self.quota_exceeded = Some(calendar.clone());
+        }
+    }
+
+    /// Returns the calendar an upload has been refused for because of the storage quota, if any (see [`Self::set_quota_exceeded`])
+    pub fn quota_exceeded(&self) -> Option<&Url> {
+        self.quota_exceeded.as_ref()
+    }
+
+    /// Record that the remote source could not be reached at all
+    pub fn set_remote_unreachable(&mut self) {
+        self.remote_unreachable = true;
+    }
+
+    /// Whether the remote source could not be reached at all (see [`Self::set_remote_unreachable`])
+    pub fn is_remote_unreachable(&self) -> bool {
+        self.remote_unreachable
+    }
+
+    /// Record that the server has throttled this sync, and asked to wait `retry_after` before sending requests again. The sync stops before the next calendar.
+    /// This counts as an error
+    pub fn set_throttled(&mut self, retry_after: Duration) {
+        self.error(&format!("The server has asked to wait {}s before sending requests again. Stopping this sync", retry_after.as_secs()));
+        self.throttled = Some(self.throttled.map_or(retry_after, |throttled| throttled.max(retry_after)));
+    }
+
+    /// How long the server has asked to wait before sending requests again, if it has throttled this sync (see [`Self::set_throttled`])
+    pub fn throttled(&self) -> Option<Duration> {
+        self.throttled
+    }
+
+    /// Let the sync be cancelled at its checkpoints, and tell `observer` about every checkpoint (see [`Self::reach_checkpoint`])
+    pub(crate) fn set_cancellation(&mut self, handle: CancellationHandle, observer: Option<CheckpointObserver>) {
+        self.cancellation = Some(handle);
+        self.checkpoint_observer = observer;
+    }
+
+    /// Record that the sync of `calendar` has reached a checkpoint.
+    /// Returns whether the sync has been cancelled, in which case it should stop here
+    pub fn reach_checkpoint(&mut self, calendar: &Url, checkpoint: SyncCheckpoint) -> bool {
+        self.trace(&format!("Checkpoint {:?} reached for calendar {}", checkpoint, calendar));
+        if let Some(observer) = &self.checkpoint_observer {
+            observer.notify(calendar, checkpoint);
+        }
+        if !self.cancelled && self.cancellation.as_ref().is_some_and(|handle| handle.is_cancelled()) {
+            log::info!("The sync has been cancelled. Stopping at checkpoint {:?} of calendar {}", checkpoint, calendar);
+            self.cancelled = true;
+        }
+        self.cancelled
+    }
+
+    /// Whether the sync has stopped at a checkpoint because it has been cancelled (see [`Self::reach_checkpoint`])
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Record that an item had dates out of the range its calendar accepts.
+    /// This does not count as an error: the item has been handled as the [`DateLimitsPolicy`](crate::provider::DateLimitsPolicy) says
+    pub fn add_date_limit_violation(&mut self, violation: DateLimitViolation) {
+        log::warn!("Item {} has dates out of the range its calendar accepts ({}). Action: {:?}", violation.item, violation.properties.join(", "), violation.action);
+        self.date_limit_violations.push(violation);
+    }
+
+    /// Returns the items that had dates out of the range their calendar accepts (see [`Self::add_date_limit_violation`])
+    pub fn date_limit_violations(&self) -> &[DateLimitViolation] {
+        &self.date_limit_violations
+    }
+
+    /// Record that the server has refused an item because of its content.
+    /// This counts as an error
+    pub fn add_rejected_item(&mut self, rejected: RejectedItem) {
+        self.error(&format!("Item {} has been refused by the server ({}){} [{}]", rejected.item, rejected.reason,
+            rejected.message.as_ref().map(|message| format!(": {}", message)).unwrap_or_default(), rejected.code()));
+        self.rejected_items.push(rejected);
+    }
+
+    /// Returns the items the server has refused because of their content (see [`Self::add_rejected_item`])
+    pub fn rejected_items(&self) -> &[RejectedItem] {
+        &self.rejected_items
+    }
+
+    /// Record that a conflict has been met, and how it is handled (`details`).
+    /// This does not count as an error
+    pub fn add_conflict(&mut self, conflict: ConflictRecord, details: &str) {
+        log::info!("Conflict on item {} [{}]: {}", conflict.item, conflict.kind, details);
+        self.conflicts.push(conflict);
+    }
+
+    /// Returns the conflicts that have been met so far (see [`Self::add_conflict`])
+    pub fn conflicts(&self) -> &[ConflictRecord] {
+        &self.conflicts
+    }
+
+    /// Record that an item has been replaced on the server by another kind of item.
+    /// This does not count as an error
+    pub fn add_component_type_change(&mut self, change: ComponentTypeChange) {
+        log::warn!("Item {} (UID {}) has been replaced on the server by a {} [{}]. Removing it locally",
+            change.item, change.previous.uid(), change.component.as_deref().unwrap_or("resource with no known component"), change.code());
+        self.component_type_changes.push(change);
+    }
+
+    /// Returns the items that have been replaced on the server by another kind of item so far (see [`Self::add_component_type_change`])
+    pub fn component_type_changes(&self) -> &[ComponentTypeChange] {
+        &self.component_type_changes
+    }
+
+    /// Record that the sync of a calendar has been paused, because it would have deleted or overwritten too many of its items.
+    /// This counts as an error
+    pub fn add_divergence_warning(&mut self, warning: DivergenceWarning) {
+        self.error(&format!("Calendar {} has diverged from the server: {} of its {} items would be deleted, and {} overwritten. Pausing its sync [{}]",
+            warning.calendar, warning.planned.deletions(), warning.items, warning.planned.overwrites(), warning.code()));
+        self.divergence_warnings.push(warning);
+    }
+
+    /// Returns the calendars whose sync has been paused so far (see [`Self::add_divergence_warning`])
+    pub fn divergence_warnings(&self) -> &[DivergenceWarning] {
+        &self.divergence_warnings
+    }
+
+    /// Record that the server has refused to change an item, because it is locked.
+    /// This does not count as an error
+    pub fn add_locked_item(&mut self, locked: LockedItem) {
+        log::info!("Item {} is locked on the server, its {} will be retried at the next sync [{}]",
+            locked.item, if locked.deletion { "deletion" } else { "upload" }, locked.code());
+        self.locked_items.push(locked);
+    }
+
+    /// Returns the items that were locked on the server so far (see [`Self::add_locked_item`])
+    pub fn locked_items(&self) -> &[LockedItem] {
+        &self.locked_items
+    }
+
+    /// Record that the server has not stored an uploaded item as it has been sent.
+    /// This counts as an error
+    pub fn add_fidelity_finding(&mut self, finding: FidelityFinding) {
+        let differences: Vec<String> = finding.differences.iter().map(|difference| difference.to_string()).collect();
+        self.error(&format!("Item {} has been altered by the server [{}]: {}", finding.item, finding.code(), differences.join(", ")));
+        self.fidelity_findings.push(finding);
+    }
+
+    /// Returns the uploaded items the server has altered so far (see [`Self::add_fidelity_finding`])
+    pub fn fidelity_findings(&self) -> &[FidelityFinding] {
+        &self.fidelity_findings
+    }
+
+    /// Record the policy a calendar is synced with
+    pub fn set_calendar_policy(&mut self, calendar: &Url, policy: CalendarSyncPolicy, is_custom_policy: bool) {
+        self.calendars.insert(calendar.clone(), CalendarSyncReport { policy, is_custom_policy });
+    }
+
+    /// Returns what has happened to every calendar so far (see [`Self::set_calendar_policy`])
+    pub fn calendars(&self) -> &BTreeMap<Url, CalendarSyncReport> {
+        &self.calendars
     }
 
     /// Reset the user-info counter
@@ -82,7 +428,7 @@ impl SyncProgress {
 
 
     pub fn is_success(&self) -> bool {
-        self.n_errors == 0
+        self.n_errors == 0 && !self.cancelled
     }
 
     /// Log an error
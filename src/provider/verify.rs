@@ -0,0 +1,102 @@
+//! Checking whether the local source still agrees with the remote one about what has been synced (see [`Provider::verify`])
+
+use std::error::Error;
+use std::collections::{BTreeMap, HashSet};
+
+use url::Url;
+
+use crate::item::{SyncStatus, VersionTag};
+use crate::traits::{SourceAdapter, CompleteCalendar, DavCalendar};
+use super::Provider;
+
+/// The differences between what the local source thinks has been synced, and what the remote source actually has (see [`Provider::verify`])
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsistencyReport {
+    /// The calendars that have been checked, i.e. the ones both sources have (subscriptions excepted)
+    pub calendars: Vec<Url>,
+    /// The items that differ, sorted by calendar and item URLs
+    pub drifts: Vec<Drift>,
+}
+
+impl ConsistencyReport {
+    /// Whether both sources agree on every synced item
+    pub fn is_consistent(&self) -> bool {
+        self.drifts.is_empty()
+    }
+}
+
+/// An item the sources do not agree about
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drift {
+    pub calendar: Url,
+    pub item: Url,
+    pub kind: DriftKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DriftKind {
+    /// The local item is marked as synced, but the remote version has changed since
+    RemotelyChanged { local: VersionTag, remote: VersionTag },
+    /// The local item is marked as synced, but the remote source does not have it
+    MissingFromRemote,
+    /// The remote source has an item the local source does not know about
+    MissingLocally,
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: SourceAdapter<T>,
+    T: CompleteCalendar + Sync + Send,
+    R: SourceAdapter<U>,
+    U: DavCalendar + Sync + Send,
+{
+    /// Compare the version tags of the local items with the ones of the remote items, and report where they differ. This does not modify anything.
+    ///
+    /// Only the items that are marked as synced are compared: local changes that have not been pushed yet are not drifts.
+    /// The next sync normally resolves every drift, so this is mostly useful to diagnose why several clients of the same server disagree.
+    #[allow(clippy::await_holding_lock)]
+    pub async fn verify(&self) -> Result<ConsistencyReport, Box<dyn Error>> {
+        let cals_local = self.local.list_calendars().await?;
+        let cals_remote: BTreeMap<Url, _> = self.remote.list_calendars().await?.into_iter().collect();
+
+        let mut report = ConsistencyReport::default();
+        for (cal_url, cal_remote) in cals_remote {
+            let cal_local = match cals_local.get(&cal_url) {
+                None => continue,
+                Some(cal_local) => cal_local,
+            };
+            let cal_remote = cal_remote.lock().unwrap();
+            if cal_remote.subscription().is_some() {
+                continue;
+            }
+            let remote_tags: BTreeMap<Url, VersionTag> = cal_remote.get_item_version_tags().await?.into_iter().collect();
+            let cal_local = cal_local.lock().unwrap();
+            let local_items = cal_local.get_items().await?;
+
+            let mut drifts = Vec::new();
+            for (url, item) in &local_items {
+                let local_tag = match item.sync_status() {
+                    SyncStatus::Synced(tag) => tag,
+                    _ => continue,
+                };
+                match remote_tags.get(url) {
+                    None => drifts.push((url.clone(), DriftKind::MissingFromRemote)),
+                    Some(remote_tag) if remote_tag != local_tag => drifts.push((url.clone(), DriftKind::RemotelyChanged {
+                        local: local_tag.clone(),
+                        remote: remote_tag.clone(),
+                    })),
+                    Some(_) => (),
+                }
+            }
+            let local_urls: HashSet<&Url> = local_items.keys().collect();
+            drifts.extend(remote_tags.keys()
+                .filter(|url| !local_urls.contains(url))
+                .map(|url| (url.clone(), DriftKind::MissingLocally)));
+            drifts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            report.drifts.extend(drifts.into_iter().map(|(item, kind)| Drift { calendar: cal_url.clone(), item, kind }));
+            report.calendars.push(cal_url);
+        }
+        Ok(report)
+    }
+}
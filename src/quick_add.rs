@@ -0,0 +1,292 @@
+//! A parser for "quick add" text inputs, that turns e.g. `buy milk tomorrow 5pm #errands !high` into a structured task.
+//!
+//! See [`crate::Task::quick_add`]. The input is split into whitespace-separated words, and:
+//! * words that start with a `#` (e.g. `#errands`) are tags. They are turned into iCal `CATEGORIES`
+//! * `!high`, `!medium` and `!low`, or `!1` (highest) to `!9` (lowest) set the iCal `PRIORITY`. In case several are given, the last one wins
+//! * date and time expressions at the very end of the input (once tags and priorities are removed) set the due date. They can be
+//!   * a date: `today`, `tomorrow`, a weekday name (`monday`...`sunday`, that designates the next such day, today excluded) or an ISO date (`2021-03-21`)
+//!   * a time: `5pm`, `5:30pm`, `12am` or 24-hour `17:00`, optionally preceded by `at`
+//!   * a date and a time, in any order (`tomorrow at 5pm`, `5pm friday`)
+//! * every other word is part of the summary.
+//!
+//! A time without a date designates the next time it happens (today, or tomorrow if this time is already over). \
+//! A date without a time gives a date-only due date (iCal `VALUE=DATE`).
+//! In case the input is only made of a date expression, it is considered as the summary instead.
+//!
+//! Keywords are case-insensitive, and are always English words, regardless of the locale of the system, so that parsing is deterministic.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use ical::property::Property;
+use url::Url;
+
+use crate::datetime::CalDateTime;
+use crate::task::Task;
+
+/// The iCal property that stores the raw input a task has been created from
+pub const QUICK_ADD_PROPERTY: &str = "X-KITCHEN-FRIDGE-QUICK-ADD";
+
+/// The result of parsing a "quick add" input (see [`Task::quick_add`]), that can be turned into a [`Task`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaskBuilder {
+    summary: String,
+    due: Option<CalDateTime>,
+    categories: Vec<String>,
+    priority: Option<u8>,
+    raw_input: String,
+}
+
+impl TaskBuilder {
+    /// Parse a quick add input. See the [module documentation](crate::quick_add) for the supported grammar
+    pub fn parse(input: &str, reference_time: &DateTime<Utc>, default_tz: &Tz) -> Self {
+        let mut categories: Vec<String> = Vec::new();
+        let mut priority = None;
+        let mut words = Vec::new();
+
+        for word in input.split_whitespace() {
+            if let Some(tag) = word.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+                if !categories.iter().any(|c| c == tag) {
+                    categories.push(tag.to_string());
+                }
+            } else if let Some(prio) = word.strip_prefix('!').and_then(parse_priority) {
+                priority = Some(prio);
+            } else {
+                words.push(word);
+            }
+        }
+
+        let today = reference_time.with_timezone(default_tz).date().naive_local();
+        let mut date = None;
+        let mut time = None;
+        let mut end = words.len();
+        while end > 0 {
+            let word = words[end - 1].to_ascii_lowercase();
+            if time.is_none() {
+                if let Some(t) = parse_time(&word) {
+                    time = Some(t);
+                    end -= 1;
+                    if end > 0 && words[end - 1].eq_ignore_ascii_case("at") {
+                        end -= 1;
+                    }
+                    continue;
+                }
+            }
+            if date.is_none() {
+                if let Some(d) = parse_date(&word, today) {
+                    date = Some(d);
+                    end -= 1;
+                    continue;
+                }
+            }
+            break;
+        }
+        if end == 0 {
+            // There would be no summary left
+            date = None;
+            time = None;
+            end = words.len();
+        }
+
+        let due = match (date, time) {
+            (None, None) => None,
+            (Some(date), None) => Some(CalDateTime::Date(date)),
+            (date, Some(time)) => {
+                let date = date.unwrap_or_else(|| {
+                    let local_now = reference_time.with_timezone(default_tz).time();
+                    if time > local_now { today } else { today + Duration::days(1) }
+                });
+                let local = CalDateTime::Zoned{ tzid: default_tz.name().to_string(), local: date.and_time(time) };
+                Some(CalDateTime::Utc(local.to_utc(default_tz)))
+            },
+        };
+
+        Self {
+            summary: words[..end].join(" "),
+            due,
+            categories,
+            priority,
+            raw_input: input.to_string(),
+        }
+    }
+
+    pub fn summary(&self) -> &str                  { &self.summary }
+    pub fn due(&self) -> Option<&CalDateTime>      { self.due.as_ref() }
+    pub fn categories(&self) -> &[String]          { &self.categories }
+    /// The iCal priority, from 1 (highest) to 9 (lowest)
+    pub fn priority(&self) -> Option<u8>           { self.priority }
+    pub fn raw_input(&self) -> &str                { &self.raw_input }
+
+    /// Create a brand new task in the given calendar.
+    ///
+    /// The raw input is kept in a [`QUICK_ADD_PROPERTY`] property of the task, so that a parsing can be debugged later on.
+    pub fn build(self, parent_calendar_url: &Url) -> Task {
+        let mut extra_parameters = Vec::new();
+        if !self.categories.is_empty() {
+            // Tags may contain characters (e.g. `,`) that separate categories
+            let categories: Vec<String> = self.categories.iter().map(|category| crate::ical::escape_text(category)).collect();
+            extra_parameters.push(property("CATEGORIES", categories.join(",")));
+        }
+        if let Some(priority) = self.priority {
+            extra_parameters.push(property("PRIORITY", priority.to_string()));
+        }
+        extra_parameters.push(property(QUICK_ADD_PROPERTY, self.raw_input));
+
+        Task::new(self.summary, false, parent_calendar_url)
+            .with_dates(self.due, None)
+            .with_extra_parameters(extra_parameters)
+    }
+}
+
+fn property(name: &str, value: String) -> Property {
+    Property {
+        name: name.to_string(),
+        params: None,
+        value: Some(value),
+    }
+}
+
+fn parse_priority(word: &str) -> Option<u8> {
+    match word.to_ascii_lowercase().as_str() {
+        "high" => Some(1),
+        "medium" => Some(5),
+        "low" => Some(9),
+        digit if digit.len() == 1 => digit.parse().ok().filter(|p| (1..=9).contains(p)),
+        _ => None,
+    }
+}
+
+/// Parse a lowercase date expression
+fn parse_date(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let weekday = match word {
+        "today" => return Some(today),
+        "tomorrow" => return Some(today + Duration::days(1)),
+        "monday" => 0,
+        "tuesday" => 1,
+        "wednesday" => 2,
+        "thursday" => 3,
+        "friday" => 4,
+        "saturday" => 5,
+        "sunday" => 6,
+        _ => {
+            if word.len() != 10 {
+                return None;
+            }
+            return NaiveDate::parse_from_str(word, "%Y-%m-%d").ok();
+        },
+    };
+    let days_ahead = (weekday + 7 - today.weekday().num_days_from_monday() as i64) % 7;
+    Some(today + Duration::days(if days_ahead == 0 { 7 } else { days_ahead }))
+}
+
+/// Parse a lowercase time expression
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    let (body, pm) = if let Some(body) = word.strip_suffix("am") {
+        (body, Some(false))
+    } else if let Some(body) = word.strip_suffix("pm") {
+        (body, Some(true))
+    } else {
+        (word, None)
+    };
+
+    let is_number = |s: &str| !s.is_empty() && s.len() <= 2 && s.bytes().all(|b| b.is_ascii_digit());
+    let (hours, minutes) = match body.split_once(':') {
+        Some((h, m)) if m.len() == 2 => (h, Some(m)),
+        Some(_) => return None,
+        None => (body, None),
+    };
+    if !is_number(hours) || !minutes.map(is_number).unwrap_or(true) {
+        return None;
+    }
+    let mut hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = match minutes {
+        Some(m) => m.parse().ok()?,
+        // A lone number is not a time
+        None if pm.is_none() => return None,
+        None => 0,
+    };
+    if let Some(pm) = pm {
+        if hours == 0 || hours > 12 {
+            return None;
+        }
+        hours = hours % 12 + if pm { 12 } else { 0 };
+    }
+    NaiveTime::from_hms_opt(hours, minutes, 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_quick_add_grammar() {
+        // A Thursday, 11:00 in Paris
+        let reference_time = Utc.ymd(2021, 3, 18).and_hms(10, 0, 0);
+        let paris: Tz = "Europe/Paris".parse().unwrap();
+        let date = |y, m, d| Some(CalDateTime::Date(NaiveDate::from_ymd(y, m, d)));
+        let utc = |m, d, h, min| Some(CalDateTime::Utc(Utc.ymd(2021, m, d).and_hms(h, min, 0)));
+
+        let cases: Vec<(&str, &str, Option<CalDateTime>, Vec<&str>, Option<u8>)> = vec![
+            ("buy milk tomorrow 5pm #errands !high", "buy milk", utc(3, 19, 16, 0), vec!["errands"], Some(1)),
+            ("call mom", "call mom", None, vec![], None),
+            ("pay rent 2021-04-01", "pay rent", date(2021, 4, 1), vec![], None),
+            ("dentist friday at 9:30", "dentist", utc(3, 19, 8, 30), vec![], None),
+            ("plan holidays 5pm Tomorrow", "plan holidays", utc(3, 19, 16, 0), vec![], None),
+            ("water plants today", "water plants", date(2021, 3, 18), vec![], None),
+            ("weekly report thursday", "weekly report", date(2021, 3, 25), vec![], None),
+            ("meeting 5PM", "meeting", utc(3, 18, 16, 0), vec![], None),
+            ("meeting 10am", "meeting", utc(3, 19, 9, 0), vec![], None),
+            ("night train 00:15 sunday", "night train", utc(3, 20, 23, 15), vec![], None),
+            ("#home !low fix the sink monday", "fix the sink", date(2021, 3, 22), vec!["home"], Some(9)),
+            ("read a book !3 #leisure #books #leisure", "read a book", None, vec!["leisure", "books"], Some(3)),
+            ("tomorrow buy bread", "tomorrow buy bread", None, vec![], None),
+            ("buy 5 apples", "buy 5 apples", None, vec![], None),
+            ("fix it !urgent # !0", "fix it !urgent # !0", None, vec![], None),
+            ("tomorrow at 5pm", "tomorrow at 5pm", None, vec![], None),
+            ("call back 13pm", "call back 13pm", None, vec![], None),
+            ("sort #a,b", "sort", None, vec!["a,b"], None),
+            ("sort #a;b #c\\d", "sort", None, vec!["a;b", "c\\d"], None),
+        ];
+
+        for (input, summary, due, categories, priority) in cases {
+            let builder = Task::quick_add(input, &reference_time, &paris);
+            assert_eq!(builder.summary(), summary, "for input {:?}", input);
+            assert_eq!(builder.due(), due.as_ref(), "for input {:?}", input);
+            assert_eq!(builder.categories(), categories.iter().map(|c| c.to_string()).collect::<Vec<_>>().as_slice(), "for input {:?}", input);
+            assert_eq!(builder.priority(), priority, "for input {:?}", input);
+            assert_eq!(builder.raw_input(), input);
+
+            // Categories survive the round trip through the iCal CATEGORIES property
+            let cal_url: Url = "https://some.calend.ar/calendar/".parse().unwrap();
+            assert_eq!(builder.build(&cal_url).categories(), categories.iter().map(|c| c.to_string()).collect::<Vec<_>>(), "for input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_quick_add_build() {
+        let reference_time = Utc.ymd(2021, 3, 18).and_hms(10, 0, 0);
+        let cal_url: Url = "https://some.calend.ar/calendar/".parse().unwrap();
+        let input = "buy milk tomorrow #errands #food !2";
+        let task = Task::quick_add(input, &reference_time, &Tz::UTC).build(&cal_url);
+
+        assert_eq!(task.name(), "buy milk");
+        assert_eq!(task.due(), Some(&CalDateTime::Date(NaiveDate::from_ymd(2021, 3, 19))));
+        let props: Vec<(&str, &str)> = task.extra_parameters().iter()
+            .map(|p| (p.name.as_str(), p.value.as_deref().unwrap()))
+            .collect();
+        assert_eq!(props, vec![
+            ("CATEGORIES", "errands,food"),
+            ("PRIORITY", "2"),
+            (QUICK_ADD_PROPERTY, input),
+        ]);
+
+        let ical = crate::ical::build_from(&crate::Item::Task(task)).unwrap();
+        assert!(ical.contains("X-KITCHEN-FRIDGE-QUICK-ADD:buy milk tomorrow #errands #food !2"));
+
+        // Separators are escaped, as RFC5545 requires for TEXT values
+        let task = Task::quick_add("sort #a,b #c;d #e\\f", &reference_time, &Tz::UTC).build(&cal_url);
+        let categories = task.extra_parameters().iter().find(|p| p.name == "CATEGORIES").unwrap();
+        assert_eq!(categories.value.as_deref(), Some("a\\,b,c\\;d,e\\\\f"));
+    }
+}
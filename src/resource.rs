@@ -1,26 +1,713 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
 use url::Url;
+use chrono::Utc;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+
+use crate::client::retry::{self, RetryPolicy, Throttled};
+use crate::error::KitchenFridgeError;
+use crate::error_code::{self, ErrorCode};
+use crate::transfer_stats::TransferCounter;
+
+/// The credentials requests are authenticated with
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new<S: ToString, T: ToString>(username: S, password: T) -> Self {
+        Self { username: username.to_string(), password: password.to_string() }
+    }
+
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<hidden>")
+            .finish()
+    }
+}
+
+/// A reply whose body has been read (see [`Resource::read_reply`])
+#[derive(Debug)]
+pub(crate) struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    /// The body of this reply, as text
+    pub fn text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+}
+
+/// How requests are authenticated
+#[derive(Clone, PartialEq, Eq)]
+pub enum Authentication {
+    /// HTTP Basic authentication, with a username and a password.
+    /// In case the server asks for Digest authentication instead, its challenges are answered with the same credentials (see [`Authentication::Digest`])
+    Basic(Credentials),
+    /// HTTP Digest authentication ([RFC7616](https://tools.ietf.org/html/rfc7616)), with a username and a password.
+    /// The password is never sent, so the first request is sent without credentials, to get a challenge from the server. The next requests answer this challenge right away
+    Digest(Credentials),
+    /// An OAuth2 bearer token ([RFC6750](https://tools.ietf.org/html/rfc6750)), as required e.g. by Google Calendar
+    Bearer(String),
+}
+
+impl std::fmt::Debug for Authentication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Basic(credentials) => f.debug_tuple("Basic").field(credentials).finish(),
+            Self::Digest(credentials) => f.debug_tuple("Digest").field(credentials).finish(),
+            Self::Bearer(_) => f.debug_tuple("Bearer").field(&"<hidden>").finish(),
+        }
+    }
+}
+
+/// A `WWW-Authenticate: Digest` challenge of a server ([RFC7616](https://tools.ietf.org/html/rfc7616#section-3.3)).
+///
+/// Only the `MD5` and `MD5-sess` algorithms, and the `auth` quality of protection, are supported
+#[derive(Clone, Debug, PartialEq)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    /// Whether the server supports `qop=auth` (otherwise, the legacy [RFC2069](https://tools.ietf.org/html/rfc2069) answer is sent)
+    qop_auth: bool,
+    /// Whether the `MD5-sess` algorithm is used
+    session: bool,
+    /// How many requests have answered this challenge
+    nonce_count: u32,
+}
+
+impl DigestChallenge {
+    /// Returns the supported Digest challenge among the `WWW-Authenticate` headers of a reply, if any
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers.get_all(WWW_AUTHENTICATE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(Self::parse)
+    }
+
+    /// Parse a `WWW-Authenticate` header. This returns `None` in case it is not a Digest challenge, or in case it uses an algorithm that is not supported
+    fn parse(header: &str) -> Option<Self> {
+        let header = header.trim_start();
+        let scheme_end = header.find(char::is_whitespace)?;
+        if !header[..scheme_end].eq_ignore_ascii_case("digest") {
+            return None;
+        }
+        let mut params = parse_auth_params(&header[scheme_end..]);
+
+        let session = match params.get("algorithm").map(|algorithm| algorithm.to_ascii_uppercase()).as_deref() {
+            None | Some("MD5") => false,
+            Some("MD5-SESS") => true,
+            Some(other) => {
+                log::warn!("The server asks for Digest authentication with algorithm {}, that is not supported", other);
+                return None;
+            },
+        };
+        let qop_auth = match params.get("qop") {
+            None => false,
+            Some(qop) if qop.split(',').any(|qop| qop.trim().eq_ignore_ascii_case("auth")) => true,
+            Some(qop) => {
+                log::warn!("The server asks for Digest authentication with quality of protection {}, that is not supported", qop);
+                return None;
+            },
+        };
+        Some(Self {
+            realm: params.remove("realm").unwrap_or_default(),
+            nonce: params.remove("nonce")?,
+            opaque: params.remove("opaque"),
+            qop_auth,
+            session,
+            nonce_count: 0,
+        })
+    }
+
+    /// The value of the `Authorization` header that answers this challenge, for a request to `uri` (i.e. a path and a query string)
+    fn answer(&mut self, credentials: &Credentials, method: &str, uri: &str) -> String {
+        self.nonce_count += 1;
+        let cnonce = uuid::Uuid::new_v4().to_simple().to_string();
+        self.answer_with(credentials, method, uri, self.nonce_count, &cnonce)
+    }
+
+    fn answer_with(&self, credentials: &Credentials, method: &str, uri: &str, nonce_count: u32, cnonce: &str) -> String {
+        let md5 = |data: String| format!("{:x}", md5::compute(data));
+
+        let mut ha1 = md5(format!("{}:{}:{}", credentials.username, self.realm, credentials.password));
+        if self.session {
+            ha1 = md5(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = md5(format!("{}:{}", method, uri));
+        let response = match self.qop_auth {
+            true => md5(format!("{}:{}:{:08x}:{}:auth:{}", ha1, self.nonce, nonce_count, cnonce, ha2)),
+            false => md5(format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let mut header = format!(r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}, response="{}""#,
+            credentials.username, self.realm, self.nonce, uri, if self.session { "MD5-sess" } else { "MD5" }, response);
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(r#", opaque="{}""#, opaque));
+        }
+        if self.qop_auth {
+            header.push_str(&format!(r#", qop=auth, nc={:08x}, cnonce="{}""#, nonce_count, cnonce));
+        }
+        header
+    }
+}
+
+/// Parse the comma-separated `name=value` parameters of a challenge (values may be quoted, e.g. `realm="a, b", algorithm=MD5`). Names are lowercased
+fn parse_auth_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_ascii_lowercase();
+        rest = rest[eq + 1..].trim_start();
+        let value = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut end = quoted.len();
+                let mut escaped = false;
+                for (i, c) in quoted.char_indices() {
+                    match c {
+                        _ if escaped => { value.push(c); escaped = false; },
+                        '\\' => escaped = true,
+                        '"' => { end = i + 1; break; },
+                        _ => value.push(c),
+                    }
+                }
+                rest = &quoted[end..];
+                value
+            },
+            None => {
+                let end = rest.find(',').unwrap_or(rest.len());
+                let value = rest[..end].trim().to_string();
+                rest = &rest[end..];
+                value
+            },
+        };
+        parsed.insert(name, value);
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    parsed
+}
+
+/// Provides the credentials requests are authenticated with, e.g. to refresh OAuth2 tokens before they expire (see [`crate::client::Client::new_with_credentials_provider`])
+///
+/// The provider is asked for credentials before every request. In case the server refuses a request (`401 Unauthorized`), it is asked once more, and the request is sent again in case it has returned other credentials.
+/// Fixed credentials ([`Credentials`] and [`Authentication`]) are providers that always return themselves
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    /// The credentials the next request is authenticated with
+    async fn get_credentials(&self) -> Result<Authentication, Box<dyn Error + Send + Sync>>;
+}
+
+#[async_trait]
+impl CredentialsProvider for Authentication {
+    async fn get_credentials(&self) -> Result<Authentication, Box<dyn Error + Send + Sync>> {
+        Ok(self.clone())
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for Credentials {
+    async fn get_credentials(&self) -> Result<Authentication, Box<dyn Error + Send + Sync>> {
+        Ok(Authentication::Basic(self.clone()))
+    }
+}
+
+/// The error returned when the [`CredentialsProvider`] of a client has been unable to provide credentials for a request
+#[derive(Debug)]
+pub struct CredentialsError {
+    /// The URL of the request that has not been sent
+    pub url: Url,
+    pub source: Box<dyn Error + Send + Sync>,
+}
+
+impl CredentialsError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::CREDENTIALS_UNAVAILABLE
+    }
+}
+
+impl std::fmt::Display for CredentialsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Unable to get credentials for {}: {} [{}]", self.url, self.source, self.code())
+    }
+}
+
+impl Error for CredentialsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+/// The error returned when a URL given by a server is on another origin than the server itself (see [`Resource::combine`]).
+///
+/// Requests to such URLs are not sent, because they would carry the credentials of this server to another one
+#[derive(Debug)]
+pub struct CrossOriginError {
+    /// The URL of the resource the other URL has been resolved against
+    pub base: Url,
+    /// The URL on another origin
+    pub url: Url,
+}
+
+impl CrossOriginError {
+    /// The stable identifier of this error (see [`crate::error_code`])
+    pub fn code(&self) -> ErrorCode {
+        error_code::CROSS_ORIGIN_URL
+    }
+}
+
+impl std::fmt::Display for CrossOriginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Refusing to send credentials for {} to {}, that is on another origin [{}]", self.base, self.url, self.code())
+    }
+}
+
+impl Error for CrossOriginError {}
+
+/// The credentials provider resources share, the credentials it has returned last, and the last Digest challenge of the server
+struct SharedCredentials {
+    provider: Arc<dyn CredentialsProvider>,
+    current: Option<Authentication>,
+    digest: Option<DigestChallenge>,
+}
+
+impl std::fmt::Debug for SharedCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedCredentials")
+            .field("current", &self.current)
+            .field("digest", &self.digest)
+            .finish()
+    }
+}
 
 /// Just a wrapper around a URL and credentials
+///
+/// It also keeps track of the data exchanged with the server (see [`crate::transfer_stats`]). Resources built by [`Resource::combine`] share the same counter, the same HTTP client, the same timeout, the same retry policy, and the same credentials (so that they can be changed at once, see [`crate::client::Client::set_credentials`]).
 #[derive(Clone, Debug)]
 pub struct Resource {
     url: Url,
-    username: String,
-    password: String,
+    credentials: Arc<RwLock<SharedCredentials>>,
+    /// How long requests wait for the server, unless the HTTP client has its own timeout (see [`crate::client::Client::set_timeout`])
+    timeout: Arc<RwLock<Option<Duration>>>,
+    /// How requests that fail because of a transient failure are retried (see [`crate::client::Client::set_retry_policy`])
+    retry_policy: Arc<RwLock<RetryPolicy>>,
+
+    http_client: reqwest::Client,
+
+    transfer_counter: TransferCounter,
+    /// The calendar the transfers made through this resource are accounted to (if any)
+    accounted_calendar: Option<Url>,
 }
 
 impl Resource {
     pub fn new(url: Url, username: String, password: String) -> Self {
-        Self { url, username, password }
+        let http_client = crate::client::http_client_builder().build()
+            .expect("unable to initialise the TLS backend (see crate::client::ClientBuilder to handle this error)");
+        Self::new_with_http_client(url, Authentication::Basic(Credentials { username, password }), http_client)
+    }
+
+    /// Create a resource whose requests are sent with a given HTTP client (and so are the requests of the resources it is combined with)
+    pub(crate) fn new_with_http_client(url: Url, authentication: Authentication, http_client: reqwest::Client) -> Self {
+        let credentials = SharedCredentials { provider: Arc::new(authentication.clone()), current: Some(authentication), digest: None };
+        let timeout = Arc::new(RwLock::new(Some(crate::client::DEFAULT_TIMEOUT)));
+        Self { url, credentials: Arc::new(RwLock::new(credentials)), timeout, retry_policy: Arc::default(), http_client, transfer_counter: TransferCounter::default(), accounted_calendar: None }
     }
 
     pub fn url(&self) -> &Url { &self.url }
-    pub fn username(&self) -> &String { &self.username }
-    pub fn password(&self) -> &String { &self.password }
+    /// The username requests are authenticated with (this is empty for bearer tokens, and until a credentials provider has been asked for credentials)
+    pub fn username(&self) -> String {
+        match &self.credentials.read().unwrap().current {
+            Some(Authentication::Basic(credentials)) | Some(Authentication::Digest(credentials)) => credentials.username.clone(),
+            _ => String::new(),
+        }
+    }
+    /// The password requests are authenticated with (this is empty for bearer tokens, and until a credentials provider has been asked for credentials)
+    pub fn password(&self) -> String {
+        match &self.credentials.read().unwrap().current {
+            Some(Authentication::Basic(credentials)) | Some(Authentication::Digest(credentials)) => credentials.password.clone(),
+            _ => String::new(),
+        }
+    }
+    /// The HTTP client requests to this resource are sent with
+    pub fn http_client(&self) -> &reqwest::Client { &self.http_client }
 
-    /// Build a new Resource by keeping the same credentials, scheme and server from `base` but changing the path part
-    pub fn combine(&self, new_path: &str) -> Resource {
+    /// Build a new Resource by keeping the same credentials, and resolving `href` against the current URL.
+    ///
+    /// `href` is usually an absolute path (as returned in a WebDAV `<href>`), that may carry its own query string or fragment.
+    /// The query string and fragment of the current URL are never carried over to the new Resource.
+    ///
+    /// Since the new Resource is sent the same credentials, `href` must be on the same origin (scheme, host and port) as the current URL, otherwise this fails with a [`CrossOriginError`]
+    pub fn combine(&self, href: &str) -> Result<Resource, KitchenFridgeError> {
+        let url = self.url.join(href)?;
+        if url.origin() != self.url.origin() {
+            return Err(CrossOriginError { base: self.url.clone(), url }.into());
+        }
         let mut built = (*self).clone();
-        built.url.set_path(&new_path);
-        built
+        built.url = url;
+        Ok(built)
+    }
+
+    /// Replace the credentials of this resource, and of every resource it shares them with
+    pub(crate) fn set_credentials(&self, credentials: Authentication) {
+        let mut shared = self.credentials.write().unwrap();
+        shared.provider = Arc::new(credentials.clone());
+        shared.current = Some(credentials);
+    }
+
+    /// Replace the credentials provider of this resource, and of every resource it shares it with
+    pub(crate) fn set_credentials_provider(&self, provider: Arc<dyn CredentialsProvider>) {
+        let mut shared = self.credentials.write().unwrap();
+        shared.provider = provider;
+        shared.current = None;
+    }
+
+    /// Ask the credentials provider for the credentials the next request is authenticated with
+    async fn get_credentials(&self) -> Result<Authentication, CredentialsError> {
+        let provider = self.credentials.read().unwrap().provider.clone();
+        let credentials = provider.get_credentials().await
+            .map_err(|source| CredentialsError { url: self.url.clone(), source })?;
+        self.credentials.write().unwrap().current = Some(credentials.clone());
+        Ok(credentials)
+    }
+
+    /// How long requests to this resource wait for the server (see [`Self::set_timeout`])
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        *self.timeout.read().unwrap()
+    }
+
+    /// Set how long requests to this resource (and to the resources it shares credentials with) wait for the server. `None` leaves it to the HTTP client
+    pub(crate) fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
+    /// Set how requests to this resource (and to the resources it shares credentials with) are retried
+    pub(crate) fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.retry_policy.write().unwrap() = policy;
+    }
+
+    /// The credentials the provider has returned last, if it has been asked already
+    pub(crate) fn current_credentials(&self) -> Option<Authentication> {
+        self.credentials.read().unwrap().current.clone()
+    }
+
+    /// Set the `Authorization` header of a request. This returns the nonce of the Digest challenge it answers, if any
+    fn authorize(&self, credentials: &Authentication, request: &mut reqwest::Request) -> Option<String> {
+        let (authorization, nonce) = match credentials {
+            Authentication::Bearer(token) => (Some(format!("Bearer {}", token)), None),
+            Authentication::Basic(password) | Authentication::Digest(password) => {
+                let mut shared = self.credentials.write().unwrap();
+                match (&mut shared.digest, credentials) {
+                    (Some(challenge), _) => {
+                        let authorization = challenge.answer(password, request.method().as_str(), Resource::href_of(request.url()));
+                        (Some(authorization), Some(challenge.nonce.clone()))
+                    },
+                    (None, Authentication::Basic(_)) => {
+                        let encoded = base64::encode(format!("{}:{}", password.username, password.password));
+                        (Some(format!("Basic {}", encoded)), None)
+                    },
+                    // Wait for a challenge rather than sending the password
+                    (None, _) => (None, None),
+                }
+            },
+        };
+        if let Some(mut value) = authorization.and_then(|authorization| HeaderValue::from_str(&authorization).ok()) {
+            value.set_sensitive(true);
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+        nonce
+    }
+
+    /// Authenticate a request with the credentials of the provider, and send it.
+    ///
+    /// Requests that can safely be sent several times are sent again in case they fail because of a transient failure (see [`RetryPolicy`]). The reply of their last attempt is returned.
+    pub(crate) async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, KitchenFridgeError> {
+        let mut request = request.build()?;
+        if let Some(timeout) = *self.timeout.read().unwrap() {
+            *request.timeout_mut() = Some(timeout);
+        }
+        let policy = *self.retry_policy.read().unwrap();
+        let is_idempotent = retry::is_idempotent(&request);
+
+        let mut attempt = 1;
+        loop {
+            let next = match attempt < policy.max_attempts {
+                true => request.try_clone(),
+                false => None,
+            };
+            let (method, url) = (request.method().clone(), request.url().clone());
+            let result = self.send_once(request).await;
+            let failure = match &result {
+                // A locked resource has not been changed at all, and neither has a throttled request, so that any request can be sent again
+                Ok(response) if matches!(response.status(), StatusCode::LOCKED | StatusCode::TOO_MANY_REQUESTS) => Some(response.status().to_string()),
+                Ok(response) if is_idempotent && retry::is_transient_status(response.status()) => Some(response.status().to_string()),
+                Err(err) if is_idempotent && retry::is_transient_error(err) => Some(err.to_string()),
+                _ => None,
+            };
+            let next = next.filter(|_| failure.is_some());
+            let retry_after = match &result {
+                Ok(response) => retry::retry_after(response.status(), response.headers(), Utc::now()),
+                Err(_) => None,
+            };
+            if let (Ok(response), Some(retry_after)) = (&result, retry_after) {
+                if next.is_none() || retry_after > policy.max_retry_after {
+                    return Err(Throttled { status: response.status(), retry_after }.into());
+                }
+            }
+            let (next, failure) = match (next, failure) {
+                (Some(next), Some(failure)) => (next, failure),
+                _ => return result,
+            };
+            let backoff = retry_after.unwrap_or_else(|| policy.backoff(attempt));
+            log::debug!("{} {} has failed ({}), retrying in {:?} (attempt {}/{})", method, url, failure, backoff, attempt + 1, policy.max_attempts);
+            tokio::time::sleep(backoff).await;
+            request = next;
+            attempt += 1;
+        }
+    }
+
+    /// Authenticate a request with the credentials of the provider, and send it once.
+    ///
+    /// In case the server replies `401 Unauthorized`, the provider is asked once more, and the request is sent a single time again in case it has returned other credentials (e.g. a token that has been refreshed, or credentials that have been changed while the request was in flight),
+    /// or in case the server has sent a Digest challenge the request has not answered yet (this challenge is then answered by the next requests as well).
+    async fn send_once(&self, mut request: reqwest::Request) -> Result<reqwest::Response, KitchenFridgeError> {
+        let credentials = self.get_credentials().await?;
+        let retry = request.try_clone();
+        let answered_nonce = self.authorize(&credentials, &mut request);
+        let response = self.http_client.execute(request).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let mut retry = match retry {
+            None => return Ok(response),
+            Some(retry) => retry,
+        };
+        let challenged = match DigestChallenge::from_headers(response.headers()) {
+            Some(challenge) if !matches!(credentials, Authentication::Bearer(_)) => {
+                // This is a new challenge, unless the server has refused the answer to this very challenge (i.e. the credentials are wrong)
+                let is_new = answered_nonce.as_deref() != Some(challenge.nonce.as_str());
+                self.credentials.write().unwrap().digest = Some(challenge);
+                is_new
+            },
+            _ => false,
+        };
+        let current = self.get_credentials().await?;
+        if current == credentials && !challenged {
+            return Ok(response);
+        }
+        match challenged {
+            true => log::debug!("Answering the Digest challenge of {}", self.url),
+            false => log::info!("A request to {} has been refused, retrying it with new credentials", self.url),
+        }
+        self.authorize(&current, &mut retry);
+        Ok(self.http_client.execute(retry).await?)
+    }
+
+    /// The running counter of the data exchanged through this resource (and the resources it has been combined with)
+    pub fn transfer_counter(&self) -> &TransferCounter { &self.transfer_counter }
+
+    /// Account the transfers made through this resource to the calendar at its URL
+    pub(crate) fn accounted_to_calendar(mut self) -> Self {
+        self.accounted_calendar = Some(self.url.clone());
+        self
+    }
+
+    /// Account for a request made through this resource
+    pub(crate) fn record_transfer(&self, operation: &str, bytes_sent: u64, bytes_received: u64) {
+        self.transfer_counter.lock().unwrap()
+            .record(operation, self.accounted_calendar.as_ref(), bytes_sent, bytes_received);
+    }
+
+    /// Read the whole reply to a request made through this resource, and account for the request.
+    ///
+    /// The bytes that are actually received are counted (rather than the `Content-Length` the reply tells, that chunked replies do not tell).
+    /// Bodies larger than `limit` bytes are not read any further, and make this fail with a [`ResponseTooLarge`](crate::dav_error::ResponseTooLarge) error.
+    /// In case the connection drops while the body is being received, what has been received so far is returned
+    pub(crate) async fn read_reply(&self, mut response: reqwest::Response, operation: &str, bytes_sent: u64, limit: usize) -> Result<Reply, Box<dyn Error>> {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let request = format!("{} {}", operation, response.url());
+        let mut body = Vec::new();
+        let mut received = 0;
+        let mut too_large = response.content_length().is_some_and(|length| length > limit as u64);
+        while !too_large {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    received += chunk.len();
+                    too_large = received > limit;
+                    if !too_large {
+                        body.extend_from_slice(&chunk);
+                    }
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    log::debug!("The reply to {} has been cut after {} bytes: {}", request, received, err);
+                    break;
+                },
+            }
+        }
+        self.record_transfer(operation, bytes_sent, received as u64);
+        if too_large {
+            return Err(Box::new(crate::dav_error::ResponseTooLarge { request, limit }));
+        }
+        Ok(Reply { status, headers, body })
+    }
+
+    /// Returns the part of a URL that must be used in a WebDAV `<href>`, i.e. its path and query string
+    pub fn href_of(url: &Url) -> &str {
+        &url[url::Position::BeforePath..url::Position::AfterQuery]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(url: &str) -> Resource {
+        Resource::new(url.parse().unwrap(), "user".to_string(), "pass".to_string())
+    }
+
+    #[test]
+    fn test_combine() {
+        let base = resource("https://my.server.com/dav/calendars/john/");
+        assert_eq!(base.combine("/dav/calendars/john/tasks/").unwrap().url().as_str(), "https://my.server.com/dav/calendars/john/tasks/");
+        assert_eq!(base.combine("/cal?v=2").unwrap().url().as_str(), "https://my.server.com/cal?v=2");
+        assert_eq!(base.combine("/cal#main").unwrap().url().as_str(), "https://my.server.com/cal#main");
+        assert_eq!(base.combine("tasks/").unwrap().url().as_str(), "https://my.server.com/dav/calendars/john/tasks/");
+        assert_eq!(base.combine("https://my.server.com/cal/").unwrap().url().as_str(), "https://my.server.com/cal/");
+        assert_eq!(base.combine("/cal").unwrap().username(), "user");
+    }
+
+    #[test]
+    fn test_combine_refuses_other_origins() {
+        let base = resource("https://my.server.com/dav/");
+        for href in ["https://other.server.com/cal/", "http://my.server.com/cal/", "https://my.server.com:8443/cal/", "//other.server.com/cal/"] {
+            match base.combine(href) {
+                Err(KitchenFridgeError::CrossOrigin(err)) => assert_eq!(err.base, *base.url()),
+                other => panic!("unexpected result for {}: {:?}", href, other.map(|resource| resource.url().clone())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_combine_drops_the_base_query() {
+        let base = resource("https://my.server.com/dav/?token=abc#frag");
+        assert_eq!(base.combine("/cal/").unwrap().url().as_str(), "https://my.server.com/cal/");
+        assert_eq!(base.combine("/cal?v=2").unwrap().url().as_str(), "https://my.server.com/cal?v=2");
+    }
+
+    /// The challenge of the example of [RFC2617](https://tools.ietf.org/html/rfc2617#section-3.5)
+    const RFC_CHALLENGE: &str = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+
+    #[test]
+    fn test_digest_challenge() {
+        let challenge = DigestChallenge::parse(RFC_CHALLENGE).unwrap();
+        assert_eq!(challenge.realm, "testrealm@host.com");
+        assert_eq!(challenge.nonce, "dcd98b7102dd2f0e8b11d0f600bfb0c093");
+        assert_eq!(challenge.opaque.as_deref(), Some("5ccc069c403ebaf9f0171e9517f40e41"));
+        assert!(challenge.qop_auth);
+        assert!(!challenge.session);
+
+        let credentials = Credentials::new("Mufasa", "Circle Of Life");
+        assert_eq!(challenge.answer_with(&credentials, "GET", "/dir/index.html", 1, "0a4f113b"),
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", algorithm=MD5, response="6629fae49393a05397450978507c4ef1", opaque="5ccc069c403ebaf9f0171e9517f40e41", qop=auth, nc=00000001, cnonce="0a4f113b""#);
+
+        // Without qop, the legacy RFC2069 answer is sent
+        let legacy = DigestChallenge::parse(r#"digest realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093""#).unwrap();
+        assert!(legacy.answer_with(&credentials, "GET", "/dir/index.html", 1, "0a4f113b").contains(r#"response="670fd8c2df070c60b045671b8b24ff02""#));
+
+        assert_eq!(DigestChallenge::parse(r#"Basic realm="testrealm@host.com""#), None);
+        assert_eq!(DigestChallenge::parse(r#"Digest realm="a", nonce="b", algorithm=SHA-256"#), None);
+        assert_eq!(DigestChallenge::parse(r#"Digest realm="a", nonce="b", qop="auth-int""#), None);
+        assert_eq!(DigestChallenge::parse(r#"Digest realm="a""#), None);
+        assert!(DigestChallenge::parse(r#"Digest realm="a", nonce="b", algorithm=MD5-sess"#).unwrap().session);
+    }
+
+    #[test]
+    fn test_parse_auth_params() {
+        let params = parse_auth_params(r#" Realm="a, \"quoted\" b",nonce=xyz , stale=TRUE"#);
+        assert_eq!(params.get("realm").map(String::as_str), Some(r#"a, "quoted" b"#));
+        assert_eq!(params.get("nonce").map(String::as_str), Some("xyz"));
+        assert_eq!(params.get("stale").map(String::as_str), Some("TRUE"));
+    }
+
+    /// A server that asks for Digest authentication (with the challenge of [`RFC_CHALLENGE`]), and checks the answers with the password of the RFC.
+    /// This returns the `Authorization` headers it has received
+    fn digest_server() -> (Url, Arc<std::sync::Mutex<Vec<Option<String>>>>) {
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&received);
+        let challenge = DigestChallenge::parse(RFC_CHALLENGE).unwrap();
+        let credentials = Credentials::new("Mufasa", "Circle Of Life");
+        let url = crate::test_server::serve("/dir/index.html", move |request| {
+            let authorization = request.header("authorization").map(str::to_string);
+            let valid = authorization.as_deref()
+                .and_then(|authorization| authorization.strip_prefix("Digest "))
+                .map(parse_auth_params)
+                .is_some_and(|params| {
+                    let nonce_count = u32::from_str_radix(&params["nc"], 16).unwrap();
+                    let expected = challenge.answer_with(&credentials, "GET", &params["uri"], nonce_count, &params["cnonce"]);
+                    parse_auth_params(&expected["Digest ".len()..])["response"] == params["response"]
+                });
+            log.lock().unwrap().push(authorization);
+            match valid {
+                true => Some("200 OK".to_string()),
+                false => Some(format!("401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"testrealm@host.com\"\r\nWWW-Authenticate: {}", RFC_CHALLENGE)),
+            }
+        });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn test_digest_authentication() {
+        // The first request is sent with Basic authentication, and is sent again to answer the challenge of the server
+        let (url, received) = digest_server();
+        let resource = Resource::new(url, "Mufasa".to_string(), "Circle Of Life".to_string());
+        let get = || resource.send(resource.http_client().get(resource.url().clone()));
+        assert_eq!(get().await.unwrap().status(), StatusCode::OK);
+        // The next requests answer the challenge right away
+        assert_eq!(get().await.unwrap().status(), StatusCode::OK);
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 3);
+        assert!(received[0].as_deref().unwrap().starts_with("Basic "));
+        assert!(received[1].as_deref().unwrap().contains("nc=00000001"));
+        assert!(received[2].as_deref().unwrap().contains("nc=00000002"));
+
+        // Wrong passwords are refused for good
+        let (url, received) = digest_server();
+        let resource = Resource::new(url, "Mufasa".to_string(), "wrong".to_string());
+        let get = || resource.send(resource.http_client().get(resource.url().clone()));
+        assert_eq!(get().await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(received.lock().unwrap().len(), 2);
+
+        // When Digest authentication is forced, the password is never sent
+        let (url, received) = digest_server();
+        let resource = Resource::new_with_http_client(url, Authentication::Digest(Credentials::new("Mufasa", "Circle Of Life")), reqwest::Client::new());
+        let get = || resource.send(resource.http_client().get(resource.url().clone()));
+        assert_eq!(get().await.unwrap().status(), StatusCode::OK);
+        assert_eq!(received.lock().unwrap()[0], None);
+    }
+
+    #[test]
+    fn test_href_of() {
+        let url: Url = "https://my.server.com/cal/item.ics?v=2#frag".parse().unwrap();
+        assert_eq!(Resource::href_of(&url), "/cal/item.ics?v=2");
+        let url: Url = "https://my.server.com/cal/item.ics".parse().unwrap();
+        assert_eq!(Resource::href_of(&url), "/cal/item.ics");
     }
 }
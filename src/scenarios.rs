@@ -1,35 +1,47 @@
 //! Multiple scenarios that are performed to test sync operations correctly work
 //!
-//! This module creates test data.
+//! This module creates test data, so that apps can test their own sync flows against the scenarios this crate is tested with. It is only available with the `test-utils` feature.
 //! To do so, "scenarii" are defined. A scenario contains an inital state before sync, changes made either on the local or remote side, then the expected final state that should be present in both sources after sync.
 //!
-//! This module builds actual CalDAV sources (actually [`crate::cache::Cache`]s, that can also mock what would be [`crate::client::Client`]s in a real program) and [`crate::provider::Provider]`s that contain this data
+//! A [`ScenarioBuilder`] builds actual CalDAV sources (actually [`crate::cache::Cache`]s, that can also mock what would be [`crate::client::Client`]s in a real program) and [`crate::provider::Provider`]s that contain this data, along with the [`Expectation`] of their content after a sync.
 //!
-//! This module can also check the sources after a sync contain the actual data we expect
-#![cfg(feature = "local_calendar_mocks_remote_calendars")]
-
-use std::path::PathBuf;
+//! [`assert_converged`] then checks the sources after a sync contain the actual data we expect
+//!
+//! ```ignore
+//! let (mut provider, expectation) = ScenarioBuilder::classic_conflict_matrix().build().await;
+//! assert!(provider.sync().await);
+//! assert_converged(&provider, &expectation).await;
+//! ```
+#![cfg(feature = "test-utils")]
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::error::Error;
 use url::Url;
 
 use chrono::Utc;
 
-use kitchen_fridge::calendar::SupportedComponents;
-use kitchen_fridge::traits::CalDavSource;
-use kitchen_fridge::traits::BaseCalendar;
-use kitchen_fridge::traits::CompleteCalendar;
-use kitchen_fridge::traits::DavCalendar;
-use kitchen_fridge::cache::Cache;
-use kitchen_fridge::Item;
-use kitchen_fridge::item::SyncStatus;
-use kitchen_fridge::Task;
-use kitchen_fridge::task::CompletionStatus;
-use kitchen_fridge::calendar::cached_calendar::CachedCalendar;
-use kitchen_fridge::provider::Provider;
-use kitchen_fridge::mock_behaviour::MockBehaviour;
-use kitchen_fridge::utils::random_url;
-
+use crate::calendar::SupportedComponents;
+use crate::traits::CalDavSource;
+use crate::traits::BaseCalendar;
+use crate::traits::CompleteCalendar;
+use crate::traits::DavCalendar;
+use crate::cache::Cache;
+use crate::Item;
+use crate::item::SyncStatus;
+use crate::Task;
+use crate::task::CompletionStatus;
+use crate::calendar::cached_calendar::CachedCalendar;
+use crate::provider::Provider;
+use crate::mock_behaviour::MockBehaviour;
+use crate::utils::random_url;
+
+/// The kind of provider the scenarios are built into: a local cache, and another cache that mocks a server
+pub type ScenarioProvider = Provider<Cache, CachedCalendar, Cache, CachedCalendar>;
+
+#[derive(Clone)]
 pub enum LocatedState {
     /// Item does not exist yet or does not exist anymore
     None,
@@ -41,6 +53,7 @@ pub enum LocatedState {
     BothSynced(ItemState),
 }
 
+#[derive(Clone)]
 pub struct ItemState {
     // TODO: if/when this crate supports Events as well, we could add such events here
     /// The calendar it is in
@@ -51,6 +64,8 @@ pub struct ItemState {
     completed: bool,
 }
 
+#[derive(Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum ChangeToApply {
     Rename(String),
     SetCompletion(bool),
@@ -61,6 +76,7 @@ pub enum ChangeToApply {
 }
 
 
+#[derive(Clone)]
 pub struct ItemScenario {
     url: Url,
     initial_state: LocatedState,
@@ -87,9 +103,9 @@ pub struct ItemScenario {
 pub fn scenarii_basic() -> Vec<ItemScenario> {
     let mut tasks = Vec::new();
 
-    let first_cal = Url::from("https://some.calend.ar/calendar-1/".parse().unwrap());
-    let second_cal = Url::from("https://some.calend.ar/calendar-2/".parse().unwrap());
-    let third_cal = Url::from("https://some.calend.ar/calendar-3/".parse().unwrap());
+    let first_cal: Url = "https://some.calend.ar/calendar-1/".parse().unwrap();
+    let second_cal: Url = "https://some.calend.ar/calendar-2/".parse().unwrap();
+    let third_cal: Url = "https://some.calend.ar/calendar-3/".parse().unwrap();
 
     tasks.push(
         ItemScenario {
@@ -418,8 +434,8 @@ pub fn scenarii_basic() -> Vec<ItemScenario> {
 pub fn scenarii_first_sync_to_local() -> Vec<ItemScenario> {
     let mut tasks = Vec::new();
 
-    let cal1 = Url::from("https://some.calend.ar/first/".parse().unwrap());
-    let cal2 = Url::from("https://some.calend.ar/second/".parse().unwrap());
+    let cal1: Url = "https://some.calend.ar/first/".parse().unwrap();
+    let cal2: Url = "https://some.calend.ar/second/".parse().unwrap();
 
     tasks.push(
         ItemScenario {
@@ -482,8 +498,8 @@ pub fn scenarii_first_sync_to_local() -> Vec<ItemScenario> {
 pub fn scenarii_first_sync_to_server() -> Vec<ItemScenario> {
     let mut tasks = Vec::new();
 
-    let cal3 = Url::from("https://some.calend.ar/third/".parse().unwrap());
-    let cal4 = Url::from("https://some.calend.ar/fourth/".parse().unwrap());
+    let cal3: Url = "https://some.calend.ar/third/".parse().unwrap();
+    let cal4: Url = "https://some.calend.ar/fourth/".parse().unwrap();
 
     tasks.push(
         ItemScenario {
@@ -547,7 +563,7 @@ pub fn scenarii_first_sync_to_server() -> Vec<ItemScenario> {
 pub fn scenarii_transient_task() -> Vec<ItemScenario> {
     let mut tasks = Vec::new();
 
-    let cal = Url::from("https://some.calend.ar/transient/".parse().unwrap());
+    let cal: Url = "https://some.calend.ar/transient/".parse().unwrap();
 
     tasks.push(
         ItemScenario {
@@ -595,58 +611,185 @@ pub fn scenarii_transient_task() -> Vec<ItemScenario> {
 }
 
 
-/// Build a `Provider` that contains the data (defined in the given scenarii) before sync
-pub async fn populate_test_provider_before_sync(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>) -> Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
-    let mut provider = populate_test_provider(scenarii, mock_behaviour, false).await;
-    apply_changes_on_provider(&mut provider, scenarii).await;
-    provider
+/// Builds a [`ScenarioProvider`] out of a set of scenarii, along with the [`Expectation`] of its content after a sync
+#[derive(Clone)]
+pub struct ScenarioBuilder {
+    scenarii: Vec<ItemScenario>,
+    mock_behaviour: Option<Arc<Mutex<MockBehaviour>>>,
+    folder: PathBuf,
+}
+
+impl ScenarioBuilder {
+    fn new(scenarii: Vec<ItemScenario>) -> Self {
+        Self { scenarii, mock_behaviour: None, folder: PathBuf::from("test_cache") }
+    }
+
+    /// Items that have been changed, completed and deleted on either side, or both (see [`scenarii_basic`])
+    pub fn classic_conflict_matrix() -> Self { Self::new(scenarii_basic()) }
+    /// A first sync, with items that are only on the server (see [`scenarii_first_sync_to_local`])
+    pub fn first_sync_to_local() -> Self { Self::new(scenarii_first_sync_to_local()) }
+    /// A first sync, with items that are only in the local cache (see [`scenarii_first_sync_to_server`])
+    pub fn first_sync_to_server() -> Self { Self::new(scenarii_first_sync_to_server()) }
+    /// A task that is added and deleted before a sync happens (see [`scenarii_transient_task`])
+    pub fn transient_task() -> Self { Self::new(scenarii_transient_task()) }
+
+    /// Let the mocked server behave this way (e.g. fail some requests) during the syncs.
+    /// Without this, a [`MockBehaviour::new`] is used
+    pub fn with_mock_behaviour(mut self, mock_behaviour: Arc<Mutex<MockBehaviour>>) -> Self {
+        self.mock_behaviour = Some(mock_behaviour);
+        self
+    }
+
+    /// The folder the local and the remote sources are saved into (in `local` and `remote` sub-folders) when they are dropped. This is `test_cache` by default
+    pub fn in_folder(mut self, folder: &Path) -> Self {
+        self.folder = folder.to_path_buf();
+        self
+    }
+
+    /// Build a provider that contains the data before sync, and the content it is expected to have after a sync.
+    ///
+    /// The mock behaviour of the server is suspended while the provider is populated. It is resumed when this returns.
+    /// This can be called several times, to get several providers with the same data
+    pub async fn build(&self) -> (ScenarioProvider, Expectation) {
+        let mock_behaviour = self.mock_behaviour.clone().unwrap_or_else(|| Arc::new(Mutex::new(MockBehaviour::new())));
+        mock_behaviour.lock().unwrap().suspend();
+        let provider = populate_test_provider_before_sync(&self.scenarii, mock_behaviour.clone(), &self.folder).await;
+        mock_behaviour.lock().unwrap().resume();
+
+        let mut items = BTreeMap::new();
+        for scenario in &self.scenarii {
+            match &scenario.after_sync {
+                LocatedState::None => (),
+                LocatedState::BothSynced(state) => { items.insert(scenario.url.clone(), ItemDescription::expected(state)); },
+                LocatedState::Local(_) | LocatedState::Remote(_) => panic!("You are not supposed to expect an item in this state after sync"),
+            }
+        }
+        let expected = populate_test_source_after_sync(&self.scenarii, &self.folder.join("expected")).await;
+        (provider, Expectation { items, expected: Arc::new(expected), mock_behaviour })
+    }
+}
+
+/// What both sources of a [`ScenarioProvider`] are expected to contain after a sync (see [`assert_converged`])
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    items: BTreeMap<Url, ItemDescription>,
+    /// A source that contains exactly what both sources are expected to contain
+    expected: Arc<Cache>,
+    mock_behaviour: Arc<Mutex<MockBehaviour>>,
+}
+
+impl Expectation {
+    /// The items both sources should contain, by URL
+    pub fn items(&self) -> &BTreeMap<Url, ItemDescription> {
+        &self.items
+    }
+}
+
+/// What is observable of an item in a scenario
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemDescription {
+    pub calendar: Url,
+    pub name: String,
+    pub completed: bool,
+    /// Whether the item is synced, i.e. has no change left to push (in the local source)
+    pub synced: bool,
+}
+
+impl ItemDescription {
+    fn expected(state: &ItemState) -> Self {
+        Self { calendar: state.calendar.clone(), name: state.name.clone(), completed: state.completed, synced: true }
+    }
+
+    fn of(calendar: &Url, item: &Item) -> Self {
+        let completed = match item {
+            Item::Task(task) => task.completed(),
+            Item::Event(_) => false,
+        };
+        Self { calendar: calendar.clone(), name: item.name().to_string(), completed, synced: matches!(item.sync_status(), SyncStatus::Synced(_)) }
+    }
+}
+
+impl Display for ItemDescription {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "\"{}\"{}{} in {}", self.name,
+            if self.completed { ", completed" } else { "" },
+            if self.synced { "" } else { ", not synced" },
+            self.calendar)
+    }
+}
+
+/// Check both sources of a provider contain what a scenario expects them to contain after a sync, and panic with a description of every difference otherwise.
+///
+/// The mock behaviour of the server is suspended from then on, so that it does not fail while it is inspected
+pub async fn assert_converged(provider: &ScenarioProvider, expectation: &Expectation) {
+    expectation.mock_behaviour.lock().unwrap().suspend();
+
+    let mut differences = Vec::new();
+    for (side, source) in [("local", provider.local()), ("remote", provider.remote())] {
+        let actual = describe(source);
+        for (url, expected) in &expectation.items {
+            match actual.get(url) {
+                None => differences.push(format!("{}: missing {} ({})", side, url, expected)),
+                Some(found) if found != expected => differences.push(format!("{}: {} is {}, instead of {}", side, url, found, expected)),
+                Some(_) => (),
+            }
+        }
+        for (url, found) in &actual {
+            if !expectation.items.contains_key(url) {
+                differences.push(format!("{}: unexpected {} ({})", side, url, found));
+            }
+        }
+    }
+
+    // The descriptions above only tell how items differ. Calendars (names, colors, components), UIDs and sync status variants are compared as well
+    if differences.is_empty() {
+        for (side, source) in [("local", provider.local()), ("remote", provider.remote())] {
+            if !source.has_same_observable_content_as(&expectation.expected).await.unwrap() {
+                differences.push(format!("{}: the observable content of the calendars differs from the expected one", side));
+            }
+        }
+        if !provider.remote().has_same_observable_content_as(provider.local()).await.unwrap() {
+            differences.push("the observable content of the local and remote sources differs".to_string());
+        }
+    }
+
+    if !differences.is_empty() {
+        panic!("The sources have not converged to the expected state:\n    {}", differences.join("\n    "));
+    }
 }
 
-/// Build a `Provider` that contains the data (defined in the given scenarii) after sync
-pub async fn populate_test_provider_after_sync(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>) -> Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
-    populate_test_provider(scenarii, mock_behaviour, true).await
+/// The items of every calendar of a source, by URL
+fn describe(source: &Cache) -> BTreeMap<Url, ItemDescription> {
+    let mut items = BTreeMap::new();
+    for (cal_url, cal) in source.get_calendars_sync().unwrap() {
+        let cal = cal.lock().unwrap();
+        for (url, item) in cal.get_items_sync().unwrap() {
+            items.insert(url, ItemDescription::of(&cal_url, item));
+        }
+    }
+    items
 }
 
-async fn populate_test_provider(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>, populate_for_final_state: bool) -> Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
-    let mut local = Cache::new(&PathBuf::from(String::from("test_cache/local/")));
-    let mut remote = Cache::new(&PathBuf::from(String::from("test_cache/remote/")));
+
+/// Build a `Provider` that contains the data (defined in the given scenarii) before sync
+#[allow(clippy::await_holding_lock)]
+async fn populate_test_provider_before_sync(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>, folder: &Path) -> ScenarioProvider {
+    let mut local = Cache::new(&folder.join("local"));
+    let mut remote = Cache::new(&folder.join("remote"));
     remote.set_mock_behaviour(Some(mock_behaviour));
 
     // Create the initial state, as if we synced both sources in a given state
     for item in scenarii {
-        let required_state = if populate_for_final_state { &item.after_sync } else { &item.initial_state };
-        let (state, sync_status) = match required_state {
+        let (state, sync_status) = match &item.initial_state {
             LocatedState::None => continue,
-            LocatedState::Local(s) => {
-                assert!(populate_for_final_state == false, "You are not supposed to expect an item in this state after sync");
-                (s, SyncStatus::NotSynced)
-            },
-            LocatedState::Remote(s) => {
-                assert!(populate_for_final_state == false, "You are not supposed to expect an item in this state after sync");
-                (s, SyncStatus::random_synced())
-            }
+            LocatedState::Local(s) => (s, SyncStatus::NotSynced),
+            LocatedState::Remote(s) => (s, SyncStatus::random_synced()),
             LocatedState::BothSynced(s) => (s, SyncStatus::random_synced()),
         };
 
-        let now = Utc::now();
-        let completion_status = match state.completed {
-            false => CompletionStatus::Uncompleted,
-            true => CompletionStatus::Completed(Some(now)),
-        };
+        let new_item = test_task(item, state, sync_status);
 
-        let new_item = Item::Task(
-            Task::new_with_parameters(
-                state.name.clone(),
-                item.url.to_string(),
-                item.url.clone(),
-                completion_status,
-                sync_status,
-                Some(now),
-                now,
-                "prod_id".to_string(), Vec::new(),
-            ));
-
-        match required_state {
+        match &item.initial_state {
             LocatedState::None => panic!("Should not happen, we've continued already"),
             LocatedState::Local(s) => {
                 get_or_insert_calendar(&mut local,  &s.calendar).await.unwrap().lock().unwrap().add_item(new_item).await.unwrap();
@@ -660,11 +803,51 @@ async fn populate_test_provider(scenarii: &[ItemScenario], mock_behaviour: Arc<M
             },
         }
     }
-    Provider::new(remote, local)
+    let mut provider = Provider::new(remote, local);
+    apply_changes_on_provider(&mut provider, scenarii).await;
+    provider
+}
+
+/// Build a source that contains the data (defined in the given scenarii) both sources are expected to contain after sync
+#[allow(clippy::await_holding_lock)]
+async fn populate_test_source_after_sync(scenarii: &[ItemScenario], folder: &Path) -> Cache {
+    let mut expected = Cache::new(folder);
+    for item in scenarii {
+        match &item.after_sync {
+            LocatedState::None => (),
+            LocatedState::BothSynced(s) => {
+                let new_item = test_task(item, s, SyncStatus::random_synced());
+                get_or_insert_calendar(&mut expected, &s.calendar).await.unwrap().lock().unwrap().add_item(new_item).await.unwrap();
+            },
+            LocatedState::Local(_) | LocatedState::Remote(_) => panic!("You are not supposed to expect an item in this state after sync"),
+        }
+    }
+    expected
+}
+
+/// The task a scenario describes, in a given state
+fn test_task(item: &ItemScenario, state: &ItemState, sync_status: SyncStatus) -> Item {
+    let now = Utc::now();
+    let completion_status = match state.completed {
+        false => CompletionStatus::Uncompleted,
+        true => CompletionStatus::Completed(Some(now)),
+    };
+
+    Item::Task(
+        Task::new_with_parameters(
+            state.name.clone(),
+            item.url.to_string(),
+            item.url.clone(),
+            completion_status,
+            sync_status,
+            Some(now),
+            now,
+            "prod_id".to_string(), Vec::new(),
+        ))
 }
 
 /// Apply `local_changes_to_apply` and `remote_changes_to_apply` to a provider that contains data before sync
-async fn apply_changes_on_provider(provider: &mut Provider<Cache, CachedCalendar, Cache, CachedCalendar>, scenarii: &[ItemScenario]) {
+async fn apply_changes_on_provider(provider: &mut ScenarioProvider, scenarii: &[ItemScenario]) {
     // Apply changes to each item
     for item in scenarii {
         let initial_calendar_url = match &item.initial_state {
@@ -723,6 +906,7 @@ where
     }
 }
 
+#[allow(clippy::await_holding_lock)]
 async fn apply_changes_on_an_existing_item<S, C>(source: &S, calendar_url: &Url, item_url: &Url, change: &ChangeToApply, is_remote: bool)
 where
     S: CalDavSource<C>,
@@ -764,6 +948,7 @@ where
 }
 
 /// Create an item, and returns the URL of the calendar it was inserted in
+#[allow(clippy::await_holding_lock)]
 async fn create_test_item<S, C>(source: &S, change: &ChangeToApply) -> Url
 where
     S: CalDavSource<C>,
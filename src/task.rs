@@ -2,12 +2,34 @@
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use std::error::Error;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
 use ical::property::Property;
 use url::Url;
 
-use crate::item::SyncStatus;
+use crate::item::{Item, RawComponent, SyncStatus};
 use crate::utils::random_url;
+use crate::datetime::CalDateTime;
+use crate::attachment::Attachment;
+use crate::quick_add::TaskBuilder;
+use crate::provider::conflict::DerivedIdentity;
+
+/// The iCal property that tells which devices created and last modified a task (see [`crate::config::DEVICE_ID`]).
+///
+/// Its value is the device that last modified the task, and its `X-ORIGIN` parameter is the device that created it, e.g. `X-KITCHEN-FRIDGE-DEVICE;X-ORIGIN=laptop:phone`
+pub const DEVICE_PROPERTY: &str = "X-KITCHEN-FRIDGE-DEVICE";
+/// The parameter of [`DEVICE_PROPERTY`] that contains the device that created a task
+pub(crate) const DEVICE_ORIGIN_PARAMETER: &str = "X-ORIGIN";
+
+/// Which devices created and last modified a task
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DeviceStamp {
+    pub origin: Option<String>,
+    pub last_modified_by: Option<String>,
+}
 
 /// RFC5545 defines the completion as several optional fields, yet some combinations make no sense.
 /// This enum provides an API that forbids such impossible combinations.
@@ -29,6 +51,25 @@ impl CompletionStatus {
     }
 }
 
+/// The time tracking values a task may have, stored as iCal durations in X- properties (see [`Task::time_tracking`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeTracking {
+    /// How much time has been spent on the task so far (`X-TIME-SPENT`)
+    Spent,
+    /// How much time is still needed to complete the task, when it differs from its estimate (`X-TIME-REMAINING`)
+    Remaining,
+}
+
+impl TimeTracking {
+    /// The name of the iCal property this value is stored in
+    pub fn property_name(&self) -> &'static str {
+        match self {
+            Self::Spent => "X-TIME-SPENT",
+            Self::Remaining => "X-TIME-REMAINING",
+        }
+    }
+}
+
 /// A to-do task
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Task {
@@ -49,10 +90,27 @@ pub struct Task {
     last_modified: DateTime<Utc>,
     /// The completion status of this task
     completion_status: CompletionStatus,
+    /// The date this task is due (iCal `DUE`)
+    #[serde(default)]
+    due: Option<CalDateTime>,
+    /// The date this task starts (iCal `DTSTART`)
+    #[serde(default)]
+    start: Option<CalDateTime>,
 
     /// The display name of the task
     name: String,
 
+    /// The documents attached to this task (iCal `ATTACH`)
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    /// The alarms of this task (iCal `VALARM` components)
+    #[serde(default)]
+    alarms: Vec<RawComponent>,
+
+
+    /// Which devices created and last modified this task (iCal [`DEVICE_PROPERTY`])
+    #[serde(default)]
+    device_stamp: DeviceStamp,
 
     /// The PRODID, as defined in iCal files
     ical_prod_id: String,
@@ -60,6 +118,11 @@ pub struct Task {
     /// Extra parameters that have not been parsed from the iCal file (because they're not supported (yet) by this crate).
     /// They are needed to serialize this item into an equivalent iCal file
     extra_parameters: Vec<Property>,
+
+    /// Other components that share the UID of this task in the same iCal resource (see [`crate::ical::MultipleComponentsPolicy`]).
+    /// They are needed to serialize this item into an equivalent iCal file
+    #[serde(default)]
+    extra_components: Vec<RawComponent>,
 }
 
 
@@ -77,7 +140,9 @@ impl Task {
             } else { CompletionStatus::Uncompleted };
         let ical_prod_id = crate::ical::default_prod_id();
         let extra_parameters = Vec::new();
+        let device = crate::config::DEVICE_ID.lock().unwrap().clone();
         Self::new_with_parameters(name, new_uid, new_url, new_completion_status, new_sync_status, new_creation_date, new_last_modified, ical_prod_id, extra_parameters)
+            .with_device_stamp(DeviceStamp { origin: device.clone(), last_modified_by: device })
     }
 
     /// Create a new Task instance, that may be synced on the server already
@@ -95,11 +160,65 @@ impl Task {
             sync_status,
             creation_date,
             last_modified,
+            due: None,
+            start: None,
+            attachments: Vec::new(),
+            alarms: Vec::new(),
+            device_stamp: DeviceStamp::default(),
             ical_prod_id,
             extra_parameters,
+            extra_components: Vec::new(),
         }
     }
 
+    /// Parse a "quick add" text input (e.g. `buy milk tomorrow 5pm #errands !high`), that can then be turned into a new Task.
+    ///
+    /// Relative dates are computed from `reference_time` (usually now), and dates and times are interpreted in `default_tz`.
+    /// See the [`crate::quick_add`] module for the supported grammar.
+    pub fn quick_add(input: &str, reference_time: &DateTime<Utc>, default_tz: &Tz) -> TaskBuilder {
+        TaskBuilder::parse(input, reference_time, default_tz)
+    }
+
+    /// Set the extra parameters of a Task that is being created.
+    pub(crate) fn with_extra_parameters(mut self, extra_parameters: Vec<Property>) -> Self {
+        self.extra_parameters = extra_parameters;
+        self
+    }
+
+    /// Set the components that share the UID of a Task that is being created out of an iCal file.
+    pub(crate) fn with_extra_components(mut self, extra_components: Vec<RawComponent>) -> Self {
+        self.extra_components = extra_components;
+        self
+    }
+
+    /// Set the attachments of a Task that is being created (e.g. out of an iCal file).
+    /// Contrary to [`Task::set_attachments`], this does not alter its sync status nor its last modification date
+    pub(crate) fn with_attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
+    /// Set the alarms of a Task that is being created (e.g. out of an iCal file).
+    /// Contrary to [`Task::set_alarms`], this does not alter its sync status nor its last modification date
+    pub(crate) fn with_alarms(mut self, alarms: Vec<RawComponent>) -> Self {
+        self.alarms = alarms;
+        self
+    }
+
+    /// Set the devices that created and last modified a Task that is being created out of an iCal file
+    pub(crate) fn with_device_stamp(mut self, device_stamp: DeviceStamp) -> Self {
+        self.device_stamp = device_stamp;
+        self
+    }
+
+    /// Set the due and start dates of a Task that is being created (e.g. out of an iCal file).
+    /// Contrary to [`Task::set_due`], this does not alter its sync status nor its last modification date
+    pub(crate) fn with_dates(mut self, due: Option<CalDateTime>, start: Option<CalDateTime>) -> Self {
+        self.due = due;
+        self.start = start;
+        self
+    }
+
     pub fn url(&self) -> &Url       { &self.url         }
     pub fn uid(&self) -> &str       { &self.uid         }
     pub fn name(&self) -> &str      { &self.name        }
@@ -109,24 +228,110 @@ impl Task {
     pub fn last_modified(&self) -> &DateTime<Utc> { &self.last_modified }
     pub fn creation_date(&self) -> Option<&DateTime<Utc>>   { self.creation_date.as_ref() }
     pub fn completion_status(&self) -> &CompletionStatus    { &self.completion_status }
+    pub fn due(&self) -> Option<&CalDateTime>               { self.due.as_ref() }
+    pub fn start(&self) -> Option<&CalDateTime>             { self.start.as_ref() }
+    pub fn attachments(&self) -> &[Attachment]              { &self.attachments }
+    pub fn alarms(&self) -> &[RawComponent]                 { &self.alarms }
     pub fn extra_parameters(&self) -> &[Property]           { &self.extra_parameters }
+    pub fn extra_components(&self) -> &[RawComponent]       { &self.extra_components }
+    /// The device that created this task, in case it has been created by a device that has a [`crate::config::DEVICE_ID`]
+    pub fn origin_device(&self) -> Option<&str>             { self.device_stamp.origin.as_deref() }
+    /// The device that last modified this task, in case it has been modified by a device that has a [`crate::config::DEVICE_ID`]
+    pub fn last_modified_by(&self) -> Option<&str>          { self.device_stamp.last_modified_by.as_deref() }
 
-    #[cfg(any(test, feature = "integration_tests"))]
+    /// The jCal ([RFC7265](https://tools.ietf.org/html/rfc7265)) representation of this task
+    pub fn to_jcal(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        crate::ical::ical_to_jcal(&crate::ical::build_from_task(self)?)
+    }
+
+    /// Parse the jCal representation of a task (see [`Task::to_jcal`])
+    pub fn from_jcal(jcal: &serde_json::Value, item_url: Url, sync_status: SyncStatus) -> Result<Self, Box<dyn Error>> {
+        match Item::from_jcal(jcal, item_url, sync_status)? {
+            Item::Task(task) => Ok(task),
+            Item::Event(_) => Err("This jCal data is not a task".into()),
+        }
+    }
+
+    #[cfg(any(test, feature = "test-utils"))]
     pub fn has_same_observable_content_as(&self, other: &Task) -> bool {
            self.url == other.url
         && self.uid == other.uid
         && self.name == other.name
+        && self.due == other.due
+        && self.start == other.start
+        && self.attachments == other.attachments
+        && self.alarms == other.alarms
         // sync status must be the same variant, but we ignore its embedded version tag
         && std::mem::discriminant(&self.sync_status) == std::mem::discriminant(&other.sync_status)
         // completion status must be the same variant, but we ignore its embedded completion date (they are not totally mocked in integration tests)
         && std::mem::discriminant(&self.completion_status) == std::mem::discriminant(&other.completion_status)
         // last modified dates are ignored (they are not totally mocked in integration tests)
+        // device stamps are ignored as well: they tell who made a change, not what the task is
     }
 
     pub fn set_sync_status(&mut self, new_status: SyncStatus) {
         self.sync_status = new_status;
     }
 
+    /// Change the URL of this task, e.g. because the server it is stored on has moved.
+    /// This is not a modification of the task, its sync status is not altered
+    pub(crate) fn set_url(&mut self, new_url: Url) {
+        self.url = new_url;
+    }
+
+    /// Whether this task takes part in a scheduling exchange, i.e. has an iCal `ORGANIZER` or `ATTENDEE` property ([RFC6638](https://tools.ietf.org/html/rfc6638#section-3.1))
+    pub fn is_scheduling_object(&self) -> bool {
+        self.extra_parameters.iter().any(|prop| prop.name == "ORGANIZER" || prop.name == "ATTENDEE")
+    }
+
+    /// Replace the `ATTENDEE` properties of this task with the ones of another version of it, e.g. the one the server has updated after an attendee has replied.
+    /// They are put where the first attendee was, so that the other properties keep their order.
+    /// This is not a modification of the task, its sync status is not altered
+    pub(crate) fn merge_attendees_of(&mut self, other: &Task) {
+        let position = self.extra_parameters.iter().position(|prop| prop.name == "ATTENDEE")
+            .unwrap_or(self.extra_parameters.len());
+        self.extra_parameters.retain(|prop| prop.name != "ATTENDEE");
+        let attendees = other.extra_parameters.iter().filter(|prop| prop.name == "ATTENDEE").cloned();
+        self.extra_parameters.splice(position..position, attendees);
+    }
+
+    /// Take the fields a user sets (name, completion, dates) from another version of this task, e.g. the one that has been uploaded to a server that has rewritten it.
+    /// This is not a modification of the task, its sync status is not altered
+    pub(crate) fn keep_user_fields_of(&mut self, other: &Task) {
+        self.name = other.name.clone();
+        self.completion_status = other.completion_status.clone();
+        self.due = other.due.clone();
+        self.start = other.start.clone();
+    }
+
+    /// Returns a copy of this task, with the given name and UID and a new URL in the given calendar, that has not been synced yet
+    pub(crate) fn derive(&self, parent_calendar_url: &Url, identity: DerivedIdentity) -> Self {
+        let mut duplicate = self.clone();
+        duplicate.name = identity.name;
+        duplicate.uid = identity.uid;
+        duplicate.url = random_url(parent_calendar_url);
+        duplicate.sync_status = SyncStatus::NotSynced;
+        duplicate
+    }
+
+    /// Mark this task as modified, even though nothing has changed, so that it is uploaded again at the next sync.
+    ///
+    /// This updates its "last modified" field (and its `DTSTAMP`), and increments its `SEQUENCE` if it has one.
+    /// The setters of this struct do nothing when they are given the current value, this is the way to force an upload anyway.
+    pub fn touch(&mut self) {
+        self.mark_modified();
+        for prop in self.extra_parameters.iter_mut().filter(|prop| prop.name == "SEQUENCE") {
+            if let Some(sequence) = prop.value.as_deref().and_then(|value| value.trim().parse::<u32>().ok()) {
+                prop.value = Some((sequence + 1).to_string());
+            }
+        }
+    }
+
+    fn mark_modified(&mut self) {
+        self.update_sync_status();
+        self.update_last_modified();
+    }
+
     fn update_sync_status(&mut self) {
         match &self.sync_status {
             SyncStatus::NotSynced => return,
@@ -143,14 +348,25 @@ impl Task {
 
     fn update_last_modified(&mut self) {
         self.last_modified = Utc::now();
+        let device = crate::config::DEVICE_ID.lock().unwrap().clone();
+        self.stamp_modification_by(device);
+    }
+
+    fn stamp_modification_by(&mut self, device: Option<String>) {
+        // Devices that have no identifier leave the stamps of the other devices untouched
+        if device.is_some() {
+            self.device_stamp.last_modified_by = device;
+        }
     }
 
 
     /// Rename a task.
-    /// This updates its "last modified" field
+    /// This updates its "last modified" field, unless the name is unchanged
     pub fn set_name(&mut self, new_name: String) {
-        self.update_sync_status();
-        self.update_last_modified();
+        if self.name == new_name {
+            return;
+        }
+        self.mark_modified();
         self.name = new_name;
     }
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
@@ -161,10 +377,242 @@ impl Task {
         self.name = new_name;
     }
 
-    /// Set the completion status
+    /// Set the due date.
+    /// This updates its "last modified" field, unless the value is unchanged
+    pub fn set_due(&mut self, new_due: Option<CalDateTime>) {
+        if self.due == new_due {
+            return;
+        }
+        self.mark_modified();
+        self.due = new_due;
+    }
+
+    /// Set the start date.
+    /// This updates its "last modified" field, unless the value is unchanged
+    pub fn set_start(&mut self, new_start: Option<CalDateTime>) {
+        if self.start == new_start {
+            return;
+        }
+        self.mark_modified();
+        self.start = new_start;
+    }
+
+    /// Set the creation date (iCal `CREATED`).
+    /// This updates its "last modified" field, unless the value is unchanged
+    pub(crate) fn set_creation_date(&mut self, new_creation_date: Option<DateTime<Utc>>) {
+        if self.creation_date == new_creation_date {
+            return;
+        }
+        self.mark_modified();
+        self.creation_date = new_creation_date;
+    }
+
+    /// Set the attachments.
+    /// This updates its "last modified" field, unless the value is unchanged
+    pub fn set_attachments(&mut self, new_attachments: Vec<Attachment>) {
+        if self.attachments == new_attachments {
+            return;
+        }
+        self.mark_modified();
+        self.attachments = new_attachments;
+    }
+
+    /// Set the alarms (`VALARM` components).
+    /// This updates its "last modified" field, unless the value is unchanged
+    pub fn set_alarms(&mut self, new_alarms: Vec<RawComponent>) {
+        if self.alarms == new_alarms {
+            return;
+        }
+        self.mark_modified();
+        self.alarms = new_alarms;
+    }
+
+    /// The categories (or tags) of this task, as found in its iCal `CATEGORIES` properties
+    pub fn categories(&self) -> Vec<String> {
+        self.extra_parameters.iter()
+            .filter(|prop| prop.name == "CATEGORIES")
+            .filter_map(|prop| prop.value.as_deref())
+            .flat_map(crate::ical::split_unescaped_commas)
+            .map(|category| crate::ical::unescape_text(category.trim()))
+            .filter(|category| !category.is_empty())
+            .collect()
+    }
+
+    /// Set the categories (or tags) of this task.
+    /// This updates its "last modified" field, unless the categories are unchanged
+    pub fn set_categories(&mut self, new_categories: Vec<String>) {
+        if self.categories() == new_categories {
+            return;
+        }
+        self.mark_modified();
+        self.extra_parameters.retain(|prop| prop.name != "CATEGORIES");
+        if !new_categories.is_empty() {
+            self.extra_parameters.push(Property {
+                name: "CATEGORIES".to_string(),
+                params: None,
+                value: Some(new_categories.iter().map(|category| crate::ical::escape_text(category)).collect::<Vec<_>>().join(",")),
+            });
+        }
+    }
+
+    /// The UIDs of the tasks this task depends on, i.e. that must be completed before this one can be worked on.
+    ///
+    /// These are its iCal `RELATED-TO` properties whose `RELTYPE` is `DEPENDS-ON` or `FINISHTOSTART` ([RFC 9253](https://datatracker.ietf.org/doc/html/rfc9253)).
+    /// Other relations (e.g. the default `PARENT` one) are not dependencies.
+    pub fn dependencies(&self) -> Vec<String> {
+        self.extra_parameters.iter()
+            .filter(|prop| prop.name == "RELATED-TO")
+            .filter(|prop| {
+                let reltype = prop.params.iter().flatten()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("RELTYPE"))
+                    .and_then(|(_, values)| values.first());
+                matches!(reltype, Some(reltype) if reltype.eq_ignore_ascii_case("DEPENDS-ON") || reltype.eq_ignore_ascii_case("FINISHTOSTART"))
+            })
+            .filter_map(|prop| prop.value.as_deref())
+            .map(|uid| uid.trim().to_string())
+            .filter(|uid| !uid.is_empty())
+            .collect()
+    }
+
+    /// The UID of the parent of this task (i.e. the task it is a subtask of), if any.
+    ///
+    /// This is its first iCal `RELATED-TO` property whose `RELTYPE` is `PARENT`, which is the default one.
+    /// Parent links may be inconsistent (e.g. form cycles), see [`crate::calendar::hierarchy::Hierarchy`] to build a tree out of them.
+    pub fn parent(&self) -> Option<String> {
+        self.extra_parameters.iter()
+            .filter(|prop| prop.name == "RELATED-TO")
+            .filter(|prop| {
+                let reltype = prop.params.iter().flatten()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("RELTYPE"))
+                    .and_then(|(_, values)| values.first());
+                reltype.is_none_or(|reltype| reltype.eq_ignore_ascii_case("PARENT"))
+            })
+            .filter_map(|prop| prop.value.as_deref())
+            .map(|uid| uid.trim().to_string())
+            .find(|uid| !uid.is_empty())
+    }
+
+    /// The description of this task (iCal `DESCRIPTION`), if any
+    pub fn description(&self) -> Option<&str> {
+        self.extra_parameters.iter()
+            .find(|prop| prop.name == "DESCRIPTION")
+            .and_then(|prop| prop.value.as_deref())
+    }
+
+    /// How long this task is expected to take, i.e. its iCal `DURATION` (e.g. as set by Tasks.org or OpenTasks).
+    ///
+    /// This is `None` when the task has a start date: its `DURATION` then tells when it is due rather than how long it takes (see RFC5545 section 3.6.2)
+    pub fn estimated_duration(&self) -> Option<Duration> {
+        if self.start.is_some() {
+            return None;
+        }
+        self.duration_property("DURATION")
+    }
+
+    /// Set how long this task is expected to take (see [`Task::estimated_duration`]).
+    /// This updates its "last modified" field, unless the value is unchanged.
+    ///
+    /// This fails for tasks that have a start date, and for negative durations
+    pub fn set_estimated_duration(&mut self, new_duration: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        if self.start.is_some() {
+            return Err(format!("Task {} has a start date, its DURATION cannot be an estimate", self.uid).into());
+        }
+        self.set_duration_property("DURATION", new_duration)
+    }
+
+    /// A time tracking value of this task, in case it has one
+    pub fn time_tracking(&self, value: TimeTracking) -> Option<Duration> {
+        self.duration_property(value.property_name())
+    }
+
+    /// Set a time tracking value of this task (see [`Task::time_tracking`]).
+    /// This updates its "last modified" field, unless the value is unchanged. This fails for negative durations
+    pub fn set_time_tracking(&mut self, value: TimeTracking, new_duration: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        self.set_duration_property(value.property_name(), new_duration)
+    }
+
+    /// How much time is still needed to complete this task: its [`TimeTracking::Remaining`] time, or else its estimated duration. This is zero for completed tasks
+    pub fn estimated_remaining(&self) -> Option<Duration> {
+        if self.completed() {
+            return Some(Duration::zero());
+        }
+        self.time_tracking(TimeTracking::Remaining).or_else(|| self.estimated_duration())
+    }
+
+    fn duration_property(&self, name: &str) -> Option<Duration> {
+        self.extra_parameters.iter()
+            .find(|prop| prop.name == name)
+            .and_then(|prop| prop.value.as_deref())
+            .and_then(crate::datetime::parse_duration)
+            .filter(|duration| *duration >= Duration::zero())
+    }
+
+    fn set_duration_property(&mut self, name: &str, new_duration: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        if new_duration.is_some_and(|duration| duration < Duration::zero()) {
+            return Err(format!("Invalid negative {} for task {}", name, self.uid).into());
+        }
+        if self.duration_property(name) == new_duration {
+            return Ok(());
+        }
+        self.mark_modified();
+        // The value is replaced in place, so that the other properties keep their order
+        let new_value = new_duration.map(|duration| crate::datetime::format_duration(&duration));
+        match (self.extra_parameters.iter().position(|prop| prop.name == name), new_value) {
+            (Some(index), Some(value)) => self.extra_parameters[index] = Property { name: name.to_string(), params: None, value: Some(value) },
+            (Some(_), None) => self.extra_parameters.retain(|prop| prop.name != name),
+            (None, Some(value)) => self.extra_parameters.push(Property { name: name.to_string(), params: None, value: Some(value) }),
+            (None, None) => (),
+        }
+        Ok(())
+    }
+
+    /// Gives a mutable access to the attachments, e.g. to move their content out when serializing this task.
+    /// This is not a modification of the task, its sync status is not altered
+    pub(crate) fn attachments_mut(&mut self) -> &mut [Attachment] {
+        &mut self.attachments
+    }
+
+    /// Returns whether this task is not completed, and is due before `now`.
+    ///
+    /// Dates without a timezone are interpreted in `reference_tz` (see e.g. [`crate::datetime::system_timezone`]).
+    /// Tasks that are due on a date (without a time) are overdue only once this date is over (see [`CalDateTime::cmp_in_tz`]).
+    pub fn is_overdue(&self, now: &DateTime<Utc>, reference_tz: &Tz) -> bool {
+        if self.completed() {
+            return false;
+        }
+        match &self.due {
+            None => false,
+            Some(due) => due.cmp_in_tz(&CalDateTime::Utc(*now), reference_tz) == Ordering::Less,
+        }
+    }
+
+    /// Returns whether this task is due on a given date, as seen from `reference_tz`
+    pub fn is_due_on(&self, date: &NaiveDate, reference_tz: &Tz) -> bool {
+        self.due.as_ref().map(|due| &due.date_in(reference_tz) == date) == Some(true)
+    }
+
+    /// Returns whether this task is due today, as seen from `reference_tz` (see [`CalDateTime::days_until`]), whether it is completed or not
+    pub fn is_due_today(&self, now: &DateTime<Utc>, reference_tz: &Tz) -> bool {
+        self.due.as_ref().map(|due| due.days_until(now, reference_tz) == 0) == Some(true)
+    }
+
+    /// Compare the due dates of two tasks, e.g. to sort them. Tasks without a due date come last, and tasks that are due on a date (without a time) come after the ones that are due at a given time on that day (see [`CalDateTime::cmp_in_tz`])
+    pub fn cmp_by_due(&self, other: &Task, reference_tz: &Tz) -> Ordering {
+        match (&self.due, &other.due) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(l), Some(r)) => l.cmp_in_tz(r, reference_tz),
+        }
+    }
+
+    /// Set the completion status.
+    /// This updates its "last modified" field, unless the status (including its completion date) is unchanged
     pub fn set_completion_status(&mut self, new_completion_status: CompletionStatus) {
-        self.update_sync_status();
-        self.update_last_modified();
+        if self.completion_status == new_completion_status {
+            return;
+        }
+        self.mark_modified();
         self.completion_status = new_completion_status;
     }
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
@@ -174,3 +622,125 @@ impl Task {
         self.completion_status = new_completion_status;
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item::{Item, VersionTag};
+
+    #[test]
+    fn test_jcal_round_trip() {
+        let url: Url = "https://some.calend.ar/calendar/some-uid.ics".parse().unwrap();
+        let mut task = Task::new("Buy milk".to_string(), false, &url);
+        task.set_due(Some(CalDateTime::from("2021-11-03T22:00:00Z".parse::<DateTime<Utc>>().unwrap())));
+        task.set_categories(vec!["Shopping".to_string(), "Food".to_string()]);
+
+        let jcal = task.to_jcal().unwrap();
+        assert_eq!(jcal[0], "vcalendar");
+        let via_jcal = Task::from_jcal(&jcal, task.url().clone(), task.sync_status().clone()).unwrap();
+        let ical = crate::ical::build_from_task(&task).unwrap();
+        let via_ical = crate::ical::parse(&ical, task.url().clone(), task.sync_status().clone()).unwrap();
+
+        assert!(via_ical.unwrap_task().has_same_observable_content_as(&via_jcal));
+        assert_eq!(via_jcal.categories(), task.categories());
+        assert_eq!(
+            crate::ical::canonical_form(&Item::Task(via_jcal)).unwrap(),
+            crate::ical::canonical_form(&via_ical).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_device_stamps() {
+        let ical = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Some app\r\nBEGIN:VTODO\r\nUID:some-uid\r\nDTSTAMP:20210321T001600\r\nSUMMARY:Buy milk\r\nX-KITCHEN-FRIDGE-DEVICE;X-ORIGIN=laptop:phone\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let url: Url = "https://some.calend.ar/calendar/some-uid.ics".parse().unwrap();
+        let item = crate::ical::parse(ical, url, SyncStatus::Synced(VersionTag::from("etag".to_string()))).unwrap();
+        let mut task = item.unwrap_task().clone();
+        assert_eq!(task.origin_device(), Some("laptop"));
+        assert_eq!(task.last_modified_by(), Some("phone"));
+        assert!(task.extra_parameters().is_empty());
+
+        // A device without an identifier does not alter the stamps
+        let unchanged = task.clone();
+        task.stamp_modification_by(None);
+        assert_eq!(task.device_stamp, unchanged.device_stamp);
+
+        task.stamp_modification_by(Some("tablet".to_string()));
+        assert_eq!(task.origin_device(), Some("laptop"));
+        assert_eq!(task.last_modified_by(), Some("tablet"));
+        assert!(task.has_same_observable_content_as(&unchanged));
+
+        let built = crate::ical::build_from(&Item::Task(task)).unwrap();
+        assert!(built.contains("X-KITCHEN-FRIDGE-DEVICE;X-ORIGIN=laptop:tablet\r\n"));
+    }
+
+    #[test]
+    fn test_unchanged_values_and_touch() {
+        let ical = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Some app\r\nBEGIN:VTODO\r\nUID:some-uid\r\nDTSTAMP:20210321T001600\r\nSUMMARY:Buy milk\r\nDUE;VALUE=DATE:20210322\r\nCATEGORIES:home,shopping\r\nSEQUENCE:3\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let url: Url = "https://some.calend.ar/calendar/some-uid.ics".parse().unwrap();
+        let synced = SyncStatus::Synced(VersionTag::from("etag".to_string()));
+        let mut task = crate::ical::parse(ical, url, synced.clone()).unwrap().unwrap_task().clone();
+        let last_modified = *task.last_modified();
+
+        // Saving a form without changing anything
+        task.set_name(task.name().to_string());
+        task.set_due(task.due().cloned());
+        task.set_start(task.start().cloned());
+        task.set_categories(task.categories());
+        task.set_completion_status(task.completion_status().clone());
+        task.set_attachments(task.attachments().to_vec());
+        task.set_alarms(task.alarms().to_vec());
+        assert_eq!(task.sync_status(), &synced);
+        assert_eq!(task.last_modified(), &last_modified);
+
+        task.touch();
+        assert!(matches!(task.sync_status(), SyncStatus::LocallyModified(_)));
+        assert!(task.last_modified() > &last_modified);
+        let sequence = task.extra_parameters().iter().find(|prop| prop.name == "SEQUENCE").unwrap();
+        assert_eq!(sequence.value.as_deref(), Some("4"));
+
+        let mut task = crate::ical::parse(ical, task.url().clone(), synced).unwrap().unwrap_task().clone();
+        task.set_name("Buy oat milk".to_string());
+        assert!(matches!(task.sync_status(), SyncStatus::LocallyModified(_)));
+    }
+
+    #[test]
+    fn test_estimated_duration_and_time_tracking() {
+        let ical = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Some app\r\nBEGIN:VTODO\r\nUID:some-uid\r\nDTSTAMP:20210321T001600\r\nSUMMARY:Write the report\r\nDUE;VALUE=DATE:20210322\r\nDURATION:PT3H\r\nX-TIME-SPENT:PT1H30M\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let url: Url = "https://some.calend.ar/calendar/some-uid.ics".parse().unwrap();
+        let synced = SyncStatus::Synced(VersionTag::from("etag".to_string()));
+        let mut task = crate::ical::parse(ical, url, synced.clone()).unwrap().unwrap_task().clone();
+        assert_eq!(task.estimated_duration(), Some(Duration::hours(3)));
+        assert_eq!(task.time_tracking(TimeTracking::Spent), Some(Duration::minutes(90)));
+        assert_eq!(task.time_tracking(TimeTracking::Remaining), None);
+        assert_eq!(task.estimated_remaining(), Some(Duration::hours(3)));
+
+        // Neither the due date nor these values are lost on a round-trip
+        let built = crate::ical::build_from(&Item::Task(task.clone())).unwrap();
+        assert!(built.contains("DUE;VALUE=DATE:20210322\r\n"));
+        assert!(built.contains("DURATION:PT3H\r\n"));
+        assert!(built.contains("X-TIME-SPENT:PT1H30M\r\n"));
+
+        task.set_estimated_duration(Some(Duration::hours(3))).unwrap();
+        assert_eq!(task.sync_status(), &synced);
+        task.set_time_tracking(TimeTracking::Remaining, Some(Duration::hours(1))).unwrap();
+        assert!(matches!(task.sync_status(), SyncStatus::LocallyModified(_)));
+        assert_eq!(task.estimated_remaining(), Some(Duration::hours(1)));
+        assert!(task.set_time_tracking(TimeTracking::Spent, Some(Duration::hours(-1))).is_err());
+        task.set_time_tracking(TimeTracking::Spent, None).unwrap();
+        let names: Vec<&str> = task.extra_parameters().iter().map(|prop| prop.name.as_str()).collect();
+        assert_eq!(names, vec!["DURATION", "X-TIME-REMAINING"]);
+
+        task.set_completion_status(CompletionStatus::Completed(None));
+        assert_eq!(task.estimated_remaining(), Some(Duration::zero()));
+
+        // With a start date, DURATION tells when the task is due
+        task.set_estimated_duration(None).unwrap();
+        task.set_start(Some(CalDateTime::from("2021-03-21T10:00:00Z".parse::<DateTime<Utc>>().unwrap())));
+        assert!(task.set_estimated_duration(Some(Duration::hours(2))).is_err());
+        let ical = ical.replace("DURATION:PT3H", "DTSTART:20210321T100000Z\r\nDURATION:PT3H");
+        let task = crate::ical::parse(&ical, task.url().clone(), synced).unwrap().unwrap_task().clone();
+        assert_eq!(task.estimated_duration(), None);
+        assert!(crate::ical::build_from(&Item::Task(task)).unwrap().contains("DURATION:PT3H\r\n"));
+    }
+}
@@ -0,0 +1,84 @@
+//! A minimal HTTP server, for the unit tests that need to talk to a server
+
+use std::io::{BufRead, BufReader, Read, Write};
+
+use url::Url;
+
+/// A request received by a [`serve`]d server
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    /// The path of the request, along with its query (if any)
+    pub path: String,
+    /// The headers of the request, with lowercase names
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of a header of this request (the name is case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Starts a server on a local port, and returns the URL of the given path on it.
+///
+/// Every request is passed to `handler`, which returns the reply to send: a status line (e.g. `200 OK`), optionally followed by `\r\n`-separated headers, and by a body after an empty line.
+/// The `Content-Length` header is added to every reply, and connections are closed after each one.
+/// In case `handler` returns `None`, the connection is kept open, and the request never gets a reply.
+pub(crate) fn serve<F>(path: &str, mut handler: F) -> Url
+where
+    F: FnMut(&Request) -> Option<String> + Send + 'static,
+{
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}{}", listener.local_addr().unwrap(), path).parse().unwrap();
+    std::thread::spawn(move || {
+        let mut unanswered = Vec::new();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let request = match read_request(&stream) {
+                Some(request) => request,
+                None => continue,
+            };
+            match handler(&request) {
+                None => unanswered.push(stream),
+                Some(reply) => {
+                    let (head, body) = reply.split_once("\r\n\r\n").unwrap_or((&reply, ""));
+                    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", head, body.len(), body);
+                },
+            }
+        }
+    });
+    url
+}
+
+fn read_request(stream: &std::net::TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut request_line = request_line.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = Request { method, path, headers, body: Vec::new() };
+    let content_length = request.header("content-length").map_or(0, |length| length.parse().unwrap());
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
@@ -1,10 +1,31 @@
 //! Traits used by multiple structs in this crate
+//!
+//! ## Custom sources
+//!
+//! Any data source (e.g. a database of tasks) can be one side of a [`Provider`](crate::provider::Provider) sync, without having anything to do with CalDAV.
+//! It must implement [`SourceAdapter`] (and the [`CalDavSource`] it extends), and its calendars must implement [`BaseCalendar`] and either
+//! * [`CompleteCalendar`] to be the `local` side of the sync (this is what [`Cache`](crate::cache::Cache) does), or
+//! * [`DavCalendar`] to be the `remote` side of the sync (this is what [`Client`](crate::client::Client) does).
+//!
+//! Only the required methods of these traits are used by the sync engine. The provided ones have sensible defaults for sources that do not support the matching feature (e.g. ctags, subscriptions or date limits).
+//! Here is what the sync engine uses:
+//!
+//! | Capability                   | Source                              | Local calendars (`C: CompleteCalendar`)                          | Remote calendars (`C: DavCalendar`)                               |
+//! |------------------------------|-------------------------------------|------------------------------------------------------------------|-------------------------------------------------------------------|
+//! | list calendars               | [`CalDavSource::get_calendars`]     |                                                                  |                                                                   |
+//! | create a missing calendar    | [`CalDavSource::create_calendar`]   |                                                                  |                                                                   |
+//! | list item stubs              |                                     | [`CompleteCalendar::get_item_urls`] and their [`SyncStatus`]es   | [`DavCalendar::get_item_version_tags`]                            |
+//! | fetch items                  |                                     | [`CompleteCalendar::get_item_by_url`]                            | [`DavCalendar::get_items_by_url`]                                 |
+//! | upsert items                 |                                     | [`BaseCalendar::add_item`], [`BaseCalendar::update_item`]        | [`BaseCalendar::add_item`], [`BaseCalendar::update_item`]         |
+//! | delete items                 |                                     | [`CompleteCalendar::immediately_delete_item`]                    | [`DavCalendar::delete_item`]                                      |
+//! | sync metadata                |                                     | [`CompleteCalendar::set_last_synced_ctag`] and similar setters   | [`DavCalendar::get_ctag`]                                         |
 
 use std::error::Error;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use csscolorparser::Color;
 use url::Url;
 
@@ -12,25 +33,112 @@ use crate::item::SyncStatus;
 use crate::item::Item;
 use crate::item::VersionTag;
 use crate::calendar::SupportedComponents;
+use crate::calendar::ComponentSet;
+use crate::calendar::CalendarFilter;
+use crate::calendar::DateLimits;
+use crate::calendar::Subscription;
+use crate::calendar::ItemChanges;
+use crate::calendar::history::ChangeSource;
+use crate::provider::CalendarSyncPolicy;
+use crate::provider::quarantine::UploadFailure;
+use crate::provider::fidelity::AlteredUpload;
 use crate::resource::Resource;
+use crate::transfer_stats::TransferCounter;
 
 /// This trait must be implemented by data sources (either local caches or remote CalDAV clients)
 ///
 /// Note that some concrete types (e.g. [`crate::cache::Cache`]) can also provide non-async versions of these functions
 #[async_trait]
-pub trait CalDavSource<T: BaseCalendar> {
+pub trait CalDavSource<T: BaseCalendar>: Send {
     /// Returns the current calendars that this source contains
     /// This function may trigger an update (that can be a long process, or that can even fail, e.g. in case of a remote server)
     async fn get_calendars(&self) -> Result<HashMap<Url, Arc<Mutex<T>>>, Box<dyn Error>>;
+    /// Returns the URLs of calendars that this source knows about, but that could not be accessed during the last [`CalDavSource::get_calendars`] (e.g. a share that has been revoked)
+    fn unavailable_calendars(&self) -> HashSet<Url> {
+        HashSet::new()
+    }
+    /// Returns the running counter of the data exchanged with this source, in case this source is able to account for it (see [`crate::transfer_stats`])
+    fn transfer_counter(&self) -> Option<TransferCounter> {
+        None
+    }
+    /// Persist the current state of this source, e.g. when a sync has to stop early and will be resumed later on.
+    ///
+    /// This does nothing by default.
+    fn checkpoint(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
     /// Returns the calendar matching the URL
     async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<T>>>;
+    /// Returns the current calendars that match a filter, sorted by name (and by URL in case two calendars have the same name).
+    ///
+    /// This is e.g. useful for an app to let the user pick a calendar among the writable calendars that support tasks
+    async fn get_calendars_matching(&self, filter: &CalendarFilter) -> Result<Vec<Arc<Mutex<T>>>, Box<dyn Error>> {
+        let mut matching: Vec<(String, Url, Arc<Mutex<T>>)> = self.get_calendars().await?
+            .into_iter()
+            .filter_map(|(url, cal)| {
+                let (matches, name) = {
+                    let cal = cal.lock().unwrap();
+                    (filter.matches(&*cal), cal.name().to_string())
+                };
+                if matches { Some((name, url, cal)) } else { None }
+            })
+            .collect();
+        let collator = crate::collation::Collator::new(None);
+        matching.sort_by(|(name_l, url_l, _), (name_r, url_r, _)| collator.compare(name_l, name_r).then_with(|| url_l.cmp(url_r)));
+        Ok(matching.into_iter().map(|(_, _, cal)| cal).collect())
+    }
     /// Create a calendar if it did not exist, and return it
     async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>)
         -> Result<Arc<Mutex<T>>, Box<dyn Error>>;
+    /// Same as [`CalDavSource::create_calendar`], but the calendar also supports components this crate does not know (see [`ComponentSet`]).
+    ///
+    /// By default, these other components are not kept.
+    async fn create_calendar_with_components(&mut self, url: Url, name: String, components: ComponentSet, color: Option<Color>)
+        -> Result<Arc<Mutex<T>>, Box<dyn Error>>
+    {
+        self.create_calendar(url, name, components.known, color).await
+    }
 
     // Removing a calendar is not supported yet
 }
 
+/// The stable surface a data source exposes to be synced by a [`Provider`](crate::provider::Provider) (see the [custom sources](self#custom-sources))
+///
+/// Every item-level operation names the calendar it applies to, so that a source can be backed by anything that stores rows of items (e.g. a SQL database), rather than by calendar objects.
+/// It is implemented by [`Cache`](crate::cache::Cache) (the usual local source) and by [`Client`](crate::client::Client) (the usual remote source).
+///
+/// Since the calendars of a source are shared behind a [`Mutex`], implementations are allowed to hold the lock of a calendar while they await its methods. That is why the futures of this trait are not required to be `Send`.
+#[async_trait(?Send)]
+pub trait SourceAdapter<T: BaseCalendar>: CalDavSource<T> {
+    /// Returns the calendars this source contains.
+    ///
+    /// This is [`CalDavSource::get_calendars`] by default.
+    async fn list_calendars(&self) -> Result<HashMap<Url, Arc<Mutex<T>>>, Box<dyn Error>> {
+        self.get_calendars().await
+    }
+    /// Returns the URLs of the items of a calendar, along with their [`SyncStatus`] (items of a remote source are always [`SyncStatus::Synced`], with their current version tag)
+    async fn list_item_stubs(&self, calendar: &Url) -> Result<HashMap<Url, SyncStatus>, Box<dyn Error>>;
+    /// Returns some items of a calendar (in the same order as `urls`), `None` standing for items that do not exist
+    async fn fetch_items(&self, calendar: &Url, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>>;
+    /// Adds an item to a calendar, or replaces the item that has the same URL, and returns its new [`SyncStatus`]
+    async fn upsert_item(&mut self, calendar: &Url, item: Item) -> Result<SyncStatus, Box<dyn Error>>;
+    /// Removes an item from a calendar for good
+    async fn delete_item(&mut self, calendar: &Url, item_url: &Url) -> Result<(), Box<dyn Error>>;
+    /// Returns what this source knows about the last sync of a calendar
+    async fn sync_metadata(&self, calendar: &Url) -> Result<SyncMetadata, Box<dyn Error>>;
+    /// Records what is known about the last sync of a calendar. Remote sources, whose metadata is managed by the server, ignore this
+    async fn set_sync_metadata(&mut self, calendar: &Url, metadata: SyncMetadata) -> Result<(), Box<dyn Error>>;
+}
+
+/// What a source knows about the last sync of a calendar (see [`SourceAdapter::sync_metadata`])
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncMetadata {
+    /// The ctag of the calendar. For local sources, this is the ctag of the remote calendar at the start of the last successful sync (see [`CompleteCalendar::last_synced_ctag`]), for remote sources this is its current ctag (see [`DavCalendar::get_ctag`])
+    pub ctag: Option<VersionTag>,
+    /// The sync token of the last sync, for sources that support incremental syncs (see [`CompleteCalendar::last_sync_token`])
+    pub sync_token: Option<String>,
+}
+
 /// This trait contains functions that are common to all calendars
 ///
 /// Note that some concrete types (e.g. [`crate::calendar::cached_calendar::CachedCalendar`]) can also provide non-async versions of these functions
@@ -48,13 +156,25 @@ pub trait BaseCalendar {
     /// Returns the user-defined color of this calendar
     fn color(&self) -> Option<&Color>;
 
+    /// Returns the names of the components this calendar supports, that this crate does not know (e.g. `VJOURNAL`), see [`crate::calendar::ComponentSet`]
+    fn other_components(&self) -> &[String] {
+        &[]
+    }
+
+    /// Returns whether the current user is allowed to add or modify items in this calendar
+    fn is_writable(&self) -> bool {
+        true
+    }
+
     /// Add an item into this calendar, and return its new sync status.
     /// For local calendars, the sync status is not modified.
     /// For remote calendars, the sync status is updated by the server
     async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>>;
 
     /// Update an item that already exists in this calendar and returns its new `SyncStatus`
-    /// This replaces a given item at a given URL
+    /// This replaces a given item at a given URL.
+    ///
+    /// Remote calendars only replace the version the sync status of `item` refers to: in case the item has changed on the server since, this returns a [`crate::calendar::PreconditionFailedError`]
     async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>>;
 
     /// Returns whether this calDAV calendar supports to-do items
@@ -85,11 +205,51 @@ pub trait DavCalendar : BaseCalendar {
 
     /// Returns a set of items.
     /// This is usually faster than calling multiple consecutive [`DavCalendar::get_item_by_url`], since it only issues one HTTP request.
+    ///
+    /// In case only some of the items could be fetched, this returns a [`crate::calendar::PartialFetchError`] that contains them.
     async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>>;
 
-    /// Delete an item
+    /// Delete an item.
+    ///
+    /// Items whose version tag is known (i.e. that have been listed, fetched or uploaded by this calendar) are only deleted in case they have not changed on the server since.
+    /// Otherwise, this returns a [`crate::calendar::PreconditionFailedError`]
     async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>>;
 
+    /// Returns (and forgets) the URL an item that has just been uploaded to `url` is actually stored at, in case the server reported another one (e.g. with a `Content-Location` header).
+    ///
+    /// Items should then be referred to by this canonical URL, since the requested one may not be valid anymore.
+    fn take_canonical_url(&self, _url: &Url) -> Option<Url> {
+        None
+    }
+
+    /// Returns (and forgets) the version the server actually stores of an item that has just been uploaded to `url`, in case the server has rewritten its content (e.g. to normalise it).
+    ///
+    /// The local copy should then be refreshed from this version, otherwise it would look like a remote modification at the next sync.
+    fn take_rewritten_item(&self, _url: &Url) -> Option<Item> {
+        None
+    }
+
+    /// Returns (and forgets) the component an item that has just been requested from `url` contains instead of one this calendar supports (e.g. `VEVENT` in a calendar that only supports tasks).
+    ///
+    /// Such items are left out of the replies of [`DavCalendar::get_items_by_url`], rather than failing the whole batch.
+    /// `Some(None)` means the item contains no known component at all.
+    fn take_unsupported_component(&self, _url: &Url) -> Option<Option<String>> {
+        None
+    }
+
+    /// Returns (and forgets) whether an item that has just been requested from `url` has kept changing on the server while it was being fetched.
+    ///
+    /// Such items are left out of the replies of [`DavCalendar::get_items_by_url`], so that they are fetched again at the next sync.
+    /// Items that have been deleted since they have been listed are not reported here: they are simply missing from the replies (or replied as `None`).
+    fn take_unstable_item(&self, _url: &Url) -> bool {
+        false
+    }
+
+    /// The range of date-times this calendar accepts. Items out of this range are handled as [`crate::provider::DateLimitsPolicy`] says when they are uploaded
+    fn date_limits(&self) -> DateLimits {
+        DateLimits::default()
+    }
+
     /// Get the URLs of all current items in this calendar
     async fn get_item_urls(&self) -> Result<HashSet<Url>, Box<dyn Error>> {
         let items = self.get_item_version_tags().await?;
@@ -98,8 +258,43 @@ pub trait DavCalendar : BaseCalendar {
             .collect())
     }
 
-    // Note: the CalDAV protocol could also enable to do this:
-    // fn get_current_version(&self) -> CTag
+    /// Returns a tag that changes whenever anything in this calendar changes (a `getctag` or a `sync-token`), or `None` in case this calendar is not able to tell.
+    ///
+    /// This is much cheaper than [`DavCalendar::get_item_version_tags`], since it does not depend on the number of items
+    async fn get_ctag(&self) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Returns the items that have changed (or have been deleted) in this calendar since it had a given sync token ([RFC6578](https://tools.ietf.org/html/rfc6578)), along with its current sync token.
+    /// `None` as a token asks for every item, as [`DavCalendar::get_item_version_tags`] does.
+    ///
+    /// This returns `Ok(None)` in case this calendar does not support sync tokens, or does not accept this one (anymore): it should then be listed in full with [`DavCalendar::get_item_version_tags`]
+    async fn get_changes_since(&self, _sync_token: Option<&str>) -> Result<Option<ItemChanges>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// The size of an item in bytes, as the server has told when it has last listed it (`getcontentlength`), in case it has (see [`crate::provider::Provider::estimate`])
+    fn item_size(&self, _url: &Url) -> Option<u64> {
+        None
+    }
+
+    /// Returns the source this calendar mirrors, in case this is a subscribed calendar (see [`Subscription`]).
+    /// Nothing is ever pushed into such calendars
+    fn subscription(&self) -> Option<Subscription> {
+        None
+    }
+
+    /// Returns whether this calendar is enabled for sync, as flagged on the server by other clients (e.g. by hiding it in the web interface of Nextcloud), or `None` in case the server does not tell.
+    ///
+    /// Providers ignore this flag, unless told otherwise (see [`crate::provider::Provider::set_respect_server_enabled_flag`])
+    fn enabled(&self) -> Option<bool> {
+        None
+    }
+
+    /// Fetch every item of the source of a subscribed calendar (see [`DavCalendar::subscription`])
+    async fn fetch_subscribed_items(&self) -> Result<Vec<Item>, Box<dyn Error>> {
+        Err("This calendar is not a subscription".into())
+    }
 }
 
 
@@ -135,4 +330,84 @@ pub trait CompleteCalendar : BaseCalendar {
 
     /// Immediately remove an item. See [`CompleteCalendar::mark_for_deletion`]
     async fn immediately_delete_item(&mut self, item_id: &Url) -> Result<(), Box<dyn Error>>;
+
+    /// Returns whether the remote counterpart of this calendar could not be accessed during the last sync
+    fn is_unavailable(&self) -> bool;
+
+    /// Mark this calendar as (un)available on the remote source.
+    /// Unavailable calendars are kept as-is, they are neither synced nor deleted
+    fn set_unavailable(&mut self, unavailable: bool);
+
+    /// Set whether the current user is allowed to write into the remote counterpart of this calendar.
+    /// See [`BaseCalendar::is_writable`]
+    fn set_writable(&mut self, writable: bool);
+
+    /// The [`DavCalendar::get_ctag`] of the remote counterpart of this calendar, as it was at the start of the last successful sync (i.e. before its items have been listed)
+    fn last_synced_ctag(&self) -> Option<&VersionTag>;
+
+    /// See [`CompleteCalendar::last_synced_ctag`]
+    fn set_last_synced_ctag(&mut self, ctag: Option<VersionTag>);
+
+    /// The sync token of the remote counterpart of this calendar, as it was at the start of the last successful sync (see [`DavCalendar::get_changes_since`]).
+    /// This should be persisted along with the calendar. Calendars that do not persist it have their remote counterparts listed in full whenever they have changed
+    fn last_sync_token(&self) -> Option<&str> {
+        None
+    }
+
+    /// See [`CompleteCalendar::last_sync_token`]
+    fn set_last_sync_token(&mut self, _sync_token: Option<String>) {}
+
+    /// When the items of this calendar have last been re-fetched from its source, in case its remote counterpart is a subscription (see [`DavCalendar::subscription`]).
+    /// This tells how stale this calendar may be
+    fn last_refreshed(&self) -> Option<DateTime<Utc>>;
+
+    /// See [`CompleteCalendar::last_refreshed`]
+    fn set_last_refreshed(&mut self, last_refreshed: Option<DateTime<Utc>>);
+
+    /// How this calendar is synced, in case it has a policy of its own (see [`crate::provider::Provider::set_calendar_sync_policy`]).
+    /// This should be persisted along with the calendar
+    fn sync_policy(&self) -> Option<CalendarSyncPolicy>;
+
+    /// See [`CompleteCalendar::sync_policy`]
+    fn set_sync_policy(&mut self, policy: Option<CalendarSyncPolicy>);
+
+    /// Why the uploads of an item of this calendar keep failing, in case they do (see [`crate::provider::quarantine`]).
+    /// This should be persisted along with the calendar. Calendars that do not persist it retry failing items at every sync
+    fn upload_failure(&self, _item_url: &Url) -> Option<UploadFailure> {
+        None
+    }
+
+    /// See [`CompleteCalendar::upload_failure`]. `None` forgets the failures of an item
+    fn set_upload_failure(&mut self, _item_url: &Url, _failure: Option<UploadFailure>) {}
+
+    /// Whether the server has altered the last upload of an item of this calendar (see [`crate::provider::fidelity`]), in which case this version of the item is not uploaded again.
+    /// This should be persisted along with the calendar. Calendars that do not persist it upload such items again at every sync
+    fn altered_upload(&self, _item_url: &Url) -> Option<AlteredUpload> {
+        None
+    }
+
+    /// See [`CompleteCalendar::altered_upload`]. `None` forgets the alteration of an item
+    fn set_altered_upload(&mut self, _item_url: &Url, _altered: Option<AlteredUpload>) {}
+
+    /// Whether this calendar is kept on this device only (see [`crate::cache::Cache::set_local_only`]).
+    /// Syncs skip such calendars in both directions, and leave their remote counterparts untouched
+    fn is_local_only(&self) -> bool {
+        false
+    }
+
+    /// Tell who the next changes of this calendar come from, for calendars that keep a history of their items (see [`crate::calendar::history`]).
+    /// The sync engine sets this while it applies its changes, and sets it back to [`ChangeSource::Local`] afterwards
+    fn set_change_source(&mut self, _source: ChangeSource) {}
+
+    /// When an item of this calendar has been marked for deletion (see [`CompleteCalendar::mark_for_deletion`]), in case this calendar keeps track of it.
+    /// This orders the outbound queue (see [`crate::cache::outbound`])
+    fn marked_for_deletion_at(&self, _item_url: &Url) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    /// Keep a copy of an item aside, in a holding area of this calendar, e.g. a local change that is about to be thrown away (see [`crate::provider::Provider::reset_calendar`]), so that it can be applied again later.
+    /// Held items are not part of the calendar, and are never synced. This should be persisted along with the calendar. Calendars that have no holding area return an error
+    fn hold_item(&mut self, item: Item) -> Result<(), Box<dyn Error>> {
+        Err(format!("Calendar {} cannot hold item {}", self.url(), item.url()).into())
+    }
 }
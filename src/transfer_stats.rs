@@ -0,0 +1,106 @@
+//! Accounting of the data exchanged with a server
+//!
+//! Only the bodies of the requests and of the replies are counted (as they are sent and received on the wire), not the HTTP headers.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use url::Url;
+
+/// A running counter of transfers, that can be shared between a [`Client`](crate::client::Client) and its calendars
+pub type TransferCounter = Arc<Mutex<TransferStats>>;
+
+/// The amount of requests and bytes exchanged with a server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransferCounters {
+    pub requests: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl TransferCounters {
+    /// The bytes that have been sent and received
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_sent + self.bytes_received
+    }
+
+    /// What has been transferred since an `earlier` snapshot of these counters
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            requests: self.requests.saturating_sub(earlier.requests),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+        }
+    }
+
+    fn add(&mut self, sent: u64, received: u64) {
+        self.requests += 1;
+        self.bytes_sent += sent;
+        self.bytes_received += received;
+    }
+}
+
+/// Transfer counters, in total and broken down by operation and by calendar
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    total: TransferCounters,
+    /// Keyed by HTTP method (e.g. `PROPFIND`, `REPORT`, `PUT`...)
+    by_operation: HashMap<String, TransferCounters>,
+    /// Requests that are not related to a calendar (e.g. the discovery of the calendars) are only counted in the totals
+    by_calendar: HashMap<Url, TransferCounters>,
+}
+
+impl TransferStats {
+    pub fn total(&self) -> &TransferCounters { &self.total }
+    pub fn by_operation(&self) -> &HashMap<String, TransferCounters> { &self.by_operation }
+    pub fn by_calendar(&self) -> &HashMap<Url, TransferCounters> { &self.by_calendar }
+
+    /// What has been transferred since an `earlier` snapshot of these stats
+    pub fn since(&self, earlier: &Self) -> Self {
+        let zero = TransferCounters::default();
+        Self {
+            total: self.total.since(&earlier.total),
+            by_operation: self.by_operation.iter()
+                .map(|(op, counters)| (op.clone(), counters.since(earlier.by_operation.get(op).unwrap_or(&zero))))
+                .filter(|(_, counters)| counters.requests > 0)
+                .collect(),
+            by_calendar: self.by_calendar.iter()
+                .map(|(url, counters)| (url.clone(), counters.since(earlier.by_calendar.get(url).unwrap_or(&zero))))
+                .filter(|(_, counters)| counters.requests > 0)
+                .collect(),
+        }
+    }
+
+    /// Account for a request
+    pub(crate) fn record(&mut self, operation: &str, calendar: Option<&Url>, sent: u64, received: u64) {
+        self.total.add(sent, received);
+        self.by_operation.entry(operation.to_string()).or_default().add(sent, received);
+        if let Some(url) = calendar {
+            self.by_calendar.entry(url.clone()).or_default().add(sent, received);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_stats() {
+        let cal: Url = "https://my.server.com/cal/".parse().unwrap();
+        let mut stats = TransferStats::default();
+        stats.record("PROPFIND", None, 100, 1000);
+        let snapshot = stats.clone();
+        stats.record("REPORT", Some(&cal), 200, 3000);
+        stats.record("PUT", Some(&cal), 500, 0);
+
+        assert_eq!(stats.total(), &TransferCounters { requests: 3, bytes_sent: 800, bytes_received: 4000 });
+        assert_eq!(stats.by_calendar()[&cal].total_bytes(), 3700);
+
+        let delta = stats.since(&snapshot);
+        assert_eq!(delta.total().total_bytes(), 3700);
+        assert!(delta.by_operation().get("PROPFIND").is_none());
+        assert_eq!(delta.by_operation()["PUT"], TransferCounters { requests: 1, bytes_sent: 500, bytes_received: 0 });
+    }
+}
@@ -1,6 +1,7 @@
 //! Some utility functions
 
 use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::sync::{Arc, Mutex};
 use std::hash::Hash;
 use std::io::{stdin, stdout, Read, Write};
@@ -8,6 +9,7 @@ use std::io::{stdin, stdout, Read, Write};
 use minidom::Element;
 use url::Url;
 
+use crate::error::KitchenFridgeError;
 use crate::traits::CompleteCalendar;
 use crate::traits::DavCalendar;
 use crate::Item;
@@ -49,6 +51,105 @@ pub fn find_elem<S: AsRef<str>>(root: &Element, searched_name: S) -> Option<&Ele
     None
 }
 
+/// Returns the values of a multi-valued DAV property (e.g. `resourcetype`), i.e. the names of every child element of the first `property_name` element.
+/// This returns `None` in case there is no such property.
+///
+/// Some properties wrap every value into an element (e.g. `<privilege><read/></privilege>` in a `current-user-privilege-set`).
+/// `wrappers` lists the names of these wrappers, from the outermost one: the values are the children of the innermost ones.
+pub fn find_multi_valued_property(root: &Element, property_name: &str, wrappers: &[&str]) -> Option<Vec<String>> {
+    let mut parents = vec![find_elem(root, property_name)?];
+    for wrapper in wrappers {
+        parents = parents.iter()
+            .flat_map(|parent| parent.children().filter(|child| child.name() == *wrapper))
+            .collect();
+    }
+    Some(parents.iter()
+        .flat_map(|parent| parent.children())
+        .map(|value| value.name().to_string())
+        .collect())
+}
+
+/// Parse an XML document received from a server.
+///
+/// The document is refused in case it is nested deeper than [`XML_MAX_DEPTH`](crate::config::XML_MAX_DEPTH), has more elements than [`XML_MAX_ELEMENTS`](crate::config::XML_MAX_ELEMENTS),
+/// or has a text larger than [`MAX_ITEM_SIZE`](crate::config::MAX_ITEM_SIZE)
+pub(crate) fn parse_xml(text: &str) -> Result<Element, KitchenFridgeError> {
+    let max_depth = *crate::config::XML_MAX_DEPTH.lock().unwrap();
+    let max_elements = *crate::config::XML_MAX_ELEMENTS.lock().unwrap();
+    let max_text_size = *crate::config::MAX_ITEM_SIZE.lock().unwrap();
+    check_xml_limits(text, max_depth, max_elements, max_text_size)
+        .map_err(|err| KitchenFridgeError::XmlParse(err.to_string()))?;
+    text.parse().map_err(|err: minidom::Error| KitchenFridgeError::XmlParse(err.to_string()))
+}
+
+/// Scan an XML document (without building it), and fail in case it exceeds the given limits
+fn check_xml_limits(text: &str, max_depth: usize, max_elements: usize, max_text_size: usize) -> Result<(), Box<dyn Error>> {
+    use minidom::quick_xml::{events::Event, Reader};
+
+    let mut reader = Reader::from_str(text);
+    let mut buf = Vec::new();
+    let mut depth: usize = 0;
+    let mut elements = 0;
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(_) => {
+                depth += 1;
+                elements += 1;
+            },
+            Event::Empty(_) => elements += 1,
+            Event::End(_) => depth = depth.saturating_sub(1),
+            Event::Text(text) | Event::CData(text) if text.len() > max_text_size => {
+                log::warn!("Refusing an XML document that has a text larger than {} bytes", max_text_size);
+                return Err(format!("XML document has a text larger than {} bytes", max_text_size).into());
+            },
+            Event::Eof => return Ok(()),
+            _ => (),
+        }
+        if depth > max_depth {
+            log::warn!("Refusing an XML document that is nested more than {} levels deep", max_depth);
+            return Err(format!("XML document is nested more than {} levels deep", max_depth).into());
+        }
+        if elements > max_elements {
+            log::warn!("Refusing an XML document that has more than {} elements", max_elements);
+            return Err(format!("XML document has more than {} elements", max_elements).into());
+        }
+        buf.clear();
+    }
+}
+
+/// Returns the complete `searched_name` elements of an XML document that has been truncated (e.g. because the connection dropped while it was received).
+///
+/// This only supports documents where the searched elements are direct children of the root element (e.g. `<response>`s of a `<multistatus>`)
+pub(crate) fn find_complete_elems_in_truncated_xml(partial: &str, searched_name: &str) -> Vec<Element> {
+    // Cut the document after the last complete searched element...
+    let end = partial.rmatch_indices("</")
+        .find_map(|(start, _)| {
+            let tag_len = partial[start..].find('>')?;
+            let tag = &partial[start + 2 .. start + tag_len];
+            let local_name = tag.rsplit(':').next()?.trim();
+            if local_name == searched_name { Some(start + tag_len + 1) } else { None }
+        });
+    let end = match end {
+        None => return Vec::new(),
+        Some(end) => end,
+    };
+
+    // ...and close the root element
+    let root_name = partial.match_indices('<')
+        .map(|(start, _)| &partial[start + 1 ..])
+        .find(|tag| !tag.starts_with('?') && !tag.starts_with('!'))
+        .and_then(|tag| tag.split(|c: char| c.is_whitespace() || c == '>' || c == '/').next());
+    let root_name = match root_name {
+        None => return Vec::new(),
+        Some(name) => name,
+    };
+
+    match parse_xml(&format!("{}</{}>", &partial[..end], root_name)) {
+        Err(_) => Vec::new(),
+        Ok(root) => find_elems(&root, searched_name).into_iter().cloned().collect(),
+    }
+}
+
 
 pub fn print_xml(element: &Element) {
     let mut writer = std::io::stdout();
@@ -155,3 +256,95 @@ pub fn random_url(parent_calendar: &Url) -> Url {
     let random = uuid::Uuid::new_v4().to_hyphenated().to_string();
     parent_calendar.join(&random).unwrap(/* this cannot panic since we've just created a string that is a valid URL */)
 }
+
+
+/// If `url` starts with `old_base`, returns the same URL that starts with `new_base` instead
+pub fn rebase_url(url: &Url, old_base: &Url, new_base: &Url) -> Option<Url> {
+    let suffix = url.as_str().strip_prefix(old_base.as_str())?;
+    Url::parse(&format!("{}{}", new_base, suffix)).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_complete_elems_in_truncated_xml() {
+        let full = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response><d:href>/cal/1.ics</d:href></d:response>
+  <d:response><d:href>/cal/2.ics</d:href></d:response>
+  <d:response><d:href>/cal/3.ics</d:href></d:response>
+</d:multistatus>"#;
+
+        let hrefs = |partial: &str| -> Vec<String> {
+            find_complete_elems_in_truncated_xml(partial, "response").iter()
+                .map(|rep| find_elem(rep, "href").unwrap().text())
+                .collect()
+        };
+
+        let truncated = &full[..full.find("/cal/3.ics").unwrap()];
+        assert_eq!(hrefs(truncated), vec!["/cal/1.ics", "/cal/2.ics"]);
+        let truncated = &full[..full.find("/cal/2.ics").unwrap() + 15];
+        assert_eq!(hrefs(truncated), vec!["/cal/1.ics"]);
+        let truncated = &full[..full.find("/cal/1.ics").unwrap()];
+        assert!(hrefs(truncated).is_empty());
+        assert!(hrefs("").is_empty());
+    }
+
+    #[test]
+    fn test_multi_valued_properties() {
+        let response: Element = r#"<d:response xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:href>/cal/</d:href>
+  <d:propstat>
+    <d:prop>
+      <d:resourcetype><d:collection/><c:calendar/><cs:shared xmlns:cs="http://calendarserver.org/ns/"/></d:resourcetype>
+      <d:supported-report-set>
+        <d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>
+        <d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>
+        <d:supported-report><d:report><d:sync-collection/></d:report></d:supported-report>
+      </d:supported-report-set>
+      <d:current-user-privilege-set>
+        <d:privilege><d:read/></d:privilege>
+        <d:privilege><d:write-content/></d:privilege>
+        <d:privilege><d:bind/></d:privilege>
+      </d:current-user-privilege-set>
+      <d:owner/>
+    </d:prop>
+  </d:propstat>
+</d:response>"#.parse().unwrap();
+
+        assert_eq!(find_multi_valued_property(&response, "resourcetype", &[]).unwrap(), vec!["collection", "calendar", "shared"]);
+        assert_eq!(
+            find_multi_valued_property(&response, "supported-report-set", &["supported-report", "report"]).unwrap(),
+            vec!["calendar-multiget", "calendar-query", "sync-collection"]
+        );
+        assert_eq!(
+            find_multi_valued_property(&response, "current-user-privilege-set", &["privilege"]).unwrap(),
+            vec!["read", "write-content", "bind"]
+        );
+        assert_eq!(find_multi_valued_property(&response, "owner", &[]), Some(Vec::new()));
+        assert_eq!(find_multi_valued_property(&response, "getctag", &[]), None);
+    }
+
+    #[test]
+    fn test_xml_limits() {
+        let nested = format!("{}{}", "<a>".repeat(100_000), "</a>".repeat(100_000));
+        let err = parse_xml(&nested).unwrap_err();
+        assert!(err.to_string().contains("levels deep"));
+
+        let flat = format!("<d:multistatus xmlns:d=\"DAV:\">{}</d:multistatus>", "<d:response/>".repeat(1000));
+        assert!(check_xml_limits(&flat, 10, 1001, 100).is_ok());
+        assert!(check_xml_limits(&flat, 10, 1000, 100).is_err());
+        assert!(check_xml_limits("<a><b><c/></b></a>", 2, 100, 100).is_ok());
+        assert!(check_xml_limits("<a><b><c></c></b></a>", 2, 100, 100).is_err());
+        assert!(check_xml_limits("<a>BEGIN:VCALENDAR</a>", 2, 100, 15).is_ok());
+        assert!(check_xml_limits("<a>BEGIN:VCALENDAR</a>", 2, 100, 14).is_err());
+        assert!(check_xml_limits("<a><![CDATA[BEGIN:VCALENDAR]]></a>", 2, 100, 14).is_err());
+
+        assert!(parse_xml(&flat).is_ok());
+        assert!(parse_xml("<a><b></a>").is_err());
+        assert!(parse_xml("</a>").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! A data source that is not a CalDAV server nor a `Cache` can be synced by a `Provider`, as long as it implements `SourceAdapter`.
+//!
+//! This uses a toy task database, whose tables are rows of items indexed by their URL (as a SQL database would store them)
+#![cfg(feature = "integration_tests")]
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use csscolorparser::Color;
+use url::Url;
+
+use kitchen_fridge::cache::Cache;
+use kitchen_fridge::calendar::SupportedComponents;
+use kitchen_fridge::item::{SyncStatus, VersionTag};
+use kitchen_fridge::mock_behaviour::MockBehaviour;
+use kitchen_fridge::provider::{CalendarSyncPolicy, Provider};
+use kitchen_fridge::traits::{BaseCalendar, CalDavSource, CompleteCalendar, SourceAdapter, SyncMetadata};
+use kitchen_fridge::{Item, Task};
+
+/// A table of the database
+struct TaskTable {
+    name: String,
+    url: Url,
+    supported_components: SupportedComponents,
+    rows: BTreeMap<Url, Item>,
+    unavailable: bool,
+    writable: bool,
+    last_synced_ctag: Option<VersionTag>,
+    last_refreshed: Option<DateTime<Utc>>,
+    sync_policy: Option<CalendarSyncPolicy>,
+}
+
+#[derive(Default)]
+struct TaskDb {
+    tables: HashMap<Url, Arc<Mutex<TaskTable>>>,
+}
+
+#[async_trait]
+impl CalDavSource<TaskTable> for TaskDb {
+    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<Mutex<TaskTable>>>, Box<dyn Error>> {
+        Ok(self.tables.clone())
+    }
+
+    async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<TaskTable>>> {
+        self.tables.get(url).cloned()
+    }
+
+    async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<Mutex<TaskTable>>, Box<dyn Error>> {
+        let table = Arc::new(Mutex::new(CompleteCalendar::new(name, url.clone(), supported_components, color)));
+        self.tables.insert(url, table.clone());
+        Ok(table)
+    }
+}
+
+impl TaskDb {
+    fn table(&self, url: &Url) -> Result<Arc<Mutex<TaskTable>>, Box<dyn Error>> {
+        self.tables.get(url).cloned().ok_or_else(|| "no such table".into())
+    }
+}
+
+#[async_trait(?Send)]
+impl SourceAdapter<TaskTable> for TaskDb {
+    async fn list_item_stubs(&self, calendar: &Url) -> Result<HashMap<Url, SyncStatus>, Box<dyn Error>> {
+        let table = self.table(calendar)?;
+        let table = table.lock().unwrap();
+        Ok(table.rows.iter().map(|(url, item)| (url.clone(), item.sync_status().clone())).collect())
+    }
+
+    async fn fetch_items(&self, calendar: &Url, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        let table = self.table(calendar)?;
+        let table = table.lock().unwrap();
+        Ok(urls.iter().map(|url| table.rows.get(url).cloned()).collect())
+    }
+
+    async fn upsert_item(&mut self, calendar: &Url, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let status = item.sync_status().clone();
+        self.table(calendar)?.lock().unwrap().rows.insert(item.url().clone(), item);
+        Ok(status)
+    }
+
+    async fn delete_item(&mut self, calendar: &Url, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        self.table(calendar)?.lock().unwrap().rows.remove(item_url).map(|_| ()).ok_or_else(|| "no such row".into())
+    }
+
+    async fn sync_metadata(&self, calendar: &Url) -> Result<SyncMetadata, Box<dyn Error>> {
+        let ctag = self.table(calendar)?.lock().unwrap().last_synced_ctag.clone();
+        Ok(SyncMetadata { ctag, sync_token: None })
+    }
+
+    async fn set_sync_metadata(&mut self, calendar: &Url, metadata: SyncMetadata) -> Result<(), Box<dyn Error>> {
+        self.table(calendar)?.lock().unwrap().last_synced_ctag = metadata.ctag;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BaseCalendar for TaskTable {
+    fn name(&self) -> &str { &self.name }
+    fn url(&self) -> &Url { &self.url }
+    fn supported_components(&self) -> SupportedComponents { self.supported_components }
+    fn color(&self) -> Option<&Color> { None }
+
+    async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        if self.rows.contains_key(item.url()) {
+            return Err("duplicate primary key".into());
+        }
+        let status = item.sync_status().clone();
+        self.rows.insert(item.url().clone(), item);
+        Ok(status)
+    }
+
+    async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let status = item.sync_status().clone();
+        match self.rows.get_mut(item.url()) {
+            None => Err("no such row".into()),
+            Some(row) => {
+                *row = item;
+                Ok(status)
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl CompleteCalendar for TaskTable {
+    fn new(name: String, url: Url, supported_components: SupportedComponents, _color: Option<Color>) -> Self {
+        Self { name, url, supported_components, rows: BTreeMap::new(), unavailable: false, writable: true, last_synced_ctag: None, last_refreshed: None, sync_policy: None }
+    }
+
+    async fn get_item_urls(&self) -> Result<HashSet<Url>, Box<dyn Error>> {
+        Ok(self.rows.keys().cloned().collect())
+    }
+
+    async fn get_items(&self) -> Result<HashMap<Url, &Item>, Box<dyn Error>> {
+        Ok(self.rows.iter().map(|(url, item)| (url.clone(), item)).collect())
+    }
+
+    async fn get_items_mut(&mut self) -> Result<HashMap<Url, &mut Item>, Box<dyn Error>> {
+        Ok(self.rows.iter_mut().map(|(url, item)| (url.clone(), item)).collect())
+    }
+
+    async fn get_item_by_url<'a>(&'a self, url: &Url) -> Option<&'a Item> {
+        self.rows.get(url)
+    }
+
+    async fn get_item_by_url_mut<'a>(&'a mut self, url: &Url) -> Option<&'a mut Item> {
+        self.rows.get_mut(url)
+    }
+
+    async fn mark_for_deletion(&mut self, url: &Url) -> Result<(), Box<dyn Error>> {
+        let row = self.rows.get_mut(url).ok_or("no such row")?;
+        match row.sync_status().clone() {
+            SyncStatus::NotSynced => { self.rows.remove(url); },
+            SyncStatus::Synced(tag) | SyncStatus::LocallyModified(tag) | SyncStatus::LocallyDeleted(tag) => row.set_sync_status(SyncStatus::LocallyDeleted(tag)),
+        }
+        Ok(())
+    }
+
+    async fn immediately_delete_item(&mut self, url: &Url) -> Result<(), Box<dyn Error>> {
+        self.rows.remove(url).map(|_| ()).ok_or_else(|| "no such row".into())
+    }
+
+    fn is_unavailable(&self) -> bool { self.unavailable }
+    fn set_unavailable(&mut self, unavailable: bool) { self.unavailable = unavailable; }
+    fn set_writable(&mut self, writable: bool) { self.writable = writable; }
+    fn last_synced_ctag(&self) -> Option<&VersionTag> { self.last_synced_ctag.as_ref() }
+    fn set_last_synced_ctag(&mut self, ctag: Option<VersionTag>) { self.last_synced_ctag = ctag; }
+    fn last_refreshed(&self) -> Option<DateTime<Utc>> { self.last_refreshed }
+    fn set_last_refreshed(&mut self, last_refreshed: Option<DateTime<Utc>>) { self.last_refreshed = last_refreshed; }
+    fn sync_policy(&self) -> Option<CalendarSyncPolicy> { self.sync_policy.clone() }
+    fn set_sync_policy(&mut self, policy: Option<CalendarSyncPolicy>) { self.sync_policy = policy; }
+}
+
+
+#[tokio::test]
+async fn test_sync_custom_source() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: Url = "https://some.server.com/calendars/database/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/custom_source_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Database".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let remote_task = Task::new("Created on the server".to_string(), false, &cal_url);
+    let remote_task_url = remote_task.url().clone();
+    remote_cal.lock().unwrap().add_item(Item::Task(remote_task)).await.unwrap();
+
+    let mut provider = Provider::new(remote, TaskDb::default());
+    assert!(provider.sync().await);
+    let table = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert_eq!(table.lock().unwrap().rows[&remote_task_url].name(), "Created on the server");
+    assert!(table.lock().unwrap().last_synced_ctag().is_some());
+    assert!(provider.local().sync_metadata(&cal_url).await.unwrap().ctag.is_some());
+    let stubs = provider.local().list_item_stubs(&cal_url).await.unwrap();
+    assert!(matches!(stubs[&remote_task_url], SyncStatus::Synced(_)));
+
+    // Changes made in the database are uploaded
+    let local_task = Task::new("Created in the database".to_string(), false, &cal_url);
+    let local_task_url = local_task.url().clone();
+    table.lock().unwrap().add_item(Item::Task(local_task)).await.unwrap();
+    table.lock().unwrap().mark_for_deletion(&remote_task_url).await.unwrap();
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+
+    let remote_items = remote_cal.lock().unwrap().get_items_sync().unwrap().keys().cloned().collect::<Vec<_>>();
+    assert_eq!(remote_items, vec![local_task_url.clone()]);
+    assert_eq!(table.lock().unwrap().rows.keys().cloned().collect::<Vec<_>>(), vec![local_task_url]);
+    // After pushing changes, the server is listed once more, to pull what may have changed on it during the sync
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert!(!provider.has_pending_work().await.unwrap());
+}
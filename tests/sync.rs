@@ -1,23 +1,23 @@
-mod scenarii;
-
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
 use std::sync::{Arc, Mutex};
 
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
 use kitchen_fridge::mock_behaviour::MockBehaviour;
+#[cfg(feature = "test-utils")]
+use kitchen_fridge::scenarios::{assert_converged, ScenarioBuilder};
 
 
 
 /// A test that simulates a regular synchronisation between a local cache and a server.
 /// Note that this uses a second cache to "mock" a server.
 struct TestFlavour {
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-    scenarii: Vec<scenarii::ItemScenario>,
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[cfg(feature = "test-utils")]
+    builder: ScenarioBuilder,
+    #[cfg(feature = "test-utils")]
     mock_behaviour: Arc<Mutex<MockBehaviour>>,
 }
 
-#[cfg(not(feature = "local_calendar_mocks_remote_calendars"))]
+#[cfg(not(feature = "test-utils"))]
 impl TestFlavour {
     pub fn normal() -> Self { Self{} }
     pub fn first_sync_to_local() -> Self { Self{} }
@@ -41,172 +41,128 @@ impl TestFlavour {
     }
 }
 
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
+#[cfg(feature = "test-utils")]
 impl TestFlavour {
+    fn new(builder: ScenarioBuilder, mock_behaviour: MockBehaviour) -> Self {
+        let mock_behaviour = Arc::new(Mutex::new(mock_behaviour));
+        Self { builder: builder.with_mock_behaviour(mock_behaviour.clone()), mock_behaviour }
+    }
+
     pub fn normal() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour::new())),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour::new())
     }
 
     pub fn first_sync_to_local() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_first_sync_to_local(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour::new())),
-        }
+        Self::new(ScenarioBuilder::first_sync_to_local(), MockBehaviour::new())
     }
 
     pub fn first_sync_to_server() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_first_sync_to_server(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour::new())),
-        }
+        Self::new(ScenarioBuilder::first_sync_to_server(), MockBehaviour::new())
     }
 
     pub fn transient_task() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_transient_task(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour::new())),
-        }
+        Self::new(ScenarioBuilder::transient_task(), MockBehaviour::new())
     }
 
     pub fn normal_with_errors1() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour::fail_now(10))),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour::fail_now(10))
     }
 
     pub fn normal_with_errors2() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_calendars_behaviour: (0,1),
-                create_calendar_behaviour: (2,2),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            get_calendars_behaviour: (0,1),
+            create_calendar_behaviour: (2,2),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors3() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_first_sync_to_server(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_calendars_behaviour: (1,6),
-                create_calendar_behaviour: (0,1),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::first_sync_to_server(), MockBehaviour{
+            get_calendars_behaviour: (1,6),
+            create_calendar_behaviour: (0,1),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors4() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_first_sync_to_server(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                add_item_behaviour: (1,3),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::first_sync_to_server(), MockBehaviour{
+            add_item_behaviour: (1,3),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors5() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_item_version_tags_behaviour: (0,1),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            get_item_version_tags_behaviour: (0,1),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors6() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_item_by_url_behaviour: (3,2),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            get_item_by_url_behaviour: (3,2),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors7() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                delete_item_behaviour: (0,2),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            delete_item_behaviour: (0,2),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors8() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                add_item_behaviour: (2,3),
-                get_item_by_url_behaviour: (1,12),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            add_item_behaviour: (2,3),
+            get_item_by_url_behaviour: (1,12),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors9() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_calendars_behaviour: (0,8),
-                delete_item_behaviour: (1,1),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            get_calendars_behaviour: (0,8),
+            delete_item_behaviour: (1,1),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors10() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_first_sync_to_server(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_calendars_behaviour: (0,8),
-                delete_item_behaviour: (1,1),
-                create_calendar_behaviour: (1,4),
-                get_item_version_tags_behaviour: (3,1),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::first_sync_to_server(), MockBehaviour{
+            get_calendars_behaviour: (0,8),
+            delete_item_behaviour: (1,1),
+            create_calendar_behaviour: (1,4),
+            get_item_version_tags_behaviour: (3,1),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors11() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                get_calendars_behaviour: (0,8),
-                delete_item_behaviour: (1,1),
-                create_calendar_behaviour: (1,4),
-                get_item_version_tags_behaviour: (3,1),
-                get_item_by_url_behaviour: (0,41),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            get_calendars_behaviour: (0,8),
+            delete_item_behaviour: (1,1),
+            create_calendar_behaviour: (1,4),
+            get_item_version_tags_behaviour: (3,1),
+            get_item_by_url_behaviour: (0,41),
+            ..MockBehaviour::default()
+        })
     }
 
     pub fn normal_with_errors12() -> Self {
-        Self {
-            scenarii: scenarii::scenarii_basic(),
-            mock_behaviour: Arc::new(Mutex::new(MockBehaviour{
-                update_item_behaviour: (0,3),
-                ..MockBehaviour::default()
-            })),
-        }
+        Self::new(ScenarioBuilder::classic_conflict_matrix(), MockBehaviour{
+            update_item_behaviour: (0,3),
+            ..MockBehaviour::default()
+        })
     }
 
 
     pub async fn run(&self, max_attempts: u32) {
+        let (mut provider, expectation) = self.builder.build().await;
         self.mock_behaviour.lock().unwrap().suspend();
-
-        let mut provider = scenarii::populate_test_provider_before_sync(&self.scenarii, Arc::clone(&self.mock_behaviour)).await;
         print_provider(&provider, "before sync").await;
-
         self.mock_behaviour.lock().unwrap().resume();
+
         for attempt in 0..max_attempts {
             println!("\nSyncing...\n");
             if provider.sync().await == true {
@@ -218,20 +174,13 @@ impl TestFlavour {
 
         print_provider(&provider, "after sync").await;
 
-        // Check the contents of both sources are the same after sync
-        assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
-
-        // But also explicitely check that every item is expected
-        let expected_provider = scenarii::populate_test_provider_after_sync(&self.scenarii, Arc::clone(&self.mock_behaviour)).await;
-
-        assert!(provider.local() .has_same_observable_content_as(expected_provider.local() ).await.unwrap());
-        assert!(provider.remote().has_same_observable_content_as(expected_provider.remote()).await.unwrap());
+        // Check both sources contain every item that is expected, and only them
+        assert_converged(&provider, &expectation).await;
 
         // Perform a second sync, even if no change has happened, just to check
         println!("Syncing again");
         provider.sync().await;
-        assert!(provider.local() .has_same_observable_content_as(expected_provider.local() ).await.unwrap());
-        assert!(provider.remote().has_same_observable_content_as(expected_provider.remote()).await.unwrap());
+        assert_converged(&provider, &expectation).await;
     }
 }
 
@@ -239,6 +188,8 @@ impl TestFlavour {
 
 async fn run_flavour(flavour: TestFlavour, max_attempts: u32) {
     let _ = env_logger::builder().is_test(true).try_init();
+    // Failing batches are retried at once, rather than after a delay
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
     flavour.run(max_attempts).await;
 }
 
@@ -338,16 +289,2289 @@ async fn test_errors_in_regular_sync12() {
     run_flavour(TestFlavour::normal_with_errors12(), 100).await;
 }
 
+/// Copy the local cache to another folder (as if it was moved to another machine, for a server that has moved as well), and check the next sync is incremental
+#[tokio::test]
 #[cfg(feature = "integration_tests")]
-use kitchen_fridge::{traits::CalDavSource,
-               provider::Provider,
-               cache::Cache,
-               calendar::cached_calendar::CachedCalendar,
-};
+async fn test_sync_after_moving_the_cache() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
 
-/// Print the contents of the provider. This is usually used for debugging
-#[allow(dead_code)]
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let old_cal_url: url::Url = "https://old.server.com/calendars/moving/".parse().unwrap();
+    let new_cal_url: url::Url = "https://new.server.com/dav/calendars/moving/".parse().unwrap();
+
+    // The (new) server and the former local cache, in a synced state
+    let mut remote = Cache::new(Path::new("test_cache/moving_remote"));
+    let mut local = Cache::new(Path::new("test_cache/moving_local_a"));
+    let remote_cal = remote.create_calendar(new_cal_url.clone(), "Moving".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let local_cal = local.create_calendar(old_cal_url.clone(), "Moving".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for i in 0..5 {
+        let sync_status = SyncStatus::random_synced();
+        let item_name = format!("item-{}", i);
+        let local_task = Task::new_with_parameters(item_name.clone(), item_name.clone(), old_cal_url.join(&item_name).unwrap(),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, sync_status.clone(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        let remote_task = Task::new_with_parameters(item_name.clone(), item_name.clone(), new_cal_url.join(&item_name).unwrap(),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, sync_status, None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        local_cal.lock().unwrap().add_item(Item::Task(local_task)).await.unwrap();
+        remote_cal.lock().unwrap().add_item(Item::Task(remote_task)).await.unwrap();
+    }
+    // A local change that has not been synced yet, that should survive the move
+    let local_changed_url = {
+        let mut local_cal = local_cal.lock().unwrap();
+        let (url, item) = local_cal.get_items_mut_sync().unwrap().into_iter().next().unwrap();
+        item.unwrap_task_mut().set_name("Renamed".to_string());
+        url
+    };
+
+    // Move the cache
+    let moved_path = Path::new("test_cache/moving_local_b");
+    local.export_to_folder(moved_path).unwrap();
+    drop(local);
+    let mut moved = Cache::from_folder(moved_path).unwrap();
+    moved.rebase_ids(&"https://old.server.com/".parse().unwrap(), &"https://new.server.com/dav/".parse().unwrap());
+
+    // The next sync must not download anything, and must only upload the local change
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour{
+        create_calendar_behaviour: (0, u32::MAX),
+        add_item_behaviour: (0, u32::MAX),
+        update_item_behaviour: (1, u32::MAX),
+        get_item_by_url_behaviour: (0, u32::MAX),
+        delete_item_behaviour: (0, u32::MAX),
+        ..MockBehaviour::default()
+    }))));
+    let mut provider = Provider::new(remote, moved);
+    assert!(provider.sync().await);
+
+    let synced_cal = provider.local().get_calendar(&new_cal_url).await.unwrap();
+    let synced_cal = synced_cal.lock().unwrap();
+    assert_eq!(synced_cal.get_items_sync().unwrap().len(), 5);
+    let changed_url = kitchen_fridge::utils::rebase_url(&local_changed_url, &"https://old.server.com/".parse().unwrap(), &"https://new.server.com/dav/".parse().unwrap()).unwrap();
+    assert_eq!(synced_cal.get_item_by_url_sync(&changed_url).unwrap().name(), "Renamed");
+}
+
+/// Simulate a connection that drops in the middle of the second batch of a download, and check the sync recovers from it
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_resume_interrupted_download_batch() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let cal_url: url::Url = "https://some.server.com/calendars/batches/".parse().unwrap();
+
+    // Enough items for several download batches
+    let mut remote = Cache::new(Path::new("test_cache/batches_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Batches".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for i in 0..70 {
+        let mut task = Task::new(format!("item-{}", i), false, &cal_url);
+        task.set_sync_status(SyncStatus::random_synced());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+
+    // The first batch (30 items) is fine, then the connection drops after 5 items of the second batch.
+    // The sync must still complete at once, by retrying the 25 items that have not been received
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour{
+        get_item_by_url_behaviour: (35, 1),
+        ..MockBehaviour::default()
+    }))));
+    let local = Cache::new(Path::new("test_cache/batches_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert_eq!(local_cal.lock().unwrap().get_items_sync().unwrap().len(), 70);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Check a sync that exceeds its transfer budget stops after the current batch, and that the next sync resumes it
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_transfer_budget() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let cal_url: url::Url = "https://some.server.com/calendars/budget/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/budget_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Budget".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for i in 0..70 {
+        let mut task = Task::new(format!("item-{}", i), false, &cal_url);
+        task.set_sync_status(SyncStatus::random_synced());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+
+    // The very first batch (30 items) exceeds the budget
+    let local_path = Path::new("test_cache/budget_local");
+    let local = Cache::new(local_path);
+    let mut provider = Provider::new(remote, local);
+    provider.set_transfer_budget(Some(1));
+    assert!(provider.sync().await);
+    let result = provider.last_sync_result().unwrap().clone();
+    assert!(result.budget_exhausted);
+    let transfers = result.transfers.unwrap();
+    assert_eq!(transfers.total().requests, 30);
+    assert_eq!(transfers.by_calendar()[&cal_url], *transfers.total());
+    assert!(transfers.by_operation()["GET"].bytes_received > 30 * 100);
+    // The local cache has been saved, so that the sync can be resumed even after a restart
+    let saved = Cache::from_folder(local_path).unwrap();
+    assert_eq!(saved.get_calendar(&cal_url).await.unwrap().lock().unwrap().get_items_sync().unwrap().len(), 30);
+
+    provider.set_transfer_budget(None);
+    assert!(provider.sync().await);
+    let result = provider.last_sync_result().unwrap();
+    assert!(!result.budget_exhausted);
+    assert_eq!(result.transfers.as_ref().unwrap().total().requests, 40);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// An account that is over quota refuses uploads, but remote changes are still pulled
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_quota_exceeded() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let cal_url: url::Url = "https://some.server.com/calendars/full/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/quota_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Full".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut remote_task = Task::new("remote item".to_string(), false, &cal_url);
+    remote_task.set_sync_status(SyncStatus::random_synced());
+    remote_cal.lock().unwrap().add_item(Item::Task(remote_task)).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour{
+        quota_exceeded: true,
+        ..MockBehaviour::default()
+    }));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+
+    let mut local = Cache::new(Path::new("test_cache/quota_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "Full".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for i in 0..3 {
+        let task = Task::new(format!("local item {}", i), false, &cal_url);
+        local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+
+    let mut provider = Provider::new(remote, local);
+    assert!(!provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().quota_exceeded.as_ref(), Some(&cal_url));
+    {
+        let local_cal = local_cal.lock().unwrap();
+        let local_items = local_cal.get_items_sync().unwrap();
+        assert_eq!(local_items.len(), 4);
+        assert_eq!(local_items.values().filter(|item| item.sync_status() == &SyncStatus::NotSynced).count(), 3);
+    }
+    assert_eq!(remote_cal.lock().unwrap().get_items_sync().unwrap().len(), 1);
+
+    // Once some space has been freed, the pending uploads succeed
+    mock_behaviour.lock().unwrap().quota_exceeded = false;
+    assert!(provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().quota_exceeded, None);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// The server stores an uploaded item at another URL than the requested one, and tells it with a `Content-Location`
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_content_location() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/canonical/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/content_location_remote"));
+    remote.create_calendar(cal_url.clone(), "Canonical".to_string(), SupportedComponents::TODO, None).await.unwrap();
+
+    let mut local = Cache::new(Path::new("test_cache/content_location_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "Canonical".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let task = Task::new("Some task".to_string(), false, &cal_url);
+    let requested_url = task.url().clone();
+    let canonical_url = cal_url.join("canonical-name.ics").unwrap();
+    local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+
+    let mut mock_behaviour = MockBehaviour::default();
+    mock_behaviour.content_locations.insert(requested_url.clone(), canonical_url.clone());
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(mock_behaviour))));
+
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    {
+        let local_cal = local_cal.lock().unwrap();
+        assert!(local_cal.get_item_by_url_sync(&requested_url).is_none());
+        assert_eq!(local_cal.get_item_by_url_sync(&canonical_url).unwrap().name(), "Some task");
+    }
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+
+    // Nothing is left to sync, and nothing has been duplicated
+    assert!(provider.sync().await);
+    assert_eq!(local_cal.lock().unwrap().get_items_sync().unwrap().len(), 1);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Items with dates the server does not accept are skipped, clamped or stripped before they are uploaded
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_date_limits() {
+    use std::path::Path;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use kitchen_fridge::{calendar::{DateLimits, SupportedComponents}, datetime::CalDateTime, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::DateLimitsPolicy;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/limited/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/date_limits_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Limited".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut mock_behaviour = MockBehaviour::default();
+    mock_behaviour.date_limits.insert(cal_url.clone(), DateLimits { min: Some(Utc.ymd(1970, 1, 1).and_hms(0, 0, 0)), max: None });
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(mock_behaviour))));
+
+    let mut local = Cache::new(Path::new("test_cache/date_limits_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "Limited".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut old_task = Task::new("Old task".to_string(), false, &cal_url);
+    old_task.set_due(Some(CalDateTime::Date(NaiveDate::from_ymd(1960, 5, 1))));
+    let old_url = old_task.url().clone();
+    local_cal.lock().unwrap().add_item(Item::Task(old_task)).await.unwrap();
+    local_cal.lock().unwrap().add_item(Item::Task(Task::new("Recent task".to_string(), false, &cal_url))).await.unwrap();
+
+    // By default, the old task is not uploaded, but this is not an error
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    let violations = &provider.last_sync_result().unwrap().date_limit_violations;
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].item, old_url);
+    assert_eq!(violations[0].properties, vec!["DUE".to_string()]);
+    assert_eq!(violations[0].action, DateLimitsPolicy::Skip);
+    assert_eq!(remote_cal.lock().unwrap().get_items_sync().unwrap().len(), 1);
+
+    // It is checked again at the next sync
+    provider.set_date_limits_policy(DateLimitsPolicy::Clamp);
+    assert!(provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().date_limit_violations[0].action, DateLimitsPolicy::Clamp);
+    let remote_due = remote_cal.lock().unwrap().get_item_by_url_sync(&old_url).unwrap().unwrap_task().due().cloned();
+    assert_eq!(remote_due, Some(CalDateTime::Date(NaiveDate::from_ymd(1970, 1, 1))));
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().date_limit_violations.is_empty());
+}
+
+/// Whether there is anything to sync can be told without syncing
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_has_pending_work() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/pending/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/pending_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Pending".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let local = Cache::new(Path::new("test_cache/pending_local"));
+
+    // The remote calendar has never been synced
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert!(!provider.has_pending_work().await.unwrap());
+
+    // Local changes
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    let task = Task::new("Some task".to_string(), false, &cal_url);
+    let task_url = task.url().clone();
+    local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    // The remote calendar has changed since the sync has started (at least because of this upload), so that it is listed once more, in case another client has changed it meanwhile
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert!(!provider.has_pending_work().await.unwrap());
+
+    // Remote changes
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&task_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Renamed".to_string());
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert!(!provider.has_pending_work().await.unwrap());
+}
+
+/// Saving an item without changing anything does not upload it again, unless it is explicitly touched
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_unchanged_save_is_not_uploaded() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/unchanged/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/unchanged_remote"));
+    remote.create_calendar(cal_url.clone(), "Unchanged".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+
+    let mut local = Cache::new(Path::new("test_cache/unchanged_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "Unchanged".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let task = Task::new("Some task".to_string(), false, &cal_url);
+    let task_url = task.url().clone();
+    local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    let puts = |provider: &Provider<Cache, CachedCalendar, Cache, CachedCalendar>| {
+        provider.last_sync_result().unwrap().transfers.as_ref().unwrap()
+            .by_operation().get("PUT").map_or(0, |counters| counters.requests)
+    };
+    assert_eq!(puts(&provider), 1);
+    // The remote calendar is listed once more after changes have been pushed
+    assert!(provider.sync().await);
+
+    {
+        let mut local_cal = local_cal.lock().unwrap();
+        let task = local_cal.get_item_by_url_mut_sync(&task_url).unwrap().unwrap_task_mut();
+        task.set_name(task.name().to_string());
+        task.set_completion_status(task.completion_status().clone());
+    }
+    assert!(!provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert_eq!(puts(&provider), 0);
+
+    local_cal.lock().unwrap().get_item_by_url_mut_sync(&task_url).unwrap().touch();
+    assert!(provider.sync().await);
+    assert_eq!(puts(&provider), 1);
+}
+
+/// An item the server refuses because it already has one with the same UID is replaced by the existing one
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_uid_conflict() {
+    use std::path::Path;
+    use chrono::Utc;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, task::CompletionStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/uid-conflict/".parse().unwrap();
+    let task = |name: &str, url: &url::Url, sync_status: SyncStatus| Item::Task(Task::new_with_parameters(
+        name.to_string(), "same-uid".to_string(), url.clone(), CompletionStatus::Uncompleted,
+        sync_status, Some(Utc::now()), Utc::now(), "prod_id".to_string(), Vec::new()));
+    let remote_url = cal_url.join("remote.ics").unwrap();
+    let local_url = cal_url.join("local.ics").unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/uid_conflict_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "UID conflict".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    remote_cal.lock().unwrap().add_item(task("Created remotely", &remote_url, SyncStatus::random_synced())).await.unwrap();
+
+    let mut local = Cache::new(Path::new("test_cache/uid_conflict_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "UID conflict".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    local_cal.lock().unwrap().add_item(task("Created locally", &local_url, SyncStatus::NotSynced)).await.unwrap();
+
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let local_items = local_cal.lock().unwrap().get_items_sync().unwrap().keys().cloned().collect::<Vec<_>>();
+    assert_eq!(local_items, vec![remote_url.clone()]);
+    assert_eq!(local_cal.lock().unwrap().get_item_by_url_sync(&remote_url).unwrap().name(), "Created remotely");
+    assert_eq!(remote_cal.lock().unwrap().get_items_sync().unwrap().len(), 1);
+    assert!(provider.last_sync_result().unwrap().rejected_items.is_empty());
+}
+
+/// When the server rewrites the items it receives, the local copies are refreshed from the stored versions, so that the next sync has nothing to do
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_server_rewrites_uploads() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let cal_url: url::Url = "https://some.server.com/calendars/rewriting/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/rewrite_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Rewriting".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    mock_behaviour.lock().unwrap().rewrite_uploads = true;
+    remote.set_mock_behaviour(Some(mock_behaviour));
+
+    let mut local = Cache::new(Path::new("test_cache/rewrite_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "Rewriting".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let task = Task::new("Water the plants".to_string(), false, &cal_url);
+    let url = task.url().clone();
+    local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let local_item = local_cal.lock().unwrap().get_item_by_url_sync(&url).unwrap().clone();
+    let remote_item = remote_cal.lock().unwrap().get_item_by_url_sync(&url).unwrap().clone();
+    assert!(kitchen_fridge::ical::have_same_content(&local_item, &remote_item));
+    assert_eq!(local_item.name(), "Water the plants");
+    assert!(matches!(local_item.sync_status(), SyncStatus::Synced(_)));
+    assert_eq!(local_item.sync_status(), remote_item.sync_status());
+
+    // The next sync lists the remote items once more (since changes have been pushed), but the rewrite does not look like a remote change
+    assert!(provider.sync().await);
+    let transfers = provider.last_sync_result().unwrap().transfers.as_ref().unwrap();
+    assert!(transfers.by_operation().get("PUT").is_none());
+    assert!(transfers.by_operation().get("GET").is_none());
+    assert!(!provider.has_pending_work().await.unwrap());
+}
+
+/// Importing an iCal file pushes its items right away, and only syncs the calendar they are imported into
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_import_and_sync_ics() {
+    use std::path::Path;
+    use chrono::Utc;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, task::CompletionStatus, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::import::UidCollisionPolicy;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/imported/".parse().unwrap();
+    let other_url: url::Url = "https://some.server.com/calendars/other/".parse().unwrap();
+    let existing_url = cal_url.join("existing.ics").unwrap();
+    let existing = Item::Task(Task::new_with_parameters(
+        "Before the import".to_string(), "existing-uid".to_string(), existing_url.clone(), CompletionStatus::Uncompleted,
+        SyncStatus::random_synced(), Some(Utc::now()), Utc::now(), "prod_id".to_string(), Vec::new()));
+
+    let mut remote = Cache::new(Path::new("test_cache/import_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Imported".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.create_calendar(other_url.clone(), "Other".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    remote_cal.lock().unwrap().add_item(existing.clone()).await.unwrap();
+
+    let mut local = Cache::new(Path::new("test_cache/import_local"));
+    let local_cal = local.create_calendar(cal_url.clone(), "Imported".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let other_cal = local.create_calendar(other_url.clone(), "Other".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    local_cal.lock().unwrap().add_item(existing).await.unwrap();
+    let pending = Task::new("Not part of the import".to_string(), false, &other_url);
+    let pending_url = pending.url().clone();
+    other_cal.lock().unwrap().add_item(Item::Task(pending)).await.unwrap();
+
+    let ics = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Some app\r\n\
+        BEGIN:VTODO\r\nUID:new-uid\r\nDTSTAMP:20211103T214742Z\r\nSUMMARY:Imported task\r\nEND:VTODO\r\n\
+        BEGIN:VTODO\r\nUID:existing-uid\r\nDTSTAMP:20211103T214742Z\r\nSUMMARY:After the import\r\nEND:VTODO\r\n\
+        BEGIN:VTODO\r\nDTSTAMP:20211103T214742Z\r\nUID:broken-uid\r\nEND:VTODO\r\n\
+        END:VCALENDAR\r\n";
+
+    let mut provider = Provider::new(remote, local);
+    let report = provider.import_and_sync_ics(&cal_url, ics, UidCollisionPolicy::Replace).await.unwrap();
+    assert_eq!(report.created.len(), 1);
+    assert_eq!(report.replaced, vec![existing_url.clone()]);
+    assert_eq!(report.invalid.iter().map(|(uid, _)| uid.as_str()).collect::<Vec<_>>(), vec!["broken-uid"]);
+    assert!(report.not_uploaded.is_empty());
+    assert!(report.sync_result.success);
+
+    let remote_items = remote_cal.lock().unwrap().get_items_sync().unwrap().values().map(|item| item.name().to_string()).collect::<std::collections::HashSet<_>>();
+    assert_eq!(remote_items, ["Imported task", "After the import"].iter().map(|name| name.to_string()).collect());
+    // Other calendars are left for the next sync
+    assert_eq!(other_cal.lock().unwrap().get_item_by_url_sync(&pending_url).unwrap().sync_status(), &SyncStatus::NotSynced);
+
+    // Importing the same file again, the existing items are kept
+    let report = provider.import_and_sync_ics(&cal_url, ics, UidCollisionPolicy::Skip).await.unwrap();
+    assert!(report.created.is_empty());
+    assert_eq!(report.skipped.len(), 2);
+    let report = provider.import_and_sync_ics(&cal_url, ics, UidCollisionPolicy::KeepBoth).await.unwrap();
+    assert_eq!(report.created.len(), 2);
+    assert_eq!(remote_cal.lock().unwrap().get_items_sync().unwrap().len(), 4);
+}
+
+/// Calendars can have sync policies of their own, that override the defaults of the provider and are persisted in the cache
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_calendar_sync_policies() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+    use kitchen_fridge::provider::conflict::{ConflictContext, Resolution};
+    use kitchen_fridge::provider::{CalendarSyncPolicy, ConflictPreference, SyncDirection};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let work_url: url::Url = "https://some.server.com/calendars/work/".parse().unwrap();
+    let personal_url: url::Url = "https://some.server.com/calendars/personal/".parse().unwrap();
+    let mirror_url: url::Url = "https://some.server.com/calendars/mirror/".parse().unwrap();
+    let cal_urls = [&work_url, &personal_url, &mirror_url];
+    let task_url = |cal_url: &url::Url| cal_url.join("task.ics").unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/policies_remote"));
+    let mut remote_cals = Vec::new();
+    for cal_url in cal_urls {
+        let remote_cal = remote.create_calendar(cal_url.clone(), cal_url.path().to_string(), SupportedComponents::TODO, None).await.unwrap();
+        let task = Task::new_with_parameters("Original".to_string(), format!("uid-{}", cal_url.path()), task_url(cal_url),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::random_synced(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+        remote_cals.push(remote_cal);
+    }
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let local = Cache::new(Path::new("test_cache/policies_local"));
+    let mut provider = Provider::new(remote, local);
+    // The default is to keep the local version
+    provider.set_conflict_resolver(|_context: ConflictContext| async { Resolution::KeepLocal });
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().calendars.values().all(|report| !report.is_custom_policy));
+
+    let work_policy = CalendarSyncPolicy { conflict_resolution: Some(ConflictPreference::PreferRemote), ..CalendarSyncPolicy::default() };
+    let mirror_policy = CalendarSyncPolicy { direction: SyncDirection::PullOnly, batch_size: Some(1), ..CalendarSyncPolicy::default() };
+    provider.set_calendar_sync_policy(&work_url, Some(work_policy.clone())).await.unwrap();
+    provider.set_calendar_sync_policy(&mirror_url, Some(mirror_policy.clone())).await.unwrap();
+
+    // Modify every item on both sides, and add a local item that is not pushed to the mirror
+    for (cal_url, remote_cal) in cal_urls.iter().zip(&remote_cals) {
+        let local_cal = provider.local().get_calendar(cal_url).await.unwrap();
+        local_cal.lock().unwrap().get_item_by_url_mut_sync(&task_url(cal_url)).unwrap().unwrap_task_mut().set_name("Local".to_string());
+        remote_cal.lock().unwrap().get_item_by_url_mut_sync(&task_url(cal_url)).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Remote".to_string());
+    }
+    let local_mirror = provider.local().get_calendar(&mirror_url).await.unwrap();
+    local_mirror.lock().unwrap().add_item(Item::Task(Task::new("Not pushed".to_string(), false, &mirror_url))).await.unwrap();
+    assert!(provider.sync().await);
+
+    let local_name = |cal_url: &url::Url| provider.local().get_calendar_sync(cal_url).unwrap().lock().unwrap().get_item_by_url_sync(&task_url(cal_url)).unwrap().name().to_string();
+    assert_eq!(local_name(&work_url), "Remote");
+    assert_eq!(local_name(&personal_url), "Local");
+    assert_eq!(local_name(&mirror_url), "Remote");
+    assert_eq!(remote_cals[1].lock().unwrap().get_item_by_url_sync(&task_url(&personal_url)).unwrap().name(), "Local");
+    assert_eq!(remote_cals[2].lock().unwrap().get_items_sync().unwrap().len(), 1);
+    assert_eq!(local_mirror.lock().unwrap().get_items_sync().unwrap().len(), 2);
+    // Calendars that changes have been pushed to are listed once more
+    assert!(provider.sync().await);
+    assert!(!provider.has_pending_work().await.unwrap());
+
+    // Each calendar tells which policy it has been synced with
+    let reports = &provider.last_sync_result().unwrap().calendars;
+    assert_eq!(reports[&work_url].policy, work_policy);
+    assert!(reports[&work_url].is_custom_policy);
+    assert_eq!(reports[&personal_url].policy, CalendarSyncPolicy::default());
+    assert!(!reports[&personal_url].is_custom_policy);
+
+    // Policies survive restarts
+    provider.local().save_to_folder().unwrap();
+    let reloaded = Cache::from_folder(Path::new("test_cache/policies_local")).unwrap();
+    assert_eq!(reloaded.get_calendar_sync(&mirror_url).unwrap().lock().unwrap().sync_policy(), Some(mirror_policy));
+    assert_eq!(reloaded.get_calendar_sync(&personal_url).unwrap().lock().unwrap().sync_policy(), None);
+}
+
+/// Servers that omit ETags but provide `DAV:getlastmodified` are synced, and the most recent version wins conflicts
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_etagless_server() {
+    use std::path::Path;
+    use chrono::{Duration, Utc};
+    use kitchen_fridge::{calendar::SupportedComponents, item::{SyncStatus, VersionTag}, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/etagless/".parse().unwrap();
+    let url_of = |name: &str| cal_url.join(&format!("{}.ics", name)).unwrap();
+    let last_synced = Utc::now() - Duration::hours(2);
+
+    let mut remote = Cache::new(Path::new("test_cache/etagless_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Etagless".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for (name, version_tag) in [("older-remote", VersionTag::from_last_modified(last_synced)), ("newer-remote", VersionTag::from_last_modified(last_synced)), ("strong-etag", VersionTag::from("\"1\"".to_string()))] {
+        let task = Task::new_with_parameters("Original".to_string(), name.to_string(), url_of(name),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::Synced(version_tag), None, Utc::now(), "prod_id".to_string(), Vec::new());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let mut provider = Provider::new(remote, Cache::new(Path::new("test_cache/etagless_local")));
+    assert!(provider.sync().await);
+
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert_eq!(local_cal.lock().unwrap().get_item_by_url_sync(&url_of("older-remote")).unwrap().server_last_modified().map(|date| date.timestamp()), Some(last_synced.timestamp()));
+
+    // Every item is modified on both sides. Local modifications happen now
+    for name in ["older-remote", "newer-remote", "strong-etag"] {
+        local_cal.lock().unwrap().get_item_by_url_mut_sync(&url_of(name)).unwrap().unwrap_task_mut().set_name("Local".to_string());
+        let mut remote_cal = remote_cal.lock().unwrap();
+        let remote_item = remote_cal.get_item_by_url_mut_sync(&url_of(name)).unwrap();
+        remote_item.unwrap_task_mut().mock_remote_calendar_set_name("Remote".to_string());
+        match name {
+            "older-remote" => remote_item.set_sync_status(SyncStatus::Synced(VersionTag::from_last_modified(Utc::now() - Duration::hours(1)))),
+            "newer-remote" => remote_item.set_sync_status(SyncStatus::Synced(VersionTag::from_last_modified(Utc::now() + Duration::hours(1)))),
+            _ => remote_item.set_sync_status(SyncStatus::Synced(VersionTag::from("\"2\"".to_string()))),
+        }
+    }
+    assert!(provider.sync().await);
+
+    let names = |cal: &Arc<Mutex<kitchen_fridge::calendar::cached_calendar::CachedCalendar>>, name: &str| cal.lock().unwrap().get_item_by_url_sync(&url_of(name)).unwrap().name().to_string();
+    assert_eq!(names(&local_cal, "older-remote"), "Local");
+    assert_eq!(names(&remote_cal, "older-remote"), "Local");
+    assert_eq!(names(&local_cal, "newer-remote"), "Remote");
+    assert_eq!(names(&remote_cal, "newer-remote"), "Remote");
+    // Strong ETags are trusted: the remote version always wins
+    assert_eq!(names(&local_cal, "strong-etag"), "Remote");
+    // The remote calendar is listed once more after changes have been pushed
+    assert!(provider.sync().await);
+    assert_eq!(names(&local_cal, "older-remote"), "Local");
+    assert!(!provider.has_pending_work().await.unwrap());
+}
+
+/// Items that have been modified the same way in both sources are not conflicts, even though they are serialized differently
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_same_changes_are_not_conflicts() {
+    use std::path::Path;
+    use chrono::Utc;
+    use kitchen_fridge::{calendar::SupportedComponents, item::{SyncStatus, VersionTag}, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/cosmetic/".parse().unwrap();
+    let url_of = |name: &str| cal_url.join(&format!("{}.ics", name)).unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/cosmetic_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Cosmetic".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for name in ["same-change", "different-changes"] {
+        let task = Task::new_with_parameters("Original".to_string(), name.to_string(), url_of(name),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::Synced(VersionTag::from("\"1\"".to_string())), None, Utc::now(), "prod_id".to_string(), Vec::new());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let mut provider = Provider::new(remote, Cache::new(Path::new("test_cache/cosmetic_local")));
+    assert!(provider.sync().await);
+
+    // Both items are modified in both sources (and at different times), but only one of them ends up with different contents
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    for (name, local_name, remote_name) in [("same-change", "Renamed", "Renamed"), ("different-changes", "Local", "Remote")] {
+        local_cal.lock().unwrap().get_item_by_url_mut_sync(&url_of(name)).unwrap().unwrap_task_mut().set_name(local_name.to_string());
+        let mut remote_cal = remote_cal.lock().unwrap();
+        let remote_item = remote_cal.get_item_by_url_mut_sync(&url_of(name)).unwrap();
+        remote_item.unwrap_task_mut().mock_remote_calendar_set_name(remote_name.to_string());
+        remote_item.set_sync_status(SyncStatus::Synced(VersionTag::from("\"2\"".to_string())));
+    }
+    assert!(provider.sync().await);
+
+    let conflicts: Vec<url::Url> = provider.last_sync_result().unwrap().conflicts.iter().map(|conflict| conflict.item.clone()).collect();
+    assert_eq!(conflicts, vec![url_of("different-changes")]);
+    let local_item = local_cal.lock().unwrap().get_item_by_url_sync(&url_of("same-change")).unwrap().clone();
+    assert_eq!(local_item.name(), "Renamed");
+    assert_eq!(local_item.sync_status(), &SyncStatus::Synced(VersionTag::from("\"2\"".to_string())));
+    assert!(!provider.has_pending_work().await.unwrap());
+}
+
+/// Items that are marked for deletion do not stay in the cache once there is nothing left to delete on the server
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_deletions_are_not_left_behind() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::{SyncStatus, VersionTag}, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::{CalendarSyncPolicy, SyncDirection};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/push-only/".parse().unwrap();
+    let gone_cal_url: url::Url = "https://some.server.com/calendars/gone/".parse().unwrap();
+    let url_of = |cal_url: &url::Url, name: &str| cal_url.join(&format!("{}.ics", name)).unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/zombies_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Push only".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for name in ["deleted-everywhere", "kept"] {
+        let task = Task::new_with_parameters(name.to_string(), name.to_string(), url_of(&cal_url, name),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::random_synced(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let mut provider = Provider::new(remote, Cache::new(Path::new("test_cache/zombies_local")));
+    assert!(provider.sync().await);
+    provider.set_calendar_sync_policy(&cal_url, Some(CalendarSyncPolicy { direction: SyncDirection::PushOnly, ..CalendarSyncPolicy::default() })).await.unwrap();
+
+    // An item is deleted from both sides. Even though remote changes are not pulled into this calendar, there is nothing left to sync
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    local_cal.lock().unwrap().mark_for_deletion_sync(&url_of(&cal_url, "deleted-everywhere")).unwrap();
+    remote_cal.lock().unwrap().immediately_delete_item_sync(&url_of(&cal_url, "deleted-everywhere")).unwrap();
+
+    // A calendar has been removed from the server, and cannot be created again
+    let gone_cal = provider.local_mut().create_calendar(gone_cal_url.clone(), "Gone".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for name in ["marked-for-deletion", "modified"] {
+        let task = Task::new_with_parameters(name.to_string(), name.to_string(), url_of(&gone_cal_url, name),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::Synced(VersionTag::from("1".to_string())), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        gone_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    gone_cal.lock().unwrap().mark_for_deletion_sync(&url_of(&gone_cal_url, "marked-for-deletion")).unwrap();
+    gone_cal.lock().unwrap().get_item_by_url_mut_sync(&url_of(&gone_cal_url, "modified")).unwrap().unwrap_task_mut().set_name("Modified".to_string());
+    mock_behaviour.lock().unwrap().create_calendar_behaviour = (0, 10);
+
+    // The sync of the calendar that is gone fails, but its deletions have been resolved anyway
+    assert!(!provider.sync().await);
+    assert!(local_cal.lock().unwrap().get_item_by_url_sync(&url_of(&cal_url, "deleted-everywhere")).is_none());
+    assert!(local_cal.lock().unwrap().get_item_by_url_sync(&url_of(&cal_url, "kept")).is_some());
+    assert!(gone_cal.lock().unwrap().get_item_by_url_sync(&url_of(&gone_cal_url, "marked-for-deletion")).is_none());
+    assert_eq!(gone_cal.lock().unwrap().get_item_by_url_sync(&url_of(&gone_cal_url, "modified")).unwrap().name(), "Modified");
+    assert!(provider.local().check().is_empty());
+}
+
+/// The pairing of the calendars of both sources can be checked (and fixed) before a first sync
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_validate_pairing() {
+    use std::path::Path;
+    use kitchen_fridge::calendar::SupportedComponents;
+    use kitchen_fridge::provider::pairing::SuspiciousPairing;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let url = |s: &str| -> url::Url { s.parse().unwrap() };
+
+    let mut remote = Cache::new(Path::new("test_cache/pairing_remote"));
+    for (cal_url, name) in [("https://new.server.com/dav/calendars/work/", "Work"), ("https://new.server.com/dav/calendars/shared/", "Shared")] {
+        remote.create_calendar(url(cal_url), name.to_string(), SupportedComponents::TODO, None).await.unwrap();
+    }
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    // The local cache has been created when the server had another URL
+    let mut local = Cache::new(Path::new("test_cache/pairing_local"));
+    for (cal_url, name) in [("https://old.server.com/calendars/work/", "Work"), ("https://old.server.com/calendars/groceries/", "Groceries"), ("https://new.server.com/dav/calendars/shared/", "Shared")] {
+        local.create_calendar(url(cal_url), name.to_string(), SupportedComponents::TODO, None).await.unwrap();
+    }
+    let mut provider = Provider::new(remote, local);
+
+    let report = provider.validate().await.unwrap();
+    assert_eq!(report.paired, vec![url("https://new.server.com/dav/calendars/shared/")]);
+    assert_eq!(report.only_local, vec![url("https://old.server.com/calendars/groceries/"), url("https://old.server.com/calendars/work/")]);
+    assert_eq!(report.only_remote, vec![url("https://new.server.com/dav/calendars/work/")]);
+    let rebase = (url("https://old.server.com/"), url("https://new.server.com/dav/"));
+    assert_eq!(report.suspicious, vec![SuspiciousPairing {
+        name: "Work".to_string(),
+        local: url("https://old.server.com/calendars/work/"),
+        remote: url("https://new.server.com/dav/calendars/work/"),
+        rebase: rebase.clone(),
+    }]);
+
+    // The first sync refuses to go on, and nothing is modified
+    provider.set_validate_before_first_sync(true);
+    assert!(!provider.sync().await);
+    assert_eq!(provider.last_pairing_report(), Some(&report));
+    assert_eq!(provider.remote().get_calendars().await.unwrap().len(), 2);
+    assert_eq!(provider.local().get_calendars().await.unwrap().len(), 3);
+
+    // Once the local URLs are remapped, calendars are paired as expected
+    provider.local_mut().rebase_ids(&rebase.0, &rebase.1);
+    let report = provider.validate().await.unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.paired.len(), 2);
+    assert!(provider.sync().await);
+    assert_eq!(provider.remote().get_calendars().await.unwrap().len(), 3);
+    assert_eq!(provider.local().get_calendars().await.unwrap().len(), 3);
+}
+
+/// Syncing the same data twice performs the same requests, in the same order
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_deterministic_sync_order() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let builder = ScenarioBuilder::classic_conflict_matrix();
+    let mut traces = Vec::new();
+    for _ in 0..2 {
+        let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::new()));
+        let (mut provider, _) = builder.clone().with_mock_behaviour(Arc::clone(&mock_behaviour)).build().await;
+        assert!(provider.sync().await);
+        let operations = mock_behaviour.lock().unwrap().operations.clone();
+        traces.push(operations);
+    }
+
+    assert!(traces[0].iter().any(|op| op.starts_with("PUT ")));
+    assert!(traces[0].iter().any(|op| op.starts_with("DELETE ")));
+    assert_eq!(traces[0], traces[1]);
+}
+
+/// Drifts between the local and the remote sources are reported without modifying anything, and are resolved by the next sync
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_verify_consistency() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+    use kitchen_fridge::provider::verify::{Drift, DriftKind};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/verified/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/verify_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Verified".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let local = Cache::new(Path::new("test_cache/verify_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    let mut urls = Vec::new();
+    for name in ["Renamed remotely", "Lost by the server", "Untouched"] {
+        let task = Task::new(name.to_string(), false, &cal_url);
+        urls.push(task.url().clone());
+        local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    assert!(provider.sync().await);
+    assert!(provider.verify().await.unwrap().is_consistent());
+
+    // Another client changes the server, and this client creates an item it has not uploaded yet
+    let local_tag = local_cal.lock().unwrap().get_item_by_url_sync(&urls[0]).unwrap().sync_status().clone();
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&urls[0]).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Renamed".to_string());
+    let remote_tag = remote_cal.lock().unwrap().get_item_by_url_sync(&urls[0]).unwrap().sync_status().clone();
+    remote_cal.lock().unwrap().immediately_delete_item(&urls[1]).await.unwrap();
+    let mut remote_task = Task::new("Added remotely".to_string(), false, &cal_url);
+    remote_task.set_sync_status(SyncStatus::random_synced());
+    let remote_task_url = remote_task.url().clone();
+    remote_cal.lock().unwrap().add_item(Item::Task(remote_task)).await.unwrap();
+    local_cal.lock().unwrap().add_item(Item::Task(Task::new("Not uploaded yet".to_string(), false, &cal_url))).await.unwrap();
+
+    let report = provider.verify().await.unwrap();
+    assert_eq!(report.calendars, vec![cal_url.clone()]);
+    let mut expected = vec![
+        Drift { calendar: cal_url.clone(), item: urls[0].clone(), kind: DriftKind::RemotelyChanged {
+            local: local_tag.version_tag().unwrap().clone(),
+            remote: remote_tag.version_tag().unwrap().clone(),
+        }},
+        Drift { calendar: cal_url.clone(), item: urls[1].clone(), kind: DriftKind::MissingFromRemote },
+        Drift { calendar: cal_url.clone(), item: remote_task_url, kind: DriftKind::MissingLocally },
+    ];
+    expected.sort_by(|a, b| a.item.cmp(&b.item));
+    assert_eq!(report.drifts, expected);
+    // Nothing has been modified
+    assert_eq!(provider.verify().await.unwrap(), report);
+    assert_eq!(local_cal.lock().unwrap().get_items_sync().unwrap().len(), 4);
+
+    assert!(provider.sync().await);
+    assert!(provider.verify().await.unwrap().is_consistent());
+}
+
+/// The history of the items tells the changes made locally from the ones the sync has applied
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_item_history() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+    use kitchen_fridge::calendar::history::{ChangeKind, ChangeSource};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/history/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/history_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "History".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let mut local = Cache::new(Path::new("test_cache/history_local"));
+    local.set_item_history_limit(Some(10));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    let task = Task::new("Water the plants".to_string(), false, &cal_url);
+    let url = task.url().clone();
+    local_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    assert!(provider.sync().await);
+
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Water the garden".to_string());
+    assert!(provider.sync().await);
+    local_cal.lock().unwrap().mark_for_deletion(&url).await.unwrap();
+    assert!(provider.sync().await);
+
+    let history: Vec<(ChangeSource, ChangeKind)> = local_cal.lock().unwrap().item_history(&url).into_iter()
+        .map(|entry| (entry.source, entry.kind.clone()))
+        .collect();
+    assert_eq!(history, vec![
+        (ChangeSource::Local, ChangeKind::Created),
+        (ChangeSource::Remote, ChangeKind::Modified { properties: vec!["SUMMARY".to_string()] }),
+        (ChangeSource::Local, ChangeKind::MarkedForDeletion),
+        (ChangeSource::Sync, ChangeKind::Deleted),
+    ]);
+}
+
+/// Calendars that are disabled on the server are left out of syncs, but only when the provider is told to, and their local copies are kept
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_server_enabled_flag() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::{BaseCalendar, DavCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let noisy_url: url::Url = "https://some.server.com/calendars/noisy/".parse().unwrap();
+    let work_url: url::Url = "https://some.server.com/calendars/work/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/enabled_flag_remote"));
+    let noisy_remote = remote.create_calendar(noisy_url.clone(), "Noisy".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let work_remote = remote.create_calendar(work_url.clone(), "Work".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    mock_behaviour.lock().unwrap().enabled.insert(noisy_url.clone(), false);
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    noisy_remote.lock().unwrap().add_item(Item::Task(Task::new("Spam".to_string(), false, &noisy_url))).await.unwrap();
+    assert_eq!(noisy_remote.lock().unwrap().enabled(), Some(false));
+    assert_eq!(work_remote.lock().unwrap().enabled(), None);
+
+    // The flag is ignored by default
+    let local = Cache::new(Path::new("test_cache/enabled_flag_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    let noisy_local = provider.local().get_calendar(&noisy_url).await.unwrap();
+    assert_eq!(noisy_local.lock().unwrap().get_items_sync().unwrap().len(), 1);
+
+    provider.set_respect_server_enabled_flag(true);
+    noisy_local.lock().unwrap().add_item(Item::Task(Task::new("Local spam".to_string(), false, &noisy_url))).await.unwrap();
+    noisy_remote.lock().unwrap().add_item(Item::Task(Task::new("More spam".to_string(), false, &noisy_url))).await.unwrap();
+    assert!(!provider.has_pending_work().await.unwrap());
+    work_remote.lock().unwrap().add_item(Item::Task(Task::new("Meeting".to_string(), false, &work_url))).await.unwrap();
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+
+    // Nothing has been exchanged for the disabled calendar, and its local copy is still there
+    let work_local = provider.local().get_calendar(&work_url).await.unwrap();
+    assert_eq!(work_local.lock().unwrap().get_items_sync().unwrap().len(), 1);
+    let noisy_local = provider.local().get_calendar(&noisy_url).await.unwrap();
+    let local_names: Vec<String> = noisy_local.lock().unwrap().get_items_sync().unwrap().values().map(|item| item.name().to_string()).collect();
+    assert_eq!(local_names.len(), 2);
+    assert!(!local_names.contains(&"More spam".to_string()));
+    assert_eq!(noisy_remote.lock().unwrap().get_items_sync().unwrap().len(), 2);
+    assert!(!provider.last_sync_result().unwrap().calendars.contains_key(&noisy_url));
+
+    // Once enabled again, it is synced as usual
+    mock_behaviour.lock().unwrap().enabled.insert(noisy_url.clone(), true);
+    assert!(provider.sync().await);
+    assert_eq!(noisy_local.lock().unwrap().get_items_sync().unwrap().len(), 3);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Local-only calendars are left out of syncs in both directions, and are merged with the server once they are paired again
+#[tokio::test]
 #[cfg(feature = "integration_tests")]
+async fn test_local_only_calendars() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let scratch_url: url::Url = "https://some.server.com/calendars/scratch/".parse().unwrap();
+    let device_url: url::Url = "https://some.server.com/calendars/device/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/local_only_remote"));
+    let scratch_remote = remote.create_calendar(scratch_url.clone(), "Scratch".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let groceries = Task::new("Groceries".to_string(), false, &scratch_url);
+    let groceries_url = groceries.url().clone();
+    scratch_remote.lock().unwrap().add_item(Item::Task(groceries)).await.unwrap();
+
+    let local = Cache::new(Path::new("test_cache/local_only_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // Changes are made on both sides while the calendar is kept local
+    provider.local_mut().set_local_only(&scratch_url, true).unwrap();
+    assert!(provider.local_mut().set_local_only(&device_url, true).is_err());
+    let device_local = provider.local_mut().create_calendar(device_url.clone(), "Device".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    provider.local_mut().set_local_only(&device_url, true).unwrap();
+    device_local.lock().unwrap().add_item(Item::Task(Task::new("Draft".to_string(), false, &device_url))).await.unwrap();
+    let scratch_local = provider.local().get_calendar(&scratch_url).await.unwrap();
+    scratch_local.lock().unwrap().add_item(Item::Task(Task::new("Local note".to_string(), false, &scratch_url))).await.unwrap();
+    scratch_remote.lock().unwrap().add_item(Item::Task(Task::new("Remote note".to_string(), false, &scratch_url))).await.unwrap();
+    scratch_remote.lock().unwrap().get_item_by_url_mut_sync(&groceries_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Groceries (remote)".to_string());
+    assert!(!provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+
+    assert!(provider.remote().get_calendar(&device_url).await.is_none());
+    assert_eq!(scratch_remote.lock().unwrap().get_items_sync().unwrap().len(), 2);
+    assert_eq!(scratch_local.lock().unwrap().get_items_sync().unwrap().len(), 2);
+    assert!(!provider.last_sync_result().unwrap().calendars.contains_key(&scratch_url));
+
+    // The flag is persisted
+    provider.local().save_to_folder().unwrap();
+    let reloaded = Cache::from_folder(Path::new("test_cache/local_only_local")).unwrap();
+    assert!(reloaded.get_calendar_sync(&scratch_url).unwrap().lock().unwrap().is_local_only());
+
+    // Once paired again, changes of both sides are merged
+    provider.local_mut().set_local_only(&scratch_url, false).unwrap();
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    let mut names: Vec<String> = scratch_local.lock().unwrap().get_items_sync().unwrap().values().map(|item| item.name().to_string()).collect();
+    names.sort();
+    assert_eq!(names, vec!["Groceries (remote)", "Local note", "Remote note"]);
+    assert_eq!(scratch_remote.lock().unwrap().get_items_sync().unwrap().len(), 3);
+    assert!(provider.remote().get_calendar(&device_url).await.is_none());
+}
+
+/// Items whose uploads keep failing are retried less and less often, until they are retried explicitly
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_quarantined_items() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/quarantine_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let local = Cache::new(Path::new("test_cache/quarantine_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    let stubborn = Task::new("Stubborn".to_string(), false, &cal_url);
+    let stubborn_url = stubborn.url().clone();
+    cal_local.lock().unwrap().add_item(Item::Task(stubborn)).await.unwrap();
+    mock_behaviour.lock().unwrap().refused_uploads.insert(stubborn_url.clone(), reqwest::StatusCode::FORBIDDEN);
+
+    // Uploads are attempted at syncs 1, 2, 4, 8...
+    let mut failures = Vec::new();
+    for _ in 0..5 {
+        provider.sync().await;
+        let quarantined = provider.local().quarantined_items();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].0, stubborn_url);
+        assert!(quarantined[0].1.last_error.contains("403"));
+        failures.push(quarantined[0].1.consecutive_failures);
+    }
+    assert_eq!(failures, vec![1, 2, 2, 3, 3]);
+    assert!(provider.local().quarantined_items()[0].1.is_backing_off());
+
+    // Retrying explicitly does not wait for the backoff, and a successful upload forgets the failures
+    mock_behaviour.lock().unwrap().refused_uploads.clear();
+    provider.local_mut().retry_now(&stubborn_url).unwrap();
+    assert!(provider.sync().await);
+    assert!(provider.local().quarantined_items().is_empty());
+    assert!(provider.local_mut().retry_now(&stubborn_url).is_err());
+    assert_eq!(cal_remote.lock().unwrap().get_items_sync().unwrap().len(), 1);
+}
+
+/// Items that are replaced on the server by another kind of item are removed locally, and reported in the result of the sync
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_component_type_changes() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/component_change_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let meeting = Task::new("Prepare the meeting".to_string(), false, &cal_url);
+    let meeting_url = meeting.url().clone();
+    cal_remote.lock().unwrap().add_item(Item::Task(meeting)).await.unwrap();
+    cal_remote.lock().unwrap().add_item(Item::Task(Task::new("Buy milk".to_string(), false, &cal_url))).await.unwrap();
+
+    let local = Cache::new(Path::new("test_cache/component_change_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().component_type_changes.is_empty());
+
+    // The server now stores an event at the URL of the task
+    cal_remote.lock().unwrap().get_item_by_url_mut_sync(&meeting_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("The meeting".to_string());
+    mock_behaviour.lock().unwrap().unsupported_components.insert(meeting_url.clone(), "VEVENT".to_string());
+    assert!(provider.sync().await);
+
+    let changes = &provider.last_sync_result().unwrap().component_type_changes;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].item, meeting_url);
+    assert_eq!(changes[0].component.as_deref(), Some("VEVENT"));
+    assert_eq!(changes[0].previous.name(), "Prepare the meeting");
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert!(cal_local.lock().unwrap().get_item_by_url_sync(&meeting_url).is_none());
+    assert_eq!(cal_local.lock().unwrap().get_items_sync().unwrap().len(), 1);
+
+    // Later syncs keep ignoring it, and never delete it from the server
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().component_type_changes.is_empty());
+    assert_eq!(cal_remote.lock().unwrap().get_items_sync().unwrap().len(), 2);
+}
+
+/// Items that are deleted from the server between the time they are listed and the time they are fetched end up just like items that were deleted before the sync
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_items_deleted_while_fetched() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::{BaseCalendar, DavCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    // Returns the names of the local items after the deletion has been synced
+    async fn sync_deletion(name: &str, cal_url: &url::Url, deleted_while_fetched: bool) -> Vec<String> {
+        let mut remote = Cache::new(Path::new(&format!("test_cache/{}_remote", name)));
+        let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+        let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+        remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+        let doomed = Task::new("Doomed".to_string(), false, cal_url);
+        let doomed_url = doomed.url().clone();
+        cal_remote.lock().unwrap().add_item(Item::Task(doomed)).await.unwrap();
+        cal_remote.lock().unwrap().add_item(Item::Task(Task::new("Survivor".to_string(), false, cal_url))).await.unwrap();
+
+        let local = Cache::new(Path::new(&format!("test_cache/{}_local", name)));
+        let mut provider = Provider::new(remote, local);
+        assert!(provider.sync().await);
+
+        if deleted_while_fetched {
+            // The item is listed as changed, but it has been deleted by the time it is fetched
+            cal_remote.lock().unwrap().get_item_by_url_mut_sync(&doomed_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Changed".to_string());
+            mock_behaviour.lock().unwrap().vanishing_items.insert(doomed_url.clone());
+        } else {
+            DavCalendar::delete_item(&mut *cal_remote.lock().unwrap(), &doomed_url).await.unwrap();
+        }
+        assert!(provider.sync().await);
+        assert!(provider.last_sync_result().unwrap().conflicts.is_empty());
+
+        // Once the deletion is actually over, there is nothing left to sync
+        mock_behaviour.lock().unwrap().vanishing_items.clear();
+        let _ = DavCalendar::delete_item(&mut *cal_remote.lock().unwrap(), &doomed_url).await;
+        assert!(provider.sync().await);
+
+        let cal_local = provider.local().get_calendar(cal_url).await.unwrap();
+        let mut names: Vec<String> = cal_local.lock().unwrap().get_items_sync().unwrap().values().map(|item| item.name().to_string()).collect();
+        names.sort();
+        names
+    }
+
+    let deleted_while_fetched = sync_deletion("deleted_while_fetched", &cal_url, true).await;
+    let deleted_before = sync_deletion("deleted_before", &cal_url, false).await;
+    assert_eq!(deleted_while_fetched, vec!["Survivor".to_string()]);
+    assert_eq!(deleted_while_fetched, deleted_before);
+}
+
+/// What has been synced can still be displayed while the remote source is unreachable, and unreachable remotes are not reported as errors of the data
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_unreachable_remote() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::{BaseCalendar, CalDavSource}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/unreachable_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    cal_remote.lock().unwrap().add_item(Item::Task(Task::new("Water the plants".to_string(), false, &cal_url))).await.unwrap();
+
+    let local = Cache::new(Path::new("test_cache/unreachable_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    assert!(provider.sync_health().is_remote_reachable());
+    let last_success = provider.sync_health().last_success;
+    assert!(last_success.is_some());
+
+    // The server now refuses connections
+    mock_behaviour.lock().unwrap().unreachable = true;
+    for _ in 0..2 {
+        assert!(!provider.sync().await);
+    }
+    assert!(provider.last_sync_result().unwrap().remote_unreachable);
+    let health = provider.sync_health();
+    assert!(!health.is_remote_reachable());
+    assert_eq!(health.consecutive_unreachable_syncs, 2);
+    assert_eq!(health.last_success, last_success);
+
+    // Everything can still be displayed
+    let calendars = provider.local_only_view().get_calendars().await.unwrap();
+    assert_eq!(calendars.keys().collect::<Vec<_>>(), vec![&cal_url]);
+    let items = calendars[&cal_url].lock().unwrap().get_items_sync().unwrap().values().map(|item| item.name().to_string()).collect::<Vec<_>>();
+    assert_eq!(items, vec!["Water the plants".to_string()]);
+
+    mock_behaviour.lock().unwrap().unreachable = false;
+    assert!(provider.sync().await);
+    assert!(!provider.last_sync_result().unwrap().remote_unreachable);
+    assert!(provider.sync_health().is_remote_reachable());
+    assert_eq!(provider.sync_health().consecutive_unreachable_syncs, 0);
+}
+
+/// A calendar can be copied on the same source (resuming an interrupted copy), and the copy can be compared to the original
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_copy_calendar() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, provider::copy, traits::{BaseCalendar, CalDavSource}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/copy_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    for i in 0..40 {
+        let task = Task::new(format!("Task {}", i), i % 3 == 0, &cal_url);
+        cal_remote.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+
+    // The copy gets interrupted, then resumed
+    mock_behaviour.lock().unwrap().add_item_behaviour = (35, 1);
+    assert!(copy::copy_calendar(&mut remote, &cal_url, "Tasks (staging)", None).await.is_err());
+    let copy_url = copy::copy_calendar(&mut remote, &cal_url, "Tasks (staging)", None).await.unwrap();
+    assert_eq!(copy_url.as_str(), "https://some.server.com/calendars/tasks--staging/");
+    let cal_copy = remote.get_calendar(&copy_url).await.unwrap();
+    assert_eq!(cal_copy.lock().unwrap().name(), "Tasks (staging)");
+    assert_eq!(cal_copy.lock().unwrap().supported_components(), SupportedComponents::TODO);
+
+    let local = Cache::new(Path::new("test_cache/copy_local"));
+    let provider = Provider::new(remote, local);
+    // Items are matched by UID, so this also checks that UIDs are preserved
+    let diff = provider.compare(&cal_url, &copy_url).await.unwrap();
+    assert!(diff.is_empty(), "{:?}", diff);
+
+    // Changes are noticed
+    let extra = Task::new("Only in the copy".to_string(), false, &copy_url);
+    let extra_uid = extra.uid().to_string();
+    cal_copy.lock().unwrap().add_item(Item::Task(extra)).await.unwrap();
+    let diff = provider.compare(&cal_url, &copy_url).await.unwrap();
+    assert_eq!(diff.only_in_b, vec![extra_uid]);
+    assert!(diff.only_in_a.is_empty());
+    assert!(diff.different.is_empty());
+}
+
+/// Local changes are queued (one operation per item), and can be pushed without pulling anything
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_outbound_queue_flush() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, cache::outbound::OutboundKind, provider::flush::FlushPolicy, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/queue/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/outbound_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Queue".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let mut urls = Vec::new();
+    for name in ["To modify", "To delete", "Modified on both sides"] {
+        let task = Task::new(name.to_string(), false, &cal_url);
+        urls.push(task.url().clone());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    let (to_modify, to_delete, on_both_sides) = (&urls[0], &urls[1], &urls[2]);
+
+    let local = Cache::new(Path::new("test_cache/outbound_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    assert!(provider.local().outbound_queue().is_empty());
+
+    // Changes made while offline
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    let created = Task::new("Created".to_string(), false, &cal_url);
+    let created_url = created.url().clone();
+    let transient = Task::new("Created then deleted".to_string(), false, &cal_url);
+    let transient_url = transient.url().clone();
+    {
+        let mut local_cal = local_cal.lock().unwrap();
+        local_cal.add_item(Item::Task(created)).await.unwrap();
+        local_cal.get_item_by_url_mut_sync(&created_url).unwrap().unwrap_task_mut().set_name("Created then renamed".to_string());
+        local_cal.add_item(Item::Task(transient)).await.unwrap();
+        local_cal.mark_for_deletion(&transient_url).await.unwrap();
+        for url in [to_modify, on_both_sides] {
+            local_cal.get_item_by_url_mut_sync(url).unwrap().unwrap_task_mut().set_name("Renamed locally".to_string());
+        }
+        local_cal.mark_for_deletion(to_delete).await.unwrap();
+    }
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(on_both_sides).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Renamed remotely".to_string());
+
+    let queue = provider.local().outbound_queue();
+    let mut operations: Vec<(url::Url, OutboundKind)> = queue.iter().map(|op| (op.item.clone(), op.kind)).collect();
+    operations.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut expected = vec![
+        (created_url.clone(), OutboundKind::Create),
+        (to_modify.clone(), OutboundKind::Update),
+        (on_both_sides.clone(), OutboundKind::Update),
+        (to_delete.clone(), OutboundKind::Delete),
+    ];
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(operations, expected);
+    assert_eq!(provider.local().estimated_requests(), 5);
+
+    // The item that has changed on the server is left for a sync
+    let report = provider.flush(&FlushPolicy { batch_size: 2, max_operations: None }).await.unwrap();
+    assert!(report.is_success());
+    assert_eq!(report.pushed.len(), 3);
+    assert_eq!(report.deferred.iter().map(|op| &op.item).collect::<Vec<_>>(), vec![on_both_sides]);
+    {
+        let remote_cal = remote_cal.lock().unwrap();
+        assert_eq!(remote_cal.get_item_by_url_sync(&created_url).unwrap().name(), "Created then renamed");
+        assert_eq!(remote_cal.get_item_by_url_sync(to_modify).unwrap().name(), "Renamed locally");
+        assert!(remote_cal.get_item_by_url_sync(to_delete).is_none());
+        assert!(remote_cal.get_item_by_url_sync(&transient_url).is_none());
+        assert_eq!(remote_cal.get_item_by_url_sync(on_both_sides).unwrap().name(), "Renamed remotely");
+    }
+    assert_eq!(provider.local().outbound_queue().iter().map(|op| &op.item).collect::<Vec<_>>(), vec![on_both_sides]);
+
+    assert!(provider.sync().await);
+    assert!(provider.local().outbound_queue().is_empty());
+}
+
+/// A sync that is cancelled at any of its checkpoints is resumed by the next sync, which ends up in the same state as an uninterrupted sync
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_cancellation_at_every_checkpoint() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use kitchen_fridge::provider::{checkpoint::SyncCheckpoint, CalendarSyncPolicy};
+    use kitchen_fridge::scenarios::Expectation;
+
+    async fn populate(builder: &ScenarioBuilder) -> (Provider<Cache, CachedCalendar, Cache, CachedCalendar>, Expectation) {
+        let (mut provider, expectation) = builder.build().await;
+        // Small batches, so that uploads have several checkpoints
+        let cal_urls: Vec<url::Url> = provider.local().get_calendars().await.unwrap().into_keys().collect();
+        for cal_url in cal_urls {
+            provider.set_calendar_sync_policy(&cal_url, Some(CalendarSyncPolicy { batch_size: Some(2), ..CalendarSyncPolicy::default() })).await.unwrap();
+        }
+        (provider, expectation)
+    }
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let builder = ScenarioBuilder::classic_conflict_matrix();
+
+    // An uninterrupted sync
+    let (mut provider, expectation) = populate(&builder).await;
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let recorder = recorded.clone();
+    provider.set_checkpoint_observer(move |cal_url, checkpoint| recorder.lock().unwrap().push((cal_url.clone(), checkpoint)));
+    assert!(provider.sync().await);
+    assert_converged(&provider, &expectation).await;
+    let checkpoints = recorded.lock().unwrap().clone();
+    assert!(checkpoints.iter().filter(|(_, checkpoint)| *checkpoint == SyncCheckpoint::UploadBatch).count() > 1);
+
+    for (cancel_at, checkpoint) in checkpoints.iter().enumerate() {
+        let (mut provider, expectation) = populate(&builder).await;
+        let handle = provider.cancellation_handle();
+        let reached = Arc::new(AtomicUsize::new(0));
+        provider.set_checkpoint_observer(move |_, _| {
+            if reached.fetch_add(1, Ordering::SeqCst) == cancel_at {
+                handle.cancel();
+            }
+        });
+        assert!(!provider.sync().await, "Sync cancelled at {:?}", checkpoint);
+        assert!(provider.last_sync_result().unwrap().cancelled);
+        assert_eq!(provider.sync_health().last_success, None);
+
+        assert!(provider.sync().await, "Sync resumed after {:?}", checkpoint);
+        assert!(!provider.last_sync_result().unwrap().cancelled);
+        assert!(provider.sync_health().last_success.is_some());
+        assert_converged(&provider, &expectation).await;
+    }
+
+    // Cancelling while no sync is running does not stop the next one
+    let (mut provider, expectation) = populate(&builder).await;
+    provider.cancellation_handle().cancel();
+    assert!(provider.sync().await);
+    assert!(!provider.last_sync_result().unwrap().cancelled);
+    assert_converged(&provider, &expectation).await;
+}
+
+/// Events are synced just like tasks, and calendars that only support events refuse tasks
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_sync_events() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, CalDateTime, Event, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/agenda/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/events_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Agenda".to_string(), SupportedComponents::EVENT, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let start = CalDateTime::Utc(chrono::TimeZone::ymd(&chrono::Utc, 2021, 3, 22).and_hms(10, 0, 0));
+    let end = CalDateTime::Utc(chrono::TimeZone::ymd(&chrono::Utc, 2021, 3, 22).and_hms(11, 0, 0));
+    let meeting = Event::new("Weekly meeting".to_string(), Some(start.clone()), Some(end.clone()), &cal_url);
+    let meeting_url = meeting.url().clone();
+    cal_remote.lock().unwrap().add_item(Item::Event(meeting)).await.unwrap();
+
+    let local = Cache::new(Path::new("test_cache/events_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    let synced = cal_local.lock().unwrap().get_item_by_url_sync(&meeting_url).unwrap().unwrap_event().clone();
+    assert_eq!(synced.name(), "Weekly meeting");
+    assert_eq!(synced.start(), Some(&start));
+    assert_eq!(synced.end(), Some(&end));
+
+    // Local changes are pushed
+    cal_local.lock().unwrap().get_item_by_url_mut_sync(&meeting_url).unwrap().unwrap_event_mut().set_location(Some("Room 101".to_string()));
+    assert!(provider.sync().await);
+    let pushed = cal_remote.lock().unwrap().get_item_by_url_sync(&meeting_url).unwrap().unwrap_event().clone();
+    assert_eq!(pushed.location(), Some("Room 101"));
+
+    let task = Task::new("Not an event".to_string(), false, &cal_url);
+    assert!(cal_local.lock().unwrap().add_item(Item::Task(task)).await.is_err());
+}
+
+/// Resetting a calendar downloads it again, and keeps the local changes aside when asked to
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_reset_calendar() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+    let other_url: url::Url = "https://some.server.com/calendars/other/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/reset_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let other_remote = remote.create_calendar(other_url.clone(), "Other".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let mut urls = Vec::new();
+    for name in ["Buy milk", "Call Mom", "Water the plants"] {
+        let task = Task::new(name.to_string(), false, &cal_url);
+        urls.push(task.url().clone());
+        cal_remote.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    other_remote.lock().unwrap().add_item(Item::Task(Task::new("Elsewhere".to_string(), false, &other_url))).await.unwrap();
+
+    let local = Cache::new(Path::new("test_cache/reset_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // Local changes that have not been pushed yet: an addition, a modification and a deletion
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    let new_task = Task::new("Brand new".to_string(), false, &cal_url);
+    let new_url = new_task.url().clone();
+    {
+        let mut cal_local = cal_local.lock().unwrap();
+        cal_local.add_item_sync(Item::Task(new_task)).unwrap();
+        cal_local.get_item_by_url_mut_sync(&urls[0]).unwrap().unwrap_task_mut().set_name("Buy oat milk".to_string());
+        cal_local.mark_for_deletion_sync(&urls[1]).unwrap();
+    }
+    // The server has changed in the meantime
+    cal_remote.lock().unwrap().get_item_by_url_mut_sync(&urls[2]).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Water the garden".to_string());
+
+    let report = provider.reset_calendar(&cal_url, true).await.unwrap();
+    let mut held = vec![new_url.clone(), urls[0].clone()];
+    held.sort();
+    assert_eq!(report.held, held);
+    assert_eq!(report.discarded, vec![urls[1].clone()]);
+    assert_eq!(report.removed, 1);
+    assert!(report.sync_result.success);
+
+    // The calendar is a fresh copy of the server
+    {
+        let cal_local = cal_local.lock().unwrap();
+        let items = cal_local.get_items_sync().unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(items.values().all(|item| matches!(item.sync_status(), SyncStatus::Synced(_))));
+        assert_eq!(items[&urls[0]].name(), "Buy milk");
+        assert_eq!(items[&urls[2]].name(), "Water the garden");
+        assert_eq!(cal_local.held_items().len(), 2);
+    }
+    assert_eq!(provider.local().get_calendar(&other_url).await.unwrap().lock().unwrap().get_items_sync().unwrap().len(), 1);
+
+    // Held changes can be applied again, and are pushed by the next sync
+    cal_local.lock().unwrap().reapply_held_item(&urls[0]).unwrap();
+    cal_local.lock().unwrap().reapply_held_item(&new_url).unwrap();
+    assert!(cal_local.lock().unwrap().held_items().is_empty());
+    assert!(provider.sync().await);
+    let remote_items = cal_remote.lock().unwrap().get_items_sync().unwrap().len();
+    assert_eq!(remote_items, 4);
+    assert_eq!(cal_remote.lock().unwrap().get_item_by_url_sync(&urls[0]).unwrap().name(), "Buy oat milk");
+}
+
+/// Syncs that would delete too many items of a calendar are paused, until they are confirmed
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_divergence_threshold() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, error_code, traits::{BaseCalendar, DavCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/divergence_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let mut urls = Vec::new();
+    for i in 0..12 {
+        let task = Task::new(format!("Task {}", i), false, &cal_url);
+        urls.push(task.url().clone());
+        cal_remote.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+
+    // Filling an empty local cache is never paused
+    let local = Cache::new(Path::new("test_cache/divergence_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().divergence_warnings.is_empty());
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert_eq!(cal_local.lock().unwrap().get_items_sync().unwrap().len(), 12);
+
+    // The server now looks like it has lost half of the calendar
+    for url in &urls[..6] {
+        DavCalendar::delete_item(&mut *cal_remote.lock().unwrap(), url).await.unwrap();
+    }
+    assert!(!provider.sync().await);
+    let warning = provider.last_sync_result().unwrap().divergence_warnings[0].clone();
+    assert_eq!(warning.calendar, cal_url);
+    assert_eq!(warning.items, 12);
+    assert_eq!(warning.planned.pulled_deletions, 6);
+    assert_eq!(warning.planned.deletions(), 6);
+    assert_eq!(warning.code(), error_code::DIVERGENT_SYNC);
+    assert_eq!(cal_local.lock().unwrap().get_items_sync().unwrap().len(), 12);
+
+    // Later syncs are paused just the same, until the very same operations are confirmed
+    assert!(!provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().divergence_warnings[0].token, warning.token);
+    assert!(provider.confirm_divergent_sync(&cal_url, "not the token").await.is_err());
+
+    DavCalendar::delete_item(&mut *cal_remote.lock().unwrap(), &urls[6]).await.unwrap();
+    assert!(!provider.confirm_divergent_sync(&cal_url, &warning.token).await.unwrap());
+    let warning = provider.last_sync_result().unwrap().divergence_warnings[0].clone();
+    assert_eq!(warning.planned.pulled_deletions, 7);
+    assert_eq!(cal_local.lock().unwrap().get_items_sync().unwrap().len(), 12);
+
+    assert!(provider.confirm_divergent_sync(&cal_url, &warning.token).await.unwrap());
+    assert_eq!(cal_local.lock().unwrap().get_items_sync().unwrap().len(), 5);
+    assert!(provider.sync().await);
+
+    // The valve can be adjusted, or removed
+    for url in &urls[7..10] {
+        DavCalendar::delete_item(&mut *cal_remote.lock().unwrap(), url).await.unwrap();
+    }
+    provider.set_divergence_threshold(Some(kitchen_fridge::provider::divergence::DivergenceThreshold { max_count: Some(2), ..Default::default() }));
+    assert!(!provider.sync().await);
+    provider.set_divergence_threshold(None);
+    assert!(provider.sync().await);
+    assert_eq!(cal_local.lock().unwrap().get_items_sync().unwrap().len(), 2);
+
+    // Edits are not counted, unless asked to
+    let threshold = kitchen_fridge::provider::divergence::DivergenceThreshold { max_count: Some(1), ..Default::default() };
+    provider.set_divergence_threshold(Some(threshold));
+    for url in &urls[10..] {
+        cal_remote.lock().unwrap().get_item_by_url_mut_sync(url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Edited".to_string());
+    }
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().divergence_warnings.is_empty());
+    provider.set_divergence_threshold(Some(kitchen_fridge::provider::divergence::DivergenceThreshold { count_overwrites: true, ..threshold }));
+    for url in &urls[10..] {
+        cal_remote.lock().unwrap().get_item_by_url_mut_sync(url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Edited again".to_string());
+    }
+    assert!(!provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().divergence_warnings[0].planned.overwrites(), 2);
+}
+
+/// Sources that have not been synced are told apart from the expected state, item by item
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+#[should_panic(expected = "local: missing")]
+async fn test_scenario_not_converged() {
+    let (provider, expectation) = ScenarioBuilder::first_sync_to_local().in_folder(std::path::Path::new("test_cache/not_converged")).build().await;
+    assert_converged(&provider, &expectation).await;
+}
+
+/// Items that are locked on the server are left as they are locally, and retried at the next syncs, without being quarantined
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_locked_items() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    *kitchen_fridge::config::DOWNLOAD_BATCH_RETRY_DELAY.lock().unwrap() = std::time::Duration::ZERO;
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/locked_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut obsolete = Task::new("Obsolete".to_string(), false, &cal_url);
+    obsolete.set_sync_status(SyncStatus::random_synced());
+    let obsolete_url = obsolete.url().clone();
+    cal_remote.lock().unwrap().add_item(Item::Task(obsolete)).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let local = Cache::new(Path::new("test_cache/locked_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // The new item is locked for the first two attempts to upload it, the deleted one for the first attempt to delete it
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    let edited = Task::new("Edited elsewhere".to_string(), false, &cal_url);
+    let edited_url = edited.url().clone();
+    cal_local.lock().unwrap().add_item(Item::Task(edited)).await.unwrap();
+    cal_local.lock().unwrap().mark_for_deletion_sync(&obsolete_url).unwrap();
+    mock_behaviour.lock().unwrap().locked_items.insert(edited_url.clone(), 2);
+    mock_behaviour.lock().unwrap().locked_items.insert(obsolete_url.clone(), 1);
+
+    assert!(provider.sync().await);
+    let locked: Vec<(url::Url, bool)> = provider.last_sync_result().unwrap().locked_items.iter().map(|locked| (locked.item.clone(), locked.deletion)).collect();
+    assert_eq!(locked.len(), 2);
+    assert!(locked.contains(&(edited_url.clone(), false)));
+    assert!(locked.contains(&(obsolete_url.clone(), true)));
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&edited_url).unwrap().sync_status(), SyncStatus::NotSynced));
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&obsolete_url).unwrap().sync_status(), SyncStatus::LocallyDeleted(_)));
+    assert!(provider.local().quarantined_items().is_empty());
+
+    assert!(provider.sync().await);
+    let locked: Vec<url::Url> = provider.last_sync_result().unwrap().locked_items.iter().map(|locked| locked.item.clone()).collect();
+    assert_eq!(locked, vec![edited_url.clone()]);
+    assert!(cal_local.lock().unwrap().get_item_by_url_sync(&obsolete_url).is_none());
+    assert!(provider.local().quarantined_items().is_empty());
+
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().locked_items.is_empty());
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&edited_url).unwrap().sync_status(), SyncStatus::Synced(_)));
+    let remote_items: Vec<url::Url> = cal_remote.lock().unwrap().get_items_sync().unwrap().keys().cloned().collect();
+    assert_eq!(remote_items, vec![edited_url]);
+}
+
+/// Remote calendars whose ctag has not changed since the last sync are not listed, but their local changes are still pushed
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_unchanged_calendars_are_not_listed() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/unchanged_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let local = Cache::new(Path::new("test_cache/unchanged_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // Listing the remote items would fail from now on
+    mock_behaviour.lock().unwrap().get_item_version_tags_behaviour = (0, 1);
+    assert!(provider.sync().await);
+
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    let task = Task::new("Some task".to_string(), false, &cal_url);
+    let task_url = task.url().clone();
+    cal_local.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    assert!(provider.sync().await);
+    assert!(cal_remote.lock().unwrap().get_item_by_url_sync(&task_url).is_some());
+    assert_eq!(mock_behaviour.lock().unwrap().get_item_version_tags_behaviour, (0, 1));
+
+    // Since changes have been pushed, the next sync lists the remote items once more, in case another client has changed them during the sync
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(!provider.sync().await);
+    assert_eq!(mock_behaviour.lock().unwrap().get_item_version_tags_behaviour, (0, 0));
+    assert!(provider.sync().await);
+    assert!(!provider.has_pending_work().await.unwrap());
+
+    // The remote calendar is listed again once it has changed
+    mock_behaviour.lock().unwrap().get_item_version_tags_behaviour = (0, 1);
+    cal_remote.lock().unwrap().get_item_by_url_mut_sync(&task_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Renamed".to_string());
+    assert!(!provider.sync().await);
+    assert_eq!(mock_behaviour.lock().unwrap().get_item_version_tags_behaviour, (0, 0));
+    assert!(provider.sync().await);
+    assert_eq!(cal_local.lock().unwrap().get_item_by_url_sync(&task_url).unwrap().name(), "Renamed");
+}
+
+/// The changes other clients make while a sync pushes changes are pulled by the next sync, even though they happened before the end of the sync that pushed
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_changes_made_during_a_pushing_sync_are_pulled() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/changes_during_push_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let existing = Task::new("Existing task".to_string(), false, &cal_url);
+    let existing_url = existing.url().clone();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    cal_remote.lock().unwrap().add_item(Item::Task(existing)).await.unwrap();
+    let local = Cache::new(Path::new("test_cache/changes_during_push_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // Another client renames the existing task while this sync uploads a new one
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    cal_local.lock().unwrap().add_item(Item::Task(Task::new("New task".to_string(), false, &cal_url))).await.unwrap();
+    mock_behaviour.lock().unwrap().edits_during_uploads.insert(existing_url.clone(), "Edited elsewhere".to_string());
+    assert!(provider.sync().await);
+    assert!(mock_behaviour.lock().unwrap().edits_during_uploads.is_empty());
+    assert_eq!(cal_local.lock().unwrap().get_item_by_url_sync(&existing_url).unwrap().name(), "Existing task");
+
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert_eq!(cal_local.lock().unwrap().get_item_by_url_sync(&existing_url).unwrap().name(), "Edited elsewhere");
+    assert!(!provider.has_pending_work().await.unwrap());
+}
+
+/// Uploads can be fetched back, so that the items a server alters are reported and kept as they have been sent
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_verify_uploads() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, ical::PropertyChange, item::SyncStatus, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::CalendarSyncPolicy;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/verify_uploads_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    mock_behaviour.lock().unwrap().truncate_names = Some(8);
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let local = Cache::new(Path::new("test_cache/verify_uploads_local"));
+    let mut provider = Provider::new(remote, local);
+    provider.set_verify_uploads(true);
+    assert!(provider.sync().await);
+
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    let long = Task::new("A rather long name".to_string(), false, &cal_url);
+    let long_url = long.url().clone();
+    cal_local.lock().unwrap().add_item(Item::Task(long)).await.unwrap();
+    cal_local.lock().unwrap().add_item(Item::Task(Task::new("Milk".to_string(), false, &cal_url))).await.unwrap();
+
+    // Only the altered item is reported, and it is kept as it has been sent
+    assert!(!provider.sync().await);
+    let findings = provider.last_sync_result().unwrap().fidelity_findings.clone();
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].item, long_url);
+    assert!(matches!(&findings[0].differences[..], [PropertyChange::Changed { name, .. }] if name == "SUMMARY"));
+    let local_item = cal_local.lock().unwrap().get_item_by_url_sync(&long_url).unwrap().clone();
+    assert_eq!(local_item.name(), "A rather long name");
+    assert!(matches!(local_item.sync_status(), SyncStatus::LocallyModified(_)));
+    assert_eq!(cal_remote.lock().unwrap().get_item_by_url_sync(&long_url).unwrap().name(), "A rather");
+
+    // It is not uploaded again until it is edited
+    mock_behaviour.lock().unwrap().operations.clear();
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().fidelity_findings.is_empty());
+    assert!(!mock_behaviour.lock().unwrap().operations.iter().any(|operation| operation.starts_with("PUT")));
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&long_url).unwrap().sync_status(), SyncStatus::LocallyModified(_)));
+    cal_local.lock().unwrap().get_item_by_url_mut_sync(&long_url).unwrap().unwrap_task_mut().set_name("Another long name".to_string());
+    assert!(!provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().fidelity_findings.len(), 1);
+    assert_eq!(cal_remote.lock().unwrap().get_item_by_url_sync(&long_url).unwrap().name(), "Another ");
+
+    // Calendars can opt out
+    provider.set_calendar_sync_policy(&cal_url, Some(CalendarSyncPolicy { verify_uploads: Some(false), ..CalendarSyncPolicy::default() })).await.unwrap();
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().fidelity_findings.is_empty());
+
+    // Properties the server adds are not alterations
+    provider.set_calendar_sync_policy(&cal_url, None).await.unwrap();
+    {
+        let mut behaviour = mock_behaviour.lock().unwrap();
+        behaviour.truncate_names = None;
+        behaviour.rewrite_uploads = true;
+    }
+    cal_local.lock().unwrap().get_item_by_url_mut_sync(&long_url).unwrap().unwrap_task_mut().set_name("Another rather long name".to_string());
+    assert!(provider.sync().await);
+    assert!(provider.last_sync_result().unwrap().fidelity_findings.is_empty());
+    assert_eq!(cal_remote.lock().unwrap().get_item_by_url_sync(&long_url).unwrap().name(), "Another rather long name");
+}
+
+/// Items that have been changed many times between two syncs are uploaded once, with their latest version, including the ones whose conflict is resolved in favour of the local version
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_one_write_per_item() {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::{BaseCalendar, DavCalendar}, Item, Task};
+    use kitchen_fridge::provider::conflict::{ConflictContext, Resolution};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/coalesced/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/coalesced_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Coalesced".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut urls = Vec::new();
+    for name in &["renamed", "conflicted"] {
+        let task = Task::new_with_parameters(name.to_string(), format!("uid-{}", name), cal_url.join(&format!("{}.ics", name)).unwrap(),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::random_synced(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        urls.push(task.url().clone());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(Arc::clone(&mock_behaviour)));
+    let local = Cache::new(Path::new("test_cache/coalesced_local"));
+    let mut provider = Provider::new(remote, local);
+    provider.set_conflict_resolver(|_: ConflictContext| async { Resolution::KeepLocal });
+    assert!(provider.sync().await);
+
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    let created = Task::new("created".to_string(), false, &cal_url);
+    urls.push(created.url().clone());
+    local_cal.lock().unwrap().add_item(Item::Task(created)).await.unwrap();
+    for version in 1..=5 {
+        for url in &urls {
+            let mut item = local_cal.lock().unwrap().get_item_by_url_sync(url).unwrap().clone();
+            let name = format!("{} v{}", item.name().split(' ').next().unwrap(), version);
+            item.unwrap_task_mut().set_name(name);
+            local_cal.lock().unwrap().update_item(item).await.unwrap();
+        }
+    }
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&urls[1]).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("conflicted remotely".to_string());
+
+    mock_behaviour.lock().unwrap().operations.clear();
+    assert!(provider.sync().await);
+    assert_eq!(provider.last_sync_result().unwrap().conflicts.len(), 1);
+
+    let mut puts: HashMap<String, usize> = HashMap::new();
+    for op in mock_behaviour.lock().unwrap().operations.iter().filter_map(|op| op.strip_prefix("PUT ")) {
+        *puts.entry(op.to_string()).or_default() += 1;
+    }
+    assert_eq!(puts.len(), 3);
+    assert!(puts.values().all(|count| *count == 1), "{:?}", puts);
+    for url in &urls {
+        let name = remote_cal.lock().unwrap().get_item_by_url(url).await.unwrap().unwrap().name().to_string();
+        assert!(name.ends_with(" v5"), "{}", name);
+    }
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Servers that support sync tokens are asked for their changes since the last sync rather than listed in full, and are listed again when they do not accept the token anymore
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_sync_tokens() {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tokens/".parse().unwrap();
+    let url_of = |name: &str| cal_url.join(&format!("{}.ics", name)).unwrap();
+    let new_task = |name: &str| Item::Task(Task::new_with_parameters(name.to_string(), format!("uid-{}", name), url_of(name),
+        kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::random_synced(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new()));
+
+    let mut remote = Cache::new(Path::new("test_cache/tokens_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Tokens".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for name in &["renamed", "deleted", "kept"] {
+        remote_cal.lock().unwrap().add_item(new_task(name)).await.unwrap();
+    }
+    // The calendar is never listed in full
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour {
+        sync_tokens: Some(HashMap::new()),
+        get_item_version_tags_behaviour: (0, u32::MAX),
+        ..MockBehaviour::default()
+    }));
+    remote.set_mock_behaviour(Some(Arc::clone(&mock_behaviour)));
+    let local = Cache::new(Path::new("test_cache/tokens_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert_eq!(local_cal.lock().unwrap().get_items_sync().unwrap().len(), 3);
+    let first_token = local_cal.lock().unwrap().last_sync_token().unwrap().to_string();
+
+    // Changes in both sources
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&url_of("renamed")).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("renamed remotely".to_string());
+    remote_cal.lock().unwrap().immediately_delete_item_sync(&url_of("deleted")).unwrap();
+    remote_cal.lock().unwrap().add_item(new_task("created")).await.unwrap();
+    let local_task = Task::new("created locally".to_string(), false, &cal_url);
+    let local_task_url = local_task.url().clone();
+    local_cal.lock().unwrap().add_item(Item::Task(local_task)).await.unwrap();
+    assert!(provider.sync().await);
+
+    let names = |cal: &Arc<Mutex<kitchen_fridge::calendar::cached_calendar::CachedCalendar>>| {
+        let mut names: Vec<String> = cal.lock().unwrap().get_items_sync().unwrap().values().map(|item| item.name().to_string()).collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&local_cal), vec!["created", "created locally", "kept", "renamed remotely"]);
+    assert!(remote_cal.lock().unwrap().get_item_by_url_sync(&local_task_url).is_some());
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+    assert_ne!(local_cal.lock().unwrap().last_sync_token().unwrap(), first_token);
+
+    // The token survives restarts
+    let saved_path = Path::new("test_cache/tokens_local_saved");
+    provider.local().export_to_folder(saved_path).unwrap();
+    let saved = Cache::from_folder(saved_path).unwrap();
+    let saved_cal = saved.get_calendar(&cal_url).await.unwrap();
+    assert_eq!(saved_cal.lock().unwrap().last_sync_token(), local_cal.lock().unwrap().last_sync_token());
+
+    // Tokens the server does not accept anymore make the next sync list the calendar in full, and start over with a new token
+    {
+        let mut behaviour = mock_behaviour.lock().unwrap();
+        behaviour.sync_tokens = Some(HashMap::new());
+        behaviour.get_item_version_tags_behaviour = (0, 0);
+    }
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&url_of("kept")).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("kept, then renamed".to_string());
+    assert!(provider.sync().await);
+    assert_eq!(local_cal.lock().unwrap().last_sync_token(), None);
+    assert_eq!(names(&local_cal), vec!["created", "created locally", "kept, then renamed", "renamed remotely"]);
+    remote_cal.lock().unwrap().immediately_delete_item_sync(&url_of("created")).unwrap();
+    assert!(provider.sync().await);
+    assert!(local_cal.lock().unwrap().last_sync_token().is_some());
+    assert_eq!(names(&local_cal), vec!["created locally", "kept, then renamed", "renamed remotely"]);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Items that are changed on the server while they are being synced are not overwritten: this is a conflict, that the next sync resolves
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_concurrent_remote_changes() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, provider::conflict::ConflictKind, traits::BaseCalendar, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/concurrent_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut urls = Vec::new();
+    for name in ["Edited", "Obsolete"] {
+        let mut task = Task::new(name.to_string(), false, &cal_url);
+        task.set_sync_status(SyncStatus::random_synced());
+        urls.push(task.url().clone());
+        cal_remote.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    let (edited_url, obsolete_url) = (urls[0].clone(), urls[1].clone());
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let local = Cache::new(Path::new("test_cache/concurrent_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // Another client changes both items once they have been listed
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    cal_local.lock().unwrap().get_item_by_url_mut_sync(&edited_url).unwrap().unwrap_task_mut().set_name("Edited locally".to_string());
+    cal_local.lock().unwrap().mark_for_deletion_sync(&obsolete_url).unwrap();
+    mock_behaviour.lock().unwrap().concurrent_changes = urls.iter().cloned().collect();
+
+    assert!(!provider.sync().await);
+    let conflicts: Vec<(url::Url, ConflictKind)> = provider.last_sync_result().unwrap().conflicts.iter().map(|conflict| (conflict.item.clone(), conflict.kind)).collect();
+    assert!(conflicts.contains(&(edited_url.clone(), ConflictKind::ModifiedInBoth)));
+    assert!(conflicts.contains(&(obsolete_url.clone(), ConflictKind::DeletedLocallyModifiedRemotely)));
+    assert!(!mock_behaviour.lock().unwrap().operations.iter().any(|operation| operation.starts_with("PUT") || operation.starts_with("DELETE")));
+    assert_eq!(cal_remote.lock().unwrap().get_item_by_url_sync(&edited_url).unwrap().name(), "Edited");
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&edited_url).unwrap().sync_status(), SyncStatus::LocallyModified(_)));
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&obsolete_url).unwrap().sync_status(), SyncStatus::LocallyDeleted(_)));
+
+    // The next sync sees these changes, and resolves the conflicts as usual
+    mock_behaviour.lock().unwrap().concurrent_changes.clear();
+    for url in &urls {
+        cal_remote.lock().unwrap().get_item_by_url_mut_sync(url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Edited remotely".to_string());
+    }
+    assert!(provider.sync().await);
+    for url in &urls {
+        let item = cal_local.lock().unwrap().get_item_by_url_sync(url).unwrap().clone();
+        assert_eq!(item.name(), "Edited remotely");
+        assert!(matches!(item.sync_status(), SyncStatus::Synced(_)));
+    }
+}
+
+/// An estimate tells what the next sync would do without writing anything, and this sync does not list the calendars again
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_estimate() {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/tasks/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/estimate_remote"));
+    let cal_remote = remote.create_calendar(cal_url.clone(), "Tasks".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mut remote_bytes = 0;
+    let mut urls = Vec::new();
+    for name in ["Groceries", "Laundry", "Taxes"] {
+        let mut task = Task::new(name.to_string(), false, &cal_url);
+        task.set_sync_status(SyncStatus::random_synced());
+        urls.push(task.url().clone());
+        let item = Item::Task(task);
+        remote_bytes += kitchen_fridge::ical::build_from(&item).unwrap().len() as u64;
+        cal_remote.lock().unwrap().add_item(item).await.unwrap();
+    }
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    mock_behaviour.lock().unwrap().sync_tokens = Some(HashMap::new());
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let local = Cache::new(Path::new("test_cache/estimate_local"));
+    let mut provider = Provider::new(remote, local);
+
+    // Before the first sync, every item is to be downloaded
+    let estimate = provider.estimate().await.unwrap();
+    assert_eq!(estimate.calendars.len(), 1);
+    assert_eq!((estimate.downloads(), estimate.uploads(), estimate.deletions()), (3, 0, 0));
+    assert_eq!(estimate.bytes(), remote_bytes);
+    assert_eq!(estimate.calendars[0].unknown_sizes, 0);
+    assert_eq!(estimate.requests(), 1);
+    assert!(provider.local().get_calendar(&cal_url).await.is_none());
+    assert_eq!(mock_behaviour.lock().unwrap().operations, vec![format!("REPORT {}", cal_url)]);
+
+    // The sync starts from the listing of the estimate
+    assert!(provider.sync().await);
+    let operations = mock_behaviour.lock().unwrap().operations.clone();
+    assert_eq!(operations.iter().filter(|operation| operation.starts_with("REPORT")).count(), 1);
+    let cal_local = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert_eq!(cal_local.lock().unwrap().get_item_urls().await.unwrap().len(), 3);
+
+    // Local changes are to be pushed
+    let added = Task::new("Dishes".to_string(), false, &cal_url);
+    cal_local.lock().unwrap().add_item(Item::Task(added)).await.unwrap();
+    cal_local.lock().unwrap().mark_for_deletion_sync(&urls[0]).unwrap();
+    mock_behaviour.lock().unwrap().operations.clear();
+    let estimate = provider.estimate().await.unwrap();
+    assert_eq!((estimate.downloads(), estimate.uploads(), estimate.deletions()), (0, 1, 1));
+    assert!(estimate.bytes() > 0);
+    assert_eq!(estimate.requests(), 2);
+    assert!(estimate.duration(std::time::Duration::from_millis(100), 1_000_000) >= std::time::Duration::from_millis(200));
+    assert!(!mock_behaviour.lock().unwrap().operations.iter().any(|operation| operation.starts_with("PUT") || operation.starts_with("DELETE")));
+    assert!(matches!(cal_local.lock().unwrap().get_item_by_url_sync(&urls[0]).unwrap().sync_status(), SyncStatus::LocallyDeleted(_)));
+
+    assert!(provider.sync().await);
+    assert_eq!(cal_remote.lock().unwrap().get_item_urls().await.unwrap().len(), 3);
+    assert!(cal_remote.lock().unwrap().get_item_by_url_sync(&urls[0]).is_none());
+}
+
+/// Subscribed calendars are refreshed when due (or on demand), and nothing is ever pushed into them
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_subscription_refresh() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::{SupportedComponents, Subscription}, traits::{BaseCalendar, CompleteCalendar}, Item, Task};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/holidays/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/subscription_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Holidays".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    mock_behaviour.lock().unwrap().subscriptions.insert(cal_url.clone(), Subscription {
+        source: "https://feeds.example.com/holidays.ics".parse().unwrap(),
+        refresh_interval: Some(chrono::Duration::hours(1)),
+    });
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+    let task = Task::new("Party".to_string(), false, &cal_url);
+    let task_url = task.url().clone();
+    remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+
+    let local = Cache::new(Path::new("test_cache/subscription_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    assert!(!local_cal.lock().unwrap().is_writable());
+    assert!(local_cal.lock().unwrap().last_refreshed().is_some());
+    assert_eq!(local_cal.lock().unwrap().get_item_by_url_sync(&task_url).unwrap().name(), "Party");
+    assert!(!provider.has_pending_work().await.unwrap());
+
+    // The refresh is not due yet
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&task_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Birthday party".to_string());
+    assert!(!provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    assert_eq!(local_cal.lock().unwrap().get_item_by_url_sync(&task_url).unwrap().name(), "Party");
+
+    // Local changes are never pushed
+    local_cal.lock().unwrap().get_item_by_url_mut_sync(&task_url).unwrap().unwrap_task_mut().set_name("Local party".to_string());
+    assert!(!provider.has_pending_work().await.unwrap());
+    assert!(provider.sync().await);
+    let transfers = provider.last_sync_result().unwrap().transfers.as_ref().unwrap();
+    assert!(transfers.by_operation().get("PUT").is_none());
+    assert_eq!(remote_cal.lock().unwrap().get_item_by_url_sync(&task_url).unwrap().name(), "Birthday party");
+
+    // Forcing a refresh
+    provider.refresh_now(&cal_url).await.unwrap();
+    assert_eq!(local_cal.lock().unwrap().get_item_by_url_sync(&task_url).unwrap().name(), "Birthday party");
+
+    // A refresh that fails is reported like any failure of the sync, and the local copy is kept until the next one
+    local_cal.lock().unwrap().set_last_refreshed(None);
+    assert!(provider.has_pending_work().await.unwrap());
+    remote_cal.lock().unwrap().add_item(Item::Task(Task::new("Picnic".to_string(), false, &cal_url))).await.unwrap();
+    mock_behaviour.lock().unwrap().get_item_version_tags_behaviour = (0, 1);
+    assert!(!provider.sync().await);
+    assert_eq!(local_cal.lock().unwrap().get_items_sync().unwrap().len(), 1);
+    assert!(local_cal.lock().unwrap().last_refreshed().is_none());
+
+    assert!(provider.sync().await);
+    assert_eq!(local_cal.lock().unwrap().get_items_sync().unwrap().len(), 2);
+}
+
+/// Archive old completed tasks, with an interruption halfway
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_archive_completed_tasks() {
+    use std::path::Path;
+    use chrono::{Duration, Utc};
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, task::CompletionStatus, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::archive::ArchivePolicy;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/main/".parse().unwrap();
+    let archive_url: url::Url = "https://some.server.com/calendars/main-archive/".parse().unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/archive_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Main".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let long_ago = Utc::now() - Duration::days(100);
+    let recently = Utc::now() - Duration::days(2);
+    let completions = vec![
+        ("old-1", CompletionStatus::Completed(Some(long_ago))),
+        ("old-2", CompletionStatus::Completed(Some(long_ago))),
+        ("old-3", CompletionStatus::Completed(Some(long_ago))),
+        ("old-4", CompletionStatus::Completed(Some(long_ago))),
+        ("recent", CompletionStatus::Completed(Some(recently))),
+        ("uncompleted", CompletionStatus::Uncompleted),
+    ];
+    for (name, completion) in completions {
+        let task = Task::new_with_parameters(name.to_string(), format!("uid-{}", name), cal_url.join(&format!("{}.ics", name)).unwrap(),
+            completion, SyncStatus::random_synced(), None, long_ago, "prod_id".to_string(), Vec::new());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::default()));
+    remote.set_mock_behaviour(Some(mock_behaviour.clone()));
+
+    let local = Cache::new(Path::new("test_cache/archive_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // An unsynced local change prevents an item from being archived
+    let old_4 = cal_url.join("old-4.ics").unwrap();
+    provider.local().get_calendar(&cal_url).await.unwrap().lock().unwrap()
+        .get_item_by_url_mut_sync(&old_4).unwrap().unwrap_task_mut().set_name("old-4 renamed".to_string());
+
+    // The second remote deletion fails
+    let policy = ArchivePolicy { completed_for_longer_than: Duration::days(30), create_archive_calendar: true };
+    mock_behaviour.lock().unwrap().delete_item_behaviour = (1, 1);
+    let report = provider.archive(&cal_url, &archive_url, &policy).await.unwrap();
+    assert_eq!(report.moved.len(), 2);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.skipped, vec![old_4.clone()]);
+
+    let report = provider.archive(&cal_url, &archive_url, &policy).await.unwrap();
+    assert_eq!(report.moved, vec![(cal_url.join("old-2.ics").unwrap(), archive_url.join("old-2.ics").unwrap())]);
+    assert!(report.failed.is_empty());
+
+    let archive = provider.local().get_calendar(&archive_url).await.unwrap();
+    assert_eq!(archive.lock().unwrap().name(), "Main (archive)");
+    let archived = archive.lock().unwrap().get_item_by_url_sync(&archive_url.join("old-1.ics").unwrap()).unwrap().clone();
+    assert_eq!(archived.uid(), "uid-old-1");
+    assert_eq!(archive.lock().unwrap().get_items_sync().unwrap().len(), 3);
+    assert_eq!(provider.local().get_calendar(&cal_url).await.unwrap().lock().unwrap().get_items_sync().unwrap().len(), 3);
+
+    assert!(provider.sync().await);
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Resolve conflicts with a callback, with every possible resolution
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_conflict_resolver() {
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::conflict::{ConflictContext, ConflictKind, Resolution};
+    use kitchen_fridge::ical::PropertyChange;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/conflicts/".parse().unwrap();
+    let names = ["keep-local", "keep-remote", "keep-both", "merged"];
+
+    let mut remote = Cache::new(Path::new("test_cache/conflicts_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Conflicts".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    for name in &names {
+        let task = Task::new_with_parameters(name.to_string(), format!("uid-{}", name), cal_url.join(&format!("{}.ics", name)).unwrap(),
+            kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::random_synced(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+        remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    }
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let local = Cache::new(Path::new("test_cache/conflicts_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    // Modify every item on both sides
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    for name in &names {
+        let url = cal_url.join(&format!("{}.ics", name)).unwrap();
+        local_cal.lock().unwrap().get_item_by_url_mut_sync(&url).unwrap().unwrap_task_mut().set_name(format!("{} local", name));
+        remote_cal.lock().unwrap().get_item_by_url_mut_sync(&url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name(format!("{} remote", name));
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls_in_resolver = calls.clone();
+    provider.set_conflict_resolver(move |context: ConflictContext| {
+        calls_in_resolver.fetch_add(1, Ordering::SeqCst);
+        // Both versions have been renamed
+        let differences = context.differences().unwrap();
+        assert!(differences.iter().any(|change| matches!(change, PropertyChange::Changed{ name, .. } if name == "SUMMARY")));
+        async move {
+            match context.local.name() {
+                "keep-local local" => Resolution::KeepLocal,
+                "keep-remote local" => Resolution::KeepRemote,
+                "keep-both local" => Resolution::KeepBoth,
+                _ => {
+                    let mut merged = context.remote.clone();
+                    merged.unwrap_task_mut().set_name(format!("{} + {}", context.local.name(), context.remote.name()));
+                    Resolution::Merged(merged)
+                },
+            }
+        }
+    });
+    assert!(provider.sync().await);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+    let conflicts = &provider.last_sync_result().unwrap().conflicts;
+    assert_eq!(conflicts.len(), 4);
+    assert!(conflicts.iter().all(|conflict| conflict.kind == ConflictKind::ModifiedInBoth && conflict.calendar == cal_url));
+    assert_eq!(conflicts[0].code().to_string(), "KF-SYNC-001 modified-in-both");
+
+    let name_of = |name: &str| {
+        let url = cal_url.join(&format!("{}.ics", name)).unwrap();
+        local_cal.lock().unwrap().get_item_by_url_sync(&url).unwrap().name().to_string()
+    };
+    assert_eq!(name_of("keep-local"), "keep-local local");
+    assert_eq!(name_of("keep-remote"), "keep-remote remote");
+    assert_eq!(name_of("keep-both"), "keep-both remote");
+    assert_eq!(name_of("merged"), "merged local + merged remote");
+
+    let items = local_cal.lock().unwrap().get_items_sync().unwrap().into_iter().map(|(_, item)| item.clone()).collect::<Vec<_>>();
+    assert_eq!(items.len(), 5);
+    let duplicate = items.iter().find(|item| item.name() == "keep-both local").unwrap();
+    assert_ne!(duplicate.uid(), "uid-keep-both");
+    assert!(items.iter().all(|item| matches!(item.sync_status(), SyncStatus::Synced(_))));
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+/// Conflicted copies are named by a custom policy
+#[tokio::test]
+#[cfg(feature = "integration_tests")]
+async fn test_conflict_naming() {
+    use std::path::Path;
+    use kitchen_fridge::{calendar::SupportedComponents, item::SyncStatus, traits::BaseCalendar, Item, Task};
+    use kitchen_fridge::provider::conflict::{ConflictContext, ConflictMetadata, ConflictNaming, DerivedIdentity, Resolution};
+
+    struct FrenchNaming;
+    impl ConflictNaming for FrenchNaming {
+        fn name_copy(&self, original: &Item, conflict: &ConflictMetadata) -> DerivedIdentity {
+            DerivedIdentity {
+                name: format!("{} (copie en conflit avec « {} »)", original.name(), conflict.remote.name()),
+                uid: format!("{}-conflit", original.uid()),
+            }
+        }
+    }
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let cal_url: url::Url = "https://some.server.com/calendars/naming/".parse().unwrap();
+    let item_url = cal_url.join("task.ics").unwrap();
+
+    let mut remote = Cache::new(Path::new("test_cache/naming_remote"));
+    let remote_cal = remote.create_calendar(cal_url.clone(), "Naming".to_string(), SupportedComponents::TODO, None).await.unwrap();
+    let task = Task::new_with_parameters("Tâche".to_string(), "uid-tache".to_string(), item_url.clone(),
+        kitchen_fridge::task::CompletionStatus::Uncompleted, SyncStatus::random_synced(), None, chrono::Utc::now(), "prod_id".to_string(), Vec::new());
+    remote_cal.lock().unwrap().add_item(Item::Task(task)).await.unwrap();
+    remote.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::default()))));
+    let local = Cache::new(Path::new("test_cache/naming_local"));
+    let mut provider = Provider::new(remote, local);
+    assert!(provider.sync().await);
+
+    let local_cal = provider.local().get_calendar(&cal_url).await.unwrap();
+    local_cal.lock().unwrap().get_item_by_url_mut_sync(&item_url).unwrap().unwrap_task_mut().set_name("Tâche locale".to_string());
+    remote_cal.lock().unwrap().get_item_by_url_mut_sync(&item_url).unwrap().unwrap_task_mut().mock_remote_calendar_set_name("Tâche distante".to_string());
+
+    provider.set_conflict_resolver(|_context: ConflictContext| async { Resolution::KeepBoth });
+    provider.set_conflict_naming(FrenchNaming);
+    assert!(provider.sync().await);
+
+    let items = local_cal.lock().unwrap().get_items_sync().unwrap().into_iter().map(|(_, item)| item.clone()).collect::<Vec<_>>();
+    assert_eq!(items.len(), 2);
+    let copy = items.iter().find(|item| item.url() != &item_url).unwrap();
+    assert_eq!(copy.name(), "Tâche locale (copie en conflit avec « Tâche distante »)");
+    assert_eq!(copy.uid(), "uid-tache-conflit");
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+}
+
+#[cfg(feature = "test-utils")]
+use kitchen_fridge::{traits::CalDavSource,
+               provider::Provider,
+               cache::Cache,
+               calendar::cached_calendar::CachedCalendar,
+};
+
+/// Print the contents of the provider. This is usually used for debugging
+#[allow(dead_code)]
+#[cfg(feature = "test-utils")]
 async fn print_provider(provider: &Provider<Cache, CachedCalendar, Cache, CachedCalendar>, title: &str) {
     let cals_server = provider.remote().get_calendars().await.unwrap();
     println!("----Server, {}-------", title);